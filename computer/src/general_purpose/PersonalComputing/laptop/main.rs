@@ -8,6 +8,7 @@
 
 //here you create visualiaztion that allows users to select which type of sub computer they want. 
 
+use computer::machine::MachineBuilder;
 use minifb::{Key, Window, WindowOptions};
 use std::time::Duration;
 
@@ -26,6 +27,17 @@ enum SpecificLaptopType {
     LinuxLaptop,
 }
 
+impl SpecificLaptopType {
+    // The machine file each model is built from
+    fn preset(&self) -> &'static str {
+        match self {
+            SpecificLaptopType::MacbookAir => "macbook_air",
+            SpecificLaptopType::Windows11 => "windows11",
+            SpecificLaptopType::LinuxLaptop => "linux_laptop",
+        }
+    }
+}
+
 struct LaptopSelection {
     visualization_type: LaptopVisualizationType,
     specific_type: Option<SpecificLaptopType>,
@@ -120,20 +132,9 @@ pub fn main() {
             }
 
             if window.is_key_pressed(Key::Enter, minifb::KeyRepeat::No) {
-                match selection.specific_type {
-                    Some(SpecificLaptopType::MacbookAir) => {
-                        launch_specific_visualization("Macbook Air");
-                        break;
-                    }
-                    Some(SpecificLaptopType::Windows11) => {
-                        launch_specific_visualization("Windows 11");
-                        break;
-                    }
-                    Some(SpecificLaptopType::LinuxLaptop) => {
-                        launch_specific_visualization("Linux Laptop");
-                        break;
-                    }
-                    None => {}
+                if let Some(laptop) = &selection.specific_type {
+                    launch_specific_visualization(laptop);
+                    break;
                 }
             }
 
@@ -259,10 +260,24 @@ fn launch_general_visualization() {
     // general::main();
 }
 
-fn launch_specific_visualization(laptop_type: &str) {
-    println!("Launching specific laptop visualization for: {}", laptop_type);
-    // Here we would call into the specific laptop visualization module
-    // specific::main(laptop_type);
+// Builds the model's hardware from its preset machine file
+fn launch_specific_visualization(laptop: &SpecificLaptopType) {
+    let builder = match MachineBuilder::preset(laptop.preset()) {
+        Ok(builder) => builder,
+        Err(err) => {
+            eprintln!("Machine file `{}` is invalid: {:?}", laptop.preset(), err);
+            return;
+        }
+    };
+    let name = builder.description().name.clone();
+    match builder.build() {
+        Ok(_hardware) => {
+            println!("Launching specific laptop visualization for: {}", name);
+            // Here we would call into the specific laptop visualization module
+            // specific::main(&name);
+        }
+        Err(err) => eprintln!("Can't build {}: {:?}", name, err),
+    }
 }
 
 fn launch_personal_visualization() {
@@ -1,22 +1,39 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+
+// Cycles of ownership kept for the bus view
+const OWNERSHIP_HISTORY: usize = 1024;
+
+// Waits of 0, 1, 2-3, 4-7 ... cycles; the last bucket takes everything longer
+const LATENCY_BUCKETS: usize = 12;
+
+const MAX_QUEUE_DEPTH: usize = 32;
 
 pub struct BusArbiter {
     // Queue of pending bus requests
     request_queue: VecDeque<BusRequest>,
-    
+
     // Current bus owner
     current_owner: Option<DeviceId>,
-    
+    owner_cycles_left: u32,
+
     // Priority levels for different devices
     device_priorities: Vec<DevicePriority>,
-    
+
+    policy: ArbitrationPolicy,
+    round_robin_next: usize, // Index into device_priorities
+    cycle: u64,
+    starvation_threshold: u32,
+
     // Statistics for visualization
     total_requests: u64,
     total_wait_cycles: u64,
     conflicts: u64,
+    device_stats: HashMap<DeviceId, DeviceStats>,
+    ownership: VecDeque<Option<DeviceId>>,
+    starvation_events: Vec<StarvationEvent>,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct DeviceId {
     device_type: DeviceType,
     id: u8,
@@ -32,13 +49,16 @@ struct DevicePriority {
 #[derive(Clone, Copy)]
 pub struct BusRequest {
     device: DeviceId,
+    #[allow(dead_code)]
     address: u32,
+    #[allow(dead_code)]
     request_type: RequestType,
     cycles_needed: u8,
     wait_cycles: u32,
+    starving: bool, // Already reported to the starvation detector
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum DeviceType {
     CPU,
     GPU,
@@ -54,76 +74,194 @@ pub enum RequestType {
     IO,
 }
 
+// How the next owner is picked when the bus frees up. Can be switched at
+// any time; queued requests keep their wait times.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArbitrationPolicy {
+    FixedPriority, // Highest base priority wins; low ones can starve
+    RoundRobin,    // Rotates through devices with something queued
+    Aging { interval: u32 }, // Waiting raises priority by one every `interval` cycles
+    WeightedFair,  // Least bus time per unit of priority goes next
+    Tdma { slot_cycles: u32, slots: Vec<DeviceId> }, // Fixed time slots, idle if their owner has nothing
+}
+
+// Grant latencies of one device, in power-of-two buckets
+#[derive(Debug, Clone, Default)]
+pub struct LatencyHistogram {
+    pub buckets: [u64; LATENCY_BUCKETS],
+    pub count: u64,
+    pub total: u64,
+    pub max: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DeviceStats {
+    pub requests: u64,
+    pub grants: u64,
+    pub busy_cycles: u64,
+    pub latency: LatencyHistogram,
+    pub starvation_events: u64,
+}
+
+// A request that waited past the threshold without being granted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StarvationEvent {
+    pub device: DeviceId,
+    pub cycle: u64,
+    pub waited: u32,
+}
+
 impl BusArbiter {
     pub fn new() -> Self {
         let mut arbiter = Self {
             request_queue: VecDeque::new(),
             current_owner: None,
+            owner_cycles_left: 0,
             device_priorities: Vec::new(),
+            policy: ArbitrationPolicy::Aging { interval: 10 },
+            round_robin_next: 0,
+            cycle: 0,
+            starvation_threshold: 256,
             total_requests: 0,
             total_wait_cycles: 0,
             conflicts: 0,
+            device_stats: HashMap::new(),
+            ownership: VecDeque::new(),
+            starvation_events: Vec::new(),
         };
 
         // Set up default device priorities
-        arbiter.device_priorities = vec![
-            DevicePriority {
-                device: DeviceId { device_type: DeviceType::CPU, id: 0 },
-                base_priority: 3,
-                current_priority: 3,
-            },
-            DevicePriority {
-                device: DeviceId { device_type: DeviceType::GPU, id: 0 },
-                base_priority: 2,
-                current_priority: 2,
-            },
-            DevicePriority {
-                device: DeviceId { device_type: DeviceType::DMA, id: 0 },
-                base_priority: 1,
-                current_priority: 1,
-            },
-        ];
+        arbiter.register_device(DeviceId::new(DeviceType::CPU, 0), 3);
+        arbiter.register_device(DeviceId::new(DeviceType::GPU, 0), 2);
+        arbiter.register_device(DeviceId::new(DeviceType::DMA, 0), 1);
 
         arbiter
     }
 
-    pub fn request_bus(&mut self, device: DeviceId, address: u32, 
-                      request_type: RequestType, cycles: u8) -> bool {
-        self.total_requests += 1;
+    // Adds a bus master, or changes the priority of one already known
+    pub fn register_device(&mut self, device: DeviceId, priority: u8) {
+        match self.device_priorities.iter_mut().find(|p| p.device == device) {
+            Some(existing) => {
+                existing.base_priority = priority;
+                existing.current_priority = priority;
+            }
+            None => self.device_priorities.push(DevicePriority {
+                device,
+                base_priority: priority,
+                current_priority: priority,
+            }),
+        }
+        self.device_stats.entry(device).or_default();
+    }
+
+    pub fn set_policy(&mut self, policy: ArbitrationPolicy) {
+        self.policy = policy;
+        for priority in &mut self.device_priorities {
+            priority.current_priority = priority.base_priority;
+        }
+    }
+
+    pub fn policy(&self) -> &ArbitrationPolicy {
+        &self.policy
+    }
+
+    // Requests that wait longer than this are reported as starving
+    pub fn set_starvation_threshold(&mut self, cycles: u32) {
+        self.starvation_threshold = cycles.max(1);
+    }
 
+    // Returns true if the device holds the bus on return. Otherwise the
+    // request waits in the queue until the policy picks it; a master has
+    // one request outstanding, so asking again while it waits adds nothing.
+    pub fn request_bus(&mut self, device: DeviceId, address: u32,
+                      request_type: RequestType, cycles: u8) -> bool {
         // Check if bus is available
         if let Some(owner) = self.current_owner {
             if owner == device {
                 return true; // Device already owns the bus
             }
-            
-            // Bus is busy, queue request
+        }
+        if self.request_queue.iter().any(|request| request.device == device) {
+            return false;
+        }
+        if self.request_queue.len() >= MAX_QUEUE_DEPTH {
+            self.conflicts += 1;
+            return false;
+        }
+
+        self.total_requests += 1;
+        self.device_stats.entry(device).or_default().requests += 1;
+        if self.current_owner.is_some() {
             self.conflicts += 1;
-            self.request_queue.push_back(BusRequest {
-                device,
-                address,
-                request_type,
-                cycles_needed: cycles,
-                wait_cycles: 0,
-            });
-            false
-        } else {
-            // Bus is free, grant immediately
-            self.current_owner = Some(device);
-            true
+        }
+
+        // Bus is busy or the policy may prefer someone else: queue request
+        self.request_queue.push_back(BusRequest {
+            device,
+            address,
+            request_type,
+            cycles_needed: cycles.max(1),
+            wait_cycles: 0,
+            starving: false,
+        });
+        if self.current_owner.is_none() {
+            self.select_next_device();
+        }
+        self.current_owner == Some(device)
+    }
+
+    // Gives the bus up before the granted cycles run out
+    pub fn release_bus(&mut self, device: DeviceId) {
+        if self.current_owner == Some(device) {
+            self.current_owner = None;
+            self.owner_cycles_left = 0;
         }
     }
 
     pub fn tick(&mut self) {
+        self.cycle += 1;
+
+        if self.ownership.len() == OWNERSHIP_HISTORY {
+            self.ownership.pop_front();
+        }
+        self.ownership.push_back(self.current_owner);
+
+        // The owner uses one more cycle of its grant
+        if let Some(owner) = self.current_owner {
+            self.device_stats.entry(owner).or_default().busy_cycles += 1;
+            self.owner_cycles_left = self.owner_cycles_left.saturating_sub(1);
+            if self.owner_cycles_left == 0 {
+                self.current_owner = None;
+            }
+        }
+
         // Update wait times and priorities
+        let aging = match self.policy {
+            ArbitrationPolicy::Aging { interval } => Some(interval.max(1)),
+            _ => None,
+        };
         for request in &mut self.request_queue {
             request.wait_cycles += 1;
             self.total_wait_cycles += 1;
-            
+
             // Increase priority of waiting devices
-            if let Some(priority) = self.device_priorities.iter_mut()
-                .find(|p| p.device == request.device) {
-                priority.current_priority = priority.current_priority.saturating_add(1);
+            if let Some(interval) = aging {
+                if request.wait_cycles % interval == 0 {
+                    if let Some(priority) = self.device_priorities.iter_mut()
+                        .find(|p| p.device == request.device) {
+                        priority.current_priority = priority.current_priority.saturating_add(1);
+                    }
+                }
+            }
+
+            if !request.starving && request.wait_cycles >= self.starvation_threshold {
+                request.starving = true;
+                self.starvation_events.push(StarvationEvent {
+                    device: request.device,
+                    cycle: self.cycle,
+                    waited: request.wait_cycles,
+                });
+                self.device_stats.entry(request.device).or_default().starvation_events += 1;
             }
         }
 
@@ -134,29 +272,39 @@ impl BusArbiter {
     }
 
     fn select_next_device(&mut self) {
-        // Find highest priority request
-        let mut highest_priority = 0;
-        let mut selected_index = 0;
-
-        for (i, request) in self.request_queue.iter().enumerate() {
-            if let Some(priority) = self.device_priorities.iter()
-                .find(|p| p.device == request.device) {
-                let effective_priority = priority.current_priority + 
-                    (request.wait_cycles / 10) as u8; // Age factor
-                if effective_priority > highest_priority {
-                    highest_priority = effective_priority;
-                    selected_index = i;
-                }
-            }
-        }
+        let selected = match &self.policy {
+            ArbitrationPolicy::FixedPriority => self.highest_priority(|p| p.base_priority as u64),
+            ArbitrationPolicy::Aging { .. } => self.highest_priority(|p| p.current_priority as u64),
+            ArbitrationPolicy::RoundRobin => self.next_in_rotation(),
+            ArbitrationPolicy::WeightedFair => self.least_served(),
+            ArbitrationPolicy::Tdma { slot_cycles, slots } => self.slot_owner(*slot_cycles, slots),
+        };
+        let Some((selected_index, grant_cycles)) = selected else {
+            return;
+        };
 
-        // Grant bus to selected device
+        // Grant bus to selected device. A grant cut short by a TDMA slot
+        // boundary leaves the rest queued for the device's next slot.
         if let Some(request) = self.request_queue.remove(selected_index) {
             self.current_owner = Some(request.device);
+            self.owner_cycles_left = grant_cycles;
+            if grant_cycles < request.cycles_needed as u32 {
+                self.request_queue.insert(selected_index, BusRequest {
+                    cycles_needed: request.cycles_needed - grant_cycles as u8,
+                    wait_cycles: 0,
+                    starving: false,
+                    ..request
+                });
+            }
+            let stats = self.device_stats.entry(request.device).or_default();
+            stats.grants += 1;
+            stats.latency.record(request.wait_cycles);
+
             // Reset priority for granted device
-            if let Some(priority) = self.device_priorities.iter_mut()
-                .find(|p| p.device == request.device) {
-                priority.current_priority = priority.base_priority;
+            if let Some(index) = self.device_priorities.iter()
+                .position(|p| p.device == request.device) {
+                self.device_priorities[index].current_priority = self.device_priorities[index].base_priority;
+                self.round_robin_next = (index + 1) % self.device_priorities.len();
             }
         }
     }
@@ -184,11 +332,321 @@ impl BusArbiter {
         self.conflicts as f32 / self.total_requests as f32
     }
 
+    // Owner of each recent cycle, oldest first; None is an idle bus
+    pub fn ownership_history(&self) -> &VecDeque<Option<DeviceId>> {
+        &self.ownership
+    }
+
+    pub fn device_stats(&self, device: DeviceId) -> Option<&DeviceStats> {
+        self.device_stats.get(&device)
+    }
+
+    pub fn devices(&self) -> Vec<DeviceId> {
+        self.device_priorities.iter().map(|p| p.device).collect()
+    }
+
+    pub fn starvation_events(&self) -> &[StarvationEvent] {
+        &self.starvation_events
+    }
+
+    // Devices with a request queued past the threshold right now
+    pub fn starving_devices(&self) -> Vec<DeviceId> {
+        let mut starving: Vec<DeviceId> = Vec::new();
+        for request in &self.request_queue {
+            if request.wait_cycles >= self.starvation_threshold && !starving.contains(&request.device) {
+                starving.push(request.device);
+            }
+        }
+        starving
+    }
+
+    // Jain's fairness index over bus time per unit of priority: 1.0 is
+    // perfectly fair, 1/n is one device taking everything
+    pub fn fairness(&self) -> f32 {
+        let shares: Vec<f64> = self.device_priorities
+            .iter()
+            .filter_map(|p| {
+                let stats = self.device_stats.get(&p.device)?;
+                (stats.requests > 0).then(|| stats.busy_cycles as f64 / p.base_priority.max(1) as f64)
+            })
+            .collect();
+        let sum: f64 = shares.iter().sum();
+        let squares: f64 = shares.iter().map(|share| share * share).sum();
+        if squares == 0.0 {
+            return 1.0;
+        }
+        (sum * sum / (shares.len() as f64 * squares)) as f32
+    }
+
     pub fn is_bus_available(&self) -> bool {
         // Bus is available if:
         // 1. No current owner OR
         // 2. Queue is not full
-        self.current_owner.is_none() || 
-        self.request_queue.len() < 32  // Maximum queue depth
+        self.current_owner.is_none() ||
+        self.request_queue.len() < MAX_QUEUE_DEPTH
+    }
+
+    // Helper methods
+    // Queue index of the best request by a per-device score, oldest first
+    // among equals. Returns it with the cycles to grant.
+    fn highest_priority(&self, score: impl Fn(&DevicePriority) -> u64) -> Option<(usize, u32)> {
+        let mut best: Option<(usize, u64)> = None;
+        for (i, request) in self.request_queue.iter().enumerate() {
+            let value = self.priority_of(request.device).map(&score).unwrap_or(0);
+            if best.is_none_or(|(_, top)| value > top) {
+                best = Some((i, value));
+            }
+        }
+        best.map(|(i, _)| (i, self.request_queue[i].cycles_needed as u32))
+    }
+
+    fn next_in_rotation(&self) -> Option<(usize, u32)> {
+        let count = self.device_priorities.len();
+        (0..count)
+            .map(|step| self.device_priorities[(self.round_robin_next + step) % count].device)
+            .find_map(|device| self.request_queue.iter().position(|request| request.device == device))
+            .or_else(|| (!self.request_queue.is_empty()).then_some(0)) // Unregistered devices go last
+            .map(|i| (i, self.request_queue[i].cycles_needed as u32))
+    }
+
+    fn least_served(&self) -> Option<(usize, u32)> {
+        let mut best: Option<(usize, f64)> = None;
+        for (i, request) in self.request_queue.iter().enumerate() {
+            let weight = self.priority_of(request.device).map_or(1, |p| p.base_priority.max(1)) as f64;
+            let served = self.device_stats.get(&request.device).map_or(0, |stats| stats.busy_cycles) as f64;
+            let virtual_time = served / weight;
+            if best.is_none_or(|(_, least)| virtual_time < least) {
+                best = Some((i, virtual_time));
+            }
+        }
+        best.map(|(i, _)| (i, self.request_queue[i].cycles_needed as u32))
+    }
+
+    // Only the slot's owner may start, and its grant ends with the slot;
+    // whatever doesn't fit is carried into its next one
+    fn slot_owner(&self, slot_cycles: u32, slots: &[DeviceId]) -> Option<(usize, u32)> {
+        if slots.is_empty() {
+            return None;
+        }
+        let slot_cycles = slot_cycles.max(1) as u64;
+        let owner = slots[(self.cycle / slot_cycles) as usize % slots.len()];
+        let left_in_slot = (slot_cycles - self.cycle % slot_cycles) as u32;
+        self.request_queue
+            .iter()
+            .position(|request| request.device == owner)
+            .map(|i| (i, (self.request_queue[i].cycles_needed as u32).min(left_in_slot)))
+    }
+
+    fn priority_of(&self, device: DeviceId) -> Option<&DevicePriority> {
+        self.device_priorities.iter().find(|p| p.device == device)
+    }
+}
+
+impl Default for BusArbiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceId {
+    pub const fn new(device_type: DeviceType, id: u8) -> Self {
+        Self { device_type, id }
+    }
+
+    pub fn device_type(&self) -> DeviceType {
+        self.device_type
+    }
+
+    pub fn label(&self) -> String {
+        format!("{:?}{}", self.device_type, self.id)
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, wait: u32) {
+        let bucket = if wait == 0 { 0 } else { (32 - wait.leading_zeros()) as usize };
+        self.buckets[bucket.min(LATENCY_BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.total += wait as u64;
+        self.max = self.max.max(wait);
+    }
+
+    pub fn mean(&self) -> f32 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.total as f32 / self.count as f32
+    }
+
+    // Upper bound of the bucket holding the given fraction of grants
+    pub fn percentile(&self, fraction: f32) -> u32 {
+        let target = (self.count as f32 * fraction.clamp(0.0, 1.0)).ceil() as u64;
+        let mut seen = 0;
+        for (bucket, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target && count > 0 {
+                return Self::bucket_limit(bucket).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    // Largest wait a bucket holds
+    pub fn bucket_limit(bucket: usize) -> u32 {
+        match bucket {
+            0 => 0,
+            b if b >= LATENCY_BUCKETS - 1 => u32::MAX,
+            b => (1 << b) - 1,
+        }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CPU: DeviceId = DeviceId::new(DeviceType::CPU, 0);
+    const GPU: DeviceId = DeviceId::new(DeviceType::GPU, 0);
+
+    // Owner of each cycle after `cycles` ticks, oldest first
+    fn owners(arbiter: &mut BusArbiter, cycles: usize) -> Vec<Option<DeviceId>> {
+        for _ in 0..cycles {
+            arbiter.tick();
+        }
+        arbiter.ownership_history().iter().rev().take(cycles).rev().copied().collect()
+    }
+
+    #[test]
+    fn tdma_grants_end_with_the_slot_and_carry_the_rest() {
+        let mut arbiter = BusArbiter::new();
+        arbiter.set_policy(ArbitrationPolicy::Tdma { slot_cycles: 4, slots: vec![CPU, GPU] });
+
+        // Six cycles don't fit a four-cycle slot: four now, two in the CPU's next slot
+        assert!(arbiter.request_bus(CPU, 0x100, RequestType::Read, 6));
+        assert!(!arbiter.request_bus(GPU, 0x200, RequestType::Read, 2));
+        let history = owners(&mut arbiter, 12);
+        assert_eq!(history[..4], [Some(CPU); 4]);
+        assert_eq!(history[4..8], [Some(GPU), Some(GPU), None, None]);
+        assert_eq!(history[8..10], [Some(CPU); 2]);
+        assert_eq!(history[10..], [None, None]);
+        assert_eq!(arbiter.device_stats(CPU).unwrap().busy_cycles, 6);
+    }
+
+    #[test]
+    fn a_waiting_master_queues_once() {
+        let mut arbiter = BusArbiter::new();
+        assert!(arbiter.request_bus(CPU, 0, RequestType::Read, 4));
+        assert!(arbiter.request_bus(CPU, 4, RequestType::Read, 4), "the owner already has it");
+        assert!(!arbiter.request_bus(GPU, 0, RequestType::Write, 1));
+        assert!(!arbiter.request_bus(GPU, 0, RequestType::Write, 1));
+        assert_eq!(arbiter.get_queue_length(), 1);
+        assert_eq!(arbiter.device_stats(GPU).unwrap().requests, 1);
+
+        owners(&mut arbiter, 4);
+        assert_eq!(arbiter.get_current_owner(), Some(GPU));
+    }
+
+    const DMA: DeviceId = DeviceId::new(DeviceType::DMA, 0);
+    const MASTERS: [DeviceId; 3] = [CPU, GPU, DMA];
+
+    // Every master asks again as soon as it has nothing queued or granted
+    fn contend(arbiter: &mut BusArbiter, cycles: u8, ticks: usize) {
+        for _ in 0..ticks {
+            for master in MASTERS {
+                arbiter.request_bus(master, 0, RequestType::Read, cycles);
+            }
+            arbiter.tick();
+        }
+    }
+
+    fn grants(arbiter: &BusArbiter, device: DeviceId) -> u64 {
+        arbiter.device_stats(device).unwrap().grants
+    }
+
+    fn busy(arbiter: &BusArbiter, device: DeviceId) -> f64 {
+        arbiter.device_stats(device).unwrap().busy_cycles as f64
+    }
+
+    #[test]
+    fn fixed_priority_starves_the_lowest_master_and_says_so() {
+        let mut arbiter = BusArbiter::new();
+        arbiter.set_policy(ArbitrationPolicy::FixedPriority);
+        arbiter.set_starvation_threshold(100);
+        contend(&mut arbiter, 2, 300);
+
+        // CPU and GPU hand the bus back and forth; DMA never gets it
+        assert!(grants(&arbiter, CPU) > 50 && grants(&arbiter, GPU) > 50);
+        assert_eq!(grants(&arbiter, DMA), 0);
+        assert_eq!(arbiter.starving_devices(), [DMA]);
+
+        // Reported once, when it crossed the threshold
+        let events = arbiter.starvation_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].device, events[0].waited), (DMA, 100));
+        assert_eq!(arbiter.device_stats(DMA).unwrap().starvation_events, 1);
+        assert!(arbiter.fairness() < 0.7);
+    }
+
+    #[test]
+    fn round_robin_rotates_through_everyone_waiting() {
+        let mut arbiter = BusArbiter::new();
+        arbiter.set_policy(ArbitrationPolicy::RoundRobin);
+        contend(&mut arbiter, 2, 60);
+
+        let mut order: Vec<DeviceId> = arbiter.ownership_history().iter().flatten().copied().collect();
+        order.dedup();
+        let expected: Vec<DeviceId> = MASTERS.iter().copied().cycle().take(order.len()).collect();
+        assert_eq!(order, expected);
+        let counts: Vec<u64> = MASTERS.iter().map(|&master| grants(&arbiter, master)).collect();
+        assert!(counts.iter().max().unwrap() - counts.iter().min().unwrap() <= 1, "{:?}", counts);
+        assert!(arbiter.starvation_events().is_empty());
+    }
+
+    #[test]
+    fn aging_promotes_a_waiting_master_past_higher_ones() {
+        let mut arbiter = BusArbiter::new();
+        arbiter.set_policy(ArbitrationPolicy::Aging { interval: 2 });
+        arbiter.set_starvation_threshold(100);
+        contend(&mut arbiter, 2, 300);
+
+        assert!(grants(&arbiter, DMA) > 20);
+        let latency = &arbiter.device_stats(DMA).unwrap().latency;
+        assert!(latency.max < 20, "DMA waited {} cycles", latency.max);
+        assert!(arbiter.starvation_events().is_empty());
+    }
+
+    #[test]
+    fn weighted_fair_splits_bus_time_by_priority() {
+        let mut arbiter = BusArbiter::new();
+        arbiter.set_policy(ArbitrationPolicy::WeightedFair);
+        contend(&mut arbiter, 2, 600);
+
+        // Priorities are 3, 2 and 1
+        let dma = busy(&arbiter, DMA);
+        assert!((busy(&arbiter, CPU) / dma - 3.0).abs() < 0.2);
+        assert!((busy(&arbiter, GPU) / dma - 2.0).abs() < 0.2);
+        assert!(arbiter.fairness() > 0.99);
+    }
+
+    #[test]
+    fn grant_latencies_land_in_power_of_two_buckets() {
+        let mut histogram = LatencyHistogram::default();
+        for wait in [0, 1, 3, 4, 7, 8, 5000] {
+            histogram.record(wait);
+        }
+        assert_eq!(histogram.buckets[..5], [1, 1, 1, 2, 1]);
+        assert_eq!(histogram.buckets[LATENCY_BUCKETS - 1], 1, "the last bucket takes the rest");
+        assert_eq!((histogram.count, histogram.max), (7, 5000));
+        assert_eq!(histogram.percentile(0.5), LatencyHistogram::bucket_limit(3));
+        assert_eq!(histogram.percentile(1.0), 5000);
+
+        // Under contention every grant is recorded with what it waited
+        let mut arbiter = BusArbiter::new();
+        arbiter.set_policy(ArbitrationPolicy::RoundRobin);
+        contend(&mut arbiter, 2, 60);
+        let latency = &arbiter.device_stats(GPU).unwrap().latency;
+        assert_eq!(latency.count, grants(&arbiter, GPU));
+        assert_eq!(latency.max, 4, "two other masters' grants of two cycles");
+    }
+}
+
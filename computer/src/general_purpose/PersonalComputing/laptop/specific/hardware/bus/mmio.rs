@@ -0,0 +1,270 @@
+use super::BusError;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+
+const TRANSACTION_LOG_LIMIT: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessSize {
+    Byte,
+    Half,
+    Word,
+    Double,
+}
+
+// Register behaviour of a memory-mapped device. Offsets are relative to the
+// region base, and the map has already checked alignment and bounds.
+pub trait MmioDevice {
+    fn read(&mut self, offset: u64, size: AccessSize) -> Result<u64, BusError>;
+    fn write(&mut self, offset: u64, size: AccessSize, value: u64) -> Result<(), BusError>;
+
+    // Widths the registers accept; the map faults anything else
+    fn supports(&self, _size: AccessSize) -> bool {
+        true
+    }
+}
+
+// Devices stay owned by their subsystem; the map holds a handle to each
+pub type DeviceHandle = Rc<RefCell<dyn MmioDevice>>;
+
+// Address decoder for the device windows of the physical address space
+pub struct MmioMap {
+    regions: BTreeMap<u64, MmioRegion>, // By base address
+    log: VecDeque<MmioTransaction>,
+    cycle: u64,
+    stats: MmioStats,
+}
+
+struct MmioRegion {
+    name: String,
+    size: u64,
+    device: DeviceHandle,
+    accesses: u64,
+}
+
+// A decoded region, for drawing the address map
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionInfo {
+    pub name: String,
+    pub base: u64,
+    pub size: u64,
+    pub accesses: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MmioAccess {
+    Read,
+    Write,
+}
+
+// One access as it crossed the bus; failed ones are kept too
+#[derive(Debug, Clone, PartialEq)]
+pub struct MmioTransaction {
+    pub cycle: u64,
+    pub access: MmioAccess,
+    pub address: u64,
+    pub size: AccessSize,
+    pub value: u64, // Data read or written; zero when the access failed
+    pub region: Option<String>,
+    pub result: Result<(), BusError>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MmioStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub bytes: u64,
+    pub unmapped: u64,
+    pub faults: u64, // Misaligned, wrong width, or refused by the device
+}
+
+impl MmioMap {
+    pub fn new() -> Self {
+        Self {
+            regions: BTreeMap::new(),
+            log: VecDeque::new(),
+            cycle: 0,
+            stats: MmioStats::default(),
+        }
+    }
+
+    pub fn map(&mut self, name: &str, base: u64, size: u64, device: DeviceHandle) -> Result<(), BusError> {
+        let end = base.checked_add(size).ok_or(BusError::AddressOutOfRange)?;
+        if size == 0 {
+            return Err(BusError::AddressOutOfRange);
+        }
+        // The region below must end before this one starts, the one above after it ends
+        let below = self.regions.range(..end).next_back();
+        if let Some((&other, region)) = below {
+            if other + region.size > base {
+                return Err(BusError::RegionOverlap(other));
+            }
+        }
+
+        self.regions.insert(base, MmioRegion {
+            name: name.to_string(),
+            size,
+            device,
+            accesses: 0,
+        });
+        Ok(())
+    }
+
+    pub fn unmap(&mut self, base: u64) -> Result<(), BusError> {
+        self.regions.remove(&base).map(|_| ()).ok_or(BusError::DeviceNotFound)
+    }
+
+    pub fn read(&mut self, address: u64, size: AccessSize) -> Result<u64, BusError> {
+        let result = self.decode_access(address, size).and_then(|(base, offset)| {
+            let region = self.regions.get_mut(&base).ok_or(BusError::UnmappedAddress(address))?;
+            region.accesses += 1;
+            let value = region.device.borrow_mut().read(offset, size).map_err(|error| locate(error, address))?;
+            Ok(value & size.mask())
+        });
+        self.stats.reads += 1;
+        self.record(MmioAccess::Read, address, size, *result.as_ref().unwrap_or(&0), &result);
+        result
+    }
+
+    pub fn write(&mut self, address: u64, size: AccessSize, value: u64) -> Result<(), BusError> {
+        let value = value & size.mask();
+        let result = self.decode_access(address, size).and_then(|(base, offset)| {
+            let region = self.regions.get_mut(&base).ok_or(BusError::UnmappedAddress(address))?;
+            region.accesses += 1;
+            region.device.borrow_mut().write(offset, size, value).map_err(|error| locate(error, address))
+        });
+        self.stats.writes += 1;
+        let logged = if result.is_ok() { value } else { 0 };
+        self.record(MmioAccess::Write, address, size, logged, &result);
+        result
+    }
+
+    // Base and name of the region an address falls in
+    pub fn decode(&self, address: u64) -> Option<(u64, &str)> {
+        let (&base, region) = self.regions.range(..=address).next_back()?;
+        if address - base < region.size {
+            Some((base, region.name.as_str()))
+        } else {
+            None
+        }
+    }
+
+    pub fn is_mapped(&self, address: u64) -> bool {
+        self.decode(address).is_some()
+    }
+
+    pub fn tick(&mut self) {
+        self.cycle += 1;
+    }
+
+    // Methods for visualization system
+    pub fn regions(&self) -> Vec<RegionInfo> {
+        self.regions
+            .iter()
+            .map(|(&base, region)| RegionInfo {
+                name: region.name.clone(),
+                base,
+                size: region.size,
+                accesses: region.accesses,
+            })
+            .collect()
+    }
+
+    // Most recent accesses, oldest first
+    pub fn transactions(&self) -> &VecDeque<MmioTransaction> {
+        &self.log
+    }
+
+    pub fn clear_transactions(&mut self) {
+        self.log.clear();
+    }
+
+    pub fn get_stats(&self) -> &MmioStats {
+        &self.stats
+    }
+
+    // Helper methods
+    // Region base and offset for an access that fits wholly inside one region
+    fn decode_access(&self, address: u64, size: AccessSize) -> Result<(u64, u64), BusError> {
+        let (base, _) = self.decode(address).ok_or(BusError::UnmappedAddress(address))?;
+        let region = &self.regions[&base];
+        if !address.is_multiple_of(size.bytes()) {
+            return Err(BusError::MisalignedAccess(address));
+        }
+        if address - base + size.bytes() > region.size || !region.device.borrow().supports(size) {
+            return Err(BusError::UnsupportedAccessSize(address));
+        }
+        Ok((base, address - base))
+    }
+
+    fn record(&mut self, access: MmioAccess, address: u64, size: AccessSize, value: u64, result: &Result<impl Sized, BusError>) {
+        let result = match result {
+            Ok(_) => {
+                self.stats.bytes += size.bytes();
+                Ok(())
+            }
+            Err(error) => {
+                match error {
+                    BusError::UnmappedAddress(_) => self.stats.unmapped += 1,
+                    _ => self.stats.faults += 1,
+                }
+                Err(error.clone())
+            }
+        };
+        if self.log.len() == TRANSACTION_LOG_LIMIT {
+            self.log.pop_front();
+        }
+        self.log.push_back(MmioTransaction {
+            cycle: self.cycle,
+            access,
+            address,
+            size,
+            value,
+            region: self.decode(address).map(|(_, name)| name.to_string()),
+            result,
+        });
+    }
+}
+
+impl Default for MmioMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Devices report faults at their own offsets; the bus reports the address
+fn locate(error: BusError, address: u64) -> BusError {
+    match error {
+        BusError::DeviceFault(_) => BusError::DeviceFault(address),
+        error => error,
+    }
+}
+
+impl AccessSize {
+    pub fn bytes(self) -> u64 {
+        match self {
+            AccessSize::Byte => 1,
+            AccessSize::Half => 2,
+            AccessSize::Word => 4,
+            AccessSize::Double => 8,
+        }
+    }
+
+    pub fn from_bytes(bytes: u64) -> Option<Self> {
+        match bytes {
+            1 => Some(AccessSize::Byte),
+            2 => Some(AccessSize::Half),
+            4 => Some(AccessSize::Word),
+            8 => Some(AccessSize::Double),
+            _ => None,
+        }
+    }
+
+    pub fn mask(self) -> u64 {
+        match self {
+            AccessSize::Double => u64::MAX,
+            _ => (1 << (self.bytes() * 8)) - 1,
+        }
+    }
+}
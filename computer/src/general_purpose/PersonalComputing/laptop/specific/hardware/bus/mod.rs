@@ -1,18 +1,31 @@
-use super::memory::MainMemory;
-use super::cpu::CPU;
-use super::gpu::GPU;
-use super::power::Power;
+use super::memory::physical::PhysicalMemory;
+use self::arbitration::{ArbitrationPolicy, BusArbiter, DeviceId, DeviceType, RequestType};
+use self::mmio::{AccessSize, DeviceHandle, MmioMap};
+use self::pci_bus::PciBus;
+use self::pci_enumeration::PciFunction;
 
 pub mod arbitration;
 pub mod memory_bus;
+pub mod mmio;
 pub mod pci_bus;
+pub mod pci_config;
+pub mod pci_enumeration;
+pub mod pcie_link;
 pub mod system_bus;
 
+// Where the platform devices sit in the system window
+pub const TIMER_BASE: u64 = 0xC000_0000;
+pub const UART_BASE: u64 = 0xC001_0000;
+pub const PLIC_BASE: u64 = 0xC400_0000;
+
+const CPU_MASTER: DeviceId = DeviceId::new(DeviceType::CPU, 0);
+
 pub struct Bus {
     memory_bus: memory_bus::MemoryBus,
     pci_bus: pci_bus::PciBus,
     system_bus: system_bus::SystemBus,
-    arbitration: arbitration::BusArbiter,
+    arbitration: BusArbiter,
+    mmio: MmioMap,
     
     // Bus statistics for visualization
     total_transfers: u64,
@@ -20,72 +33,147 @@ pub struct Bus {
     bandwidth_usage: Vec<f32>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum BusError {
     AddressOutOfRange,
     DeviceNotFound,
     BusUnavailable,
     TransactionTimeout,
     ArbitrationFailed,
+
+    // Decode errors, with the faulting address
+    UnmappedAddress(u64),
+    MisalignedAccess(u64),
+    UnsupportedAccessSize(u64),
+    RegionOverlap(u64), // Base of the region already there
+    DeviceFault(u64),   // The device refused the access
 }
 
 impl Bus {
     pub fn new() -> Self {
         Self {
             memory_bus: memory_bus::MemoryBus::new(),
-            pci_bus: pci_bus::PciBus::new(),
+            pci_bus: PciBus::laptop(),
             system_bus: system_bus::SystemBus::new(),
-            arbitration: arbitration::BusArbiter::new(),
+            arbitration: BusArbiter::new(),
+            mmio: MmioMap::new(),
             total_transfers: 0,
             current_utilization: 0.0,
             bandwidth_usage: Vec::new(),
         }
     }
 
-    pub fn read(&mut self, address: u32) -> Result<Option<u32>, BusError> {
+    pub fn read<M: PhysicalMemory>(&mut self, address: u32, memory: &mut M) -> Result<Option<u32>, BusError> {
+        self.read_sized(address as u64, AccessSize::Word, memory).map(|value| Some(value as u32))
+    }
+
+    pub fn write<M: PhysicalMemory>(&mut self, address: u32, data: u32, memory: &mut M) -> Result<(), BusError> {
+        self.write_sized(address as u64, AccessSize::Word, data as u64, memory)
+    }
+
+    // Registers a device's register window; accesses to it go to the device
+    pub fn map_device(&mut self, name: &str, base: u64, size: u64, device: DeviceHandle) -> Result<(), BusError> {
+        self.mmio.map(name, base, size, device)
+    }
+
+    pub fn unmap_device(&mut self, base: u64) -> Result<(), BusError> {
+        self.mmio.unmap(base)
+    }
+
+    // Firmware's PCI bring-up: bus numbers, BAR addresses, bridge windows
+    pub fn enumerate_pci(&mut self) -> Vec<PciFunction> {
+        self.pci_bus.enumerate()
+    }
+
+    // Accesses without a named master come from core 0
+    pub fn read_sized<M: PhysicalMemory>(&mut self, address: u64, size: AccessSize, memory: &mut M) -> Result<u64, BusError> {
+        self.read_by(CPU_MASTER, address, size, memory)
+    }
+
+    pub fn write_sized<M: PhysicalMemory>(&mut self, address: u64, size: AccessSize, data: u64, memory: &mut M) -> Result<(), BusError> {
+        self.write_by(CPU_MASTER, address, size, data, memory)
+    }
+
+    // The master has to win arbitration first; until the arbiter grants it
+    // the bus the access fails with BusUnavailable and its request stays
+    // queued. The ECAM window is PCI config space. Otherwise mapped regions
+    // win; what's left of the memory window is the machine's RAM, lent for
+    // the access, and anything else is a bus error.
+    pub fn read_by<M: PhysicalMemory>(&mut self, master: DeviceId, address: u64, size: AccessSize, memory: &mut M) -> Result<u64, BusError> {
+        self.acquire(master, address, size, RequestType::Read)?;
         self.total_transfers += 1;
-        
-        // Check if bus is available through arbiter
-        if !self.arbitration.is_bus_available() {
-            return Err(BusError::BusUnavailable);
+
+        if PciBus::is_ecam(address) {
+            return self.pci_bus.ecam_read(address, size);
         }
-        
-        // Determine which bus to use based on address range
-        match self.get_bus_for_address(address) {
-            BusType::Memory => Ok(self.memory_bus.read(address)),
-            BusType::PCI => Ok(self.pci_bus.read(address)),
-            BusType::System => Ok(self.system_bus.read(address)),
+        if !self.mmio.is_mapped(address) {
+            if let Some(BusType::Memory) = self.get_bus_for_address(address) {
+                return self.dram_read(address, size, memory);
+            }
         }
+        self.mmio.read(address, size)
     }
 
-    pub fn write(&mut self, address: u32, data: u32) -> Result<(), BusError> {
+    pub fn write_by<M: PhysicalMemory>(&mut self, master: DeviceId, address: u64, size: AccessSize, data: u64, memory: &mut M) -> Result<(), BusError> {
+        self.acquire(master, address, size, RequestType::Write)?;
         self.total_transfers += 1;
-        
-        if !self.arbitration.is_bus_available() {
-            return Err(BusError::BusUnavailable);
+
+        if PciBus::is_ecam(address) {
+            return self.pci_bus.ecam_write(address, size, data);
         }
+        if !self.mmio.is_mapped(address) {
+            if let Some(BusType::Memory) = self.get_bus_for_address(address) {
+                return self.dram_write(address, size, data, memory);
+            }
+        }
+        self.mmio.write(address, size, data)
+    }
 
-        match self.get_bus_for_address(address) {
-            BusType::Memory => {
-                self.memory_bus.write(address, data);
-                Ok(())
-            },
-            BusType::PCI => {
-                self.pci_bus.write(address, data);
-                Ok(())
-            },
-            BusType::System => {
-                self.system_bus.write(address, data);
-                Ok(())
-            },
+    // A doubleword takes two beats of the 32-bit data path
+    fn acquire(&mut self, master: DeviceId, address: u64, size: AccessSize, kind: RequestType) -> Result<(), BusError> {
+        let beats = if size == AccessSize::Double { 2 } else { 1 };
+        if self.arbitration.request_bus(master, address as u32, kind, beats) {
+            Ok(())
+        } else {
+            Err(BusError::BusUnavailable)
         }
     }
 
-    fn get_bus_for_address(&self, address: u32) -> BusType {
+    // The memory bus moves aligned words, so a doubleword takes two
+    // requests; the bytes themselves come from RAM
+    fn dram_read<M: PhysicalMemory>(&mut self, address: u64, size: AccessSize, memory: &mut M) -> Result<u64, BusError> {
+        self.dram_request(address, size, false)?;
+        let mut bytes = [0u8; 8];
+        memory
+            .read_physical(address, &mut bytes[..size.bytes() as usize])
+            .map_err(|_| BusError::UnmappedAddress(address))?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn dram_write<M: PhysicalMemory>(&mut self, address: u64, size: AccessSize, data: u64, memory: &mut M) -> Result<(), BusError> {
+        self.dram_request(address, size, true)?;
+        memory
+            .write_physical(address, &data.to_le_bytes()[..size.bytes() as usize])
+            .map_err(|_| BusError::UnmappedAddress(address))
+    }
+
+    fn dram_request(&mut self, address: u64, size: AccessSize, is_write: bool) -> Result<(), BusError> {
+        if !address.is_multiple_of(size.bytes()) {
+            return Err(BusError::MisalignedAccess(address));
+        }
+        let words = if size == AccessSize::Double { 2 } else { 1 };
+        for word in 0..words {
+            self.memory_bus.request((address & !3) as u32 + word * 4, is_write);
+        }
+        Ok(())
+    }
+
+    fn get_bus_for_address(&self, address: u64) -> Option<BusType> {
         match address {
-            0x0000_0000..=0x7FFF_FFFF => BusType::Memory, // First 2GB for main memory
-            0x8000_0000..=0xBFFF_FFFF => BusType::PCI,    // Next 1GB for PCI devices
-            _ => BusType::System,                          // Rest for system devices
+            0x0000_0000..=0x7FFF_FFFF => Some(BusType::Memory), // First 2GB for main memory
+            0x8000_0000..=0xBFFF_FFFF => Some(BusType::PCI),    // Next 1GB for PCI devices
+            0xC000_0000..=0xFFFF_FFFF => Some(BusType::System), // Rest for system devices
+            _ => None,
         }
     }
 
@@ -102,12 +190,29 @@ impl Bus {
         self.total_transfers
     }
 
+    pub fn mmio(&self) -> &MmioMap {
+        &self.mmio
+    }
+
+    pub fn pci(&self) -> &PciBus {
+        &self.pci_bus
+    }
+
+    pub fn arbiter(&self) -> &BusArbiter {
+        &self.arbitration
+    }
+
+    pub fn set_arbitration_policy(&mut self, policy: ArbitrationPolicy) {
+        self.arbitration.set_policy(policy);
+    }
+
     pub fn tick(&mut self) {
         // Update all bus components
         self.memory_bus.tick();
         self.pci_bus.tick();
         self.system_bus.tick();
         self.arbitration.tick();
+        self.mmio.tick();
         
         // Update statistics
         self.update_utilization();
@@ -117,9 +222,9 @@ impl Bus {
     fn update_utilization(&mut self) {
         // Calculate current bus utilization
         let total_pending = 
-            self.memory_bus.pending_requests.len() +
-            self.pci_bus.pending_transactions.len() +
-            self.system_bus.pending_operations.len();
+            self.memory_bus.pending_count() +
+            self.pci_bus.pending_count() +
+            self.system_bus.pending_count();
         
         self.current_utilization = total_pending as f32 / 100.0;
     }
@@ -133,6 +238,12 @@ impl Bus {
     }
 }
 
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 enum BusType {
     Memory,
     PCI,
@@ -140,3 +251,97 @@ enum BusType {
 }
 
 
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::dma::coherence::CachedMemory;
+    use crate::hardware::io::devices::uart::Uart;
+    use crate::hardware::memory::physical::SystemRam;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn ram() -> SystemRam {
+        SystemRam::new(64 * 1024)
+    }
+
+    #[test]
+    fn narrow_dram_accesses_use_their_byte_lane() {
+        let (mut bus, mut ram) = (Bus::new(), ram());
+        bus.write_sized(0x1000, AccessSize::Word, 0x4433_2211, &mut ram).unwrap();
+        assert_eq!(bus.read_sized(0x1001, AccessSize::Byte, &mut ram), Ok(0x22));
+        assert_eq!(bus.read_sized(0x1002, AccessSize::Half, &mut ram), Ok(0x4433));
+
+        // Only the written lane changes
+        bus.write_sized(0x1003, AccessSize::Byte, 0xAA, &mut ram).unwrap();
+        bus.write_sized(0x1000, AccessSize::Half, 0xBBCC, &mut ram).unwrap();
+        assert_eq!(bus.read_sized(0x1000, AccessSize::Word, &mut ram), Ok(0xAA33_BBCC));
+    }
+
+    #[test]
+    fn doublewords_split_into_two_words() {
+        let (mut bus, mut ram) = (Bus::new(), ram());
+        bus.write_sized(0x2000, AccessSize::Double, 0x8877_6655_4433_2211, &mut ram).unwrap();
+        assert_eq!(bus.read(0x2000, &mut ram), Ok(Some(0x4433_2211)));
+        assert_eq!(bus.read(0x2004, &mut ram), Ok(Some(0x8877_6655)));
+        assert_eq!(bus.read_sized(0x2000, AccessSize::Double, &mut ram), Ok(0x8877_6655_4433_2211));
+
+        assert_eq!(bus.read_sized(0x2004, AccessSize::Double, &mut ram), Err(BusError::MisalignedAccess(0x2004)));
+        assert_eq!(bus.write_sized(0x2001, AccessSize::Half, 0, &mut ram), Err(BusError::MisalignedAccess(0x2001)));
+    }
+
+    #[test]
+    fn the_memory_window_is_the_ram_behind_the_caches() {
+        let mut bus = Bus::new();
+        let mut memory = CachedMemory::new(ram(), 16);
+
+        // A store still sitting dirty in the L3 is what a bus master reads
+        memory.cpu_write_u32(0x3000, 0x1234_5678).unwrap();
+        assert_eq!(bus.read_sized(0x3000, AccessSize::Word, &mut memory), Ok(0x1234_5678));
+
+        // And what the bus writes is what the CPU loads next
+        bus.write_sized(0x3004, AccessSize::Word, 0x9ABC_DEF0, &mut memory).unwrap();
+        assert_eq!(memory.cpu_read_u32(0x3004), Ok(0x9ABC_DEF0));
+        assert_eq!(memory.memory_mut().read_u32(0x3004), Ok(0x9ABC_DEF0));
+
+        // Past the end of RAM nothing answers
+        assert_eq!(bus.read_sized(0x10_0000, AccessSize::Word, &mut memory), Err(BusError::UnmappedAddress(0x10_0000)));
+    }
+
+    #[test]
+    fn mapped_devices_win_over_dram_and_the_rest_is_unmapped() {
+        let (mut bus, mut ram) = (Bus::new(), ram());
+        let uart = Rc::new(RefCell::new(Uart::new()));
+        bus.map_device("uart", 0x3000, 8, uart.clone()).unwrap();
+        bus.write_sized(0x3000, AccessSize::Byte, b'A' as u64, &mut ram).unwrap();
+        assert_eq!(uart.borrow_mut().take_output(), b"A");
+        assert_eq!(ram.read_u32(0x3000), Ok(0));
+        assert_eq!(bus.read_sized(0x3000, AccessSize::Word, &mut ram), Err(BusError::UnsupportedAccessSize(0x3000)));
+
+        assert_eq!(bus.read_sized(UART_BASE, AccessSize::Byte, &mut ram), Err(BusError::UnmappedAddress(UART_BASE)));
+    }
+
+    #[test]
+    fn masters_wait_for_a_grant() {
+        let (mut bus, mut ram) = (Bus::new(), ram());
+        let dma = DeviceId::new(DeviceType::DMA, 0);
+        bus.write_sized(0x4000, AccessSize::Double, 7, &mut ram).unwrap();
+
+        // Core 0 holds the bus for the doubleword's two beats
+        assert_eq!(bus.read_by(dma, 0x4000, AccessSize::Word, &mut ram), Err(BusError::BusUnavailable));
+        bus.tick();
+        assert_eq!(bus.read_by(dma, 0x4000, AccessSize::Word, &mut ram), Err(BusError::BusUnavailable));
+        bus.tick();
+        assert_eq!(bus.read_by(dma, 0x4000, AccessSize::Word, &mut ram), Ok(7));
+        assert_eq!(bus.arbiter().get_current_owner(), Some(dma));
+    }
+
+    #[test]
+    fn ecam_reaches_config_space() {
+        let (mut bus, mut ram) = (Bus::new(), ram());
+        let host_bridge = bus.read_sized(pci_bus::ECAM_BASE, AccessSize::Word, &mut ram).unwrap();
+        assert_ne!(host_bridge & 0xFFFF, 0xFFFF, "bus 0 device 0 is there");
+    }
+}
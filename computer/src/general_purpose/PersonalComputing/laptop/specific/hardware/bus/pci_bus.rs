@@ -1,56 +1,217 @@
+use super::mmio::AccessSize;
+use super::pci_config::*;
+use super::pci_enumeration::{self, PciFunction};
+use super::pcie_link::{LinkConfig, PcieLink, Tlp, TlpType, TransferCost};
+use super::BusError;
+use std::collections::BTreeMap;
+
+// Pending transactions count down in periods of the 100 MHz reference clock
+const REFCLK_NS: u64 = 10;
+
+// Enhanced configuration access: every function's 4 KiB config space,
+// memory-mapped at bus << 20 | device << 15 | function << 12
+pub const ECAM_BASE: u64 = 0xB000_0000;
+pub const ECAM_SIZE: u64 = 256 << 20;
+
+// The bus the root complex sits on
+pub const ROOT_SEGMENT: usize = 0;
+
 pub struct PciBus {
-    devices: Vec<PCIDevice>,
-    bandwidth: u64,          // PCIe bandwidth (16 GT/s for PCIe 4.0 x16)
+    segments: Vec<Segment>,
+    link: PcieLink, // The GPU's Gen4 x16 link to the root complex
     current_load: u64,
     pending_transactions: Vec<PCITransaction>,
+    bulk_transfers: u64,
+    bulk_bytes: u64,
+    config_reads: u64,
+    config_writes: u64,
+}
+
+// One physical bus. Its number is whatever its bridge's secondary bus
+// register says, so it only has one after enumeration.
+struct Segment {
+    parent: Option<(usize, u8, u8)>, // Segment, device and function of the bridge above
+    slots: BTreeMap<(u8, u8), PCIDevice>, // By device and function
 }
 
 struct PCIDevice {
-    id: u16,
-    vendor_id: u16,
-    device_type: DeviceType,
-    base_address: u32,
-    interrupt_line: u8,
-    enabled: bool,
+    config: ConfigSpace,
+    downstream: Option<usize>, // Bridges: the segment behind them
 }
 
 struct PCITransaction {
-    device_id: u16,
-    address: u32,
+    target: PciAddress,
+    tlp: Tlp,
+    #[allow(dead_code)]
     data: Option<u32>,
-    is_write: bool,
     cycles_remaining: u32,
 }
 
-enum DeviceType {
-    GPU,
-    NetworkCard,
-    SoundCard,
-    StorageController,
-    Other,
-}
-
 impl PciBus {
     pub fn new() -> Self {
         Self {
-            devices: Vec::new(),
-            bandwidth: 32_000_000_000, // 32 GB/s
+            segments: vec![Segment { parent: None, slots: BTreeMap::new() }],
+            link: PcieLink::new(LinkConfig::default()),
             current_load: 0,
             pending_transactions: Vec::new(),
+            bulk_transfers: 0,
+            bulk_bytes: 0,
+            config_reads: 0,
+            config_writes: 0,
+        }
+    }
+
+    // The laptop's hierarchy: a host bridge and three root ports, with the
+    // discrete GPU (plus its HDMI audio function), the NVMe SSD and the Wi-Fi
+    // card behind them
+    pub fn laptop() -> Self {
+        let mut pci = Self::new();
+        let host = ClassCode { class: 0x06, subclass: 0x00, prog_if: 0x00 };
+        pci.attach(ROOT_SEGMENT, 0x00, 0, ConfigSpace::endpoint(0x8086, 0x9A14, host).with_revision(0x01));
+
+        let root_port = |device_id, width| {
+            ConfigSpace::bridge(0x8086, device_id)
+                .with_revision(0x01)
+                .with_interrupt_pin(1)
+                .with_capability(Capability::PciExpress { port: PortType::RootPort, generation: 4, width })
+                .with_capability(Capability::Msi { vectors: 1 })
+                .with_capability(Capability::PowerManagement)
+        };
+
+        let graphics = pci.attach(ROOT_SEGMENT, 0x01, 0, root_port(0x9A09, 16)).unwrap_or(ROOT_SEGMENT);
+        let gpu = ClassCode { class: 0x03, subclass: 0x00, prog_if: 0x00 };
+        pci.attach(graphics, 0x00, 0, ConfigSpace::endpoint(0x10DE, 0x2520, gpu)
+            .with_revision(0xA1)
+            .multifunction()
+            .with_interrupt_pin(1)
+            .with_bar(0, BarKind::Memory32 { size: 16 << 20, prefetchable: false })  // Registers
+            .with_bar(1, BarKind::Memory64 { size: 256 << 20, prefetchable: true })  // VRAM aperture
+            .with_bar(3, BarKind::Memory64 { size: 32 << 20, prefetchable: true })   // Doorbells
+            .with_bar(5, BarKind::Io { size: 128 })
+            .with_capability(Capability::PowerManagement)
+            .with_capability(Capability::Msi { vectors: 1 })
+            .with_capability(Capability::PciExpress { port: PortType::Endpoint, generation: 4, width: 16 }));
+        let audio = ClassCode { class: 0x04, subclass: 0x03, prog_if: 0x00 };
+        pci.attach(graphics, 0x00, 1, ConfigSpace::endpoint(0x10DE, 0x228E, audio)
+            .with_revision(0xA1)
+            .with_interrupt_pin(2)
+            .with_bar(0, BarKind::Memory32 { size: 16 << 10, prefetchable: false })
+            .with_capability(Capability::PowerManagement)
+            .with_capability(Capability::Msi { vectors: 1 })
+            .with_capability(Capability::PciExpress { port: PortType::Endpoint, generation: 4, width: 16 }));
+
+        let storage = pci.attach(ROOT_SEGMENT, 0x06, 0, root_port(0x9A0F, 4)).unwrap_or(ROOT_SEGMENT);
+        let nvme = ClassCode { class: 0x01, subclass: 0x08, prog_if: 0x02 };
+        pci.attach(storage, 0x00, 0, ConfigSpace::endpoint(0x144D, 0xA80A, nvme)
+            .with_interrupt_pin(1)
+            .with_bar(0, BarKind::Memory64 { size: 16 << 10, prefetchable: false })
+            .with_capability(Capability::PowerManagement)
+            .with_capability(Capability::Msi { vectors: 32 })
+            .with_capability(Capability::PciExpress { port: PortType::Endpoint, generation: 4, width: 4 })
+            .with_capability(Capability::MsiX { table_size: 33, bar: 0, table_offset: 0x3000, pba_offset: 0x2000 }));
+
+        let wireless = pci.attach(ROOT_SEGMENT, 0x1C, 0, root_port(0xA0B8, 1)).unwrap_or(ROOT_SEGMENT);
+        let network = ClassCode { class: 0x02, subclass: 0x80, prog_if: 0x00 };
+        pci.attach(wireless, 0x00, 0, ConfigSpace::endpoint(0x8086, 0x2725, network)
+            .with_revision(0x1A)
+            .with_interrupt_pin(1)
+            .with_bar(0, BarKind::Memory64 { size: 16 << 10, prefetchable: false })
+            .with_capability(Capability::PowerManagement)
+            .with_capability(Capability::Msi { vectors: 1 })
+            .with_capability(Capability::PciExpress { port: PortType::Endpoint, generation: 2, width: 1 })
+            .with_capability(Capability::MsiX { table_size: 16, bar: 0, table_offset: 0x2000, pba_offset: 0x3000 }));
+
+        pci
+    }
+
+    // Plugs a function into a slot. A bridge brings a new, empty segment
+    // behind it, which is returned.
+    pub fn attach(&mut self, segment: usize, device: u8, function: u8, config: ConfigSpace) -> Option<usize> {
+        if segment >= self.segments.len() {
+            return None;
         }
+        let downstream = match config.header_type() {
+            HeaderType::Bridge => {
+                self.segments.push(Segment { parent: Some((segment, device, function)), slots: BTreeMap::new() });
+                Some(self.segments.len() - 1)
+            }
+            HeaderType::Endpoint => None,
+        };
+        self.segments[segment].slots.insert((device & 0x1F, function & 0x7), PCIDevice { config, downstream });
+        downstream
+    }
+
+    // Firmware's pass over the hierarchy; see `pci_enumeration::enumerate`
+    pub fn enumerate(&mut self) -> Vec<PciFunction> {
+        pci_enumeration::enumerate(self)
+    }
+
+    // Config cycles through the ECAM window. Config space takes 1, 2 and
+    // 4 byte accesses, naturally aligned.
+    pub fn ecam_read(&mut self, address: u64, size: AccessSize) -> Result<u64, BusError> {
+        let (target, register) = self.ecam_decode(address, size)?;
+        Ok(self.config_read(target, register, size.bytes() as usize) as u64)
+    }
+
+    pub fn ecam_write(&mut self, address: u64, size: AccessSize, data: u64) -> Result<(), BusError> {
+        let (target, register) = self.ecam_decode(address, size)?;
+        self.config_write(target, register, size.bytes() as usize, data as u32);
+        Ok(())
+    }
+
+    pub fn is_ecam(address: u64) -> bool {
+        (ECAM_BASE..ECAM_BASE + ECAM_SIZE).contains(&address)
+    }
+
+    pub fn with_link(mut self, config: LinkConfig) -> Self {
+        self.link = PcieLink::new(config);
+        self
+    }
+
+    // Block DMAs across the link, such as page migrations, as the device
+    // sees them: reading host memory or writing it. Return nanoseconds.
+    pub fn dma_read(&mut self, address: u64, bytes: u64) -> u64 {
+        self.bulk_transfers += 1;
+        self.bulk_bytes += bytes;
+        self.link.dma_read(address, bytes).ns
+    }
+
+    pub fn dma_write(&mut self, address: u64, bytes: u64) -> u64 {
+        self.bulk_transfers += 1;
+        self.bulk_bytes += bytes;
+        self.link.dma_write(address, bytes).ns
+    }
+
+    pub fn link(&self) -> &PcieLink {
+        &self.link
+    }
+
+    // Packet breakdown of the most recent DMA or register access
+    pub fn last_transfer(&self) -> &TransferCost {
+        self.link.last_transfer()
+    }
+
+    // Bulk transfers made and bytes they moved
+    pub fn bulk_traffic(&self) -> (u64, u64) {
+        (self.bulk_transfers, self.bulk_bytes)
+    }
+
+    // Config reads and writes, however they arrived
+    pub fn config_traffic(&self) -> (u64, u64) {
+        (self.config_reads, self.config_writes)
     }
 
     pub fn read(&mut self, address: u32) -> Option<u32> {
         // Find target device
-        let device = self.find_device_by_address(address)?;
-        
-        // Create transaction
+        let target = self.find_device_by_address(address)?;
+
+        // Create transaction: MRd down, CplD back
+        let cost = self.link.mmio_read(address as u64, 4);
         self.pending_transactions.push(PCITransaction {
-            device_id: device.id,
-            address,
+            target,
+            tlp: Tlp { kind: TlpType::MemRead, address: address as u64, length: 4, tag: 0 },
             data: None,
-            is_write: false,
-            cycles_remaining: 10, // PCI latency
+            cycles_remaining: cost.ns.div_ceil(REFCLK_NS) as u32,
         });
 
         // Simulate read
@@ -58,26 +219,24 @@ impl PciBus {
     }
 
     pub fn write(&mut self, address: u32, data: u32) {
-        if let Some(device) = self.find_device_by_address(address) {
+        if let Some(target) = self.find_device_by_address(address) {
+            let cost = self.link.mmio_write(address as u64, 4);
             self.pending_transactions.push(PCITransaction {
-                device_id: device.id,
-                address,
+                target,
+                tlp: Tlp { kind: TlpType::MemWrite, address: address as u64, length: 4, tag: 0 },
                 data: Some(data),
-                is_write: true,
-                cycles_remaining: 10,
+                cycles_remaining: cost.ns.div_ceil(REFCLK_NS) as u32,
             });
         }
     }
 
-    fn find_device_by_address(&self, address: u32) -> Option<&PCIDevice> {
-        self.devices.iter().find(|dev| {
-            address >= dev.base_address && 
-            address < dev.base_address + 0x1000 // 4KB per device
-        })
+    pub fn pending_count(&self) -> usize {
+        self.pending_transactions.len()
     }
 
-    pub fn register_device(&mut self, device: PCIDevice) {
-        self.devices.push(device);
+    // Register accesses still crossing the link: target and request TLP
+    pub fn in_flight(&self) -> Vec<(PciAddress, Tlp)> {
+        self.pending_transactions.iter().map(|transaction| (transaction.target, transaction.tlp)).collect()
     }
 
     pub fn tick(&mut self) {
@@ -87,9 +246,97 @@ impl PciBus {
                 transaction.cycles_remaining -= 1;
                 true
             } else {
-                self.current_load -= 1;
+                self.current_load = self.current_load.saturating_sub(1);
                 false
             }
         });
     }
+
+    // Helper methods
+    // The function whose enabled memory BAR claims the address
+    fn find_device_by_address(&self, address: u32) -> Option<PciAddress> {
+        for (index, segment) in self.segments.iter().enumerate() {
+            for (&(device, function), slot) in &segment.slots {
+                if slot.config.command() & COMMAND_MEMORY == 0 {
+                    continue;
+                }
+                let claims = (0..6)
+                    .filter_map(|bar| slot.config.memory_bar(bar))
+                    .any(|(base, size)| base != 0 && (base..base + size).contains(&(address as u64)));
+                if claims {
+                    return Some(PciAddress::new(self.bus_number(index)?, device, function));
+                }
+            }
+        }
+        None
+    }
+
+    fn ecam_decode(&self, address: u64, size: AccessSize) -> Result<(PciAddress, u16), BusError> {
+        if !Self::is_ecam(address) {
+            return Err(BusError::UnmappedAddress(address));
+        }
+        if !address.is_multiple_of(size.bytes()) {
+            return Err(BusError::MisalignedAccess(address));
+        }
+        if size == AccessSize::Double {
+            return Err(BusError::UnsupportedAccessSize(address));
+        }
+        Ok(PciAddress::from_ecam_offset(address - ECAM_BASE))
+    }
+
+    // Bus number a segment answers to, from the bridge above it
+    fn bus_number(&self, segment: usize) -> Option<u8> {
+        match self.segments.get(segment)?.parent {
+            None => Some(0),
+            Some((parent, device, function)) => {
+                let (secondary, _) = self.segments.get(parent)?.slots.get(&(device, function))?.config.bus_range()?;
+                (secondary != 0).then_some(secondary)
+            }
+        }
+    }
+
+    // Type 0 cycles stop at the segment numbered like the target; type 1
+    // cycles are forwarded by whichever bridge's bus range covers it.
+    // Returns the segment the target sits on.
+    fn route(&self, target: PciAddress) -> Option<usize> {
+        let mut segment = ROOT_SEGMENT;
+        let mut number = 0;
+        while number != target.bus {
+            let (next, secondary) = self.segments[segment].slots.values().find_map(|slot| {
+                let (secondary, subordinate) = slot.config.bus_range()?;
+                let forwards = secondary > number && (secondary..=subordinate).contains(&target.bus);
+                forwards.then_some((slot.downstream?, secondary))
+            })?;
+            segment = next;
+            number = secondary;
+        }
+        Some(segment)
+    }
+}
+
+impl ConfigAccess for PciBus {
+    fn config_read(&mut self, address: PciAddress, register: u16, bytes: usize) -> u32 {
+        self.config_reads += 1;
+        let slot = self.route(address).and_then(|segment| self.segments[segment].slots.get(&(address.device, address.function)));
+        match slot {
+            Some(slot) => slot.config.read(register, bytes),
+            None => u32::MAX >> (32 - bytes.clamp(1, 4) * 8),
+        }
+    }
+
+    fn config_write(&mut self, address: PciAddress, register: u16, bytes: usize, value: u32) {
+        self.config_writes += 1;
+        let Some(segment) = self.route(address) else {
+            return;
+        };
+        if let Some(slot) = self.segments[segment].slots.get_mut(&(address.device, address.function)) {
+            slot.config.write(register, bytes, value);
+        }
+    }
+}
+
+impl Default for PciBus {
+    fn default() -> Self {
+        Self::new()
+    }
 }
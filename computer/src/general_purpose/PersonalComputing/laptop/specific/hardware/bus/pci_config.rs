@@ -0,0 +1,430 @@
+use std::fmt;
+
+// 4 KiB PCIe configuration space of one function. Every byte has a write
+// mask, so software sees exactly what hardware would: read-only IDs, BARs
+// that read back their size after all-ones is written, and so on.
+pub const CONFIG_SPACE_SIZE: usize = 4096;
+
+// Common header
+pub const PCI_VENDOR_ID: u16 = 0x00;
+pub const PCI_DEVICE_ID: u16 = 0x02;
+pub const PCI_COMMAND: u16 = 0x04;
+pub const PCI_STATUS: u16 = 0x06;
+pub const PCI_REVISION: u16 = 0x08;
+pub const PCI_PROG_IF: u16 = 0x09;
+pub const PCI_SUBCLASS: u16 = 0x0A;
+pub const PCI_CLASS: u16 = 0x0B;
+pub const PCI_CACHE_LINE: u16 = 0x0C;
+pub const PCI_HEADER_TYPE: u16 = 0x0E;
+pub const PCI_BAR0: u16 = 0x10;
+pub const PCI_CAPABILITIES: u16 = 0x34;
+pub const PCI_INTERRUPT_LINE: u16 = 0x3C;
+pub const PCI_INTERRUPT_PIN: u16 = 0x3D;
+
+// Type 1 (bridge) header
+pub const PCI_PRIMARY_BUS: u16 = 0x18;
+pub const PCI_SECONDARY_BUS: u16 = 0x19;
+pub const PCI_SUBORDINATE_BUS: u16 = 0x1A;
+pub const PCI_MEMORY_BASE: u16 = 0x20; // Bits 15:4 are address bits 31:20
+pub const PCI_MEMORY_LIMIT: u16 = 0x22;
+
+// Command bits
+pub const COMMAND_IO: u16 = 1 << 0;
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+pub const STATUS_CAPABILITIES: u16 = 1 << 4;
+pub const HEADER_MULTIFUNCTION: u8 = 0x80;
+
+// Capability IDs
+pub const CAP_POWER_MANAGEMENT: u8 = 0x01;
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_PCI_EXPRESS: u8 = 0x10;
+pub const CAP_MSIX: u8 = 0x11;
+
+const FIRST_CAPABILITY: u16 = 0x40;
+
+// Bus/device/function of one config space, as lspci prints it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,   // 0 to 31
+    pub function: u8, // 0 to 7
+}
+
+// Config cycles, however they reach the hierarchy. Absent functions read
+// all-ones and drop writes, like a master abort.
+pub trait ConfigAccess {
+    fn config_read(&mut self, address: PciAddress, register: u16, bytes: usize) -> u32;
+    fn config_write(&mut self, address: PciAddress, register: u16, bytes: usize, value: u32);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderType {
+    Endpoint, // Type 0
+    Bridge,   // Type 1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClassCode {
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarKind {
+    Memory32 { size: u64, prefetchable: bool },
+    Memory64 { size: u64, prefetchable: bool }, // Takes this BAR and the next
+    Io { size: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortType {
+    Endpoint = 0x0,
+    RootPort = 0x4,
+    UpstreamPort = 0x5,
+    DownstreamPort = 0x6,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    PowerManagement,
+    Msi { vectors: u8 }, // A power of two, up to 32
+    MsiX { table_size: u16, bar: u8, table_offset: u32, pba_offset: u32 },
+    PciExpress { port: PortType, generation: u8, width: u8 },
+}
+
+impl PciAddress {
+    pub fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self { bus, device: device & 0x1F, function: function & 0x7 }
+    }
+
+    // Offset into the ECAM window: 1 MiB per bus, 32 KiB per device, 4 KiB per function
+    pub fn ecam_offset(self, register: u16) -> u64 {
+        (self.bus as u64) << 20 | (self.device as u64) << 15 | (self.function as u64) << 12 | (register as u64 & 0xFFF)
+    }
+
+    pub fn from_ecam_offset(offset: u64) -> (Self, u16) {
+        let address = Self::new((offset >> 20) as u8, (offset >> 15) as u8, (offset >> 12) as u8);
+        (address, (offset & 0xFFF) as u16)
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+#[derive(Clone)]
+pub struct ConfigSpace {
+    data: Vec<u8>,
+    write_mask: Vec<u8>,
+    bars: [Option<BarKind>; 6],
+    header: HeaderType,
+    last_capability: Option<u16>,
+    next_capability: u16,
+}
+
+impl ConfigSpace {
+    pub fn endpoint(vendor_id: u16, device_id: u16, class: ClassCode) -> Self {
+        let mut space = Self::blank(vendor_id, device_id, class, HeaderType::Endpoint);
+        space.set_mask(PCI_CACHE_LINE, &[0xFF]);
+        space.set_mask(PCI_INTERRUPT_LINE, &[0xFF]);
+        space
+    }
+
+    // A PCI-to-PCI bridge or PCIe port: forwards bus numbers and a memory window
+    pub fn bridge(vendor_id: u16, device_id: u16) -> Self {
+        let class = ClassCode { class: 0x06, subclass: 0x04, prog_if: 0x00 };
+        let mut space = Self::blank(vendor_id, device_id, class, HeaderType::Bridge);
+        space.set_mask(PCI_PRIMARY_BUS, &[0xFF, 0xFF, 0xFF]);
+        space.set_mask(PCI_MEMORY_BASE, &[0xF0, 0xFF, 0xF0, 0xFF]);
+        space.set_mask(PCI_INTERRUPT_LINE, &[0xFF]);
+        space
+    }
+
+    pub fn with_revision(mut self, revision: u8) -> Self {
+        self.data[PCI_REVISION as usize] = revision;
+        self
+    }
+
+    // INTA# through INTD#, 1 to 4
+    pub fn with_interrupt_pin(mut self, pin: u8) -> Self {
+        self.data[PCI_INTERRUPT_PIN as usize] = pin;
+        self
+    }
+
+    pub fn multifunction(mut self) -> Self {
+        self.data[PCI_HEADER_TYPE as usize] |= HEADER_MULTIFUNCTION;
+        self
+    }
+
+    pub fn with_bar(mut self, index: usize, kind: BarKind) -> Self {
+        let register = PCI_BAR0 + index as u16 * 4;
+        let (flags, mask) = match kind {
+            BarKind::Memory32 { size, prefetchable } => ((prefetchable as u32) << 3, !(size.next_power_of_two() as u32 - 1) & !0xF),
+            BarKind::Memory64 { size, prefetchable } => {
+                let mask = !(size.next_power_of_two() - 1);
+                self.set_mask(register + 4, &((mask >> 32) as u32).to_le_bytes());
+                (0x4 | (prefetchable as u32) << 3, mask as u32 & !0xF)
+            }
+            BarKind::Io { size } => (0x1, !(size.next_power_of_two() - 1) & !0x3),
+        };
+        self.write_raw(register, &flags.to_le_bytes());
+        self.set_mask(register, &mask.to_le_bytes());
+        self.bars[index] = Some(kind);
+        self
+    }
+
+    // Appends to the capability list
+    pub fn with_capability(mut self, capability: Capability) -> Self {
+        let offset = self.next_capability;
+        let (id, body, mask): (u8, Vec<u8>, Vec<u8>) = match capability {
+            Capability::PowerManagement => (CAP_POWER_MANAGEMENT, vec![0x03, 0x00, 0x00, 0x00, 0x00, 0x00], vec![0, 0, 0x03, 0x81, 0, 0]),
+            Capability::Msi { vectors } => {
+                // 64-bit capable; multiple message capable in bits 3:1
+                let control = 0x80 | ((vectors.max(1).next_power_of_two().trailing_zeros() as u8) << 1);
+                let mut mask = vec![0x71, 0x00];
+                mask.extend([0xFC, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+                let mut body = vec![control, 0x00];
+                body.extend([0; 10]);
+                (CAP_MSI, body, mask)
+            }
+            Capability::MsiX { table_size, bar, table_offset, pba_offset } => {
+                let control = (table_size.max(1) - 1) & 0x7FF;
+                let mut body = control.to_le_bytes().to_vec();
+                body.extend((table_offset & !0x7 | bar as u32).to_le_bytes());
+                body.extend((pba_offset & !0x7 | bar as u32).to_le_bytes());
+                let mut mask = vec![0x00, 0xC0];
+                mask.extend([0; 8]);
+                (CAP_MSIX, body, mask)
+            }
+            Capability::PciExpress { port, generation, width } => {
+                let mut body = vec![0; 0x3A];
+                body[0..2].copy_from_slice(&(0x2 | (port as u16) << 4).to_le_bytes());
+                // Link capabilities: max speed and width; link status: current ones
+                let link = generation as u32 & 0xF | (width as u32 & 0x3F) << 4;
+                body[0x0A..0x0E].copy_from_slice(&link.to_le_bytes());
+                body[0x10..0x12].copy_from_slice(&(link as u16).to_le_bytes());
+                let mut mask = vec![0; 0x3A];
+                mask[0x06..0x08].copy_from_slice(&[0xFF, 0x7F]); // Device control
+                mask[0x0E..0x10].copy_from_slice(&[0xFB, 0x0F]); // Link control
+                (CAP_PCI_EXPRESS, body, mask)
+            }
+        };
+
+        self.data[offset as usize] = id;
+        self.write_raw(offset + 2, &body);
+        self.set_mask(offset + 2, &mask);
+        match self.last_capability {
+            Some(last) => self.data[last as usize + 1] = offset as u8,
+            None => self.data[PCI_CAPABILITIES as usize] = offset as u8,
+        }
+        let status = self.read_raw(PCI_STATUS, 2) as u16 | STATUS_CAPABILITIES;
+        self.write_raw(PCI_STATUS, &status.to_le_bytes());
+        self.last_capability = Some(offset);
+        self.next_capability = (offset + 2 + body.len() as u16).next_multiple_of(4);
+        self
+    }
+
+    // Little-endian read of 1, 2 or 4 bytes
+    pub fn read(&self, register: u16, bytes: usize) -> u32 {
+        if register as usize + bytes > CONFIG_SPACE_SIZE {
+            return u32::MAX;
+        }
+        self.read_raw(register, bytes)
+    }
+
+    // Only the writable bits change
+    pub fn write(&mut self, register: u16, bytes: usize, value: u32) {
+        if register as usize + bytes > CONFIG_SPACE_SIZE {
+            return;
+        }
+        for (index, byte) in value.to_le_bytes().iter().take(bytes).enumerate() {
+            let at = register as usize + index;
+            let mask = self.write_mask[at];
+            self.data[at] = (self.data[at] & !mask) | (byte & mask);
+        }
+    }
+
+    pub fn header_type(&self) -> HeaderType {
+        self.header
+    }
+
+    pub fn command(&self) -> u16 {
+        self.read_raw(PCI_COMMAND, 2) as u16
+    }
+
+    // Base and size of a memory BAR, once software has programmed it
+    pub fn memory_bar(&self, index: usize) -> Option<(u64, u64)> {
+        let register = PCI_BAR0 + index as u16 * 4;
+        match self.bars.get(index).copied().flatten()? {
+            BarKind::Memory32 { size, .. } => Some(((self.read_raw(register, 4) & !0xF) as u64, size.next_power_of_two())),
+            BarKind::Memory64 { size, .. } => {
+                let low = (self.read_raw(register, 4) & !0xF) as u64;
+                let high = self.read_raw(register + 4, 4) as u64;
+                Some((high << 32 | low, size.next_power_of_two()))
+            }
+            BarKind::Io { .. } => None,
+        }
+    }
+
+    pub fn bar_kind(&self, index: usize) -> Option<BarKind> {
+        self.bars.get(index).copied().flatten()
+    }
+
+    // Bridge only: the bus numbers it forwards, secondary through subordinate
+    pub fn bus_range(&self) -> Option<(u8, u8)> {
+        if self.header != HeaderType::Bridge {
+            return None;
+        }
+        Some((self.data[PCI_SECONDARY_BUS as usize], self.data[PCI_SUBORDINATE_BUS as usize]))
+    }
+
+    // Bridge only: the memory window it forwards downstream, if open
+    pub fn memory_window(&self) -> Option<(u64, u64)> {
+        if self.header != HeaderType::Bridge {
+            return None;
+        }
+        let base = ((self.read_raw(PCI_MEMORY_BASE, 2) & 0xFFF0) as u64) << 16;
+        let limit = ((self.read_raw(PCI_MEMORY_LIMIT, 2) & 0xFFF0) as u64) << 16 | 0xF_FFFF;
+        if limit < base {
+            return None;
+        }
+        Some((base, limit + 1))
+    }
+
+    // Helper methods
+    fn blank(vendor_id: u16, device_id: u16, class: ClassCode, header: HeaderType) -> Self {
+        let mut space = Self {
+            data: vec![0; CONFIG_SPACE_SIZE],
+            write_mask: vec![0; CONFIG_SPACE_SIZE],
+            bars: [None; 6],
+            header,
+            last_capability: None,
+            next_capability: FIRST_CAPABILITY,
+        };
+        space.write_raw(PCI_VENDOR_ID, &vendor_id.to_le_bytes());
+        space.write_raw(PCI_DEVICE_ID, &device_id.to_le_bytes());
+        space.write_raw(PCI_PROG_IF, &[class.prog_if, class.subclass, class.class]);
+        space.data[PCI_HEADER_TYPE as usize] = match header {
+            HeaderType::Endpoint => 0x00,
+            HeaderType::Bridge => 0x01,
+        };
+        space.set_mask(PCI_COMMAND, &(COMMAND_IO | COMMAND_MEMORY | COMMAND_BUS_MASTER | COMMAND_INTX_DISABLE).to_le_bytes());
+        space
+    }
+
+    fn read_raw(&self, register: u16, bytes: usize) -> u32 {
+        let start = register as usize;
+        let mut value = [0u8; 4];
+        value[..bytes].copy_from_slice(&self.data[start..start + bytes]);
+        u32::from_le_bytes(value)
+    }
+
+    fn write_raw(&mut self, register: u16, bytes: &[u8]) {
+        let start = register as usize;
+        self.data[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn set_mask(&mut self, register: u16, mask: &[u8]) {
+        let start = register as usize;
+        self.write_mask[start..start + mask.len()].copy_from_slice(mask);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NVME: ClassCode = ClassCode { class: 0x01, subclass: 0x08, prog_if: 0x02 };
+
+    #[test]
+    fn addresses_round_trip_through_the_ecam_offset() {
+        let address = PciAddress::new(2, 0x1F, 7);
+        let offset = address.ecam_offset(0x104);
+        assert_eq!(offset, 2 << 20 | 0x1F << 15 | 7 << 12 | 0x104);
+        assert_eq!(PciAddress::from_ecam_offset(offset), (address, 0x104));
+        assert_eq!(address.to_string(), "02:1f.7");
+    }
+
+    #[test]
+    fn only_writable_bits_change() {
+        let mut space = ConfigSpace::endpoint(0x144D, 0xA808, NVME);
+        space.write(PCI_VENDOR_ID, 4, 0x1234_5678);
+        assert_eq!(space.read(PCI_VENDOR_ID, 4), 0xA808_144D);
+
+        space.write(PCI_COMMAND, 2, 0xFFFF);
+        assert_eq!(space.command(), COMMAND_IO | COMMAND_MEMORY | COMMAND_BUS_MASTER | COMMAND_INTX_DISABLE);
+        space.write(PCI_INTERRUPT_LINE, 1, 19);
+        assert_eq!(space.read(PCI_INTERRUPT_LINE, 1), 19);
+
+        // Past the end reads all-ones and writes go nowhere
+        assert_eq!(space.read(CONFIG_SPACE_SIZE as u16 - 2, 4), u32::MAX);
+        space.write(CONFIG_SPACE_SIZE as u16 - 2, 4, 0);
+    }
+
+    #[test]
+    fn bars_read_back_their_size_mask_and_then_their_base() {
+        let mut space = ConfigSpace::endpoint(0x144D, 0xA808, NVME)
+            .with_bar(0, BarKind::Memory32 { size: 0x3000, prefetchable: false })
+            .with_bar(1, BarKind::Io { size: 0x20 })
+            .with_bar(2, BarKind::Memory64 { size: 0x10_0000, prefetchable: true });
+
+        // Sizes round up to a power of two; the type bits stay put
+        space.write(PCI_BAR0, 4, u32::MAX);
+        assert_eq!(space.read(PCI_BAR0, 4), 0xFFFF_C000);
+        space.write(PCI_BAR0 + 4, 4, u32::MAX);
+        assert_eq!(space.read(PCI_BAR0 + 4, 4), 0xFFFF_FFE1);
+        space.write(PCI_BAR0 + 8, 4, u32::MAX);
+        space.write(PCI_BAR0 + 12, 4, u32::MAX);
+        assert_eq!((space.read(PCI_BAR0 + 8, 4), space.read(PCI_BAR0 + 12, 4)), (0xFFF0_000C, u32::MAX));
+
+        space.write(PCI_BAR0, 4, 0x8000_0000);
+        space.write(PCI_BAR0 + 8, 4, 0x8010_0000);
+        space.write(PCI_BAR0 + 12, 4, 0x1);
+        assert_eq!(space.memory_bar(0), Some((0x8000_0000, 0x4000)));
+        assert_eq!(space.memory_bar(1), None, "I/O BARs aren't memory");
+        assert_eq!(space.memory_bar(2), Some((0x1_8010_0000, 0x10_0000)));
+        assert_eq!(space.bar_kind(3), None);
+    }
+
+    #[test]
+    fn capabilities_chain_from_the_header() {
+        let space = ConfigSpace::endpoint(0x144D, 0xA808, NVME)
+            .with_capability(Capability::PowerManagement)
+            .with_capability(Capability::Msi { vectors: 4 })
+            .with_capability(Capability::PciExpress { port: PortType::Endpoint, generation: 4, width: 4 });
+        assert_ne!(space.read(PCI_STATUS, 2) as u16 & STATUS_CAPABILITIES, 0);
+
+        let mut chain = Vec::new();
+        let mut pointer = space.read(PCI_CAPABILITIES, 1) as u16;
+        while pointer != 0 {
+            chain.push((pointer, space.read(pointer, 1) as u8));
+            pointer = space.read(pointer + 1, 1) as u16;
+        }
+        assert_eq!(chain, [(0x40, CAP_POWER_MANAGEMENT), (0x48, CAP_MSI), (0x58, CAP_PCI_EXPRESS)]);
+
+        // 64-bit MSI, four vectors; an x4 Gen4 link
+        assert_eq!(space.read(0x48 + 2, 2), 0x80 | 2 << 1);
+        assert_eq!(space.read(0x58 + 0x12, 2), 4 | 4 << 4);
+    }
+
+    #[test]
+    fn bridges_decode_bus_numbers_and_a_memory_window() {
+        let mut bridge = ConfigSpace::bridge(0x8086, 0x9A23);
+        bridge.write(PCI_PRIMARY_BUS, 4, 0xFF03_0201);
+        assert_eq!(bridge.bus_range(), Some((2, 3)));
+
+        bridge.write(PCI_MEMORY_BASE, 4, 0x8010_8000);
+        assert_eq!(bridge.memory_window(), Some((0x8000_0000, 0x8020_0000)));
+        bridge.write(PCI_MEMORY_BASE, 4, 0x0000_FFF0);
+        assert_eq!(bridge.memory_window(), None, "a limit below the base is closed");
+
+        let endpoint = ConfigSpace::endpoint(0x144D, 0xA808, NVME);
+        assert_eq!((endpoint.bus_range(), endpoint.memory_window()), (None, None));
+    }
+}
@@ -0,0 +1,473 @@
+use super::pci_config::*;
+
+// Firmware places memory BARs in this window; above it sits the ECAM window
+pub const MEMORY_WINDOW_BASE: u64 = 0x8000_0000;
+pub const MEMORY_WINDOW_END: u64 = 0xB000_0000;
+
+// Bridge memory windows have 1 MiB granularity
+const BRIDGE_ALIGNMENT: u64 = 0x10_0000;
+
+// Legacy INTA# of slot 0 on the root bus lands on this IRQ; every other pin
+// swizzles its way up to the root bus and lands relative to it
+const FIRST_INTX_IRQ: u8 = 16;
+
+// Chained capabilities longer than this are a broken list, not a device
+const CAPABILITY_LIMIT: usize = 48;
+
+// One function as software discovered it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PciFunction {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: ClassCode,
+    pub revision: u8,
+    pub header: HeaderType,
+    pub bars: Vec<PciBar>,
+    pub capabilities: Vec<u8>, // IDs in list order
+    pub interrupt_pin: u8,
+    pub interrupt_line: u8,
+    pub bus_range: Option<(u8, u8)>, // Bridges: secondary through subordinate
+    pub command: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PciBar {
+    pub index: usize,
+    pub base: u64, // Zero until firmware assigns it
+    pub kind: BarKind,
+}
+
+// Firmware bring-up: numbers every bus depth-first, sizes and places memory
+// BARs bridge by bridge, opens bridge windows around what sits behind them,
+// routes legacy interrupts and enables decoding. Returns what it found.
+pub fn enumerate(config: &mut impl ConfigAccess) -> Vec<PciFunction> {
+    let mut next_bus = 1;
+    let mut allocator = MEMORY_WINDOW_BASE;
+    enumerate_bus(config, 0, &mut Vec::new(), &mut next_bus, &mut allocator);
+    scan(config)
+}
+
+// What an OS does at boot: walks the hierarchy firmware left behind, sizing
+// BARs without moving them
+pub fn scan(config: &mut impl ConfigAccess) -> Vec<PciFunction> {
+    let mut found = Vec::new();
+    scan_bus(config, 0, &mut found);
+    found
+}
+
+impl PciFunction {
+    // Firmware turns on decoding or bus mastering for every function it set
+    // up; one with all three off is parked and has nothing for a driver
+    pub fn is_enabled(&self) -> bool {
+        self.command & (COMMAND_IO | COMMAND_MEMORY | COMMAND_BUS_MASTER) != 0
+    }
+}
+
+// The legacy interrupt a pin ends up on. Each bridge on the way up rotates
+// the pin by the device number it was seen at, as the PCI-to-PCI bridge spec
+// has it, and the root bus does the same once more. `bridges` lists the
+// device numbers of the bridges above `device`, root first.
+pub fn route_intx(bridges: &[u8], device: u8, pin: u8) -> Option<u8> {
+    if !(1..=4).contains(&pin) {
+        return None;
+    }
+    let swizzled = bridges.iter().rev().fold((pin - 1 + device) % 4, |pin, &bridge| (pin + bridge) % 4);
+    Some(FIRST_INTX_IRQ + swizzled)
+}
+
+// One line per function, in the manner of `lspci -nn`
+pub fn describe(function: &PciFunction) -> String {
+    let code = (function.class.class as u16) << 8 | function.class.subclass as u16;
+    let mut line = format!(
+        "{} {} [{:04x}]: {} Device [{:04x}:{:04x}]",
+        function.address,
+        class_name(function.class),
+        code,
+        vendor_name(function.vendor_id),
+        function.vendor_id,
+        function.device_id,
+    );
+    if function.revision != 0 {
+        line.push_str(&format!(" (rev {:02x})", function.revision));
+    }
+    if function.class.prog_if != 0 {
+        line.push_str(&format!(" (prog-if {:02x})", function.class.prog_if));
+    }
+    line
+}
+
+pub fn class_name(class: ClassCode) -> &'static str {
+    match (class.class, class.subclass) {
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "Non-Volatile memory controller",
+        (0x01, _) => "Mass storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA compatible controller",
+        (0x03, _) => "Display controller",
+        (0x04, 0x03) => "Audio device",
+        (0x04, _) => "Multimedia controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x0C, 0x03) => "USB controller",
+        (0x0C, 0x05) => "SMBus",
+        _ => "Unclassified device",
+    }
+}
+
+pub fn vendor_name(vendor_id: u16) -> &'static str {
+    match vendor_id {
+        0x8086 => "Intel Corporation",
+        0x10DE => "NVIDIA Corporation",
+        0x1002 => "Advanced Micro Devices, Inc. [AMD/ATI]",
+        0x144D => "Samsung Electronics Co Ltd",
+        0x10EC => "Realtek Semiconductor Co., Ltd.",
+        _ => "Unknown vendor",
+    }
+}
+
+pub fn capability_name(id: u8) -> &'static str {
+    match id {
+        CAP_POWER_MANAGEMENT => "Power Management",
+        CAP_MSI => "MSI",
+        CAP_PCI_EXPRESS => "Express",
+        CAP_MSIX => "MSI-X",
+        _ => "Unknown",
+    }
+}
+
+// Helper methods
+// Returns the highest bus number found below `bus`. `bridges` holds the
+// device numbers of the bridges leading down to it.
+fn enumerate_bus(config: &mut impl ConfigAccess, bus: u8, bridges: &mut Vec<u8>, next_bus: &mut u8, allocator: &mut u64) -> u8 {
+    for device in 0..32 {
+        for function in 0..function_count(config, PciAddress::new(bus, device, 0)) {
+            let address = PciAddress::new(bus, device, function);
+            if config.config_read(address, PCI_VENDOR_ID, 2) == 0xFFFF {
+                continue;
+            }
+            let bridge = config.config_read(address, PCI_HEADER_TYPE, 1) & 0x7F == 0x01;
+            let mut command = COMMAND_BUS_MASTER;
+
+            for bar in size_bars(config, address, if bridge { 2 } else { 6 }) {
+                if place_bar(config, address, &bar, allocator).is_some() {
+                    command |= COMMAND_MEMORY;
+                }
+            }
+
+            if bridge {
+                // Open the bus range wide while walking below, then close it down
+                let secondary = *next_bus;
+                *next_bus = next_bus.saturating_add(1);
+                config.config_write(address, PCI_PRIMARY_BUS, 4, bus as u32 | (secondary as u32) << 8 | 0xFF << 16);
+                *allocator = align_up(*allocator, BRIDGE_ALIGNMENT);
+                let window_base = *allocator;
+                bridges.push(device);
+                let subordinate = enumerate_bus(config, secondary, bridges, next_bus, allocator);
+                bridges.pop();
+                config.config_write(address, PCI_SUBORDINATE_BUS, 1, subordinate as u32);
+
+                // This model forwards prefetchable BARs through the one memory window too
+                *allocator = align_up(*allocator, BRIDGE_ALIGNMENT);
+                let (base, limit) = if *allocator > window_base {
+                    command |= COMMAND_MEMORY;
+                    ((window_base >> 16) as u32 & 0xFFF0, ((*allocator - 1) >> 16) as u32 & 0xFFF0)
+                } else {
+                    (0xFFF0, 0x0000) // Limit below base: closed
+                };
+                config.config_write(address, PCI_MEMORY_BASE, 4, base | limit << 16);
+            }
+
+            let pin = config.config_read(address, PCI_INTERRUPT_PIN, 1) as u8;
+            if let Some(line) = route_intx(bridges, device, pin) {
+                config.config_write(address, PCI_INTERRUPT_LINE, 1, line as u32);
+            }
+            let current = config.config_read(address, PCI_COMMAND, 2);
+            config.config_write(address, PCI_COMMAND, 2, current | command as u32);
+        }
+    }
+    next_bus.saturating_sub(1).max(bus)
+}
+
+fn scan_bus(config: &mut impl ConfigAccess, bus: u8, found: &mut Vec<PciFunction>) {
+    for device in 0..32 {
+        for function in 0..function_count(config, PciAddress::new(bus, device, 0)) {
+            let address = PciAddress::new(bus, device, function);
+            let vendor_id = config.config_read(address, PCI_VENDOR_ID, 2) as u16;
+            if vendor_id == 0xFFFF {
+                continue;
+            }
+            let header = match config.config_read(address, PCI_HEADER_TYPE, 1) & 0x7F {
+                0x01 => HeaderType::Bridge,
+                _ => HeaderType::Endpoint,
+            };
+            let bus_range = match header {
+                HeaderType::Bridge => {
+                    let numbers = config.config_read(address, PCI_PRIMARY_BUS, 4);
+                    Some(((numbers >> 8) as u8, (numbers >> 16) as u8))
+                }
+                HeaderType::Endpoint => None,
+            };
+            let class = config.config_read(address, PCI_REVISION, 4);
+
+            found.push(PciFunction {
+                address,
+                vendor_id,
+                device_id: config.config_read(address, PCI_DEVICE_ID, 2) as u16,
+                class: ClassCode { class: (class >> 24) as u8, subclass: (class >> 16) as u8, prog_if: (class >> 8) as u8 },
+                revision: class as u8,
+                header,
+                bars: size_bars(config, address, if header == HeaderType::Bridge { 2 } else { 6 }),
+                capabilities: capabilities(config, address),
+                interrupt_pin: config.config_read(address, PCI_INTERRUPT_PIN, 1) as u8,
+                interrupt_line: config.config_read(address, PCI_INTERRUPT_LINE, 1) as u8,
+                bus_range,
+                command: config.config_read(address, PCI_COMMAND, 2) as u16,
+            });
+
+            // Only forward: a secondary at or below this bus would loop
+            if let Some((secondary, _)) = bus_range {
+                if secondary > bus {
+                    scan_bus(config, secondary, found);
+                }
+            }
+        }
+    }
+}
+
+fn function_count(config: &mut impl ConfigAccess, address: PciAddress) -> u8 {
+    if config.config_read(address, PCI_VENDOR_ID, 2) == 0xFFFF {
+        return 0;
+    }
+    if config.config_read(address, PCI_HEADER_TYPE, 1) as u8 & HEADER_MULTIFUNCTION != 0 {
+        8
+    } else {
+        1
+    }
+}
+
+// The classic probe: write all-ones, read back which address bits stuck,
+// restore. Decoding is off meanwhile so the BAR never claims stray cycles.
+fn size_bars(config: &mut impl ConfigAccess, address: PciAddress, count: usize) -> Vec<PciBar> {
+    let command = config.config_read(address, PCI_COMMAND, 2);
+    config.config_write(address, PCI_COMMAND, 2, command & !(COMMAND_IO | COMMAND_MEMORY) as u32);
+
+    let mut bars = Vec::new();
+    let mut index = 0;
+    while index < count {
+        let register = PCI_BAR0 + index as u16 * 4;
+        let original = config.config_read(address, register, 4);
+        config.config_write(address, register, 4, u32::MAX);
+        let probe = config.config_read(address, register, 4);
+        config.config_write(address, register, 4, original);
+
+        if probe == 0 {
+            index += 1;
+            continue;
+        }
+        if probe & 0x1 != 0 {
+            // 16-bit decoders may read the upper half back as zero
+            let size = (!(probe & !0x3 | 0xFFFF_0000)).wrapping_add(1);
+            bars.push(PciBar { index, base: (original & !0x3) as u64, kind: BarKind::Io { size } });
+            index += 1;
+            continue;
+        }
+
+        let prefetchable = probe & 0x8 != 0;
+        if probe & 0x6 == 0x4 && index + 1 < count {
+            let original_high = config.config_read(address, register + 4, 4);
+            config.config_write(address, register + 4, 4, u32::MAX);
+            let probe_high = config.config_read(address, register + 4, 4);
+            config.config_write(address, register + 4, 4, original_high);
+
+            let mask = (probe_high as u64) << 32 | (probe & !0xF) as u64;
+            let base = (original_high as u64) << 32 | (original & !0xF) as u64;
+            bars.push(PciBar { index, base, kind: BarKind::Memory64 { size: (!mask).wrapping_add(1), prefetchable } });
+            index += 2;
+        } else {
+            let size = (!(probe & !0xF)).wrapping_add(1) as u64;
+            bars.push(PciBar { index, base: (original & !0xF) as u64, kind: BarKind::Memory32 { size, prefetchable } });
+            index += 1;
+        }
+    }
+
+    config.config_write(address, PCI_COMMAND, 2, command);
+    bars
+}
+
+// Naturally aligned, bump-allocated from the window. There is no I/O space
+// on this platform, so I/O BARs stay unassigned.
+fn place_bar(config: &mut impl ConfigAccess, address: PciAddress, bar: &PciBar, allocator: &mut u64) -> Option<u64> {
+    let size = match bar.kind {
+        BarKind::Memory32 { size, .. } | BarKind::Memory64 { size, .. } => size,
+        BarKind::Io { .. } => return None,
+    };
+    let base = align_up(*allocator, size);
+    if base + size > MEMORY_WINDOW_END {
+        return None;
+    }
+
+    let register = PCI_BAR0 + bar.index as u16 * 4;
+    config.config_write(address, register, 4, base as u32);
+    if let BarKind::Memory64 { .. } = bar.kind {
+        config.config_write(address, register + 4, 4, (base >> 32) as u32);
+    }
+    *allocator = base + size;
+    Some(base)
+}
+
+fn capabilities(config: &mut impl ConfigAccess, address: PciAddress) -> Vec<u8> {
+    let mut found = Vec::new();
+    if config.config_read(address, PCI_STATUS, 2) as u16 & STATUS_CAPABILITIES == 0 {
+        return found;
+    }
+    let mut pointer = config.config_read(address, PCI_CAPABILITIES, 1) as u16 & 0xFC;
+    while pointer != 0 && found.len() < CAPABILITY_LIMIT {
+        found.push(config.config_read(address, pointer, 1) as u8);
+        pointer = config.config_read(address, pointer + 1, 1) as u16 & 0xFC;
+    }
+    found
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    value.next_multiple_of(alignment.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::bus::pci_bus::{PciBus, ROOT_SEGMENT};
+
+    fn find(functions: &[PciFunction], class: u8, subclass: u8) -> &PciFunction {
+        functions.iter().find(|function| function.class.class == class && function.class.subclass == subclass).unwrap()
+    }
+
+    #[test]
+    fn interrupt_pins_swizzle_through_each_bridge() {
+        let functions = PciBus::laptop().enumerate();
+
+        // GPU INTA# behind the port at 00:01: rotated by device 0, then by 1
+        assert_eq!(find(&functions, 0x03, 0x00).interrupt_line, FIRST_INTX_IRQ + 1);
+        // Its audio function's INTB#: pin 1, plus 1 for the root port
+        assert_eq!(find(&functions, 0x04, 0x03).interrupt_line, FIRST_INTX_IRQ + 2);
+        // NVMe behind 00:06 and Wi-Fi behind 00:1c
+        assert_eq!(find(&functions, 0x01, 0x08).interrupt_line, FIRST_INTX_IRQ + 2);
+        assert_eq!(find(&functions, 0x02, 0x80).interrupt_line, FIRST_INTX_IRQ);
+        // The root ports themselves sit on bus 0
+        let storage_port = functions.iter().find(|function| function.address == PciAddress::new(0, 0x06, 0)).unwrap();
+        assert_eq!(storage_port.interrupt_line, FIRST_INTX_IRQ + 2);
+    }
+
+    #[test]
+    fn route_intx_follows_the_bridge_path() {
+        assert_eq!(route_intx(&[], 3, 1), Some(FIRST_INTX_IRQ + 3));
+        assert_eq!(route_intx(&[2, 1], 0, 4), Some(FIRST_INTX_IRQ + 2));
+        assert_eq!(route_intx(&[1], 0, 0), None, "no pin, no line");
+        assert_eq!(route_intx(&[1], 0, 5), None);
+    }
+
+    #[test]
+    fn functions_are_enabled_once_firmware_has_run() {
+        let mut pci = PciBus::laptop();
+        assert!(scan(&mut pci).iter().all(|function| !function.is_enabled()));
+        assert!(pci.enumerate().iter().all(PciFunction::is_enabled));
+    }
+
+    const NVME: ClassCode = ClassCode { class: 0x01, subclass: 0x08, prog_if: 0x02 };
+
+    fn endpoint(bars: &[(usize, BarKind)]) -> ConfigSpace {
+        bars.iter().fold(ConfigSpace::endpoint(0x144D, 0xA808, NVME), |space, &(index, kind)| space.with_bar(index, kind))
+    }
+
+    fn memory32(size: u64) -> BarKind {
+        BarKind::Memory32 { size, prefetchable: false }
+    }
+
+    fn at(functions: &[PciFunction], address: PciAddress) -> &PciFunction {
+        functions.iter().find(|function| function.address == address).unwrap()
+    }
+
+    // Base and end of a bridge's memory window, as programmed
+    fn window(pci: &mut PciBus, bridge: PciAddress) -> (u64, u64) {
+        let registers = pci.config_read(bridge, PCI_MEMORY_BASE, 4) as u64;
+        ((registers & 0xFFF0) << 16, ((registers >> 16 & 0xFFF0) << 16 | 0xF_FFFF) + 1)
+    }
+
+    #[test]
+    fn sizing_writes_all_ones_and_restores_what_was_there() {
+        let mut pci = PciBus::new();
+        pci.attach(ROOT_SEGMENT, 2, 0, endpoint(&[(0, memory32(0x4000)), (1, BarKind::Io { size: 0x20 })]));
+        let address = PciAddress::new(0, 2, 0);
+        pci.config_write(address, PCI_BAR0, 4, 0x9000_0000);
+        pci.config_write(address, PCI_COMMAND, 2, COMMAND_MEMORY as u32);
+
+        let functions = scan(&mut pci);
+        let bars = &at(&functions, address).bars;
+        assert_eq!(bars[0], PciBar { index: 0, base: 0x9000_0000, kind: memory32(0x4000) });
+        assert_eq!(bars[1], PciBar { index: 1, base: 0, kind: BarKind::Io { size: 0x20 } });
+
+        // The probe left neither the mask nor decoding changed
+        assert_eq!(pci.config_read(address, PCI_BAR0, 4), 0x9000_0000);
+        assert_eq!(pci.config_read(address, PCI_COMMAND, 2), COMMAND_MEMORY as u32);
+    }
+
+    #[test]
+    fn bars_are_placed_naturally_aligned() {
+        let mut pci = PciBus::new();
+        pci.attach(ROOT_SEGMENT, 2, 0, endpoint(&[(0, memory32(0x1000)), (1, memory32(0x10_0000)), (2, memory32(0x4000))]));
+        let functions = pci.enumerate();
+
+        let bases: Vec<u64> = at(&functions, PciAddress::new(0, 2, 0)).bars.iter().map(|bar| bar.base).collect();
+        assert_eq!(bases, [MEMORY_WINDOW_BASE, MEMORY_WINDOW_BASE + 0x10_0000, MEMORY_WINDOW_BASE + 0x20_0000]);
+    }
+
+    #[test]
+    fn a_64_bit_bar_is_one_bar_over_two_registers() {
+        let mut pci = PciBus::new();
+        let wide = BarKind::Memory64 { size: 0x10_0000, prefetchable: true };
+        pci.attach(ROOT_SEGMENT, 2, 0, endpoint(&[(0, wide), (2, memory32(0x1000))]));
+        let address = PciAddress::new(0, 2, 0);
+
+        let functions = pci.enumerate();
+        let bars = &at(&functions, address).bars;
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[0], PciBar { index: 0, base: MEMORY_WINDOW_BASE, kind: wide });
+        assert_eq!(bars[1], PciBar { index: 2, base: MEMORY_WINDOW_BASE + 0x10_0000, kind: memory32(0x1000) });
+        assert_eq!(pci.config_read(address, PCI_BAR0 + 4, 4), 0);
+
+        // The upper register is part of the base
+        pci.config_write(address, PCI_BAR0 + 4, 4, 0x2);
+        assert_eq!(at(&scan(&mut pci), address).bars[0].base, 0x2_0000_0000 | MEMORY_WINDOW_BASE);
+    }
+
+    #[test]
+    fn nested_bridges_get_bus_ranges_and_windows_around_what_is_behind_them() {
+        let mut pci = PciBus::new();
+        let outer = pci.attach(ROOT_SEGMENT, 1, 0, ConfigSpace::bridge(0x8086, 0x9A23)).unwrap();
+        let inner = pci.attach(outer, 0, 0, ConfigSpace::bridge(0x8086, 0x9A25)).unwrap();
+        pci.attach(inner, 0, 0, endpoint(&[(0, memory32(0x20_0000))]));
+        pci.attach(outer, 1, 0, endpoint(&[(0, memory32(0x1000))]));
+        pci.attach(ROOT_SEGMENT, 3, 0, endpoint(&[(0, memory32(0x1000))]));
+        pci.attach(ROOT_SEGMENT, 4, 0, ConfigSpace::bridge(0x8086, 0x9A27));
+        let functions = pci.enumerate();
+
+        // Depth first: the inner bridge's bus is numbered before its sibling's
+        let (outer_port, inner_port, empty_port) = (PciAddress::new(0, 1, 0), PciAddress::new(1, 0, 0), PciAddress::new(0, 4, 0));
+        assert_eq!(at(&functions, outer_port).bus_range, Some((1, 2)));
+        assert_eq!(at(&functions, inner_port).bus_range, Some((2, 2)));
+        assert_eq!(at(&functions, empty_port).bus_range, Some((3, 3)));
+
+        // Each window covers what's below it, rounded out to 1 MiB
+        assert_eq!(at(&functions, PciAddress::new(2, 0, 0)).bars[0].base, MEMORY_WINDOW_BASE);
+        assert_eq!(at(&functions, PciAddress::new(1, 1, 0)).bars[0].base, MEMORY_WINDOW_BASE + 0x20_0000);
+        assert_eq!(window(&mut pci, inner_port), (MEMORY_WINDOW_BASE, MEMORY_WINDOW_BASE + 0x20_0000));
+        assert_eq!(window(&mut pci, outer_port), (MEMORY_WINDOW_BASE, MEMORY_WINDOW_BASE + 0x30_0000));
+
+        // Past the outer window, and a bridge with nothing behind it stays closed
+        assert_eq!(at(&functions, PciAddress::new(0, 3, 0)).bars[0].base, MEMORY_WINDOW_BASE + 0x30_0000);
+        assert_eq!(pci.config_read(empty_port, PCI_MEMORY_BASE, 4), 0x0000_FFF0);
+    }
+}
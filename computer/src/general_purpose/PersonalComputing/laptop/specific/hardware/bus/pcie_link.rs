@@ -0,0 +1,795 @@
+use crate::simulation::time::PS_PER_NS;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, VecDeque};
+
+// Times inside the model are picoseconds, as in SimTime: a DLLP on a Gen4
+// x16 link takes well under a nanosecond
+
+// The Tag field is 8 bits wide, so no requester has more reads in flight
+const MAX_TAGS: usize = 256;
+
+const SEQUENCE_BYTES: u64 = 2;
+const LCRC_BYTES: u64 = 4;
+const ECRC_BYTES: u64 = 4;
+const DLLP_BYTES: u64 = 8; // 4 of content, 2 of CRC, 2 of framing
+const DATA_CREDIT_BYTES: u64 = 16;
+
+// No request may cross a 4 KiB boundary
+const REQUEST_BOUNDARY: u64 = 4096;
+
+const TLP_LOG_LIMIT: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Generation {
+    Gen1,
+    Gen2,
+    Gen3,
+    Gen4,
+    Gen5,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionOrder {
+    InOrder,     // The completer finishes each read before starting the next
+    Interleaved, // Completions for different tags share the link round-robin
+}
+
+#[derive(Debug, Clone)]
+pub struct LinkConfig {
+    pub generation: Generation,
+    pub width: u8,                 // Lanes: 1, 2, 4, 8 or 16
+    pub max_payload: u32,          // MPS: largest write or completion payload
+    pub max_read_request: u32,     // MRRS
+    pub completion_boundary: u32,  // RCB: split completions end on multiples of this
+    pub ecrc: bool,
+    pub tags: usize,               // Reads a requester may have outstanding, up to 256
+    pub posted_credits: (u32, u32), // Header and data credits the receiver advertises; 0 is infinite
+    pub non_posted_credits: u32,   // Header credits
+    pub credit_return_ns: u64,     // Receiver drain time before an UpdateFC frees credits
+    pub ack_coalesce: u32,         // TLPs acknowledged per ACK DLLP
+    pub flight_ns: u64,            // One way through PHY, SerDes and the wire
+    pub completion_latency_ns: u64, // Completer's memory or register latency
+    pub completion_order: CompletionOrder,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlpType {
+    MemRead,        // MRd
+    MemWrite,       // MWr
+    Completion,     // Cpl
+    CompletionData, // CplD
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tlp {
+    pub kind: TlpType,
+    pub address: u64,
+    pub length: u32, // Bytes requested, written or returned
+    pub tag: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Upstream,   // Device to root complex
+    Downstream, // Root complex to device
+}
+
+// One TLP as it crossed the link, for drawing a timeline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TlpRecord {
+    pub direction: Direction,
+    pub tlp: Tlp,
+    pub start_ns: f64, // From the start of its transfer
+    pub end_ns: f64,
+}
+
+// Where a transfer's time and bytes went. Payload is what the caller asked
+// for; everything else is the price of moving it as packets.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TransferCost {
+    pub payload_bytes: u64,
+    pub header_bytes: u64,
+    pub framing_bytes: u64, // Framing tokens, sequence numbers, LCRC and ECRC
+    pub dllp_bytes: u64,    // ACKs and credit updates
+    pub tlps: u64,
+    pub dllps: u64,
+    pub credit_stall_ns: u64, // Waiting for the receiver to free buffer space
+    pub tag_stall_ns: u64,    // Waiting for a read to finish so its tag frees up
+    pub reordered: u64,       // Completions that arrived ahead of earlier data
+    pub peak_reorder_bytes: u64,
+    pub ns: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LinkStats {
+    pub reads: u64,  // MRd
+    pub writes: u64, // MWr
+    pub completions: u64,
+    pub completions_with_data: u64,
+    pub dllps: u64,
+    pub payload_bytes: u64,
+    pub wire_bytes: u64, // TLP and DLLP bytes before line encoding
+    pub credit_stall_ns: u64,
+    pub tag_stall_ns: u64,
+    pub reordered: u64,
+    pub busy_ns: u64,
+}
+
+pub struct PcieLink {
+    config: LinkConfig,
+    upstream: Lane,
+    downstream: Lane,
+    stats: LinkStats,
+    last_transfer: TransferCost,
+    log: VecDeque<TlpRecord>,
+}
+
+// One direction of the link. Both directions start idle for every transfer.
+#[derive(Default)]
+struct Lane {
+    free_at: u64,
+    unacked: u32,
+}
+
+// Receiver buffer space as the transmitter sees it
+struct CreditPool {
+    headers: Option<u32>, // None when advertised infinite
+    data: Option<u32>,
+    returns: BinaryHeap<Reverse<(u64, u32, u32)>>,
+}
+
+// A read request and the completions it still owes
+struct PendingRead {
+    base: u64,
+    offset: u64, // Within the whole transfer
+    tag: u8,
+    ready: Option<u64>, // When the completer has the data
+    chunks: VecDeque<(u64, u32)>, // Offset within the request and length
+}
+
+impl PcieLink {
+    pub fn new(config: LinkConfig) -> Self {
+        Self {
+            config,
+            upstream: Lane::default(),
+            downstream: Lane::default(),
+            stats: LinkStats::default(),
+            last_transfer: TransferCost::default(),
+            log: VecDeque::new(),
+        }
+    }
+
+    // A device writing host memory: posted MWr TLPs up to MPS each, paced
+    // by the root complex's posted credits
+    pub fn dma_write(&mut self, address: u64, bytes: u64) -> TransferCost {
+        self.begin();
+        let mut cost = TransferCost::default();
+        let mut posted = CreditPool::new(self.config.posted_credits.0, self.config.posted_credits.1);
+        let mut last = 0;
+
+        for (chunk, length) in split(address, bytes, self.config.max_payload as u64) {
+            let tlp = Tlp { kind: TlpType::MemWrite, address: chunk, length, tag: 0 };
+            let (headers, data) = tlp.credits();
+            let free_at = self.upstream.free_at;
+            let ready = posted.acquire(free_at, headers, data);
+            cost.credit_stall_ns += ps_to_ns(ready - free_at);
+            let arrival = self.transmit(Direction::Upstream, ready, tlp, &mut cost);
+            let returned = self.update_fc(Direction::Downstream, arrival, &mut cost);
+            posted.release(returned, headers, data);
+            last = arrival;
+        }
+        self.finish(cost, last)
+    }
+
+    // A device reading host memory: MRd TLPs up to MRRS each, one tag per
+    // outstanding request, answered by CplDs split at the completion
+    // boundary. Completions for different tags may overtake each other, so
+    // the requester holds data back until everything before it has arrived.
+    pub fn dma_read(&mut self, address: u64, bytes: u64) -> TransferCost {
+        self.begin();
+        let mut cost = TransferCost::default();
+        let mut non_posted = CreditPool::new(self.config.non_posted_credits, 0);
+        // Free tags by when they came back; a read takes whichever frees first
+        let tag_count = self.config.tags.clamp(1, MAX_TAGS);
+        let mut tags: BinaryHeap<Reverse<(u64, u8)>> = (0..tag_count).map(|tag| Reverse((0, tag as u8))).collect();
+
+        let mut reads: Vec<PendingRead> = Vec::new();
+        let mut offset = 0;
+        for (base, length) in split(address, bytes, self.config.max_read_request as u64) {
+            reads.push(PendingRead {
+                base,
+                offset,
+                tag: 0, // Assigned at issue
+                ready: None,
+                chunks: self.completion_chunks(base, length),
+            });
+            offset += length as u64;
+        }
+
+        let mut issued = 0;
+        let mut next_served = 0;
+        let mut arrived: BTreeMap<u64, u64> = BTreeMap::new(); // Held back: offset to length
+        let mut delivered = 0; // Contiguous bytes handed to the device
+        let mut last = 0;
+
+        loop {
+            let issue_at = if issued < reads.len() {
+                tags.peek().map(|Reverse((free, _))| (*free).max(self.upstream.free_at))
+            } else {
+                None
+            };
+            let completion = self.next_completion(&reads, next_served);
+
+            let issue = match (issue_at, completion) {
+                (None, None) => break,
+                (Some(at), Some((_, complete_at))) => at <= complete_at,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+            };
+
+            if issue {
+                let Reverse((tag_free, tag)) = tags.pop().unwrap_or(Reverse((0, 0)));
+                let free_at = self.upstream.free_at;
+                cost.tag_stall_ns += ps_to_ns(tag_free.saturating_sub(free_at));
+                let start = tag_free.max(free_at);
+                let ready = non_posted.acquire(start, 1, 0);
+                cost.credit_stall_ns += ps_to_ns(ready - start);
+
+                reads[issued].tag = tag;
+                let read = &reads[issued];
+                let length = read.chunks.iter().map(|&(_, length)| length).sum();
+                let tlp = Tlp { kind: TlpType::MemRead, address: read.base, length, tag: read.tag };
+                let arrival = self.transmit(Direction::Upstream, ready, tlp, &mut cost);
+                let returned = self.update_fc(Direction::Downstream, arrival, &mut cost);
+                non_posted.release(returned, 1, 0);
+                reads[issued].ready = Some(arrival + self.config.completion_latency_ns * PS_PER_NS);
+                issued += 1;
+                continue;
+            }
+
+            let Some((index, at)) = completion else {
+                break;
+            };
+            let Some((chunk, length)) = reads[index].chunks.pop_front() else {
+                break;
+            };
+            let read = &reads[index];
+            let tlp = Tlp { kind: TlpType::CompletionData, address: read.base + chunk, length, tag: read.tag };
+            let at_offset = read.offset + chunk;
+            // Endpoints advertise infinite completion credits, so nothing to wait for
+            let arrival = self.transmit(Direction::Downstream, at, tlp, &mut cost);
+            last = last.max(arrival);
+            if reads[index].chunks.is_empty() {
+                tags.push(Reverse((arrival, read.tag)));
+            }
+            next_served = index + 1;
+
+            if at_offset != delivered {
+                cost.reordered += 1;
+            }
+            arrived.insert(at_offset, length as u64);
+            while let Some(length) = arrived.remove(&delivered) {
+                delivered += length;
+            }
+            let held: u64 = arrived.values().sum();
+            cost.peak_reorder_bytes = cost.peak_reorder_bytes.max(held);
+        }
+        self.finish(cost, last)
+    }
+
+    // The CPU reading a device register: MRd down, CplD back up
+    pub fn mmio_read(&mut self, address: u64, bytes: u32) -> TransferCost {
+        self.begin();
+        let mut cost = TransferCost::default();
+        let request = Tlp { kind: TlpType::MemRead, address, length: bytes, tag: 0 };
+        let arrival = self.transmit(Direction::Downstream, 0, request, &mut cost);
+        let ready = arrival + self.config.completion_latency_ns * PS_PER_NS;
+        let completion = Tlp { kind: TlpType::CompletionData, address, length: bytes, tag: 0 };
+        let last = self.transmit(Direction::Upstream, ready, completion, &mut cost);
+        self.finish(cost, last)
+    }
+
+    // Posted, so done once it lands
+    pub fn mmio_write(&mut self, address: u64, bytes: u32) -> TransferCost {
+        self.begin();
+        let mut cost = TransferCost::default();
+        let tlp = Tlp { kind: TlpType::MemWrite, address, length: bytes, tag: 0 };
+        let last = self.transmit(Direction::Downstream, 0, tlp, &mut cost);
+        self.finish(cost, last)
+    }
+
+    // Bytes per second one direction carries before any packet overhead
+    pub fn raw_bandwidth(&self) -> f64 {
+        self.config.generation.transfer_rate() * 1e9 * self.config.width as f64 / 8.0 / self.config.generation.encoding()
+    }
+
+    pub fn config(&self) -> &LinkConfig {
+        &self.config
+    }
+
+    // Methods for visualization system
+    pub fn get_stats(&self) -> &LinkStats {
+        &self.stats
+    }
+
+    pub fn last_transfer(&self) -> &TransferCost {
+        &self.last_transfer
+    }
+
+    // TLPs of the most recent transfers, oldest first
+    pub fn tlp_log(&self) -> &VecDeque<TlpRecord> {
+        &self.log
+    }
+
+    // Helper methods
+    fn begin(&mut self) {
+        self.upstream = Lane::default();
+        self.downstream = Lane::default();
+    }
+
+    fn finish(&mut self, mut cost: TransferCost, last: u64) -> TransferCost {
+        cost.ns = ps_to_ns(last);
+        self.stats.payload_bytes += cost.payload_bytes;
+        self.stats.wire_bytes += cost.wire_bytes() + cost.dllp_bytes;
+        self.stats.credit_stall_ns += cost.credit_stall_ns;
+        self.stats.tag_stall_ns += cost.tag_stall_ns;
+        self.stats.reordered += cost.reordered;
+        self.stats.busy_ns += cost.ns;
+        self.last_transfer = cost;
+        cost
+    }
+
+    // Serializes a TLP onto one direction; returns when its last byte
+    // reaches the far end. The receiver ACKs every few TLPs on the other one.
+    fn transmit(&mut self, direction: Direction, at: u64, tlp: Tlp, cost: &mut TransferCost) -> u64 {
+        let framing = self.config.generation.framing_bytes()
+            + SEQUENCE_BYTES
+            + LCRC_BYTES
+            + if self.config.ecrc { ECRC_BYTES } else { 0 };
+        let bytes = tlp.header_bytes() + tlp.payload_bytes() + framing;
+        cost.payload_bytes += tlp.payload_bytes();
+        cost.header_bytes += tlp.header_bytes();
+        cost.framing_bytes += framing;
+        cost.tlps += 1;
+        match tlp.kind {
+            TlpType::MemRead => self.stats.reads += 1,
+            TlpType::MemWrite => self.stats.writes += 1,
+            TlpType::Completion => self.stats.completions += 1,
+            TlpType::CompletionData => self.stats.completions_with_data += 1,
+        }
+
+        let duration = self.serialize_ps(bytes);
+        let lane = self.lane(direction);
+        let start = at.max(lane.free_at);
+        lane.free_at = start + duration;
+        lane.unacked += 1;
+        let end = start + duration;
+        let arrival = end + self.config.flight_ns * PS_PER_NS;
+
+        if self.log.len() == TLP_LOG_LIMIT {
+            self.log.pop_front();
+        }
+        self.log.push_back(TlpRecord {
+            direction,
+            tlp,
+            start_ns: start as f64 / PS_PER_NS as f64,
+            end_ns: end as f64 / PS_PER_NS as f64,
+        });
+
+        if self.lane(direction).unacked >= self.config.ack_coalesce.max(1) {
+            self.lane(direction).unacked = 0;
+            self.send_dllp(direction.reverse(), arrival, cost);
+        }
+        arrival
+    }
+
+    // The receiver drains the TLP, then tells the transmitter the space is
+    // free again. Returns when the transmitter learns of it.
+    fn update_fc(&mut self, direction: Direction, arrival: u64, cost: &mut TransferCost) -> u64 {
+        self.send_dllp(direction, arrival + self.config.credit_return_ns * PS_PER_NS, cost)
+    }
+
+    fn send_dllp(&mut self, direction: Direction, at: u64, cost: &mut TransferCost) -> u64 {
+        let duration = self.serialize_ps(DLLP_BYTES);
+        let flight = self.config.flight_ns * PS_PER_NS;
+        let lane = self.lane(direction);
+        let start = at.max(lane.free_at);
+        lane.free_at = start + duration;
+        cost.dllp_bytes += DLLP_BYTES;
+        cost.dllps += 1;
+        self.stats.dllps += 1;
+        start + duration + flight
+    }
+
+    // Which read the completer sends from next, and when
+    fn next_completion(&self, reads: &[PendingRead], next_served: usize) -> Option<(usize, u64)> {
+        let free_at = self.downstream.free_at;
+        let waiting = |read: &PendingRead| read.ready.is_some() && !read.chunks.is_empty();
+        match self.config.completion_order {
+            CompletionOrder::InOrder => reads
+                .iter()
+                .position(|read| !read.chunks.is_empty())
+                .filter(|&index| waiting(&reads[index]))
+                .map(|index| (index, free_at.max(reads[index].ready.unwrap_or(0)))),
+            CompletionOrder::Interleaved => {
+                // Round-robin over reads whose data is ready; otherwise whichever is ready first
+                let count = reads.len().max(1);
+                let ready_now = (0..reads.len())
+                    .map(|step| (next_served + step) % count)
+                    .find(|&index| waiting(&reads[index]) && reads[index].ready.unwrap_or(0) <= free_at);
+                match ready_now {
+                    Some(index) => Some((index, free_at)),
+                    None => reads
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, read)| waiting(read))
+                        .min_by_key(|(_, read)| read.ready)
+                        .map(|(index, read)| (index, free_at.max(read.ready.unwrap_or(0)))),
+                }
+            }
+        }
+    }
+
+    // A completer may split a read anywhere the data ends on a completion
+    // boundary; it sends the largest pieces MPS allows
+    fn completion_chunks(&self, base: u64, length: u32) -> VecDeque<(u64, u32)> {
+        let boundary = self.config.completion_boundary.max(4) as u64;
+        let payload = (self.config.max_payload as u64).max(boundary);
+        let mut chunks = VecDeque::new();
+        let mut offset = 0;
+        while offset < length as u64 {
+            let address = base + offset;
+            let size = (payload - address % boundary).min(length as u64 - offset);
+            chunks.push_back((offset, size as u32));
+            offset += size;
+        }
+        chunks
+    }
+
+    fn serialize_ps(&self, bytes: u64) -> u64 {
+        let generation = self.config.generation;
+        let bits_per_ns = generation.transfer_rate() * self.config.width.max(1) as f64;
+        (bytes as f64 * 8.0 * generation.encoding() / bits_per_ns * PS_PER_NS as f64).ceil() as u64
+    }
+
+    fn lane(&mut self, direction: Direction) -> &mut Lane {
+        match direction {
+            Direction::Upstream => &mut self.upstream,
+            Direction::Downstream => &mut self.downstream,
+        }
+    }
+}
+
+impl Default for PcieLink {
+    fn default() -> Self {
+        Self::new(LinkConfig::default())
+    }
+}
+
+impl Default for LinkConfig {
+    // A Gen4 x16 graphics slot
+    fn default() -> Self {
+        Self {
+            generation: Generation::Gen4,
+            width: 16,
+            max_payload: 256,
+            max_read_request: 512,
+            completion_boundary: 64,
+            ecrc: false,
+            tags: 64,
+            posted_credits: (128, 1024),
+            non_posted_credits: 32,
+            credit_return_ns: 400,
+            ack_coalesce: 4,
+            flight_ns: 150,
+            completion_latency_ns: 350,
+            completion_order: CompletionOrder::Interleaved,
+        }
+    }
+}
+
+impl Generation {
+    // GT/s per lane
+    pub fn transfer_rate(self) -> f64 {
+        match self {
+            Generation::Gen1 => 2.5,
+            Generation::Gen2 => 5.0,
+            Generation::Gen3 => 8.0,
+            Generation::Gen4 => 16.0,
+            Generation::Gen5 => 32.0,
+        }
+    }
+
+    // Link speed field of the PCIe capability
+    pub fn from_speed(speed: u8) -> Option<Self> {
+        match speed {
+            1 => Some(Generation::Gen1),
+            2 => Some(Generation::Gen2),
+            3 => Some(Generation::Gen3),
+            4 => Some(Generation::Gen4),
+            5 => Some(Generation::Gen5),
+            _ => None,
+        }
+    }
+
+    // Line bits per data bit: 8b/10b up to Gen2, 128b/130b after
+    fn encoding(self) -> f64 {
+        match self {
+            Generation::Gen1 | Generation::Gen2 => 10.0 / 8.0,
+            _ => 130.0 / 128.0,
+        }
+    }
+
+    // STP and END symbols up to Gen2; a 4-byte STP token after
+    fn framing_bytes(self) -> u64 {
+        match self {
+            Generation::Gen1 | Generation::Gen2 => 2,
+            _ => 4,
+        }
+    }
+}
+
+impl Direction {
+    fn reverse(self) -> Self {
+        match self {
+            Direction::Upstream => Direction::Downstream,
+            Direction::Downstream => Direction::Upstream,
+        }
+    }
+}
+
+impl TlpType {
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            TlpType::MemRead => "MRd",
+            TlpType::MemWrite => "MWr",
+            TlpType::Completion => "Cpl",
+            TlpType::CompletionData => "CplD",
+        }
+    }
+}
+
+impl Tlp {
+    // 3 DW, or 4 DW for a memory request above 4 GiB
+    pub fn header_bytes(&self) -> u64 {
+        match self.kind {
+            TlpType::MemRead | TlpType::MemWrite if self.address > u32::MAX as u64 => 16,
+            _ => 12,
+        }
+    }
+
+    // Padded out to whole DWs
+    pub fn payload_bytes(&self) -> u64 {
+        match self.kind {
+            TlpType::MemWrite | TlpType::CompletionData => (self.length as u64).next_multiple_of(4),
+            TlpType::MemRead | TlpType::Completion => 0,
+        }
+    }
+
+    // Header and data credits the TLP occupies in the receiver's buffers
+    fn credits(&self) -> (u32, u32) {
+        (1, self.payload_bytes().div_ceil(DATA_CREDIT_BYTES) as u32)
+    }
+}
+
+impl TransferCost {
+    // TLP bytes before line encoding
+    pub fn wire_bytes(&self) -> u64 {
+        self.payload_bytes + self.header_bytes + self.framing_bytes
+    }
+
+    // Payload share of the TLP bytes
+    pub fn efficiency(&self) -> f32 {
+        if self.wire_bytes() == 0 {
+            return 0.0;
+        }
+        self.payload_bytes as f32 / self.wire_bytes() as f32
+    }
+
+    // Payload bytes per second over the whole transfer, latency included
+    pub fn effective_bandwidth(&self) -> f64 {
+        if self.ns == 0 {
+            return 0.0;
+        }
+        self.payload_bytes as f64 * 1e9 / self.ns as f64
+    }
+}
+
+impl CreditPool {
+    fn new(headers: u32, data: u32) -> Self {
+        Self {
+            headers: (headers != 0).then_some(headers),
+            data: (data != 0).then_some(data),
+            returns: BinaryHeap::new(),
+        }
+    }
+
+    // Earliest time from `at` when the credits are there; takes them
+    fn acquire(&mut self, at: u64, headers: u32, data: u32) -> u64 {
+        let mut time = at;
+        loop {
+            while let Some(&Reverse((returned, back_headers, back_data))) = self.returns.peek() {
+                if returned > time {
+                    break;
+                }
+                self.returns.pop();
+                self.headers = self.headers.map(|count| count + back_headers);
+                self.data = self.data.map(|count| count + back_data);
+            }
+            let enough = self.headers.is_none_or(|count| count >= headers) && self.data.is_none_or(|count| count >= data);
+            if enough {
+                self.headers = self.headers.map(|count| count - headers);
+                self.data = self.data.map(|count| count - data);
+                return time;
+            }
+            match self.returns.peek() {
+                Some(&Reverse((returned, _, _))) => time = returned,
+                // More than was ever advertised; let it through rather than deadlock
+                None => return time,
+            }
+        }
+    }
+
+    fn release(&mut self, at: u64, headers: u32, data: u32) {
+        let headers = if self.headers.is_some() { headers } else { 0 };
+        let data = if self.data.is_some() { data } else { 0 };
+        self.returns.push(Reverse((at, headers, data)));
+    }
+}
+
+// Pieces of at most `limit` bytes that never cross a 4 KiB boundary
+fn split(address: u64, bytes: u64, limit: u64) -> Vec<(u64, u32)> {
+    let limit = limit.max(4);
+    let mut pieces = Vec::new();
+    let mut offset = 0;
+    while offset < bytes {
+        let at = address + offset;
+        let size = limit.min(bytes - offset).min(REQUEST_BOUNDARY - at % REQUEST_BOUNDARY);
+        pieces.push((at, size as u32));
+        offset += size;
+    }
+    pieces
+}
+
+fn ps_to_ns(ps: u64) -> u64 {
+    ps.div_ceil(PS_PER_NS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn a_tag_is_reused_only_after_its_last_completion() {
+        let mut link = PcieLink::new(LinkConfig {
+            tags: 4,
+            completion_order: CompletionOrder::Interleaved,
+            ..LinkConfig::default()
+        });
+        link.dma_read(0x1000, 16 * 512);
+
+        // Bytes each tag still owes, replayed from the TLP log
+        let mut owed: HashMap<u8, u32> = HashMap::new();
+        for record in link.tlp_log() {
+            let tlp = record.tlp;
+            match tlp.kind {
+                TlpType::MemRead => {
+                    assert!(!owed.contains_key(&tlp.tag), "tag {} reissued while in flight", tlp.tag);
+                    owed.insert(tlp.tag, tlp.length);
+                }
+                TlpType::CompletionData => {
+                    let left = owed.get_mut(&tlp.tag).expect("completion for a tag nobody issued");
+                    *left -= tlp.length;
+                    if *left == 0 {
+                        owed.remove(&tlp.tag);
+                    }
+                }
+                _ => {}
+            }
+        }
+        assert!(owed.is_empty());
+    }
+
+    #[test]
+    fn more_tags_than_the_field_holds_are_capped() {
+        // Slow completions and unlimited credits: only tags limit the reads in flight
+        let latency_ns = 1_000_000;
+        let config = LinkConfig {
+            non_posted_credits: 0,
+            completion_latency_ns: latency_ns,
+            ..LinkConfig::default()
+        };
+        let mut link = PcieLink::new(LinkConfig { tags: 256, ..config.clone() });
+        assert!(link.dma_read(0, 256 * 512).ns < 2 * latency_ns);
+
+        // Read 257 can't go out until the first read's data is back
+        let mut link = PcieLink::new(LinkConfig { tags: 1000, ..config });
+        assert!(link.dma_read(0, 257 * 512).ns >= 2 * latency_ns);
+    }
+
+    // Nothing but serialization: no wire delay, credits or ACK traffic in the way
+    fn bare(generation: Generation, width: u8) -> LinkConfig {
+        LinkConfig {
+            generation,
+            width,
+            posted_credits: (0, 0),
+            ack_coalesce: u32::MAX,
+            flight_ns: 0,
+            ..LinkConfig::default()
+        }
+    }
+
+    #[test]
+    fn a_4k_dma_pays_for_headers_framing_and_dllps() {
+        let mut link = PcieLink::default();
+        let write = link.dma_write(0x1000, 4096);
+
+        // 16 MWr of 256 bytes, each with a 3 DW header, STP, sequence number and LCRC
+        assert_eq!((write.payload_bytes, write.tlps), (4096, 16));
+        assert_eq!(write.header_bytes, 16 * 12);
+        assert_eq!(write.framing_bytes, 16 * (4 + 2 + 4));
+        assert!(write.dllps > 0 && write.dllp_bytes == write.dllps * DLLP_BYTES);
+        assert!(write.efficiency() < 0.93);
+        assert_eq!(link.get_stats().wire_bytes, write.wire_bytes() + write.dllp_bytes);
+
+        let raw_ns = 4096.0 / link.raw_bandwidth() * 1e9;
+        assert!(write.ns as f64 > raw_ns);
+        assert!(write.effective_bandwidth() < link.raw_bandwidth());
+
+        // A read pays for its requests and a round trip on top
+        let read = link.dma_read(0x1000, 4096);
+        assert_eq!(read.payload_bytes, 4096);
+        assert!(read.wire_bytes() > write.wire_bytes());
+        assert!(read.ns > write.ns + link.config().completion_latency_ns);
+    }
+
+    #[test]
+    fn without_latency_a_transfer_takes_exactly_its_wire_bytes() {
+        let mut link = PcieLink::new(bare(Generation::Gen4, 16));
+        let cost = link.dma_write(0x1000, 4096);
+        let wire_ns = cost.wire_bytes() as f64 / link.raw_bandwidth() * 1e9;
+        assert!(cost.ns as f64 >= wire_ns && (cost.ns as f64) < wire_ns + 1.1, "{} ns for {:.1} ns of bytes", cost.ns, wire_ns);
+    }
+
+    #[test]
+    fn bandwidth_scales_with_generation_and_lanes() {
+        let raw = |generation, width| PcieLink::new(bare(generation, width)).raw_bandwidth();
+        assert_eq!(raw(Generation::Gen1, 1), 250e6);
+        assert_eq!(raw(Generation::Gen2, 1), 500e6);
+        assert_eq!(raw(Generation::Gen3, 8), 2.0 * raw(Generation::Gen3, 4));
+        assert_eq!(raw(Generation::Gen5, 4), 2.0 * raw(Generation::Gen4, 4));
+        // 128b/130b buys Gen3 more than its bit rate alone would
+        assert!(raw(Generation::Gen3, 1) > 1.6 * raw(Generation::Gen2, 1));
+
+        let time = |generation, width| PcieLink::new(bare(generation, width)).dma_write(0, 1 << 20).ns as f64;
+        let speedup = time(Generation::Gen3, 8) / time(Generation::Gen4, 8);
+        assert!((1.99..2.01).contains(&speedup), "Gen3 to Gen4: {speedup}");
+        let speedup = time(Generation::Gen4, 4) / time(Generation::Gen4, 16);
+        assert!((3.99..4.01).contains(&speedup), "x4 to x16: {speedup}");
+    }
+
+    #[test]
+    fn writes_stall_when_credits_run_out_and_resume_as_they_come_back() {
+        let unlimited = LinkConfig { posted_credits: (0, 0), ..LinkConfig::default() };
+        let free = PcieLink::new(unlimited.clone()).dma_write(0x1000, 4096);
+        assert_eq!(free.credit_stall_ns, 0);
+
+        let mut link = PcieLink::new(LinkConfig { posted_credits: (2, 0), ..unlimited.clone() });
+        let starved = link.dma_write(0x1000, 4096);
+        assert_eq!(starved.payload_bytes, 4096, "every write still gets through");
+        assert!(starved.credit_stall_ns > 0);
+        assert!(starved.ns > free.ns + starved.credit_stall_ns / 2);
+
+        // Two go back to back; the third waits for the first's UpdateFC
+        let writes: Vec<&TlpRecord> = link.tlp_log().iter().filter(|record| record.tlp.kind == TlpType::MemWrite).collect();
+        assert_eq!(writes[1].start_ns, writes[0].end_ns);
+        let round_trip = (2 * unlimited.flight_ns + unlimited.credit_return_ns) as f64;
+        assert!(writes[2].start_ns >= writes[0].end_ns + round_trip);
+
+        // Data credits run out the same way: 16 of them hold one 256-byte write
+        let data_starved = PcieLink::new(LinkConfig { posted_credits: (0, 16), ..unlimited }).dma_write(0x1000, 4096);
+        assert!(data_starved.credit_stall_ns > starved.credit_stall_ns);
+    }
+}
+
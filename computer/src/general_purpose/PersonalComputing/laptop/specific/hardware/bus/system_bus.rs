@@ -1,3 +1,8 @@
+use super::{PLIC_BASE, TIMER_BASE, UART_BASE};
+use crate::hardware::interrupt::PLIC_REGION_SIZE;
+use crate::hardware::io::devices::timer::TIMER_REGION_SIZE;
+use crate::hardware::io::devices::uart::UART_REGION_SIZE;
+
 pub struct SystemBus {
     peripherals: Vec<SystemPeripheral>,
     current_load: u64,
@@ -6,12 +11,15 @@ pub struct SystemBus {
 
 struct SystemPeripheral {
     id: u8,
+    #[allow(dead_code)]
     peripheral_type: PeripheralType,
     base_address: u32,
     size: u32,
+    #[allow(dead_code)]
     interrupt: Option<u8>,
 }
 
+#[allow(dead_code)]
 struct SystemOperation {
     peripheral_id: u8,
     operation_type: OperationType,
@@ -20,6 +28,7 @@ struct SystemOperation {
     cycles_remaining: u32,
 }
 
+#[allow(dead_code)]
 enum PeripheralType {
     Timer,
     UART,
@@ -29,6 +38,7 @@ enum PeripheralType {
     PowerManagement,
 }
 
+#[allow(dead_code)]
 enum OperationType {
     Read,
     Write,
@@ -47,19 +57,27 @@ impl SystemBus {
         bus.register_peripheral(SystemPeripheral {
             id: 0,
             peripheral_type: PeripheralType::Timer,
-            base_address: 0xC000_0000,
-            size: 0x1000,
+            base_address: TIMER_BASE as u32,
+            size: TIMER_REGION_SIZE as u32,
             interrupt: Some(0),
         });
 
         bus.register_peripheral(SystemPeripheral {
             id: 1,
             peripheral_type: PeripheralType::InterruptController,
-            base_address: 0xC000_1000,
-            size: 0x1000,
+            base_address: PLIC_BASE as u32,
+            size: PLIC_REGION_SIZE as u32,
             interrupt: None,
         });
 
+        bus.register_peripheral(SystemPeripheral {
+            id: 2,
+            peripheral_type: PeripheralType::UART,
+            base_address: UART_BASE as u32,
+            size: UART_REGION_SIZE as u32,
+            interrupt: Some(10),
+        });
+
         bus
     }
 
@@ -73,6 +91,7 @@ impl SystemBus {
             data: None,
             cycles_remaining: 5,
         });
+        self.current_load += 1;
 
         // Simulate read from peripheral
         Some(0)
@@ -87,6 +106,7 @@ impl SystemBus {
                 data: Some(data),
                 cycles_remaining: 5,
             });
+            self.current_load += 1;
         }
     }
 
@@ -101,6 +121,10 @@ impl SystemBus {
         self.peripherals.push(peripheral);
     }
 
+    pub fn pending_count(&self) -> usize {
+        self.pending_operations.len()
+    }
+
     pub fn tick(&mut self) {
         // Process pending operations
         self.pending_operations.retain_mut(|operation| {
@@ -114,3 +138,9 @@ impl SystemBus {
         });
     }
 }
+
+impl Default for SystemBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
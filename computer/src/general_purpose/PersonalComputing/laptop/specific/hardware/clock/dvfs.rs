@@ -0,0 +1,366 @@
+use super::governor::Governor;
+use super::{ClockDomain, ClockGenerator};
+use crate::hardware::power::regulators::VoltageRegulators;
+use crate::hardware::power::PowerDomain;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperatingPoint {
+    pub frequency: u64, // Hz
+    pub voltage_mv: u32,
+}
+
+// The frequencies a domain is validated at, with the voltage each needs,
+// lowest first
+#[derive(Debug, Clone)]
+pub struct OppTable {
+    points: Vec<OperatingPoint>,
+}
+
+// Dynamic voltage and frequency scaling. Each sample, a domain's governor
+// picks an operating point from its utilization, and the controller
+// sequences the change so the silicon never runs faster than its voltage
+// allows: going up, the rail rises before the PLL moves; going down, the
+// frequency drops before the rail does.
+pub struct DvfsController {
+    domains: HashMap<ClockDomain, DvfsDomain>,
+    sampling_ns: u64,
+    stats: DvfsStats,
+}
+
+struct DvfsDomain {
+    table: OppTable,
+    rail: PowerDomain,
+    governor: Governor,
+    current: usize,
+    target: usize,
+    transition: Transition,
+    busy_ns: u64,
+    window_ns: u64,
+    utilization: f32,      // Over the last completed sample
+    residency_ns: Vec<u64>, // Time at each point, like cpufreq's time_in_state
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Stable,
+    RaisingVoltage,  // Waiting for the rail before raising the frequency
+    Relocking,       // Waiting for the PLL
+    LoweringVoltage, // Frequency is down; the rail follows
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DvfsStats {
+    pub samples: u64,
+    pub transitions: u64,
+    pub transition_ns: u64, // Domain time spent mid-transition
+}
+
+impl OppTable {
+    pub fn new(mut points: Vec<OperatingPoint>) -> Self {
+        points.sort_by_key(|point| point.frequency);
+        points.dedup_by_key(|point| point.frequency);
+        Self { points }
+    }
+
+    pub fn laptop_cpu() -> Self {
+        Self::new(vec![
+            OperatingPoint { frequency: 800_000_000, voltage_mv: 650 },
+            OperatingPoint { frequency: 1_200_000_000, voltage_mv: 700 },
+            OperatingPoint { frequency: 1_600_000_000, voltage_mv: 750 },
+            OperatingPoint { frequency: 2_000_000_000, voltage_mv: 800 },
+            OperatingPoint { frequency: 2_400_000_000, voltage_mv: 850 },
+            OperatingPoint { frequency: 2_800_000_000, voltage_mv: 920 },
+            OperatingPoint { frequency: 3_200_000_000, voltage_mv: 1000 },
+            OperatingPoint { frequency: 3_600_000_000, voltage_mv: 1100 },
+        ])
+    }
+
+    pub fn laptop_gpu() -> Self {
+        Self::new(vec![
+            OperatingPoint { frequency: 400_000_000, voltage_mv: 650 },
+            OperatingPoint { frequency: 800_000_000, voltage_mv: 720 },
+            OperatingPoint { frequency: 1_200_000_000, voltage_mv: 800 },
+            OperatingPoint { frequency: 1_600_000_000, voltage_mv: 900 },
+        ])
+    }
+
+    // The points a part is rated for, up to its maximum frequency
+    pub fn up_to(mut self, frequency: u64) -> Self {
+        self.points.retain(|point| point.frequency <= frequency);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> OperatingPoint {
+        self.points[index.min(self.points.len() - 1)]
+    }
+
+    pub fn min(&self) -> OperatingPoint {
+        self.points[0]
+    }
+
+    pub fn max(&self) -> OperatingPoint {
+        self.points[self.points.len() - 1]
+    }
+
+    // The lowest point at or above a frequency, or the highest there is
+    pub fn ceil(&self, frequency: u64) -> usize {
+        self.points
+            .iter()
+            .position(|point| point.frequency >= frequency)
+            .unwrap_or(self.points.len() - 1)
+    }
+
+    pub fn points(&self) -> &[OperatingPoint] {
+        &self.points
+    }
+}
+
+impl DvfsController {
+    pub fn new(sampling_ns: u64) -> Self {
+        Self {
+            domains: HashMap::new(),
+            sampling_ns: sampling_ns.max(1),
+            stats: DvfsStats::default(),
+        }
+    }
+
+    // Puts a clock domain under DVFS, starting at the point its clock boots at.
+    // An empty table leaves the domain alone.
+    pub fn add_domain(&mut self, domain: ClockDomain, rail: PowerDomain, table: OppTable, governor: Governor, boot_frequency: u64) {
+        if table.is_empty() {
+            return;
+        }
+        let current = table.ceil(boot_frequency);
+        let residency_ns = vec![0; table.len()];
+        self.domains.insert(domain, DvfsDomain {
+            table,
+            rail,
+            governor,
+            current,
+            target: current,
+            transition: Transition::Stable,
+            busy_ns: 0,
+            window_ns: 0,
+            utilization: 0.0,
+            residency_ns,
+        });
+    }
+
+    pub fn set_governor(&mut self, domain: ClockDomain, governor: Governor) {
+        if let Some(state) = self.domains.get_mut(&domain) {
+            state.governor = governor;
+        }
+    }
+
+    // Components report how long they were busy; the governor sees the sum
+    // over each sample
+    pub fn record_busy(&mut self, domain: ClockDomain, busy_ns: u64) {
+        if let Some(state) = self.domains.get_mut(&domain) {
+            state.busy_ns += busy_ns;
+        }
+    }
+
+    pub fn update(&mut self, elapsed_ns: u64, clocks: &mut ClockGenerator, regulators: &mut VoltageRegulators) {
+        for (&domain, state) in self.domains.iter_mut() {
+            state.residency_ns[state.current] += elapsed_ns;
+            if state.transition != Transition::Stable {
+                self.stats.transition_ns += elapsed_ns;
+            }
+            state.step(domain, clocks, regulators);
+
+            state.window_ns += elapsed_ns;
+            if state.window_ns < self.sampling_ns {
+                continue;
+            }
+            state.utilization = (state.busy_ns as f64 / state.window_ns as f64).min(1.0) as f32;
+            state.busy_ns = 0;
+            state.window_ns = 0;
+            self.stats.samples += 1;
+
+            // A sample landing mid-transition is skipped, as cpufreq does
+            if state.transition != Transition::Stable {
+                continue;
+            }
+            let frequency = state.table.get(state.current).frequency;
+            let chosen = state.governor.select(&state.table, frequency, state.utilization);
+            if chosen != state.current {
+                state.begin(chosen, domain, clocks, regulators);
+                self.stats.transitions += 1;
+            }
+        }
+    }
+
+    // Methods for visualization system
+    pub fn get_stats(&self) -> &DvfsStats {
+        &self.stats
+    }
+
+    pub fn operating_point(&self, domain: ClockDomain) -> Option<OperatingPoint> {
+        self.domains.get(&domain).map(|state| state.table.get(state.current))
+    }
+
+    pub fn transition(&self, domain: ClockDomain) -> Option<Transition> {
+        self.domains.get(&domain).map(|state| state.transition)
+    }
+
+    pub fn governor(&self, domain: ClockDomain) -> Option<Governor> {
+        self.domains.get(&domain).map(|state| state.governor)
+    }
+
+    pub fn utilization(&self, domain: ClockDomain) -> f32 {
+        self.domains.get(&domain).map_or(0.0, |state| state.utilization)
+    }
+
+    // Frequency and time spent at it, per operating point
+    pub fn time_in_state(&self, domain: ClockDomain) -> Vec<(u64, u64)> {
+        self.domains
+            .get(&domain)
+            .map(|state| {
+                state.table.points()
+                    .iter()
+                    .zip(&state.residency_ns)
+                    .map(|(point, &ns)| (point.frequency, ns))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl DvfsDomain {
+    fn begin(&mut self, target: usize, domain: ClockDomain, clocks: &mut ClockGenerator, regulators: &mut VoltageRegulators) {
+        self.target = target;
+        if target > self.current {
+            let _ = regulators.set_target(self.rail, self.table.get(target).voltage_mv);
+            self.transition = Transition::RaisingVoltage;
+        } else {
+            let _ = clocks.set_frequency(domain, self.table.get(target).frequency);
+            self.transition = Transition::Relocking;
+        }
+    }
+
+    fn step(&mut self, domain: ClockDomain, clocks: &mut ClockGenerator, regulators: &mut VoltageRegulators) {
+        match self.transition {
+            Transition::Stable => {}
+            Transition::RaisingVoltage => {
+                if regulators.is_settled(self.rail) {
+                    let _ = clocks.set_frequency(domain, self.table.get(self.target).frequency);
+                    self.transition = Transition::Relocking;
+                }
+            }
+            Transition::Relocking => {
+                if !clocks.is_locked(domain) {
+                    return;
+                }
+                let lowering = self.target < self.current;
+                self.current = self.target;
+                if lowering {
+                    let _ = regulators.set_target(self.rail, self.table.get(self.current).voltage_mv);
+                    self.transition = Transition::LoweringVoltage;
+                } else {
+                    self.transition = Transition::Stable;
+                }
+            }
+            Transition::LoweringVoltage => {
+                if regulators.is_settled(self.rail) {
+                    self.transition = Transition::Stable;
+                }
+            }
+        }
+    }
+}
+
+impl Default for DvfsController {
+    fn default() -> Self {
+        Self::new(1_000_000) // 1 ms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_NS: u64 = 1_000;
+
+    fn machine(governor: Governor) -> (DvfsController, ClockGenerator, VoltageRegulators) {
+        let mut dvfs = DvfsController::new(SAMPLE_NS);
+        dvfs.add_domain(ClockDomain::CPU, PowerDomain::CPU, OppTable::laptop_cpu(), governor, 2_400_000_000);
+        (dvfs, ClockGenerator::default(), VoltageRegulators::default())
+    }
+
+    // One sample period, in the order Hardware::advance runs them
+    fn step(dvfs: &mut DvfsController, clocks: &mut ClockGenerator, regulators: &mut VoltageRegulators) {
+        clocks.advance(SAMPLE_NS);
+        regulators.update(SAMPLE_NS);
+        dvfs.update(SAMPLE_NS, clocks, regulators);
+    }
+
+    #[test]
+    fn raising_frequency_waits_for_the_rail() {
+        let (mut dvfs, mut clocks, mut regulators) = machine(Governor::Performance);
+        step(&mut dvfs, &mut clocks, &mut regulators);
+        assert_eq!(dvfs.transition(ClockDomain::CPU), Some(Transition::RaisingVoltage));
+
+        // 850 to 1100 mV at 10 mV/µs; the clock mustn't move before the rail arrives
+        while dvfs.transition(ClockDomain::CPU) == Some(Transition::RaisingVoltage) {
+            assert_eq!(clocks.get_frequency(ClockDomain::CPU), Some(2_400_000_000));
+            step(&mut dvfs, &mut clocks, &mut regulators);
+        }
+        assert_eq!(regulators.voltage_mv(PowerDomain::CPU), Some(1100.0));
+        assert_eq!(dvfs.transition(ClockDomain::CPU), Some(Transition::Relocking));
+
+        while dvfs.transition(ClockDomain::CPU) != Some(Transition::Stable) {
+            step(&mut dvfs, &mut clocks, &mut regulators);
+        }
+        assert_eq!(clocks.get_frequency(ClockDomain::CPU), Some(3_600_000_000));
+        assert_eq!(dvfs.operating_point(ClockDomain::CPU).map(|point| point.voltage_mv), Some(1100));
+
+        // Samples that landed mid-transition didn't start another one
+        assert_eq!(dvfs.get_stats().transitions, 1);
+        assert!(dvfs.get_stats().transition_ns > 0);
+    }
+
+    #[test]
+    fn lowering_frequency_drops_the_clock_before_the_rail() {
+        let (mut dvfs, mut clocks, mut regulators) = machine(Governor::Powersave);
+        step(&mut dvfs, &mut clocks, &mut regulators);
+
+        // 800 MHz is the same VCO divided by three, so there is no relock
+        assert_eq!(clocks.get_frequency(ClockDomain::CPU), Some(800_000_000));
+        step(&mut dvfs, &mut clocks, &mut regulators);
+        assert_eq!(dvfs.transition(ClockDomain::CPU), Some(Transition::LoweringVoltage));
+        assert!(regulators.voltage_mv(PowerDomain::CPU).unwrap() > 650.0);
+
+        while dvfs.transition(ClockDomain::CPU) != Some(Transition::Stable) {
+            step(&mut dvfs, &mut clocks, &mut regulators);
+        }
+        assert_eq!(regulators.voltage_mv(PowerDomain::CPU), Some(650.0));
+        assert_eq!(dvfs.operating_point(ClockDomain::CPU).map(|point| point.frequency), Some(800_000_000));
+    }
+
+    #[test]
+    fn governor_sees_recorded_busy_time() {
+        let (mut dvfs, mut clocks, mut regulators) = machine(Governor::ondemand());
+        dvfs.record_busy(ClockDomain::CPU, 900);
+        step(&mut dvfs, &mut clocks, &mut regulators);
+        assert_eq!(dvfs.utilization(ClockDomain::CPU), 0.9);
+        assert_eq!(dvfs.transition(ClockDomain::CPU), Some(Transition::RaisingVoltage));
+
+        // Residency is charged to the point the domain was at
+        let residency = dvfs.time_in_state(ClockDomain::CPU);
+        assert_eq!(residency[4], (2_400_000_000, SAMPLE_NS));
+        assert_eq!(residency.iter().map(|&(_, ns)| ns).sum::<u64>(), SAMPLE_NS);
+
+        // A domain with no operating points isn't scaled at all
+        dvfs.add_domain(ClockDomain::GPU, PowerDomain::GPU, OppTable::new(Vec::new()), Governor::Performance, 0);
+        assert_eq!(dvfs.operating_point(ClockDomain::GPU), None);
+    }
+}
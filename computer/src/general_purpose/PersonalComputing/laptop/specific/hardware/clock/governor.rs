@@ -0,0 +1,91 @@
+use super::dvfs::OppTable;
+
+// cpufreq-style policies: given how busy a domain was over the last sample,
+// pick the operating point to run at next
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Governor {
+    Performance,                      // Always the highest point
+    Powersave,                        // Always the lowest point
+    Ondemand { up_threshold: f32 },   // Jump to max above the threshold, scale with load below it
+    #[default]
+    Schedutil,                        // 1.25 × frequency-invariant utilization, for headroom
+}
+
+impl Governor {
+    pub fn ondemand() -> Self {
+        Governor::Ondemand { up_threshold: 0.80 }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Governor::Performance => "performance",
+            Governor::Powersave => "powersave",
+            Governor::Ondemand { .. } => "ondemand",
+            Governor::Schedutil => "schedutil",
+        }
+    }
+
+    // Index into the table. `utilization` is the busy fraction at `current` Hz.
+    pub fn select(&self, table: &OppTable, current: u64, utilization: f32) -> usize {
+        let utilization = utilization.clamp(0.0, 1.0) as f64;
+        let (min, max) = (table.min().frequency, table.max().frequency);
+        match *self {
+            Governor::Performance => table.len() - 1,
+            Governor::Powersave => 0,
+            Governor::Ondemand { up_threshold } => {
+                if utilization > up_threshold as f64 {
+                    table.len() - 1
+                } else {
+                    table.ceil(min + ((max - min) as f64 * utilization) as u64)
+                }
+            }
+            Governor::Schedutil => {
+                // 1.25 × max × (utilization × current / max): made frequency
+                // invariant, so a slow clock doesn't look falsely loaded
+                table.ceil((1.25 * current as f64 * utilization) as u64)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GHZ: u64 = 1_000_000_000;
+
+    #[test]
+    fn fixed_policies_pin_the_ends_of_the_table() {
+        let table = OppTable::laptop_cpu();
+        assert_eq!(Governor::Performance.select(&table, 800_000_000, 0.0), table.len() - 1);
+        assert_eq!(Governor::Powersave.select(&table, 3_600_000_000, 1.0), 0);
+    }
+
+    #[test]
+    fn ondemand_jumps_to_max_above_the_threshold() {
+        let table = OppTable::laptop_cpu();
+        let governor = Governor::ondemand();
+        assert_eq!(governor.select(&table, 2 * GHZ, 0.9), table.len() - 1);
+
+        // Below it, load scales linearly between min and max: half load
+        // is 2.2 GHz, rounded up to the 2.4 GHz point
+        assert_eq!(table.get(governor.select(&table, 2 * GHZ, 0.5)).frequency, 2_400_000_000);
+        assert_eq!(governor.select(&table, 2 * GHZ, 0.0), 0);
+    }
+
+    #[test]
+    fn schedutil_is_frequency_invariant() {
+        let table = OppTable::laptop_cpu();
+
+        // Half busy at 2 GHz is 1 GHz of work; with headroom that wants 1.25 GHz
+        assert_eq!(table.get(Governor::Schedutil.select(&table, 2 * GHZ, 0.5)).frequency, 1_600_000_000);
+
+        // Flat out at the slowest point only asks for the next one up
+        assert_eq!(table.get(Governor::Schedutil.select(&table, 800_000_000, 1.0)).frequency, 1_200_000_000);
+        assert_eq!(
+            Governor::Schedutil.select(&table, 800_000_000, 1.5),
+            Governor::Schedutil.select(&table, 800_000_000, 1.0),
+            "utilization is clamped to 1"
+        );
+    }
+}
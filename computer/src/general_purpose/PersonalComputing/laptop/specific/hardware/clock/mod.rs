@@ -1,54 +1,326 @@
+use crate::simulation::time::SimTime;
 use std::collections::HashMap;
 
+pub mod dvfs;
+pub mod governor;
+
+const NS_PER_SECOND: u128 = 1_000_000_000;
+const MAX_DIVIDER: u32 = 64;
+
+// Every domain has its own PLL multiplying the reference clock up into the
+// VCO range, and a post-divider bringing it down to the domain frequency.
+// Changing the divider is glitch-free and immediate; changing the feedback
+// divider moves the VCO, and the domain is gated until the PLL relocks.
 pub struct ClockGenerator {
     domains: HashMap<ClockDomain, Clock>,
-    pll: PhaseLockedLoop,
     config: ClockConfig,
     stats: ClockStats,
 }
 
 struct Clock {
     frequency: u64,  // Hz
-    multiplier: u32,
-    divider: u32,
+    divider: u32,    // Post-divider after the PLL
     enabled: bool,
+    pll: PhaseLockedLoop,
+    pending_divider: Option<u32>, // Applied once the PLL relocks
+    residue: u128,   // Fraction of a tick carried between advances, in Hz·ns
 }
 
 struct PhaseLockedLoop {
     reference_freq: u64,
     feedback_div: u32,
-    output_div: u32,
     locked: bool,
+    lock_remaining_ns: u64,
+}
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub enum ClockDomain {
+    CPU,
+    GPU,
+    Memory,
+    Bus,
+    IO,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClockConfig {
+    pub ref_freq: u64,
+    pub cpu_freq: u64,
+    pub gpu_freq: u64,
+    pub mem_freq: u64,
+    pub bus_freq: u64,
+    pub io_freq: u64,
+    pub vco_min: u64,
+    pub vco_max: u64,
+    pub lock_time_ns: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClockError {
+    InvalidDomain,
+    Unreachable(u64), // No feedback and post-divider pair keeps the VCO in range
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ClockStats {
+    pub relocks: u64,
+    pub divider_changes: u64, // Frequency changes that didn't need a relock
+    pub gated_ns: u64,        // Domain time lost waiting for PLL lock
 }
 
+pub const ALL_DOMAINS: [ClockDomain; 5] = [
+    ClockDomain::CPU,
+    ClockDomain::GPU,
+    ClockDomain::Memory,
+    ClockDomain::Bus,
+    ClockDomain::IO,
+];
+
 impl ClockGenerator {
     pub fn new(config: ClockConfig) -> Self {
-        let mut domains = HashMap::new();
-        domains.insert(ClockDomain::CPU, Clock::new(config.cpu_freq));
-        domains.insert(ClockDomain::Memory, Clock::new(config.mem_freq));
-        domains.insert(ClockDomain::Bus, Clock::new(config.bus_freq));
-
-        Self {
-            domains,
-            pll: PhaseLockedLoop::new(config.ref_freq),
-            config,
+        let mut generator = Self {
+            domains: HashMap::new(),
+            config: config.clone(),
             stats: ClockStats::default(),
+        };
+        for (domain, freq) in [
+            (ClockDomain::CPU, config.cpu_freq),
+            (ClockDomain::GPU, config.gpu_freq),
+            (ClockDomain::Memory, config.mem_freq),
+            (ClockDomain::Bus, config.bus_freq),
+            (ClockDomain::IO, config.io_freq),
+        ] {
+            // Boot frequencies come out of reset already locked
+            let (feedback, divider) = generator
+                .calculate_dividers(freq, None)
+                .unwrap_or((config.vco_min.div_ceil(config.ref_freq) as u32, 1));
+            generator.domains.insert(domain, Clock::new(config.ref_freq, feedback, divider));
         }
+        generator
     }
 
     pub fn set_frequency(&mut self, domain: ClockDomain, freq: u64) -> Result<(), ClockError> {
-        if let Some(clock) = self.domains.get_mut(&domain) {
-            let (mul, div) = self.calculate_dividers(freq)?;
-            clock.multiplier = mul;
-            clock.divider = div;
-            clock.frequency = freq;
-            Ok(())
+        let current = self.domains.get(&domain).ok_or(ClockError::InvalidDomain)?.pll.feedback_div;
+        let (feedback, divider) = self.calculate_dividers(freq, Some(current))?;
+        let lock_time = self.config.lock_time_ns;
+        let clock = self.domains.get_mut(&domain).ok_or(ClockError::InvalidDomain)?;
+
+        if feedback == clock.pll.feedback_div && clock.pll.locked {
+            clock.divider = divider;
+            clock.frequency = clock.pll.vco() / divider as u64;
+            self.stats.divider_changes += 1;
         } else {
-            Err(ClockError::InvalidDomain)
+            clock.pll.relock(feedback, lock_time);
+            clock.pending_divider = Some(divider);
+            self.stats.relocks += 1;
         }
+        Ok(())
     }
 
     pub fn get_frequency(&self, domain: ClockDomain) -> Option<u64> {
         self.domains.get(&domain).map(|clock| clock.frequency)
     }
-} 
\ No newline at end of file
+
+    pub fn set_enabled(&mut self, domain: ClockDomain, enabled: bool) -> Result<(), ClockError> {
+        self.domains.get_mut(&domain).ok_or(ClockError::InvalidDomain)?.enabled = enabled;
+        Ok(())
+    }
+
+    pub fn is_locked(&self, domain: ClockDomain) -> bool {
+        self.domains.get(&domain).is_some_and(|clock| clock.pll.locked)
+    }
+
+    // Clock period in picoseconds, 0 for a stopped domain
+    pub fn period_ps(&self, domain: ClockDomain) -> u64 {
+        match self.domains.get(&domain) {
+            Some(clock) if clock.frequency > 0 => 1_000_000_000_000 / clock.frequency,
+            _ => 0,
+        }
+    }
+
+    // Conversions between a domain's cycles and the global time base
+    pub fn cycles_to_time(&self, domain: ClockDomain, cycles: u64) -> SimTime {
+        SimTime::from_cycles(cycles, self.get_frequency(domain).unwrap_or(0))
+    }
+
+    pub fn time_to_cycles(&self, domain: ClockDomain, time: SimTime) -> u64 {
+        time.to_cycles(self.get_frequency(domain).unwrap_or(0))
+    }
+
+    // Moves simulated time forward and returns how many edges each domain
+    // saw. Fractions of a tick carry over, so slow domains don't drift, and a
+    // relocking domain gets nothing until its PLL locks.
+    pub fn advance(&mut self, elapsed_ns: u64) -> HashMap<ClockDomain, u64> {
+        let mut ticks = HashMap::new();
+        for (&domain, clock) in self.domains.iter_mut() {
+            let mut running_ns = elapsed_ns;
+            if !clock.pll.locked {
+                let gated = running_ns.min(clock.pll.lock_remaining_ns);
+                clock.pll.lock_remaining_ns -= gated;
+                running_ns -= gated;
+                self.stats.gated_ns += gated;
+                if clock.pll.lock_remaining_ns == 0 {
+                    clock.pll.locked = true;
+                    if let Some(divider) = clock.pending_divider.take() {
+                        clock.divider = divider;
+                    }
+                    clock.frequency = clock.pll.vco() / clock.divider as u64;
+                    clock.residue = 0;
+                }
+            }
+            if !clock.enabled || !clock.pll.locked {
+                ticks.insert(domain, 0);
+                continue;
+            }
+            clock.residue += clock.frequency as u128 * running_ns as u128;
+            ticks.insert(domain, (clock.residue / NS_PER_SECOND) as u64);
+            clock.residue %= NS_PER_SECOND;
+        }
+        ticks
+    }
+
+    // Methods for visualization system
+    pub fn get_stats(&self) -> &ClockStats {
+        &self.stats
+    }
+
+    // Feedback divider, post-divider and VCO frequency of a domain's PLL
+    pub fn pll_settings(&self, domain: ClockDomain) -> Option<(u32, u32, u64)> {
+        self.domains
+            .get(&domain)
+            .map(|clock| (clock.pll.feedback_div, clock.divider, clock.pll.vco()))
+    }
+
+    // Helper methods
+    // Closest reachable frequency with the VCO in range. Among equally close
+    // pairs, one that keeps the current feedback divider wins, since it
+    // needs no relock.
+    fn calculate_dividers(&self, freq: u64, current_feedback: Option<u32>) -> Result<(u32, u32), ClockError> {
+        let reference = self.config.ref_freq;
+        (1..=MAX_DIVIDER)
+            .filter_map(|divider| {
+                let feedback = ((freq * divider as u64 + reference / 2) / reference) as u32;
+                let vco = reference * feedback as u64;
+                if feedback == 0 || vco < self.config.vco_min || vco > self.config.vco_max {
+                    return None;
+                }
+                let error = (vco / divider as u64).abs_diff(freq);
+                Some((error, Some(feedback) != current_feedback, divider, feedback))
+            })
+            .min()
+            .map(|(_, _, divider, feedback)| (feedback, divider))
+            .ok_or(ClockError::Unreachable(freq))
+    }
+}
+
+impl Clock {
+    fn new(reference_freq: u64, feedback_div: u32, divider: u32) -> Self {
+        let pll = PhaseLockedLoop::new(reference_freq, feedback_div);
+        Self {
+            frequency: pll.vco() / divider as u64,
+            divider,
+            enabled: true,
+            pll,
+            pending_divider: None,
+            residue: 0,
+        }
+    }
+}
+
+impl PhaseLockedLoop {
+    fn new(reference_freq: u64, feedback_div: u32) -> Self {
+        Self {
+            reference_freq,
+            feedback_div,
+            locked: true,
+            lock_remaining_ns: 0,
+        }
+    }
+
+    fn vco(&self) -> u64 {
+        self.reference_freq * self.feedback_div as u64
+    }
+
+    fn relock(&mut self, feedback_div: u32, lock_time_ns: u64) {
+        self.feedback_div = feedback_div;
+        self.locked = false;
+        self.lock_remaining_ns = lock_time_ns.max(1);
+    }
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        Self {
+            ref_freq: 100_000_000,    // 100 MHz BCLK
+            cpu_freq: 2_400_000_000,
+            gpu_freq: 1_200_000_000,
+            mem_freq: 1_600_000_000,  // DDR4-3200 I/O clock
+            bus_freq: 1_000_000_000,
+            io_freq: 100_000_000,
+            vco_min: 1_600_000_000,
+            vco_max: 4_800_000_000,
+            lock_time_ns: 20_000,     // 20 µs
+        }
+    }
+}
+
+impl Default for ClockGenerator {
+    fn default() -> Self {
+        Self::new(ClockConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fractions_of_a_tick_carry_between_advances() {
+        let mut clocks = ClockGenerator::default();
+
+        // 100 MHz is a tick every 10 ns; 15 ns twice is three ticks, not two
+        assert_eq!(clocks.advance(15)[&ClockDomain::IO], 1);
+        assert_eq!(clocks.advance(15)[&ClockDomain::IO], 2);
+
+        // 2.4 GHz is 2.4 ticks per ns
+        let cpu: u64 = (0..5).map(|_| clocks.advance(1)[&ClockDomain::CPU]).sum();
+        assert_eq!(cpu, 12);
+        assert_eq!(clocks.period_ps(ClockDomain::CPU), 416);
+    }
+
+    #[test]
+    fn divider_changes_are_immediate_and_vco_changes_relock() {
+        let mut clocks = ClockGenerator::default();
+        assert_eq!(clocks.pll_settings(ClockDomain::CPU), Some((24, 1, 2_400_000_000)));
+
+        // Halving the frequency keeps the VCO where it is
+        clocks.set_frequency(ClockDomain::CPU, 1_200_000_000).unwrap();
+        assert_eq!(clocks.get_frequency(ClockDomain::CPU), Some(1_200_000_000));
+        assert_eq!(clocks.pll_settings(ClockDomain::CPU), Some((24, 2, 2_400_000_000)));
+        assert_eq!(clocks.get_stats().divider_changes, 1);
+
+        // 3 GHz needs a new VCO; the domain is gated for the 20 µs lock time
+        clocks.set_frequency(ClockDomain::CPU, 3_000_000_000).unwrap();
+        assert!(!clocks.is_locked(ClockDomain::CPU));
+        assert_eq!(clocks.get_frequency(ClockDomain::CPU), Some(1_200_000_000));
+        assert_eq!(clocks.advance(10_000)[&ClockDomain::CPU], 0);
+        assert_eq!(clocks.advance(10_001)[&ClockDomain::CPU], 3, "the nanosecond after lock runs at 3 GHz");
+        assert!(clocks.is_locked(ClockDomain::CPU));
+        assert_eq!(clocks.get_frequency(ClockDomain::CPU), Some(3_000_000_000));
+        assert_eq!(clocks.get_stats().relocks, 1);
+        assert_eq!(clocks.get_stats().gated_ns, 20_000);
+    }
+
+    #[test]
+    fn unreachable_frequencies_and_gated_domains() {
+        let mut clocks = ClockGenerator::default();
+        assert_eq!(clocks.set_frequency(ClockDomain::CPU, 5_000_000_000), Err(ClockError::Unreachable(5_000_000_000)));
+        assert_eq!(clocks.get_frequency(ClockDomain::CPU), Some(2_400_000_000));
+
+        clocks.set_enabled(ClockDomain::GPU, false).unwrap();
+        let ticks = clocks.advance(1_000);
+        assert_eq!(ticks[&ClockDomain::GPU], 0);
+        assert_eq!(ticks[&ClockDomain::Bus], 1_000);
+        assert_eq!(clocks.time_to_cycles(ClockDomain::Bus, SimTime::from_ns(3)), 3);
+    }
+}
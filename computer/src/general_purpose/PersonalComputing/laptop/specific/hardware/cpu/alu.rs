@@ -99,3 +99,9 @@ impl ALU {
          self.flags.overflow, self.flags.negative)
     }
 }
+
+impl Default for ALU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
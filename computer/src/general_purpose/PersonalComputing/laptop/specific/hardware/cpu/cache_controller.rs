@@ -1,34 +1,60 @@
-use std::collections::HashMap;
+use crate::hardware::clock::ClockDomain;
+use crate::hardware::dma::coherence::{CachedMemory, Snoop};
+use crate::hardware::memory::access::{AccessKind, AccessTrace, Hop, HopOutcome, MemoryLevel};
+use crate::hardware::memory::error::MemoryResult;
+use crate::hardware::memory::physical::PhysicalMemory;
+use std::collections::VecDeque;
 
+const TRACE_LIMIT: usize = 256;
+const LINE_SIZE: usize = 64;
+
+// One core's private L1 and L2. Both write through to the L3 all the cores
+// share, so they never hold the only copy of anything, and they catch up on
+// what other cores and devices did to their lines before every access. The
+// L3 and memory aren't held here; every access borrows them for as long as
+// it takes, so the whole machine stays one owned tree.
 pub struct CacheController {
+    core: usize, // Which of the L3's snoop queues is ours
     l1_cache: Cache,
     l2_cache: Cache,
-    l3_cache: Cache,
-    memory_bus: *mut Bus,
-    
+    frequency: u64, // Core clock, to express memory hops in core cycles
+
     // Statistics for visualization
     access_count: u64,
     miss_count: u64,
+    traces: VecDeque<AccessTrace>,
+}
+
+// Size and speed of one level; lines are always 64 bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheGeometry {
+    pub size: usize, // Bytes
+    pub ways: usize,
+    pub latency: u64, // Core cycles
 }
 
-struct Cache {
+pub struct Cache {
     lines: Vec<CacheLine>,
     sets: usize,
     ways: usize,
     line_size: usize,
-    write_policy: WritePolicy,
+    latency: u64, // Core cycles for a lookup and the data's return
+    level: MemoryLevel,
+    #[allow(dead_code)]
     replacement_policy: ReplacementPolicy,
+    access_clock: u64,
 }
 
+#[derive(Clone)]
 struct CacheLine {
     tag: u32,
     data: Vec<u8>,
     state: CacheState,
     last_access: u64,
-    dirty: bool,
+    stale: bool, // A device wrote RAM under it without snooping
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum CacheState {
     Modified,
     Exclusive,
@@ -36,11 +62,7 @@ pub enum CacheState {
     Invalid,
 }
 
-enum WritePolicy {
-    WriteBack,
-    WriteThrough,
-}
-
+#[allow(dead_code)]
 enum ReplacementPolicy {
     LRU,
     FIFO,
@@ -48,68 +70,61 @@ enum ReplacementPolicy {
 }
 
 impl CacheController {
-    pub fn new(memory_bus: *mut Bus) -> Self {
+    pub fn new() -> Self {
+        Self::with_levels(
+            0,
+            CacheGeometry { size: 32 * 1024, ways: 8, latency: 4 },
+            CacheGeometry { size: 256 * 1024, ways: 8, latency: 12 },
+        )
+    }
+
+    // Each level's set count must come out a power of two; machine
+    // descriptions are checked for that before they get here
+    pub fn with_levels(core: usize, l1: CacheGeometry, l2: CacheGeometry) -> Self {
         Self {
-            l1_cache: Cache::new(32, 8, 64, WritePolicy::WriteThrough),
-            l2_cache: Cache::new(256, 8, 64, WritePolicy::WriteBack),
-            l3_cache: Cache::new(2048, 16, 64, WritePolicy::WriteBack),
-            memory_bus,
+            core,
+            l1_cache: Cache::new(l1, MemoryLevel::L1),
+            l2_cache: Cache::new(l2, MemoryLevel::L2),
+            frequency: 3_000_000_000,
             access_count: 0,
             miss_count: 0,
+            traces: VecDeque::new(),
         }
     }
 
-    pub fn read(&mut self, address: u32) -> Option<u32> {
-        self.access_count += 1;
-        
-        // Try L1 cache first
-        if let Some(data) = self.l1_cache.read(address) {
-            return Some(data);
-        }
-        
-        // L1 miss, try L2
-        self.miss_count += 1;
-        if let Some(data) = self.l2_cache.read(address) {
-            self.l1_cache.insert(address, data);
-            return Some(data);
-        }
-        
-        // L2 miss, try L3
-        if let Some(data) = self.l3_cache.read(address) {
-            self.l2_cache.insert(address, data);
-            self.l1_cache.insert(address, data);
-            return Some(data);
-        }
-        
-        // Cache miss, fetch from memory
-        let data = unsafe { (*self.memory_bus).read(address) };
-        if let Some(data) = data {
-            self.l3_cache.insert(address, data);
-            self.l2_cache.insert(address, data);
-            self.l1_cache.insert(address, data);
-            Some(data)
-        } else {
-            None
-        }
+    // A load's trip down the hierarchy and back. Levels that missed are
+    // filled on the way back up.
+    pub fn read<M: PhysicalMemory>(&mut self, address: u32, memory: &mut CachedMemory<M>) -> MemoryResult<AccessTrace> {
+        self.snoop(memory);
+        let address = address & !3; // Word accesses
+        let mut trace = AccessTrace::new(AccessKind::Load, address as u64);
+        let line = self.fetch_line(address, memory, &mut trace)?;
+        let offset = self.l1_cache.get_offset(address);
+        trace.value = u32::from_le_bytes(line[offset..offset + 4].try_into().unwrap());
+        Ok(self.finish(trace, memory))
     }
 
-    pub fn write(&mut self, address: u32, data: u32) {
-        self.access_count += 1;
-        
-        // Write to L1 cache
-        match self.l1_cache.write_policy {
-            WritePolicy::WriteThrough => {
-                // Write to all cache levels and memory
-                self.l1_cache.write(address, data);
-                self.l2_cache.write(address, data);
-                self.l3_cache.write(address, data);
-                unsafe { (*self.memory_bus).write(address, data); }
-            }
-            WritePolicy::WriteBack => {
-                // Write only to L1, mark as dirty
-                self.l1_cache.write(address, data);
+    // Stores allocate like loads do, update the private copies and go on
+    // through to the L3, where write-back keeps them until eviction
+    pub fn write<M: PhysicalMemory>(&mut self, address: u32, data: u32, memory: &mut CachedMemory<M>) -> MemoryResult<AccessTrace> {
+        self.snoop(memory);
+        let address = address & !3;
+        let mut trace = AccessTrace::new(AccessKind::Store, address as u64);
+        trace.value = data;
+        self.fetch_line(address, memory, &mut trace)?;
+        for level in 0..2 {
+            let cache = self.level_mut(level);
+            if let Some(index) = cache.lookup(address) {
+                cache.write_word(index, address, data);
             }
         }
+        memory.core_write(self.core, address as u64, &data.to_le_bytes())?;
+        Ok(self.finish(trace, memory))
+    }
+
+    // The clock generator owns the frequency; the caches just follow it
+    pub fn set_frequency(&mut self, frequency: u64) {
+        self.frequency = frequency;
     }
 
     // Methods for visualization system
@@ -120,72 +135,185 @@ impl CacheController {
         self.miss_count as f32 / self.access_count as f32
     }
 
-    pub fn get_cache_state(&self, level: usize) -> &Cache {
+    // The private levels, 1 and 2; the L3 is the shared CachedMemory's
+    pub fn get_cache_state(&self, level: usize) -> Option<&Cache> {
         match level {
-            1 => &self.l1_cache,
-            2 => &self.l2_cache,
-            3 => &self.l3_cache,
-            _ => panic!("Invalid cache level")
+            1 => Some(&self.l1_cache),
+            2 => Some(&self.l2_cache),
+            _ => None,
         }
     }
 
-    pub fn get_line_state(&self, level: usize, set: usize, way: usize) -> CacheState {
-        let cache = self.get_cache_state(level);
-        let index = set * cache.ways + way;
-        cache.lines[index].state
+    pub fn get_line_state(&self, level: usize, set: usize, way: usize) -> Option<CacheState> {
+        let cache = self.get_cache_state(level)?;
+        if way >= cache.ways {
+            return None;
+        }
+        cache.lines.get(set * cache.ways + way).map(|line| line.state)
+    }
+
+    // The most recent accesses, oldest first, with every hop they took
+    pub fn recent_traces(&self) -> &VecDeque<AccessTrace> {
+        &self.traces
+    }
+
+    // Helper methods
+    // Finds the line in the first private level holding it, or gets it
+    // from the L3, recording a hop per level visited
+    fn fetch_line<M: PhysicalMemory>(&mut self, address: u32, memory: &mut CachedMemory<M>, trace: &mut AccessTrace) -> MemoryResult<Vec<u8>> {
+        self.access_count += 1;
+        let mut found = None;
+        for level in 0..2 {
+            let cache = self.level_mut(level);
+            let hit = cache.lookup(address);
+            trace.hops.push(Hop {
+                level: cache.level,
+                outcome: if hit.is_some() { HopOutcome::Hit } else { HopOutcome::Miss },
+                cycles: cache.latency,
+                domain: ClockDomain::CPU,
+            });
+            if let Some(index) = hit {
+                let line = &mut cache.lines[index];
+                let stale = std::mem::take(&mut line.stale); // Reported once per copy
+                found = Some((level, line.data.clone()));
+                if stale {
+                    memory.stale_read(address as u64 & !(LINE_SIZE as u64 - 1));
+                }
+                break;
+            }
+            if level == 0 {
+                self.miss_count += 1;
+            }
+        }
+
+        let (hit_level, line) = match found {
+            Some(found) => found,
+            None => {
+                let line_address = self.l2_cache.line_address(address);
+                (2, memory.core_read(line_address as u64, &mut trace.hops)?.to_vec())
+            }
+        };
+
+        // Fill the levels that missed. Nothing in them is dirty, so victims
+        // just go.
+        for level in (0..hit_level).rev() {
+            self.level_mut(level).fill(address, &line);
+        }
+        Ok(line)
+    }
+
+    // Applies what happened to lines this core may hold since it last looked
+    fn snoop<M: PhysicalMemory>(&mut self, memory: &mut CachedMemory<M>) {
+        for snoop in memory.take_snoops(self.core) {
+            for level in 0..2 {
+                let cache = self.level_mut(level);
+                match snoop {
+                    // Beyond the 32-bit physical space the private levels cover
+                    Snoop::Invalidate(line) | Snoop::Stale(line) if line > u32::MAX as u64 => {}
+                    Snoop::Invalidate(line) => cache.invalidate(line as u32),
+                    Snoop::Stale(line) => cache.mark_stale(line as u32),
+                    Snoop::InvalidateAll => cache.invalidate_all(),
+                }
+            }
+        }
+    }
+
+    fn finish<M: PhysicalMemory>(&mut self, mut trace: AccessTrace, memory: &CachedMemory<M>) -> AccessTrace {
+        let (core, memory_clock) = (self.frequency, memory.memory().frequency());
+        let time = trace.time_with(|domain| if domain == ClockDomain::CPU { core } else { memory_clock });
+        trace.core_cycles = time.to_cycles(core).max(1);
+        if self.traces.len() == TRACE_LIMIT {
+            self.traces.pop_front();
+        }
+        self.traces.push_back(trace.clone());
+        trace
+    }
+
+    fn level_mut(&mut self, level: usize) -> &mut Cache {
+        match level {
+            0 => &mut self.l1_cache,
+            _ => &mut self.l2_cache,
+        }
     }
 }
 
 impl Cache {
-    pub fn new(sets: usize, ways: usize, line_size: usize, write_policy: WritePolicy) -> Self {
-        let total_lines = sets * ways;
+    fn new(geometry: CacheGeometry, level: MemoryLevel) -> Self {
+        let ways = geometry.ways.max(1);
+        let sets = (geometry.size / (ways * LINE_SIZE)).max(1);
         Self {
             lines: vec![CacheLine {
                 tag: 0,
-                data: vec![0; line_size],
+                data: vec![0; LINE_SIZE],
                 state: CacheState::Invalid,
                 last_access: 0,
-                dirty: false,
-            }; total_lines],
+                stale: false,
+            }; sets * ways],
             sets,
             ways,
-            line_size,
-            write_policy,
+            line_size: LINE_SIZE,
+            latency: geometry.latency,
+            level,
             replacement_policy: ReplacementPolicy::LRU,
+            access_clock: 0,
         }
     }
 
-    pub fn read(&self, address: u32) -> Option<u32> {
+    // Index of the line holding `address`, marking it recently used
+    fn lookup(&mut self, address: u32) -> Option<usize> {
+        self.access_clock += 1;
+        let index = self.find(address)?;
+        self.lines[index].last_access = self.access_clock;
+        Some(index)
+    }
+
+    fn write_word(&mut self, index: usize, address: u32, data: u32) {
+        let offset = self.get_offset(address);
+        self.lines[index].data[offset..offset + 4].copy_from_slice(&data.to_le_bytes());
+    }
+
+    fn fill(&mut self, address: u32, data: &[u8]) {
         let set_index = self.get_set_index(address);
+        let index = self.evict_line(set_index);
         let tag = self.get_tag(address);
+        self.access_clock += 1;
 
-        // Check if line exists in cache
-        for way in 0..self.ways {
-            let line_index = set_index * self.ways + way;
-            let line = &self.lines[line_index];
-            
-            if line.tag == tag && line.state != CacheState::Invalid {
-                // Cache hit
-                let offset = self.get_offset(address);
-                return Some(u32::from_le_bytes(line.data[offset..offset+4].try_into().unwrap()));
-            }
-        }
-        None
+        let line = &mut self.lines[index];
+        line.tag = tag;
+        line.data.copy_from_slice(data);
+        line.state = CacheState::Exclusive;
+        line.stale = false;
+        line.last_access = self.access_clock;
     }
 
-    pub fn write(&mut self, address: u32, data: u32) {
-        let set_index = self.get_set_index(address);
+    // Snoops look the line up without counting as a use
+    fn find(&self, address: u32) -> Option<usize> {
+        let set_start = self.get_set_index(address) * self.ways;
         let tag = self.get_tag(address);
-        let offset = self.get_offset(address);
+        (set_start..set_start + self.ways)
+            .find(|&index| self.lines[index].state != CacheState::Invalid && self.lines[index].tag == tag)
+    }
 
-        // Find or allocate cache line
-        let line_index = self.find_or_allocate_line(set_index, tag);
-        let line = &mut self.lines[line_index];
+    fn invalidate(&mut self, address: u32) {
+        if let Some(index) = self.find(address) {
+            self.lines[index].state = CacheState::Invalid;
+        }
+    }
+
+    fn mark_stale(&mut self, address: u32) {
+        if let Some(index) = self.find(address) {
+            self.lines[index].stale = true;
+        }
+    }
 
-        // Write data
-        line.data[offset..offset+4].copy_from_slice(&data.to_le_bytes());
-        line.state = CacheState::Modified;
-        line.dirty = true;
+    fn invalidate_all(&mut self) {
+        for line in &mut self.lines {
+            line.state = CacheState::Invalid;
+        }
+    }
+
+    fn line_address(&self, address: u32) -> u32 {
+        address & !(self.line_size as u32 - 1)
     }
 
     fn get_set_index(&self, address: u32) -> usize {
@@ -201,56 +329,97 @@ impl Cache {
     }
 
     fn get_offset_bits(&self) -> u32 {
-        (self.line_size as f32).log2() as u32
+        self.line_size.trailing_zeros()
     }
 
     fn get_set_bits(&self) -> u32 {
-        (self.sets as f32).log2() as u32
+        self.sets.trailing_zeros()
     }
 
-    fn find_or_allocate_line(&mut self, set_index: usize, tag: u32) -> usize {
-        // Try to find existing line
+    fn evict_line(&mut self, set_index: usize) -> usize {
+        // Invalid ways first, then least recently used
         let set_start = set_index * self.ways;
-        for way in 0..self.ways {
-            let line_index = set_start + way;
-            if self.lines[line_index].tag == tag {
-                return line_index;
-            }
-        }
+        (set_start..set_start + self.ways)
+            .min_by_key(|&index| {
+                let line = &self.lines[index];
+                (line.state != CacheState::Invalid, line.last_access)
+            })
+            .unwrap_or(set_start)
+    }
+}
 
-        // Need to allocate new line
-        self.evict_line(set_index)
+impl Default for CacheController {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    fn evict_line(&mut self, set_index: usize) -> usize {
-        // Simple LRU implementation
-        let set_start = set_index * self.ways;
-        let mut oldest_access = u64::MAX;
-        let mut oldest_index = set_start;
-
-        for way in 0..self.ways {
-            let line_index = set_start + way;
-            if self.lines[line_index].last_access < oldest_access {
-                oldest_access = self.lines[line_index].last_access;
-                oldest_index = line_index;
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::memory::physical::SystemRam;
 
-        oldest_index
+    const L3: CacheGeometry = CacheGeometry { size: 64 * 1024, ways: 16, latency: 40 };
+
+    fn core(index: usize) -> CacheController {
+        let mut caches = CacheController::with_levels(
+            index,
+            CacheGeometry { size: 4 * 1024, ways: 4, latency: 4 },
+            CacheGeometry { size: 16 * 1024, ways: 8, latency: 12 },
+        );
+        caches.set_frequency(1_000_000_000);
+        caches
     }
 
-    fn insert(&mut self, address: u32, data: u32) {
-        let set_index = self.get_set_index(address);
-        let tag = self.get_tag(address);
-        let offset = self.get_offset(address);
+    fn levels(trace: &AccessTrace) -> Vec<(MemoryLevel, HopOutcome)> {
+        trace.hops.iter().map(|hop| (hop.level, hop.outcome)).collect()
+    }
+
+    #[test]
+    fn cores_share_one_l3_and_pay_for_each_level_they_visit() {
+        let mut l3 = CachedMemory::with_l3(SystemRam::new(1 << 20), L3);
+        let (mut first, mut second) = (core(0), core(1));
+
+        let store = first.write(0x1000, 7, &mut l3).unwrap();
+        assert_eq!(
+            levels(&store),
+            [(MemoryLevel::L1, HopOutcome::Miss), (MemoryLevel::L2, HopOutcome::Miss), (MemoryLevel::L3, HopOutcome::Miss)]
+        );
+        assert_eq!(store.core_cycles, 4 + 12 + 40);
 
-        // Find or allocate cache line
-        let line_index = self.find_or_allocate_line(set_index, tag);
-        let line = &mut self.lines[line_index];
+        // The other core misses privately and finds the line in the L3
+        let load = second.read(0x1000, &mut l3).unwrap();
+        assert_eq!(load.value, 7);
+        assert_eq!(load.served_by(), Some(MemoryLevel::L3));
+        let again = second.read(0x1000, &mut l3).unwrap();
+        assert_eq!((again.served_by(), again.core_cycles), (Some(MemoryLevel::L1), 4));
+    }
+
+    #[test]
+    fn a_store_invalidates_the_other_cores_private_copies() {
+        let mut l3 = CachedMemory::with_l3(SystemRam::new(1 << 20), L3);
+        let (mut first, mut second) = (core(0), core(1));
+        second.read(0x2000, &mut l3).unwrap();
+        first.write(0x2004, 9, &mut l3).unwrap();
+
+        let load = second.read(0x2004, &mut l3).unwrap();
+        assert_eq!(load.value, 9);
+        assert_eq!(load.served_by(), Some(MemoryLevel::L3));
+
+        // The writer's own copies stay valid and up to date
+        let own = first.read(0x2004, &mut l3).unwrap();
+        assert_eq!((own.value, own.served_by()), (9, Some(MemoryLevel::L1)));
+        assert_eq!(l3.memory_mut().read_u32(0x2004).unwrap(), 0); // Still only in the L3
+    }
 
-        // Write data
-        line.data[offset..offset+4].copy_from_slice(&data.to_le_bytes());
-        line.state = CacheState::Modified;
-        line.dirty = true;
+    #[test]
+    fn only_the_private_levels_have_state_here() {
+        let caches = core(0);
+        assert!(caches.get_cache_state(1).is_some() && caches.get_cache_state(2).is_some());
+        assert!(caches.get_cache_state(3).is_none());
+        assert!(caches.get_cache_state(0).is_none());
+        assert!(caches.get_line_state(1, 0, 0) == Some(CacheState::Invalid));
+        assert!(caches.get_line_state(1, 0, 4).is_none());
+        assert!(caches.get_line_state(1, 16, 0).is_none());
     }
 }
@@ -2,7 +2,6 @@ use super::{alu::ALU, registers::RegisterFile};
 
 pub struct ExecutionUnit {
     alu: ALU,
-    registers: *mut RegisterFile,
     current_operation: Option<Operation>,
     busy: bool,
     cycles_remaining: u32,
@@ -19,23 +18,23 @@ pub struct Operation {
 }
 
 impl ExecutionUnit {
-    pub fn new(registers: *mut RegisterFile) -> Self {
+    pub fn new() -> Self {
         Self {
             alu: ALU::new(),
-            registers,
             current_operation: None,
             busy: false,
             cycles_remaining: 0,
         }
     }
 
-    pub fn execute(&mut self, operation: Operation) -> bool {
+    // The core lends its register file for each call rather than the unit
+    // holding on to it
+    pub fn execute(&mut self, operation: Operation, registers: &mut RegisterFile) -> bool {
         if self.busy {
             return false;
         }
 
         self.busy = true;
-        self.current_operation = Some(operation.clone());
         
         // Set cycles needed based on operation type
         self.cycles_remaining = match operation.opcode {
@@ -46,21 +45,19 @@ impl ExecutionUnit {
         };
 
         // Get operands from registers
-        let rs1_val = unsafe { (*self.registers).read_gpr(operation.rs1) };
-        let rs2_val = unsafe { (*self.registers).read_gpr(operation.rs2) };
+        let rs1_val = registers.read_gpr(operation.rs1);
+        let rs2_val = registers.read_gpr(operation.rs2);
 
         // Execute operation
         let result = self.alu.execute(operation.opcode, rs1_val, rs2_val);
 
         // Store result
-        if let Some(mut op) = self.current_operation {
-            op.result = Some(result);
-        }
+        self.current_operation = Some(Operation { result: Some(result), ..operation });
 
         true
     }
 
-    pub fn tick(&mut self) {
+    pub fn tick(&mut self, registers: &mut RegisterFile) {
         if !self.busy {
             return;
         }
@@ -73,9 +70,7 @@ impl ExecutionUnit {
             // Operation complete, write back result
             if let Some(op) = &self.current_operation {
                 if let Some(result) = op.result {
-                    unsafe {
-                        (*self.registers).write_gpr(op.rd, result);
-                    }
+                    registers.write_gpr(op.rd, result);
                 }
             }
             self.busy = false;
@@ -100,3 +95,9 @@ impl ExecutionUnit {
         &self.alu
     }
 }
+
+impl Default for ExecutionUnit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
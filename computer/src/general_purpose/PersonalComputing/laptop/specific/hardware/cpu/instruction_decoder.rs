@@ -74,3 +74,9 @@ impl InstructionDecoder {
         }
     }
 }
+
+impl Default for InstructionDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
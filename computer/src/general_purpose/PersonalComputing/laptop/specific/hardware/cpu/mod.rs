@@ -1,4 +1,12 @@
 use super::bus::Bus;
+use super::interrupt::error::InterruptResult;
+use super::interrupt::{InterruptController, InterruptID};
+use super::dma::coherence::CachedMemory;
+use super::memory::access::AccessTrace;
+use super::memory::error::{MemoryError, MemoryResult};
+use super::memory::mmu::virtual_memory::VirtualMemoryManager;
+use super::memory::Memory;
+use crate::simulation::rng::SimRng;
 
 pub mod alu;
 pub mod branch_predictor;
@@ -9,55 +17,82 @@ pub mod pipeline;
 pub mod registers;
 
 pub struct CPU {
-    registers: registers::RegisterFile,
     pipeline: pipeline::Pipeline,
     branch_predictor: branch_predictor::BranchPredictor,
     cache_controller: cache_controller::CacheController,
+    mmu: VirtualMemoryManager,
     execution_unit: execution_unit::ExecutionUnit,
-    instruction_decoder: instruction_decoder::InstructionDecoder,
     
     // CPU state
     frequency: u32,      // Current clock frequency
     temperature: f32,    // Current temperature
     power_state: u8,     // Current power state (C0, C1, etc)
-    bus: *mut Bus,       // System bus connection
 }
 
 impl CPU {
-    pub fn new(bus: *mut Bus) -> Self {
-        let registers = registers::RegisterFile::new();
-        
+    pub fn new() -> Self {
+        Self::with_caches(cache_controller::CacheController::new())
+    }
+
+    pub fn with_caches(cache_controller: cache_controller::CacheController) -> Self {
         Self {
-            registers,
-            pipeline: pipeline::Pipeline::new(registers.clone()),
+            pipeline: pipeline::Pipeline::new(registers::RegisterFile::new()),
             branch_predictor: branch_predictor::BranchPredictor::new(1024),
-            cache_controller: cache_controller::CacheController::new(bus),
-            execution_unit: execution_unit::ExecutionUnit::new(&registers as *mut _),
-            instruction_decoder: instruction_decoder::InstructionDecoder::new(),
+            cache_controller,
+            mmu: VirtualMemoryManager::new(SimRng::new(0)),
+            execution_unit: execution_unit::ExecutionUnit::new(),
             frequency: 3_000_000_000,
             temperature: 40.0,
             power_state: 0,
-            bus,
         }
     }
 
-    pub fn tick(&mut self) {
+    // The shared L3 and the bus are lent for the cycle. The core's loads
+    // and stores are translated by its MMU, then reach device registers over
+    // the bus or memory through its caches and the L3.
+    pub fn tick(&mut self, memory: &mut CachedMemory<Memory>, bus: &mut Bus) {
         // Update pipeline
-        self.pipeline.tick();
+        self.pipeline.tick(&mut self.cache_controller, memory, &mut self.mmu, bus);
         
-        // Execute current instruction
-        if let Some(instruction) = self.pipeline.get_current_instruction() {
-            let decoded = self.instruction_decoder.decode(instruction);
-            self.execution_unit.execute(decoded);
+        // Execute current instruction; the pipeline hands it over already decoded
+        if let Some(instruction) = self.pipeline.get_current_instruction().cloned() {
+            let operation = execution_unit::Operation {
+                opcode: instruction.opcode,
+                rd: instruction.rd,
+                rs1: instruction.rs1,
+                rs2: instruction.rs2,
+                immediate: instruction.immediate,
+                result: None,
+            };
+            self.execution_unit.execute(operation, self.pipeline.registers_mut());
         }
         
         // Update execution unit
-        self.execution_unit.tick();
+        self.execution_unit.tick(self.pipeline.registers_mut());
         
         // Update temperature based on activity
         self.update_temperature();
     }
 
+    // Samples the controller's pending line for this core between
+    // instructions and traps to the claimed source's vector
+    pub fn check_interrupts(&mut self, interrupts: &mut InterruptController, core: usize) -> Option<InterruptID> {
+        if !self.pipeline.at_instruction_boundary() || !self.pipeline.registers().interrupts_enabled() {
+            return None;
+        }
+        let id = interrupts.claim(core)?;
+        self.pipeline.registers_mut().trap(id.0);
+        Some(id)
+    }
+
+    // The handler's return: unwinds one trap and completes its source
+    pub fn return_from_interrupt(&mut self, interrupts: &mut InterruptController, core: usize) -> InterruptResult<()> {
+        match self.pipeline.registers_mut().return_from_trap() {
+            Some(vector) => interrupts.complete(core, InterruptID(vector)),
+            None => Ok(()),
+        }
+    }
+
     fn update_temperature(&mut self) {
         // Simple temperature model based on CPU activity
         let activity_factor = if self.execution_unit.is_busy() { 1.0 } else { 0.1 };
@@ -68,6 +103,43 @@ impl CPU {
             (ambient_temp + (max_temp - ambient_temp) * activity_factor) * 0.01;
     }
 
+    // The clock generator owns the frequency; the core just follows it
+    pub fn set_frequency(&mut self, frequency: u32) {
+        self.frequency = frequency;
+        self.cache_controller.set_frequency(frequency as u64);
+        self.pipeline.set_frequency(frequency as u64);
+    }
+
+    pub fn set_bus_frequency(&mut self, frequency: u64) {
+        self.pipeline.set_bus_frequency(frequency);
+    }
+
+    // The TLB's replacement stream
+    pub fn reseed(&mut self, rng: &SimRng) {
+        self.mmu.reseed(&rng.fork("mmu"));
+    }
+
+    pub fn mmu_mut(&mut self) -> &mut VirtualMemoryManager {
+        &mut self.mmu
+    }
+
+    pub fn issue(&mut self, instruction: pipeline::Instruction) {
+        self.pipeline.issue(instruction);
+    }
+
+    // A single load outside the pipeline, for following one access through
+    // every level of the machine. It takes the memory stage's path, so a
+    // busy bus fails it rather than retrying.
+    pub fn load(&mut self, address: u32, memory: &mut CachedMemory<Memory>, bus: &mut Bus) -> MemoryResult<AccessTrace> {
+        self.pipeline
+            .load(address, &mut self.cache_controller, memory, &mut self.mmu, bus)?
+            .ok_or(MemoryError::BusError)
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.execution_unit.is_busy()
+    }
+
     // Methods for visualization system
     pub fn get_frequency(&self) -> u32 {
         self.frequency
@@ -93,8 +165,88 @@ impl CPU {
         &self.cache_controller
     }
 
+    pub fn get_registers(&self) -> &registers::RegisterFile {
+        self.pipeline.registers()
+    }
+
     pub fn get_execution_unit(&self) -> &execution_unit::ExecutionUnit {
         &self.execution_unit
     }
 }
 
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::registers::SR_INTERRUPT_ENABLE;
+
+    const UART: InterruptID = InterruptID(10);
+    const DISK: InterruptID = InterruptID(12);
+
+    // A core running at 0x1000 with interrupts on, and two level sources
+    fn machine() -> (CPU, InterruptController) {
+        let mut plic = InterruptController::default();
+        for (id, priority) in [(UART, 1), (DISK, 3)] {
+            plic.set_priority(id, priority).unwrap();
+            plic.enable(0, id).unwrap();
+        }
+        let mut cpu = CPU::new();
+        let registers = cpu.pipeline.registers_mut();
+        registers.set_vector_base(0x8000_0000);
+        registers.set_pc(0x1000);
+        registers.set_sr(SR_INTERRUPT_ENABLE);
+        (cpu, plic)
+    }
+
+    #[test]
+    fn a_claimed_source_traps_to_its_vector_with_interrupts_masked() {
+        let (mut cpu, mut plic) = machine();
+        plic.set_level(UART, true).unwrap();
+
+        // Masked, the core leaves it pending
+        cpu.pipeline.registers_mut().set_sr(0);
+        assert_eq!(cpu.check_interrupts(&mut plic, 0), None);
+        assert!(plic.is_pending(UART));
+
+        cpu.pipeline.registers_mut().set_sr(SR_INTERRUPT_ENABLE);
+        assert_eq!(cpu.check_interrupts(&mut plic, 0), Some(UART));
+        let registers = cpu.get_registers();
+        assert_eq!(registers.get_pc(), 0x8000_0000 + 10 * 4);
+        assert_eq!((registers.get_epc(), registers.get_cause()), (0x1000, 10));
+        assert!(!registers.interrupts_enabled());
+
+        // Even a higher priority waits until the handler unmasks
+        plic.set_level(DISK, true).unwrap();
+        assert_eq!(cpu.check_interrupts(&mut plic, 0), None);
+        cpu.pipeline.registers_mut().set_sr(SR_INTERRUPT_ENABLE);
+        assert_eq!(cpu.check_interrupts(&mut plic, 0), Some(DISK));
+        assert_eq!(cpu.get_registers().trap_depth(), 2);
+        assert_eq!(plic.in_service(0), [UART, DISK]);
+    }
+
+    #[test]
+    fn returning_restores_the_interrupted_state_and_samples_again() {
+        let (mut cpu, mut plic) = machine();
+        plic.set_level(UART, true).unwrap();
+        assert_eq!(cpu.check_interrupts(&mut plic, 0), Some(UART));
+        cpu.pipeline.registers_mut().set_pc(0x8000_0100);
+
+        cpu.return_from_interrupt(&mut plic, 0).unwrap();
+        let registers = cpu.get_registers();
+        assert_eq!((registers.get_pc(), registers.trap_depth()), (0x1000, 0));
+        assert!(registers.interrupts_enabled());
+        assert!(plic.in_service(0).is_empty());
+
+        // The device still holds its line, so the next boundary traps again
+        assert_eq!(cpu.check_interrupts(&mut plic, 0), Some(UART));
+        plic.set_level(UART, false).unwrap();
+        cpu.return_from_interrupt(&mut plic, 0).unwrap();
+        assert_eq!(cpu.check_interrupts(&mut plic, 0), None);
+        assert_eq!(cpu.get_registers().get_pc(), 0x1000);
+    }
+}
@@ -1,4 +1,18 @@
 use super::{registers::RegisterFile, alu::ALU};
+use super::cache_controller::CacheController;
+use crate::hardware::bus::mmio::AccessSize;
+use crate::hardware::bus::{Bus, BusError};
+use crate::hardware::clock::ClockDomain;
+use crate::hardware::dma::coherence::CachedMemory;
+use crate::hardware::memory::access::{AccessKind, AccessTrace, Hop, HopOutcome, MemoryLevel};
+use crate::hardware::memory::error::{MemoryError, MemoryResult};
+use crate::hardware::memory::mmu::virtual_memory::VirtualMemoryManager;
+use crate::hardware::memory::types::VirtualAddress;
+use crate::hardware::memory::Memory;
+use std::collections::VecDeque;
+
+// A TLB miss walks the page table before the access can start
+const PAGE_WALK_CYCLES: u64 = 30;
 
 pub enum PipelineStage {
     Fetch,
@@ -14,13 +28,27 @@ pub struct Pipeline {
     stall_count: usize,
     
     // Pipeline components
-    registers: RegisterFile,
+    registers: RegisterFile, // The core's only register file
     alu: ALU,
     
     // Pipeline state
     current_instruction: Option<Instruction>,
     branch_taken: bool,
     data_hazard: bool,
+    fetch_queue: VecDeque<Instruction>,
+    
+    // Memory stage state
+    memory_wait: u64,            // Core cycles until the access in flight returns
+    memory_result: Option<u32>,  // Value loaded by the instruction in the memory stage
+    load_value: Option<u32>,     // ...and once it reaches writeback
+    last_access: Option<AccessTrace>,
+    memory_faults: u64,
+    retry_access: bool,          // Lost arbitration for the bus; reissue next cycle
+    bus_stalls: u64,
+
+    // Clocks, to express bus hops in core cycles
+    frequency: u64,
+    bus_frequency: u64,
 }
 
 #[derive(Clone)]
@@ -36,7 +64,7 @@ pub struct Instruction {
 impl Pipeline {
     pub fn new(registers: RegisterFile) -> Self {
         Self {
-            stages: [None; 5],
+            stages: Default::default(),
             stalled: [false; 5],
             stall_count: 0,
             registers,
@@ -44,13 +72,26 @@ impl Pipeline {
             current_instruction: None,
             branch_taken: false,
             data_hazard: false,
+            fetch_queue: VecDeque::new(),
+            memory_wait: 0,
+            memory_result: None,
+            load_value: None,
+            last_access: None,
+            memory_faults: 0,
+            retry_access: false,
+            bus_stalls: 0,
+            frequency: 3_000_000_000,
+            bus_frequency: 1_000_000_000,
         }
     }
 
-    pub fn tick(&mut self) {
+    // Loads and stores are translated by the core's MMU, then go to device
+    // registers over the bus or through the caches to memory. Everything
+    // is lent for the cycle.
+    pub fn tick(&mut self, cache: &mut CacheController, memory: &mut CachedMemory<Memory>, mmu: &mut VirtualMemoryManager, bus: &mut Bus) {
         // Move instructions through pipeline stages
         self.writeback_stage();
-        self.memory_stage();
+        self.memory_stage(cache, memory, mmu, bus);
         self.execute_stage();
         self.decode_stage();
         self.fetch_stage();
@@ -58,6 +99,34 @@ impl Pipeline {
         // Handle hazards and stalls
         self.check_hazards();
         self.update_stalls();
+        self.advance();
+    }
+
+    // Queues an instruction for fetch, standing in for instruction memory
+    pub fn issue(&mut self, instruction: Instruction) {
+        self.fetch_queue.push_back(instruction);
+    }
+
+    // Between instructions: no load or store is half done, so a trap here
+    // leaves nothing to unwind
+    pub fn at_instruction_boundary(&self) -> bool {
+        self.memory_wait == 0
+    }
+
+    pub fn set_frequency(&mut self, frequency: u64) {
+        self.frequency = frequency;
+    }
+
+    pub fn set_bus_frequency(&mut self, frequency: u64) {
+        self.bus_frequency = frequency;
+    }
+
+    pub fn registers(&self) -> &RegisterFile {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut RegisterFile {
+        &mut self.registers
     }
 
     fn fetch_stage(&mut self) {
@@ -77,13 +146,9 @@ impl Pipeline {
             return;
         }
 
-        if let Some(instruction) = &self.stages[0] {
-            // Decode instruction
-            let rs1_value = self.registers.read_gpr(instruction.rs1);
-            let rs2_value = self.registers.read_gpr(instruction.rs2);
-            
+        if let Some(instruction) = self.stages[0].clone() {
             // Check for data hazards
-            self.check_data_dependencies(instruction);
+            self.check_data_dependencies(&instruction);
         }
     }
 
@@ -92,7 +157,7 @@ impl Pipeline {
             return;
         }
 
-        if let Some(instruction) = &self.stages[1] {
+        if let Some(instruction) = self.stages[1].clone() {
             // Execute instruction using ALU
             let result = self.alu.execute(
                 instruction.opcode,
@@ -102,22 +167,45 @@ impl Pipeline {
             
             // Handle branches
             if self.is_branch(instruction.opcode) {
-                self.handle_branch(instruction, result);
+                self.handle_branch(&instruction, result);
             }
         }
     }
 
-    fn memory_stage(&mut self) {
-        if self.stalled[3] {
+    fn memory_stage(&mut self, cache: &mut CacheController, memory: &mut CachedMemory<Memory>, mmu: &mut VirtualMemoryManager, bus: &mut Bus) {
+        // An access in flight holds the stage until its last hop returns
+        if self.memory_wait > 0 {
+            self.memory_wait -= 1;
             return;
         }
+        self.retry_access = false;
 
-        if let Some(instruction) = &self.stages[2] {
-            // Handle memory operations
-            match instruction.opcode {
-                0x20 => self.memory_read(instruction),  // Load
-                0x21 => self.memory_write(instruction), // Store
-                _ => {}
+        let Some(instruction) = self.stages[2].clone() else {
+            return;
+        };
+        let access = if self.is_load(instruction.opcode) {
+            self.memory_read(&instruction, cache, memory, mmu, bus)
+        } else if self.is_store(instruction.opcode) {
+            self.memory_write(&instruction, cache, memory, mmu, bus)
+        } else {
+            return;
+        };
+
+        match access {
+            Ok(None) => {
+                // The request stays queued at the arbiter
+                self.retry_access = true;
+                self.bus_stalls += 1;
+            }
+            Ok(Some(trace)) => {
+                self.memory_wait = trace.core_cycles.saturating_sub(1);
+                self.memory_result = Some(trace.value);
+                self.last_access = Some(trace);
+            }
+            Err(_) => {
+                // No exception vectors for faults yet; the access is squashed
+                self.memory_faults += 1;
+                self.stages[2] = None;
             }
         }
     }
@@ -151,6 +239,25 @@ impl Pipeline {
             self.stall_count = 0;
             self.stalled = [false; 5];
         }
+        self.stalled[3] = self.memory_wait > 0 || self.retry_access;
+    }
+
+    // Moves each instruction on to the next stage. A memory access in flight
+    // or waiting for the bus holds everything behind it; a decode stall lets
+    // the older instructions drain and sends a bubble into execute.
+    fn advance(&mut self) {
+        if self.memory_wait > 0 || self.retry_access {
+            self.stages[3] = None;
+            return;
+        }
+        self.stages[3] = self.stages[2].take();
+        self.load_value = self.memory_result.take();
+        self.stages[2] = self.stages[1].take();
+        if self.stalled[1] {
+            return;
+        }
+        self.stages[1] = self.stages[0].take();
+        self.stages[0] = self.fetch_queue.pop_front();
     }
 
     // Methods for visualization system
@@ -170,6 +277,20 @@ impl Pipeline {
         self.current_instruction.as_ref()
     }
 
+    // The last load or store, hop by hop
+    pub fn get_last_access(&self) -> Option<&AccessTrace> {
+        self.last_access.as_ref()
+    }
+
+    pub fn get_memory_faults(&self) -> u64 {
+        self.memory_faults
+    }
+
+    // Cycles a load or store spent waiting to be granted the bus
+    pub fn get_bus_stalls(&self) -> u64 {
+        self.bus_stalls
+    }
+
     fn writes_register(&self, opcode: u8) -> bool {
         // Check if instruction writes to a register
         match opcode {
@@ -185,19 +306,24 @@ impl Pipeline {
 
     fn check_data_dependencies(&mut self, instruction: &Instruction) {
         // Check for RAW hazards
-        for stage in &self.stages[1..] {
-            if let Some(prev_instr) = stage {
-                if self.writes_register(prev_instr.opcode) {
-                    if prev_instr.rd == instruction.rs1 || 
-                       prev_instr.rd == instruction.rs2 {
-                        self.data_hazard = true;
-                        return;
-                    }
-                }
+        for prev_instr in self.stages[1..].iter().flatten() {
+            if self.writes_register(prev_instr.opcode)
+                && (prev_instr.rd == instruction.rs1 || prev_instr.rd == instruction.rs2)
+            {
+                self.data_hazard = true;
+                return;
             }
         }
     }
 
+    fn is_load(&self, opcode: u8) -> bool {
+        matches!(opcode, 0x40..=0x4F)
+    }
+
+    fn is_store(&self, opcode: u8) -> bool {
+        matches!(opcode, 0x50..=0x5F)
+    }
+
     fn is_branch(&self, opcode: u8) -> bool {
         matches!(opcode, 0x30..=0x3F)  // Branch instruction opcodes
     }
@@ -214,27 +340,94 @@ impl Pipeline {
         }
     }
 
-    fn memory_read(&mut self, instruction: &Instruction) {
-        // Memory read operation would be handled by cache/memory system
-        // For now, just simulate a read
-        let address = instruction.address + instruction.immediate;
-        // In real implementation, would call memory system here
-        // let data = memory_system.read(address);
+    // No trace means the bus was busy and the access has to be reissued
+    fn memory_read(&mut self, instruction: &Instruction, cache: &mut CacheController, memory: &mut CachedMemory<Memory>, mmu: &mut VirtualMemoryManager, bus: &mut Bus) -> MemoryResult<Option<AccessTrace>> {
+        let address = instruction.address.wrapping_add(instruction.immediate);
+        self.load(address, cache, memory, mmu, bus)
+    }
+
+    // A load from a virtual address, the way the memory stage issues it
+    pub fn load(&mut self, address: u32, cache: &mut CacheController, memory: &mut CachedMemory<Memory>, mmu: &mut VirtualMemoryManager, bus: &mut Bus) -> MemoryResult<Option<AccessTrace>> {
+        let (physical, translation) = self.translate(address, mmu)?;
+        let trace = if bus.mmio().is_mapped(physical) {
+            let value = match bus.read_sized(physical, AccessSize::Word, memory) {
+                Err(BusError::BusUnavailable) => return Ok(None),
+                Err(_) => return Err(MemoryError::BusError),
+                Ok(value) => value as u32,
+            };
+            let mut trace = self.uncached(AccessKind::Load, physical);
+            trace.value = value;
+            trace
+        } else {
+            cache.read(Self::cacheable(physical)?, memory)?
+        };
+        Ok(Some(self.with_translation(trace, translation)))
     }
 
-    fn memory_write(&mut self, instruction: &Instruction) {
-        // Memory write operation would be handled by cache/memory system
-        let address = instruction.address + instruction.immediate;
+    fn memory_write(&mut self, instruction: &Instruction, cache: &mut CacheController, memory: &mut CachedMemory<Memory>, mmu: &mut VirtualMemoryManager, bus: &mut Bus) -> MemoryResult<Option<AccessTrace>> {
+        let address = instruction.address.wrapping_add(instruction.immediate);
         let data = self.registers.read_gpr(instruction.rs2);
-        // In real implementation, would call memory system here
-        // memory_system.write(address, data);
+        let (physical, translation) = self.translate(address, mmu)?;
+        let trace = if bus.mmio().is_mapped(physical) {
+            match bus.write_sized(physical, AccessSize::Word, data as u64, memory) {
+                Err(BusError::BusUnavailable) => return Ok(None),
+                Err(_) => return Err(MemoryError::BusError),
+                Ok(()) => {}
+            }
+            let mut trace = self.uncached(AccessKind::Store, physical);
+            trace.value = data;
+            trace
+        } else {
+            cache.write(Self::cacheable(physical)?, data, memory)?
+        };
+        Ok(Some(self.with_translation(trace, translation)))
+    }
+
+    // With translation off, as for firmware, there's no TLB hop
+    fn translate(&self, address: u32, mmu: &mut VirtualMemoryManager) -> MemoryResult<(u64, Option<Hop>)> {
+        let (physical, outcome) = mmu.translate_traced(VirtualAddress(address as u64))?;
+        let hop = outcome.map(|outcome| Hop {
+            level: MemoryLevel::TLB,
+            outcome,
+            cycles: if outcome == HopOutcome::Hit { 1 } else { PAGE_WALK_CYCLES },
+            domain: ClockDomain::CPU,
+        });
+        Ok((physical.0, hop))
+    }
+
+    // The caches only cover the 32-bit physical space
+    fn cacheable(physical: u64) -> MemoryResult<u32> {
+        u32::try_from(physical).map_err(|_| MemoryError::AddressOutOfRange)
+    }
+
+    // Device registers bypass the caches: one beat on the bus, then the
+    // device answers in the same clock
+    fn uncached(&self, kind: AccessKind, physical: u64) -> AccessTrace {
+        let mut trace = AccessTrace::new(kind, physical);
+        trace.hops.push(Hop { level: MemoryLevel::Bus, outcome: HopOutcome::Scheduled, cycles: 1, domain: ClockDomain::Bus });
+        trace.hops.push(Hop { level: MemoryLevel::Device, outcome: HopOutcome::Hit, cycles: 1, domain: ClockDomain::Bus });
+        let (core, bus) = (self.frequency, self.bus_frequency);
+        trace.core_cycles = trace
+            .time_with(|domain| if domain == ClockDomain::CPU { core } else { bus })
+            .to_cycles(core)
+            .max(1);
+        trace
+    }
+
+    // The lookup is in the core's clock, ahead of everything else
+    fn with_translation(&self, mut trace: AccessTrace, translation: Option<Hop>) -> AccessTrace {
+        if let Some(hop) = translation {
+            trace.core_cycles += hop.cycles;
+            trace.hops.insert(0, hop);
+        }
+        trace
     }
 
     fn get_result(&self, instruction: &Instruction) -> u32 {
         // Get result from appropriate pipeline stage
         match instruction.opcode {
             0x00..=0x0F => self.alu.get_accumulator(), // ALU result
-            0x40..=0x4F => 0, // Load result (would come from memory)
+            0x40..=0x4F => self.load_value.unwrap_or(0), // Loaded by the memory stage
             _ => 0,
         }
     }
@@ -1,3 +1,6 @@
+// Status register bits
+pub const SR_INTERRUPT_ENABLE: u32 = 1 << 0;
+
 pub struct RegisterFile {
     // General purpose registers
     gprs: [u32; 32],
@@ -8,6 +11,12 @@ pub struct RegisterFile {
     lr: u32,    // Link Register
     sr: u32,    // Status Register
     
+    // Trap registers
+    vector_base: u32,          // Handler for vector n is at vector_base + 4 * n
+    epc: u32,                  // Where the innermost trap interrupted
+    cause: u32,                // Vector of the innermost trap
+    trap_frames: Vec<TrapFrame>, // State the hardware stacked for each nested trap, innermost last
+    
     // Register activity tracking for visualization
     last_written: Option<usize>,
     last_read: Option<usize>,
@@ -21,6 +30,10 @@ impl RegisterFile {
             sp: 0xFFFF_FFF0, // Initialize stack pointer to near top of memory
             lr: 0,
             sr: 0,
+            vector_base: 0,
+            epc: 0,
+            cause: 0,
+            trap_frames: Vec::new(),
             last_written: None,
             last_read: None,
         }
@@ -48,6 +61,43 @@ impl RegisterFile {
     pub fn get_sr(&self) -> u32 { self.sr }
     pub fn set_sr(&mut self, value: u32) { self.sr = value; }
 
+    pub fn get_vector_base(&self) -> u32 { self.vector_base }
+    pub fn set_vector_base(&mut self, value: u32) { self.vector_base = value; }
+
+    pub fn get_epc(&self) -> u32 { self.epc }
+    pub fn get_cause(&self) -> u32 { self.cause }
+
+    pub fn interrupts_enabled(&self) -> bool {
+        self.sr & SR_INTERRUPT_ENABLE != 0
+    }
+
+    // Enters the handler for a vector with interrupts masked. The
+    // interrupted PC and status are stacked in hardware, so a handler that
+    // unmasks them can have a higher-priority trap nest on top, and each
+    // return unwinds exactly one level.
+    pub fn trap(&mut self, vector: u32) {
+        self.trap_frames.push(TrapFrame { pc: self.pc, sr: self.sr, cause: self.cause });
+        self.epc = self.pc;
+        self.cause = vector;
+        self.sr &= !SR_INTERRUPT_ENABLE;
+        self.pc = self.vector_base.wrapping_add(vector.wrapping_mul(4));
+    }
+
+    // Returns from the innermost trap, giving back the vector it served
+    pub fn return_from_trap(&mut self) -> Option<u32> {
+        let frame = self.trap_frames.pop()?;
+        let vector = self.cause;
+        self.pc = frame.pc;
+        self.sr = frame.sr;
+        self.cause = frame.cause;
+        self.epc = self.trap_frames.last().map_or(0, |outer| outer.pc);
+        Some(vector)
+    }
+
+    pub fn trap_depth(&self) -> usize {
+        self.trap_frames.len()
+    }
+
     // Methods for visualization system
     pub fn get_register_values(&self) -> &[u32] {
         &self.gprs
@@ -62,3 +112,15 @@ impl RegisterFile {
         self.last_written = None;
     }
 }
+
+impl Default for RegisterFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct TrapFrame {
+    pc: u32,
+    sr: u32,
+    cause: u32, // Of the trap this one interrupted
+}
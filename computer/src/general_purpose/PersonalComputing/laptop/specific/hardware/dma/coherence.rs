@@ -0,0 +1,407 @@
+use crate::hardware::clock::ClockDomain;
+use crate::hardware::cpu::cache_controller::CacheGeometry;
+use crate::hardware::memory::access::{Hop, HopOutcome, MemoryLevel};
+use crate::hardware::memory::error::MemoryResult;
+use crate::hardware::memory::physical::PhysicalMemory;
+use std::collections::{HashMap, VecDeque};
+
+pub const CACHE_LINE: u64 = 64;
+
+const EVENT_LOG_LIMIT: usize = 256;
+
+// Snoops a core can fall behind by before it just drops everything private
+const SNOOP_QUEUE_LIMIT: usize = 4096;
+
+// RAM behind the last level cache, as the cores and DMA both see it. The
+// L3 is shared by every core and is write-back, write-allocate; the cores'
+// private L1 and L2 write through to it, so every store lands here. DMA goes
+// to RAM directly. A coherent interconnect snoops the L3 on every DMA access
+// and invalidates the cores' private copies of what a device wrote. Without
+// it, the driver must clean lines before a device reads them and invalidate
+// them before the CPU reads what a device wrote, and every time it forgets
+// is recorded.
+pub struct CachedMemory<M: PhysicalMemory> {
+    memory: M,
+    lines: HashMap<u64, CachedLine>, // By line address
+    capacity: usize,                 // In lines
+    latency: u64,                    // Core cycles for a lookup and the data's return
+    clock: u64,
+    snoops: Vec<Vec<Snoop>>, // Per core, waiting for its next access
+    stats: CoherenceStats,
+    events: VecDeque<StaleAccess>,
+}
+
+struct CachedLine {
+    data: [u8; CACHE_LINE as usize],
+    dirty: bool,
+    stale: bool,    // A device wrote RAM under this line since it was filled
+    reported: bool, // The CPU has already been caught reading it
+    last_use: u64,
+}
+
+// What a core's private caches must do about a line before they next look
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Snoop {
+    Invalidate(u64), // Another core, a coherent device or the driver changed it
+    Stale(u64),      // A device wrote RAM under it without snooping
+    InvalidateAll,   // The core fell too far behind to say which lines
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaleKind {
+    CpuReadStale,      // The CPU read cached data a device had since overwritten in RAM
+    DeviceReadStale,   // A device read RAM while newer data sat dirty in the cache
+    DeviceWriteLost,   // A dirty line was written back over data a device had written
+    DirtyDiscarded,    // An invalidate dropped CPU writes that never reached RAM
+}
+
+// One coherence bug, at the line it happened on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StaleAccess {
+    pub kind: StaleKind,
+    pub line: u64,
+    pub cycle: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CoherenceStats {
+    pub cpu_reads: u64,
+    pub cpu_writes: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub writebacks: u64,
+    pub cleaned: u64,     // Lines written back by explicit maintenance
+    pub invalidated: u64, // Lines dropped by explicit maintenance
+    pub snoops: u64,      // DMA accesses a coherent interconnect served from or into the cache
+    pub stale_cpu_reads: u64,
+    pub stale_device_reads: u64,
+    pub lost_device_writes: u64,
+    pub discarded_dirty: u64,
+}
+
+impl<M: PhysicalMemory> CachedMemory<M> {
+    pub fn new(memory: M, capacity_lines: usize) -> Self {
+        Self {
+            memory,
+            lines: HashMap::new(),
+            capacity: capacity_lines.max(1),
+            latency: 0,
+            clock: 0,
+            snoops: Vec::new(),
+            stats: CoherenceStats::default(),
+            events: VecDeque::new(),
+        }
+    }
+
+    // The machine's L3, sized and timed as described
+    pub fn with_l3(memory: M, l3: CacheGeometry) -> Self {
+        let mut cached = Self::new(memory, l3.size / CACHE_LINE as usize);
+        cached.latency = l3.latency;
+        cached
+    }
+
+    // CPU loads and stores, through the cache
+    pub fn cpu_read(&mut self, address: u64, buffer: &mut [u8]) -> MemoryResult<()> {
+        self.stats.cpu_reads += 1;
+        let mut done = 0;
+        while done < buffer.len() {
+            let at = address + done as u64;
+            let line = at & !(CACHE_LINE - 1);
+            let offset = (at - line) as usize;
+            let count = (CACHE_LINE as usize - offset).min(buffer.len() - done);
+            self.fill(line)?;
+            self.check_stale(line);
+            let cached = &self.lines[&line];
+            buffer[done..done + count].copy_from_slice(&cached.data[offset..offset + count]);
+            done += count;
+        }
+        Ok(())
+    }
+
+    pub fn cpu_write(&mut self, address: u64, data: &[u8]) -> MemoryResult<()> {
+        self.cpu_write_from(address, data, None)
+    }
+
+    fn cpu_write_from(&mut self, address: u64, data: &[u8], core: Option<usize>) -> MemoryResult<()> {
+        self.stats.cpu_writes += 1;
+        let mut done = 0;
+        while done < data.len() {
+            let at = address + done as u64;
+            let line = at & !(CACHE_LINE - 1);
+            let offset = (at - line) as usize;
+            let count = (CACHE_LINE as usize - offset).min(data.len() - done);
+            self.fill(line)?;
+            let cached = self.lines.get_mut(&line).expect("line was just filled");
+            cached.data[offset..offset + count].copy_from_slice(&data[done..done + count]);
+            cached.dirty = true;
+            self.broadcast(Snoop::Invalidate(line), core);
+            done += count;
+        }
+        Ok(())
+    }
+
+    pub fn cpu_read_u32(&mut self, address: u64) -> MemoryResult<u32> {
+        let mut bytes = [0u8; 4];
+        self.cpu_read(address, &mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn cpu_write_u32(&mut self, address: u64, value: u32) -> MemoryResult<()> {
+        self.cpu_write(address, &value.to_le_bytes())
+    }
+
+    pub fn cpu_write_u64(&mut self, address: u64, value: u64) -> MemoryResult<()> {
+        self.cpu_write(address, &value.to_le_bytes())
+    }
+
+    // A core's L2 miss: the line from the L3, filled from memory if it isn't
+    // there. Records the L3's hop and, on a miss, memory's.
+    pub fn core_read(&mut self, line: u64, hops: &mut Vec<Hop>) -> MemoryResult<[u8; CACHE_LINE as usize]> {
+        self.stats.cpu_reads += 1;
+        let below = self.fill(line)?;
+        hops.push(Hop {
+            level: MemoryLevel::L3,
+            outcome: if below.is_some() { HopOutcome::Miss } else { HopOutcome::Hit },
+            cycles: self.latency,
+            domain: ClockDomain::CPU,
+        });
+        hops.extend(below.into_iter().flatten());
+        self.check_stale(line);
+        Ok(self.lines[&line].data)
+    }
+
+    // A core's store, written through from its private levels. Every other
+    // core's copy of the line is now out of date.
+    pub fn core_write(&mut self, core: usize, address: u64, data: &[u8]) -> MemoryResult<()> {
+        self.cpu_write_from(address, data, Some(core))
+    }
+
+    // What a core's private caches have to catch up on, oldest first. A core
+    // starts being snooped from its first access.
+    pub fn take_snoops(&mut self, core: usize) -> Vec<Snoop> {
+        if self.snoops.len() <= core {
+            self.snoops.resize(core + 1, Vec::new());
+        }
+        std::mem::take(&mut self.snoops[core])
+    }
+
+    // A core hit on a private copy of a line a device had overwritten
+    pub fn stale_read(&mut self, line: u64) {
+        self.stats.stale_cpu_reads += 1;
+        self.record(StaleKind::CpuReadStale, line);
+    }
+
+    // Cache maintenance, by physical range, as a driver issues it
+    // Writes dirty lines back and keeps them: before a device reads
+    pub fn clean_range(&mut self, address: u64, length: u64) -> MemoryResult<()> {
+        for line in line_range(address, length) {
+            if self.lines.get(&line).is_some_and(|cached| cached.dirty) {
+                self.write_back(line)?;
+                self.stats.cleaned += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // Drops lines without writing them back, from every level: before the
+    // CPU reads what a device wrote. Dirty data in the range is lost.
+    pub fn invalidate_range(&mut self, address: u64, length: u64) {
+        for line in line_range(address, length) {
+            self.broadcast(Snoop::Invalidate(line), None);
+            if let Some(cached) = self.lines.remove(&line) {
+                self.stats.invalidated += 1;
+                if cached.dirty {
+                    self.stats.discarded_dirty += 1;
+                    self.record(StaleKind::DirtyDiscarded, line);
+                }
+            }
+        }
+    }
+
+    pub fn clean_invalidate_range(&mut self, address: u64, length: u64) -> MemoryResult<()> {
+        self.clean_range(address, length)?;
+        self.invalidate_range(address, length);
+        Ok(())
+    }
+
+    // Device side. With `coherent`, dirty cached data is snooped on reads and
+    // cached copies are updated on writes; without, RAM is all there is.
+    pub fn dma_read(&mut self, address: u64, buffer: &mut [u8], coherent: bool) -> MemoryResult<()> {
+        self.memory.read_physical(address, buffer)?;
+        for line in line_range(address, buffer.len() as u64) {
+            let Some(cached) = self.lines.get(&line) else {
+                continue;
+            };
+            if !cached.dirty {
+                continue;
+            }
+            if coherent {
+                let (start, end, offset) = overlap(line, address, buffer.len() as u64);
+                buffer[start..end].copy_from_slice(&cached.data[offset..offset + end - start]);
+                self.stats.snoops += 1;
+            } else {
+                self.stats.stale_device_reads += 1;
+                self.record(StaleKind::DeviceReadStale, line);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn dma_write(&mut self, address: u64, data: &[u8], coherent: bool) -> MemoryResult<()> {
+        self.memory.write_physical(address, data)?;
+        for line in line_range(address, data.len() as u64) {
+            let snoop = if coherent { Snoop::Invalidate(line) } else { Snoop::Stale(line) };
+            self.broadcast(snoop, None);
+            let Some(cached) = self.lines.get_mut(&line) else {
+                continue;
+            };
+            if coherent {
+                let (start, end, offset) = overlap(line, address, data.len() as u64);
+                cached.data[offset..offset + end - start].copy_from_slice(&data[start..end]);
+                self.stats.snoops += 1;
+            } else {
+                cached.stale = true;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut M {
+        &mut self.memory
+    }
+
+    // Methods for visualization system
+    pub fn get_stats(&self) -> &CoherenceStats {
+        &self.stats
+    }
+
+    // Coherence bugs, oldest first
+    pub fn stale_accesses(&self) -> &VecDeque<StaleAccess> {
+        &self.events
+    }
+
+    pub fn cached_lines(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn dirty_lines(&self) -> usize {
+        self.lines.values().filter(|cached| cached.dirty).count()
+    }
+
+    // Helper methods
+    // Brings a line in if it isn't cached, returning the hops memory took
+    // to supply it; None on a hit
+    fn fill(&mut self, line: u64) -> MemoryResult<Option<Vec<Hop>>> {
+        self.clock += 1;
+        if let Some(cached) = self.lines.get_mut(&line) {
+            cached.last_use = self.clock;
+            self.stats.hits += 1;
+            return Ok(None);
+        }
+        self.stats.misses += 1;
+        if self.lines.len() >= self.capacity {
+            self.evict()?;
+        }
+        let mut data = [0u8; CACHE_LINE as usize];
+        let hops = self.memory.read_line_traced(line, &mut data)?;
+        self.lines.insert(line, CachedLine { data, dirty: false, stale: false, reported: false, last_use: self.clock });
+        Ok(Some(hops))
+    }
+
+    // Reported once per line; it stays stale until refilled
+    fn check_stale(&mut self, line: u64) {
+        let Some(cached) = self.lines.get_mut(&line) else {
+            return;
+        };
+        if cached.stale && !cached.reported {
+            cached.reported = true;
+            self.stale_read(line);
+        }
+    }
+
+    // Queues a snoop for every core but the one that caused it
+    fn broadcast(&mut self, snoop: Snoop, from: Option<usize>) {
+        for (core, queue) in self.snoops.iter_mut().enumerate() {
+            if Some(core) == from {
+                continue;
+            }
+            if queue.len() >= SNOOP_QUEUE_LIMIT {
+                queue.clear();
+                queue.push(Snoop::InvalidateAll);
+            }
+            if queue.last() != Some(&Snoop::InvalidateAll) {
+                queue.push(snoop);
+            }
+        }
+    }
+
+    fn evict(&mut self) -> MemoryResult<()> {
+        let victim = self.lines.iter().min_by_key(|(_, cached)| cached.last_use).map(|(&line, _)| line);
+        if let Some(line) = victim {
+            if self.lines[&line].dirty {
+                self.write_back(line)?;
+            }
+            self.lines.remove(&line);
+        }
+        Ok(())
+    }
+
+    fn write_back(&mut self, line: u64) -> MemoryResult<()> {
+        let Some(cached) = self.lines.get_mut(&line) else {
+            return Ok(());
+        };
+        let data = cached.data;
+        let clobbers = cached.stale;
+        cached.dirty = false;
+        cached.stale = false;
+        cached.reported = false;
+        self.memory.write_physical(line, &data)?;
+        self.stats.writebacks += 1;
+        if clobbers {
+            self.stats.lost_device_writes += 1;
+            self.record(StaleKind::DeviceWriteLost, line);
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, kind: StaleKind, line: u64) {
+        if self.events.len() == EVENT_LOG_LIMIT {
+            self.events.pop_front();
+        }
+        self.events.push_back(StaleAccess { kind, line, cycle: self.clock });
+    }
+}
+
+// What a master on the system bus sees: RAM, with the L3 snooped
+impl<M: PhysicalMemory> PhysicalMemory for CachedMemory<M> {
+    fn size(&self) -> u64 {
+        self.memory.size()
+    }
+
+    fn read_physical(&mut self, address: u64, buffer: &mut [u8]) -> MemoryResult<()> {
+        self.dma_read(address, buffer, true)
+    }
+
+    fn write_physical(&mut self, address: u64, data: &[u8]) -> MemoryResult<()> {
+        self.dma_write(address, data, true)
+    }
+}
+
+// Line addresses touched by a range
+fn line_range(address: u64, length: u64) -> impl Iterator<Item = u64> {
+    let first = address & !(CACHE_LINE - 1);
+    let end = address + length;
+    (first..end).step_by(CACHE_LINE as usize)
+}
+
+// Where a line and a range overlap: start and end in the range's buffer,
+// and the offset into the line
+fn overlap(line: u64, address: u64, length: u64) -> (usize, usize, usize) {
+    let start = line.max(address);
+    let end = (line + CACHE_LINE).min(address + length);
+    ((start - address) as usize, (end - address) as usize, (start - line) as usize)
+}
@@ -0,0 +1,70 @@
+// Scatter-gather descriptors live in RAM, where the driver builds them and
+// the engine fetches them one at a time. Layout, little-endian:
+//
+//   0x00  source       u64
+//   0x08  destination  u64
+//   0x10  length       u32
+//   0x14  control      u32
+//   0x18  next         u64   0 ends the chain
+pub const DESCRIPTOR_SIZE: usize = 32;
+
+// Control bits
+pub const CONTROL_SOURCE_DEVICE: u32 = 1 << 0;      // Source is a device register or FIFO on the bus
+pub const CONTROL_DESTINATION_DEVICE: u32 = 1 << 1;
+pub const CONTROL_FIXED_SOURCE: u32 = 1 << 2;       // Don't advance: a FIFO register
+pub const CONTROL_FIXED_DESTINATION: u32 = 1 << 3;
+pub const CONTROL_INTERRUPT: u32 = 1 << 4;          // Interrupt when this descriptor completes
+pub const CONTROL_ERROR: u32 = 1 << 29;             // Written back by the engine
+pub const CONTROL_DONE: u32 = 1 << 30;              // Written back by the engine
+pub const CONTROL_OWN: u32 = 1 << 31;               // Set by the driver to hand it to the engine
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Descriptor {
+    pub source: u64,
+    pub destination: u64,
+    pub length: u32,
+    pub control: u32,
+    pub next: u64,
+}
+
+impl Descriptor {
+    pub fn new(source: u64, destination: u64, length: u32) -> Self {
+        Self { source, destination, length, control: CONTROL_OWN, next: 0 }
+    }
+
+    pub fn with_control(mut self, bits: u32) -> Self {
+        self.control |= bits;
+        self
+    }
+
+    pub fn chained_to(mut self, next: u64) -> Self {
+        self.next = next;
+        self
+    }
+
+    pub fn owned_by_hardware(&self) -> bool {
+        self.control & CONTROL_OWN != 0
+    }
+
+    pub fn to_bytes(self) -> [u8; DESCRIPTOR_SIZE] {
+        let mut bytes = [0u8; DESCRIPTOR_SIZE];
+        bytes[0x00..0x08].copy_from_slice(&self.source.to_le_bytes());
+        bytes[0x08..0x10].copy_from_slice(&self.destination.to_le_bytes());
+        bytes[0x10..0x14].copy_from_slice(&self.length.to_le_bytes());
+        bytes[0x14..0x18].copy_from_slice(&self.control.to_le_bytes());
+        bytes[0x18..0x20].copy_from_slice(&self.next.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; DESCRIPTOR_SIZE]) -> Self {
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap_or([0; 8]));
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap_or([0; 4]));
+        Self {
+            source: u64_at(0x00),
+            destination: u64_at(0x08),
+            length: u32_at(0x10),
+            control: u32_at(0x14),
+            next: u64_at(0x18),
+        }
+    }
+}
@@ -0,0 +1,16 @@
+#[derive(Debug, Clone, PartialEq)]
+pub enum DMAError {
+    // Request errors
+    ZeroLength,
+    QueueFull,
+
+    // Descriptor errors, with the descriptor's address
+    DescriptorNotReady(u64), // OWN bit clear: the driver never handed it over
+    ChainTooLong(u64),
+
+    // Transfer errors, with the faulting address
+    MemoryFault(u64),
+    BusFault(u64),
+}
+
+pub type DMAResult<T> = Result<T, DMAError>;
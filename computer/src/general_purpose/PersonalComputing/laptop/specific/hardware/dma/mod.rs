@@ -1,9 +1,23 @@
-use super::error::{DMAError, DMAResult};
+use self::coherence::CachedMemory;
+use self::descriptor::*;
+use self::error::{DMAError, DMAResult};
+use super::bus::arbitration::{DeviceId, DeviceType};
+use super::bus::mmio::AccessSize;
+use super::bus::{Bus, BusError};
+use super::interrupt::{InterruptController, InterruptID, Trigger};
+use super::memory::physical::PhysicalMemory;
 use std::collections::VecDeque;
 
+pub mod coherence;
+pub mod descriptor;
+pub mod error;
+
+// The engine is one bus master, whichever channel is moving data
+const BUS_MASTER: DeviceId = DeviceId::new(DeviceType::DMA, 0);
+
 pub struct DMAController {
     channels: Vec<DMAChannel>,
-    transfer_queue: VecDeque<DMATransfer>,
+    transfer_queue: VecDeque<DMARequest>,
     config: DMAConfig,
     stats: DMAStats,
 }
@@ -12,20 +26,92 @@ struct DMAChannel {
     id: u8,
     state: ChannelState,
     current_transfer: Option<DMATransfer>,
+    moved: usize,              // Bytes of the current transfer already moved
+    descriptor: Option<u64>,   // Address of the descriptor being worked, for status writeback
+    next: u64,                 // Where the chain continues, 0 at the end
+    chain_length: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct DMATransfer {
+    pub source: u64,
+    pub destination: u64,
+    pub size: usize,
+    pub priority: u8,
+    pub flags: TransferFlags,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransferFlags {
+    pub source_device: bool,      // Read through the bus rather than from RAM
+    pub destination_device: bool,
+    pub fixed_source: bool,       // A FIFO register: the address doesn't advance
+    pub fixed_destination: bool,
+    pub interrupt: bool,          // Raise the channel's interrupt on completion
+}
+
+#[derive(Debug, Clone)]
+pub struct DMAConfig {
+    pub num_channels: u8,
+    pub burst_bytes: usize,      // Moved per channel per tick
+    pub coherent: bool,          // Whether DMA snoops the CPU caches
+    pub irq_base: u32,           // Channel n interrupts on irq_base + n
+    pub irq_priority: u8,        // What the channels' sources are given when connected
+    pub max_descriptors: usize,  // Longest chain before the engine assumes a loop
+    pub queue_depth: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelState {
+    Idle,
+    FetchingDescriptor(u64),
+    Transferring,
+    Error(DMAError),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DMAStats {
+    pub transfers_requested: u64,
+    pub transfers_completed: u64,
+    pub descriptors_processed: u64,
+    pub bytes_transferred: u64,
+    pub bus_accesses: u64,
+    pub bus_stalls: u64, // Bursts put off because another master held the bus
+    pub interrupts_raised: u64,
+    pub interrupts_dropped: u64, // irq_base + channel is beyond the controller's sources
+    pub errors: u64,
 }
 
-struct DMATransfer {
-    source: u64,
-    destination: u64,
-    size: usize,
-    priority: u8,
-    flags: TransferFlags,
+enum DMARequest {
+    Single(DMATransfer),
+    Chain { head: u64, priority: u8 },
+}
+
+impl DMARequest {
+    fn priority(&self) -> u8 {
+        match self {
+            DMARequest::Single(transfer) => transfer.priority,
+            DMARequest::Chain { priority, .. } => *priority,
+        }
+    }
+}
+
+impl TransferFlags {
+    fn from_control(control: u32) -> Self {
+        Self {
+            source_device: control & CONTROL_SOURCE_DEVICE != 0,
+            destination_device: control & CONTROL_DESTINATION_DEVICE != 0,
+            fixed_source: control & CONTROL_FIXED_SOURCE != 0,
+            fixed_destination: control & CONTROL_FIXED_DESTINATION != 0,
+            interrupt: control & CONTROL_INTERRUPT != 0,
+        }
+    }
 }
 
 impl DMAController {
     pub fn new(config: DMAConfig) -> Self {
         let channels = (0..config.num_channels)
-            .map(|id| DMAChannel::new(id))
+            .map(DMAChannel::new)
             .collect();
 
         Self {
@@ -37,24 +123,456 @@ impl DMAController {
     }
 
     pub fn request_transfer(&mut self, transfer: DMATransfer) -> DMAResult<()> {
-        self.transfer_queue.push_back(transfer);
-        Ok(())
+        if transfer.size == 0 {
+            return Err(DMAError::ZeroLength);
+        }
+        self.enqueue(DMARequest::Single(transfer))
     }
 
-    pub fn tick(&mut self) {
-        // Process pending transfers
-        while let Some(transfer) = self.transfer_queue.pop_front() {
-            if let Some(channel) = self.find_available_channel() {
-                channel.start_transfer(transfer);
-            } else {
-                self.transfer_queue.push_front(transfer);
+    // Hands the engine a descriptor chain already built in RAM
+    pub fn request_chain(&mut self, head: u64, priority: u8) -> DMAResult<()> {
+        self.enqueue(DMARequest::Chain { head, priority })
+    }
+
+    // Sets up the channels' sources. A channel pulses its line when it
+    // finishes, and nothing in the engine would ever deassert a level, so
+    // they're edge-triggered. Channels past the controller's sources are
+    // counted as dropped when they fire.
+    pub fn connect(&self, interrupts: &mut InterruptController) {
+        for channel in &self.channels {
+            let id = InterruptID(self.config.irq_base + channel.id as u32);
+            let _ = interrupts.set_trigger(id, Trigger::Edge);
+            let _ = interrupts.set_priority(id, self.config.irq_priority);
+        }
+    }
+
+    pub fn tick<M: PhysicalMemory>(
+        &mut self,
+        bus: &mut Bus,
+        memory: &mut CachedMemory<M>,
+        interrupts: &mut InterruptController,
+    ) {
+        // Process pending transfers, most urgent first
+        while let Some(index) = self.most_urgent() {
+            let Some(channel) = self.find_available_channel() else {
                 break;
-            }
+            };
+            let request = self.transfer_queue.remove(index).expect("index came from the queue");
+            self.channels[channel].start(request);
         }
 
         // Update active channels
+        let context = TickContext { config: &self.config };
         for channel in &mut self.channels {
-            channel.tick();
+            channel.tick(&context, bus, memory, interrupts, &mut self.stats);
+        }
+    }
+
+    // Clears a channel's error so it can take new work
+    pub fn reset_channel(&mut self, channel: u8) {
+        if let Some(channel) = self.channels.get_mut(channel as usize) {
+            *channel = DMAChannel::new(channel.id);
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.transfer_queue.is_empty()
+            && self.channels.iter().all(|channel| !channel.is_busy())
+    }
+
+    // Methods for visualization system
+    pub fn get_stats(&self) -> &DMAStats {
+        &self.stats
+    }
+
+    pub fn channel_states(&self) -> Vec<ChannelState> {
+        self.channels.iter().map(|channel| channel.state.clone()).collect()
+    }
+
+    pub fn queued(&self) -> usize {
+        self.transfer_queue.len()
+    }
+
+    // Helper methods
+    fn enqueue(&mut self, request: DMARequest) -> DMAResult<()> {
+        if self.transfer_queue.len() >= self.config.queue_depth {
+            return Err(DMAError::QueueFull);
         }
+        self.transfer_queue.push_back(request);
+        self.stats.transfers_requested += 1;
+        Ok(())
+    }
+
+    // Highest priority wins; FIFO among equals
+    fn most_urgent(&self) -> Option<usize> {
+        self.transfer_queue
+            .iter()
+            .enumerate()
+            .max_by_key(|(index, request)| (request.priority(), std::cmp::Reverse(*index)))
+            .map(|(index, _)| index)
+    }
+
+    fn find_available_channel(&self) -> Option<usize> {
+        self.channels.iter().position(|channel| channel.state == ChannelState::Idle)
     }
-} 
\ No newline at end of file
+}
+
+impl Default for DMAConfig {
+    fn default() -> Self {
+        Self {
+            num_channels: 8,
+            burst_bytes: 64,
+            coherent: false,
+            irq_base: 48,
+            irq_priority: 1,
+            max_descriptors: 1024,
+            queue_depth: 64,
+        }
+    }
+}
+
+impl Default for DMAController {
+    fn default() -> Self {
+        Self::new(DMAConfig::default())
+    }
+}
+
+struct TickContext<'a> {
+    config: &'a DMAConfig,
+}
+
+impl DMAChannel {
+    fn new(id: u8) -> Self {
+        Self {
+            id,
+            state: ChannelState::Idle,
+            current_transfer: None,
+            moved: 0,
+            descriptor: None,
+            next: 0,
+            chain_length: 0,
+        }
+    }
+
+    fn is_busy(&self) -> bool {
+        matches!(self.state, ChannelState::FetchingDescriptor(_) | ChannelState::Transferring)
+    }
+
+    fn start(&mut self, request: DMARequest) {
+        self.chain_length = 0;
+        match request {
+            DMARequest::Single(transfer) => {
+                self.begin(transfer, None, 0);
+            }
+            DMARequest::Chain { head, .. } => {
+                self.state = ChannelState::FetchingDescriptor(head);
+            }
+        }
+    }
+
+    fn begin(&mut self, transfer: DMATransfer, descriptor: Option<u64>, next: u64) {
+        self.current_transfer = Some(transfer);
+        self.moved = 0;
+        self.descriptor = descriptor;
+        self.next = next;
+        self.state = ChannelState::Transferring;
+    }
+
+    fn tick<M: PhysicalMemory>(
+        &mut self,
+        context: &TickContext,
+        bus: &mut Bus,
+        memory: &mut CachedMemory<M>,
+        interrupts: &mut InterruptController,
+        stats: &mut DMAStats,
+    ) {
+        let result = match self.state {
+            ChannelState::FetchingDescriptor(address) => self.fetch(address, context, memory, stats),
+            ChannelState::Transferring => self.burst(context, bus, memory, interrupts, stats),
+            _ => return,
+        };
+        if let Err(error) = result {
+            // Report the failure in the descriptor too, so the driver sees where the chain stopped
+            if let Some(address) = self.descriptor.take() {
+                let _ = write_status(memory, address, CONTROL_ERROR, context.config.coherent);
+            }
+            self.current_transfer = None;
+            self.state = ChannelState::Error(error);
+            stats.errors += 1;
+            self.interrupt(context, interrupts, stats);
+        }
+    }
+
+    // One descriptor per tick: the fetch is a memory read of its own
+    fn fetch<M: PhysicalMemory>(
+        &mut self,
+        address: u64,
+        context: &TickContext,
+        memory: &mut CachedMemory<M>,
+        stats: &mut DMAStats,
+    ) -> DMAResult<()> {
+        self.chain_length += 1;
+        if self.chain_length > context.config.max_descriptors {
+            return Err(DMAError::ChainTooLong(address));
+        }
+        let mut bytes = [0u8; DESCRIPTOR_SIZE];
+        memory
+            .dma_read(address, &mut bytes, context.config.coherent)
+            .map_err(|_| DMAError::MemoryFault(address))?;
+        let descriptor = Descriptor::from_bytes(&bytes);
+        if !descriptor.owned_by_hardware() {
+            return Err(DMAError::DescriptorNotReady(address));
+        }
+        stats.descriptors_processed += 1;
+
+        let transfer = DMATransfer {
+            source: descriptor.source,
+            destination: descriptor.destination,
+            size: descriptor.length as usize,
+            priority: 0,
+            flags: TransferFlags::from_control(descriptor.control),
+        };
+        self.begin(transfer, Some(address), descriptor.next);
+        Ok(())
+    }
+
+    fn burst<M: PhysicalMemory>(
+        &mut self,
+        context: &TickContext,
+        bus: &mut Bus,
+        memory: &mut CachedMemory<M>,
+        interrupts: &mut InterruptController,
+        stats: &mut DMAStats,
+    ) -> DMAResult<()> {
+        let Some(transfer) = self.current_transfer.clone() else {
+            self.state = ChannelState::Idle;
+            return Ok(());
+        };
+        let coherent = context.config.coherent;
+        let count = context.config.burst_bytes.max(1).min(transfer.size - self.moved);
+        let offset = self.moved as u64;
+
+        let mut buffer = vec![0u8; count];
+        if transfer.flags.source_device {
+            let base = if transfer.flags.fixed_source { transfer.source } else { transfer.source + offset };
+            if !read_device(bus, memory, base, transfer.flags.fixed_source, &mut buffer, stats)? {
+                stats.bus_stalls += 1;
+                return Ok(());
+            }
+        } else {
+            let source = transfer.source + offset;
+            memory
+                .dma_read(source, &mut buffer, coherent)
+                .map_err(|_| DMAError::MemoryFault(source))?;
+        }
+
+        if transfer.flags.destination_device {
+            let base = if transfer.flags.fixed_destination { transfer.destination } else { transfer.destination + offset };
+            if !write_device(bus, memory, base, transfer.flags.fixed_destination, &buffer, stats)? {
+                stats.bus_stalls += 1;
+                return Ok(());
+            }
+        } else {
+            let destination = transfer.destination + offset;
+            memory
+                .dma_write(destination, &buffer, coherent)
+                .map_err(|_| DMAError::MemoryFault(destination))?;
+        }
+
+        self.moved += count;
+        stats.bytes_transferred += count as u64;
+        if self.moved < transfer.size {
+            return Ok(());
+        }
+
+        // Segment done: hand the descriptor back to the driver
+        if let Some(address) = self.descriptor.take() {
+            write_status(memory, address, CONTROL_DONE, coherent)?;
+        }
+        if transfer.flags.interrupt {
+            self.interrupt(context, interrupts, stats);
+        }
+        self.current_transfer = None;
+        if self.next != 0 {
+            self.state = ChannelState::FetchingDescriptor(self.next);
+        } else {
+            self.state = ChannelState::Idle;
+            stats.transfers_completed += 1;
+        }
+        Ok(())
+    }
+
+    fn interrupt(&self, context: &TickContext, interrupts: &mut InterruptController, stats: &mut DMAStats) {
+        let id = InterruptID(context.config.irq_base + self.id as u32);
+        match interrupts.raise_interrupt(id) {
+            Ok(()) => stats.interrupts_raised += 1,
+            Err(_) => stats.interrupts_dropped += 1,
+        }
+    }
+}
+
+// Device endpoints go through the bus a word at a time, bytes for the tail.
+// Returns false, having moved nothing, if the arbiter gave the bus to
+// someone else; once granted, the engine keeps it for the rest of the burst.
+// An endpoint in the memory window reaches RAM the way any bus master does.
+fn read_device<M: PhysicalMemory>(bus: &mut Bus, memory: &mut CachedMemory<M>, base: u64, fixed: bool, buffer: &mut [u8], stats: &mut DMAStats) -> DMAResult<bool> {
+    let mut done = 0;
+    while done < buffer.len() {
+        let size = if buffer.len() - done >= 4 { AccessSize::Word } else { AccessSize::Byte };
+        let address = if fixed { base } else { base + done as u64 };
+        let value = match bus.read_by(BUS_MASTER, address, size, memory) {
+            Ok(value) => value,
+            Err(BusError::BusUnavailable) if done == 0 => return Ok(false),
+            Err(_) => return Err(DMAError::BusFault(address)),
+        };
+        let bytes = size.bytes() as usize;
+        buffer[done..done + bytes].copy_from_slice(&value.to_le_bytes()[..bytes]);
+        done += bytes;
+        stats.bus_accesses += 1;
+    }
+    Ok(true)
+}
+
+fn write_device<M: PhysicalMemory>(bus: &mut Bus, memory: &mut CachedMemory<M>, base: u64, fixed: bool, data: &[u8], stats: &mut DMAStats) -> DMAResult<bool> {
+    let mut done = 0;
+    while done < data.len() {
+        let size = if data.len() - done >= 4 { AccessSize::Word } else { AccessSize::Byte };
+        let address = if fixed { base } else { base + done as u64 };
+        let bytes = size.bytes() as usize;
+        let mut value = [0u8; 8];
+        value[..bytes].copy_from_slice(&data[done..done + bytes]);
+        match bus.write_by(BUS_MASTER, address, size, u64::from_le_bytes(value), memory) {
+            Ok(()) => {}
+            Err(BusError::BusUnavailable) if done == 0 => return Ok(false),
+            Err(_) => return Err(DMAError::BusFault(address)),
+        }
+        done += bytes;
+        stats.bus_accesses += 1;
+    }
+    Ok(true)
+}
+
+// Clears OWN and sets the given status bits in a descriptor's control word
+fn write_status<M: PhysicalMemory>(memory: &mut CachedMemory<M>, address: u64, status: u32, coherent: bool) -> DMAResult<()> {
+    let control_address = address + 0x14;
+    let mut control = [0u8; 4];
+    memory
+        .dma_read(control_address, &mut control, coherent)
+        .map_err(|_| DMAError::MemoryFault(control_address))?;
+    let control = (u32::from_le_bytes(control) & !CONTROL_OWN) | status;
+    memory
+        .dma_write(control_address, &control.to_le_bytes(), coherent)
+        .map_err(|_| DMAError::MemoryFault(control_address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::cpu::cache_controller::{CacheController, CacheGeometry};
+    use crate::hardware::memory::physical::SystemRam;
+
+    const IRQ: InterruptID = InterruptID(48);
+
+    fn run<M: PhysicalMemory>(dma: &mut DMAController, memory: &mut CachedMemory<M>, interrupts: &mut InterruptController) {
+        let mut bus = Bus::new();
+        for _ in 0..1000 {
+            if dma.is_idle() {
+                return;
+            }
+            dma.tick(&mut bus, memory, interrupts);
+            interrupts.tick();
+        }
+        panic!("the engine never went idle");
+    }
+
+    fn copy(source: u64, destination: u64, size: usize, interrupt: bool) -> DMATransfer {
+        DMATransfer {
+            source,
+            destination,
+            size,
+            priority: 0,
+            flags: TransferFlags { interrupt, ..TransferFlags::default() },
+        }
+    }
+
+    #[test]
+    fn a_descriptor_chain_gathers_its_segments_and_hands_each_back() {
+        let mut memory = CachedMemory::new(SystemRam::new(64 * 1024), 64);
+        let ram = memory.memory_mut();
+        ram.write_physical(0x1000, &[0xAA; 100]).unwrap();
+        ram.write_physical(0x3000, &[0xBB; 40]).unwrap();
+        ram.write_physical(0x8000, &Descriptor::new(0x1000, 0x5000, 100).chained_to(0x8020).to_bytes()).unwrap();
+        ram.write_physical(0x8020, &Descriptor::new(0x3000, 0x5064, 40).to_bytes()).unwrap();
+
+        let mut dma = DMAController::default();
+        dma.request_chain(0x8000, 0).unwrap();
+        run(&mut dma, &mut memory, &mut InterruptController::default());
+
+        let mut gathered = [0u8; 140];
+        memory.memory_mut().read_physical(0x5000, &mut gathered).unwrap();
+        assert!(gathered[..100].iter().all(|&byte| byte == 0xAA));
+        assert!(gathered[100..].iter().all(|&byte| byte == 0xBB));
+
+        for address in [0x8000, 0x8020] {
+            let control = memory.memory_mut().read_u32(address + 0x14).unwrap();
+            assert_eq!(control & (CONTROL_OWN | CONTROL_DONE), CONTROL_DONE);
+        }
+        let stats = dma.get_stats();
+        assert_eq!((stats.descriptors_processed, stats.transfers_completed, stats.bytes_transferred), (2, 1, 140));
+    }
+
+    #[test]
+    fn completion_interrupts_once_and_the_claim_acknowledges_it() {
+        let mut memory = CachedMemory::new(SystemRam::new(64 * 1024), 64);
+        let mut plic = InterruptController::default();
+        let mut dma = DMAController::default();
+        dma.connect(&mut plic);
+        plic.enable(0, IRQ).unwrap();
+
+        dma.request_transfer(copy(0x1000, 0x2000, 256, true)).unwrap();
+        run(&mut dma, &mut memory, &mut plic);
+        assert_eq!(plic.pending_for(0), Some(IRQ));
+        assert_eq!(plic.claim(0), Some(IRQ));
+        plic.complete(0, IRQ).unwrap();
+
+        // The pulse is spent: completing doesn't bring it straight back
+        assert_eq!(plic.pending_for(0), None);
+        assert_eq!(dma.get_stats().interrupts_raised, 1);
+
+        // And the next transfer interrupts again
+        dma.request_transfer(copy(0x1000, 0x2000, 256, true)).unwrap();
+        run(&mut dma, &mut memory, &mut plic);
+        assert_eq!(plic.claim(0), Some(IRQ));
+    }
+
+    #[test]
+    fn only_a_non_coherent_transfer_leaves_a_core_loading_stale_data() {
+        for coherent in [false, true] {
+            let l3 = CacheGeometry { size: 64 * 1024, ways: 16, latency: 40 };
+            let mut memory = CachedMemory::with_l3(SystemRam::new(1 << 20), l3);
+            let mut core = CacheController::with_levels(
+                0,
+                CacheGeometry { size: 4 * 1024, ways: 4, latency: 4 },
+                CacheGeometry { size: 16 * 1024, ways: 8, latency: 12 },
+            );
+            memory.memory_mut().write_u32(0x4000, 0x1111_1111).unwrap();
+            memory.memory_mut().write_u32(0x1000, 0x2222_2222).unwrap();
+            assert_eq!(core.read(0x4000, &mut memory).unwrap().value, 0x1111_1111);
+
+            let mut dma = DMAController::new(DMAConfig { coherent, ..DMAConfig::default() });
+            dma.request_transfer(copy(0x1000, 0x4000, 4, false)).unwrap();
+            run(&mut dma, &mut memory, &mut InterruptController::default());
+
+            let load = core.read(0x4000, &mut memory).unwrap();
+            if coherent {
+                assert_eq!(load.value, 0x2222_2222);
+                assert_eq!(memory.get_stats().stale_cpu_reads, 0);
+            } else {
+                // The core's L1 still holds what was there before the device wrote
+                assert_eq!(load.value, 0x1111_1111);
+                assert_eq!(memory.get_stats().stale_cpu_reads, 1);
+                assert_eq!(memory.stale_accesses()[0].kind, coherence::StaleKind::CpuReadStale);
+            }
+        }
+    }
+}
@@ -1,38 +1,704 @@
-use std::collections::VecDeque;
-use super::super::error::GPUResult;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use super::super::error::{GPUError, GPUResult};
+use super::super::scheduler::dispatcher::Dispatcher;
+use super::super::scheduler::workload::{Priority, Workload, WorkloadType};
+use super::super::sync::GPUFence;
+use crate::hardware::bus::mmio::{AccessSize, MmioDevice};
+use crate::hardware::bus::BusError;
+use crate::hardware::memory::physical::PhysicalMemory;
+
+// The command processor consumes a ring buffer the driver fills in system
+// memory. The driver writes packets, then publishes its write pointer through
+// the doorbell register; the CP fetches up to it and reports its read pointer.
+
+// Registers
+pub const CP_RING_BASE_LO: u32 = 0x00;
+pub const CP_RING_BASE_HI: u32 = 0x04;
+pub const CP_RING_SIZE: u32 = 0x08; // Bytes, a power of two
+pub const CP_RPTR: u32 = 0x0C;
+pub const CP_WPTR: u32 = 0x10; // Doorbell
+pub const CP_STATUS: u32 = 0x14;
+pub const CP_INT_STATUS: u32 = 0x18; // Write one to clear
+
+// CP_STATUS bits
+pub const STATUS_BUSY: u32 = 1 << 0;
+pub const STATUS_WAITING: u32 = 1 << 1;
+pub const STATUS_HALTED: u32 = 1 << 2;
+
+// CP_INT_STATUS bits
+pub const INT_FENCE: u32 = 1 << 0;
+pub const INT_ERROR: u32 = 1 << 1;
+
+// Context state registers written by SET_STATE
+pub const STATE_PIPELINE: u32 = 0x00;
+pub const STATE_PRIORITY: u32 = 0x01;
+pub const STATE_CONTEXT: u32 = 0x02; // Dispatcher context the ring's work runs in
+
+// Fence signal flags
+pub const SIGNAL_INTERRUPT: u32 = 1 << 0;
+
+// Packet header: opcode in bits 31:24, payload length in dwords in bits 15:0
+pub mod opcode {
+    pub const NOP: u8 = 0x00;
+    pub const SET_STATE: u8 = 0x01;
+    pub const DRAW: u8 = 0x02;
+    pub const DISPATCH: u8 = 0x03;
+    pub const COPY: u8 = 0x04;
+    pub const SIGNAL: u8 = 0x05;
+    pub const WAIT: u8 = 0x06;
+}
+
+const PREFETCH_DEPTH: usize = 8;
+const DECODE_CYCLES: u64 = 2;
+const COPY_BYTES_PER_CYCLE: u64 = 64;
+// The copy engine's staging buffer; larger copies go through it piece by piece
+const COPY_CHUNK_BYTES: u64 = 4096;
+const EVENT_LOG_LIMIT: usize = 1024;
 
 pub struct CommandProcessor {
+    ring: RingState,
     command_queue: VecDeque<GPUCommand>,
     current_context: Option<GPUContext>,
+    fences: Vec<Arc<GPUFence>>,
+    busy_cycles: u64,
+    waiting: Option<(u32, u64)>,
+    // Header of the packet that stopped the CP
+    halted: Option<u32>,
+    interrupt_status: u32,
+    next_workload_id: u64,
+    cycle: u64,
+    events: VecDeque<CommandEvent>,
     stats: CommandStats,
 }
 
+struct RingState {
+    base: u64,
+    size: u32,
+    rptr: u32, // Byte offsets into the ring
+    wptr: u32,
+}
+
+struct GPUContext {
+    registers: HashMap<u32, u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GPUCommand {
+    Nop,
+    SetState { register: u32, value: u32 },
+    Draw { primitive_count: u32, instance_count: u32 },
+    Dispatch { workload_type: WorkloadType, group_count: [u32; 3] },
+    Copy { src: u64, dst: u64, size: u64 },
+    Signal { fence: u32, value: u64, interrupt: bool },
+    Wait { fence: u32, value: u64 },
+}
+
+// What the CP did and when, for animating the CPU-GPU handoff
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandEvent {
+    pub cycle: u64,
+    pub kind: CommandEventKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandEventKind {
+    Doorbell { wptr: u32 },
+    Fetched { offset: u32, command: GPUCommand },
+    Dispatched { workload: u64, workload_type: WorkloadType },
+    Copied { bytes: u64 },
+    FenceSignaled { fence: u32, value: u64 },
+    WaitStarted { fence: u32, value: u64 },
+    WaitEnded { fence: u32, value: u64 },
+    Halted { offset: u32, header: u32 },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CommandStats {
+    pub cycles: u64,
+    pub busy_cycles: u64,
+    pub wait_cycles: u64,
+    pub backpressure_cycles: u64,
+    pub doorbells: u64,
+    pub packets: u64,
+    pub dwords_fetched: u64,
+    pub draws: u64,
+    pub dispatches: u64,
+    pub bytes_copied: u64,
+    pub fences_signaled: u64,
+}
+
 impl CommandProcessor {
     pub fn new() -> Self {
         Self {
+            ring: RingState { base: 0, size: 0, rptr: 0, wptr: 0 },
             command_queue: VecDeque::new(),
             current_context: None,
+            fences: Vec::new(),
+            busy_cycles: 0,
+            waiting: None,
+            halted: None,
+            interrupt_status: 0,
+            next_workload_id: 1,
+            cycle: 0,
+            events: VecDeque::new(),
             stats: CommandStats::default(),
         }
     }
 
+    // Fences live on the GPU; packets name them by index
+    pub fn create_fence(&mut self) -> (u32, Arc<GPUFence>) {
+        let fence = Arc::new(GPUFence::new());
+        self.fences.push(fence.clone());
+        ((self.fences.len() - 1) as u32, fence)
+    }
+
+    pub fn fence(&self, id: u32) -> Option<&Arc<GPUFence>> {
+        self.fences.get(id as usize)
+    }
+
+    pub fn read_register(&self, offset: u32) -> GPUResult<u32> {
+        Ok(match offset {
+            CP_RING_BASE_LO => self.ring.base as u32,
+            CP_RING_BASE_HI => (self.ring.base >> 32) as u32,
+            CP_RING_SIZE => self.ring.size,
+            CP_RPTR => self.ring.rptr,
+            CP_WPTR => self.ring.wptr,
+            CP_STATUS => self.status(),
+            CP_INT_STATUS => self.interrupt_status,
+            _ => return Err(GPUError::InvalidRegister),
+        })
+    }
+
+    pub fn write_register(&mut self, offset: u32, value: u32) -> GPUResult<()> {
+        match offset {
+            // Reprogramming the ring resets it and clears a halt
+            CP_RING_BASE_LO => {
+                self.ring.base = (self.ring.base & !0xFFFF_FFFF) | value as u64;
+                self.reset_ring();
+            }
+            CP_RING_BASE_HI => {
+                self.ring.base = (self.ring.base & 0xFFFF_FFFF) | (value as u64) << 32;
+                self.reset_ring();
+            }
+            CP_RING_SIZE => {
+                if !value.is_power_of_two() || value < 16 {
+                    return Err(GPUError::InvalidRegister);
+                }
+                self.ring.size = value;
+                self.reset_ring();
+            }
+            CP_WPTR => {
+                if value >= self.ring.size || !value.is_multiple_of(4) {
+                    return Err(GPUError::InvalidRegister);
+                }
+                self.ring.wptr = value;
+                self.stats.doorbells += 1;
+                self.log(CommandEventKind::Doorbell { wptr: value });
+            }
+            CP_INT_STATUS => self.interrupt_status &= !value,
+            CP_RPTR | CP_STATUS => {}
+            _ => return Err(GPUError::InvalidRegister),
+        }
+        Ok(())
+    }
+
+    // Bypasses the ring, straight into the prefetch queue
     pub fn submit_command(&mut self, command: GPUCommand) -> GPUResult<()> {
+        if self.command_queue.len() >= PREFETCH_DEPTH {
+            return Err(GPUError::QueueFull);
+        }
         self.command_queue.push_back(command);
         Ok(())
     }
 
-    pub fn process_commands(&mut self) -> GPUResult<()> {
-        while let Some(cmd) = self.command_queue.pop_front() {
-            self.execute_command(cmd)?;
+    // One CP clock: fetch the next packet into the prefetch queue while the
+    // head of the queue executes
+    pub fn tick(&mut self, memory: &mut dyn PhysicalMemory, dispatcher: &mut Dispatcher) {
+        self.cycle += 1;
+        self.stats.cycles += 1;
+        if self.halted.is_some() {
+            return;
+        }
+
+        if self.command_queue.len() < PREFETCH_DEPTH {
+            if let Err(header) = self.fetch_packet(memory) {
+                self.halt(header);
+                return;
+            }
+        }
+
+        if self.busy_cycles > 0 {
+            self.busy_cycles -= 1;
+            self.stats.busy_cycles += 1;
+            return;
+        }
+        if let Some((fence, value)) = self.waiting {
+            if !self.fences[fence as usize].is_reached(value) {
+                self.stats.wait_cycles += 1;
+                return;
+            }
+            self.waiting = None;
+            self.log(CommandEventKind::WaitEnded { fence, value });
+        }
+        if let Some(command) = self.command_queue.pop_front() {
+            match self.execute_command(command.clone(), memory, dispatcher) {
+                Ok(()) => {}
+                // The dispatcher is full: hold the packet and try again next cycle
+                Err(GPUError::QueueFull) => {
+                    self.stats.backpressure_cycles += 1;
+                    self.command_queue.push_front(command);
+                }
+                Err(_) => self.halt(command.header()),
+            }
+        }
+    }
+
+    // Runs until the ring drains, or until the CP blocks on a fence only
+    // something outside the ring can signal
+    pub fn process_commands(&mut self, memory: &mut dyn PhysicalMemory, dispatcher: &mut Dispatcher) -> GPUResult<()> {
+        while self.is_busy() {
+            if let Some((fence, value)) = self.waiting {
+                if self.busy_cycles == 0 && !self.fences[fence as usize].is_reached(value) {
+                    return Ok(());
+                }
+            }
+            self.tick(memory, dispatcher);
+        }
+        if let Some(header) = self.halted {
+            return Err(GPUError::InvalidPacket(header));
         }
         Ok(())
     }
+
+    pub fn is_busy(&self) -> bool {
+        self.halted.is_none() && (self.ring.rptr != self.ring.wptr || !self.command_queue.is_empty() || self.busy_cycles > 0 || self.waiting.is_some())
+    }
+
+    pub fn interrupt_pending(&self) -> bool {
+        self.interrupt_status != 0
+    }
+
+    pub fn get_stats(&self) -> &CommandStats {
+        &self.stats
+    }
+
+    // Methods for visualization system
+    pub fn events(&self) -> impl Iterator<Item = &CommandEvent> {
+        self.events.iter()
+    }
+
+    pub fn pending_commands(&self) -> impl Iterator<Item = &GPUCommand> {
+        self.command_queue.iter()
+    }
+
+    pub fn ring_pointers(&self) -> (u32, u32) {
+        (self.ring.rptr, self.ring.wptr)
+    }
+
+    fn fetch_packet(&mut self, memory: &mut dyn PhysicalMemory) -> Result<(), u32> {
+        let ring = &self.ring;
+        if ring.size == 0 || ring.rptr == ring.wptr {
+            return Ok(());
+        }
+        let available = ring.wptr.wrapping_sub(ring.rptr) & (ring.size - 1);
+        let offset = ring.rptr;
+        let header = self.read_ring(memory, offset).map_err(|_| 0u32)?;
+        let length = header & 0xFFFF;
+        if (length + 1) * 4 > available {
+            // The doorbell only ever covers whole packets
+            return Err(header);
+        }
+
+        let mut payload = Vec::with_capacity(length as usize);
+        for index in 0..length {
+            payload.push(self.read_ring(memory, offset + (index + 1) * 4).map_err(|_| header)?);
+        }
+        let command = GPUCommand::decode(header, &payload).map_err(|_| header)?;
+
+        self.ring.rptr = (offset + (length + 1) * 4) & (self.ring.size - 1);
+        self.stats.dwords_fetched += (length + 1) as u64;
+        self.stats.packets += 1;
+        self.log(CommandEventKind::Fetched { offset, command: command.clone() });
+        self.command_queue.push_back(command);
+        Ok(())
+    }
+
+    fn execute_command(&mut self, command: GPUCommand, memory: &mut dyn PhysicalMemory, dispatcher: &mut Dispatcher) -> GPUResult<()> {
+        self.busy_cycles = DECODE_CYCLES;
+        match command.clone() {
+            GPUCommand::Nop => {}
+            GPUCommand::SetState { register, value } => {
+                self.context().registers.insert(register, value);
+            }
+            GPUCommand::Draw { primitive_count, instance_count } => {
+                let vertices = (primitive_count as u64 * 3)
+                    .checked_mul(instance_count as u64)
+                    .ok_or(GPUError::InvalidPacket(command.header()))?;
+                self.dispatch(WorkloadType::Shader, vertices, dispatcher)?;
+                self.stats.draws += 1;
+            }
+            GPUCommand::Dispatch { workload_type, group_count } => {
+                let groups = group_count
+                    .iter()
+                    .try_fold(1u64, |groups, &count| groups.checked_mul(count as u64))
+                    .ok_or(GPUError::InvalidPacket(command.header()))?;
+                self.dispatch(workload_type, groups, dispatcher)?;
+                self.stats.dispatches += 1;
+            }
+            GPUCommand::Copy { src, dst, size } => {
+                copy(memory, src, dst, size)?;
+                self.busy_cycles += size.div_ceil(COPY_BYTES_PER_CYCLE);
+                self.stats.bytes_copied += size;
+                self.log(CommandEventKind::Copied { bytes: size });
+            }
+            GPUCommand::Signal { fence, value, interrupt } => {
+                self.fences.get(fence as usize).ok_or(GPUError::InvalidPacket(fence))?.signal(value);
+                if interrupt {
+                    self.interrupt_status |= INT_FENCE;
+                }
+                self.stats.fences_signaled += 1;
+                self.log(CommandEventKind::FenceSignaled { fence, value });
+            }
+            GPUCommand::Wait { fence, value } => {
+                if fence as usize >= self.fences.len() {
+                    return Err(GPUError::InvalidPacket(fence));
+                }
+                self.waiting = Some((fence, value));
+                self.log(CommandEventKind::WaitStarted { fence, value });
+            }
+        }
+        Ok(())
+    }
+
+    fn dispatch(&mut self, workload_type: WorkloadType, work_items: u64, dispatcher: &mut Dispatcher) -> GPUResult<()> {
+        let priority = match self.context().registers.get(&STATE_PRIORITY) {
+            Some(0) => Priority::Low,
+            Some(2) => Priority::High,
+            Some(3) => Priority::RealTime,
+            _ => Priority::Normal,
+        };
+        let context = self.context().registers.get(&STATE_CONTEXT).copied().unwrap_or(0);
+        let mut workload = Workload::new(self.next_workload_id, workload_type, priority);
+        workload.set_context(context);
+        workload.submit_time = self.cycle;
+        workload.work_items = work_items;
+        dispatcher.submit_workload(workload)?;
+
+        self.log(CommandEventKind::Dispatched { workload: self.next_workload_id, workload_type });
+        self.next_workload_id += 1;
+        Ok(())
+    }
+
+    fn halt(&mut self, header: u32) {
+        self.halted = Some(header);
+        self.interrupt_status |= INT_ERROR;
+        self.log(CommandEventKind::Halted { offset: self.ring.rptr, header });
+    }
+
+    // Helper methods
+    fn status(&self) -> u32 {
+        let mut status = 0;
+        if self.is_busy() {
+            status |= STATUS_BUSY;
+        }
+        if self.waiting.is_some() {
+            status |= STATUS_WAITING;
+        }
+        if self.halted.is_some() {
+            status |= STATUS_HALTED;
+        }
+        status
+    }
+
+    fn reset_ring(&mut self) {
+        self.ring.rptr = 0;
+        self.ring.wptr = 0;
+        self.command_queue.clear();
+        self.waiting = None;
+        self.busy_cycles = 0;
+        self.halted = None;
+    }
+
+    // Nothing can be read from a ring that has not been given a size yet
+    fn read_ring(&self, memory: &mut dyn PhysicalMemory, offset: u32) -> GPUResult<u32> {
+        let mask = self.ring.size.checked_sub(1).ok_or(GPUError::InvalidRegister)?;
+        let address = self.ring.base + (offset & mask) as u64;
+        memory.read_u32(address).map_err(|_| GPUError::AccessViolation)
+    }
+
+    fn context(&mut self) -> &mut GPUContext {
+        self.current_context.get_or_insert_with(|| GPUContext { registers: HashMap::new() })
+    }
+
+    fn log(&mut self, kind: CommandEventKind) {
+        if self.events.len() == EVENT_LOG_LIMIT {
+            self.events.pop_front();
+        }
+        self.events.push_back(CommandEvent { cycle: self.cycle, kind });
+    }
 }
 
-pub enum GPUCommand {
-    Draw { primitive_count: u32, instance_count: u32 },
-    Compute { group_count: [u32; 3] },
-    CopyBuffer { src: BufferHandle, dst: BufferHandle, size: u64 },
-    SetPipeline(PipelineHandle),
-    // ... more commands
-} 
\ No newline at end of file
+// Moves a copy packet's bytes through a fixed staging buffer, so the packet's
+// size only costs memory once both ranges are known to be inside RAM.
+// Overlapping ranges are walked from the end when the destination is higher.
+fn copy(memory: &mut dyn PhysicalMemory, src: u64, dst: u64, size: u64) -> GPUResult<()> {
+    let inside = |start: u64| start.checked_add(size).is_some_and(|end| end <= memory.size());
+    if !inside(src) || !inside(dst) {
+        return Err(GPUError::AccessViolation);
+    }
+
+    let mut chunk = [0u8; COPY_CHUNK_BYTES as usize];
+    let chunks = size.div_ceil(COPY_CHUNK_BYTES);
+    for index in 0..chunks {
+        let index = if dst > src { chunks - 1 - index } else { index };
+        let offset = index * COPY_CHUNK_BYTES;
+        let length = (size - offset).min(COPY_CHUNK_BYTES) as usize;
+        memory.read_physical(src + offset, &mut chunk[..length]).map_err(|_| GPUError::AccessViolation)?;
+        memory.write_physical(dst + offset, &chunk[..length]).map_err(|_| GPUError::AccessViolation)?;
+    }
+    Ok(())
+}
+
+// The CP's register block as the host sees it through a BAR
+impl MmioDevice for CommandProcessor {
+    fn read(&mut self, offset: u64, _size: AccessSize) -> Result<u64, BusError> {
+        self.read_register(offset as u32).map(|value| value as u64).map_err(|_| BusError::DeviceFault(offset))
+    }
+
+    fn write(&mut self, offset: u64, _size: AccessSize, value: u64) -> Result<(), BusError> {
+        self.write_register(offset as u32, value as u32).map_err(|_| BusError::DeviceFault(offset))
+    }
+
+    fn supports(&self, size: AccessSize) -> bool {
+        size == AccessSize::Word
+    }
+}
+
+impl Default for CommandProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GPUCommand {
+    pub fn encode(&self) -> Vec<u32> {
+        let (opcode, payload) = match *self {
+            GPUCommand::Nop => (opcode::NOP, vec![]),
+            GPUCommand::SetState { register, value } => (opcode::SET_STATE, vec![register, value]),
+            GPUCommand::Draw { primitive_count, instance_count } => (opcode::DRAW, vec![primitive_count, instance_count]),
+            GPUCommand::Dispatch { workload_type, group_count } => {
+                let kind = match workload_type {
+                    WorkloadType::Shader => 0,
+                    WorkloadType::RayTracing => 1,
+                    WorkloadType::Tensor => 2,
+                };
+                (opcode::DISPATCH, vec![kind, group_count[0], group_count[1], group_count[2]])
+            }
+            GPUCommand::Copy { src, dst, size } => (
+                opcode::COPY,
+                vec![src as u32, (src >> 32) as u32, dst as u32, (dst >> 32) as u32, size as u32, (size >> 32) as u32],
+            ),
+            GPUCommand::Signal { fence, value, interrupt } => (
+                opcode::SIGNAL,
+                vec![fence, value as u32, (value >> 32) as u32, if interrupt { SIGNAL_INTERRUPT } else { 0 }],
+            ),
+            GPUCommand::Wait { fence, value } => (opcode::WAIT, vec![fence, value as u32, (value >> 32) as u32]),
+        };
+        let mut packet = vec![(opcode as u32) << 24 | payload.len() as u32];
+        packet.extend(payload);
+        packet
+    }
+
+    // The packet header this command encodes to, as reported when it faults
+    pub fn header(&self) -> u32 {
+        self.encode()[0]
+    }
+
+    pub fn decode(header: u32, payload: &[u32]) -> GPUResult<Self> {
+        let expected = match (header >> 24) as u8 {
+            opcode::NOP => 0,
+            opcode::SET_STATE | opcode::DRAW => 2,
+            opcode::DISPATCH | opcode::SIGNAL => 4,
+            opcode::COPY => 6,
+            opcode::WAIT => 3,
+            _ => return Err(GPUError::InvalidPacket(header)),
+        };
+        if payload.len() != expected || (header & 0xFFFF) as usize != expected {
+            return Err(GPUError::InvalidPacket(header));
+        }
+        let wide = |index: usize| payload[index] as u64 | (payload[index + 1] as u64) << 32;
+
+        Ok(match (header >> 24) as u8 {
+            opcode::NOP => GPUCommand::Nop,
+            opcode::SET_STATE => GPUCommand::SetState { register: payload[0], value: payload[1] },
+            opcode::DRAW => GPUCommand::Draw { primitive_count: payload[0], instance_count: payload[1] },
+            opcode::DISPATCH => GPUCommand::Dispatch {
+                workload_type: match payload[0] {
+                    0 => WorkloadType::Shader,
+                    1 => WorkloadType::RayTracing,
+                    2 => WorkloadType::Tensor,
+                    _ => return Err(GPUError::InvalidPacket(header)),
+                },
+                group_count: [payload[1], payload[2], payload[3]],
+            },
+            opcode::COPY => GPUCommand::Copy { src: wide(0), dst: wide(2), size: wide(4) },
+            opcode::SIGNAL => GPUCommand::Signal { fence: payload[0], value: wide(1), interrupt: payload[3] & SIGNAL_INTERRUPT != 0 },
+            _ => GPUCommand::Wait { fence: payload[0], value: wide(1) },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::memory::physical::SystemRam;
+
+    const RING_BASE: u64 = 0x1000;
+    const RING_SIZE: u32 = 64;
+
+    struct Rig {
+        cp: CommandProcessor,
+        memory: SystemRam,
+        dispatcher: Dispatcher,
+        wptr: u32,
+    }
+
+    impl Rig {
+        fn new() -> Self {
+            let mut cp = CommandProcessor::new();
+            cp.write_register(CP_RING_BASE_LO, RING_BASE as u32).unwrap();
+            cp.write_register(CP_RING_SIZE, RING_SIZE).unwrap();
+            Self { cp, memory: SystemRam::new(1 << 16), dispatcher: Dispatcher::new(), wptr: 0 }
+        }
+
+        // Writes packets at the write pointer, wrapping at the end of the ring, then rings the doorbell
+        fn submit(&mut self, commands: &[GPUCommand]) {
+            for dword in commands.iter().flat_map(GPUCommand::encode) {
+                self.memory.write_u32(RING_BASE + self.wptr as u64, dword).unwrap();
+                self.wptr = (self.wptr + 4) % RING_SIZE;
+            }
+            self.cp.write_register(CP_WPTR, self.wptr).unwrap();
+        }
+
+        fn run(&mut self) -> GPUResult<()> {
+            self.cp.process_commands(&mut self.memory, &mut self.dispatcher)
+        }
+    }
+
+    fn set_state(register: u32, value: u32) -> GPUCommand {
+        GPUCommand::SetState { register, value }
+    }
+
+    #[test]
+    fn packets_straddling_the_end_of_the_ring_wrap_to_its_start() {
+        let mut rig = Rig::new();
+        rig.submit(&[set_state(0x10, 1), set_state(0x11, 2), set_state(0x12, 3), set_state(0x13, 4)]);
+        rig.run().unwrap();
+        assert_eq!(rig.cp.ring_pointers(), (48, 48));
+
+        // The second of these starts 4 bytes from the end and finishes at the ring's start
+        rig.submit(&[set_state(0x14, 5), set_state(0x15, 6), GPUCommand::Nop]);
+        rig.run().unwrap();
+        assert_eq!(rig.cp.ring_pointers(), (12, 12));
+        assert_eq!(rig.cp.get_stats().packets, 7);
+        assert_eq!(rig.cp.context().registers.get(&0x15), Some(&6));
+        let fetched: Vec<u32> = rig
+            .cp
+            .events()
+            .filter_map(|event| match event.kind {
+                CommandEventKind::Fetched { offset, .. } => Some(offset),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(fetched, vec![0, 12, 24, 36, 48, 60, 8]);
+    }
+
+    #[test]
+    fn zero_sized_rings_are_rejected() {
+        let mut cp = CommandProcessor::new();
+        let mut memory = SystemRam::new(4096);
+        assert_eq!(cp.read_ring(&mut memory, 0), Err(GPUError::InvalidRegister));
+        assert_eq!(cp.write_register(CP_RING_SIZE, 0), Err(GPUError::InvalidRegister));
+        assert_eq!(cp.write_register(CP_WPTR, 0), Err(GPUError::InvalidRegister));
+
+        // A fault with no ring behind it still reports the packet
+        let copy = GPUCommand::Copy { src: 0, dst: 1 << 40, size: 16 };
+        cp.submit_command(copy.clone()).unwrap();
+        assert_eq!(cp.process_commands(&mut memory, &mut Dispatcher::new()), Err(GPUError::InvalidPacket(copy.header())));
+    }
+
+    #[test]
+    fn wait_blocks_until_the_fence_is_signaled_and_signal_raises_an_interrupt() {
+        let mut rig = Rig::new();
+        let (gate, gate_fence) = rig.cp.create_fence();
+        let (done, done_fence) = rig.cp.create_fence();
+        rig.submit(&[GPUCommand::Wait { fence: gate, value: 2 }, GPUCommand::Signal { fence: done, value: 7, interrupt: true }]);
+
+        rig.run().unwrap();
+        assert_eq!(rig.cp.read_register(CP_STATUS).unwrap() & STATUS_WAITING, STATUS_WAITING);
+        gate_fence.signal(1);
+        rig.run().unwrap();
+        assert_eq!(done_fence.value(), 0);
+        assert!(!rig.cp.interrupt_pending());
+
+        gate_fence.signal(2);
+        rig.run().unwrap();
+        assert_eq!(done_fence.value(), 7);
+        assert_eq!(rig.cp.read_register(CP_STATUS), Ok(0));
+        assert_eq!(rig.cp.read_register(CP_INT_STATUS), Ok(INT_FENCE));
+        rig.cp.write_register(CP_INT_STATUS, INT_FENCE).unwrap();
+        assert!(!rig.cp.interrupt_pending());
+    }
+
+    #[test]
+    fn dispatch_submits_the_group_count_at_the_context_priority() {
+        let mut rig = Rig::new();
+        rig.submit(&[
+            set_state(STATE_PRIORITY, 2),
+            GPUCommand::Dispatch { workload_type: WorkloadType::Tensor, group_count: [4, 2, 3] },
+        ]);
+        rig.run().unwrap();
+
+        let workload = rig.dispatcher.queued().next().unwrap();
+        assert_eq!((workload.workload_type, workload.priority, workload.work_items), (WorkloadType::Tensor, Priority::High, 24));
+        assert_eq!(rig.cp.get_stats().dispatches, 1);
+    }
+
+    #[test]
+    fn overflowing_group_counts_fault_the_packet() {
+        let mut rig = Rig::new();
+        let dispatch = GPUCommand::Dispatch { workload_type: WorkloadType::Shader, group_count: [u32::MAX; 3] };
+        rig.submit(std::slice::from_ref(&dispatch));
+
+        assert_eq!(rig.run(), Err(GPUError::InvalidPacket(dispatch.header())));
+        assert_eq!(rig.cp.read_register(CP_STATUS).unwrap() & STATUS_HALTED, STATUS_HALTED);
+        assert_eq!(rig.cp.read_register(CP_INT_STATUS).unwrap() & INT_ERROR, INT_ERROR);
+        assert_eq!(rig.dispatcher.queued().count(), 0);
+    }
+
+    #[test]
+    fn copies_move_data_in_chunks_and_handle_overlap() {
+        let mut rig = Rig::new();
+        let size = COPY_CHUNK_BYTES * 2 + 100;
+        let pattern: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        rig.memory.write_physical(0x4000, &pattern).unwrap();
+
+        // The destination overlaps the source's tail, so the copy must run backwards
+        rig.submit(&[GPUCommand::Copy { src: 0x4000, dst: 0x4000 + 1000, size }]);
+        rig.run().unwrap();
+        let mut copied = vec![0u8; size as usize];
+        rig.memory.read_physical(0x4000 + 1000, &mut copied).unwrap();
+        assert_eq!(copied, pattern);
+        assert_eq!(rig.cp.get_stats().bytes_copied, size);
+    }
+
+    #[test]
+    fn copies_outside_memory_fault_without_allocating() {
+        let mut rig = Rig::new();
+        let copy = GPUCommand::Copy { src: 0, dst: 0x4000, size: u64::MAX };
+        rig.submit(std::slice::from_ref(&copy));
+        assert_eq!(rig.run(), Err(GPUError::InvalidPacket(copy.header())));
+        assert_eq!(rig.cp.get_stats().bytes_copied, 0);
+    }
+}
@@ -3,9 +3,6 @@ pub mod ray_core;
 pub mod shader_core;
 pub mod tensor_core;
 
-use super::error::{GPUError, GPUResult};
-use super::memory::GPUMemory;
-
 pub use self::shader_core::ShaderCore;
 pub use self::ray_core::RayCore;
 pub use self::tensor_core::TensorCore;
@@ -79,23 +76,138 @@ impl Vec3 {
             z: self.z / len,
         }
     }
+
+    pub fn add(&self, other: &Vec3) -> Vec3 {
+        Vec3::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+
+    pub fn sub(&self, other: &Vec3) -> Vec3 {
+        Vec3::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+
+    pub fn scale(&self, factor: f32) -> Vec3 {
+        Vec3::new(self.x * factor, self.y * factor, self.z * factor)
+    }
+
+    pub fn length(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+}
+
+impl Vec4 {
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        Self { x, y, z, w }
+    }
+
+    pub fn from_vec3(v: &Vec3, w: f32) -> Self {
+        Self { x: v.x, y: v.y, z: v.z, w }
+    }
+
+    pub fn xyz(&self) -> Vec3 {
+        Vec3::new(self.x, self.y, self.z)
+    }
+
+    pub fn dot(&self, other: &Vec4) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z + self.w * other.w
+    }
+
+    pub fn lerp(&self, other: &Vec4, t: f32) -> Vec4 {
+        Vec4 {
+            x: self.x + (other.x - self.x) * t,
+            y: self.y + (other.y - self.y) * t,
+            z: self.z + (other.z - self.z) * t,
+            w: self.w + (other.w - self.w) * t,
+        }
+    }
 }
 
 impl Mat4 {
     pub fn identity() -> Self {
         let mut data = [[0.0; 4]; 4];
-        for i in 0..4 {
-            data[i][i] = 1.0;
+        for (i, row) in data.iter_mut().enumerate() {
+            row[i] = 1.0;
         }
         Self { data }
     }
 
+    pub fn translation(x: f32, y: f32, z: f32) -> Self {
+        let mut m = Self::identity();
+        m.data[0][3] = x;
+        m.data[1][3] = y;
+        m.data[2][3] = z;
+        m
+    }
+
+    pub fn scale(x: f32, y: f32, z: f32) -> Self {
+        let mut m = Self::identity();
+        m.data[0][0] = x;
+        m.data[1][1] = y;
+        m.data[2][2] = z;
+        m
+    }
+
+    pub fn rotation_x(angle: f32) -> Self {
+        let (s, c) = angle.sin_cos();
+        let mut m = Self::identity();
+        m.data[1][1] = c;
+        m.data[1][2] = -s;
+        m.data[2][1] = s;
+        m.data[2][2] = c;
+        m
+    }
+
+    pub fn rotation_y(angle: f32) -> Self {
+        let (s, c) = angle.sin_cos();
+        let mut m = Self::identity();
+        m.data[0][0] = c;
+        m.data[0][2] = s;
+        m.data[2][0] = -s;
+        m.data[2][2] = c;
+        m
+    }
+
+    pub fn rotation_z(angle: f32) -> Self {
+        let (s, c) = angle.sin_cos();
+        let mut m = Self::identity();
+        m.data[0][0] = c;
+        m.data[0][1] = -s;
+        m.data[1][0] = s;
+        m.data[1][1] = c;
+        m
+    }
+
+    // OpenGL-style projection: right handed, camera looks down -z, depth maps to [-1, 1]
+    pub fn perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> Self {
+        let f = 1.0 / (fov_y / 2.0).tan();
+        let mut data = [[0.0; 4]; 4];
+        data[0][0] = f / aspect;
+        data[1][1] = f;
+        data[2][2] = (far + near) / (near - far);
+        data[2][3] = 2.0 * far * near / (near - far);
+        data[3][2] = -1.0;
+        Self { data }
+    }
+
+    pub fn look_at(eye: &Vec3, target: &Vec3, up: &Vec3) -> Self {
+        let forward = target.sub(eye).normalize();
+        let side = forward.cross(up).normalize();
+        let up = side.cross(&forward);
+        Self {
+            data: [
+                [side.x, side.y, side.z, -side.dot(eye)],
+                [up.x, up.y, up.z, -up.dot(eye)],
+                [-forward.x, -forward.y, -forward.z, forward.dot(eye)],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
     pub fn multiply(&self, other: &Mat4) -> Mat4 {
         let mut result = [[0.0; 4]; 4];
-        for i in 0..4 {
-            for j in 0..4 {
+        for (i, row) in result.iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
                 for k in 0..4 {
-                    result[i][j] += self.data[i][k] * other.data[k][j];
+                    *cell += self.data[i][k] * other.data[k][j];
                 }
             }
         }
@@ -1,30 +1,109 @@
 use super::super::error::{GPUError, GPUResult};
+use super::super::display::Framebuffer;
+use super::Vec3;
+
+// Binned SAH: split candidates per axis, and the relative cost of a box test
+// against a triangle test
+const SAH_BINS: usize = 12;
+const TRAVERSAL_COST: f32 = 1.0;
+const INTERSECTION_COST: f32 = 2.0;
+const MAX_LEAF_PRIMITIVES: usize = 8;
+
+// Fixed-function unit latencies, in cycles
+const BOX_TEST_CYCLES: u64 = 1;
+const TRIANGLE_TEST_CYCLES: u64 = 4;
 
 pub struct RayCore {
     id: u32,
     state: CoreState,
     bvh: Option<BVHTree>,
-    rays: Vec<Ray>,
     stats: RayStats,
 }
 
-struct Ray {
-    origin: Vector3,
-    direction: Vector3,
-    t_min: f32,
-    t_max: f32,
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CoreState {
+    Idle,
+    Active,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+    pub t_min: f32,
+    pub t_max: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Intersection {
+    pub t: f32,
+    pub u: f32,
+    pub v: f32,
+    pub primitive: usize,
+    pub normal: Vec3, // Geometric, facing against the ray
+}
+
+// Per-ray traversal cost, and the nodes the ray touched in visit order
+#[derive(Debug, Clone, Default)]
+pub struct RayTrace {
+    pub hit: Option<Intersection>,
+    pub node_tests: u32,
+    pub triangle_tests: u32,
+    pub visited_nodes: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Material {
+    pub albedo: Vec3,
+    pub emission: Vec3,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Primitive {
+    pub vertices: [Vec3; 3],
+    pub material: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Scene {
+    primitives: Vec<Primitive>,
+    materials: Vec<Material>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AABB {
+    pub min: Vec3,
+    pub max: Vec3,
 }
 
+// Nodes are stored depth first, so an interior node's left child follows it.
+// `escape` is the next node to visit once this subtree is done or missed,
+// which lets traversal run without a stack.
 struct BVHTree {
     nodes: Vec<BVHNode>,
     primitives: Vec<Primitive>,
+    materials: Vec<Material>,
 }
 
-struct BVHNode {
-    bounds: AABB,
-    left: Option<usize>,
-    right: Option<usize>,
-    primitive_index: Option<usize>,
+#[derive(Debug, Clone)]
+pub struct BVHNode {
+    pub bounds: AABB,
+    pub left: Option<usize>,
+    pub right: Option<usize>,
+    pub escape: usize,
+    pub first_primitive: usize,
+    pub primitive_count: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RayStats {
+    pub rays_started: usize,
+    pub rays_hit: usize,
+    pub node_tests: u64,
+    pub triangle_tests: u64,
+    pub cycles: u64,
+    pub bvh_nodes: usize,
+    pub bvh_depth: usize,
 }
 
 impl RayCore {
@@ -33,54 +112,736 @@ impl RayCore {
             id,
             state: CoreState::Idle,
             bvh: None,
-            rays: Vec::new(),
             stats: RayStats::default(),
         }
     }
 
-    pub fn trace_rays(&mut self, rays: Vec<Ray>) -> GPUResult<Vec<Intersection>> {
+    // Builds the acceleration structure; primitives are reordered into leaf order
+    pub fn load_scene(&mut self, scene: Scene) -> GPUResult<()> {
+        if scene.primitives.is_empty() {
+            return Err(GPUError::InvalidProgram("scene has no primitives".to_string()));
+        }
+        if scene.primitives.iter().any(|primitive| primitive.material >= scene.materials.len()) {
+            return Err(GPUError::InvalidProgram("primitive references a missing material".to_string()));
+        }
+
+        let bvh = BVHTree::build(scene);
+        self.stats.bvh_nodes = bvh.nodes.len();
+        self.stats.bvh_depth = bvh.depth(0);
+        self.bvh = Some(bvh);
+        Ok(())
+    }
+
+    // One result per ray, in order
+    pub fn trace_rays(&mut self, rays: Vec<Ray>) -> GPUResult<Vec<Option<Intersection>>> {
+        if self.bvh.is_none() {
+            return Err(GPUError::InvalidProgram("no scene loaded".to_string()));
+        }
         self.state = CoreState::Active;
-        self.rays = rays;
-        self.stats.rays_started += self.rays.len();
-
-        let mut intersections = Vec::new();
-        for ray in &self.rays {
-            if let Some(hit) = self.trace_ray(ray) {
-                intersections.push(hit);
-                self.stats.rays_hit += 1;
+        self.stats.rays_started += rays.len();
+
+        let intersections = rays.iter().map(|ray| self.trace_ray(ray, false).hit).collect();
+        self.state = CoreState::Idle;
+        Ok(intersections)
+    }
+
+    // Methods for visualization system
+    pub fn trace_debug(&mut self, ray: &Ray) -> GPUResult<RayTrace> {
+        if self.bvh.is_none() {
+            return Err(GPUError::InvalidProgram("no scene loaded".to_string()));
+        }
+        self.stats.rays_started += 1;
+        Ok(self.trace_ray(ray, true))
+    }
+
+    pub fn bvh_nodes(&self) -> &[BVHNode] {
+        self.bvh.as_ref().map_or(&[], |bvh| &bvh.nodes)
+    }
+
+    pub fn primitive_count(&self) -> usize {
+        self.bvh.as_ref().map_or(0, |bvh| bvh.primitives.len())
+    }
+
+    pub fn primitive(&self, index: usize) -> Option<&Primitive> {
+        self.bvh.as_ref().and_then(|bvh| bvh.primitives.get(index))
+    }
+
+    pub fn material(&self, primitive: usize) -> Option<&Material> {
+        let bvh = self.bvh.as_ref()?;
+        bvh.primitives.get(primitive).map(|primitive| &bvh.materials[primitive.material])
+    }
+
+    pub fn state(&self) -> CoreState {
+        self.state
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn get_stats(&self) -> &RayStats {
+        &self.stats
+    }
+
+    fn trace_ray(&mut self, ray: &Ray, record: bool) -> RayTrace {
+        let trace = match &self.bvh {
+            Some(bvh) => bvh.traverse(ray, record),
+            None => RayTrace::default(),
+        };
+        if trace.hit.is_some() {
+            self.stats.rays_hit += 1;
+        }
+        self.stats.node_tests += trace.node_tests as u64;
+        self.stats.triangle_tests += trace.triangle_tests as u64;
+        self.stats.cycles += trace.node_tests as u64 * BOX_TEST_CYCLES + trace.triangle_tests as u64 * TRIANGLE_TEST_CYCLES;
+        trace
+    }
+}
+
+impl BVHTree {
+    fn build(scene: Scene) -> Self {
+        let Scene { primitives, materials } = scene;
+        let bounds: Vec<AABB> = primitives.iter().map(Primitive::bounds).collect();
+        let centroids: Vec<Vec3> = bounds.iter().map(AABB::center).collect();
+        let mut order: Vec<usize> = (0..primitives.len()).collect();
+        let mut nodes = Vec::with_capacity(primitives.len() * 2);
+
+        Self::build_node(&mut nodes, &mut order, 0, &bounds, &centroids);
+        let primitives = order.iter().map(|&index| primitives[index]).collect();
+        Self { nodes, primitives, materials }
+    }
+
+    fn build_node(nodes: &mut Vec<BVHNode>, order: &mut [usize], first: usize, bounds: &[AABB], centroids: &[Vec3]) -> usize {
+        let index = nodes.len();
+        let node_bounds = order.iter().fold(AABB::empty(), |acc, &i| acc.union(&bounds[i]));
+        nodes.push(BVHNode {
+            bounds: node_bounds,
+            left: None,
+            right: None,
+            escape: 0,
+            first_primitive: first,
+            primitive_count: order.len(),
+        });
+
+        if let Some(split) = Self::find_split(order, &node_bounds, bounds, centroids) {
+            let (left, right) = order.split_at_mut(split);
+            let left_index = Self::build_node(nodes, left, first, bounds, centroids);
+            let right_index = Self::build_node(nodes, right, first + split, bounds, centroids);
+            let node = &mut nodes[index];
+            node.left = Some(left_index);
+            node.right = Some(right_index);
+            node.primitive_count = 0;
+        }
+        nodes[index].escape = nodes.len();
+        index
+    }
+
+    // Bins centroids along each axis and takes the cheapest SAH split, or None
+    // when keeping a leaf is cheaper. Partitions `order` in place.
+    fn find_split(order: &mut [usize], node_bounds: &AABB, bounds: &[AABB], centroids: &[Vec3]) -> Option<usize> {
+        if order.len() <= 1 {
+            return None;
+        }
+        let centroid_bounds = order.iter().fold(AABB::empty(), |acc, &i| acc.union(&AABB { min: centroids[i], max: centroids[i] }));
+        let leaf_cost = order.len() as f32 * INTERSECTION_COST;
+        let parent_area = node_bounds.surface_area().max(f32::EPSILON);
+
+        let mut best: Option<(f32, usize, usize)> = None; // cost, axis, bins to the left
+        for axis in 0..3 {
+            let (low, high) = (centroid_bounds.min.axis(axis), centroid_bounds.max.axis(axis));
+            if high - low <= f32::EPSILON {
+                continue;
+            }
+            let bin_of = |i: usize| (((centroids[i].axis(axis) - low) / (high - low) * SAH_BINS as f32) as usize).min(SAH_BINS - 1);
+
+            let mut bin_bounds = [AABB::empty(); SAH_BINS];
+            let mut bin_counts = [0usize; SAH_BINS];
+            for &i in order.iter() {
+                let bin = bin_of(i);
+                bin_counts[bin] += 1;
+                bin_bounds[bin] = bin_bounds[bin].union(&bounds[i]);
+            }
+
+            // Sweep from the right to get suffix areas, then from the left
+            let mut right_area = [0.0f32; SAH_BINS];
+            let mut right_count = [0usize; SAH_BINS];
+            let (mut acc, mut count) = (AABB::empty(), 0);
+            for bin in (1..SAH_BINS).rev() {
+                acc = acc.union(&bin_bounds[bin]);
+                count += bin_counts[bin];
+                right_area[bin] = acc.surface_area();
+                right_count[bin] = count;
+            }
+            let (mut acc, mut count) = (AABB::empty(), 0);
+            for split in 1..SAH_BINS {
+                acc = acc.union(&bin_bounds[split - 1]);
+                count += bin_counts[split - 1];
+                if count == 0 || right_count[split] == 0 {
+                    continue;
+                }
+                let cost = TRAVERSAL_COST
+                    + INTERSECTION_COST * (acc.surface_area() * count as f32 + right_area[split] * right_count[split] as f32) / parent_area;
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, split));
+                }
             }
         }
 
-        Ok(intersections)
+        match best {
+            Some((cost, axis, split)) if cost < leaf_cost || order.len() > MAX_LEAF_PRIMITIVES => {
+                let (low, high) = (centroid_bounds.min.axis(axis), centroid_bounds.max.axis(axis));
+                let bin_of = |i: usize| (((centroids[i].axis(axis) - low) / (high - low) * SAH_BINS as f32) as usize).min(SAH_BINS - 1);
+                order.sort_by_key(|&i| bin_of(i) >= split);
+                Some(order.iter().filter(|&&i| bin_of(i) < split).count())
+            }
+            // Every centroid coincides: split down the middle so leaves stay small
+            None if order.len() > MAX_LEAF_PRIMITIVES => Some(order.len() / 2),
+            _ => None,
+        }
+    }
+
+    fn traverse(&self, ray: &Ray, record: bool) -> RayTrace {
+        let mut trace = RayTrace::default();
+        let inverse = Vec3::new(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+        let mut closest = ray.t_max;
+
+        let mut index = 0;
+        while index < self.nodes.len() {
+            let node = &self.nodes[index];
+            trace.node_tests += 1;
+            if record {
+                trace.visited_nodes.push(index);
+            }
+
+            if !node.bounds.intersect(ray, &inverse, closest) {
+                index = node.escape;
+                continue;
+            }
+            if node.left.is_some() {
+                index += 1;
+                continue;
+            }
+
+            // Leaf node - test primitive intersections
+            for offset in 0..node.primitive_count {
+                let primitive = node.first_primitive + offset;
+                trace.triangle_tests += 1;
+                if let Some(mut hit) = self.primitives[primitive].intersect(ray, closest) {
+                    hit.primitive = primitive;
+                    closest = hit.t;
+                    trace.hit = Some(hit);
+                }
+            }
+            index = node.escape;
+        }
+        trace
     }
 
-    fn trace_ray(&self, ray: &Ray) -> Option<Intersection> {
-        if let Some(bvh) = &self.bvh {
-            self.traverse_bvh(ray, &bvh.nodes[0])
-        } else {
-            None
+    fn depth(&self, index: usize) -> usize {
+        let node = &self.nodes[index];
+        match (node.left, node.right) {
+            (Some(left), Some(right)) => 1 + self.depth(left).max(self.depth(right)),
+            _ => 1,
         }
     }
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize(),
+            t_min: 1e-4,
+            t_max: f32::INFINITY,
+        }
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin.add(&self.direction.scale(t))
+    }
+}
+
+impl Primitive {
+    fn bounds(&self) -> AABB {
+        self.vertices.iter().fold(AABB::empty(), |acc, vertex| acc.union(&AABB { min: *vertex, max: *vertex }))
+    }
+
+    pub fn area(&self) -> f32 {
+        let [v0, v1, v2] = self.vertices;
+        v1.sub(&v0).cross(&v2.sub(&v0)).length() / 2.0
+    }
+
+    // Uniform over the triangle, for light sampling
+    fn sample(&self, r1: f32, r2: f32) -> Vec3 {
+        let [v0, v1, v2] = self.vertices;
+        let root = r1.sqrt();
+        v0.scale(1.0 - root).add(&v1.scale(root * (1.0 - r2))).add(&v2.scale(root * r2))
+    }
 
-    fn traverse_bvh(&self, ray: &Ray, node: &BVHNode) -> Option<Intersection> {
-        if !node.bounds.intersect(ray) {
+    // Moller-Trumbore
+    fn intersect(&self, ray: &Ray, t_max: f32) -> Option<Intersection> {
+        let [v0, v1, v2] = self.vertices;
+        let (edge1, edge2) = (v1.sub(&v0), v2.sub(&v0));
+        let p = ray.direction.cross(&edge2);
+        let determinant = edge1.dot(&p);
+        if determinant.abs() < 1e-9 {
+            return None;
+        }
+        let inverse = 1.0 / determinant;
+        let s = ray.origin.sub(&v0);
+        let u = s.dot(&p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(&edge1);
+        let v = ray.direction.dot(&q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(&q) * inverse;
+        if t < ray.t_min || t >= t_max {
             return None;
         }
 
-        // Leaf node - test primitive intersection
-        if let Some(prim_idx) = node.primitive_index {
-            return self.intersect_primitive(ray, &self.bvh.as_ref().unwrap().primitives[prim_idx]);
+        let normal = edge1.cross(&edge2).normalize();
+        let normal = if normal.dot(&ray.direction) > 0.0 { normal.scale(-1.0) } else { normal };
+        Some(Intersection { t, u, v, primitive: 0, normal })
+    }
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_material(&mut self, material: Material) -> usize {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    pub fn add_triangle(&mut self, vertices: [Vec3; 3], material: usize) {
+        self.primitives.push(Primitive { vertices, material });
+    }
+
+    // An indexed triangle mesh, three indices per triangle
+    pub fn add_mesh(&mut self, positions: &[Vec3], indices: &[u32], material: usize) -> GPUResult<()> {
+        for triangle in indices.chunks_exact(3) {
+            let vertex = |index: u32| positions.get(index as usize).copied().ok_or(GPUError::OutOfBounds);
+            self.add_triangle([vertex(triangle[0])?, vertex(triangle[1])?, vertex(triangle[2])?], material);
+        }
+        Ok(())
+    }
+
+    pub fn add_quad(&mut self, corners: [Vec3; 4], material: usize) {
+        self.add_triangle([corners[0], corners[1], corners[2]], material);
+        self.add_triangle([corners[0], corners[2], corners[3]], material);
+    }
+
+    pub fn add_box(&mut self, min: Vec3, max: Vec3, material: usize) {
+        let corner = |x: bool, y: bool, z: bool| Vec3::new(
+            if x { max.x } else { min.x },
+            if y { max.y } else { min.y },
+            if z { max.z } else { min.z },
+        );
+        let positions: Vec<Vec3> = (0..8).map(|i| corner(i & 1 != 0, i & 2 != 0, i & 4 != 0)).collect();
+        let indices = [
+            0, 2, 3, 0, 3, 1, 4, 5, 7, 4, 7, 6, // -z, +z
+            0, 1, 5, 0, 5, 4, 2, 6, 7, 2, 7, 3, // -y, +y
+            0, 4, 6, 0, 6, 2, 1, 3, 7, 1, 7, 5, // -x, +x
+        ];
+        self.add_mesh(&positions, &indices, material).expect("box indices are in range");
+    }
+
+    // A Cornell box: red and green side walls, a ceiling light and two blocks
+    pub fn cornell_box() -> Self {
+        let mut scene = Scene::new();
+        let white = scene.add_material(Material { albedo: Vec3::new(0.73, 0.73, 0.73), emission: Vec3::new(0.0, 0.0, 0.0) });
+        let red = scene.add_material(Material { albedo: Vec3::new(0.65, 0.05, 0.05), emission: Vec3::new(0.0, 0.0, 0.0) });
+        let green = scene.add_material(Material { albedo: Vec3::new(0.12, 0.45, 0.15), emission: Vec3::new(0.0, 0.0, 0.0) });
+        let light = scene.add_material(Material { albedo: Vec3::new(0.0, 0.0, 0.0), emission: Vec3::new(15.0, 15.0, 15.0) });
+
+        let p = |x: f32, y: f32, z: f32| Vec3::new(x, y, z);
+        scene.add_quad([p(-1.0, -1.0, -1.0), p(1.0, -1.0, -1.0), p(1.0, -1.0, 1.0), p(-1.0, -1.0, 1.0)], white); // floor
+        scene.add_quad([p(-1.0, 1.0, -1.0), p(-1.0, 1.0, 1.0), p(1.0, 1.0, 1.0), p(1.0, 1.0, -1.0)], white); // ceiling
+        scene.add_quad([p(-1.0, -1.0, -1.0), p(-1.0, 1.0, -1.0), p(1.0, 1.0, -1.0), p(1.0, -1.0, -1.0)], white); // back
+        scene.add_quad([p(-1.0, -1.0, -1.0), p(-1.0, -1.0, 1.0), p(-1.0, 1.0, 1.0), p(-1.0, 1.0, -1.0)], red);
+        scene.add_quad([p(1.0, -1.0, -1.0), p(1.0, 1.0, -1.0), p(1.0, 1.0, 1.0), p(1.0, -1.0, 1.0)], green);
+        scene.add_quad([p(-0.3, 0.99, -0.3), p(0.3, 0.99, -0.3), p(0.3, 0.99, 0.3), p(-0.3, 0.99, 0.3)], light);
+        scene.add_box(p(-0.6, -1.0, -0.6), p(-0.1, 0.2, -0.1), white);
+        scene.add_box(p(0.1, -1.0, 0.0), p(0.6, -0.4, 0.5), white);
+        scene
+    }
+
+    pub fn primitive_count(&self) -> usize {
+        self.primitives.len()
+    }
+}
+
+impl AABB {
+    pub fn empty() -> Self {
+        Self {
+            min: Vec3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Vec3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn union(&self, other: &AABB) -> AABB {
+        AABB {
+            min: Vec3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            max: Vec3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        self.min.add(&self.max).scale(0.5)
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let extent = self.max.sub(&self.min);
+        if extent.x < 0.0 {
+            return 0.0;
+        }
+        2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+    }
+
+    // Slab test, clipped to the closest hit found so far
+    pub fn intersect(&self, ray: &Ray, inverse: &Vec3, t_max: f32) -> bool {
+        let (mut near, mut far) = (ray.t_min, t_max);
+        for axis in 0..3 {
+            let t0 = (self.min.axis(axis) - ray.origin.axis(axis)) * inverse.axis(axis);
+            let t1 = (self.max.axis(axis) - ray.origin.axis(axis)) * inverse.axis(axis);
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
         }
+        near <= far
+    }
+}
 
-        // Internal node - recurse
-        let left_hit = node.left.map(|idx| self.traverse_bvh(ray, &self.bvh.as_ref().unwrap().nodes[idx])).flatten();
-        let right_hit = node.right.map(|idx| self.traverse_bvh(ray, &self.bvh.as_ref().unwrap().nodes[idx])).flatten();
+impl Vec3 {
+    fn axis(&self, axis: usize) -> f32 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+}
 
-        // Return closest intersection
-        match (left_hit, right_hit) {
-            (Some(l), Some(r)) => Some(if l.t < r.t { l } else { r }),
-            (Some(h), None) | (None, Some(h)) => Some(h),
-            (None, None) => None,
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    position: Vec3,
+    forward: Vec3,
+    right: Vec3,
+    up: Vec3,
+    tan_half_fov: f32,
+}
+
+impl Camera {
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3, fov_y: f32) -> Self {
+        let forward = target.sub(&eye).normalize();
+        let right = forward.cross(&up).normalize();
+        Self {
+            position: eye,
+            forward,
+            right,
+            up: right.cross(&forward),
+            tan_half_fov: (fov_y / 2.0).tan(),
         }
     }
-}
\ No newline at end of file
+
+    // (x, y) in pixels, y down; fractional parts jitter within the pixel
+    pub fn ray(&self, x: f32, y: f32, width: u32, height: u32) -> Ray {
+        let aspect = width as f32 / height as f32;
+        let sx = (2.0 * x / width as f32 - 1.0) * self.tan_half_fov * aspect;
+        let sy = (1.0 - 2.0 * y / height as f32) * self.tan_half_fov;
+        let direction = self.forward.add(&self.right.scale(sx)).add(&self.up.scale(sy));
+        Ray::new(self.position, direction)
+    }
+}
+
+// Wavefront path tracer: every live path's next ray, and its shadow ray toward a
+// sampled light, go to the RayCore as batches per bounce, the way a GPU keeps
+// its traversal units busy
+pub struct PathTracer {
+    camera: Camera,
+    samples_per_pixel: u32,
+    max_bounces: u32,
+    seed: u64,
+}
+
+struct PathState {
+    pixel: usize,
+    ray: Ray,
+    throughput: Vec3,
+    bounce: u32,
+}
+
+impl PathTracer {
+    pub fn new(camera: Camera, samples_per_pixel: u32, max_bounces: u32) -> Self {
+        Self { camera, samples_per_pixel, max_bounces, seed: 0x853c49e6748fea9b }
+    }
+
+    // The ray through a pixel's center, for picking in the visualization
+    pub fn pixel_ray(&self, x: u32, y: u32, width: u32, height: u32) -> Ray {
+        self.camera.ray(x as f32 + 0.5, y as f32 + 0.5, width, height)
+    }
+
+    pub fn render(&self, core: &mut RayCore, framebuffer: &mut Framebuffer) -> GPUResult<()> {
+        let (width, height) = (framebuffer.width(), framebuffer.height());
+        let mut radiance = vec![Vec3::new(0.0, 0.0, 0.0); (width * height) as usize];
+        let mut rng = self.seed;
+
+        let lights: Vec<usize> = (0..core.primitive_count())
+            .filter(|&index| core.material(index).is_some_and(|material| material.emission.length() > 0.0))
+            .collect();
+
+        for _ in 0..self.samples_per_pixel {
+            let mut paths: Vec<PathState> = (0..width * height).map(|pixel| {
+                let (x, y) = (pixel % width, pixel / width);
+                let ray = self.camera.ray(x as f32 + random(&mut rng), y as f32 + random(&mut rng), width, height);
+                PathState { pixel: pixel as usize, ray, throughput: Vec3::new(1.0, 1.0, 1.0), bounce: 0 }
+            }).collect();
+
+            for _ in 0..=self.max_bounces {
+                if paths.is_empty() {
+                    break;
+                }
+                let hits = core.trace_rays(paths.iter().map(|path| path.ray).collect())?;
+
+                let mut next = Vec::with_capacity(paths.len());
+                let mut shadow_rays = Vec::new();
+                let mut shadow_contributions = Vec::new();
+                for (mut path, hit) in paths.into_iter().zip(hits) {
+                    let Some(hit) = hit else { continue };
+                    let material = *core.material(hit.primitive).ok_or(GPUError::OutOfBounds)?;
+                    // Later bounces already counted emitters through the light samples
+                    if path.bounce == 0 {
+                        radiance[path.pixel] = radiance[path.pixel].add(&multiply(&path.throughput, &material.emission));
+                    }
+                    if material.albedo.length() == 0.0 {
+                        continue;
+                    }
+                    let origin = path.ray.at(hit.t).add(&hit.normal.scale(1e-4));
+
+                    // Next event estimation: one shadow ray toward a random point on a random light
+                    if !lights.is_empty() {
+                        let index = lights[((random(&mut rng) * lights.len() as f32) as usize).min(lights.len() - 1)];
+                        let light = *core.primitive(index).ok_or(GPUError::OutOfBounds)?;
+                        let emission = core.material(index).ok_or(GPUError::OutOfBounds)?.emission;
+                        let point = light.sample(random(&mut rng), random(&mut rng));
+                        let offset = point.sub(&origin);
+                        let distance = offset.length();
+                        let direction = offset.scale(1.0 / distance);
+                        let light_normal = light.vertices[1].sub(&light.vertices[0]).cross(&light.vertices[2].sub(&light.vertices[0])).normalize();
+                        let (cos_surface, cos_light) = (hit.normal.dot(&direction), light_normal.dot(&direction).abs());
+                        if cos_surface > 0.0 {
+                            let weight = cos_surface * cos_light * light.area() * lights.len() as f32
+                                / (std::f32::consts::PI * distance * distance);
+                            let contribution = multiply(&multiply(&path.throughput, &material.albedo), &emission).scale(weight);
+                            let mut ray = Ray::new(origin, direction);
+                            ray.t_max = distance * (1.0 - 1e-3);
+                            shadow_rays.push(ray);
+                            shadow_contributions.push((path.pixel, contribution));
+                        }
+                    }
+
+                    // Cosine-weighted bounce: the cosine and pdf cancel, leaving the albedo
+                    path.throughput = multiply(&path.throughput, &material.albedo);
+                    path.ray = Ray::new(origin, cosine_sample(&hit.normal, &mut rng));
+                    path.bounce += 1;
+                    next.push(path);
+                }
+
+                let occluded = core.trace_rays(shadow_rays)?;
+                for ((pixel, contribution), blocker) in shadow_contributions.into_iter().zip(occluded) {
+                    if blocker.is_none() {
+                        radiance[pixel] = radiance[pixel].add(&contribution);
+                    }
+                }
+                paths = next;
+            }
+        }
+
+        // Average, tone map and gamma encode
+        let samples = self.samples_per_pixel.max(1) as f32;
+        for (pixel, value) in radiance.iter().enumerate() {
+            let encode = |channel: f32| {
+                let mapped = channel / samples;
+                ((mapped / (1.0 + mapped)).powf(1.0 / 2.2) * 255.0).round() as u8
+            };
+            let (x, y) = (pixel as u32 % width, pixel as u32 / width);
+            framebuffer.write_pixel(x, y, [encode(value.x), encode(value.y), encode(value.z), 255])?;
+        }
+        Ok(())
+    }
+}
+
+fn multiply(a: &Vec3, b: &Vec3) -> Vec3 {
+    Vec3::new(a.x * b.x, a.y * b.y, a.z * b.z)
+}
+
+fn random(state: &mut u64) -> f32 {
+    *state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+    (*state >> 40) as f32 / (1u64 << 24) as f32
+}
+
+fn cosine_sample(normal: &Vec3, rng: &mut u64) -> Vec3 {
+    let (r1, r2) = (random(rng), random(rng));
+    let phi = 2.0 * std::f32::consts::PI * r1;
+    let radius = r2.sqrt();
+    let helper = if normal.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+    let tangent = helper.cross(normal).normalize();
+    let bitangent = normal.cross(&tangent);
+    tangent.scale(radius * phi.cos())
+        .add(&bitangent.scale(radius * phi.sin()))
+        .add(&normal.scale((1.0 - r2).sqrt()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matte() -> Material {
+        Material { albedo: Vec3::new(0.5, 0.5, 0.5), emission: Vec3::new(0.0, 0.0, 0.0) }
+    }
+
+    // Small triangles scattered through a 20-unit cube
+    fn scattered_scene(count: usize, rng: &mut u64) -> Scene {
+        let mut scene = Scene::new();
+        let material = scene.add_material(matte());
+        for _ in 0..count {
+            let center = Vec3::new(random(rng) * 20.0 - 10.0, random(rng) * 20.0 - 10.0, random(rng) * 20.0 - 10.0);
+            let mut corner = || center.add(&Vec3::new(random(rng) - 0.5, random(rng) - 0.5, random(rng) - 0.5));
+            let vertices = [corner(), corner(), corner()];
+            scene.add_triangle(vertices, material);
+        }
+        scene
+    }
+
+    fn loaded(scene: Scene) -> RayCore {
+        let mut core = RayCore::new(0);
+        core.load_scene(scene).unwrap();
+        core
+    }
+
+    fn brute_force(core: &RayCore, ray: &Ray) -> Option<(usize, f32)> {
+        let mut closest: Option<(usize, f32)> = None;
+        for index in 0..core.primitive_count() {
+            let t_max = closest.map_or(ray.t_max, |(_, t)| t);
+            if let Some(hit) = core.primitive(index).unwrap().intersect(ray, t_max) {
+                closest = Some((index, hit.t));
+            }
+        }
+        closest
+    }
+
+    #[test]
+    fn traversal_agrees_with_brute_force() {
+        let mut rng = 0x2545f4914f6cdd1d;
+        let mut core = loaded(scattered_scene(300, &mut rng));
+
+        let rays: Vec<Ray> = (0..500).map(|_| {
+            let origin = Vec3::new(random(&mut rng) * 30.0 - 15.0, random(&mut rng) * 30.0 - 15.0, 15.0);
+            let target = Vec3::new(random(&mut rng) * 20.0 - 10.0, random(&mut rng) * 20.0 - 10.0, random(&mut rng) * 20.0 - 10.0);
+            Ray::new(origin, target.sub(&origin))
+        }).collect();
+        let hits = core.trace_rays(rays.clone()).unwrap();
+
+        let mut hit_count = 0;
+        for (ray, hit) in rays.iter().zip(hits) {
+            match (brute_force(&core, ray), hit) {
+                (Some((primitive, t)), Some(hit)) => {
+                    assert_eq!(hit.primitive, primitive);
+                    assert!((hit.t - t).abs() < 1e-5);
+                    hit_count += 1;
+                }
+                (None, None) => {}
+                (expected, actual) => panic!("brute force {:?}, BVH {:?}", expected, actual.map(|hit| hit.primitive)),
+            }
+        }
+        assert!(hit_count > 0);
+        assert_eq!(core.get_stats().rays_hit, hit_count);
+    }
+
+    #[test]
+    fn nearest_hit_wins_and_misses_return_none() {
+        let mut scene = Scene::new();
+        let material = scene.add_material(matte());
+        let wall = |z: f32| [Vec3::new(-1.0, -1.0, z), Vec3::new(1.0, -1.0, z), Vec3::new(1.0, 1.0, z), Vec3::new(-1.0, 1.0, z)];
+        scene.add_quad(wall(-3.0), material);
+        scene.add_quad(wall(-1.0), material);
+        let mut core = loaded(scene);
+
+        let origin = Vec3::new(0.1, 0.2, 0.0);
+        let mut short = Ray::new(origin, Vec3::new(0.0, 0.0, -1.0));
+        short.t_max = 0.5;
+        let hits = core.trace_rays(vec![
+            Ray::new(origin, Vec3::new(0.0, 0.0, -1.0)),
+            Ray::new(origin, Vec3::new(0.0, 0.0, 1.0)),
+            Ray::new(Vec3::new(5.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0)),
+            short,
+        ]).unwrap();
+
+        let nearest = hits[0].expect("ray toward the walls hits");
+        assert!((nearest.t - 1.0).abs() < 1e-5);
+        assert!(nearest.normal.z > 0.0, "normal faces back along the ray");
+        assert!(hits[1].is_none());
+        assert!(hits[2].is_none());
+        assert!(hits[3].is_none());
+    }
+
+    #[test]
+    fn sah_keeps_leaves_small_and_rays_skip_most_of_the_tree() {
+        let mut rng = 0x9e3779b97f4a7c15;
+        let mut core = loaded(scattered_scene(512, &mut rng));
+
+        let nodes = core.bvh_nodes();
+        let leaves: Vec<&BVHNode> = nodes.iter().filter(|node| node.left.is_none()).collect();
+        assert!(leaves.iter().all(|leaf| leaf.primitive_count <= MAX_LEAF_PRIMITIVES));
+        assert_eq!(leaves.iter().map(|leaf| leaf.primitive_count).sum::<usize>(), 512);
+        for (index, node) in nodes.iter().enumerate() {
+            assert!(node.escape > index && node.escape <= nodes.len());
+            if let Some(left) = node.left {
+                assert_eq!(left, index + 1, "left child follows its parent");
+            }
+        }
+        assert_eq!(core.get_stats().bvh_nodes, nodes.len());
+        assert!(core.get_stats().bvh_depth < 20);
+
+        let node_count = nodes.len() as u32;
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 15.0), Vec3::new(0.05, 0.02, -1.0));
+        let trace = core.trace_debug(&ray).unwrap();
+        assert_eq!(trace.visited_nodes.len() as u32, trace.node_tests);
+        assert!(trace.node_tests < node_count / 4, "{} of {} nodes tested", trace.node_tests, node_count);
+        assert!(trace.triangle_tests < 64, "{} triangle tests", trace.triangle_tests);
+        assert_eq!(trace.hit.map(|hit| hit.primitive), brute_force(&core, &ray).map(|(primitive, _)| primitive));
+    }
+
+    #[test]
+    fn coincident_centroids_still_split_into_small_leaves() {
+        let mut scene = Scene::new();
+        let material = scene.add_material(matte());
+        for _ in 0..40 {
+            scene.add_triangle([Vec3::new(-1.0, -1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)], material);
+        }
+        let mut core = loaded(scene);
+
+        assert!(core.bvh_nodes().iter().all(|node| node.primitive_count <= MAX_LEAF_PRIMITIVES));
+        let hit = core.trace_rays(vec![Ray::new(Vec3::new(0.0, 0.0, 2.0), Vec3::new(0.0, 0.0, -1.0))]).unwrap()[0];
+        assert!((hit.expect("stacked triangles are hit").t - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn bad_scenes_and_unloaded_cores_are_rejected() {
+        let mut core = RayCore::new(0);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(core.trace_rays(vec![ray]).is_err());
+        assert!(core.trace_debug(&ray).is_err());
+        assert!(core.load_scene(Scene::new()).is_err());
+
+        let mut scene = Scene::new();
+        scene.add_triangle([Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)], 3);
+        assert!(core.load_scene(scene).is_err());
+        assert!(core.bvh_nodes().is_empty());
+    }
+}
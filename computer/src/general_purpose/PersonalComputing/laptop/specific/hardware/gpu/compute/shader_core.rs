@@ -1,76 +1,975 @@
 use super::super::error::{GPUError, GPUResult};
+use super::super::memory::{GPUMemory, GPUMemoryConfig, SharedMemory};
+use super::ComputeCore;
+use std::collections::VecDeque;
+
+// Threads run in lockstep groups of this many lanes
+pub const WARP_SIZE: usize = 32;
+pub const PREDICATE_REGISTERS: usize = 8;
+
+// A launch that runs this long is assumed hung, as a driver's watchdog would
+const WATCHDOG_CYCLES: u64 = 100_000_000;
+
+const FULL_MASK: u32 = u32::MAX;
 
 pub struct ShaderCore {
     id: u32,
     state: CoreState,
+    config: ShaderCoreConfig,
+    memory: GPUMemory,
     workload: Option<ShaderWorkload>,
+    finished: Option<ShaderWorkload>,
+    warps: Vec<Warp>,
+    pending_warps: VecDeque<u32>,
+    resident_limit: usize,
+    next_warp: usize,
+    cycle: u64,
+    fault: Option<GPUError>,
     stats: CoreStats,
 }
 
-struct ShaderWorkload {
+#[derive(Debug, Clone)]
+pub struct ShaderCoreConfig {
+    pub max_warps: usize,
+    pub register_file_size: usize, // 32-bit registers shared by all resident threads
+    pub alu_latency: u64,
+    pub sfu_latency: u64,
+    pub texture_latency: u64,
+    pub memory: GPUMemoryConfig,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CoreState {
+    Idle,
+    Active,
+    Faulted,
+}
+
+pub struct ShaderWorkload {
     program: ShaderProgram,
-    input_data: Vec<f32>,
-    output_data: Vec<f32>,
+    threads: u32,
+    memory: Vec<u32>,
+    shared: SharedMemory,
+    textures: Vec<Texture>,
     progress: f32,
 }
 
-struct ShaderProgram {
+pub struct ShaderProgram {
     instructions: Vec<ShaderInstruction>,
-    uniforms: Vec<Uniform>,
-    attributes: Vec<Attribute>,
+    uniforms: Vec<u32>,
+    registers: u8, // Per thread; bounds how many warps fit in the register file
 }
 
-enum ShaderInstruction {
+// Registers are untyped 32-bit values; float ops reinterpret their bits
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShaderInstruction {
+    // Float ALU
     Add(u8, u8, u8),
+    Sub(u8, u8, u8),
     Mul(u8, u8, u8),
     Mad(u8, u8, u8, u8),
-    Load(u8, u32),
-    Store(u32, u8),
-    // More instructions...
+    Min(u8, u8, u8),
+    Max(u8, u8, u8),
+
+    // Special function unit
+    Rcp(u8, u8),
+    Sqrt(u8, u8),
+
+    // Integer ALU
+    IAdd(u8, u8, u8),
+    IMul(u8, u8, u8),
+    And(u8, u8, u8),
+    Or(u8, u8, u8),
+    Shl(u8, u8, u8),
+    Shr(u8, u8, u8),
+    I2F(u8, u8),
+    F2I(u8, u8),
+
+    // Moves
+    Mov(u8, u8),
+    Imm(u8, u32),
+    Uniform(u8, u16),
+    Special(u8, SpecialRegister),
+
+    // Compare into a predicate register
+    SetP(u8, CompareOp, u8, u8),
+    ISetP(u8, CompareOp, u8, u8),
+
+    // Memory: byte address from a register plus an immediate offset
+    Load(u8, u8, u32),
+    Store(u8, u32, u8),
+    LoadShared(u8, u8, u32),
+    StoreShared(u8, u32, u8),
+    // Bilinear sample of texture slot at (u, v) into four consecutive registers
+    TexLoad(u8, u8, u8, u8),
+
+    // Control flow. Lanes where the predicate (optionally negated) holds jump to
+    // `target`; both sides rejoin at `reconverge`, the branch's post-dominator.
+    Branch { predicate: u8, negate: bool, target: usize, reconverge: usize },
+    Jump(usize),
+    Exit,
+    Nop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Lt,
+    Le,
+    Eq,
+    Ne,
+    Ge,
+    Gt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpecialRegister {
+    LaneId,
+    WarpId,
+    ThreadId,
+    ThreadCount,
+}
+
+#[derive(Debug, Clone)]
+pub struct Texture {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<[f32; 4]>,
+}
+
+struct Warp {
+    id: u32,
+    registers: Vec<[u32; WARP_SIZE]>,
+    predicates: [u32; PREDICATE_REGISTERS], // One bit per lane
+    stack: Vec<StackEntry>,
+    ready_at: u64,
+}
+
+// Reconvergence stack: the top entry is what the warp executes, with its lanes
+#[derive(Debug, Clone)]
+struct StackEntry {
+    pc: usize,
+    reconverge: Option<usize>,
+    mask: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CoreStats {
+    pub workloads_started: u64,
+    pub workloads_completed: u64,
+    pub cycles: u64,
+    pub issue_cycles: u64,
+    pub stall_cycles: u64,
+    pub warp_instructions: u64,
+    pub thread_instructions: u64,
+    pub divergent_branches: u64,
+    pub reconvergences: u64,
+    pub memory_instructions: u64,
+    pub shared_instructions: u64,
+    pub memory_stall_cycles: u64, // Latency added by memory instructions beyond an L1 hit
+    pub texture_instructions: u64,
+    pub warps_launched: u64,
+    pub max_stack_depth: usize,
+    pub resident_warp_cycles: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct WarpSnapshot {
+    pub id: u32,
+    pub pc: usize,
+    pub active_mask: u32,
+    pub stack_depth: usize,
+    pub ready_at: u64,
 }
 
 impl ShaderCore {
     pub fn new(id: u32) -> Self {
+        Self::with_config(id, ShaderCoreConfig::default())
+    }
+
+    pub fn with_config(id: u32, config: ShaderCoreConfig) -> Self {
         Self {
             id,
             state: CoreState::Idle,
+            memory: GPUMemory::with_config(config.memory.clone()),
+            config,
             workload: None,
+            finished: None,
+            warps: Vec::new(),
+            pending_warps: VecDeque::new(),
+            resident_limit: 0,
+            next_warp: 0,
+            cycle: 0,
+            fault: None,
             stats: CoreStats::default(),
         }
     }
 
     pub fn execute_workload(&mut self, workload: ShaderWorkload) -> GPUResult<()> {
+        if self.workload.is_some() {
+            return Err(GPUError::DeviceBusy);
+        }
+        workload.program.validate(workload.textures.len())?;
+
+        let warps = (workload.threads as usize).div_ceil(WARP_SIZE) as u32;
+        self.resident_limit = self.config.warps_for(workload.program.registers);
+        self.pending_warps = (0..warps).collect();
+        self.warps.clear();
+        self.next_warp = 0;
+        self.cycle = 0;
+        self.fault = None;
         self.state = CoreState::Active;
         self.workload = Some(workload);
         self.stats.workloads_started += 1;
+        self.launch_warps();
+        if self.warps.is_empty() {
+            self.complete_workload();
+        }
         Ok(())
     }
 
+    // One core clock: the warp scheduler issues at most one instruction
     pub fn tick(&mut self) {
+        if self.state != CoreState::Active {
+            return;
+        }
+        self.cycle += 1;
+        self.stats.cycles += 1;
+        self.stats.resident_warp_cycles += self.warps.len() as u64;
+
+        match self.select_warp() {
+            Some(index) => {
+                self.stats.issue_cycles += 1;
+                if let Err(error) = self.issue(index) {
+                    self.fault = Some(error);
+                    self.state = CoreState::Faulted;
+                    return;
+                }
+                if self.warps[index].stack.is_empty() {
+                    self.warps.remove(index);
+                    if self.next_warp > index {
+                        self.next_warp -= 1;
+                    }
+                    self.launch_warps();
+                }
+            }
+            None => self.stats.stall_cycles += 1,
+        }
+
+        self.update_progress();
+        if self.warps.is_empty() && self.pending_warps.is_empty() {
+            self.complete_workload();
+        }
+    }
+
+    // Runs the current workload to completion and hands it back with its memory
+    pub fn run(&mut self) -> GPUResult<ShaderWorkload> {
+        let start = self.cycle;
+        while self.state == CoreState::Active {
+            if self.cycle - start >= WATCHDOG_CYCLES {
+                self.fault = Some(GPUError::Timeout);
+                self.state = CoreState::Faulted;
+                break;
+            }
+            self.tick();
+        }
+        if let Some(error) = self.fault.take() {
+            self.workload = None;
+            self.warps.clear();
+            self.pending_warps.clear();
+            self.state = CoreState::Idle;
+            return Err(error);
+        }
+        self.finished.take().ok_or(GPUError::InvalidProgram("no workload".to_string()))
+    }
+
+    pub fn take_finished(&mut self) -> Option<ShaderWorkload> {
+        self.finished.take()
+    }
+
+    pub fn fault(&self) -> Option<&GPUError> {
+        self.fault.as_ref()
+    }
+
+    pub fn state(&self) -> CoreState {
+        self.state
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    // Resident warps the register file allows for a program, over the hardware maximum
+    pub fn theoretical_occupancy(&self, program: &ShaderProgram) -> f32 {
+        self.config.warps_for(program.registers) as f32 / self.config.max_warps as f32
+    }
+
+    // Average resident warps over the hardware maximum, across all cycles run
+    pub fn achieved_occupancy(&self) -> f32 {
+        if self.stats.cycles == 0 {
+            return 0.0;
+        }
+        self.stats.resident_warp_cycles as f32 / (self.stats.cycles * self.config.max_warps as u64) as f32
+    }
+
+    pub fn get_stats(&self) -> &CoreStats {
+        &self.stats
+    }
+
+    // The SM's L1, L2 and coalescer, for cache statistics
+    pub fn memory(&self) -> &GPUMemory {
+        &self.memory
+    }
+
+    // Methods for visualization system
+    pub fn warp_snapshots(&self) -> Vec<WarpSnapshot> {
+        self.warps
+            .iter()
+            .map(|warp| {
+                let top = warp.stack.last();
+                WarpSnapshot {
+                    id: warp.id,
+                    pc: top.map(|entry| entry.pc).unwrap_or(0),
+                    active_mask: top.map(|entry| entry.mask).unwrap_or(0),
+                    stack_depth: warp.stack.len(),
+                    ready_at: warp.ready_at,
+                }
+            })
+            .collect()
+    }
+
+    // Helper methods
+    fn launch_warps(&mut self) {
+        let (threads, registers) = match &self.workload {
+            Some(workload) => (workload.threads, workload.program.registers as usize),
+            None => return,
+        };
+        while self.warps.len() < self.resident_limit {
+            let id = match self.pending_warps.pop_front() {
+                Some(id) => id,
+                None => break,
+            };
+            // The last warp of a launch may be partially populated
+            let first = id as usize * WARP_SIZE;
+            let lanes = (threads as usize - first).min(WARP_SIZE);
+            let mask = if lanes == WARP_SIZE { FULL_MASK } else { (1u32 << lanes) - 1 };
+            self.warps.push(Warp {
+                id,
+                registers: vec![[0; WARP_SIZE]; registers],
+                predicates: [0; PREDICATE_REGISTERS],
+                stack: vec![StackEntry { pc: 0, reconverge: None, mask }],
+                ready_at: self.cycle,
+            });
+            self.stats.warps_launched += 1;
+        }
+    }
+
+    // Loose round robin over warps whose previous instruction has completed
+    fn select_warp(&mut self) -> Option<usize> {
+        let count = self.warps.len();
+        for offset in 0..count {
+            let index = (self.next_warp + offset) % count;
+            if self.warps[index].ready_at <= self.cycle {
+                self.next_warp = (index + 1) % count;
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    fn issue(&mut self, index: usize) -> GPUResult<()> {
+        let workload = self.workload.as_mut().ok_or(GPUError::InvalidProgram("no workload".to_string()))?;
+        let config = &self.config;
+        let memory = &mut self.memory;
+        let stats = &mut self.stats;
+        let warp = &mut self.warps[index];
+        let top = warp.stack.last().cloned().ok_or(GPUError::InvalidProgram("warp has exited".to_string()))?;
+        let mask = top.mask;
+
+        // Falling off the end of the program is an implicit exit
+        let instruction = match workload.program.instructions.get(top.pc) {
+            Some(instruction) => instruction.clone(),
+            None => ShaderInstruction::Exit,
+        };
+        stats.warp_instructions += 1;
+        stats.thread_instructions += mask.count_ones() as u64;
+
+        let mut next_pc = top.pc + 1;
+        let latency = match instruction {
+            ShaderInstruction::Add(d, a, b) => warp.float_op(mask, d, a, b, |x, y| x + y, config.alu_latency),
+            ShaderInstruction::Sub(d, a, b) => warp.float_op(mask, d, a, b, |x, y| x - y, config.alu_latency),
+            ShaderInstruction::Mul(d, a, b) => warp.float_op(mask, d, a, b, |x, y| x * y, config.alu_latency),
+            ShaderInstruction::Min(d, a, b) => warp.float_op(mask, d, a, b, f32::min, config.alu_latency),
+            ShaderInstruction::Max(d, a, b) => warp.float_op(mask, d, a, b, f32::max, config.alu_latency),
+            ShaderInstruction::Mad(d, a, b, c) => {
+                for lane in lanes(mask) {
+                    let value = f(warp.registers[a as usize][lane]) * f(warp.registers[b as usize][lane])
+                        + f(warp.registers[c as usize][lane]);
+                    warp.registers[d as usize][lane] = value.to_bits();
+                }
+                config.alu_latency
+            }
+            ShaderInstruction::Rcp(d, a) => warp.float_op(mask, d, a, a, |x, _| 1.0 / x, config.sfu_latency),
+            ShaderInstruction::Sqrt(d, a) => warp.float_op(mask, d, a, a, |x, _| x.sqrt(), config.sfu_latency),
+            ShaderInstruction::IAdd(d, a, b) => warp.int_op(mask, d, a, b, u32::wrapping_add, config.alu_latency),
+            ShaderInstruction::IMul(d, a, b) => warp.int_op(mask, d, a, b, u32::wrapping_mul, config.alu_latency),
+            ShaderInstruction::And(d, a, b) => warp.int_op(mask, d, a, b, |x, y| x & y, config.alu_latency),
+            ShaderInstruction::Or(d, a, b) => warp.int_op(mask, d, a, b, |x, y| x | y, config.alu_latency),
+            ShaderInstruction::Shl(d, a, b) => warp.int_op(mask, d, a, b, |x, y| x.wrapping_shl(y), config.alu_latency),
+            ShaderInstruction::Shr(d, a, b) => warp.int_op(mask, d, a, b, |x, y| x.wrapping_shr(y), config.alu_latency),
+            ShaderInstruction::I2F(d, a) => warp.int_op(mask, d, a, a, |x, _| (x as i32 as f32).to_bits(), config.alu_latency),
+            ShaderInstruction::F2I(d, a) => warp.int_op(mask, d, a, a, |x, _| f(x) as i32 as u32, config.alu_latency),
+            ShaderInstruction::Mov(d, a) => warp.int_op(mask, d, a, a, |x, _| x, config.alu_latency),
+            ShaderInstruction::Imm(d, value) => {
+                for lane in lanes(mask) {
+                    warp.registers[d as usize][lane] = value;
+                }
+                config.alu_latency
+            }
+            ShaderInstruction::Uniform(d, slot) => {
+                let value = workload.program.uniforms[slot as usize];
+                for lane in lanes(mask) {
+                    warp.registers[d as usize][lane] = value;
+                }
+                config.alu_latency
+            }
+            ShaderInstruction::Special(d, register) => {
+                for lane in lanes(mask) {
+                    warp.registers[d as usize][lane] = match register {
+                        SpecialRegister::LaneId => lane as u32,
+                        SpecialRegister::WarpId => warp.id,
+                        SpecialRegister::ThreadId => warp.id * WARP_SIZE as u32 + lane as u32,
+                        SpecialRegister::ThreadCount => workload.threads,
+                    };
+                }
+                config.alu_latency
+            }
+            ShaderInstruction::SetP(p, op, a, b) => {
+                warp.set_predicate(mask, p, |registers, lane| op.holds(f(registers[a as usize][lane]), f(registers[b as usize][lane])));
+                config.alu_latency
+            }
+            ShaderInstruction::ISetP(p, op, a, b) => {
+                warp.set_predicate(mask, p, |registers, lane| {
+                    op.holds(registers[a as usize][lane] as i32, registers[b as usize][lane] as i32)
+                });
+                config.alu_latency
+            }
+            ShaderInstruction::Load(d, address, offset) => {
+                stats.memory_instructions += 1;
+                let mut addresses = [0u64; WARP_SIZE];
+                for lane in lanes(mask) {
+                    let word = word_index(warp.registers[address as usize][lane], offset, workload.memory.len())?;
+                    warp.registers[d as usize][lane] = workload.memory[word];
+                    addresses[lane] = word as u64 * 4;
+                }
+                let latency = memory.access(self.cycle, mask, &addresses, 4, false)?.max(config.memory.l1.hit_latency);
+                stats.memory_stall_cycles += latency - config.memory.l1.hit_latency;
+                latency
+            }
+            ShaderInstruction::Store(address, offset, s) => {
+                stats.memory_instructions += 1;
+                let mut addresses = [0u64; WARP_SIZE];
+                for lane in lanes(mask) {
+                    let word = word_index(warp.registers[address as usize][lane], offset, workload.memory.len())?;
+                    workload.memory[word] = warp.registers[s as usize][lane];
+                    addresses[lane] = word as u64 * 4;
+                }
+                let latency = memory.access(self.cycle, mask, &addresses, 4, true)?.max(config.memory.l1.hit_latency);
+                stats.memory_stall_cycles += latency - config.memory.l1.hit_latency;
+                latency
+            }
+            ShaderInstruction::LoadShared(d, address, offset) => {
+                stats.shared_instructions += 1;
+                let addresses = warp.registers[address as usize].map(|base| base.wrapping_add(offset));
+                let mut values = warp.registers[d as usize];
+                let wavefronts = workload.shared.load(mask, &addresses, &mut values)?;
+                warp.registers[d as usize] = values;
+                config.memory.shared_latency + wavefronts.saturating_sub(1) as u64
+            }
+            ShaderInstruction::StoreShared(address, offset, s) => {
+                stats.shared_instructions += 1;
+                let addresses = warp.registers[address as usize].map(|base| base.wrapping_add(offset));
+                let wavefronts = workload.shared.store(mask, &addresses, &warp.registers[s as usize])?;
+                config.memory.shared_latency + wavefronts.saturating_sub(1) as u64
+            }
+            ShaderInstruction::TexLoad(d, u, v, slot) => {
+                stats.texture_instructions += 1;
+                let texture = &workload.textures[slot as usize];
+                for lane in lanes(mask) {
+                    let texel = texture.sample(f(warp.registers[u as usize][lane]), f(warp.registers[v as usize][lane]));
+                    for (channel, value) in texel.iter().enumerate() {
+                        warp.registers[d as usize + channel][lane] = value.to_bits();
+                    }
+                }
+                config.texture_latency
+            }
+            ShaderInstruction::Branch { predicate, negate, target, reconverge } => {
+                let holds = if negate { !warp.predicates[predicate as usize] } else { warp.predicates[predicate as usize] };
+                let taken = mask & holds;
+                if taken == mask {
+                    next_pc = target;
+                } else if taken != 0 {
+                    // Divergence: park the full mask at the reconvergence point and run
+                    // each side alone, taken side first
+                    stats.divergent_branches += 1;
+                    warp.stack.pop();
+                    // A path already headed for the same point needn't park twice,
+                    // which keeps divergent loops from growing the stack
+                    if top.reconverge != Some(reconverge) {
+                        warp.stack.push(StackEntry { pc: reconverge, reconverge: top.reconverge, mask });
+                    }
+                    if top.pc + 1 != reconverge {
+                        warp.stack.push(StackEntry { pc: top.pc + 1, reconverge: Some(reconverge), mask: mask & !taken });
+                    }
+                    if target != reconverge {
+                        warp.stack.push(StackEntry { pc: target, reconverge: Some(reconverge), mask: taken });
+                    }
+                    stats.max_stack_depth = stats.max_stack_depth.max(warp.stack.len());
+                    warp.ready_at = self.cycle + config.alu_latency;
+                    warp.pop_reconverged(stats);
+                    return Ok(());
+                }
+                config.alu_latency
+            }
+            ShaderInstruction::Jump(target) => {
+                next_pc = target;
+                config.alu_latency
+            }
+            ShaderInstruction::Exit => {
+                // Exited lanes leave every pending path of the warp
+                for entry in warp.stack.iter_mut() {
+                    entry.mask &= !mask;
+                }
+                warp.ready_at = self.cycle + config.alu_latency;
+                warp.pop_reconverged(stats);
+                return Ok(());
+            }
+            ShaderInstruction::Nop => config.alu_latency,
+        };
+
+        if let Some(entry) = warp.stack.last_mut() {
+            entry.pc = next_pc;
+        }
+        warp.ready_at = self.cycle + latency;
+        warp.pop_reconverged(stats);
+        Ok(())
+    }
+
+    fn update_progress(&mut self) {
         if let Some(workload) = &mut self.workload {
-            // Process shader instructions
-            self.execute_instructions(workload);
-            
-            if workload.progress >= 1.0 {
-                self.complete_workload();
+            let total = (workload.threads as usize).div_ceil(WARP_SIZE).max(1);
+            let done = total - self.pending_warps.len() - self.warps.len();
+            workload.progress = done as f32 / total as f32;
+        }
+    }
+
+    fn complete_workload(&mut self) {
+        if let Some(mut workload) = self.workload.take() {
+            workload.progress = 1.0;
+            self.finished = Some(workload);
+            self.stats.workloads_completed += 1;
+        }
+        self.state = CoreState::Idle;
+    }
+}
+
+impl ComputeCore for ShaderCore {
+    fn tick(&mut self) {
+        ShaderCore::tick(self);
+    }
+
+    fn is_idle(&self) -> bool {
+        self.state != CoreState::Active
+    }
+
+    fn get_utilization(&self) -> f32 {
+        self.stats.lane_utilization()
+    }
+}
+
+impl Warp {
+    fn float_op<F: Fn(f32, f32) -> f32>(&mut self, mask: u32, d: u8, a: u8, b: u8, op: F, latency: u64) -> u64 {
+        for lane in lanes(mask) {
+            let value = op(f(self.registers[a as usize][lane]), f(self.registers[b as usize][lane]));
+            self.registers[d as usize][lane] = value.to_bits();
+        }
+        latency
+    }
+
+    fn int_op<F: Fn(u32, u32) -> u32>(&mut self, mask: u32, d: u8, a: u8, b: u8, op: F, latency: u64) -> u64 {
+        for lane in lanes(mask) {
+            self.registers[d as usize][lane] = op(self.registers[a as usize][lane], self.registers[b as usize][lane]);
+        }
+        latency
+    }
+
+    fn set_predicate<F: Fn(&[[u32; WARP_SIZE]], usize) -> bool>(&mut self, mask: u32, p: u8, test: F) {
+        let mut bits = self.predicates[p as usize] & !mask;
+        for lane in lanes(mask) {
+            if test(&self.registers, lane) {
+                bits |= 1 << lane;
             }
         }
+        self.predicates[p as usize] = bits;
     }
 
-    fn execute_instructions(&mut self, workload: &mut ShaderWorkload) {
-        // Execute shader program instructions
-        for instruction in &workload.program.instructions {
-            match instruction {
-                ShaderInstruction::Add(dst, src1, src2) => {
-                    // Perform addition
+    // Paths that reached their reconvergence point, or ran out of lanes, hand
+    // control back to the entry beneath them
+    fn pop_reconverged(&mut self, stats: &mut CoreStats) {
+        while let Some(top) = self.stack.last() {
+            if top.mask == 0 {
+                self.stack.pop();
+            } else if Some(top.pc) == top.reconverge {
+                let join = top.pc;
+                self.stack.pop();
+                // Counted once, when the last path arrives and the parked lanes resume
+                if self.stack.last().map(|entry| entry.pc == join).unwrap_or(false) {
+                    stats.reconvergences += 1;
                 }
-                ShaderInstruction::Mul(dst, src1, src2) => {
-                    // Perform multiplication
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+impl ShaderWorkload {
+    pub fn new(program: ShaderProgram, threads: u32, memory: Vec<u32>) -> Self {
+        Self {
+            program,
+            threads,
+            memory,
+            shared: SharedMemory::new(0),
+            textures: Vec::new(),
+            progress: 0.0,
+        }
+    }
+
+    // Scratchpad every warp of the launch can see
+    pub fn with_shared_memory(mut self, bytes: usize) -> Self {
+        self.shared = SharedMemory::new(bytes);
+        self
+    }
+
+    pub fn with_textures(mut self, textures: Vec<Texture>) -> Self {
+        self.textures = textures;
+        self
+    }
+
+    pub fn memory(&self) -> &[u32] {
+        &self.memory
+    }
+
+    pub fn shared_memory(&self) -> &SharedMemory {
+        &self.shared
+    }
+
+    pub fn into_memory(self) -> Vec<u32> {
+        self.memory
+    }
+
+    pub fn progress(&self) -> f32 {
+        self.progress
+    }
+}
+
+impl ShaderProgram {
+    pub fn new(instructions: Vec<ShaderInstruction>, registers: u8) -> Self {
+        Self {
+            instructions,
+            uniforms: Vec::new(),
+            registers,
+        }
+    }
+
+    pub fn with_uniforms(mut self, uniforms: Vec<u32>) -> Self {
+        self.uniforms = uniforms;
+        self
+    }
+
+    pub fn instructions(&self) -> &[ShaderInstruction] {
+        &self.instructions
+    }
+
+    // Rejects out-of-range operands up front so execution never has to
+    fn validate(&self, textures: usize) -> GPUResult<()> {
+        let invalid = |pc: usize, what: &str| Err(GPUError::InvalidProgram(format!("{} at {}", what, pc)));
+        let length = self.instructions.len();
+        for (pc, instruction) in self.instructions.iter().enumerate() {
+            let (registers, predicate, target): (Vec<u8>, Option<u8>, Vec<usize>) = match instruction {
+                ShaderInstruction::Add(d, a, b)
+                | ShaderInstruction::Sub(d, a, b)
+                | ShaderInstruction::Mul(d, a, b)
+                | ShaderInstruction::Min(d, a, b)
+                | ShaderInstruction::Max(d, a, b)
+                | ShaderInstruction::IAdd(d, a, b)
+                | ShaderInstruction::IMul(d, a, b)
+                | ShaderInstruction::And(d, a, b)
+                | ShaderInstruction::Or(d, a, b)
+                | ShaderInstruction::Shl(d, a, b)
+                | ShaderInstruction::Shr(d, a, b) => (vec![*d, *a, *b], None, vec![]),
+                ShaderInstruction::Mad(d, a, b, c) => (vec![*d, *a, *b, *c], None, vec![]),
+                ShaderInstruction::Rcp(d, a)
+                | ShaderInstruction::Sqrt(d, a)
+                | ShaderInstruction::I2F(d, a)
+                | ShaderInstruction::F2I(d, a)
+                | ShaderInstruction::Mov(d, a) => (vec![*d, *a], None, vec![]),
+                ShaderInstruction::Imm(d, _) | ShaderInstruction::Special(d, _) => (vec![*d], None, vec![]),
+                ShaderInstruction::Uniform(d, slot) => {
+                    if *slot as usize >= self.uniforms.len() {
+                        return invalid(pc, "uniform out of range");
+                    }
+                    (vec![*d], None, vec![])
+                }
+                ShaderInstruction::SetP(p, _, a, b) | ShaderInstruction::ISetP(p, _, a, b) => (vec![*a, *b], Some(*p), vec![]),
+                ShaderInstruction::Load(d, a, _) | ShaderInstruction::LoadShared(d, a, _) => (vec![*d, *a], None, vec![]),
+                ShaderInstruction::Store(a, _, s) | ShaderInstruction::StoreShared(a, _, s) => (vec![*a, *s], None, vec![]),
+                ShaderInstruction::TexLoad(d, u, v, slot) => {
+                    if *slot as usize >= textures {
+                        return Err(GPUError::InvalidTexture);
+                    }
+                    (vec![*d, d.saturating_add(3), *u, *v], None, vec![])
                 }
-                // Handle other instructions...
+                ShaderInstruction::Branch { predicate, target, reconverge, .. } => (vec![], Some(*predicate), vec![*target, *reconverge]),
+                ShaderInstruction::Jump(target) => (vec![], None, vec![*target]),
+                ShaderInstruction::Exit | ShaderInstruction::Nop => (vec![], None, vec![]),
+            };
+            if registers.iter().any(|&register| register >= self.registers) {
+                return invalid(pc, "register out of range");
             }
+            if predicate.map(|p| p as usize >= PREDICATE_REGISTERS).unwrap_or(false) {
+                return invalid(pc, "predicate out of range");
+            }
+            if target.iter().any(|&target| target > length) {
+                return invalid(pc, "branch target out of range");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CompareOp {
+    fn holds<T: PartialOrd>(&self, a: T, b: T) -> bool {
+        match self {
+            CompareOp::Lt => a < b,
+            CompareOp::Le => a <= b,
+            CompareOp::Eq => a == b,
+            CompareOp::Ne => a != b,
+            CompareOp::Ge => a >= b,
+            CompareOp::Gt => a > b,
+        }
+    }
+}
+
+impl Texture {
+    pub fn new(width: u32, height: u32, texels: Vec<[f32; 4]>) -> GPUResult<Self> {
+        if width == 0 || height == 0 || texels.len() != (width * height) as usize {
+            return Err(GPUError::InvalidTexture);
+        }
+        Ok(Self { width, height, texels })
+    }
+
+    // Normalized coordinates with wrap addressing and bilinear filtering
+    pub fn sample(&self, u: f32, v: f32) -> [f32; 4] {
+        let x = u.rem_euclid(1.0) * self.width as f32 - 0.5;
+        let y = v.rem_euclid(1.0) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let texel = |tx: f32, ty: f32| {
+            let tx = (tx as i64).rem_euclid(self.width as i64) as usize;
+            let ty = (ty as i64).rem_euclid(self.height as i64) as usize;
+            self.texels[ty * self.width as usize + tx]
+        };
+        let (a, b) = (texel(x0, y0), texel(x0 + 1.0, y0));
+        let (c, d) = (texel(x0, y0 + 1.0), texel(x0 + 1.0, y0 + 1.0));
+
+        let mut result = [0.0; 4];
+        for channel in 0..4 {
+            let top = a[channel] + (b[channel] - a[channel]) * fx;
+            let bottom = c[channel] + (d[channel] - c[channel]) * fx;
+            result[channel] = top + (bottom - top) * fy;
+        }
+        result
+    }
+}
+
+impl ShaderCoreConfig {
+    fn warps_for(&self, registers: u8) -> usize {
+        let per_warp = (registers as usize).max(1) * WARP_SIZE;
+        (self.register_file_size / per_warp).clamp(1, self.max_warps.max(1))
+    }
+}
+
+impl Default for ShaderCoreConfig {
+    fn default() -> Self {
+        Self {
+            max_warps: 48,
+            register_file_size: 65536,
+            alu_latency: 4,
+            sfu_latency: 16,
+            texture_latency: 300,
+            memory: GPUMemoryConfig::default(),
+        }
+    }
+}
+
+impl CoreStats {
+    // Fraction of lanes doing useful work in issued instructions; divergence lowers it
+    pub fn lane_utilization(&self) -> f32 {
+        if self.warp_instructions == 0 {
+            return 0.0;
+        }
+        self.thread_instructions as f32 / (self.warp_instructions * WARP_SIZE as u64) as f32
+    }
+
+    pub fn ipc(&self) -> f32 {
+        if self.cycles == 0 {
+            return 0.0;
         }
-        
-        workload.progress += 0.1; // Simplified progress tracking
+        self.warp_instructions as f32 / self.cycles as f32
     }
-} 
\ No newline at end of file
+
+}
+
+fn lanes(mask: u32) -> impl Iterator<Item = usize> {
+    (0..WARP_SIZE).filter(move |lane| mask & (1 << lane) != 0)
+}
+
+fn f(bits: u32) -> f32 {
+    f32::from_bits(bits)
+}
+
+fn word_index(address: u32, offset: u32, words: usize) -> GPUResult<usize> {
+    let address = address.wrapping_add(offset) as usize;
+    if !address.is_multiple_of(4) || address / 4 >= words {
+        return Err(GPUError::AccessViolation);
+    }
+    Ok(address / 4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ShaderInstruction::*;
+
+    // Stores r`value` to word ThreadId of the output buffer
+    fn store_per_thread(value: u8) -> Vec<ShaderInstruction> {
+        vec![Special(5, SpecialRegister::ThreadId), Imm(6, 4), IMul(7, 5, 6), Store(7, 0, value), Exit]
+    }
+
+    fn run(program: ShaderProgram, threads: u32, words: usize) -> (ShaderCore, Vec<u32>) {
+        let mut core = ShaderCore::new(0);
+        core.execute_workload(ShaderWorkload::new(program, threads, vec![u32::MAX; words])).unwrap();
+        let memory = core.run().unwrap().into_memory();
+        (core, memory)
+    }
+
+    #[test]
+    fn if_else_diverges_and_reconverges_once() {
+        let mut instructions = vec![
+            Special(0, SpecialRegister::ThreadId),
+            Imm(1, 16),
+            ISetP(0, CompareOp::Lt, 0, 1),
+            Branch { predicate: 0, negate: false, target: 6, reconverge: 8 },
+            Imm(2, 200),
+            Jump(8),
+            Imm(2, 100),
+            Nop,
+        ];
+        instructions.extend(store_per_thread(2));
+        let (core, memory) = run(ShaderProgram::new(instructions, 8), 32, 32);
+
+        let expected: Vec<u32> = (0..32).map(|tid| if tid < 16 { 100 } else { 200 }).collect();
+        assert_eq!(memory, expected);
+        let stats = core.get_stats();
+        assert_eq!(stats.divergent_branches, 1);
+        assert_eq!(stats.reconvergences, 1);
+        assert_eq!(stats.max_stack_depth, 3);
+    }
+
+    #[test]
+    fn divergent_loops_keep_the_stack_flat() {
+        // Lane n loops n times, so lanes leave the loop one at a time
+        let mut instructions = vec![
+            Special(0, SpecialRegister::LaneId),
+            Imm(1, 0),
+            Imm(2, 1),
+            ISetP(0, CompareOp::Lt, 1, 0),
+            Branch { predicate: 0, negate: true, target: 7, reconverge: 7 },
+            IAdd(1, 1, 2),
+            Jump(3),
+        ];
+        instructions.extend(store_per_thread(1));
+        let (core, memory) = run(ShaderProgram::new(instructions, 8), 32, 32);
+
+        assert_eq!(memory, (0..32).collect::<Vec<u32>>());
+        let stats = core.get_stats();
+        assert_eq!(stats.divergent_branches, 31);
+        assert_eq!(stats.reconvergences, 1);
+        assert_eq!(stats.max_stack_depth, 2);
+        assert!(stats.lane_utilization() < 0.75);
+    }
+
+    #[test]
+    fn lanes_that_exit_inside_a_branch_leave_every_path() {
+        // Odd lanes exit early; the rest store after the join
+        let mut instructions = vec![
+            Special(0, SpecialRegister::LaneId),
+            Imm(1, 1),
+            And(2, 0, 1),
+            ISetP(0, CompareOp::Eq, 2, 1),
+            Branch { predicate: 0, negate: false, target: 6, reconverge: 7 },
+            Jump(7),
+            Exit,
+            Imm(3, 7),
+        ];
+        instructions.extend(store_per_thread(3));
+        let (core, memory) = run(ShaderProgram::new(instructions, 8), 32, 32);
+
+        let expected: Vec<u32> = (0..32).map(|lane| if lane % 2 == 0 { 7 } else { u32::MAX }).collect();
+        assert_eq!(memory, expected);
+        assert_eq!(core.state(), CoreState::Idle);
+        assert!(core.warp_snapshots().is_empty());
+    }
+
+    #[test]
+    fn partial_warps_only_run_their_populated_lanes() {
+        let program = ShaderProgram::new(vec![Imm(0, 9)].into_iter().chain(store_per_thread(0)).collect(), 8);
+        let (core, memory) = run(program, 40, 64);
+
+        assert!(memory[..40].iter().all(|&word| word == 9));
+        assert!(memory[40..].iter().all(|&word| word == u32::MAX));
+        let stats = core.get_stats();
+        assert_eq!(stats.warps_launched, 2);
+        assert_eq!(stats.lane_utilization(), 40.0 / 64.0);
+    }
+
+    #[test]
+    fn register_pressure_bounds_resident_warps() {
+        let core = ShaderCore::new(0);
+        // 64 registers x 32 lanes fills 2048 of the 65536-entry register file per warp
+        let heavy = ShaderProgram::new(store_per_thread(0), 64);
+        assert_eq!(core.theoretical_occupancy(&heavy), 32.0 / 48.0);
+        let light = ShaderProgram::new(store_per_thread(0), 8);
+        assert_eq!(core.theoretical_occupancy(&light), 1.0);
+
+        let (core, _) = run(ShaderProgram::new(store_per_thread(0), 64), 64 * 32, 64 * 32);
+        assert_eq!(core.get_stats().warps_launched, 64);
+        assert!(core.achieved_occupancy() <= 32.0 / 48.0);
+    }
+
+    #[test]
+    fn bad_programs_are_rejected_before_they_run() {
+        let mut core = ShaderCore::new(0);
+        let program = ShaderProgram::new(vec![Mov(0, 8), Exit], 8);
+        let result = core.execute_workload(ShaderWorkload::new(program, 32, Vec::new()));
+        assert!(matches!(result, Err(GPUError::InvalidProgram(_))));
+        assert_eq!(core.state(), CoreState::Idle);
+
+        // Out-of-bounds stores fault the launch instead of panicking
+        let program = ShaderProgram::new(store_per_thread(0), 8);
+        core.execute_workload(ShaderWorkload::new(program, 32, vec![0; 16])).unwrap();
+        assert_eq!(core.run().err(), Some(GPUError::AccessViolation));
+        assert_eq!(core.state(), CoreState::Idle);
+    }
+}
@@ -1,24 +1,42 @@
 use super::super::error::{GPUError, GPUResult};
-use super::super::memory::GPUMemory;
-use std::collections::VecDeque;
+use super::ComputeCore;
+use std::collections::{HashMap, VecDeque};
+
+// An operation that runs this long is assumed hung, as a driver's watchdog would
+const WATCHDOG_CYCLES: u64 = 100_000_000;
 
 pub struct TensorCore {
     id: usize,
+    config: TensorCoreConfig,
     matrix_unit: MatrixUnit,
     activation_unit: ActivationUnit,
     accumulator: Accumulator,
     instruction_queue: VecDeque<TensorInstruction>,
+    tensors: HashMap<usize, Tensor>,
+    fault: Option<GPUError>,
     stats: TensorStats,
     state: CoreState,
 }
 
+#[derive(Debug, Clone)]
+pub struct TensorCoreConfig {
+    pub queue_depth: usize,
+    pub fp16_macs_per_cycle: u32, // Other precisions scale from this
+    pub operand_bandwidth: u32,   // Bytes per cycle into the matrix unit
+    pub issue_overhead: u32,      // Cycles per MMA instruction on top of the math
+    pub activation_lanes: u32,
+}
+
+// Holds the fragments of one MMA step: an m x k slice of A and a k x n slice
+// of B, zero padded where the tile overhangs the matrix
 struct MatrixUnit {
     current_op: Option<MatrixOperation>,
     input_buffers: [Vec<f32>; 2],
-    output_buffer: Vec<f32>,
     cycles_remaining: u32,
+    stall_remaining: u32,
 }
 
+// Epilogue: bias, accumulate input and activation, applied per output tile
 struct ActivationUnit {
     function: ActivationFunction,
     input_buffer: Vec<f32>,
@@ -26,117 +44,519 @@ struct ActivationUnit {
     cycles_remaining: u32,
 }
 
+// FP32 for float inputs; INT8 products sum exactly in 32-bit integers and are
+// rescaled to FP32 in the epilogue
 struct Accumulator {
     values: Vec<f32>,
+    integers: Vec<i32>,
     scale_factor: f32,
     bias: Vec<f32>,
 }
 
-#[derive(Clone)]
-struct TensorInstruction {
+#[derive(Debug, Clone)]
+pub struct TensorInstruction {
     op_type: TensorOp,
-    input_shapes: [Shape; 2],
-    output_shape: Shape,
-    activation: Option<ActivationFunction>,
+    inputs: [usize; 2],
+    bias: Option<usize>,
+    accumulate: Option<usize>,
+    output: usize,
+    tile: Option<TileShape>,
+    activation: ActivationFunction,
 }
 
-#[derive(Clone, Copy)]
-enum TensorOp {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TensorOp {
     MatMul,
-    Conv2D,
-    MaxPool,
     Add,
-    Scale,
+    Convert(Precision),
 }
 
-#[derive(Clone, Copy)]
-enum ActivationFunction {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ActivationFunction {
     ReLU,
+    GELU,
     Sigmoid,
     Tanh,
     None,
 }
 
-#[derive(Clone)]
-struct Shape {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Precision {
+    FP32,
+    FP16,
+    BF16,
+    INT8,
+}
+
+// The M x N x K shape one MMA instruction covers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileShape {
+    pub m: usize,
+    pub n: usize,
+    pub k: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shape {
     dimensions: Vec<usize>,
 }
 
+// Values are stored already rounded to the tensor's precision; INT8 tensors
+// hold the integer codes and a per-tensor scale
+#[derive(Debug, Clone)]
+pub struct Tensor {
+    shape: Shape,
+    precision: Precision,
+    data: Vec<f32>,
+    scale: f32,
+}
+
 struct MatrixOperation {
-    op_type: TensorOp,
-    shapes: [Shape; 2],
+    instruction: TensorInstruction,
+    tile: TileShape,
+    precision: Precision,
+    dims: (usize, usize, usize),
+    tiles: (usize, usize, usize),
     current_position: usize,
-    total_elements: usize,
+    k_step: usize,
+    total_tiles: usize,
+    output: Tensor,
 }
 
-#[derive(Clone, Copy, PartialEq)]
-enum CoreState {
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CoreState {
     Idle,
     Computing,
     Activating,
     Stalled,
+    Faulted,
 }
 
-struct TensorStats {
-    operations_completed: u64,
-    total_flops: u64,
-    utilization: f32,
-    current_throughput: f32,
+#[derive(Debug, Clone, Default)]
+pub struct TensorStats {
+    pub operations_completed: u64,
+    pub total_flops: u64,  // Useful work, 2 * M * N * K per GEMM
+    pub padded_flops: u64, // Issued, including tile padding
+    pub mma_instructions: u64,
+    pub cycles: u64,
+    pub compute_cycles: u64,
+    pub stall_cycles: u64,
+    pub activation_cycles: u64,
+    pub mac_slots: u64, // Peak MACs the unit could have done while busy
+    pub utilization: f32,
+    pub current_throughput: f32, // Useful flops per cycle
 }
 
 impl TensorCore {
     pub fn new(id: usize) -> Self {
+        Self::with_config(id, TensorCoreConfig::default())
+    }
+
+    pub fn with_config(id: usize, config: TensorCoreConfig) -> Self {
         Self {
             id,
+            config,
             matrix_unit: MatrixUnit::new(),
             activation_unit: ActivationUnit::new(),
             accumulator: Accumulator::new(),
             instruction_queue: VecDeque::new(),
+            tensors: HashMap::new(),
+            fault: None,
             stats: TensorStats::default(),
             state: CoreState::Idle,
         }
     }
 
+    pub fn load_tensor(&mut self, slot: usize, tensor: Tensor) {
+        self.tensors.insert(slot, tensor);
+    }
+
+    pub fn tensor(&self, slot: usize) -> Option<&Tensor> {
+        self.tensors.get(&slot)
+    }
+
+    pub fn take_tensor(&mut self, slot: usize) -> Option<Tensor> {
+        self.tensors.remove(&slot)
+    }
+
+    pub fn submit(&mut self, instruction: TensorInstruction) -> GPUResult<()> {
+        if self.instruction_queue.len() >= self.config.queue_depth {
+            return Err(GPUError::QueueFull);
+        }
+        self.instruction_queue.push_back(instruction);
+        Ok(())
+    }
+
     pub fn tick(&mut self) {
-        match self.state {
+        if self.state == CoreState::Faulted {
+            return;
+        }
+        if self.state != CoreState::Idle {
+            self.stats.cycles += 1;
+            if let Some(op) = &self.matrix_unit.current_op {
+                if op.instruction.op_type == TensorOp::MatMul {
+                    self.stats.mac_slots += self.config.macs_per_cycle(op.precision) as u64;
+                }
+            }
+        }
+
+        let result = match self.state {
             CoreState::Idle => self.check_queue(),
             CoreState::Computing => self.compute_step(),
             CoreState::Activating => self.activation_step(),
-            CoreState::Stalled => self.handle_stall(),
+            CoreState::Stalled => {
+                self.handle_stall();
+                Ok(())
+            }
+            CoreState::Faulted => Ok(()),
+        };
+        if let Err(error) = result {
+            self.fault = Some(error);
+            self.state = CoreState::Faulted;
         }
 
         self.update_stats();
     }
 
-    fn compute_step(&mut self) {
-        if let Some(op) = &mut self.matrix_unit.current_op {
-            if self.matrix_unit.cycles_remaining > 0 {
-                self.matrix_unit.cycles_remaining -= 1;
-                self.stats.total_flops += self.get_flops_per_cycle(op);
-            } else {
-                // Computation complete
-                self.finish_computation();
-                if op.current_position >= op.total_elements {
-                    self.state = CoreState::Activating;
-                }
+    // Drains the queue; a fault discards whatever was still queued
+    pub fn run(&mut self) -> GPUResult<()> {
+        let start = self.stats.cycles;
+        while self.state != CoreState::Faulted && !(self.state == CoreState::Idle && self.instruction_queue.is_empty()) {
+            if self.stats.cycles - start >= WATCHDOG_CYCLES {
+                self.fault = Some(GPUError::Timeout);
+                self.state = CoreState::Faulted;
+                break;
             }
-        } else if let Some(instruction) = self.instruction_queue.pop_front() {
-            self.start_computation(instruction);
-        } else {
+            self.tick();
+        }
+        if let Some(error) = self.fault.take() {
+            self.matrix_unit.current_op = None;
+            self.instruction_queue.clear();
             self.state = CoreState::Idle;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    pub fn fault(&self) -> Option<&GPUError> {
+        self.fault.as_ref()
+    }
+
+    pub fn state(&self) -> CoreState {
+        self.state
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn get_stats(&self) -> &TensorStats {
+        &self.stats
+    }
+
+    // Methods for visualization system
+    pub fn current_tile(&self) -> Option<(usize, usize, usize)> {
+        self.matrix_unit.current_op.as_ref().filter(|op| op.total_tiles > 0).map(|op| {
+            (op.current_position / op.tiles.1, op.current_position % op.tiles.1, op.k_step)
+        })
+    }
+
+    pub fn progress(&self) -> f32 {
+        match &self.matrix_unit.current_op {
+            Some(op) if op.total_tiles > 0 => op.current_position as f32 / op.total_tiles as f32,
+            _ => 0.0,
+        }
+    }
+
+    fn check_queue(&mut self) -> GPUResult<()> {
+        match self.instruction_queue.pop_front() {
+            Some(instruction) => self.start_computation(instruction),
+            None => Ok(()),
+        }
+    }
+
+    fn compute_step(&mut self) -> GPUResult<()> {
+        self.stats.compute_cycles += 1;
+        if self.matrix_unit.cycles_remaining > 1 {
+            self.matrix_unit.cycles_remaining -= 1;
+            return Ok(());
+        }
+        self.matrix_unit.cycles_remaining = 0;
+
+        // The MMA retires: fold this k slice into the accumulator
+        self.finish_computation()?;
+        let op = self.matrix_unit.current_op.as_mut().ok_or_else(no_operation)?;
+        op.k_step += 1;
+        if op.k_step == op.tiles.2 {
+            self.start_epilogue()
+        } else {
+            self.begin_step()
         }
     }
 
-    fn activation_step(&mut self) {
-        if self.activation_unit.cycles_remaining > 0 {
+    fn activation_step(&mut self) -> GPUResult<()> {
+        self.stats.activation_cycles += 1;
+        if self.activation_unit.cycles_remaining > 1 {
             self.activation_unit.cycles_remaining -= 1;
+            return Ok(());
+        }
+        self.activation_unit.cycles_remaining = 0;
+        self.apply_activation()
+    }
+
+    fn handle_stall(&mut self) {
+        self.stats.stall_cycles += 1;
+        if self.matrix_unit.stall_remaining > 1 {
+            self.matrix_unit.stall_remaining -= 1;
         } else {
-            self.apply_activation();
+            self.matrix_unit.stall_remaining = 0;
             self.state = CoreState::Computing;
         }
     }
 
-    // Helper methods...
+    fn start_computation(&mut self, instruction: TensorInstruction) -> GPUResult<()> {
+        match instruction.op_type {
+            TensorOp::MatMul => self.start_matmul(instruction),
+            TensorOp::Add | TensorOp::Convert(_) => self.start_elementwise(instruction),
+        }
+    }
+
+    fn start_matmul(&mut self, instruction: TensorInstruction) -> GPUResult<()> {
+        let a = self.operand(instruction.inputs[0])?;
+        let b = self.operand(instruction.inputs[1])?;
+        if a.precision != b.precision {
+            return Err(GPUError::InvalidProgram(format!("mixed operand precisions {:?} and {:?}", a.precision, b.precision)));
+        }
+        if a.cols() != b.rows() {
+            return Err(GPUError::InvalidProgram(format!("cannot multiply {}x{} by {}x{}", a.rows(), a.cols(), b.rows(), b.cols())));
+        }
+        let (m, n, k) = (a.rows(), b.cols(), a.cols());
+        if m * n * k == 0 {
+            return Err(GPUError::InvalidProgram("empty matrix".to_string()));
+        }
+        if let Some(slot) = instruction.bias {
+            let bias = self.operand(slot)?;
+            if bias.len() != n {
+                return Err(GPUError::InvalidProgram(format!("bias of {} for {} columns", bias.len(), n)));
+            }
+        }
+        if let Some(slot) = instruction.accumulate {
+            let c = self.operand(slot)?;
+            if c.rows() != m || c.cols() != n {
+                return Err(GPUError::InvalidProgram(format!("accumulate input is {}x{}, expected {}x{}", c.rows(), c.cols(), m, n)));
+            }
+        }
+
+        let precision = a.precision;
+        let tile = instruction.tile.unwrap_or_else(|| TileShape::for_precision(precision));
+        if tile.m == 0 || tile.n == 0 || tile.k == 0 {
+            return Err(GPUError::InvalidProgram("empty tile shape".to_string()));
+        }
+        let tiles = (m.div_ceil(tile.m), n.div_ceil(tile.n), k.div_ceil(tile.k));
+        self.accumulator.scale_factor = if precision == Precision::INT8 { a.scale * b.scale } else { 1.0 };
+        self.activation_unit.function = instruction.activation;
+
+        self.matrix_unit.current_op = Some(MatrixOperation {
+            instruction,
+            tile,
+            precision,
+            dims: (m, n, k),
+            tiles,
+            current_position: 0,
+            k_step: 0,
+            total_tiles: tiles.0 * tiles.1,
+            output: Tensor::zeros(m, n),
+        });
+        self.stats.total_flops += 2 * (m * n * k) as u64;
+        self.begin_step()
+    }
+
+    // Add and Convert stream through the activation unit without the matrix unit
+    fn start_elementwise(&mut self, instruction: TensorInstruction) -> GPUResult<()> {
+        let input = self.operand(instruction.inputs[0])?;
+        let mut output = match instruction.op_type {
+            TensorOp::Add => {
+                let other = self.operand(instruction.inputs[1])?;
+                if input.shape != other.shape {
+                    return Err(GPUError::InvalidProgram("add of mismatched shapes".to_string()));
+                }
+                let sum = input.to_f32().iter().zip(other.to_f32()).map(|(x, y)| x + y).collect();
+                Tensor::from_shape(input.shape.clone(), sum)
+            }
+            _ => Tensor::from_shape(input.shape.clone(), input.to_f32()),
+        };
+        output.data.iter_mut().for_each(|value| *value = instruction.activation.apply(*value));
+        if let TensorOp::Convert(precision) = instruction.op_type {
+            output = output.quantize(precision);
+        }
+
+        let lanes = self.config.activation_lanes.max(1) as usize;
+        self.activation_unit.cycles_remaining = (output.len().div_ceil(lanes) as u32).max(1) * instruction.activation.cost();
+        self.matrix_unit.current_op = Some(MatrixOperation {
+            instruction,
+            tile: TileShape { m: 1, n: 1, k: 1 },
+            precision: output.precision,
+            dims: (output.rows(), output.cols(), 0),
+            tiles: (0, 0, 0),
+            current_position: 0,
+            k_step: 0,
+            total_tiles: 0,
+            output,
+        });
+        self.state = CoreState::Activating;
+        Ok(())
+    }
+
+    // Loads the fragments for the current (tile, k) step. Operand fetch is
+    // double buffered behind the previous MMA, so only the excess stalls, except
+    // on the first step of an operation where nothing hides it.
+    fn begin_step(&mut self) -> GPUResult<()> {
+        let op = self.matrix_unit.current_op.as_ref().ok_or_else(no_operation)?;
+        let first = op.current_position == 0 && op.k_step == 0;
+        let (tile, (m, n, k)) = (op.tile, op.dims);
+        let (row0, col0) = ((op.current_position / op.tiles.1) * tile.m, (op.current_position % op.tiles.1) * tile.n);
+        let k0 = op.k_step * tile.k;
+        let a = self.operand(op.instruction.inputs[0])?;
+        let b = self.operand(op.instruction.inputs[1])?;
+
+        let mut a_fragment = vec![0.0; tile.m * tile.k];
+        for i in 0..tile.m.min(m.saturating_sub(row0)) {
+            for kk in 0..tile.k.min(k.saturating_sub(k0)) {
+                a_fragment[i * tile.k + kk] = a.data[(row0 + i) * k + k0 + kk];
+            }
+        }
+        let mut b_fragment = vec![0.0; tile.k * tile.n];
+        for kk in 0..tile.k.min(k.saturating_sub(k0)) {
+            for j in 0..tile.n.min(n.saturating_sub(col0)) {
+                b_fragment[kk * tile.n + j] = b.data[(k0 + kk) * n + col0 + j];
+            }
+        }
+        self.matrix_unit.input_buffers = [a_fragment, b_fragment];
+
+        let macs = (tile.m * tile.n * tile.k) as u32;
+        let math = macs.div_ceil(self.config.macs_per_cycle(op.precision)) + self.config.issue_overhead;
+        let bytes = ((tile.m * tile.k + tile.k * tile.n) * op.precision.bytes()) as u32;
+        let fetch = bytes.div_ceil(self.config.operand_bandwidth.max(1));
+        self.matrix_unit.cycles_remaining = math;
+        self.matrix_unit.stall_remaining = if first { fetch } else { fetch.saturating_sub(math) };
+        self.stats.mma_instructions += 1;
+        self.stats.padded_flops += 2 * macs as u64;
+
+        if op.k_step == 0 {
+            self.accumulator.reset(tile.m * tile.n);
+        }
+        self.state = if self.matrix_unit.stall_remaining > 0 { CoreState::Stalled } else { CoreState::Computing };
+        Ok(())
+    }
+
+    fn finish_computation(&mut self) -> GPUResult<()> {
+        let op = self.matrix_unit.current_op.as_ref().ok_or_else(no_operation)?;
+        let tile = op.tile;
+        let [a, b] = &self.matrix_unit.input_buffers;
+        for i in 0..tile.m {
+            for j in 0..tile.n {
+                let index = i * tile.n + j;
+                if op.precision == Precision::INT8 {
+                    let dot: i32 = (0..tile.k).map(|kk| a[i * tile.k + kk] as i32 * b[kk * tile.n + j] as i32).sum();
+                    self.accumulator.integers[index] += dot;
+                } else {
+                    for kk in 0..tile.k {
+                        self.accumulator.values[index] += a[i * tile.k + kk] * b[kk * tile.n + j];
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn start_epilogue(&mut self) -> GPUResult<()> {
+        let op = self.matrix_unit.current_op.as_ref().ok_or_else(no_operation)?;
+        let tile = op.tile;
+        let (row0, col0) = ((op.current_position / op.tiles.1) * tile.m, (op.current_position % op.tiles.1) * tile.n);
+        let (m, n, _) = op.dims;
+        let (rows, cols) = (tile.m.min(m - row0), tile.n.min(n - col0));
+
+        let bias = op.instruction.bias.map(|slot| self.operand(slot).map(Tensor::to_f32)).transpose()?;
+        let accumulate = op.instruction.accumulate.map(|slot| self.operand(slot).map(Tensor::to_f32)).transpose()?;
+        self.accumulator.bias = (0..cols).map(|j| bias.as_ref().map_or(0.0, |bias| bias[col0 + j])).collect();
+
+        let mut input = Vec::with_capacity(rows * cols);
+        for i in 0..rows {
+            for j in 0..cols {
+                let index = i * tile.n + j;
+                let product = if op.precision == Precision::INT8 {
+                    self.accumulator.integers[index] as f32 * self.accumulator.scale_factor
+                } else {
+                    self.accumulator.values[index]
+                };
+                let c = accumulate.as_ref().map_or(0.0, |c| c[(row0 + i) * n + col0 + j]);
+                input.push(product + c + self.accumulator.bias[j]);
+            }
+        }
+
+        let lanes = self.config.activation_lanes.max(1) as usize;
+        self.activation_unit.cycles_remaining = (input.len().div_ceil(lanes) as u32).max(1) * self.activation_unit.function.cost();
+        self.activation_unit.input_buffer = input;
+        self.state = CoreState::Activating;
+        Ok(())
+    }
+
+    fn apply_activation(&mut self) -> GPUResult<()> {
+        let function = self.activation_unit.function;
+        self.activation_unit.output_buffer = self.activation_unit.input_buffer.iter().map(|&x| function.apply(x)).collect();
+
+        let op = self.matrix_unit.current_op.as_mut().ok_or_else(no_operation)?;
+        if op.instruction.op_type == TensorOp::MatMul && op.current_position < op.total_tiles {
+            // Write the finished tile back and move to the next one
+            let tile = op.tile;
+            let (row0, col0) = ((op.current_position / op.tiles.1) * tile.m, (op.current_position % op.tiles.1) * tile.n);
+            let n = op.dims.1;
+            let cols = self.accumulator.bias.len();
+            for (index, value) in self.activation_unit.output_buffer.iter().enumerate() {
+                op.output.data[(row0 + index / cols) * n + col0 + index % cols] = *value;
+            }
+            op.current_position += 1;
+            op.k_step = 0;
+            if op.current_position < op.total_tiles {
+                return self.begin_step();
+            }
+        }
+
+        let op = self.matrix_unit.current_op.take().ok_or_else(no_operation)?;
+        self.tensors.insert(op.instruction.output, op.output);
+        self.stats.operations_completed += 1;
+        self.state = CoreState::Idle;
+        Ok(())
+    }
+
+    fn update_stats(&mut self) {
+        if self.stats.mac_slots > 0 {
+            self.stats.utilization = (self.stats.total_flops / 2) as f32 / self.stats.mac_slots as f32;
+            self.stats.utilization = self.stats.utilization.min(1.0);
+        }
+        if self.stats.cycles > 0 {
+            self.stats.current_throughput = self.stats.total_flops as f32 / self.stats.cycles as f32;
+        }
+    }
+
+    // Helper methods
+    fn operand(&self, slot: usize) -> GPUResult<&Tensor> {
+        self.tensors.get(&slot).ok_or_else(|| GPUError::InvalidProgram(format!("tensor slot {} is empty", slot)))
+    }
+}
+
+impl ComputeCore for TensorCore {
+    fn tick(&mut self) {
+        TensorCore::tick(self);
+    }
+
+    fn is_idle(&self) -> bool {
+        self.state == CoreState::Idle && self.instruction_queue.is_empty()
+    }
+
+    fn get_utilization(&self) -> f32 {
+        self.stats.utilization
+    }
 }
 
 impl MatrixUnit {
@@ -144,8 +564,507 @@ impl MatrixUnit {
         Self {
             current_op: None,
             input_buffers: [Vec::new(), Vec::new()],
+            cycles_remaining: 0,
+            stall_remaining: 0,
+        }
+    }
+}
+
+impl ActivationUnit {
+    fn new() -> Self {
+        Self {
+            function: ActivationFunction::None,
+            input_buffer: Vec::new(),
             output_buffer: Vec::new(),
             cycles_remaining: 0,
         }
     }
 }
+
+impl Accumulator {
+    fn new() -> Self {
+        Self {
+            values: Vec::new(),
+            integers: Vec::new(),
+            scale_factor: 1.0,
+            bias: Vec::new(),
+        }
+    }
+
+    fn reset(&mut self, size: usize) {
+        self.values.clear();
+        self.values.resize(size, 0.0);
+        self.integers.clear();
+        self.integers.resize(size, 0);
+    }
+}
+
+impl TensorInstruction {
+    // D = activation(A x B + C + bias)
+    pub fn matmul(a: usize, b: usize, output: usize) -> Self {
+        Self {
+            op_type: TensorOp::MatMul,
+            inputs: [a, b],
+            bias: None,
+            accumulate: None,
+            output,
+            tile: None,
+            activation: ActivationFunction::None,
+        }
+    }
+
+    pub fn add(a: usize, b: usize, output: usize) -> Self {
+        Self { op_type: TensorOp::Add, ..Self::matmul(a, b, output) }
+    }
+
+    pub fn convert(input: usize, precision: Precision, output: usize) -> Self {
+        Self { op_type: TensorOp::Convert(precision), ..Self::matmul(input, input, output) }
+    }
+
+    pub fn with_bias(mut self, slot: usize) -> Self {
+        self.bias = Some(slot);
+        self
+    }
+
+    pub fn with_accumulate(mut self, slot: usize) -> Self {
+        self.accumulate = Some(slot);
+        self
+    }
+
+    pub fn with_activation(mut self, activation: ActivationFunction) -> Self {
+        self.activation = activation;
+        self
+    }
+
+    pub fn with_tile(mut self, tile: TileShape) -> Self {
+        self.tile = Some(tile);
+        self
+    }
+}
+
+impl ActivationFunction {
+    pub fn apply(&self, x: f32) -> f32 {
+        match self {
+            ActivationFunction::ReLU => x.max(0.0),
+            // tanh approximation, as most inference kernels use
+            ActivationFunction::GELU => 0.5 * x * (1.0 + (0.797_884_6 * (x + 0.044_715 * x * x * x)).tanh()),
+            ActivationFunction::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+            ActivationFunction::Tanh => x.tanh(),
+            ActivationFunction::None => x,
+        }
+    }
+
+    // Epilogue passes per element; transcendental functions go through the SFU
+    fn cost(&self) -> u32 {
+        match self {
+            ActivationFunction::None | ActivationFunction::ReLU => 1,
+            ActivationFunction::GELU | ActivationFunction::Sigmoid | ActivationFunction::Tanh => 4,
+        }
+    }
+}
+
+impl Precision {
+    pub fn bytes(&self) -> usize {
+        match self {
+            Precision::FP32 => 4,
+            Precision::FP16 | Precision::BF16 => 2,
+            Precision::INT8 => 1,
+        }
+    }
+
+    // Round to the nearest representable value, ties to even
+    pub fn round(&self, x: f32) -> f32 {
+        match self {
+            Precision::FP32 => x,
+            Precision::FP16 => round_to_f16(x),
+            Precision::BF16 => round_to_bf16(x),
+            Precision::INT8 => x.round_ties_even().clamp(-127.0, 127.0),
+        }
+    }
+
+    // Error against an f32 reference a small sigmoid-output MLP should stay within
+    pub fn mlp_tolerance(&self) -> f32 {
+        match self {
+            Precision::FP32 => 1e-4,
+            Precision::FP16 => 1e-2,
+            Precision::BF16 | Precision::INT8 => 5e-2,
+        }
+    }
+}
+
+impl TileShape {
+    pub fn new(m: usize, n: usize, k: usize) -> Self {
+        Self { m, n, k }
+    }
+
+    // The native MMA shapes: k doubles as the inputs narrow
+    pub fn for_precision(precision: Precision) -> Self {
+        match precision {
+            Precision::FP32 => Self::new(16, 8, 8),
+            Precision::FP16 | Precision::BF16 => Self::new(16, 8, 16),
+            Precision::INT8 => Self::new(16, 8, 32),
+        }
+    }
+}
+
+impl Shape {
+    pub fn matrix(rows: usize, cols: usize) -> Self {
+        Self { dimensions: vec![rows, cols] }
+    }
+
+    pub fn dimensions(&self) -> &[usize] {
+        &self.dimensions
+    }
+
+    pub fn elements(&self) -> usize {
+        self.dimensions.iter().product()
+    }
+}
+
+impl Tensor {
+    pub fn new(rows: usize, cols: usize, values: Vec<f32>) -> GPUResult<Self> {
+        if values.len() != rows * cols {
+            return Err(GPUError::InvalidProgram(format!("{} values for a {}x{} tensor", values.len(), rows, cols)));
+        }
+        Ok(Self::from_shape(Shape::matrix(rows, cols), values))
+    }
+
+    pub fn zeros(rows: usize, cols: usize) -> Self {
+        Self::from_shape(Shape::matrix(rows, cols), vec![0.0; rows * cols])
+    }
+
+    fn from_shape(shape: Shape, data: Vec<f32>) -> Self {
+        Self { shape, precision: Precision::FP32, data, scale: 1.0 }
+    }
+
+    // INT8 uses symmetric per-tensor scaling over [-127, 127]
+    pub fn quantize(&self, precision: Precision) -> Tensor {
+        let values = self.to_f32();
+        let scale = if precision == Precision::INT8 {
+            let max = values.iter().fold(0.0f32, |max, value| max.max(value.abs()));
+            if max > 0.0 { max / 127.0 } else { 1.0 }
+        } else {
+            1.0
+        };
+        Tensor {
+            shape: self.shape.clone(),
+            precision,
+            data: values.iter().map(|value| precision.round(value / scale)).collect(),
+            scale,
+        }
+    }
+
+    pub fn to_f32(&self) -> Vec<f32> {
+        self.data.iter().map(|value| value * self.scale).collect()
+    }
+
+    pub fn rows(&self) -> usize {
+        self.shape.dimensions[0]
+    }
+
+    pub fn cols(&self) -> usize {
+        self.shape.dimensions.get(1).copied().unwrap_or(1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
+}
+
+impl TensorCoreConfig {
+    pub fn macs_per_cycle(&self, precision: Precision) -> u32 {
+        match precision {
+            Precision::FP32 => self.fp16_macs_per_cycle / 4,
+            Precision::FP16 | Precision::BF16 => self.fp16_macs_per_cycle,
+            Precision::INT8 => self.fp16_macs_per_cycle * 2,
+        }
+    }
+}
+
+impl Default for TensorCoreConfig {
+    fn default() -> Self {
+        Self {
+            queue_depth: 64,
+            fp16_macs_per_cycle: 256,
+            operand_bandwidth: 128,
+            issue_overhead: 2,
+            activation_lanes: 32,
+        }
+    }
+}
+
+// MLP inference demo: a small fully connected network run layer by layer on a
+// tensor core, next to the same network evaluated on the host in f32
+pub struct MlpDemo {
+    layers: Vec<DenseLayer>,
+    tolerance: Option<f32>,
+}
+
+struct DenseLayer {
+    inputs: usize,
+    outputs: usize,
+    weights: Vec<f32>, // inputs x outputs, row major
+    bias: Vec<f32>,
+    activation: ActivationFunction,
+}
+
+#[derive(Debug, Clone)]
+pub struct MlpReport {
+    pub output: Vec<f32>,
+    pub reference: Vec<f32>,
+    pub max_abs_error: f32,
+    pub cycles: u64,
+}
+
+impl MlpDemo {
+    // Hidden layers use GELU, the output layer a sigmoid. Weights come from a
+    // fixed-seed generator so runs are reproducible.
+    pub fn new(sizes: &[usize], seed: u64) -> Self {
+        let mut state = seed;
+        let mut next = move || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((state >> 40) as f32 / (1u64 << 24) as f32) * 2.0 - 1.0
+        };
+
+        let layers = sizes.windows(2).enumerate().map(|(index, pair)| {
+            let (inputs, outputs) = (pair[0], pair[1]);
+            let range = 1.0 / (inputs as f32).sqrt();
+            DenseLayer {
+                inputs,
+                outputs,
+                weights: (0..inputs * outputs).map(|_| next() * range).collect(),
+                bias: (0..outputs).map(|_| next() * 0.1).collect(),
+                activation: if index + 2 == sizes.len() { ActivationFunction::Sigmoid } else { ActivationFunction::GELU },
+            }
+        }).collect();
+        Self { layers, tolerance: None }
+    }
+
+    // Overrides the largest error against the reference that `run` accepts
+    pub fn with_tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = Some(tolerance);
+        self
+    }
+
+    pub fn reference(&self, input: &[f32], batch: usize) -> Vec<f32> {
+        let mut activations = input.to_vec();
+        for layer in &self.layers {
+            let mut output = vec![0.0; batch * layer.outputs];
+            for row in 0..batch {
+                for column in 0..layer.outputs {
+                    let sum: f32 = (0..layer.inputs)
+                        .map(|i| activations[row * layer.inputs + i] * layer.weights[i * layer.outputs + column])
+                        .sum();
+                    output[row * layer.outputs + column] = layer.activation.apply(sum + layer.bias[column]);
+                }
+            }
+            activations = output;
+        }
+        activations
+    }
+
+    // Slot 0 holds the activations in flight; each layer's weights and bias
+    // sit in their own slots, and the bias and activation fuse into the GEMM.
+    // Fails if any output strays from the host reference by more than the tolerance.
+    pub fn run(&self, core: &mut TensorCore, input: &[f32], batch: usize, precision: Precision) -> GPUResult<MlpReport> {
+        let inputs = self.layers.first().map_or(0, |layer| layer.inputs);
+        let start = core.get_stats().cycles;
+
+        core.load_tensor(0, Tensor::new(batch, inputs, input.to_vec())?);
+        core.submit(TensorInstruction::convert(0, precision, 0))?;
+        for (index, layer) in self.layers.iter().enumerate() {
+            let (weights, bias) = (2 + 2 * index, 3 + 2 * index);
+            core.load_tensor(weights, Tensor::new(layer.inputs, layer.outputs, layer.weights.clone())?.quantize(precision));
+            core.load_tensor(bias, Tensor::new(1, layer.outputs, layer.bias.clone())?);
+            core.submit(TensorInstruction::matmul(0, weights, 1).with_bias(bias).with_activation(layer.activation))?;
+            if index + 1 < self.layers.len() {
+                core.submit(TensorInstruction::convert(1, precision, 0))?;
+            }
+        }
+        core.run()?;
+
+        let output = core
+            .take_tensor(1)
+            .map(|tensor| tensor.to_f32())
+            .ok_or_else(|| GPUError::InvalidProgram("network produced no output".to_string()))?;
+        let reference = self.reference(input, batch);
+        if output.len() != reference.len() {
+            return Err(GPUError::InvalidProgram(format!("{} outputs, expected {}", output.len(), reference.len())));
+        }
+        let max_abs_error = output.iter().zip(&reference).fold(0.0f32, |max, (x, y)| max.max((x - y).abs()));
+        let tolerance = self.tolerance.unwrap_or_else(|| precision.mlp_tolerance());
+        if max_abs_error.is_nan() || max_abs_error > tolerance {
+            return Err(GPUError::ToleranceExceeded { max_abs_error, tolerance });
+        }
+        Ok(MlpReport { output, reference, max_abs_error, cycles: core.get_stats().cycles - start })
+    }
+}
+
+fn no_operation() -> GPUError {
+    GPUError::InvalidProgram("no operation in flight".to_string())
+}
+
+fn round_to_f16(x: f32) -> f32 {
+    const MAX: f32 = 65504.0;
+    const MIN_NORMAL: f32 = 6.103_515_6e-5; // 2^-14
+    if !x.is_finite() {
+        return x;
+    }
+    if x.abs() < MIN_NORMAL {
+        // Subnormals are multiples of 2^-24
+        let step = 5.960_464_5e-8;
+        return (x / step).round_ties_even() * step;
+    }
+    let bits = x.to_bits();
+    let rounded = f32::from_bits((bits + 0x0FFF + ((bits >> 13) & 1)) & !0x1FFF);
+    if rounded.abs() > MAX { f32::INFINITY.copysign(x) } else { rounded }
+}
+
+fn round_to_bf16(x: f32) -> f32 {
+    if x.is_nan() {
+        return x;
+    }
+    let bits = x.to_bits();
+    f32::from_bits((bits + 0x7FFF + ((bits >> 16) & 1)) & !0xFFFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matrix(rows: usize, cols: usize, seed: usize) -> Tensor {
+        let values = (0..rows * cols).map(|i| ((i * 7 + seed) % 11) as f32 - 5.0).collect();
+        Tensor::new(rows, cols, values).unwrap()
+    }
+
+    fn host_matmul(a: &Tensor, b: &Tensor) -> Vec<f32> {
+        let (a_values, b_values) = (a.to_f32(), b.to_f32());
+        let (m, n, k) = (a.rows(), b.cols(), a.cols());
+        (0..m * n)
+            .map(|index| (0..k).map(|kk| a_values[index / n * k + kk] * b_values[kk * n + index % n]).sum())
+            .collect()
+    }
+
+    fn mlp_input(batch: usize, width: usize) -> Vec<f32> {
+        (0..batch * width).map(|i| ((i % 13) as f32 - 6.0) / 6.0).collect()
+    }
+
+    #[test]
+    fn ragged_tiles_multiply_exactly_with_a_fused_epilogue() {
+        let mut core = TensorCore::new(0);
+        let (a, b) = (matrix(5, 7, 1), matrix(7, 3, 4));
+        let bias = Tensor::new(1, 3, vec![1.0, -100.0, 0.5]).unwrap();
+        let expected: Vec<f32> = host_matmul(&a, &b)
+            .iter()
+            .enumerate()
+            .map(|(index, value)| (value + bias.to_f32()[index % 3]).max(0.0))
+            .collect();
+
+        core.load_tensor(0, a);
+        core.load_tensor(1, b);
+        core.load_tensor(2, bias);
+        let instruction = TensorInstruction::matmul(0, 1, 3)
+            .with_bias(2)
+            .with_activation(ActivationFunction::ReLU)
+            .with_tile(TileShape::new(2, 2, 2));
+        core.submit(instruction).unwrap();
+        core.run().unwrap();
+
+        assert_eq!(core.tensor(3).unwrap().to_f32(), expected);
+        let stats = core.get_stats();
+        assert_eq!(stats.total_flops, 2 * 5 * 3 * 7);
+        // 3 x 2 output tiles, 4 k steps each, all padded to 2x2x2
+        assert_eq!(stats.mma_instructions, 24);
+        assert_eq!(stats.padded_flops, 24 * 2 * 8);
+    }
+
+    #[test]
+    fn int8_products_accumulate_exactly_before_rescaling() {
+        let mut core = TensorCore::new(0);
+        let (a, b) = (matrix(16, 64, 2).quantize(Precision::INT8), matrix(64, 8, 5).quantize(Precision::INT8));
+        let codes: Vec<f32> = host_matmul(
+            &Tensor::new(16, 64, a.data.clone()).unwrap(),
+            &Tensor::new(64, 8, b.data.clone()).unwrap(),
+        );
+        let scale = a.scale() * b.scale();
+
+        core.load_tensor(0, a);
+        core.load_tensor(1, b);
+        core.submit(TensorInstruction::matmul(0, 1, 2)).unwrap();
+        core.run().unwrap();
+
+        let expected: Vec<f32> = codes.iter().map(|code| code * scale).collect();
+        assert_eq!(core.tensor(2).unwrap().to_f32(), expected);
+    }
+
+    #[test]
+    fn smaller_tiles_cost_more_cycles_for_the_same_gemm() {
+        let cycles = |tile: TileShape| {
+            let mut core = TensorCore::new(0);
+            core.load_tensor(0, matrix(64, 64, 0).quantize(Precision::FP16));
+            core.load_tensor(1, matrix(64, 64, 3).quantize(Precision::FP16));
+            core.submit(TensorInstruction::matmul(0, 1, 2).with_tile(tile)).unwrap();
+            core.run().unwrap();
+            core.get_stats().cycles
+        };
+        let native = cycles(TileShape::for_precision(Precision::FP16));
+        let small = cycles(TileShape::new(4, 4, 4));
+        assert!(small > native, "4x4x4 tiles took {} cycles, native {}", small, native);
+    }
+
+    #[test]
+    fn missing_operands_fault_instead_of_panicking() {
+        let mut core = TensorCore::new(0);
+        core.load_tensor(0, matrix(4, 4, 0));
+        core.submit(TensorInstruction::matmul(0, 9, 1)).unwrap();
+        assert!(matches!(core.run(), Err(GPUError::InvalidProgram(_))));
+        assert_eq!(core.state(), CoreState::Idle);
+
+        // An operand taken away between MMA steps faults the next step
+        core.load_tensor(1, matrix(4, 64, 1));
+        core.load_tensor(2, matrix(64, 4, 2));
+        core.submit(TensorInstruction::matmul(1, 2, 3).with_tile(TileShape::new(4, 4, 4))).unwrap();
+        core.tick();
+        assert_ne!(core.state(), CoreState::Idle);
+        core.take_tensor(2);
+        assert!(matches!(core.run(), Err(GPUError::InvalidProgram(_))));
+        assert!(core.tensor(3).is_none());
+    }
+
+    #[test]
+    fn mlp_demo_tracks_the_host_reference_at_every_precision() {
+        let demo = MlpDemo::new(&[32, 64, 64, 10], 7);
+        let input = mlp_input(8, 32);
+        for precision in [Precision::FP32, Precision::FP16, Precision::BF16, Precision::INT8] {
+            let mut core = TensorCore::new(0);
+            let report = demo.run(&mut core, &input, 8, precision).unwrap();
+            assert_eq!(report.output.len(), 8 * 10);
+            assert!(report.max_abs_error <= precision.mlp_tolerance());
+            assert!(report.cycles > 0);
+        }
+    }
+
+    #[test]
+    fn mlp_demo_fails_beyond_its_tolerance() {
+        let demo = MlpDemo::new(&[32, 64, 10], 7).with_tolerance(1e-6);
+        let mut core = TensorCore::new(0);
+        let result = demo.run(&mut core, &mlp_input(4, 32), 4, Precision::INT8);
+        assert!(matches!(result, Err(GPUError::ToleranceExceeded { tolerance, .. }) if tolerance == 1e-6));
+    }
+}
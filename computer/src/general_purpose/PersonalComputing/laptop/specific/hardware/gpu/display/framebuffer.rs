@@ -19,11 +19,12 @@ pub enum PixelFormat {
     R8G8B8A8,
 }
 
-struct FramebufferStats {
-    frames_rendered: u64,
-    pixels_written: u64,
-    bandwidth_usage: f32,
-    refresh_rate: f32,
+#[derive(Debug, Clone, Default)]
+pub struct FramebufferStats {
+    pub frames_rendered: u64,
+    pub pixels_written: u64,
+    pub bandwidth_usage: f32,
+    pub refresh_rate: f32,
 }
 
 impl Framebuffer {
@@ -43,12 +44,14 @@ impl Framebuffer {
 
     pub fn clear(&mut self, color: [u8; 4]) {
         let pixel_size = self.format.bytes_per_pixel();
+        let encoded = self.format.encode(color);
         for pixel in self.buffer.chunks_mut(pixel_size) {
-            pixel.copy_from_slice(&color[..pixel_size]);
+            pixel.copy_from_slice(&encoded[..pixel_size]);
         }
         self.stats.pixels_written += (self.width * self.height) as u64;
     }
 
+    // Colors are always RGBA; the framebuffer stores them in its own byte order
     pub fn write_pixel(&mut self, x: u32, y: u32, color: [u8; 4]) -> GPUResult<()> {
         if x >= self.width || y >= self.height {
             return Err(GPUError::OutOfBounds);
@@ -56,12 +59,47 @@ impl Framebuffer {
 
         let pixel_size = self.format.bytes_per_pixel();
         let offset = (y * self.width + x) as usize * pixel_size;
-        self.buffer[offset..offset + pixel_size].copy_from_slice(&color[..pixel_size]);
+        let encoded = self.format.encode(color);
+        self.buffer[offset..offset + pixel_size].copy_from_slice(&encoded[..pixel_size]);
         self.stats.pixels_written += 1;
         
         Ok(())
     }
 
+    pub fn read_pixel(&self, x: u32, y: u32) -> GPUResult<[u8; 4]> {
+        if x >= self.width || y >= self.height {
+            return Err(GPUError::OutOfBounds);
+        }
+
+        let pixel_size = self.format.bytes_per_pixel();
+        let offset = (y * self.width + x) as usize * pixel_size;
+        Ok(self.format.decode(&self.buffer[offset..offset + pixel_size]))
+    }
+
+    // Copies the frame into a 0RGB window buffer such as minifb's, at (x, y)
+    pub fn blit(&self, target: &mut [u32], target_width: usize, x: usize, y: usize) {
+        let target_height = target.len() / target_width.max(1);
+        let pixel_size = self.format.bytes_per_pixel();
+        for row in 0..self.height as usize {
+            if y + row >= target_height {
+                break;
+            }
+            for column in 0..(self.width as usize).min(target_width.saturating_sub(x)) {
+                let offset = (row * self.width as usize + column) * pixel_size;
+                let [r, g, b, _] = self.format.decode(&self.buffer[offset..offset + pixel_size]);
+                target[(y + row) * target_width + x + column] = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+            }
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
     pub fn present(&mut self, vram: &mut VRAMController) -> GPUResult<()> {
         vram.write(self.vram_address, &self.buffer)?;
         self.stats.frames_rendered += 1;
@@ -80,6 +118,10 @@ impl Framebuffer {
 
         Ok(())
     }
+
+    pub fn get_stats(&self) -> &FramebufferStats {
+        &self.stats
+    }
 }
 
 impl PixelFormat {
@@ -89,4 +131,22 @@ impl PixelFormat {
             PixelFormat::RGB8 | PixelFormat::BGR8 => 3,
         }
     }
+
+    fn encode(&self, [r, g, b, a]: [u8; 4]) -> [u8; 4] {
+        match self {
+            PixelFormat::RGBA8 | PixelFormat::R8G8B8A8 => [r, g, b, a],
+            PixelFormat::BGRA8 => [b, g, r, a],
+            PixelFormat::RGB8 => [r, g, b, 255],
+            PixelFormat::BGR8 => [b, g, r, 255],
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> [u8; 4] {
+        match self {
+            PixelFormat::RGBA8 | PixelFormat::R8G8B8A8 => [bytes[0], bytes[1], bytes[2], bytes[3]],
+            PixelFormat::BGRA8 => [bytes[2], bytes[1], bytes[0], bytes[3]],
+            PixelFormat::RGB8 => [bytes[0], bytes[1], bytes[2], 255],
+            PixelFormat::BGR8 => [bytes[2], bytes[1], bytes[0], 255],
+        }
+    }
 }
@@ -7,16 +7,180 @@ pub use self::framebuffer::Framebuffer;
 pub use self::output::DisplayOutput;
 pub use self::rasterizer::Rasterizer;
 
+use super::compute::{Mat4, Vec3};
+use super::error::GPUResult;
+use super::memory::VRAMController;
+use crate::hardware::bus::mmio::{AccessSize, MmioDevice};
+use crate::hardware::bus::BusError;
+use self::framebuffer::PixelFormat;
+use self::rasterizer::{Mesh, PolygonMode, ShadingMode};
+
+// The display engine's registers, at this offset inside the GPU's BAR0
+pub const DISPLAY_REGISTER_OFFSET: u64 = 0x61_0000;
+pub const DISPLAY_REGION_SIZE: u64 = 0x1000;
+
+// Register offsets
+pub const DISPLAY_WIDTH: u64 = 0x00;   // Read only
+pub const DISPLAY_HEIGHT: u64 = 0x04;  // Read only
+pub const DISPLAY_CONTROL: u64 = 0x08;
+pub const DISPLAY_STATUS: u64 = 0x0C;  // Read only
+pub const DISPLAY_RENDER: u64 = 0x10;  // Write a time in ms to draw a frame; reads the frame count
+
+pub const CONTROL_ENABLE: u32 = 1 << 0;
+pub const CONTROL_WIREFRAME: u32 = 1 << 1;
+pub const STATUS_ENABLED: u32 = 1 << 0;
+pub const STATUS_FAULT: u32 = 1 << 1; // The last frame failed to render
+
 pub struct Display {
     output: DisplayOutput,
     rasterizer: Rasterizer,
+    framebuffer: Framebuffer,
+    control: u32,
+    frames: u64,
+    faulted: bool,
 }
 
 impl Display {
-    pub fn new() -> Self {
-        Self {
-            output: DisplayOutput::new(),
-            rasterizer: Rasterizer::new(),
+    pub fn new(width: u32, height: u32, vram: &mut VRAMController) -> GPUResult<Self> {
+        Ok(Self {
+            output: DisplayOutput::new(2),
+            rasterizer: Rasterizer::new(width, height),
+            framebuffer: Framebuffer::new(width, height, PixelFormat::RGBA8, vram)?,
+            control: 0,
+            frames: 0,
+            faulted: false,
+        })
+    }
+
+    // A lit, spinning cube: exercises every stage of the pipeline once per frame
+    pub fn render_scene(&mut self, time: f32) -> GPUResult<()> {
+        let aspect = self.framebuffer.width() as f32 / self.framebuffer.height() as f32;
+        self.framebuffer.clear([32, 32, 32, 255]);
+        self.rasterizer.clear_depth();
+        self.rasterizer.set_projection(Mat4::perspective(60f32.to_radians(), aspect, 0.1, 100.0));
+        self.rasterizer.set_view(Mat4::look_at(&Vec3::new(0.0, 0.0, 3.0), &Vec3::new(0.0, 0.0, 0.0), &Vec3::new(0.0, 1.0, 0.0)));
+        self.rasterizer.set_model(Mat4::rotation_y(time).multiply(&Mat4::rotation_x(time * 0.7)));
+        self.rasterizer.set_shading(ShadingMode::Lambert { light_direction: Vec3::new(-0.4, -0.6, -1.0), ambient: 0.2 });
+        self.rasterizer.draw_mesh(&Mesh::cube(), &mut self.framebuffer)
+    }
+
+    // Vsync paces presents to the panel's refresh
+    pub fn set_refresh_rate(&mut self, hz: u32) {
+        self.output.set_refresh_rate(hz);
+    }
+
+    pub fn set_wireframe(&mut self, wireframe: bool) {
+        self.rasterizer.set_polygon_mode(if wireframe { PolygonMode::Line } else { PolygonMode::Fill });
+    }
+
+    // Copy the last frame into a minifb window buffer
+    pub fn blit(&self, target: &mut [u32], target_width: usize, x: usize, y: usize) {
+        self.framebuffer.blit(target, target_width, x, y);
+    }
+
+    pub fn rasterizer(&self) -> &Rasterizer {
+        &self.rasterizer
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+}
+
+// Word registers only. A render request while the engine is disabled is
+// dropped, as the scanout isn't running.
+impl MmioDevice for Display {
+    fn read(&mut self, offset: u64, _size: AccessSize) -> Result<u64, BusError> {
+        let value = match offset {
+            DISPLAY_WIDTH => self.framebuffer.width(),
+            DISPLAY_HEIGHT => self.framebuffer.height(),
+            DISPLAY_CONTROL => self.control,
+            DISPLAY_STATUS => {
+                let enabled = if self.control & CONTROL_ENABLE != 0 { STATUS_ENABLED } else { 0 };
+                enabled | if self.faulted { STATUS_FAULT } else { 0 }
+            }
+            DISPLAY_RENDER => self.frames as u32,
+            _ => return Err(BusError::DeviceFault(offset)),
+        };
+        Ok(value as u64)
+    }
+
+    fn write(&mut self, offset: u64, _size: AccessSize, value: u64) -> Result<(), BusError> {
+        match offset {
+            DISPLAY_WIDTH | DISPLAY_HEIGHT | DISPLAY_STATUS => {}
+            DISPLAY_CONTROL => {
+                self.control = value as u32 & (CONTROL_ENABLE | CONTROL_WIREFRAME);
+                self.set_wireframe(self.control & CONTROL_WIREFRAME != 0);
+            }
+            DISPLAY_RENDER if self.control & CONTROL_ENABLE != 0 => {
+                self.faulted = self.render_scene(value as f32 / 1000.0).is_err();
+                if !self.faulted {
+                    self.frames += 1;
+                }
+            }
+            DISPLAY_RENDER => {}
+            _ => return Err(BusError::DeviceFault(offset)),
         }
+        Ok(())
     }
-}
\ No newline at end of file
+
+    fn supports(&self, size: AccessSize) -> bool {
+        size == AccessSize::Word
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::gpu::memory::VRAMConfig;
+
+    #[test]
+    fn rendered_scene_blits_into_a_window_buffer() {
+        let mut vram = VRAMController::with_config(VRAMConfig {
+            total_size: 1024 * 1024,
+            page_size: 4096,
+            num_banks: 4,
+            bank_width: 64,
+        });
+        let mut display = Display::new(64, 48, &mut vram).unwrap();
+        display.render_scene(0.5).unwrap();
+
+        // A 0RGB window twice the frame's width, with the frame at (16, 8)
+        let mut window = vec![0xFF00_0000u32; 128 * 64];
+        display.blit(&mut window, 128, 16, 8);
+        assert_eq!(window[0], 0xFF00_0000);
+        assert_eq!(window[8 * 128 + 16], 0x0020_2020);
+        let center = (8 + 24) * 128 + 16 + 32;
+        assert_ne!(window[center], 0x0020_2020);
+        assert!(display.rasterizer().get_stats().fragments_passed > 0);
+    }
+
+    #[test]
+    fn registers_drive_the_render_loop() {
+        let mut vram = VRAMController::with_config(VRAMConfig {
+            total_size: 1024 * 1024,
+            page_size: 4096,
+            num_banks: 4,
+            bank_width: 64,
+        });
+        let mut display = Display::new(64, 48, &mut vram).unwrap();
+        assert_eq!(display.read(DISPLAY_WIDTH, AccessSize::Word), Ok(64));
+        assert_eq!(display.read(DISPLAY_HEIGHT, AccessSize::Word), Ok(48));
+
+        // Disabled, so the request is dropped
+        display.write(DISPLAY_RENDER, AccessSize::Word, 500).unwrap();
+        assert_eq!(display.read(DISPLAY_RENDER, AccessSize::Word), Ok(0));
+
+        display.write(DISPLAY_CONTROL, AccessSize::Word, (CONTROL_ENABLE | CONTROL_WIREFRAME) as u64).unwrap();
+        display.write(DISPLAY_RENDER, AccessSize::Word, 500).unwrap();
+        display.write(DISPLAY_RENDER, AccessSize::Word, 516).unwrap();
+        assert_eq!(display.frames(), 2);
+        assert_eq!(display.read(DISPLAY_STATUS, AccessSize::Word), Ok(STATUS_ENABLED as u64));
+
+        // Sizes are fixed by the mode, and there's nothing past the render register
+        display.write(DISPLAY_WIDTH, AccessSize::Word, 1).unwrap();
+        assert_eq!(display.read(DISPLAY_WIDTH, AccessSize::Word), Ok(64));
+        assert_eq!(display.read(0x14, AccessSize::Word), Err(BusError::DeviceFault(0x14)));
+        assert!(!display.supports(AccessSize::Double));
+    }
+}
@@ -1,63 +1,67 @@
-use super::super::error::{GPUError, GPUResult};
-use super::framebuffer::Framebuffer;
-use std::time::{Duration, Instant};
+use super::super::error::GPUResult;
+use crate::simulation::time::SimTime;
 
 pub struct DisplayOutput {
     current_buffer: usize,
-    framebuffers: Vec<Framebuffer>,
+    buffer_count: usize,
     vsync_enabled: bool,
-    last_present: Instant,
-    refresh_interval: Duration,
+    last_present: SimTime,
+    refresh_interval: SimTime,
     stats: OutputStats,
 }
 
+#[derive(Default)]
 struct OutputStats {
     frames_displayed: u64,
-    frame_time: Duration,
+    frame_time: SimTime,
     vsync_waits: u64,
     tearing_events: u64,
 }
 
 impl DisplayOutput {
-    pub fn new(width: u32, height: u32, num_buffers: usize) -> Self {
+    pub fn new(num_buffers: usize) -> Self {
         Self {
             current_buffer: 0,
-            framebuffers: Vec::new(), // Initialize in constructor
+            buffer_count: num_buffers.max(1),
             vsync_enabled: true,
-            last_present: Instant::now(),
-            refresh_interval: Duration::from_nanos(16_666_667), // 60Hz
+            last_present: SimTime::ZERO,
+            refresh_interval: SimTime::from_ns(16_666_667), // 60Hz
             stats: OutputStats::default(),
         }
     }
 
-    pub fn present(&mut self) -> GPUResult<()> {
-        let now = Instant::now();
-        self.stats.frame_time = now - self.last_present;
+    // Presents at simulated time `now` and returns when the frame actually
+    // reaches the screen. With vsync that is the next refresh, which the
+    // caller schedules rather than sleeping the host thread.
+    pub fn present(&mut self, now: SimTime) -> GPUResult<SimTime> {
+        let elapsed = now - self.last_present;
+        let mut shown = now;
 
         if self.vsync_enabled {
-            let elapsed = now - self.last_present;
             if elapsed < self.refresh_interval {
                 self.stats.vsync_waits += 1;
-                std::thread::sleep(self.refresh_interval - elapsed);
+                shown = self.last_present + self.refresh_interval;
             }
-        } else if now - self.last_present < self.refresh_interval {
+        } else if elapsed < self.refresh_interval {
             self.stats.tearing_events += 1;
         }
 
         self.swap_buffers()?;
+        self.stats.frame_time = shown - self.last_present;
         self.stats.frames_displayed += 1;
-        self.last_present = now;
+        self.last_present = shown;
 
-        Ok(())
+        Ok(shown)
     }
 
     fn swap_buffers(&mut self) -> GPUResult<()> {
-        self.current_buffer = (self.current_buffer + 1) % self.framebuffers.len();
+        self.current_buffer = (self.current_buffer + 1) % self.buffer_count;
         Ok(())
     }
 
-    pub fn get_current_framebuffer(&mut self) -> &mut Framebuffer {
-        &mut self.framebuffers[self.current_buffer]
+    // Which of the swap chain's buffers is on screen
+    pub fn current_buffer(&self) -> usize {
+        self.current_buffer
     }
 
     pub fn set_vsync(&mut self, enabled: bool) {
@@ -65,6 +69,6 @@ impl DisplayOutput {
     }
 
     pub fn set_refresh_rate(&mut self, hz: u32) {
-        self.refresh_interval = Duration::from_secs(1) / hz;
+        self.refresh_interval = SimTime::from_cycles(1, hz as u64);
     }
 }
@@ -1,23 +1,28 @@
 use super::super::error::{GPUError, GPUResult};
 use super::super::compute::{Vec3, Vec4, Mat4};
+use super::super::compute::shader_core::Texture;
 use super::framebuffer::Framebuffer;
 
 pub struct Rasterizer {
     viewport: Viewport,
+    width: u32,
+    height: u32,
     depth_buffer: Vec<f32>,
     clip_planes: Vec<Plane>,
     transform: Transform,
     state: RasterState,
+    shading: ShadingMode,
     stats: RasterStats,
 }
 
-struct Viewport {
-    x: i32,
-    y: i32,
-    width: u32,
-    height: u32,
-    min_depth: f32,
-    max_depth: f32,
+#[derive(Clone, Copy, Debug)]
+pub struct Viewport {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub min_depth: f32,
+    pub max_depth: f32,
 }
 
 struct Transform {
@@ -27,9 +32,11 @@ struct Transform {
     viewport: Mat4,
 }
 
-struct Plane {
-    normal: Vec3,
-    distance: f32,
+// Keeps the side where dot(normal, xyz) + distance * w >= 0, in clip space
+#[derive(Clone, Copy, Debug)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub distance: f32,
 }
 
 #[derive(Clone)]
@@ -41,138 +48,434 @@ struct RasterState {
     blend_mode: BlendMode,
 }
 
-#[derive(Clone, Copy)]
-enum CullFace {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CullFace {
     None,
     Front,
     Back,
     Both,
 }
 
-#[derive(Clone, Copy)]
-enum PolygonMode {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PolygonMode {
     Point,
     Line,
     Fill,
 }
 
-#[derive(Clone, Copy)]
-enum BlendMode {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
     None,
     Alpha,
     Additive,
     Multiply,
 }
 
-struct RasterStats {
-    triangles_submitted: u64,
-    triangles_culled: u64,
-    fragments_generated: u64,
-    fragments_passed: u64,
+// The fragment stage: what color a covered pixel gets
+#[derive(Clone, Debug)]
+pub enum ShadingMode {
+    VertexColor,
+    Lambert { light_direction: Vec3, ambient: f32 },
+    Textured(Texture),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Vertex {
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub color: Vec4,
+    pub uv: [f32; 2],
+}
+
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+// Vertex stage output; everything after position is a varying
+#[derive(Clone, Copy)]
+struct ClipVertex {
+    position: Vec4,
+    normal: Vec3,
+    color: Vec4,
+    uv: [f32; 2],
+}
+
+// After the perspective divide and viewport transform
+#[derive(Clone, Copy)]
+struct ScreenVertex {
+    x: f32,
+    y: f32,
+    depth: f32,
+    inv_w: f32,
+    varyings: ClipVertex,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RasterStats {
+    pub triangles_submitted: u64,
+    pub triangles_culled: u64,
+    pub triangles_clipped: u64,
+    pub triangles_rasterized: u64,
+    pub fragments_generated: u64,
+    pub fragments_passed: u64,
+    pub fragments_blended: u64,
 }
 
 impl Rasterizer {
     pub fn new(width: u32, height: u32) -> Self {
+        let viewport = Viewport::new(0, 0, width, height);
         Self {
-            viewport: Viewport::new(0, 0, width, height),
+            viewport,
+            width,
+            height,
             depth_buffer: vec![1.0; (width * height) as usize],
             clip_planes: Vec::new(),
-            transform: Transform::default(),
+            transform: Transform { viewport: viewport.matrix(), ..Transform::default() },
             state: RasterState::default(),
+            shading: ShadingMode::VertexColor,
             stats: RasterStats::default(),
         }
     }
 
-    pub fn draw_triangle(&mut self, vertices: [Vec4; 3], framebuffer: &mut Framebuffer) -> GPUResult<()> {
+    pub fn set_viewport(&mut self, viewport: Viewport) {
+        self.viewport = viewport;
+        self.transform.viewport = viewport.matrix();
+    }
+
+    pub fn set_model(&mut self, model: Mat4) {
+        self.transform.model = model;
+    }
+
+    pub fn set_view(&mut self, view: Mat4) {
+        self.transform.view = view;
+    }
+
+    pub fn set_projection(&mut self, projection: Mat4) {
+        self.transform.projection = projection;
+    }
+
+    pub fn set_depth_test(&mut self, test: bool, write: bool) {
+        self.state.depth_test = test;
+        self.state.depth_write = write;
+    }
+
+    pub fn set_cull_face(&mut self, cull_face: CullFace) {
+        self.state.cull_face = cull_face;
+    }
+
+    pub fn set_polygon_mode(&mut self, polygon_mode: PolygonMode) {
+        self.state.polygon_mode = polygon_mode;
+    }
+
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.state.blend_mode = blend_mode;
+    }
+
+    pub fn set_shading(&mut self, shading: ShadingMode) {
+        self.shading = shading;
+    }
+
+    pub fn add_clip_plane(&mut self, plane: Plane) {
+        self.clip_planes.push(plane);
+    }
+
+    pub fn clear_clip_planes(&mut self) {
+        self.clip_planes.clear();
+    }
+
+    pub fn clear_depth(&mut self) {
+        self.depth_buffer.iter_mut().for_each(|depth| *depth = 1.0);
+    }
+
+    pub fn draw_mesh(&mut self, mesh: &Mesh, framebuffer: &mut Framebuffer) -> GPUResult<()> {
+        for triangle in mesh.indices.chunks_exact(3) {
+            let vertex = |index: u32| mesh.vertices.get(index as usize).copied().ok_or(GPUError::OutOfBounds);
+            self.draw_triangle([vertex(triangle[0])?, vertex(triangle[1])?, vertex(triangle[2])?], framebuffer)?;
+        }
+        Ok(())
+    }
+
+    pub fn draw_triangle(&mut self, vertices: [Vertex; 3], framebuffer: &mut Framebuffer) -> GPUResult<()> {
+        if framebuffer.width() != self.width || framebuffer.height() != self.height {
+            return Err(GPUError::OutOfBounds);
+        }
         self.stats.triangles_submitted += 1;
 
         // Transform vertices
         let transformed = self.transform_vertices(vertices);
-        
-        // Clip against view frustum
-        if !self.clip_triangle(&transformed) {
+
+        // Clip against view frustum and user planes; may yield a polygon
+        let polygon = self.clip_triangle(&transformed);
+        if polygon.len() < 3 {
             self.stats.triangles_culled += 1;
             return Ok(());
         }
 
-        // Perform backface culling
-        if self.should_cull(&transformed) {
+        // Culling and the polygon mode apply to the clipped polygon as a whole, so
+        // the fan it is split into for filling never shows up as extra edges
+        let screen: Vec<ScreenVertex> = polygon.iter().map(|vertex| self.to_screen_space(vertex)).collect();
+        if self.should_cull(&screen) {
             self.stats.triangles_culled += 1;
             return Ok(());
         }
+        self.stats.triangles_rasterized += 1;
 
-        // Rasterize triangle
         match self.state.polygon_mode {
-            PolygonMode::Point => self.rasterize_points(&transformed, framebuffer),
-            PolygonMode::Line => self.rasterize_wireframe(&transformed, framebuffer),
-            PolygonMode::Fill => self.rasterize_filled(&transformed, framebuffer),
+            PolygonMode::Point => self.rasterize_points(&screen, framebuffer),
+            PolygonMode::Line => self.rasterize_wireframe(&screen, framebuffer),
+            PolygonMode::Fill => {
+                for i in 1..screen.len() - 1 {
+                    self.rasterize_filled(&[screen[0], screen[i], screen[i + 1]], framebuffer)?;
+                }
+                Ok(())
+            }
         }
     }
 
-    fn transform_vertices(&self, vertices: [Vec4; 3]) -> [Vec4; 3] {
+    pub fn get_stats(&self) -> &RasterStats {
+        &self.stats
+    }
+
+    // Methods for visualization system
+    pub fn depth_at(&self, x: u32, y: u32) -> Option<f32> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(self.depth_buffer[(y * self.width + x) as usize])
+    }
+
+    // Vertex stage: positions to clip space, normals to world space. Normals
+    // assume the model matrix has no non-uniform scale.
+    fn transform_vertices(&self, vertices: [Vertex; 3]) -> [ClipVertex; 3] {
         let mvp = self.transform.projection
             .multiply(&self.transform.view)
             .multiply(&self.transform.model);
 
-        [
-            mvp.transform_vector(&vertices[0]),
-            mvp.transform_vector(&vertices[1]),
-            mvp.transform_vector(&vertices[2]),
-        ]
+        vertices.map(|vertex| {
+            let normal = self.transform.model.transform_vector(&Vec4::from_vec3(&vertex.normal, 0.0)).xyz();
+            ClipVertex {
+                position: mvp.transform_vector(&Vec4::from_vec3(&vertex.position, 1.0)),
+                normal: if normal.length() > 0.0 { normal.normalize() } else { normal },
+                color: vertex.color,
+                uv: vertex.uv,
+            }
+        })
     }
 
-    fn rasterize_filled(&mut self, vertices: &[Vec4; 3], framebuffer: &mut Framebuffer) -> GPUResult<()> {
-        // Convert to screen space
-        let screen_verts = self.to_screen_space(vertices);
+    // Sutherland-Hodgman in homogeneous coordinates, before the divide, so
+    // geometry behind the camera never reaches it
+    fn clip_triangle(&mut self, vertices: &[ClipVertex; 3]) -> Vec<ClipVertex> {
+        let frustum = [
+            Vec4::new(1.0, 0.0, 0.0, 1.0),
+            Vec4::new(-1.0, 0.0, 0.0, 1.0),
+            Vec4::new(0.0, 1.0, 0.0, 1.0),
+            Vec4::new(0.0, -1.0, 0.0, 1.0),
+            Vec4::new(0.0, 0.0, 1.0, 1.0),
+            Vec4::new(0.0, 0.0, -1.0, 1.0),
+        ];
+        let user = self.clip_planes.iter().map(|plane| Vec4::from_vec3(&plane.normal, plane.distance));
+
+        let mut polygon = vertices.to_vec();
+        let mut clipped = false;
+        for plane in frustum.into_iter().chain(user) {
+            let distances: Vec<f32> = polygon.iter().map(|vertex| plane.dot(&vertex.position)).collect();
+            if distances.iter().all(|&distance| distance >= 0.0) {
+                continue;
+            }
+            clipped = true;
+
+            let mut output = Vec::with_capacity(polygon.len() + 1);
+            for i in 0..polygon.len() {
+                let j = (i + 1) % polygon.len();
+                let (current, next) = (&polygon[i], &polygon[j]);
+                let (dc, dn) = (distances[i], distances[j]);
+                if dc >= 0.0 {
+                    output.push(*current);
+                }
+                if (dc >= 0.0) != (dn >= 0.0) {
+                    output.push(current.lerp(next, dc / (dc - dn)));
+                }
+            }
+            polygon = output;
+            if polygon.len() < 3 {
+                break;
+            }
+        }
+
+        if clipped {
+            self.stats.triangles_clipped += 1;
+        }
+        polygon
+    }
+
+    fn to_screen_space(&self, vertex: &ClipVertex) -> ScreenVertex {
+        let inv_w = 1.0 / vertex.position.w;
+        let ndc = Vec4::new(vertex.position.x * inv_w, vertex.position.y * inv_w, vertex.position.z * inv_w, 1.0);
+        let window = self.transform.viewport.transform_vector(&ndc);
+        ScreenVertex { x: window.x, y: window.y, depth: window.z, inv_w, varyings: *vertex }
+    }
+
+    // Counter-clockwise in normalized device coordinates is front facing. The
+    // viewport flips y, so that shows up as a negative area in window space.
+    fn should_cull(&self, polygon: &[ScreenVertex]) -> bool {
+        let front = polygon_area(polygon) < 0.0;
+        match self.state.cull_face {
+            CullFace::None => false,
+            CullFace::Front => front,
+            CullFace::Back => !front,
+            CullFace::Both => true,
+        }
+    }
+
+    fn rasterize_filled(&mut self, triangle: &[ScreenVertex; 3], framebuffer: &mut Framebuffer) -> GPUResult<()> {
+        // Edge functions want a consistent winding
+        let mut triangle = *triangle;
+        let mut area = signed_area(&triangle);
+        if area == 0.0 {
+            return Ok(());
+        }
+        if area < 0.0 {
+            triangle.swap(1, 2);
+            area = -area;
+        }
+        let [v0, v1, v2] = triangle;
 
         // Calculate bounding box
-        let (min_x, min_y, max_x, max_y) = self.get_bounding_box(&screen_verts);
-
-        // Rasterize pixels
-        for y in min_y..=max_y {
-            for x in min_x..=max_x {
-                if let Some(bary) = self.compute_barycentric(x as f32, y as f32, &screen_verts) {
-                    if self.is_inside_triangle(&bary) {
-                        self.stats.fragments_generated += 1;
-                        
-                        let depth = self.interpolate_depth(&vertices, &bary);
-                        if self.depth_test(x, y, depth) {
-                            self.write_pixel(x, y, depth, framebuffer)?;
-                            self.stats.fragments_passed += 1;
-                        }
-                    }
+        let (min_x, min_y, max_x, max_y) = self.get_bounding_box(&triangle);
+
+        // Rasterize pixels, sampling at pixel centers
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let w0 = edge(&v1, &v2, px, py);
+                let w1 = edge(&v2, &v0, px, py);
+                let w2 = edge(&v0, &v1, px, py);
+                if !(covers(w0, &v1, &v2) && covers(w1, &v2, &v0) && covers(w2, &v0, &v1)) {
+                    continue;
                 }
+                self.stats.fragments_generated += 1;
+
+                let weights = [w0 / area, w1 / area, w2 / area];
+                let depth = weights[0] * v0.depth + weights[1] * v1.depth + weights[2] * v2.depth;
+                let varyings = interpolate(&triangle, weights);
+                self.shade_fragment(x, y, depth, &varyings, framebuffer)?;
             }
         }
 
         Ok(())
     }
 
+    // The polygon's outline: its original edges plus any the clipper cut along
+    fn rasterize_wireframe(&mut self, polygon: &[ScreenVertex], framebuffer: &mut Framebuffer) -> GPUResult<()> {
+        for i in 0..polygon.len() {
+            let (a, b) = (&polygon[i], &polygon[(i + 1) % polygon.len()]);
+            let steps = (b.x - a.x).abs().max((b.y - a.y).abs()).ceil().max(1.0) as u32;
+            for step in 0..=steps {
+                let t = step as f32 / steps as f32;
+                let (x, y) = (a.x + (b.x - a.x) * t, a.y + (b.y - a.y) * t);
+                let varyings = interpolate(&[*a, *b, *b], [1.0 - t, t, 0.0]);
+                self.plot(x, y, a.depth + (b.depth - a.depth) * t, &varyings, framebuffer)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rasterize_points(&mut self, polygon: &[ScreenVertex], framebuffer: &mut Framebuffer) -> GPUResult<()> {
+        for vertex in polygon {
+            self.plot(vertex.x, vertex.y, vertex.depth, &vertex.varyings, framebuffer)?;
+        }
+        Ok(())
+    }
+
+    fn plot(&mut self, x: f32, y: f32, depth: f32, varyings: &ClipVertex, framebuffer: &mut Framebuffer) -> GPUResult<()> {
+        let (x, y) = (x.floor() as i32, y.floor() as i32);
+        if !self.in_viewport(x, y) {
+            return Ok(());
+        }
+        self.stats.fragments_generated += 1;
+        self.shade_fragment(x, y, depth, varyings, framebuffer)
+    }
+
+    // Fragment stage: shade, depth test, then blend with what is already there
+    fn shade_fragment(&mut self, x: i32, y: i32, depth: f32, varyings: &ClipVertex, framebuffer: &mut Framebuffer) -> GPUResult<()> {
+        if !self.depth_test(x, y, depth) {
+            return Ok(());
+        }
+        self.stats.fragments_passed += 1;
+        let color = self.shade(varyings);
+        self.write_pixel(x, y, depth, color, framebuffer)
+    }
+
+    fn shade(&self, varyings: &ClipVertex) -> Vec4 {
+        match &self.shading {
+            ShadingMode::VertexColor => varyings.color,
+            ShadingMode::Lambert { light_direction, ambient } => {
+                let normal = if varyings.normal.length() > 0.0 { varyings.normal.normalize() } else { varyings.normal };
+                let diffuse = normal.dot(&light_direction.normalize().scale(-1.0)).max(0.0);
+                let light = (ambient + (1.0 - ambient) * diffuse).min(1.0);
+                let color = varyings.color;
+                Vec4::new(color.x * light, color.y * light, color.z * light, color.w)
+            }
+            ShadingMode::Textured(texture) => {
+                let [r, g, b, a] = texture.sample(varyings.uv[0], varyings.uv[1]);
+                let color = varyings.color;
+                Vec4::new(r * color.x, g * color.y, b * color.z, a * color.w)
+            }
+        }
+    }
+
     fn depth_test(&self, x: i32, y: i32, depth: f32) -> bool {
         if !self.state.depth_test {
             return true;
         }
 
-        let idx = (y * self.viewport.width as i32 + x) as usize;
+        let idx = (y * self.width as i32 + x) as usize;
         depth <= self.depth_buffer[idx]
     }
 
-    fn write_pixel(&mut self, x: i32, y: i32, depth: f32, framebuffer: &mut Framebuffer) -> GPUResult<()> {
-        let idx = (y * self.viewport.width as i32 + x) as usize;
-        
+    fn write_pixel(&mut self, x: i32, y: i32, depth: f32, color: Vec4, framebuffer: &mut Framebuffer) -> GPUResult<()> {
+        let idx = (y * self.width as i32 + x) as usize;
+
         if self.state.depth_write {
             self.depth_buffer[idx] = depth;
         }
 
-        // Write color (simplified - would need actual color interpolation)
-        framebuffer.write_pixel(x as u32, y as u32, [255, 255, 255, 255])
+        let color = if self.state.blend_mode == BlendMode::None {
+            color
+        } else {
+            self.stats.fragments_blended += 1;
+            let destination = to_color(framebuffer.read_pixel(x as u32, y as u32)?);
+            blend(self.state.blend_mode, color, destination)
+        };
+        framebuffer.write_pixel(x as u32, y as u32, to_rgba8(color))
+    }
+
+    // Helper methods
+    fn get_bounding_box(&self, triangle: &[ScreenVertex; 3]) -> (i32, i32, i32, i32) {
+        let (left, top) = (self.viewport.x.max(0), self.viewport.y.max(0));
+        let right = (self.viewport.x + self.viewport.width as i32).min(self.width as i32);
+        let bottom = (self.viewport.y + self.viewport.height as i32).min(self.height as i32);
+
+        let xs = triangle.iter().map(|vertex| vertex.x);
+        let ys = triangle.iter().map(|vertex| vertex.y);
+        let min_x = xs.clone().fold(f32::INFINITY, f32::min).floor() as i32;
+        let max_x = xs.fold(f32::NEG_INFINITY, f32::max).ceil() as i32;
+        let min_y = ys.clone().fold(f32::INFINITY, f32::min).floor() as i32;
+        let max_y = ys.fold(f32::NEG_INFINITY, f32::max).ceil() as i32;
+        (min_x.max(left), min_y.max(top), max_x.min(right), max_y.min(bottom))
     }
 
-    // Helper methods...
+    fn in_viewport(&self, x: i32, y: i32) -> bool {
+        x >= self.viewport.x.max(0)
+            && y >= self.viewport.y.max(0)
+            && x < (self.viewport.x + self.viewport.width as i32).min(self.width as i32)
+            && y < (self.viewport.y + self.viewport.height as i32).min(self.height as i32)
+    }
 }
 
 impl Viewport {
-    fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
         Self {
             x,
             y,
@@ -182,6 +485,62 @@ impl Viewport {
             max_depth: 1.0,
         }
     }
+
+    // NDC to window coordinates, with y pointing down the framebuffer
+    fn matrix(&self) -> Mat4 {
+        let (half_width, half_height) = (self.width as f32 / 2.0, self.height as f32 / 2.0);
+        let half_depth = (self.max_depth - self.min_depth) / 2.0;
+        Mat4 {
+            data: [
+                [half_width, 0.0, 0.0, self.x as f32 + half_width],
+                [0.0, -half_height, 0.0, self.y as f32 + half_height],
+                [0.0, 0.0, half_depth, self.min_depth + half_depth],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+}
+
+impl ClipVertex {
+    fn lerp(&self, other: &ClipVertex, t: f32) -> ClipVertex {
+        ClipVertex {
+            position: self.position.lerp(&other.position, t),
+            normal: self.normal.add(&other.normal.sub(&self.normal).scale(t)),
+            color: self.color.lerp(&other.color, t),
+            uv: [
+                self.uv[0] + (other.uv[0] - self.uv[0]) * t,
+                self.uv[1] + (other.uv[1] - self.uv[1]) * t,
+            ],
+        }
+    }
+}
+
+impl Mesh {
+    // A unit cube centered on the origin, one color per face, wound
+    // counter-clockwise as seen from outside
+    pub fn cube() -> Self {
+        let faces = [
+            (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0), Vec4::new(0.9, 0.2, 0.2, 1.0)),
+            (Vec3::new(-1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0), Vec4::new(0.2, 0.9, 0.9, 1.0)),
+            (Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 0.0), Vec4::new(0.2, 0.9, 0.2, 1.0)),
+            (Vec3::new(0.0, -1.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), Vec4::new(0.9, 0.2, 0.9, 1.0)),
+            (Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec4::new(0.2, 0.2, 0.9, 1.0)),
+            (Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec4::new(0.9, 0.9, 0.2, 1.0)),
+        ];
+
+        let mut vertices = Vec::with_capacity(24);
+        let mut indices = Vec::with_capacity(36);
+        for (normal, u, v, color) in faces {
+            let base = vertices.len() as u32;
+            for (su, sv) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                let position = normal.add(&u.scale(su)).add(&v.scale(sv)).scale(0.5);
+                let uv = [(su + 1.0) / 2.0, (sv + 1.0) / 2.0];
+                vertices.push(Vertex { position, normal, color, uv });
+            }
+            indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+        Self { vertices, indices }
+    }
 }
 
 impl Default for Transform {
@@ -194,3 +553,190 @@ impl Default for Transform {
         }
     }
 }
+
+impl Default for RasterState {
+    fn default() -> Self {
+        Self {
+            depth_test: true,
+            depth_write: true,
+            cull_face: CullFace::Back,
+            polygon_mode: PolygonMode::Fill,
+            blend_mode: BlendMode::None,
+        }
+    }
+}
+
+fn signed_area(triangle: &[ScreenVertex; 3]) -> f32 {
+    let [a, b, c] = triangle;
+    edge(a, b, c.x, c.y)
+}
+
+// Twice the signed area, by the shoelace formula; same sign convention as a triangle's
+fn polygon_area(polygon: &[ScreenVertex]) -> f32 {
+    (0..polygon.len())
+        .map(|i| {
+            let (a, b) = (&polygon[i], &polygon[(i + 1) % polygon.len()]);
+            a.x * b.y - b.x * a.y
+        })
+        .sum()
+}
+
+fn edge(a: &ScreenVertex, b: &ScreenVertex, x: f32, y: f32) -> f32 {
+    (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+}
+
+// Top-left fill rule: a pixel center exactly on a shared edge belongs to one
+// triangle only, so adjacent triangles neither overlap nor leave gaps
+fn covers(weight: f32, a: &ScreenVertex, b: &ScreenVertex) -> bool {
+    if weight != 0.0 {
+        return weight > 0.0;
+    }
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    dy < 0.0 || (dy == 0.0 && dx > 0.0)
+}
+
+// Perspective-correct: interpolate attribute / w and 1 / w linearly in screen space
+fn interpolate(triangle: &[ScreenVertex; 3], weights: [f32; 3]) -> ClipVertex {
+    let inv_w: f32 = (0..3).map(|i| weights[i] * triangle[i].inv_w).sum();
+    let factors: Vec<f32> = (0..3).map(|i| weights[i] * triangle[i].inv_w / inv_w).collect();
+
+    let weighted = |value: &dyn Fn(&ClipVertex) -> Vec4| {
+        (0..3).fold(Vec4::new(0.0, 0.0, 0.0, 0.0), |sum, i| {
+            let v = value(&triangle[i].varyings);
+            Vec4::new(sum.x + v.x * factors[i], sum.y + v.y * factors[i], sum.z + v.z * factors[i], sum.w + v.w * factors[i])
+        })
+    };
+    let uv = weighted(&|varyings| Vec4::new(varyings.uv[0], varyings.uv[1], 0.0, 0.0));
+    ClipVertex {
+        position: weighted(&|varyings| varyings.position),
+        normal: weighted(&|varyings| Vec4::from_vec3(&varyings.normal, 0.0)).xyz(),
+        color: weighted(&|varyings| varyings.color),
+        uv: [uv.x, uv.y],
+    }
+}
+
+fn blend(mode: BlendMode, source: Vec4, destination: Vec4) -> Vec4 {
+    match mode {
+        BlendMode::None => source,
+        BlendMode::Alpha => {
+            let alpha = source.w;
+            Vec4::new(
+                source.x * alpha + destination.x * (1.0 - alpha),
+                source.y * alpha + destination.y * (1.0 - alpha),
+                source.z * alpha + destination.z * (1.0 - alpha),
+                alpha + destination.w * (1.0 - alpha),
+            )
+        }
+        BlendMode::Additive => Vec4::new(
+            source.x + destination.x,
+            source.y + destination.y,
+            source.z + destination.z,
+            source.w + destination.w,
+        ),
+        BlendMode::Multiply => Vec4::new(
+            source.x * destination.x,
+            source.y * destination.y,
+            source.z * destination.z,
+            source.w * destination.w,
+        ),
+    }
+}
+
+fn to_color(rgba: [u8; 4]) -> Vec4 {
+    Vec4::new(rgba[0] as f32 / 255.0, rgba[1] as f32 / 255.0, rgba[2] as f32 / 255.0, rgba[3] as f32 / 255.0)
+}
+
+fn to_rgba8(color: Vec4) -> [u8; 4] {
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    [channel(color.x), channel(color.y), channel(color.z), channel(color.w)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::gpu::display::framebuffer::PixelFormat;
+    use crate::hardware::gpu::memory::{VRAMConfig, VRAMController};
+
+    const SIZE: u32 = 64;
+    const BACKGROUND: [u8; 4] = [0, 0, 0, 255];
+
+    fn framebuffer() -> Framebuffer {
+        let mut vram = VRAMController::with_config(VRAMConfig {
+            total_size: 1024 * 1024,
+            page_size: 4096,
+            num_banks: 4,
+            bank_width: 64,
+        });
+        let mut framebuffer = Framebuffer::new(SIZE, SIZE, PixelFormat::RGBA8, &mut vram).unwrap();
+        framebuffer.clear(BACKGROUND);
+        framebuffer
+    }
+
+    fn vertex(x: f32, y: f32) -> Vertex {
+        Vertex {
+            position: Vec3::new(x, y, 0.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            color: Vec4::new(1.0, 1.0, 1.0, 1.0),
+            uv: [0.0, 0.0],
+        }
+    }
+
+    // NDC to the pixel the viewport maps it into
+    fn pixel(x: f32, y: f32) -> (u32, u32) {
+        (((x + 1.0) * SIZE as f32 / 2.0) as u32, ((1.0 - y) * SIZE as f32 / 2.0) as u32)
+    }
+
+    // Counter-clockwise, with the top cut off by y <= 0.4 so it clips to a quad
+    fn clipped_triangle(rasterizer: &mut Rasterizer) -> [Vertex; 3] {
+        rasterizer.add_clip_plane(Plane { normal: Vec3::new(0.0, -1.0, 0.0), distance: 0.4 });
+        [vertex(-0.8, -0.8), vertex(0.8, -0.8), vertex(0.0, 0.8)]
+    }
+
+    #[test]
+    fn wireframe_draws_the_clipped_outline_without_fan_diagonals() {
+        let mut rasterizer = Rasterizer::new(SIZE, SIZE);
+        let mut framebuffer = framebuffer();
+        rasterizer.set_polygon_mode(PolygonMode::Line);
+        let triangle = clipped_triangle(&mut rasterizer);
+        rasterizer.draw_triangle(triangle, &mut framebuffer).unwrap();
+
+        // The original bottom edge and the edge the clip plane cut are both drawn
+        let (x, y) = pixel(0.0, -0.8);
+        assert_ne!(framebuffer.read_pixel(x, y).unwrap(), BACKGROUND);
+        let (x, y) = pixel(0.0, 0.4);
+        assert_ne!(framebuffer.read_pixel(x, y).unwrap(), BACKGROUND);
+
+        // Nothing along the diagonal from (-0.8, -0.8) to (0.2, 0.4) the fill fan uses
+        for t in [0.3, 0.5, 0.7] {
+            let (x, y) = pixel(-0.8 + t, -0.8 + 1.2 * t);
+            assert_eq!(framebuffer.read_pixel(x, y).unwrap(), BACKGROUND, "diagonal drawn at t = {}", t);
+        }
+        let stats = rasterizer.get_stats();
+        assert_eq!((stats.triangles_clipped, stats.triangles_rasterized), (1, 1));
+    }
+
+    #[test]
+    fn filled_fans_cover_the_clipped_polygon() {
+        let mut rasterizer = Rasterizer::new(SIZE, SIZE);
+        let mut framebuffer = framebuffer();
+        let triangle = clipped_triangle(&mut rasterizer);
+        rasterizer.draw_triangle(triangle, &mut framebuffer).unwrap();
+
+        let (x, y) = pixel(-0.3, -0.2);
+        assert_eq!(framebuffer.read_pixel(x, y).unwrap(), [255, 255, 255, 255]);
+        let (x, y) = pixel(0.0, 0.6);
+        assert_eq!(framebuffer.read_pixel(x, y).unwrap(), BACKGROUND);
+        assert_eq!(rasterizer.depth_at(x, y), Some(1.0));
+    }
+
+    #[test]
+    fn back_faces_are_culled_as_a_whole_polygon() {
+        let mut rasterizer = Rasterizer::new(SIZE, SIZE);
+        let mut framebuffer = framebuffer();
+        rasterizer.add_clip_plane(Plane { normal: Vec3::new(0.0, -1.0, 0.0), distance: 0.4 });
+        rasterizer.draw_triangle([vertex(-0.8, -0.8), vertex(0.0, 0.8), vertex(0.8, -0.8)], &mut framebuffer).unwrap();
+
+        let stats = rasterizer.get_stats();
+        assert_eq!((stats.triangles_culled, stats.fragments_generated), (1, 0));
+    }
+}
@@ -0,0 +1,26 @@
+#[derive(Debug, Clone, PartialEq)]
+pub enum GPUError {
+    // Memory errors
+    OutOfMemory,
+    OutOfBounds,
+    AccessViolation,
+    PageFault,
+
+    // Execution errors
+    InvalidProgram(String),
+    InvalidTexture,
+    Timeout,
+    ToleranceExceeded { max_abs_error: f32, tolerance: f32 },
+
+    // Scheduling errors
+    QueueFull,
+    DeviceBusy,
+    InvalidContext(u32),
+
+    // Command submission errors
+    InvalidRegister,
+    InvalidPacket(u32),
+    RingFull,
+}
+
+pub type GPUResult<T> = Result<T, GPUError>;
@@ -1,303 +1,467 @@
-use super::super::error::{GPUError, GPUResult};
-use std::collections::HashMap;
+use super::coalescer::{MemoryTransaction, LINE_SIZE, SECTOR_SIZE};
 
+// A stack of sectored set-associative caches, L1 first. A tag covers a whole
+// line but every sector has its own valid and dirty bit, so a miss fetches
+// only the sectors a warp asked for. Only tags and sector state are modeled;
+// the bytes themselves stay in memory.
 pub struct GPUCache {
     levels: Vec<CacheLevel>,
-    coherency: CoherencyController,
+    dram_latency: u64,
     stats: CacheStats,
 }
 
 struct CacheLevel {
     sets: Vec<CacheSet>,
     config: CacheConfig,
+    clock: u64,
+    random_state: u64,
     stats: LevelStats,
 }
 
 struct CacheSet {
     lines: Vec<CacheLine>,
-    replacement: ReplacementPolicy,
 }
 
+#[derive(Clone, Default)]
 struct CacheLine {
     tag: u64,
-    data: Vec<u8>,
-    flags: LineFlags,
-    last_access: u64,
-    access_count: u64,
-}
-
-#[derive(Clone)]
-struct LineFlags {
     valid: bool,
-    dirty: bool,
-    shared: bool,
-    exclusive: bool,
+    valid_sectors: u8,
+    dirty_sectors: u8,
+    last_access: u64,
+    inserted: u64,
 }
 
-struct CacheConfig {
-    size: usize,
-    line_size: usize,
-    associativity: usize,
-    write_policy: WritePolicy,
-    allocation_policy: AllocationPolicy,
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    pub name: String,
+    pub size: usize,
+    pub associativity: usize,
+    pub hit_latency: u64,
+    pub write_policy: WritePolicy,
+    pub allocation_policy: AllocationPolicy,
+    pub replacement: ReplacementPolicy,
 }
 
-#[derive(Clone, Copy)]
-enum WritePolicy {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WritePolicy {
     WriteBack,
     WriteThrough,
 }
 
-#[derive(Clone, Copy)]
-enum AllocationPolicy {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AllocationPolicy {
     WriteAllocate,
     NoWriteAllocate,
 }
 
-#[derive(Clone, Copy)]
-enum ReplacementPolicy {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplacementPolicy {
     LRU,
     FIFO,
     Random,
-    PLRU, // Pseudo-LRU
-}
-
-struct CoherencyController {
-    protocol: CoherencyProtocol,
-    directory: HashMap<u64, DirectoryEntry>,
-    pending_requests: Vec<CoherencyRequest>,
-}
-
-#[derive(Clone, Copy)]
-enum CoherencyProtocol {
-    MESI,
-    MOESI,
-    MESIF,
-}
-
-struct DirectoryEntry {
-    state: CoherencyState,
-    sharers: Vec<usize>,
-    owner: Option<usize>,
-}
-
-#[derive(Clone, Copy, PartialEq)]
-enum CoherencyState {
-    Modified,
-    Exclusive,
-    Shared,
-    Invalid,
-    Forward, // For MESIF
-    Owned,   // For MOESI
-}
-
-struct CoherencyRequest {
-    address: u64,
-    request_type: RequestType,
-    requester: usize,
-    state: RequestState,
 }
 
-#[derive(Clone, Copy)]
-enum RequestType {
-    ReadShared,
-    ReadExclusive,
-    Upgrade,
-    Writeback,
-    Invalidate,
+// Where the slowest sector of a transaction came from, and what it cost
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheAccess {
+    pub latency: u64,
+    pub level: Option<usize>, // None when it went to DRAM
+    pub dram_sectors: u32,
 }
 
-#[derive(Clone, Copy)]
-enum RequestState {
-    Pending,
-    WaitingForAcks,
-    Complete,
+#[derive(Debug, Clone, Default)]
+pub struct CacheStats {
+    pub transactions: u64,
+    pub dram_sectors_read: u64,
+    pub dram_sectors_written: u64,
 }
 
-struct CacheStats {
-    accesses: u64,
-    hits: u64,
-    misses: u64,
-    evictions: u64,
-    writebacks: u64,
-}
-
-struct LevelStats {
-    hit_rate: f32,
-    miss_latency: f32,
-    bandwidth_usage: f32,
-    power_consumption: f32,
+#[derive(Debug, Clone, Default)]
+pub struct LevelStats {
+    pub accesses: u64,
+    pub hits: u64,           // Every requested sector present
+    pub sector_misses: u64,  // Tag present, some sectors not
+    pub misses: u64,         // Tag absent
+    pub sectors_requested: u64,
+    pub sectors_hit: u64,
+    pub evictions: u64,
+    pub writeback_sectors: u64,
 }
 
 impl GPUCache {
-    pub fn new(configs: Vec<CacheConfig>) -> Self {
-        let levels = configs.into_iter()
-            .map(|config| CacheLevel::new(config))
-            .collect();
-
+    pub fn new(configs: Vec<CacheConfig>, dram_latency: u64) -> Self {
         Self {
-            levels,
-            coherency: CoherencyController::new(),
+            levels: configs.into_iter().map(CacheLevel::new).collect(),
+            dram_latency,
             stats: CacheStats::default(),
         }
     }
 
-    pub fn read(&mut self, address: u64) -> GPUResult<Vec<u8>> {
-        self.stats.accesses += 1;
+    // Looks a transaction up level by level. Reads fill every level they
+    // missed in on the way back; writes stop at the first write-back level.
+    pub fn access(&mut self, transaction: &MemoryTransaction) -> CacheAccess {
+        self.stats.transactions += 1;
+        if transaction.write {
+            self.write(0, transaction.line, transaction.sector_mask)
+        } else {
+            self.read(transaction.line, transaction.sector_mask)
+        }
+    }
 
-        // Try each cache level
-        for level in &mut self.levels {
-            if let Some(data) = level.read(address)? {
-                self.stats.hits += 1;
-                return Ok(data);
+    // Writes every dirty sector back and drops all lines
+    pub fn flush(&mut self) {
+        for index in 0..self.levels.len() {
+            let dirty = self.levels[index].invalidate_all();
+            for (line, sectors) in dirty {
+                self.write_back(index + 1, line, sectors);
             }
         }
+    }
 
-        // Cache miss
-        self.stats.misses += 1;
-        self.handle_miss(address, false)
+    pub fn levels(&self) -> usize {
+        self.levels.len()
     }
 
-    pub fn write(&mut self, address: u64, data: &[u8]) -> GPUResult<()> {
-        self.stats.accesses += 1;
+    pub fn level_stats(&self, level: usize) -> Option<&LevelStats> {
+        self.levels.get(level).map(|level| &level.stats)
+    }
 
-        // Handle coherency
-        self.coherency.handle_write(address)?;
+    pub fn level_config(&self, level: usize) -> Option<&CacheConfig> {
+        self.levels.get(level).map(|level| &level.config)
+    }
+
+    pub fn get_stats(&self) -> &CacheStats {
+        &self.stats
+    }
 
-        // Write to cache hierarchy
-        match self.levels[0].write(address, data)? {
-            WriteResult::Hit => {
-                self.stats.hits += 1;
-                Ok(())
+    // Helper methods
+    fn read(&mut self, line: u64, sectors: u8) -> CacheAccess {
+        let mut missing = sectors;
+        let mut asked = Vec::with_capacity(self.levels.len());
+        let mut result = None;
+        for (index, level) in self.levels.iter_mut().enumerate() {
+            asked.push(missing);
+            missing &= !level.lookup(line, missing);
+            if missing == 0 {
+                result = Some(CacheAccess { latency: level.config.hit_latency, level: Some(index), dram_sectors: 0 });
+                break;
             }
-            WriteResult::Miss => {
-                self.stats.misses += 1;
-                self.handle_miss(address, true)?;
-                self.levels[0].write(address, data)
-                    .map(|_| ())
+        }
+        let result = result.unwrap_or_else(|| {
+            self.stats.dram_sectors_read += missing.count_ones() as u64;
+            CacheAccess { latency: self.dram_latency, level: None, dram_sectors: missing.count_ones() }
+        });
+
+        // Fill from the bottom up so victims written back land in a level
+        // that already holds the new line
+        for (index, &sectors) in asked.iter().enumerate().rev() {
+            if let Some((victim, dirty)) = self.levels[index].fill(line, sectors, 0) {
+                self.write_back(index + 1, victim, dirty);
             }
         }
+        result
     }
 
-    fn handle_miss(&mut self, address: u64, is_write: bool) -> GPUResult<Vec<u8>> {
-        // Fetch from next level or memory
-        let data = self.fetch_data(address)?;
-
-        // Update cache
-        self.update_cache(address, &data, is_write)?;
+    fn write(&mut self, start: usize, line: u64, sectors: u8) -> CacheAccess {
+        for index in start..self.levels.len() {
+            let level = &mut self.levels[index];
+            let present = level.lookup(line, sectors);
+            let latency = level.config.hit_latency;
+            let write_back = level.config.write_policy == WritePolicy::WriteBack;
+            let allocate = level.config.allocation_policy == AllocationPolicy::WriteAllocate;
+
+            // Stores carry byte masks, so allocating a sector needs no fetch
+            if present != 0 || allocate {
+                let dirty = if write_back { sectors } else { 0 };
+                if let Some((victim, victim_dirty)) = level.fill(line, sectors, dirty) {
+                    self.write_back(index + 1, victim, victim_dirty);
+                }
+            }
+            if write_back {
+                return CacheAccess { latency, level: Some(index), dram_sectors: 0 };
+            }
+        }
+        self.stats.dram_sectors_written += sectors.count_ones() as u64;
+        CacheAccess { latency: self.dram_latency, level: None, dram_sectors: sectors.count_ones() }
+    }
 
-        Ok(data)
+    fn write_back(&mut self, level: usize, line: u64, sectors: u8) {
+        if sectors == 0 {
+            return;
+        }
+        if level == self.levels.len() {
+            self.stats.dram_sectors_written += sectors.count_ones() as u64;
+        } else {
+            self.write(level, line, sectors);
+        }
     }
 }
 
 impl CacheLevel {
     fn new(config: CacheConfig) -> Self {
-        let num_sets = config.size / (config.line_size * config.associativity);
+        // Set indexing takes address bits, so odd sizes round down to a power of two
+        let associativity = config.associativity.max(1);
+        let num_sets = (config.size / (LINE_SIZE as usize * associativity)).max(1);
+        let num_sets = 1 << num_sets.ilog2();
         let sets = (0..num_sets)
-            .map(|_| CacheSet::new(config.associativity, config.line_size))
+            .map(|_| CacheSet { lines: vec![CacheLine::default(); associativity] })
             .collect();
 
         Self {
             sets,
             config,
+            clock: 0,
+            random_state: 0x2545_F491_4F6C_DD1D,
             stats: LevelStats::default(),
         }
     }
 
-    fn read(&mut self, address: u64) -> GPUResult<Option<Vec<u8>>> {
-        let set_index = self.get_set_index(address);
-        let tag = self.get_tag(address);
+    // Returns the requested sectors this level holds
+    fn lookup(&mut self, line: u64, sectors: u8) -> u8 {
+        self.clock += 1;
+        self.stats.accesses += 1;
+        self.stats.sectors_requested += sectors.count_ones() as u64;
+
+        let (set, tag) = self.locate(line);
+        let clock = self.clock;
+        match self.sets[set].lines.iter_mut().find(|entry| entry.valid && entry.tag == tag) {
+            Some(entry) => {
+                entry.last_access = clock;
+                let present = entry.valid_sectors & sectors;
+                if present == sectors {
+                    self.stats.hits += 1;
+                } else {
+                    self.stats.sector_misses += 1;
+                }
+                self.stats.sectors_hit += present.count_ones() as u64;
+                present
+            }
+            None => {
+                self.stats.misses += 1;
+                0
+            }
+        }
+    }
 
-        self.sets[set_index].read(tag)
+    // Makes the sectors valid, allocating the line if it isn't here. Returns
+    // the victim's line address and dirty sectors if one had to go.
+    fn fill(&mut self, line: u64, sectors: u8, dirty: u8) -> Option<(u64, u8)> {
+        let (set, tag) = self.locate(line);
+        let clock = self.clock;
+        if let Some(entry) = self.sets[set].lines.iter_mut().find(|entry| entry.valid && entry.tag == tag) {
+            entry.valid_sectors |= sectors;
+            entry.dirty_sectors |= dirty;
+            return None;
+        }
+
+        let victim = self.select_victim(set);
+        let sets = self.sets.len() as u64;
+        let entry = &mut self.sets[set].lines[victim];
+        let evicted = if entry.valid {
+            self.stats.evictions += 1;
+            self.stats.writeback_sectors += entry.dirty_sectors.count_ones() as u64;
+            let address = (entry.tag * sets + set as u64) * LINE_SIZE;
+            Some((address, entry.dirty_sectors))
+        } else {
+            None
+        };
+        *entry = CacheLine {
+            tag,
+            valid: true,
+            valid_sectors: sectors,
+            dirty_sectors: dirty,
+            last_access: clock,
+            inserted: clock,
+        };
+        evicted
     }
 
-    fn write(&mut self, address: u64, data: &[u8]) -> GPUResult<WriteResult> {
-        let set_index = self.get_set_index(address);
-        let tag = self.get_tag(address);
+    fn invalidate_all(&mut self) -> Vec<(u64, u8)> {
+        let sets = self.sets.len() as u64;
+        let mut dirty = Vec::new();
+        for (index, set) in self.sets.iter_mut().enumerate() {
+            for entry in set.lines.iter_mut().filter(|entry| entry.valid) {
+                if entry.dirty_sectors != 0 {
+                    dirty.push(((entry.tag * sets + index as u64) * LINE_SIZE, entry.dirty_sectors));
+                }
+                *entry = CacheLine::default();
+            }
+        }
+        dirty
+    }
 
-        self.sets[set_index].write(tag, data, &self.config)
+    fn select_victim(&mut self, set: usize) -> usize {
+        let lines = &self.sets[set].lines;
+        if let Some(free) = lines.iter().position(|entry| !entry.valid) {
+            return free;
+        }
+        match self.config.replacement {
+            ReplacementPolicy::LRU => (0..lines.len()).min_by_key(|&way| lines[way].last_access).unwrap_or(0),
+            ReplacementPolicy::FIFO => (0..lines.len()).min_by_key(|&way| lines[way].inserted).unwrap_or(0),
+            ReplacementPolicy::Random => {
+                // xorshift64
+                self.random_state ^= self.random_state << 13;
+                self.random_state ^= self.random_state >> 7;
+                self.random_state ^= self.random_state << 17;
+                (self.random_state % lines.len() as u64) as usize
+            }
+        }
+    }
+
+    fn locate(&self, line: u64) -> (usize, u64) {
+        let index = line / LINE_SIZE;
+        let sets = self.sets.len() as u64;
+        ((index % sets) as usize, index / sets)
     }
 }
 
-impl CacheSet {
-    fn new(associativity: usize, line_size: usize) -> Self {
+impl CacheConfig {
+    // Per-SM L1: write-through and no write-allocate, so stores go straight to L2
+    pub fn l1() -> Self {
         Self {
-            lines: vec![CacheLine::new(line_size); associativity],
+            name: "L1".to_string(),
+            size: 32 * 1024,
+            associativity: 4,
+            hit_latency: 28,
+            write_policy: WritePolicy::WriteThrough,
+            allocation_policy: AllocationPolicy::NoWriteAllocate,
             replacement: ReplacementPolicy::LRU,
         }
     }
 
-    fn read(&mut self, tag: u64) -> GPUResult<Option<Vec<u8>>> {
-        if let Some(line) = self.find_line(tag) {
-            line.last_access += 1;
-            line.access_count += 1;
-            Ok(Some(line.data.clone()))
-        } else {
-            Ok(None)
+    // Device-wide L2, the point of coherence for every SM
+    pub fn l2() -> Self {
+        Self {
+            name: "L2".to_string(),
+            size: 512 * 1024,
+            associativity: 16,
+            hit_latency: 190,
+            write_policy: WritePolicy::WriteBack,
+            allocation_policy: AllocationPolicy::WriteAllocate,
+            replacement: ReplacementPolicy::LRU,
         }
     }
+}
 
-    fn write(&mut self, tag: u64, data: &[u8], config: &CacheConfig) -> GPUResult<WriteResult> {
-        if let Some(line) = self.find_line(tag) {
-            line.data.copy_from_slice(data);
-            line.flags.dirty = config.write_policy == WritePolicy::WriteBack;
-            Ok(WriteResult::Hit)
-        } else {
-            match config.allocation_policy {
-                AllocationPolicy::WriteAllocate => {
-                    self.allocate_line(tag, data)?;
-                    Ok(WriteResult::Miss)
-                }
-                AllocationPolicy::NoWriteAllocate => Ok(WriteResult::Miss),
-            }
+impl LevelStats {
+    // Sector hit rate; partial hits count for the sectors they had
+    pub fn hit_rate(&self) -> f32 {
+        if self.sectors_requested == 0 {
+            return 0.0;
         }
+        self.sectors_hit as f32 / self.sectors_requested as f32
     }
+}
 
-    fn find_line(&mut self, tag: u64) -> Option<&mut CacheLine> {
-        self.lines.iter_mut().find(|line| line.flags.valid && line.tag == tag)
+impl CacheStats {
+    pub fn dram_bytes(&self) -> u64 {
+        (self.dram_sectors_read + self.dram_sectors_written) * SECTOR_SIZE
     }
+}
 
-    fn allocate_line(&mut self, tag: u64, data: &[u8]) -> GPUResult<()> {
-        let victim_index = self.select_victim();
-        let line = &mut self.lines[victim_index];
-        
-        // Handle writeback if needed
-        if line.flags.valid && line.flags.dirty {
-            // Writeback to next level
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DRAM_LATENCY: u64 = 500;
 
-        line.tag = tag;
-        line.data.copy_from_slice(data);
-        line.flags = LineFlags::default();
-        line.flags.valid = true;
-        
-        Ok(())
+    fn read(line: u64, sector_mask: u8) -> MemoryTransaction {
+        MemoryTransaction { line, sector_mask, lanes: 1, write: false }
     }
 
-    fn select_victim(&self) -> usize {
-        match self.replacement {
-            ReplacementPolicy::LRU => self.select_lru(),
-            ReplacementPolicy::FIFO => self.select_fifo(),
-            ReplacementPolicy::Random => self.select_random(),
-            ReplacementPolicy::PLRU => self.select_plru(),
+    fn write(line: u64, sector_mask: u8) -> MemoryTransaction {
+        MemoryTransaction { line, sector_mask, lanes: 1, write: true }
+    }
+
+    // A single set, so every line competes for the same ways
+    fn tiny(name: &str, ways: usize, replacement: ReplacementPolicy) -> CacheConfig {
+        CacheConfig {
+            name: name.to_string(),
+            size: LINE_SIZE as usize * ways,
+            associativity: ways,
+            hit_latency: 10,
+            write_policy: WritePolicy::WriteBack,
+            allocation_policy: AllocationPolicy::WriteAllocate,
+            replacement,
         }
     }
-}
 
-impl CacheLine {
-    fn new(size: usize) -> Self {
-        Self {
-            tag: 0,
-            data: vec![0; size],
-            flags: LineFlags::default(),
-            last_access: 0,
-            access_count: 0,
+    #[test]
+    fn misses_fill_every_level_on_the_way_back() {
+        let mut cache = GPUCache::new(vec![CacheConfig::l1(), CacheConfig::l2()], DRAM_LATENCY);
+        assert_eq!(cache.access(&read(0x4000, 0b0001)), CacheAccess { latency: DRAM_LATENCY, level: None, dram_sectors: 1 });
+        assert_eq!(cache.access(&read(0x4000, 0b0001)), CacheAccess { latency: 28, level: Some(0), dram_sectors: 0 });
+        assert_eq!(cache.level_stats(0).unwrap().misses, 1);
+        assert_eq!(cache.level_stats(0).unwrap().hits, 1);
+        assert_eq!(cache.get_stats().dram_sectors_read, 1);
+    }
+
+    #[test]
+    fn sector_misses_fetch_only_the_missing_sectors() {
+        let mut cache = GPUCache::new(vec![CacheConfig::l1(), CacheConfig::l2()], DRAM_LATENCY);
+        cache.access(&read(0x4000, 0b0001));
+        let access = cache.access(&read(0x4000, 0b0111));
+        assert_eq!((access.level, access.dram_sectors), (None, 2));
+
+        let l1 = cache.level_stats(0).unwrap();
+        assert_eq!(l1.sector_misses, 1);
+        assert_eq!(l1.sectors_requested, 4);
+        assert_eq!(l1.sectors_hit, 1);
+        assert_eq!(l1.hit_rate(), 0.25);
+    }
+
+    #[test]
+    fn l1_stores_write_through_to_l2_without_allocating() {
+        let mut cache = GPUCache::new(vec![CacheConfig::l1(), CacheConfig::l2()], DRAM_LATENCY);
+        assert_eq!(cache.access(&write(0x8000, 0b0011)), CacheAccess { latency: 190, level: Some(1), dram_sectors: 0 });
+        assert_eq!(cache.get_stats().dram_sectors_written, 0);
+
+        // The store allocated in L2 only, and the sectors need no fetch
+        assert_eq!(cache.access(&read(0x8000, 0b0011)).level, Some(1));
+        assert_eq!(cache.get_stats().dram_sectors_read, 0);
+    }
+
+    #[test]
+    fn dirty_victims_and_flushes_write_back_their_sectors() {
+        let mut cache = GPUCache::new(vec![tiny("L2", 1, ReplacementPolicy::LRU)], DRAM_LATENCY);
+        cache.access(&write(0, 0b0101));
+        cache.access(&read(LINE_SIZE, 0b0001));
+
+        let stats = cache.level_stats(0).unwrap();
+        assert_eq!((stats.evictions, stats.writeback_sectors), (1, 2));
+        assert_eq!(cache.get_stats().dram_sectors_written, 2);
+
+        cache.access(&write(LINE_SIZE, 0b1000));
+        cache.flush();
+        assert_eq!(cache.get_stats().dram_sectors_written, 3);
+        assert_eq!(cache.access(&read(LINE_SIZE, 0b1000)).level, None);
+        assert_eq!(cache.get_stats().dram_bytes(), (3 + 2) * SECTOR_SIZE);
+    }
+
+    #[test]
+    fn lru_keeps_the_reused_line_and_fifo_the_newer_one() {
+        for (policy, kept) in [(ReplacementPolicy::LRU, 0), (ReplacementPolicy::FIFO, LINE_SIZE)] {
+            let mut cache = GPUCache::new(vec![tiny("L1", 2, policy)], DRAM_LATENCY);
+            cache.access(&read(0, 1));
+            cache.access(&read(LINE_SIZE, 1));
+            cache.access(&read(0, 1));
+            cache.access(&read(2 * LINE_SIZE, 1));
+            assert_eq!(cache.access(&read(kept, 1)).level, Some(0), "{:?}", policy);
         }
     }
-}
 
-#[derive(Clone, Copy)]
-enum WriteResult {
-    Hit,
-    Miss,
-} 
\ No newline at end of file
+    #[test]
+    fn odd_sizes_round_down_to_a_power_of_two_sets() {
+        let mut config = tiny("L1", 1, ReplacementPolicy::Random);
+        config.size = LINE_SIZE as usize * 3;
+        let mut cache = GPUCache::new(vec![config], DRAM_LATENCY);
+
+        // Two sets: lines 0 and 2 collide, line 1 doesn't
+        cache.access(&read(0, 1));
+        cache.access(&read(LINE_SIZE, 1));
+        cache.access(&read(2 * LINE_SIZE, 1));
+        assert_eq!(cache.access(&read(LINE_SIZE, 1)).level, Some(0));
+        assert_eq!(cache.access(&read(0, 1)).level, None);
+    }
+}
@@ -0,0 +1,176 @@
+use super::super::error::{GPUError, GPUResult};
+
+// Caches tag 128-byte lines but move data in 32-byte sectors
+pub const LINE_SIZE: u64 = 128;
+pub const SECTOR_SIZE: u64 = 32;
+pub const SECTORS_PER_LINE: u32 = (LINE_SIZE / SECTOR_SIZE) as u32;
+
+// Merges the per-lane addresses of one warp memory instruction into as few
+// line transactions as possible. Thirty-two consecutive words become a single
+// line with all four sectors; thirty-two scattered words become thirty-two.
+pub struct CoalescingUnit {
+    stats: CoalescerStats,
+}
+
+// One request to the L1: a line and the sectors of it that lanes touched
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryTransaction {
+    pub line: u64,
+    pub sector_mask: u8,
+    pub lanes: u32,
+    pub write: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CoalescerStats {
+    pub requests: u64,
+    pub transactions: u64,
+    pub sectors: u64,
+    pub bytes_requested: u64,
+    pub uncoalesced_requests: u64, // Needed more than one line
+}
+
+impl CoalescingUnit {
+    pub fn new() -> Self {
+        Self {
+            stats: CoalescerStats::default(),
+        }
+    }
+
+    // Lanes set in mask access `size` bytes at addresses[lane]. Accesses must be
+    // naturally aligned, so none straddles a sector. Transactions come out in
+    // order of the first lane that needs each line.
+    pub fn coalesce(&mut self, mask: u32, addresses: &[u64], size: u32, write: bool) -> GPUResult<Vec<MemoryTransaction>> {
+        if !size.is_power_of_two() || size as u64 > SECTOR_SIZE {
+            return Err(GPUError::AccessViolation);
+        }
+
+        let mut transactions: Vec<MemoryTransaction> = Vec::new();
+        for (lane, &address) in addresses.iter().enumerate().take(32) {
+            if mask & (1 << lane) == 0 {
+                continue;
+            }
+            if address % size as u64 != 0 {
+                return Err(GPUError::AccessViolation);
+            }
+            let line = address & !(LINE_SIZE - 1);
+            let sector = 1u8 << ((address % LINE_SIZE) / SECTOR_SIZE);
+            match transactions.iter_mut().find(|transaction| transaction.line == line) {
+                Some(transaction) => {
+                    transaction.sector_mask |= sector;
+                    transaction.lanes |= 1 << lane;
+                }
+                None => transactions.push(MemoryTransaction { line, sector_mask: sector, lanes: 1 << lane, write }),
+            }
+            self.stats.bytes_requested += size as u64;
+        }
+
+        if !transactions.is_empty() {
+            self.stats.requests += 1;
+        }
+        if transactions.len() > 1 {
+            self.stats.uncoalesced_requests += 1;
+        }
+        self.stats.transactions += transactions.len() as u64;
+        self.stats.sectors += transactions.iter().map(|transaction| transaction.sector_mask.count_ones() as u64).sum::<u64>();
+        Ok(transactions)
+    }
+
+    pub fn get_stats(&self) -> &CoalescerStats {
+        &self.stats
+    }
+}
+
+impl Default for CoalescingUnit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CoalescerStats {
+    // Bytes lanes asked for over bytes moved in whole sectors; 1.0 wastes nothing
+    pub fn efficiency(&self) -> f32 {
+        if self.sectors == 0 {
+            return 0.0;
+        }
+        self.bytes_requested as f32 / (self.sectors * SECTOR_SIZE) as f32
+    }
+
+    pub fn transactions_per_request(&self) -> f32 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.transactions as f32 / self.requests as f32
+    }
+
+    pub fn sectors_per_request(&self) -> f32 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.sectors as f32 / self.requests as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lanes(base: u64, stride: u64) -> Vec<u64> {
+        (0..32).map(|lane| base + lane * stride).collect()
+    }
+
+    #[test]
+    fn consecutive_words_become_one_full_line() {
+        let mut unit = CoalescingUnit::new();
+        let transactions = unit.coalesce(u32::MAX, &lanes(0x1000, 4), 4, false).unwrap();
+        assert_eq!(transactions, vec![MemoryTransaction { line: 0x1000, sector_mask: 0xF, lanes: u32::MAX, write: false }]);
+
+        let stats = unit.get_stats();
+        assert_eq!(stats.efficiency(), 1.0);
+        assert_eq!(stats.uncoalesced_requests, 0);
+    }
+
+    #[test]
+    fn misaligned_bases_spill_into_a_second_line() {
+        let mut unit = CoalescingUnit::new();
+        let transactions = unit.coalesce(u32::MAX, &lanes(0x1040, 4), 4, true).unwrap();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!((transactions[0].line, transactions[0].sector_mask, transactions[0].lanes), (0x1000, 0b1100, 0x0000_FFFF));
+        assert_eq!((transactions[1].line, transactions[1].sector_mask, transactions[1].lanes), (0x1080, 0b0011, 0xFFFF_0000));
+        assert!(transactions.iter().all(|transaction| transaction.write));
+        assert_eq!(unit.get_stats().uncoalesced_requests, 1);
+    }
+
+    #[test]
+    fn line_strides_need_a_transaction_per_lane() {
+        let mut unit = CoalescingUnit::new();
+        let transactions = unit.coalesce(u32::MAX, &lanes(0, LINE_SIZE), 4, false).unwrap();
+        assert_eq!(transactions.len(), 32);
+        assert!(transactions.iter().all(|transaction| transaction.sector_mask.count_ones() == 1));
+
+        let stats = unit.get_stats();
+        assert_eq!(stats.transactions_per_request(), 32.0);
+        assert_eq!(stats.efficiency(), 4.0 / SECTOR_SIZE as f32);
+    }
+
+    #[test]
+    fn transactions_follow_the_first_lane_that_needs_each_line() {
+        let mut unit = CoalescingUnit::new();
+        let addresses = [0x300, 0x100, 0x304, 0x200];
+        let lines: Vec<u64> = unit.coalesce(0b1111, &addresses, 4, false).unwrap().iter().map(|transaction| transaction.line).collect();
+        assert_eq!(lines, vec![0x300, 0x100, 0x200]);
+    }
+
+    #[test]
+    fn inactive_lanes_and_bad_sizes() {
+        let mut unit = CoalescingUnit::new();
+        assert_eq!(unit.coalesce(0, &lanes(0, 4), 4, false), Ok(Vec::new()));
+        assert_eq!(unit.get_stats().requests, 0);
+
+        // A masked-off lane's bad address doesn't matter
+        assert_eq!(unit.coalesce(0b01, &[0, 3], 4, false).unwrap().len(), 1);
+        assert_eq!(unit.coalesce(0b11, &[0, 3], 4, false), Err(GPUError::AccessViolation));
+        assert_eq!(unit.coalesce(1, &[0], 3, false), Err(GPUError::AccessViolation));
+        assert_eq!(unit.coalesce(1, &[0], 64, false), Err(GPUError::AccessViolation));
+    }
+}
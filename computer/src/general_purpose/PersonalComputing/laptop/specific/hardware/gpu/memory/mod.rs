@@ -0,0 +1,226 @@
+// Export all modules in memory
+pub mod cache;
+pub mod coalescer;
+pub mod shared;
+pub mod unified;
+pub mod vram;
+
+use super::error::GPUResult;
+
+pub use self::cache::{CacheConfig, CacheStats, GPUCache, LevelStats};
+pub use self::coalescer::{CoalescerStats, CoalescingUnit, MemoryTransaction};
+pub use self::shared::{SharedMemory, SharedMemoryStats};
+pub use self::unified::{Location, MemoryAdvice, MigrationStats, UnifiedConfig, UnifiedMemory};
+pub use self::vram::{VRAMConfig, VRAMController};
+
+// Lanes in a warp memory request
+const REQUEST_LANES: usize = 32;
+
+// The global memory path of one SM: coalescer, then the cache stack, then a
+// DRAM channel that moves a fixed number of sectors per cycle. Uncoalesced
+// requests pay twice: more transactions, and a longer queue at the channel.
+pub struct GPUMemory {
+    coalescer: CoalescingUnit,
+    cache: GPUCache,
+    config: GPUMemoryConfig,
+    dram_free_slot: u64, // In sector slots: cycle * dram_sectors_per_cycle
+    cycle: u64,
+    stats: GPUMemoryStats,
+}
+
+#[derive(Debug, Clone)]
+pub struct GPUMemoryConfig {
+    pub l1: CacheConfig,
+    pub l2: CacheConfig,
+    pub dram_latency: u64,
+    pub dram_sectors_per_cycle: u64,
+    pub transaction_interval: u64, // The L1 takes one transaction of a request per this many cycles
+    pub shared_latency: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GPUMemoryStats {
+    pub requests: u64,
+    pub total_latency: u64,
+    pub max_latency: u64,
+    pub dram_queue_cycles: u64,
+}
+
+// Address streams for comparing access patterns; every lane reads one word
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessPattern {
+    Sequential,
+    Strided(u64), // Bytes between neighbouring lanes
+    Random { span: u64, seed: u64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct PatternReport {
+    pub pattern: AccessPattern,
+    pub requests: u64,
+    pub transactions_per_request: f32,
+    pub sectors_per_request: f32,
+    pub efficiency: f32,
+    pub l1_hit_rate: f32,
+    pub l2_hit_rate: f32,
+    pub dram_bytes: u64,
+    pub average_latency: f32,
+}
+
+impl GPUMemory {
+    pub fn new() -> Self {
+        Self::with_config(GPUMemoryConfig::default())
+    }
+
+    pub fn with_config(config: GPUMemoryConfig) -> Self {
+        Self {
+            coalescer: CoalescingUnit::new(),
+            cache: GPUCache::new(vec![config.l1.clone(), config.l2.clone()], config.dram_latency),
+            config,
+            dram_free_slot: 0,
+            cycle: 0,
+            stats: GPUMemoryStats::default(),
+        }
+    }
+
+    // One warp load or store issued at cycle `now`. Returns cycles until the
+    // last lane has its data: the slowest transaction, counting the ones ahead
+    // of it in the L1 and the sectors ahead of it at the DRAM channel.
+    pub fn access(&mut self, now: u64, mask: u32, addresses: &[u64], size: u32, write: bool) -> GPUResult<u64> {
+        let transactions = self.coalescer.coalesce(mask, addresses, size, write)?;
+        let mut latency = 0;
+        for (index, transaction) in transactions.iter().enumerate() {
+            let issue = index as u64 * self.config.transaction_interval;
+            let before = self.cache.get_stats().dram_sectors_read + self.cache.get_stats().dram_sectors_written;
+            let access = self.cache.access(transaction);
+            // Writebacks a fill pushed out share the channel with the fill itself
+            let sectors = self.cache.get_stats().dram_sectors_read + self.cache.get_stats().dram_sectors_written - before;
+            let queue = self.reserve_dram(now + issue, sectors);
+            let cost = if access.level.is_none() { access.latency + queue } else { access.latency };
+            latency = latency.max(cost + issue);
+        }
+        if !transactions.is_empty() {
+            self.stats.requests += 1;
+            self.stats.total_latency += latency;
+            self.stats.max_latency = self.stats.max_latency.max(latency);
+        }
+        Ok(latency)
+    }
+
+    // Runs a pattern against a fresh hierarchy, a warp request every `interval` cycles
+    pub fn profile(config: GPUMemoryConfig, pattern: AccessPattern, requests: u64, interval: u64) -> GPUResult<PatternReport> {
+        let mut memory = Self::with_config(config);
+        for request in 0..requests {
+            let addresses = pattern.addresses(request);
+            memory.access(request * interval, u32::MAX, &addresses, 4, false)?;
+        }
+        Ok(memory.report(pattern))
+    }
+
+    pub fn report(&self, pattern: AccessPattern) -> PatternReport {
+        let coalescer = self.coalescer.get_stats();
+        let hit_rate = |level| self.cache.level_stats(level).map(|stats| stats.hit_rate()).unwrap_or(0.0);
+        PatternReport {
+            pattern,
+            requests: self.stats.requests,
+            transactions_per_request: coalescer.transactions_per_request(),
+            sectors_per_request: coalescer.sectors_per_request(),
+            efficiency: coalescer.efficiency(),
+            l1_hit_rate: hit_rate(0),
+            l2_hit_rate: hit_rate(1),
+            dram_bytes: self.cache.get_stats().dram_bytes(),
+            average_latency: self.stats.average_latency(),
+        }
+    }
+
+    // Drops every cached line, writing dirty sectors back
+    pub fn flush(&mut self) {
+        self.cache.flush();
+    }
+
+    pub fn tick(&mut self) {
+        self.cycle += 1;
+    }
+
+    pub fn config(&self) -> &GPUMemoryConfig {
+        &self.config
+    }
+
+    pub fn cache(&self) -> &GPUCache {
+        &self.cache
+    }
+
+    pub fn coalescer_stats(&self) -> &CoalescerStats {
+        self.coalescer.get_stats()
+    }
+
+    pub fn get_stats(&self) -> &GPUMemoryStats {
+        &self.stats
+    }
+
+    // Helper methods
+    // Books sectors on the DRAM channel; returns how long they wait for it
+    fn reserve_dram(&mut self, now: u64, sectors: u64) -> u64 {
+        if sectors == 0 {
+            return 0;
+        }
+        let rate = self.config.dram_sectors_per_cycle.max(1);
+        let start = self.dram_free_slot.max(now * rate);
+        self.dram_free_slot = start + sectors;
+        let queue = (start + sectors).div_ceil(rate) - now;
+        self.stats.dram_queue_cycles += queue;
+        queue
+    }
+}
+
+impl Default for GPUMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for GPUMemoryConfig {
+    fn default() -> Self {
+        Self {
+            l1: CacheConfig::l1(),
+            l2: CacheConfig::l2(),
+            dram_latency: 400,
+            dram_sectors_per_cycle: 2,
+            transaction_interval: 1,
+            shared_latency: 24,
+        }
+    }
+}
+
+impl GPUMemoryStats {
+    pub fn average_latency(&self) -> f32 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.total_latency as f32 / self.requests as f32
+    }
+}
+
+impl AccessPattern {
+    // Byte addresses of the 32 lanes of the request-th warp request
+    pub fn addresses(&self, request: u64) -> Vec<u64> {
+        (0..REQUEST_LANES as u64)
+            .map(|lane| {
+                let thread = request * REQUEST_LANES as u64 + lane;
+                match *self {
+                    AccessPattern::Sequential => thread * 4,
+                    AccessPattern::Strided(stride) => thread * stride,
+                    AccessPattern::Random { span, seed } => (mix(seed ^ thread) % (span / 4).max(1)) * 4,
+                }
+            })
+            .collect()
+    }
+}
+
+// splitmix64 finalizer
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
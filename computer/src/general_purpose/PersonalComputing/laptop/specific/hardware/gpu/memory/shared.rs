@@ -0,0 +1,170 @@
+use super::super::error::{GPUError, GPUResult};
+
+// Successive 4-byte words live in successive banks
+pub const SHARED_BANKS: usize = 32;
+pub const BANK_WIDTH: u32 = 4;
+
+// On-chip scratchpad shared by the warps of a launch. Each bank serves one
+// word per cycle, so lanes hitting different words of the same bank are
+// replayed one after another; lanes reading the same word share a broadcast.
+pub struct SharedMemory {
+    words: Vec<u32>,
+    stats: SharedMemoryStats,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SharedMemoryStats {
+    pub accesses: u64,
+    pub wavefronts: u64,
+    pub bank_conflicts: u64, // Wavefronts beyond the first
+    pub broadcasts: u64,     // Lanes served by another lane's word
+}
+
+impl SharedMemory {
+    pub fn new(bytes: usize) -> Self {
+        Self {
+            words: vec![0; bytes.div_ceil(BANK_WIDTH as usize)],
+            stats: SharedMemoryStats::default(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.words.len() * BANK_WIDTH as usize
+    }
+
+    // Reads a word per active lane; returns the wavefronts it took
+    pub fn load(&mut self, mask: u32, addresses: &[u32], values: &mut [u32]) -> GPUResult<u32> {
+        let wavefronts = self.schedule(mask, addresses)?;
+        for lane in active(mask, addresses.len()) {
+            values[lane] = self.words[(addresses[lane] / BANK_WIDTH) as usize];
+        }
+        Ok(wavefronts)
+    }
+
+    // Lanes storing to the same word: the highest lane wins
+    pub fn store(&mut self, mask: u32, addresses: &[u32], values: &[u32]) -> GPUResult<u32> {
+        let wavefronts = self.schedule(mask, addresses)?;
+        for lane in active(mask, addresses.len()) {
+            self.words[(addresses[lane] / BANK_WIDTH) as usize] = values[lane];
+        }
+        Ok(wavefronts)
+    }
+
+    pub fn words(&self) -> &[u32] {
+        &self.words
+    }
+
+    pub fn get_stats(&self) -> &SharedMemoryStats {
+        &self.stats
+    }
+
+    // Helper methods
+    // The access takes as many wavefronts as the busiest bank has distinct words
+    fn schedule(&mut self, mask: u32, addresses: &[u32]) -> GPUResult<u32> {
+        let mut banks: [Vec<u32>; SHARED_BANKS] = std::array::from_fn(|_| Vec::new());
+        let mut lanes = 0;
+        for lane in active(mask, addresses.len()) {
+            let address = addresses[lane];
+            if !address.is_multiple_of(BANK_WIDTH) || (address / BANK_WIDTH) as usize >= self.words.len() {
+                return Err(GPUError::AccessViolation);
+            }
+            let word = address / BANK_WIDTH;
+            let bank = &mut banks[word as usize % SHARED_BANKS];
+            if bank.contains(&word) {
+                self.stats.broadcasts += 1;
+            } else {
+                bank.push(word);
+            }
+            lanes += 1;
+        }
+        if lanes == 0 {
+            return Ok(0);
+        }
+
+        let wavefronts = banks.iter().map(|bank| bank.len() as u32).max().unwrap_or(0);
+        self.stats.accesses += 1;
+        self.stats.wavefronts += wavefronts as u64;
+        self.stats.bank_conflicts += wavefronts as u64 - 1;
+        Ok(wavefronts)
+    }
+}
+
+impl SharedMemoryStats {
+    // Average wavefronts per access; 1.0 is conflict free, 32.0 fully serialized
+    pub fn replay_factor(&self) -> f32 {
+        if self.accesses == 0 {
+            return 0.0;
+        }
+        self.wavefronts as f32 / self.accesses as f32
+    }
+}
+
+fn active(mask: u32, lanes: usize) -> impl Iterator<Item = usize> {
+    (0..lanes.min(32)).filter(move |lane| mask & (1 << lane) != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strided(stride_words: u32) -> Vec<u32> {
+        (0..32).map(|lane| lane * stride_words * BANK_WIDTH).collect()
+    }
+
+    #[test]
+    fn consecutive_words_take_one_wavefront() {
+        let mut shared = SharedMemory::new(16 * 1024);
+        assert_eq!(shared.store(u32::MAX, &strided(1), &(0..32).collect::<Vec<_>>()), Ok(1));
+
+        let mut values = [0u32; 32];
+        assert_eq!(shared.load(u32::MAX, &strided(1), &mut values), Ok(1));
+        assert_eq!(values.to_vec(), (0..32).collect::<Vec<_>>());
+        assert_eq!(shared.get_stats().bank_conflicts, 0);
+    }
+
+    #[test]
+    fn strides_that_share_banks_replay() {
+        let mut shared = SharedMemory::new(16 * 1024);
+        let mut values = [0u32; 32];
+        assert_eq!(shared.load(u32::MAX, &strided(2), &mut values), Ok(2));
+        assert_eq!(shared.load(u32::MAX, &strided(32), &mut values), Ok(32));
+        // Odd strides visit every bank once
+        assert_eq!(shared.load(u32::MAX, &strided(3), &mut values), Ok(1));
+
+        let stats = shared.get_stats();
+        assert_eq!(stats.bank_conflicts, 1 + 31);
+        assert_eq!(stats.replay_factor(), 35.0 / 3.0);
+    }
+
+    #[test]
+    fn lanes_reading_one_word_share_a_broadcast() {
+        let mut shared = SharedMemory::new(1024);
+        shared.store(1, &[64], &[7]).unwrap();
+
+        let mut values = [0u32; 32];
+        assert_eq!(shared.load(u32::MAX, &[64; 32], &mut values), Ok(1));
+        assert!(values.iter().all(|&value| value == 7));
+        assert_eq!(shared.get_stats().broadcasts, 31);
+    }
+
+    #[test]
+    fn highest_lane_wins_a_store_to_one_word() {
+        let mut shared = SharedMemory::new(1024);
+        let values: Vec<u32> = (100..132).collect();
+        shared.store(0x0000_FFFF, &[8; 32], &values).unwrap();
+        assert_eq!(shared.words()[2], 115);
+    }
+
+    #[test]
+    fn inactive_lanes_are_ignored_and_bad_addresses_fault() {
+        let mut shared = SharedMemory::new(1024);
+        let mut values = [0u32; 32];
+        assert_eq!(shared.load(0, &strided(1), &mut values), Ok(0));
+        assert_eq!(shared.get_stats().accesses, 0);
+
+        // Lane 1 is out of range but masked off
+        assert_eq!(shared.load(0b01, &[0, 4096], &mut values), Ok(1));
+        assert_eq!(shared.load(0b11, &[0, 4096], &mut values), Err(GPUError::AccessViolation));
+        assert_eq!(shared.load(0b01, &[2], &mut values), Err(GPUError::AccessViolation));
+    }
+}
@@ -0,0 +1,575 @@
+use super::super::error::{GPUError, GPUResult};
+use super::vram::VRAMController;
+use crate::hardware::bus::pci_bus::PciBus;
+use crate::hardware::memory::physical::PhysicalMemory;
+use std::collections::HashMap;
+
+// Unified addressing: the CPU and the GPU share one virtual address space for
+// managed allocations. Every page keeps a home frame in system RAM and may also
+// hold a VRAM frame. A side that touches a page the other side owns takes a
+// fault, and the driver migrates the page across PCIe before replaying the access.
+pub struct UnifiedMemory<M: PhysicalMemory> {
+    system: M,
+    vram: VRAMController,
+    link: PciBus,
+    config: UnifiedConfig,
+    pages: HashMap<u64, UnifiedPage>,
+    allocations: HashMap<u64, u64>, // Base to size
+    system_free: Vec<u64>,
+    next_system: u64,
+    next_virtual: u64,
+    clock: u64,
+    stats: MigrationStats,
+}
+
+struct UnifiedPage {
+    system_address: u64,
+    vram_frame: Option<usize>,
+    dirty: bool, // The VRAM copy is newer than the system one
+    preferred: Option<Location>,
+    last_access: u64,
+    evicted: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Location {
+    System,
+    Vram,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryAdvice {
+    // Pinned to System, GPU accesses go over PCIe instead of migrating the page
+    PreferredLocation(Location),
+    Unset,
+}
+
+#[derive(Debug, Clone)]
+pub struct UnifiedConfig {
+    pub system_base: u64, // System RAM handed over to managed allocations
+    pub system_size: u64,
+    pub virtual_base: u64,
+    pub fault_batch: u64, // Pages migrated per GPU fault, as an aligned block
+    pub fault_overhead_ns: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MigrationStats {
+    pub gpu_faults: u64,
+    pub cpu_faults: u64,
+    pub pages_to_vram: u64,
+    pub pages_to_system: u64,
+    pub bytes_to_vram: u64,
+    pub bytes_to_system: u64,
+    pub evictions: u64,
+    pub clean_evictions: u64, // Dropped without a copy back
+    pub refaults: u64,        // Evicted pages the GPU wanted again
+    pub prefetched_pages: u64,
+    pub remote_accesses: u64,
+    pub remote_bytes: u64,
+    pub stall_ns: u64,
+}
+
+impl<M: PhysicalMemory> UnifiedMemory<M> {
+    pub fn new(system: M, vram: VRAMController, link: PciBus, config: UnifiedConfig) -> Self {
+        Self {
+            system,
+            vram,
+            link,
+            next_system: config.system_base,
+            next_virtual: config.virtual_base,
+            config,
+            pages: HashMap::new(),
+            allocations: HashMap::new(),
+            system_free: Vec::new(),
+            clock: 0,
+            stats: MigrationStats::default(),
+        }
+    }
+
+    // Managed allocation: zeroed, resident in system RAM until first touched by the GPU
+    pub fn allocate(&mut self, size: u64) -> GPUResult<u64> {
+        let page_size = self.page_size();
+        let count = size.div_ceil(page_size).max(1);
+        let available = self.system_free.len() as u64 + (self.config.system_base + self.config.system_size - self.next_system) / page_size;
+        if count > available {
+            return Err(GPUError::OutOfMemory);
+        }
+
+        let base = self.next_virtual;
+        let zeros = vec![0u8; page_size as usize];
+        for index in 0..count {
+            let system_address = match self.system_free.pop() {
+                Some(address) => address,
+                None => {
+                    self.next_system += page_size;
+                    self.next_system - page_size
+                }
+            };
+            self.system.write_physical(system_address, &zeros).map_err(|_| GPUError::AccessViolation)?;
+            self.pages.insert(base / page_size + index, UnifiedPage {
+                system_address,
+                vram_frame: None,
+                dirty: false,
+                preferred: None,
+                last_access: 0,
+                evicted: false,
+            });
+        }
+        // A guard page between allocations catches overruns
+        self.next_virtual += (count + 1) * page_size;
+        self.allocations.insert(base, count * page_size);
+        Ok(base)
+    }
+
+    pub fn free(&mut self, base: u64) -> GPUResult<()> {
+        let size = self.allocations.remove(&base).ok_or(GPUError::AccessViolation)?;
+        let page_size = self.page_size();
+        for vpn in base / page_size..(base + size) / page_size {
+            if let Some(page) = self.pages.remove(&vpn) {
+                if let Some(frame) = page.vram_frame {
+                    self.vram.free_frame(frame);
+                }
+                self.system_free.push(page.system_address);
+            }
+        }
+        Ok(())
+    }
+
+    // GPU side. Returns the nanoseconds the access stalled on faults and migrations.
+    pub fn gpu_read(&mut self, address: u64, buffer: &mut [u8]) -> GPUResult<u64> {
+        let mut stall = 0;
+        let mut done = 0;
+        while done < buffer.len() {
+            let (vpn, offset, length) = self.split(address + done as u64, buffer.len() - done);
+            stall += self.gpu_touch(vpn, length as u64)?;
+            let page = &self.pages[&vpn];
+            let chunk = &mut buffer[done..done + length];
+            match page.vram_frame {
+                Some(frame) => self.vram.read_frame(frame, offset, chunk)?,
+                None => self.system.read_physical(page.system_address + offset as u64, chunk).map_err(|_| GPUError::AccessViolation)?,
+            }
+            done += length;
+        }
+        self.stats.stall_ns += stall;
+        Ok(stall)
+    }
+
+    pub fn gpu_write(&mut self, address: u64, data: &[u8]) -> GPUResult<u64> {
+        let mut stall = 0;
+        let mut done = 0;
+        while done < data.len() {
+            let (vpn, offset, length) = self.split(address + done as u64, data.len() - done);
+            stall += self.gpu_touch(vpn, length as u64)?;
+            let page = self.pages.get_mut(&vpn).ok_or(GPUError::PageFault)?;
+            let chunk = &data[done..done + length];
+            match page.vram_frame {
+                Some(frame) => {
+                    page.dirty = true;
+                    self.vram.write_frame(frame, offset, chunk)?;
+                }
+                None => self.system.write_physical(page.system_address + offset as u64, chunk).map_err(|_| GPUError::AccessViolation)?,
+            }
+            done += length;
+        }
+        self.stats.stall_ns += stall;
+        Ok(stall)
+    }
+
+    // CPU side: a page the GPU holds comes home first
+    pub fn cpu_read(&mut self, address: u64, buffer: &mut [u8]) -> GPUResult<u64> {
+        let mut stall = 0;
+        let mut done = 0;
+        while done < buffer.len() {
+            let (vpn, offset, length) = self.split(address + done as u64, buffer.len() - done);
+            stall += self.cpu_touch(vpn)?;
+            let system_address = self.pages[&vpn].system_address + offset as u64;
+            self.system.read_physical(system_address, &mut buffer[done..done + length]).map_err(|_| GPUError::AccessViolation)?;
+            done += length;
+        }
+        self.stats.stall_ns += stall;
+        Ok(stall)
+    }
+
+    pub fn cpu_write(&mut self, address: u64, data: &[u8]) -> GPUResult<u64> {
+        let mut stall = 0;
+        let mut done = 0;
+        while done < data.len() {
+            let (vpn, offset, length) = self.split(address + done as u64, data.len() - done);
+            stall += self.cpu_touch(vpn)?;
+            let system_address = self.pages[&vpn].system_address + offset as u64;
+            self.system.write_physical(system_address, &data[done..done + length]).map_err(|_| GPUError::AccessViolation)?;
+            done += length;
+        }
+        self.stats.stall_ns += stall;
+        Ok(stall)
+    }
+
+    // Moves a range ahead of use in one DMA per contiguous run, with no fault
+    // overhead. Returns the nanoseconds the copy took.
+    pub fn prefetch(&mut self, address: u64, size: u64, target: Location) -> GPUResult<u64> {
+        let page_size = self.page_size();
+        let pages: Vec<u64> = (address / page_size..(address + size).div_ceil(page_size))
+            .filter(|vpn| self.pages.get(vpn).map(|page| page.location() != target).unwrap_or(false))
+            .collect();
+        let time = match target {
+            Location::Vram => self.migrate_to_vram(&pages)?,
+            Location::System => {
+                let mut time = 0;
+                for &vpn in &pages {
+                    time += self.migrate_to_system(vpn)?;
+                }
+                time
+            }
+        };
+        self.stats.prefetched_pages += pages.len() as u64;
+        Ok(time)
+    }
+
+    pub fn advise(&mut self, address: u64, size: u64, advice: MemoryAdvice) -> GPUResult<()> {
+        let page_size = self.page_size();
+        for vpn in address / page_size..(address + size).div_ceil(page_size) {
+            let page = self.pages.get_mut(&vpn).ok_or(GPUError::PageFault)?;
+            page.preferred = match advice {
+                MemoryAdvice::PreferredLocation(location) => Some(location),
+                MemoryAdvice::Unset => None,
+            };
+        }
+        Ok(())
+    }
+
+    pub fn location(&self, address: u64) -> Option<Location> {
+        self.pages.get(&(address / self.page_size())).map(|page| page.location())
+    }
+
+    // Managed bytes over VRAM capacity; above 1.0 the working set cannot all be resident
+    pub fn oversubscription(&self) -> f32 {
+        let managed: u64 = self.allocations.values().sum();
+        managed as f32 / (self.vram.total_frames() as u64 * self.page_size()) as f32
+    }
+
+    pub fn resident_pages(&self) -> usize {
+        self.pages.values().filter(|page| page.vram_frame.is_some()).count()
+    }
+
+    pub fn vram(&self) -> &VRAMController {
+        &self.vram
+    }
+
+    pub fn link(&self) -> &PciBus {
+        &self.link
+    }
+
+    pub fn system(&mut self) -> &mut M {
+        &mut self.system
+    }
+
+    pub fn get_stats(&self) -> &MigrationStats {
+        &self.stats
+    }
+
+    // Helper methods
+    fn page_size(&self) -> u64 {
+        self.vram.page_size() as u64
+    }
+
+    fn split(&self, address: u64, length: usize) -> (u64, usize, usize) {
+        let page_size = self.page_size();
+        let offset = (address % page_size) as usize;
+        (address / page_size, offset, length.min(page_size as usize - offset))
+    }
+
+    // Resolves a GPU access to a page: a hit, a remote access, or a fault
+    // that migrates the page's whole aligned block
+    fn gpu_touch(&mut self, vpn: u64, bytes: u64) -> GPUResult<u64> {
+        self.clock += 1;
+        let clock = self.clock;
+        let page = self.pages.get_mut(&vpn).ok_or(GPUError::PageFault)?;
+        page.last_access = clock;
+        if page.vram_frame.is_some() {
+            return Ok(0);
+        }
+        if page.preferred == Some(Location::System) {
+            self.stats.remote_accesses += 1;
+            self.stats.remote_bytes += bytes;
+            return Ok(self.link.dma_read(page.system_address, bytes));
+        }
+
+        self.stats.gpu_faults += 1;
+        let batch = self.config.fault_batch.max(1);
+        let first = vpn / batch * batch;
+        let block: Vec<u64> = (first..first + batch)
+            .filter(|candidate| {
+                self.pages
+                    .get(candidate)
+                    .map(|page| page.vram_frame.is_none() && page.preferred != Some(Location::System))
+                    .unwrap_or(false)
+            })
+            .collect();
+        Ok(self.config.fault_overhead_ns + self.migrate_to_vram(&block)?)
+    }
+
+    fn cpu_touch(&mut self, vpn: u64) -> GPUResult<u64> {
+        let page = self.pages.get(&vpn).ok_or(GPUError::PageFault)?;
+        if page.vram_frame.is_none() {
+            return Ok(0);
+        }
+        self.stats.cpu_faults += 1;
+        Ok(self.config.fault_overhead_ns + self.migrate_to_system(vpn)?)
+    }
+
+    // Copies the pages into VRAM as one transfer, evicting the least recently
+    // used resident pages outside the batch if VRAM is full
+    fn migrate_to_vram(&mut self, pages: &[u64]) -> GPUResult<u64> {
+        if pages.is_empty() {
+            return Ok(0);
+        }
+        let page_size = self.page_size();
+        let mut time = 0;
+        let mut buffer = vec![0u8; page_size as usize];
+        for &vpn in pages {
+            let frame = match self.vram.allocate_frame() {
+                Ok(frame) => frame,
+                Err(_) => {
+                    time += self.evict(pages)?;
+                    self.vram.allocate_frame()?
+                }
+            };
+            let page = self.pages.get_mut(&vpn).ok_or(GPUError::PageFault)?;
+            self.system.read_physical(page.system_address, &mut buffer).map_err(|_| GPUError::AccessViolation)?;
+            self.vram.write_frame(frame, 0, &buffer)?;
+            page.vram_frame = Some(frame);
+            page.dirty = false;
+            page.last_access = self.clock;
+            if page.evicted {
+                page.evicted = false;
+                self.stats.refaults += 1;
+            }
+        }
+        let bytes = pages.len() as u64 * page_size;
+        self.stats.pages_to_vram += pages.len() as u64;
+        self.stats.bytes_to_vram += bytes;
+        // The GPU pulls the block out of system RAM
+        let source = pages.first().and_then(|vpn| self.pages.get(vpn)).map_or(0, |page| page.system_address);
+        Ok(time + self.link.dma_read(source, bytes))
+    }
+
+    fn migrate_to_system(&mut self, vpn: u64) -> GPUResult<u64> {
+        let page_size = self.page_size();
+        let page = self.pages.get_mut(&vpn).ok_or(GPUError::PageFault)?;
+        let frame = match page.vram_frame.take() {
+            Some(frame) => frame,
+            None => return Ok(0),
+        };
+        let mut time = 0;
+        if page.dirty {
+            let mut buffer = vec![0u8; page_size as usize];
+            self.vram.read_frame(frame, 0, &mut buffer)?;
+            self.system.write_physical(page.system_address, &buffer).map_err(|_| GPUError::AccessViolation)?;
+            page.dirty = false;
+            self.stats.pages_to_system += 1;
+            self.stats.bytes_to_system += page_size;
+            time = self.link.dma_write(page.system_address, page_size);
+        }
+        self.vram.free_frame(frame);
+        Ok(time)
+    }
+
+    fn evict(&mut self, protected: &[u64]) -> GPUResult<u64> {
+        let victim = self
+            .pages
+            .iter()
+            .filter(|(vpn, page)| page.vram_frame.is_some() && !protected.contains(vpn))
+            .min_by_key(|(_, page)| page.last_access)
+            .map(|(&vpn, _)| vpn)
+            .ok_or(GPUError::OutOfMemory)?;
+
+        self.stats.evictions += 1;
+        if !self.pages[&victim].dirty {
+            self.stats.clean_evictions += 1;
+        }
+        let time = self.migrate_to_system(victim)?;
+        if let Some(page) = self.pages.get_mut(&victim) {
+            page.evicted = true;
+        }
+        Ok(time)
+    }
+}
+
+impl UnifiedPage {
+    fn location(&self) -> Location {
+        if self.vram_frame.is_some() {
+            Location::Vram
+        } else {
+            Location::System
+        }
+    }
+}
+
+impl Default for UnifiedConfig {
+    fn default() -> Self {
+        Self {
+            system_base: 0,
+            system_size: 0,
+            virtual_base: 0x7F00_0000_0000,
+            fault_batch: 16, // 64KB blocks of 4KB pages
+            fault_overhead_ns: 20_000,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::vram::VRAMConfig;
+    use crate::hardware::memory::physical::SystemRam;
+
+    const PAGE: u64 = 4096;
+    const FAULT_NS: u64 = 20_000;
+
+    fn unified(vram_pages: usize, fault_batch: u64) -> UnifiedMemory<SystemRam> {
+        let vram = VRAMController::with_config(VRAMConfig {
+            total_size: vram_pages * PAGE as usize,
+            page_size: PAGE as usize,
+            num_banks: 32,
+            bank_width: 256,
+        });
+        let config = UnifiedConfig {
+            system_base: 0,
+            system_size: 64 * PAGE,
+            fault_batch,
+            fault_overhead_ns: FAULT_NS,
+            ..UnifiedConfig::default()
+        };
+        UnifiedMemory::new(SystemRam::new(64 * PAGE as usize), vram, PciBus::new(), config)
+    }
+
+    #[test]
+    fn allocations_start_zeroed_in_system_ram_behind_a_guard_page() {
+        let mut memory = unified(8, 4);
+        let base = memory.allocate(3 * PAGE).unwrap();
+        assert_eq!(memory.location(base + 2 * PAGE), Some(Location::System));
+
+        let mut buffer = [0xFFu8; 16];
+        assert_eq!(memory.cpu_read(base + PAGE, &mut buffer), Ok(0));
+        assert_eq!(buffer, [0; 16]);
+        assert_eq!(memory.gpu_read(base + 3 * PAGE, &mut buffer), Err(GPUError::PageFault));
+        assert_eq!(memory.allocate(PAGE).unwrap(), base + 4 * PAGE);
+    }
+
+    #[test]
+    fn gpu_faults_migrate_the_whole_aligned_block() {
+        let mut memory = unified(16, 4);
+        let base = memory.allocate(8 * PAGE).unwrap();
+        memory.cpu_write(base + PAGE + 8, b"unified").unwrap();
+
+        let mut buffer = [0u8; 7];
+        let stall = memory.gpu_read(base + PAGE + 8, &mut buffer).unwrap();
+        assert_eq!(&buffer, b"unified");
+        assert!(stall > FAULT_NS, "fault overhead plus the copy, got {}", stall);
+        for page in 0..8 {
+            let expected = if page < 4 { Location::Vram } else { Location::System };
+            assert_eq!(memory.location(base + page * PAGE), Some(expected), "page {}", page);
+        }
+
+        // Resident now: no further stalls
+        assert_eq!(memory.gpu_read(base + 3 * PAGE, &mut buffer), Ok(0));
+        let stats = memory.get_stats();
+        assert_eq!((stats.gpu_faults, stats.pages_to_vram, stats.bytes_to_vram), (1, 4, 4 * PAGE));
+        assert_eq!(memory.resident_pages(), 4);
+    }
+
+    #[test]
+    fn cpu_faults_copy_back_only_dirty_pages() {
+        let mut memory = unified(16, 1);
+        let base = memory.allocate(2 * PAGE).unwrap();
+        memory.gpu_write(base, b"dirty").unwrap();
+        let mut buffer = [0u8; 5];
+        memory.gpu_read(base + PAGE, &mut buffer).unwrap();
+
+        assert!(memory.cpu_read(base, &mut buffer).unwrap() > FAULT_NS);
+        assert_eq!(&buffer, b"dirty");
+        assert_eq!(memory.cpu_read(base + PAGE, &mut buffer), Ok(FAULT_NS));
+
+        let stats = memory.get_stats();
+        assert_eq!((stats.cpu_faults, stats.pages_to_system, stats.bytes_to_system), (2, 1, PAGE));
+        assert_eq!(memory.resident_pages(), 0);
+        assert_eq!(memory.vram().free_frames(), 16);
+    }
+
+    #[test]
+    fn oversubscription_evicts_the_least_recently_used_page() {
+        let mut memory = unified(2, 1);
+        let base = memory.allocate(3 * PAGE).unwrap();
+        assert_eq!(memory.oversubscription(), 1.5);
+
+        let mut buffer = [0u8; 4];
+        memory.gpu_write(base, b"keep").unwrap();
+        memory.gpu_read(base + PAGE, &mut buffer).unwrap();
+        memory.gpu_read(base + 2 * PAGE, &mut buffer).unwrap();
+        assert_eq!(memory.location(base), Some(Location::System));
+        assert_eq!(memory.location(base + PAGE), Some(Location::Vram));
+
+        // The dirty victim was written back, so the refault sees its data
+        memory.gpu_read(base, &mut buffer).unwrap();
+        assert_eq!(&buffer, b"keep");
+        let stats = memory.get_stats();
+        assert_eq!((stats.evictions, stats.clean_evictions, stats.refaults), (2, 1, 1));
+        assert_eq!(stats.pages_to_system, 1);
+    }
+
+    #[test]
+    fn system_preferred_pages_are_accessed_remotely() {
+        let mut memory = unified(8, 4);
+        let base = memory.allocate(4 * PAGE).unwrap();
+        memory.advise(base, 4 * PAGE, MemoryAdvice::PreferredLocation(Location::System)).unwrap();
+
+        memory.gpu_write(base + 100, b"remote").unwrap();
+        let mut buffer = [0u8; 6];
+        assert_eq!(memory.cpu_read(base + 100, &mut buffer), Ok(0));
+        assert_eq!(&buffer, b"remote");
+
+        let stats = memory.get_stats();
+        assert_eq!((stats.gpu_faults, stats.remote_accesses, stats.remote_bytes), (0, 1, 6));
+        assert_eq!(memory.resident_pages(), 0);
+
+        memory.advise(base, PAGE, MemoryAdvice::Unset).unwrap();
+        memory.gpu_read(base, &mut buffer).unwrap();
+        assert_eq!(memory.get_stats().gpu_faults, 1);
+        // Pages still pinned to System stay out of the migrated block
+        assert_eq!(memory.resident_pages(), 1);
+        assert_eq!(memory.advise(base + 8 * PAGE, PAGE, MemoryAdvice::Unset), Err(GPUError::PageFault));
+    }
+
+    #[test]
+    fn prefetch_moves_pages_without_faulting() {
+        let mut memory = unified(8, 4);
+        let base = memory.allocate(6 * PAGE).unwrap();
+        assert!(memory.prefetch(base, 6 * PAGE, Location::Vram).unwrap() > 0);
+        assert_eq!(memory.resident_pages(), 6);
+
+        let mut buffer = [0u8; 8];
+        assert_eq!(memory.gpu_read(base + 5 * PAGE, &mut buffer), Ok(0));
+        memory.prefetch(base, 2 * PAGE, Location::System).unwrap();
+        assert_eq!(memory.resident_pages(), 4);
+
+        let stats = memory.get_stats();
+        assert_eq!((stats.gpu_faults, stats.prefetched_pages), (0, 8));
+    }
+
+    #[test]
+    fn freeing_returns_frames_and_system_pages() {
+        let mut memory = unified(4, 4);
+        let base = memory.allocate(64 * PAGE).unwrap();
+        assert_eq!(memory.allocate(PAGE), Err(GPUError::OutOfMemory));
+
+        let mut buffer = [0u8; 4];
+        memory.gpu_read(base, &mut buffer).unwrap();
+        assert_eq!(memory.vram().free_frames(), 0);
+
+        memory.free(base).unwrap();
+        assert_eq!(memory.vram().free_frames(), 4);
+        assert_eq!(memory.free(base), Err(GPUError::AccessViolation));
+        assert!(memory.allocate(64 * PAGE).is_ok());
+    }
+}
@@ -1,17 +1,23 @@
 use super::super::error::{GPUError, GPUResult};
 use std::collections::HashMap;
 
+// Device memory carved into page frames. Allocations get GPU virtual
+// addresses mapped page by page onto frames; unified memory borrows frames
+// directly and keeps its own mapping. Frames are only backed by host
+// memory once written; until then they read as zeros.
 pub struct VRAMController {
-    memory: Vec<u8>,
+    memory: HashMap<usize, Box<[u8]>>,
+    total_frames: usize,
     page_table: HashMap<u64, PhysicalPage>,
     free_pages: Vec<usize>,
+    next_virtual: u64,
+    clock: u64,
     stats: VRAMStats,
     config: VRAMConfig,
 }
 
 struct PhysicalPage {
-    address: usize,
-    size: usize,
+    frame: usize,
     flags: PageFlags,
     last_access: u64,
     access_count: u64,
@@ -21,113 +27,193 @@ struct PhysicalPage {
 struct PageFlags {
     readable: bool,
     writable: bool,
+    #[allow(dead_code)]
     cacheable: bool,
     resident: bool,
 }
 
-struct VRAMStats {
-    total_bytes: u64,
-    used_bytes: u64,
-    page_faults: u64,
-    bandwidth_usage: f32,
-    power_consumption: f32,
+#[derive(Debug, Clone, Default)]
+pub struct VRAMStats {
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    pub page_faults: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
 }
 
-struct VRAMConfig {
-    total_size: usize,
-    page_size: usize,
-    num_banks: usize,
-    bank_width: usize,
+#[derive(Debug, Clone)]
+pub struct VRAMConfig {
+    pub total_size: usize,
+    pub page_size: usize,
+    pub num_banks: usize,
+    pub bank_width: usize, // Bits
 }
 
 impl VRAMController {
     pub fn new() -> Self {
-        let config = VRAMConfig {
+        Self::with_config(VRAMConfig {
             total_size: 8 * 1024 * 1024 * 1024, // 8GB
             page_size: 4096,
             num_banks: 32,
-            bank_width: 256, // bits
-        };
+            bank_width: 256,
+        })
+    }
 
+    pub fn with_config(config: VRAMConfig) -> Self {
+        let frames = config.total_size / config.page_size;
         Self {
-            memory: vec![0; config.total_size],
+            memory: HashMap::new(),
+            total_frames: frames,
             page_table: HashMap::new(),
-            free_pages: (0..(config.total_size / config.page_size)).collect(),
-            stats: VRAMStats::default(),
+            // Popped from the back, so low frames go first
+            free_pages: (0..frames).rev().collect(),
+            // Page zero stays unmapped so a null GPU pointer faults
+            next_virtual: config.page_size as u64,
+            clock: 0,
+            stats: VRAMStats { total_bytes: (frames * config.page_size) as u64, ..VRAMStats::default() },
             config,
         }
     }
 
     pub fn allocate(&mut self, size: usize) -> GPUResult<u64> {
-        let num_pages = (size + self.config.page_size - 1) / self.config.page_size;
-        
+        let num_pages = size.div_ceil(self.config.page_size).max(1);
         if self.free_pages.len() < num_pages {
             return Err(GPUError::OutOfMemory);
         }
 
-        let virtual_address = self.generate_virtual_address();
-        
-        for _ in 0..num_pages {
-            let physical_page = self.free_pages.pop().unwrap();
-            self.page_table.insert(virtual_address, PhysicalPage {
-                address: physical_page * self.config.page_size,
-                size: self.config.page_size,
+        let virtual_address = self.next_virtual;
+        let first_page = virtual_address / self.config.page_size as u64;
+        for page in 0..num_pages as u64 {
+            let frame = self.allocate_frame()?;
+            self.page_table.insert(first_page + page, PhysicalPage {
+                frame,
                 flags: PageFlags::default(),
                 last_access: 0,
                 access_count: 0,
             });
         }
-
-        self.stats.used_bytes += size as u64;
+        self.next_virtual += (num_pages * self.config.page_size) as u64;
         Ok(virtual_address)
     }
 
     pub fn read(&mut self, address: u64, buffer: &mut [u8]) -> GPUResult<()> {
-        let page = self.get_page(address)?;
-        let offset = (address as usize) % self.config.page_size;
-        
-        if offset + buffer.len() > page.size {
-            return Err(GPUError::AccessViolation);
+        let mut done = 0;
+        while done < buffer.len() {
+            let (frame, offset, length) = self.translate(address + done as u64, buffer.len() - done, false)?;
+            self.copy_out(frame, offset, &mut buffer[done..done + length]);
+            done += length;
         }
-
-        buffer.copy_from_slice(&self.memory[page.address + offset..][..buffer.len()]);
-        self.update_access_stats(page);
-        
+        self.stats.bytes_read += buffer.len() as u64;
         Ok(())
     }
 
     pub fn write(&mut self, address: u64, buffer: &[u8]) -> GPUResult<()> {
-        let page = self.get_page(address)?;
-        let offset = (address as usize) % self.config.page_size;
-        
-        if !page.flags.writable {
-            return Err(GPUError::AccessViolation);
+        let mut done = 0;
+        while done < buffer.len() {
+            let (frame, offset, length) = self.translate(address + done as u64, buffer.len() - done, true)?;
+            self.frame_mut(frame)[offset..offset + length].copy_from_slice(&buffer[done..done + length]);
+            done += length;
         }
+        self.stats.bytes_written += buffer.len() as u64;
+        Ok(())
+    }
+
+    // Raw frames, for managers that keep their own page tables
+    pub fn allocate_frame(&mut self) -> GPUResult<usize> {
+        let frame = self.free_pages.pop().ok_or(GPUError::OutOfMemory)?;
+        self.stats.used_bytes += self.config.page_size as u64;
+        Ok(frame)
+    }
+
+    pub fn free_frame(&mut self, frame: usize) {
+        self.memory.remove(&frame);
+        self.free_pages.push(frame);
+        self.stats.used_bytes -= self.config.page_size as u64;
+    }
+
+    pub fn read_frame(&mut self, frame: usize, offset: usize, buffer: &mut [u8]) -> GPUResult<()> {
+        self.check_range(frame, offset, buffer.len())?;
+        self.copy_out(frame, offset, buffer);
+        self.stats.bytes_read += buffer.len() as u64;
+        Ok(())
+    }
+
+    pub fn write_frame(&mut self, frame: usize, offset: usize, data: &[u8]) -> GPUResult<()> {
+        self.check_range(frame, offset, data.len())?;
+        self.frame_mut(frame)[offset..offset + data.len()].copy_from_slice(data);
+        self.stats.bytes_written += data.len() as u64;
+        Ok(())
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.free_pages.len()
+    }
 
-        if offset + buffer.len() > page.size {
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.config.page_size
+    }
+
+    pub fn get_stats(&self) -> &VRAMStats {
+        &self.stats
+    }
+
+    pub fn tick(&mut self) {
+        self.clock += 1;
+    }
+
+    // Helper methods
+    // Frame, offset in it, and how much of `length` fits before the page ends
+    fn translate(&mut self, address: u64, length: usize, write: bool) -> GPUResult<(usize, usize, usize)> {
+        let page_size = self.config.page_size;
+        let clock = self.clock;
+        let page = match self.page_table.get_mut(&(address / page_size as u64)) {
+            Some(page) => page,
+            None => {
+                self.stats.page_faults += 1;
+                return Err(GPUError::PageFault);
+            }
+        };
+        if !page.flags.resident {
+            self.stats.page_faults += 1;
+            return Err(GPUError::PageFault);
+        }
+        if (write && !page.flags.writable) || (!write && !page.flags.readable) {
             return Err(GPUError::AccessViolation);
         }
+        page.last_access = clock;
+        page.access_count += 1;
+
+        let offset = (address % page_size as u64) as usize;
+        Ok((page.frame, offset, length.min(page_size - offset)))
+    }
 
-        self.memory[page.address + offset..][..buffer.len()].copy_from_slice(buffer);
-        self.update_access_stats(page);
-        
+    fn check_range(&self, frame: usize, offset: usize, length: usize) -> GPUResult<()> {
+        if frame >= self.total_frames || offset + length > self.config.page_size {
+            return Err(GPUError::OutOfBounds);
+        }
         Ok(())
     }
 
-    fn get_page(&mut self, address: u64) -> GPUResult<&mut PhysicalPage> {
-        self.page_table.get_mut(&(address / self.config.page_size as u64))
-            .ok_or(GPUError::PageFault)
+    fn copy_out(&self, frame: usize, offset: usize, buffer: &mut [u8]) {
+        match self.memory.get(&frame) {
+            Some(bytes) => buffer.copy_from_slice(&bytes[offset..offset + buffer.len()]),
+            None => buffer.fill(0),
+        }
     }
 
-    fn update_access_stats(&mut self, page: &mut PhysicalPage) {
-        page.last_access = self.stats.total_bytes;
-        page.access_count += 1;
-        self.stats.bandwidth_usage += self.config.page_size as f32;
+    fn frame_mut(&mut self, frame: usize) -> &mut [u8] {
+        let page_size = self.config.page_size;
+        self.memory.entry(frame).or_insert_with(|| vec![0; page_size].into_boxed_slice())
     }
+}
 
-    fn generate_virtual_address(&self) -> u64 {
-        // Simple address generation - would need better scheme in real implementation
-        (self.page_table.len() as u64) * self.config.page_size as u64
+impl Default for VRAMController {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -140,4 +226,4 @@ impl Default for PageFlags {
             resident: true,
         }
     }
-} 
\ No newline at end of file
+}
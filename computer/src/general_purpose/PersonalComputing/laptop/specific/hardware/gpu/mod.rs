@@ -1,25 +1,35 @@
 // Export all modules in gpu
 
 pub mod compute;
+pub mod error;
 pub mod display;
 pub mod memory;
 pub mod scheduler;
 pub mod command;
-pub mod resources;
 pub mod sync;
 
-use super::bus::Bus;
-use super::error::{GPUError, GPUResult};
-use self::compute::{ShaderCore, RayCore, TensorCore};
+use self::error::GPUResult;
+use self::compute::{ComputeCore, ShaderCore, RayCore, TensorCore};
 use self::memory::{GPUMemory, VRAMController};
 use self::scheduler::Dispatcher;
-use self::display::DisplayController;
+use self::display::Display;
 use self::command::CommandProcessor;
-use self::resources::ResourceManager;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// The panel GPU::new() drives when no machine description names one
+const BOOT_WIDTH: u32 = 1920;
+const BOOT_HEIGHT: u32 = 1080;
+const BOOT_REFRESH_HZ: u32 = 60;
+
+// Board power at idle and with every core busy
+const IDLE_WATTS: f32 = 5.0;
+const PEAK_WATTS: f32 = 60.0;
 
 pub struct GPU {
     // Core components
     shader_cores: Vec<ShaderCore>,
+    #[allow(dead_code)]
     ray_cores: Vec<RayCore>,
     tensor_cores: Vec<TensorCore>,
     
@@ -28,20 +38,17 @@ pub struct GPU {
     vram: VRAMController,
     
     // Display and scheduling
-    display: DisplayController,
+    display: Rc<RefCell<Display>>, // Shared with the bus, which maps its registers
     dispatcher: Dispatcher,
     
-    // System interface
-    bus: *mut Bus,
-    
     // State and metrics
     power_state: PowerState,
     temperature: f32,
     utilization: f32,
     stats: GPUStats,
     
+    #[allow(dead_code)]
     command_processor: CommandProcessor,
-    resource_manager: ResourceManager,
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -52,33 +59,43 @@ pub enum PowerState {
     Sleep,
 }
 
-struct GPUStats {
-    frames_rendered: u64,
-    shader_invocations: u64,
-    ray_traces: u64,
-    tensor_ops: u64,
-    memory_bandwidth: f32,
-    power_consumption: f32,
+#[derive(Debug, Clone, Default)]
+pub struct GPUStats {
+    pub frames_rendered: u64,
+    pub shader_invocations: u64,
+    pub ray_traces: u64,
+    pub tensor_ops: u64,
+    pub memory_bandwidth: f32,
+    pub power_consumption: f32,
 }
 
 impl GPU {
-    pub fn new(bus: *mut Bus) -> Self {
-        Self {
-            shader_cores: (0..32).map(|id| ShaderCore::new(id)).collect(),
-            ray_cores: (0..4).map(|id| RayCore::new(id)).collect(),
-            tensor_cores: (0..8).map(|id| TensorCore::new(id)).collect(),
+    pub fn new() -> GPUResult<Self> {
+        Self::with_shape(32, 4, 8, BOOT_WIDTH, BOOT_HEIGHT, BOOT_REFRESH_HZ)
+    }
+
+    // Core counts per unit type and the built-in panel, as a machine
+    // description gives them. The GPU holds no bus connection; Hardware
+    // maps its display registers. Fails if the panel's framebuffer doesn't
+    // fit in VRAM.
+    pub fn with_shape(shader_cores: u32, ray_cores: u32, tensor_cores: u32, width: u32, height: u32, refresh_hz: u32) -> GPUResult<Self> {
+        let mut vram = VRAMController::new();
+        let mut display = Display::new(width, height, &mut vram)?;
+        display.set_refresh_rate(refresh_hz);
+        Ok(Self {
+            shader_cores: (0..shader_cores).map(ShaderCore::new).collect(),
+            ray_cores: (0..ray_cores).map(RayCore::new).collect(),
+            tensor_cores: (0..tensor_cores as usize).map(TensorCore::new).collect(),
             memory: GPUMemory::new(),
-            vram: VRAMController::new(),
-            display: DisplayController::new(),
+            vram,
+            display: Rc::new(RefCell::new(display)),
             dispatcher: Dispatcher::new(),
-            bus,
             power_state: PowerState::Active,
             temperature: 45.0,
             utilization: 0.0,
             stats: GPUStats::default(),
             command_processor: CommandProcessor::new(),
-            resource_manager: ResourceManager::new(),
-        }
+        })
     }
 
     pub fn tick(&mut self) {
@@ -86,10 +103,10 @@ impl GPU {
         self.update_cores();
         self.memory.tick();
         self.vram.tick();
-        self.display.tick();
         self.dispatcher.tick();
         
         // Update system state
+        self.update_utilization();
         self.update_temperature();
         self.update_power_state();
         self.update_stats();
@@ -99,13 +116,65 @@ impl GPU {
         for core in &mut self.shader_cores {
             core.tick();
         }
-        for core in &mut self.ray_cores {
-            core.tick();
-        }
+        // Ray cores trace a batch to completion in trace_rays, so they
+        // have nothing to step here
         for core in &mut self.tensor_cores {
             core.tick();
         }
     }
 
+    // Mean busy fraction over the shader and tensor cores
+    fn update_utilization(&mut self) {
+        let cores = self.shader_cores.len() + self.tensor_cores.len();
+        if cores == 0 {
+            self.utilization = 0.0;
+            return;
+        }
+        let busy: f32 = self.shader_cores.iter().map(|core| core.get_utilization()).sum::<f32>()
+            + self.tensor_cores.iter().map(|core| core.get_utilization()).sum::<f32>();
+        self.utilization = busy / cores as f32;
+    }
+
+    fn update_temperature(&mut self) {
+        // Same first-order model as the CPU, driven by core utilization
+        let ambient_temp = 25.0;
+        let max_temp = 85.0;
+        self.temperature = self.temperature * 0.99 +
+            (ambient_temp + (max_temp - ambient_temp) * self.utilization) * 0.01;
+    }
+
+    fn update_power_state(&mut self) {
+        let all_idle = self.shader_cores.iter().all(|core| core.is_idle())
+            && self.tensor_cores.iter().all(|core| core.is_idle());
+        self.power_state = if all_idle { PowerState::Idle } else { PowerState::Active };
+    }
+
+    fn update_stats(&mut self) {
+        let watts = match self.power_state {
+            PowerState::Active => IDLE_WATTS + (PEAK_WATTS - IDLE_WATTS) * self.utilization,
+            PowerState::Idle => IDLE_WATTS,
+            PowerState::LowPower => IDLE_WATTS / 2.0,
+            PowerState::Sleep => 0.0,
+        };
+        self.stats.power_consumption = watts;
+    }
+
+    pub fn get_temperature(&self) -> f32 {
+        self.temperature
+    }
+
+    pub fn get_utilization(&self) -> f32 {
+        self.utilization
+    }
+
+    // The display engine, for the bus to map and the window to blit from
+    pub fn display(&self) -> Rc<RefCell<Display>> {
+        Rc::clone(&self.display)
+    }
+
     // State management methods...
+
+    pub fn get_stats(&self) -> &GPUStats {
+        &self.stats
+    }
 }
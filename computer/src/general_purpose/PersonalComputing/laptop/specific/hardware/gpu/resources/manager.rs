@@ -1,28 +0,0 @@
-use std::collections::HashMap;
-use super::super::error::GPUResult;
-
-pub struct ResourceManager {
-    buffers: HashMap<BufferHandle, Buffer>,
-    textures: HashMap<TextureHandle, Texture>,
-    pipelines: HashMap<PipelineHandle, Pipeline>,
-    descriptors: HashMap<DescriptorHandle, Descriptor>,
-}
-
-impl ResourceManager {
-    pub fn new() -> Self {
-        Self {
-            buffers: HashMap::new(),
-            textures: HashMap::new(),
-            pipelines: HashMap::new(),
-            descriptors: HashMap::new(),
-        }
-    }
-
-    pub fn create_buffer(&mut self, desc: BufferDesc) -> GPUResult<BufferHandle> {
-        // Implementation
-    }
-
-    pub fn create_texture(&mut self, desc: TextureDesc) -> GPUResult<TextureHandle> {
-        // Implementation
-    }
-} 
\ No newline at end of file
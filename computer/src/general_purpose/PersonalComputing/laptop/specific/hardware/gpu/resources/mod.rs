@@ -1,2 +0,0 @@
-pub mod manager;
-pub use manager::ResourceManager; 
\ No newline at end of file
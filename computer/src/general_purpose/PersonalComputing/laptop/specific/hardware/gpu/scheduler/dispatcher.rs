@@ -1,11 +1,31 @@
 use super::super::error::{GPUError, GPUResult};
-use super::workload::{Workload, WorkloadType, Priority};
-use super::super::compute::{ShaderCore, RayCore, TensorCore};
-use std::collections::VecDeque;
+use super::workload::{Workload, WorkloadState, WorkloadType};
+use std::collections::{BTreeMap, VecDeque};
 
+// Context zero belongs to the kernel and always exists
+pub const KERNEL_CONTEXT: u32 = 0;
+
+// Cycles to save or restore one in-flight workload: shader registers and
+// shared memory, a ray core's traversal stacks, a tensor core's tiles
+const SHADER_STATE_CYCLES: u64 = 200;
+const RAY_STATE_CYCLES: u64 = 400;
+const TENSOR_STATE_CYCLES: u64 = 300;
+
+// A context kept waiting this many time slices gains a priority level
+const BOOST_SLICES: u64 = 4;
+
+// The engines belong to one context at a time. Within it, every queue hands
+// its free cores to ready workloads in priority order; a workload spreads
+// over all the cores it gets and retires a work item per core per cycle.
+// Contexts take turns by time slice, and a more urgent one preempts the
+// running context mid-draw, paying to save and restore in-flight state.
 pub struct Dispatcher {
     queues: WorkloadQueues,
     scheduler: WorkloadScheduler,
+    contexts: BTreeMap<u32, ContextState>,
+    next_context: u32,
+    cores: [usize; 3], // Shader, ray tracing, tensor
+    completed: Vec<u64>,
     stats: DispatchStats,
 }
 
@@ -21,151 +41,691 @@ struct WorkloadScheduler {
     time_slice: u32,
     preemption_enabled: bool,
     priority_boost: bool,
+    switch_cycles: u64,
+    active: Option<u32>,
+    slice_start: u64,
+    slice_rank: u64, // Rank the active context was granted, boost included
+    switch: Option<ContextSwitch>,
+    draining: bool, // Slice over, waiting for in-flight work to retire
+}
+
+struct ContextSwitch {
+    to: u32,
+    rank: u64,
+    remaining: u64,
 }
 
-struct DispatchStats {
-    workloads_submitted: u64,
-    workloads_completed: u64,
-    queue_full_events: u64,
-    preemptions: u64,
-    avg_latency: f32,
+struct ContextState {
+    pid: u32,
+    name: String,
+    last_run: u64,
+    stats: ContextStats,
+}
+
+#[derive(Debug, Clone)]
+pub struct DispatcherConfig {
+    pub shader_cores: usize,
+    pub ray_cores: usize,
+    pub tensor_cores: usize,
+    pub time_slice: u32,
+    pub preemption_enabled: bool, // Off: contexts only switch between workloads
+    pub priority_boost: bool,     // Age waiting contexts so low priority can't starve
+    pub switch_cycles: u64,       // Fixed cost of a switch, on top of saved state
+    pub max_queue_size: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DispatchStats {
+    pub workloads_submitted: u64,
+    pub workloads_completed: u64,
+    pub queue_full_events: u64,
+    pub preemptions: u64, // Switches that interrupted in-flight work
+    pub context_switches: u64,
+    pub switch_cycles: u64,
+    pub busy_cycles: u64,
+    pub idle_cycles: u64,
+    pub avg_latency: f32, // Submission to completion, in cycles
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ContextStats {
+    pub busy_cycles: u64, // Cycles with at least one core working for it
+    pub core_cycles: u64,
+    pub switch_cycles: u64, // Cycles spent switching to it
+    pub time_slices: u64,
+    pub preemptions: u64,
+    pub workloads_completed: u64,
+}
+
+// A context's counters at one instant; diff two to get utilization over the interval
+#[derive(Debug, Clone)]
+pub struct ContextUsage {
+    pub context: u32,
+    pub pid: u32,
+    pub name: String,
+    pub active: bool,
+    pub queued: usize,
+    pub stats: ContextStats,
 }
 
 impl Dispatcher {
     pub fn new() -> Self {
+        Self::with_config(DispatcherConfig::default())
+    }
+
+    pub fn with_config(config: DispatcherConfig) -> Self {
+        let mut contexts = BTreeMap::new();
+        contexts.insert(KERNEL_CONTEXT, ContextState::new(0, "kernel"));
         Self {
-            queues: WorkloadQueues::new(),
-            scheduler: WorkloadScheduler::new(),
+            queues: WorkloadQueues::new(config.max_queue_size),
+            scheduler: WorkloadScheduler::new(&config),
+            contexts,
+            next_context: KERNEL_CONTEXT + 1,
+            cores: [config.shader_cores, config.ray_cores, config.tensor_cores],
+            completed: Vec::new(),
             stats: DispatchStats::default(),
         }
     }
 
-    pub fn submit_workload(&mut self, workload: Workload) -> GPUResult<()> {
-        self.stats.workloads_submitted += 1;
+    // A GPU context for one process's submissions
+    pub fn create_context(&mut self, pid: u32, name: &str) -> u32 {
+        let context = self.next_context;
+        self.next_context += 1;
+        let mut state = ContextState::new(pid, name);
+        state.last_run = self.scheduler.current_time;
+        self.contexts.insert(context, state);
+        context
+    }
 
-        match workload.workload_type {
-            WorkloadType::Shader => self.queues.push_shader(workload)?,
-            WorkloadType::RayTracing => self.queues.push_ray(workload)?,
-            WorkloadType::Tensor => self.queues.push_tensor(workload)?,
+    // Drops a context along with whatever it still had queued
+    pub fn destroy_context(&mut self, context: u32) -> GPUResult<()> {
+        if context == KERNEL_CONTEXT || self.contexts.remove(&context).is_none() {
+            return Err(GPUError::InvalidContext(context));
+        }
+        for workload_type in [WorkloadType::Shader, WorkloadType::RayTracing, WorkloadType::Tensor] {
+            self.queues.queue_mut(workload_type).retain(|workload| workload.context != context);
+        }
+        if self.scheduler.active == Some(context) {
+            self.scheduler.active = None;
         }
+        if self.scheduler.switch.as_ref().is_some_and(|switch| switch.to == context) {
+            self.scheduler.switch = None;
+        }
+        Ok(())
+    }
+
+    // Latency is measured on the dispatcher's clock, so it stamps submit_time
+    pub fn submit_workload(&mut self, mut workload: Workload) -> GPUResult<()> {
+        if !self.contexts.contains_key(&workload.context) {
+            return Err(GPUError::InvalidContext(workload.context));
+        }
+        workload.submit_time = self.scheduler.current_time;
+        if let Err(error) = self.queues.push(workload) {
+            self.stats.queue_full_events += 1;
+            return Err(error);
+        }
+        self.stats.workloads_submitted += 1;
 
         Ok(())
     }
 
+    // Waiting workloads per queue: shader, ray tracing, tensor
+    pub fn queue_lengths(&self) -> (usize, usize, usize) {
+        (self.queues.shader_queue.len(), self.queues.ray_queue.len(), self.queues.tensor_queue.len())
+    }
+
     pub fn tick(&mut self) {
         self.scheduler.current_time += 1;
 
-        // Check for preemption
-        if self.scheduler.preemption_enabled {
-            self.check_preemption();
+        // Check for preemption and the end of the time slice
+        if self.scheduler.switch.is_none() {
+            if let Some((next, rank)) = self.select_context() {
+                self.begin_switch(next, rank);
+            }
         }
 
-        // Schedule workloads
-        self.schedule_workloads();
+        // Saving and restoring state holds the engines for the cycle
+        if self.scheduler.switch.is_some() {
+            self.advance_switch();
+        } else {
+            self.schedule_workloads();
+        }
+    }
 
-        // Update statistics
-        self.update_stats();
+    pub fn is_idle(&self) -> bool {
+        self.scheduler.switch.is_none() && self.queued().next().is_none()
     }
 
-    fn schedule_workloads(&mut self) {
-        // Schedule shader workloads
-        while let Some(workload) = self.queues.shader_queue.front() {
-            if !self.can_schedule(workload) {
-                break;
-            }
-            let workload = self.queues.shader_queue.pop_front().unwrap();
-            self.dispatch_shader_workload(workload);
-        }
+    pub fn active_context(&self) -> Option<u32> {
+        self.scheduler.active
+    }
 
-        // Schedule ray tracing workloads
-        while let Some(workload) = self.queues.ray_queue.front() {
-            if !self.can_schedule(workload) {
-                break;
-            }
-            let workload = self.queues.ray_queue.pop_front().unwrap();
-            self.dispatch_ray_workload(workload);
+    pub fn is_switching(&self) -> bool {
+        self.scheduler.switch.is_some()
+    }
+
+    pub fn current_time(&self) -> u64 {
+        self.scheduler.current_time
+    }
+
+    // Every queued workload, shader queue first, each in dispatch order
+    pub fn queued(&self) -> impl Iterator<Item = &Workload> {
+        self.queues.shader_queue.iter().chain(self.queues.ray_queue.iter()).chain(self.queues.tensor_queue.iter())
+    }
+
+    pub fn completed(&self) -> &[u64] {
+        &self.completed
+    }
+
+    pub fn context_stats(&self, context: u32) -> Option<&ContextStats> {
+        self.contexts.get(&context).map(|state| &state.stats)
+    }
+
+    pub fn get_stats(&self) -> &DispatchStats {
+        &self.stats
+    }
+
+    // Methods for visualization system
+    pub fn context_usage(&self) -> Vec<ContextUsage> {
+        self.contexts
+            .iter()
+            .map(|(&context, state)| ContextUsage {
+                context,
+                pid: state.pid,
+                name: state.name.clone(),
+                active: self.scheduler.active == Some(context),
+                queued: self.queued().filter(|workload| workload.context == context).count(),
+                stats: state.stats.clone(),
+            })
+            .collect()
+    }
+
+    // Helper methods
+    // The context that should own the engines next, if it isn't the current one
+    fn select_context(&mut self) -> Option<(u32, u64)> {
+        self.scheduler.draining = false;
+        let (candidate, base, boosted) = self.best_waiting_context()?;
+        let active = match self.scheduler.active {
+            Some(active) => active,
+            None => return Some((candidate, boosted)),
+        };
+        let active_base = match self.context_rank(active) {
+            Some((rank, _)) => rank,
+            None => return Some((candidate, boosted)),
+        };
+
+        // A more urgent context doesn't wait for the slice to end. Boost
+        // never preempts, and a boosted context keeps its rank for the slice.
+        if self.scheduler.preemption_enabled && base > active_base.max(self.scheduler.slice_rank) {
+            return Some((candidate, boosted));
+        }
+        // Equals take turns; an outranked context waits for its boost
+        let elapsed = self.scheduler.current_time - self.scheduler.slice_start;
+        if elapsed < self.scheduler.time_slice as u64 || boosted < active_base {
+            return None;
         }
+        if self.scheduler.preemption_enabled || !self.has_in_flight(active) {
+            return Some((candidate, boosted));
+        }
+        self.scheduler.draining = true;
+        None
+    }
+
+    // Best priority among a context's ready workloads, alone and with its aging boost
+    fn context_rank(&self, context: u32) -> Option<(u64, u64)> {
+        let best = self
+            .queued()
+            .filter(|workload| workload.context == context && workload.is_ready(&self.completed))
+            .map(|workload| workload.priority as u64)
+            .max()?;
+        let state = self.contexts.get(&context)?;
+        let boost = if self.scheduler.priority_boost && self.scheduler.active != Some(context) {
+            let waited = self.scheduler.current_time - state.last_run;
+            waited / (self.scheduler.time_slice as u64 * BOOST_SLICES).max(1)
+        } else {
+            0
+        };
+        Some((best, best + boost))
+    }
 
-        // Schedule tensor workloads
-        while let Some(workload) = self.queues.tensor_queue.front() {
-            if !self.can_schedule(workload) {
-                break;
+    // Highest ranked context other than the active one; ties go to the longest waiting
+    fn best_waiting_context(&self) -> Option<(u32, u64, u64)> {
+        let mut best: Option<(u32, u64, u64, u64)> = None;
+        for (&context, state) in &self.contexts {
+            if self.scheduler.active == Some(context) {
+                continue;
+            }
+            if let Some((base, boosted)) = self.context_rank(context) {
+                let better = match best {
+                    Some((_, _, best_rank, best_last_run)) => boosted > best_rank || (boosted == best_rank && state.last_run < best_last_run),
+                    None => true,
+                };
+                if better {
+                    best = Some((context, base, boosted, state.last_run));
+                }
             }
-            let workload = self.queues.tensor_queue.pop_front().unwrap();
-            self.dispatch_tensor_workload(workload);
         }
+        best.map(|(context, base, boosted, _)| (context, base, boosted))
     }
 
-    fn can_schedule(&self, workload: &Workload) -> bool {
-        // Check resource availability
-        match workload.workload_type {
-            WorkloadType::Shader => self.get_available_shader_cores() > 0,
-            WorkloadType::RayTracing => self.get_available_ray_cores() > 0,
-            WorkloadType::Tensor => self.get_available_tensor_cores() > 0,
-        }
+    fn has_in_flight(&self, context: u32) -> bool {
+        self.queued().any(|workload| workload.context == context && workload.state == WorkloadState::Running)
     }
 
-    fn check_preemption(&mut self) {
-        // Check for higher priority workloads
-        if let Some(high_priority) = self.get_highest_priority_workload() {
-            if self.should_preempt(&high_priority) {
-                self.preempt_workload(&high_priority);
+    // Saves the outgoing context's in-flight work and restores what the
+    // incoming one was interrupted in; the engines stall for the sum
+    fn begin_switch(&mut self, to: u32, rank: u64) {
+        let now = self.scheduler.current_time;
+        let mut cost = self.scheduler.switch_cycles;
+
+        if let Some(from) = self.scheduler.active.take() {
+            let mut interrupted = false;
+            for workload_type in [WorkloadType::Shader, WorkloadType::RayTracing, WorkloadType::Tensor] {
+                for workload in self.queues.queue_mut(workload_type).iter_mut() {
+                    if workload.context == from && workload.state == WorkloadState::Running {
+                        workload.preempt();
+                        cost += state_cycles(workload_type);
+                        interrupted = true;
+                    }
+                }
+            }
+            if let Some(state) = self.contexts.get_mut(&from) {
+                state.last_run = now;
+                if interrupted {
+                    state.stats.preemptions += 1;
+                }
+            }
+            if interrupted {
                 self.stats.preemptions += 1;
             }
         }
+
+        cost += self
+            .queued()
+            .filter(|workload| workload.context == to && workload.state == WorkloadState::Preempted)
+            .map(|workload| state_cycles(workload.workload_type))
+            .sum::<u64>();
+
+        self.stats.context_switches += 1;
+        self.scheduler.draining = false;
+        if cost == 0 {
+            self.finish_switch(to, rank);
+        } else {
+            self.scheduler.switch = Some(ContextSwitch { to, rank, remaining: cost });
+        }
+    }
+
+    fn advance_switch(&mut self) {
+        let switch = match self.scheduler.switch.as_mut() {
+            Some(switch) => switch,
+            None => return,
+        };
+        switch.remaining -= 1;
+        let (to, rank, done) = (switch.to, switch.rank, switch.remaining == 0);
+
+        self.stats.switch_cycles += 1;
+        if let Some(state) = self.contexts.get_mut(&to) {
+            state.stats.switch_cycles += 1;
+        }
+        if done {
+            self.scheduler.switch = None;
+            self.finish_switch(to, rank);
+        }
     }
 
-    fn should_preempt(&self, workload: &Workload) -> bool {
-        // Implement preemption policy
-        workload.priority == Priority::High && 
-        self.scheduler.current_time - workload.submit_time > self.scheduler.time_slice
+    fn finish_switch(&mut self, to: u32, rank: u64) {
+        self.scheduler.active = Some(to);
+        self.scheduler.slice_rank = rank;
+        self.scheduler.slice_start = self.scheduler.current_time;
+        if let Some(state) = self.contexts.get_mut(&to) {
+            state.stats.time_slices += 1;
+        }
     }
 
-    // Helper methods for resource management
-    fn get_available_shader_cores(&self) -> usize {
-        // Implementation
-        0
+    // Hands each queue's free cores to the active context's ready workloads,
+    // highest priority first
+    fn schedule_workloads(&mut self) {
+        let now = self.scheduler.current_time;
+        let active = match self.scheduler.active {
+            Some(active) => active,
+            None => {
+                self.stats.idle_cycles += 1;
+                return;
+            }
+        };
+
+        let completed = &self.completed;
+        let draining = self.scheduler.draining;
+        let mut finished = Vec::new();
+        let mut used = 0;
+        for (index, workload_type) in [WorkloadType::Shader, WorkloadType::RayTracing, WorkloadType::Tensor].into_iter().enumerate() {
+            let mut free = self.cores[index];
+            let queue = self.queues.queue_mut(workload_type);
+            let mut position = 0;
+            while position < queue.len() && free > 0 {
+                let workload = &mut queue[position];
+                if !can_schedule(workload, active, completed, draining) {
+                    position += 1;
+                    continue;
+                }
+                let remaining = workload.work_items.max(1) - workload.stats.core_cycles;
+                let cores = free.min(remaining as usize);
+                free -= cores;
+                match workload.state {
+                    WorkloadState::Pending => workload.start(now),
+                    WorkloadState::Preempted => workload.state = WorkloadState::Running,
+                    _ => {}
+                }
+                workload.stats.core_cycles += cores as u64;
+
+                if workload.stats.core_cycles >= workload.work_items.max(1) {
+                    if let Some(mut workload) = queue.remove(position) {
+                        workload.complete(now);
+                        finished.push(workload);
+                    }
+                } else {
+                    position += 1;
+                }
+            }
+            used += self.cores[index] - free;
+        }
+
+        if used > 0 {
+            self.stats.busy_cycles += 1;
+        } else {
+            self.stats.idle_cycles += 1;
+        }
+        if let Some(state) = self.contexts.get_mut(&active) {
+            if used > 0 {
+                state.stats.busy_cycles += 1;
+                state.stats.core_cycles += used as u64;
+            }
+            state.stats.workloads_completed += finished.len() as u64;
+        }
+        for workload in finished {
+            self.update_stats(&workload, now);
+            self.completed.push(workload.id);
+        }
     }
 
-    fn get_available_ray_cores(&self) -> usize {
-        // Implementation
-        0
+    fn update_stats(&mut self, workload: &Workload, now: u64) {
+        let latency = (now - workload.submit_time) as f32;
+        let completed = self.stats.workloads_completed as f32;
+        self.stats.avg_latency = (self.stats.avg_latency * completed + latency) / (completed + 1.0);
+        self.stats.workloads_completed += 1;
     }
+}
 
-    fn get_available_tensor_cores(&self) -> usize {
-        // Implementation
-        0
+impl Default for Dispatcher {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl WorkloadQueues {
-    fn new() -> Self {
+    fn new(max_queue_size: usize) -> Self {
         Self {
             shader_queue: VecDeque::new(),
             ray_queue: VecDeque::new(),
             tensor_queue: VecDeque::new(),
-            max_queue_size: 1000,
+            max_queue_size,
         }
     }
 
-    fn push_shader(&mut self, workload: Workload) -> GPUResult<()> {
-        if self.shader_queue.len() >= self.max_queue_size {
+    // Behind everything of equal or higher priority, ahead of the rest
+    fn push(&mut self, workload: Workload) -> GPUResult<()> {
+        let max_queue_size = self.max_queue_size;
+        let queue = self.queue_mut(workload.workload_type);
+        if queue.len() >= max_queue_size {
             return Err(GPUError::QueueFull);
         }
-        self.shader_queue.push_back(workload);
+        let position = queue.iter().position(|queued| queued.priority < workload.priority).unwrap_or(queue.len());
+        queue.insert(position, workload);
         Ok(())
     }
 
-    // Similar implementations for ray and tensor queues...
+    fn queue_mut(&mut self, workload_type: WorkloadType) -> &mut VecDeque<Workload> {
+        match workload_type {
+            WorkloadType::Shader => &mut self.shader_queue,
+            WorkloadType::RayTracing => &mut self.ray_queue,
+            WorkloadType::Tensor => &mut self.tensor_queue,
+        }
+    }
 }
 
 impl WorkloadScheduler {
-    fn new() -> Self {
+    fn new(config: &DispatcherConfig) -> Self {
         Self {
             current_time: 0,
+            time_slice: config.time_slice,
+            preemption_enabled: config.preemption_enabled,
+            priority_boost: config.priority_boost,
+            switch_cycles: config.switch_cycles,
+            active: None,
+            slice_start: 0,
+            slice_rank: 0,
+            switch: None,
+            draining: false,
+        }
+    }
+}
+
+impl ContextState {
+    fn new(pid: u32, name: &str) -> Self {
+        Self {
+            pid,
+            name: name.to_string(),
+            last_run: 0,
+            stats: ContextStats::default(),
+        }
+    }
+}
+
+impl Default for DispatcherConfig {
+    fn default() -> Self {
+        Self {
+            shader_cores: 32,
+            ray_cores: 4,
+            tensor_cores: 8,
             time_slice: 1000,
             preemption_enabled: true,
             priority_boost: true,
+            switch_cycles: 100,
+            max_queue_size: 1000,
+        }
+    }
+}
+
+impl ContextStats {
+    // Share of `cycles` the context kept the engines busy
+    pub fn utilization(&self, cycles: u64) -> f32 {
+        if cycles == 0 {
+            return 0.0;
+        }
+        self.busy_cycles as f32 / cycles as f32
+    }
+}
+
+// A draining context only finishes what it already started
+fn can_schedule(workload: &Workload, active: u32, completed: &[u64], draining: bool) -> bool {
+    workload.context == active && workload.is_ready(completed) && (!draining || workload.state == WorkloadState::Running)
+}
+
+fn state_cycles(workload_type: WorkloadType) -> u64 {
+    match workload_type {
+        WorkloadType::Shader => SHADER_STATE_CYCLES,
+        WorkloadType::RayTracing => RAY_STATE_CYCLES,
+        WorkloadType::Tensor => TENSOR_STATE_CYCLES,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::workload::Priority;
+
+    const SLICE: u32 = 50;
+    const SWITCH: u64 = 2;
+
+    fn config() -> DispatcherConfig {
+        DispatcherConfig {
+            shader_cores: 4,
+            ray_cores: 1,
+            tensor_cores: 1,
+            time_slice: SLICE,
+            preemption_enabled: true,
+            priority_boost: false,
+            switch_cycles: SWITCH,
+            max_queue_size: 4,
+        }
+    }
+
+    fn workload(id: u64, context: u32, workload_type: WorkloadType, priority: Priority, work_items: u64) -> Workload {
+        let mut workload = Workload::new(id, workload_type, priority);
+        workload.set_context(context);
+        workload.work_items = work_items;
+        workload
+    }
+
+    fn run_until_idle(dispatcher: &mut Dispatcher, limit: u64) {
+        for _ in 0..limit {
+            if dispatcher.is_idle() {
+                return;
+            }
+            dispatcher.tick();
         }
+        panic!("dispatcher still busy after {} cycles", limit);
+    }
+
+    fn state_of(dispatcher: &Dispatcher, id: u64) -> Option<WorkloadState> {
+        dispatcher.queued().find(|workload| workload.id == id).map(|workload| workload.state)
+    }
+
+    #[test]
+    fn higher_priority_workloads_dispatch_first() {
+        let mut dispatcher = Dispatcher::with_config(config());
+        let context = dispatcher.create_context(10, "game");
+        dispatcher.submit_workload(workload(1, context, WorkloadType::Shader, Priority::Low, 4)).unwrap();
+        dispatcher.submit_workload(workload(2, context, WorkloadType::Shader, Priority::High, 4)).unwrap();
+        dispatcher.submit_workload(workload(3, context, WorkloadType::Shader, Priority::Normal, 4)).unwrap();
+
+        run_until_idle(&mut dispatcher, 100);
+        assert_eq!(dispatcher.completed(), &[2, 3, 1]);
+        // One switch onto the context, then one workload per cycle on four cores
+        assert_eq!(dispatcher.get_stats().switch_cycles, SWITCH);
+        assert_eq!(dispatcher.context_stats(context).unwrap().busy_cycles, 3);
+    }
+
+    #[test]
+    fn engines_run_in_parallel_but_dependencies_wait() {
+        let mut dispatcher = Dispatcher::with_config(config());
+        let context = dispatcher.create_context(10, "render");
+        let mut denoise = workload(3, context, WorkloadType::Tensor, Priority::RealTime, 1);
+        denoise.add_dependency(2);
+        dispatcher.submit_workload(denoise).unwrap();
+        dispatcher.submit_workload(workload(1, context, WorkloadType::Shader, Priority::Normal, 4)).unwrap();
+        dispatcher.submit_workload(workload(2, context, WorkloadType::RayTracing, Priority::Normal, 3)).unwrap();
+
+        run_until_idle(&mut dispatcher, 100);
+        assert_eq!(dispatcher.completed(), &[1, 2, 3]);
+        // Shader and ray work overlapped: 3 ray cycles, then the tensor op
+        assert_eq!(dispatcher.context_stats(context).unwrap().busy_cycles, 4);
+        assert_eq!(dispatcher.context_stats(context).unwrap().core_cycles, 4 + 3 + 1);
+    }
+
+    #[test]
+    fn urgent_contexts_preempt_mid_draw_and_pay_for_the_state() {
+        let mut dispatcher = Dispatcher::with_config(config());
+        let background = dispatcher.create_context(10, "miner");
+        let foreground = dispatcher.create_context(11, "compositor");
+        dispatcher.submit_workload(workload(1, background, WorkloadType::Shader, Priority::Low, 400)).unwrap();
+        for _ in 0..10 {
+            dispatcher.tick();
+        }
+        assert_eq!(state_of(&dispatcher, 1), Some(WorkloadState::Running));
+
+        dispatcher.submit_workload(workload(2, foreground, WorkloadType::Shader, Priority::High, 4)).unwrap();
+        dispatcher.tick();
+        assert!(dispatcher.is_switching());
+        assert_eq!(state_of(&dispatcher, 1), Some(WorkloadState::Preempted));
+
+        // Switch cost plus saving the interrupted draw
+        let mut switching = 1;
+        while dispatcher.active_context() != Some(foreground) {
+            dispatcher.tick();
+            switching += 1;
+        }
+        assert_eq!(switching, SWITCH + SHADER_STATE_CYCLES);
+        dispatcher.tick();
+        assert_eq!(dispatcher.completed(), &[2]);
+
+        // Going back restores the draw where it stopped
+        run_until_idle(&mut dispatcher, 1000);
+        assert_eq!(dispatcher.completed(), &[2, 1]);
+        assert_eq!(dispatcher.get_stats().preemptions, 1);
+        assert_eq!(dispatcher.context_stats(background).unwrap().preemptions, 1);
+        // Switching back in restored the draw's state as well
+        assert_eq!(dispatcher.context_stats(background).unwrap().switch_cycles, SWITCH + SWITCH + SHADER_STATE_CYCLES);
+        assert_eq!(dispatcher.context_stats(background).unwrap().core_cycles, 400);
+    }
+
+    #[test]
+    fn equal_contexts_take_turns_by_time_slice() {
+        let mut dispatcher = Dispatcher::with_config(DispatcherConfig { preemption_enabled: false, ..config() });
+        let a = dispatcher.create_context(10, "a");
+        let b = dispatcher.create_context(11, "b");
+        for id in 0..4 {
+            dispatcher.submit_workload(workload(id, a, WorkloadType::Shader, Priority::Normal, 4 * SLICE as u64)).unwrap();
+            dispatcher.submit_workload(workload(10 + id, b, WorkloadType::RayTracing, Priority::Normal, SLICE as u64)).unwrap();
+        }
+
+        run_until_idle(&mut dispatcher, 10_000);
+        let (a_stats, b_stats) = (dispatcher.context_stats(a).unwrap(), dispatcher.context_stats(b).unwrap());
+        assert!(a_stats.time_slices >= 3 && b_stats.time_slices >= 3, "{} and {}", a_stats.time_slices, b_stats.time_slices);
+        // Without preemption a slice ends only once in-flight work drains
+        assert_eq!(dispatcher.get_stats().preemptions, 0);
+        assert_eq!(dispatcher.get_stats().switch_cycles, dispatcher.get_stats().context_switches * SWITCH);
+
+        let total = dispatcher.current_time();
+        let busy = a_stats.utilization(total) + b_stats.utilization(total);
+        assert!(busy > 0.9 && busy <= 1.0, "{}", busy);
+    }
+
+    #[test]
+    fn priority_boost_keeps_low_priority_contexts_from_starving() {
+        for (boost, starved) in [(false, true), (true, false)] {
+            let mut dispatcher = Dispatcher::with_config(DispatcherConfig { priority_boost: boost, ..config() });
+            let busy = dispatcher.create_context(10, "busy");
+            let idle = dispatcher.create_context(11, "idle");
+            dispatcher.submit_workload(workload(1, busy, WorkloadType::Tensor, Priority::High, 20 * SLICE as u64)).unwrap();
+            dispatcher.submit_workload(workload(2, idle, WorkloadType::Tensor, Priority::Low, 1)).unwrap();
+
+            run_until_idle(&mut dispatcher, 100_000);
+            assert_eq!(dispatcher.completed()[0] == 1, starved, "boost {}", boost);
+        }
+    }
+
+    #[test]
+    fn contexts_are_checked_and_destroyed_with_their_work() {
+        let mut dispatcher = Dispatcher::with_config(config());
+        assert_eq!(dispatcher.submit_workload(workload(1, 7, WorkloadType::Shader, Priority::Normal, 1)), Err(GPUError::InvalidContext(7)));
+        assert_eq!(dispatcher.destroy_context(KERNEL_CONTEXT), Err(GPUError::InvalidContext(KERNEL_CONTEXT)));
+
+        let context = dispatcher.create_context(10, "app");
+        for id in 0..4 {
+            dispatcher.submit_workload(workload(id, context, WorkloadType::Shader, Priority::Normal, 100)).unwrap();
+        }
+        assert_eq!(dispatcher.submit_workload(workload(4, context, WorkloadType::Shader, Priority::Normal, 100)), Err(GPUError::QueueFull));
+        assert_eq!(dispatcher.get_stats().queue_full_events, 1);
+
+        dispatcher.tick();
+        let usage = dispatcher.context_usage();
+        assert_eq!(usage.len(), 2);
+        assert_eq!((usage[1].pid, usage[1].queued), (10, 4));
+
+        dispatcher.destroy_context(context).unwrap();
+        assert_eq!(dispatcher.queue_lengths(), (0, 0, 0));
+        assert!(dispatcher.is_idle());
+        assert_eq!(dispatcher.active_context(), None);
     }
 }
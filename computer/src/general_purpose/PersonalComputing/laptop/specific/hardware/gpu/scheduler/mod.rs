@@ -2,16 +2,5 @@
 pub mod dispatcher;
 pub mod workload;
 
-pub struct Scheduler {
-    dispatcher: dispatcher::Dispatcher,
-    workload: workload::Workload,
-}
-
-impl Scheduler {
-    pub fn new() -> Self {
-        Self {
-            dispatcher: dispatcher::Dispatcher::new(),
-            workload: workload::Workload::new(),
-        }
-    }
-}   
\ No newline at end of file
+pub use self::dispatcher::{ContextStats, ContextUsage, DispatchStats, Dispatcher, DispatcherConfig, KERNEL_CONTEXT};
+pub use self::workload::{Priority, Workload, WorkloadState, WorkloadType};
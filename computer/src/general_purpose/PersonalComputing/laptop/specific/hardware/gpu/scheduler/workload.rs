@@ -1,25 +1,25 @@
-use super::super::error::GPUResult;
-
 #[derive(Clone)]
 pub struct Workload {
     pub id: u64,
+    pub context: u32, // GPU context of the submitting process
     pub workload_type: WorkloadType,
     pub priority: Priority,
     pub submit_time: u64,
     pub deadline: Option<u64>,
+    pub work_items: u64, // Vertices for a draw, groups for a dispatch
     pub dependencies: Vec<u64>,
     pub state: WorkloadState,
     pub stats: WorkloadStats,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum WorkloadType {
     Shader,
     RayTracing,
     Tensor,
 }
 
-#[derive(Clone, Copy, PartialEq, Ord, PartialOrd, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Ord, PartialOrd, Eq)]
 pub enum Priority {
     Low,
     Normal,
@@ -27,7 +27,7 @@ pub enum Priority {
     RealTime,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum WorkloadState {
     Pending,
     Running,
@@ -36,7 +36,7 @@ pub enum WorkloadState {
     Failed,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct WorkloadStats {
     pub start_time: Option<u64>,
     pub end_time: Option<u64>,
@@ -49,10 +49,12 @@ impl Workload {
     pub fn new(id: u64, workload_type: WorkloadType, priority: Priority) -> Self {
         Self {
             id,
+            context: 0,
             workload_type,
             priority,
             submit_time: 0,
             deadline: None,
+            work_items: 0,
             dependencies: Vec::new(),
             state: WorkloadState::Pending,
             stats: WorkloadStats::default(),
@@ -63,6 +65,10 @@ impl Workload {
         self.deadline = Some(deadline);
     }
 
+    pub fn set_context(&mut self, context: u32) {
+        self.context = context;
+    }
+
     pub fn add_dependency(&mut self, dependency_id: u64) {
         self.dependencies.push(dependency_id);
     }
@@ -1,27 +1,121 @@
-use std::sync::{atomic::{AtomicU64, AtomicBool, Ordering}, Mutex};
-use std::thread::Thread;
+use std::sync::atomic::{AtomicU64, Ordering};
+use super::super::error::{GPUError, GPUResult};
+use crate::simulation::time::SimTime;
 
+// A timeline semaphore: a counter that only moves forward. Work signals the
+// value it completes; a waiter asks for "at least n", so one fence orders a
+// whole stream of submissions.
 pub struct GPUFence {
     value: AtomicU64,
-    waiting_threads: Mutex<Vec<Thread>>,
-    signaled: AtomicBool,
 }
 
 impl GPUFence {
     pub fn new() -> Self {
+        Self::with_value(0)
+    }
+
+    pub fn with_value(value: u64) -> Self {
         Self {
-            value: AtomicU64::new(0),
-            waiting_threads: Mutex::new(Vec::new()),
-            signaled: AtomicBool::new(false),
+            value: AtomicU64::new(value),
         }
     }
 
-    pub fn signal(&self) {
-        self.signaled.store(true, Ordering::Release);
-        // Wake waiting threads
+    pub fn value(&self) -> u64 {
+        self.value.load(Ordering::Acquire)
+    }
+
+    pub fn is_reached(&self, value: u64) -> bool {
+        self.value() >= value
     }
 
-    pub fn wait(&self) {
-        // Implementation
+    // A lower value than the current one is ignored
+    pub fn signal(&self, value: u64) {
+        self.value.fetch_max(value, Ordering::AcqRel);
+    }
+
+    // A wait with a deadline in simulated time. `step` runs the machine on
+    // and returns the time it got to; nothing here reads the host's clock,
+    // so whether a wait times out depends only on the simulation.
+    pub fn wait_until(&self, value: u64, deadline: SimTime, mut step: impl FnMut() -> SimTime) -> bool {
+        while !self.is_reached(value) {
+            if step() >= deadline {
+                return self.is_reached(value);
+            }
+        }
+        true
+    }
+
+    // A wait from the simulated CPU: instead of blocking, it advances the
+    // simulation one step at a time until the GPU gets there. Returns the
+    // number of steps the CPU spent waiting.
+    pub fn wait_with(&self, value: u64, max_steps: u64, mut step: impl FnMut()) -> GPUResult<u64> {
+        let mut steps = 0;
+        while !self.is_reached(value) {
+            if steps == max_steps {
+                return Err(GPUError::Timeout);
+            }
+            step();
+            steps += 1;
+        }
+        Ok(steps)
+    }
+}
+
+impl Default for GPUFence {
+    fn default() -> Self {
+        Self::new()
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_time_out_in_simulated_time() {
+        let fence = GPUFence::new();
+        let mut now = SimTime::ZERO;
+        let mut steps = 0;
+        let reached = fence.wait_until(1, SimTime::from_ns(50), || {
+            now += SimTime::from_ns(10);
+            steps += 1;
+            now
+        });
+        assert!(!reached);
+        assert_eq!(steps, 5);
+    }
+
+    #[test]
+    fn already_reached_values_take_no_steps() {
+        let fence = GPUFence::with_value(3);
+        assert!(fence.wait_until(3, SimTime::ZERO, || unreachable!("nothing to wait for")));
+    }
+
+    #[test]
+    fn signal_during_a_step_ends_the_wait() {
+        let fence = GPUFence::new();
+        let mut now = SimTime::ZERO;
+        let reached = fence.wait_until(2, SimTime::from_us(1), || {
+            now += SimTime::from_ns(10);
+            if now == SimTime::from_ns(30) {
+                fence.signal(2);
+            }
+            now
+        });
+        assert!(reached);
+        assert_eq!(now, SimTime::from_ns(30));
+
+        // Values only move forward
+        fence.signal(1);
+        assert_eq!(fence.value(), 2);
+    }
+
+    #[test]
+    fn a_signal_on_the_deadline_step_still_counts() {
+        let fence = GPUFence::new();
+        assert!(fence.wait_until(1, SimTime::from_ns(10), || {
+            fence.signal(1);
+            SimTime::from_ns(10)
+        }));
+    }
+}
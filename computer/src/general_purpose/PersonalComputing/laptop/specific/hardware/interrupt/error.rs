@@ -0,0 +1,11 @@
+#[derive(Debug, Clone, PartialEq)]
+pub enum InterruptError {
+    // Configuration errors
+    InvalidSource(u32),
+    InvalidTarget(usize),
+
+    // Claim/complete protocol errors
+    NotClaimed(u32), // Completed a source the target never claimed
+}
+
+pub type InterruptResult<T> = Result<T, InterruptError>;
@@ -1,53 +1,522 @@
-use super::error::{InterruptError, InterruptResult};
+use self::error::{InterruptError, InterruptResult};
+use crate::hardware::bus::mmio::{AccessSize, MmioDevice};
+use crate::hardware::bus::BusError;
 use std::collections::VecDeque;
 
+pub mod error;
+
+// The keyboard controller keeps its PC wiring
+pub const IRQ_KEYBOARD: u32 = 1;
+
+const EVENT_LOG_LIMIT: usize = 256;
+
+// Register layout of the RISC-V PLIC, all 32-bit registers
+pub const PLIC_REGION_SIZE: u64 = 0x40_0000;
+pub const PLIC_PRIORITY: u64 = 0x00_0000;  // 4 bytes per source
+pub const PLIC_PENDING: u64 = 0x00_1000;   // One bit per source
+pub const PLIC_ENABLE: u64 = 0x00_2000;    // A bit array per target
+pub const PLIC_ENABLE_STRIDE: u64 = 0x80;
+pub const PLIC_CONTEXT: u64 = 0x20_0000;   // Threshold, then claim/complete
+pub const PLIC_CONTEXT_STRIDE: u64 = 0x1000;
+
+// A platform interrupt controller in the mould of the RISC-V PLIC, with the
+// per-core delivery of an APIC. Each source has a priority and a gateway
+// that turns its line into a pending bit; each target (one per core) has
+// enable bits, a threshold, and a stack of claimed sources, so a
+// higher-priority interrupt can preempt a handler that is still running.
+// Source 0 is reserved and never interrupts.
 pub struct InterruptController {
-    pending: VecDeque<Interrupt>,
-    handlers: Vec<InterruptHandler>,
-    mask: InterruptMask,
+    sources: Vec<Source>,
+    targets: Vec<Target>,
     config: InterruptConfig,
     stats: InterruptStats,
+    events: VecDeque<InterruptEvent>,
+    cycle: u64,
 }
 
-struct Interrupt {
-    id: InterruptID,
-    priority: u8,
-    data: Vec<u8>,
-    timestamp: u64,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InterruptID(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Level, // Pending for as long as the device holds its line
+    Edge,  // Pending once per pulse
 }
 
-type InterruptHandler = Box<dyn Fn(&Interrupt) -> InterruptResult<()>>;
+struct Source {
+    priority: u8,     // 0 never interrupts
+    trigger: Trigger,
+    line: bool,       // Level of the device's interrupt line
+    pending: bool,
+    active: bool,     // Claimed and not yet completed: the gateway is closed
+    raised_at: u64,   // When it last became pending, for latency
+}
+
+struct Target {
+    enabled: Vec<bool>,          // By source
+    threshold: u8,               // Only priorities above this interrupt the core
+    in_service: Vec<(InterruptID, u8)>, // Claimed sources, innermost last
+}
+
+#[derive(Debug, Clone)]
+pub struct InterruptConfig {
+    pub num_sources: u32,
+    pub num_targets: usize,  // One per core
+    pub max_priority: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptEventKind {
+    Raised,
+    Claimed,
+    Preempted, // Claimed on top of a handler that was still running
+    Completed,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptEvent {
+    pub kind: InterruptEventKind,
+    pub source: InterruptID,
+    pub target: Option<usize>,
+    pub cycle: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InterruptStats {
+    pub interrupts_raised: u64,
+    pub interrupts_claimed: u64,
+    pub interrupts_completed: u64,
+    pub preemptions: u64,
+    pub coalesced: u64,      // Edges that arrived while the source was already pending
+    pub max_nesting: usize,
+    pub total_latency: u64,  // Raise to claim, in cycles
+    pub max_latency: u64,
+}
 
 impl InterruptController {
     pub fn new(config: InterruptConfig) -> Self {
+        let sources = (0..config.num_sources)
+            .map(|_| Source {
+                priority: 0,
+                trigger: Trigger::Level,
+                line: false,
+                pending: false,
+                active: false,
+                raised_at: 0,
+            })
+            .collect();
+        let targets = (0..config.num_targets)
+            .map(|_| Target {
+                enabled: vec![false; config.num_sources as usize],
+                threshold: 0,
+                in_service: Vec::new(),
+            })
+            .collect();
+
         Self {
-            pending: VecDeque::new(),
-            handlers: Vec::new(),
-            mask: InterruptMask::default(),
+            sources,
+            targets,
             config,
             stats: InterruptStats::default(),
+            events: VecDeque::new(),
+            cycle: 0,
         }
     }
 
-    pub fn raise_interrupt(&mut self, interrupt: Interrupt) -> InterruptResult<()> {
-        if !self.mask.is_enabled(interrupt.id) {
-            return Err(InterruptError::Masked);
+    pub fn tick(&mut self) {
+        self.cycle += 1;
+    }
+
+    // Source configuration
+    pub fn set_priority(&mut self, id: InterruptID, priority: u8) -> InterruptResult<()> {
+        let max_priority = self.config.max_priority;
+        self.source_mut(id)?.priority = priority.min(max_priority);
+        Ok(())
+    }
+
+    pub fn set_trigger(&mut self, id: InterruptID, trigger: Trigger) -> InterruptResult<()> {
+        self.source_mut(id)?.trigger = trigger;
+        Ok(())
+    }
+
+    // Target configuration
+    pub fn enable(&mut self, target: usize, id: InterruptID) -> InterruptResult<()> {
+        self.set_enabled(target, id, true)
+    }
+
+    pub fn disable(&mut self, target: usize, id: InterruptID) -> InterruptResult<()> {
+        self.set_enabled(target, id, false)
+    }
+
+    pub fn set_threshold(&mut self, target: usize, threshold: u8) -> InterruptResult<()> {
+        self.target_mut(target)?.threshold = threshold;
+        Ok(())
+    }
+
+    // Device side. A pulse on an edge source, or asserting a level source,
+    // which stays pending until the device deasserts it.
+    pub fn raise_interrupt(&mut self, id: InterruptID) -> InterruptResult<()> {
+        let cycle = self.cycle;
+        let source = self.source_mut(id)?;
+        source.line = true;
+        // A level source that is pending or in its handler can't become
+        // pending again; raising it again just folds into that one
+        let newly_pending = !source.pending && (source.trigger == Trigger::Edge || !source.active);
+        if newly_pending {
+            source.pending = true;
+            source.raised_at = cycle;
+        }
+        if source.trigger == Trigger::Edge {
+            source.line = false;
         }
 
-        self.pending.push_back(interrupt);
-        self.stats.interrupts_raised += 1;
+        if !newly_pending {
+            self.stats.coalesced += 1;
+        } else {
+            self.stats.interrupts_raised += 1;
+            self.record(InterruptEventKind::Raised, id, None);
+        }
+        Ok(())
+    }
+
+    pub fn set_level(&mut self, id: InterruptID, asserted: bool) -> InterruptResult<()> {
+        if asserted {
+            let source = self.source_mut(id)?;
+            if source.line && source.trigger == Trigger::Level {
+                return Ok(());
+            }
+            return self.raise_interrupt(id);
+        }
+        let source = self.source_mut(id)?;
+        source.line = false;
+        if source.trigger == Trigger::Level {
+            source.pending = false;
+        }
+        Ok(())
+    }
+
+    // The interrupt-pending line into a core: the source a claim would
+    // return, if it outranks both the threshold and whatever the core is
+    // already handling
+    pub fn pending_for(&self, target: usize) -> Option<InterruptID> {
+        let target_state = self.targets.get(target)?;
+        let floor = target_state
+            .in_service
+            .last()
+            .map_or(target_state.threshold, |&(_, priority)| priority.max(target_state.threshold));
+
+        // Highest priority wins; ties go to the lowest source number
+        self.sources
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(index, source)| source.pending && source.priority > floor && target_state.enabled[*index])
+            .max_by_key(|(index, source)| (source.priority, std::cmp::Reverse(*index)))
+            .map(|(index, _)| InterruptID(index as u32))
+    }
+
+    // Takes the best pending source for a core and closes its gateway until
+    // the handler completes it
+    pub fn claim(&mut self, target: usize) -> Option<InterruptID> {
+        let id = self.pending_for(target)?;
+        let cycle = self.cycle;
+        let source = &mut self.sources[id.0 as usize];
+        source.pending = false;
+        source.active = true;
+        let priority = source.priority;
+        let latency = cycle - source.raised_at;
+
+        let target_state = &mut self.targets[target];
+        let nested = !target_state.in_service.is_empty();
+        target_state.in_service.push((id, priority));
+        let depth = target_state.in_service.len();
+
+        self.stats.interrupts_claimed += 1;
+        self.stats.total_latency += latency;
+        self.stats.max_latency = self.stats.max_latency.max(latency);
+        self.stats.max_nesting = self.stats.max_nesting.max(depth);
+        if nested {
+            self.stats.preemptions += 1;
+            self.record(InterruptEventKind::Preempted, id, Some(target));
+        } else {
+            self.record(InterruptEventKind::Claimed, id, Some(target));
+        }
+        Some(id)
+    }
+
+    // The handler is done: reopen the gateway. A level source whose device
+    // still holds the line is pending again at once.
+    pub fn complete(&mut self, target: usize, id: InterruptID) -> InterruptResult<()> {
+        let target_state = self.target_mut(target)?;
+        let position = target_state
+            .in_service
+            .iter()
+            .rposition(|&(claimed, _)| claimed == id)
+            .ok_or(InterruptError::NotClaimed(id.0))?;
+        target_state.in_service.remove(position);
+
+        let cycle = self.cycle;
+        let source = self.source_mut(id)?;
+        source.active = false;
+        if source.trigger == Trigger::Level && source.line && !source.pending {
+            source.pending = true;
+            source.raised_at = cycle;
+        }
+
+        self.stats.interrupts_completed += 1;
+        self.record(InterruptEventKind::Completed, id, Some(target));
         Ok(())
     }
 
-    pub fn handle_pending(&mut self) {
-        while let Some(interrupt) = self.pending.pop_front() {
-            if let Some(handler) = self.handlers.get(interrupt.id.0 as usize) {
-                if let Ok(()) = handler(&interrupt) {
-                    self.stats.interrupts_handled += 1;
-                } else {
-                    self.stats.interrupts_failed += 1;
+    // Methods for visualization system
+    pub fn get_stats(&self) -> &InterruptStats {
+        &self.stats
+    }
+
+    pub fn events(&self) -> &VecDeque<InterruptEvent> {
+        &self.events
+    }
+
+    pub fn is_pending(&self, id: InterruptID) -> bool {
+        self.sources.get(id.0 as usize).is_some_and(|source| source.pending)
+    }
+
+    // Sources a core is handling, outermost first
+    pub fn in_service(&self, target: usize) -> Vec<InterruptID> {
+        self.targets
+            .get(target)
+            .map(|target| target.in_service.iter().map(|&(id, _)| id).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn average_latency(&self) -> f64 {
+        if self.stats.interrupts_claimed == 0 {
+            return 0.0;
+        }
+        self.stats.total_latency as f64 / self.stats.interrupts_claimed as f64
+    }
+
+    // Helper methods
+    fn source_mut(&mut self, id: InterruptID) -> InterruptResult<&mut Source> {
+        if id.0 == 0 {
+            return Err(InterruptError::InvalidSource(0));
+        }
+        self.sources.get_mut(id.0 as usize).ok_or(InterruptError::InvalidSource(id.0))
+    }
+
+    fn target_mut(&mut self, target: usize) -> InterruptResult<&mut Target> {
+        self.targets.get_mut(target).ok_or(InterruptError::InvalidTarget(target))
+    }
+
+    fn set_enabled(&mut self, target: usize, id: InterruptID, enabled: bool) -> InterruptResult<()> {
+        self.source_mut(id)?;
+        self.target_mut(target)?.enabled[id.0 as usize] = enabled;
+        Ok(())
+    }
+
+    fn record(&mut self, kind: InterruptEventKind, source: InterruptID, target: Option<usize>) {
+        if self.events.len() == EVENT_LOG_LIMIT {
+            self.events.pop_front();
+        }
+        self.events.push_back(InterruptEvent { kind, source, target, cycle: self.cycle });
+    }
+}
+
+impl Default for InterruptConfig {
+    fn default() -> Self {
+        Self {
+            num_sources: 64,
+            num_targets: 1,
+            max_priority: 7,
+        }
+    }
+}
+
+impl Default for InterruptController {
+    fn default() -> Self {
+        Self::new(InterruptConfig::default())
+    }
+}
+
+// The same controller as a driver sees it. Reading a target's claim register
+// claims, writing it completes; source 0 and out-of-range bits read as zero
+// and ignore writes, as the PLIC spec has it.
+impl MmioDevice for InterruptController {
+    fn read(&mut self, offset: u64, _size: AccessSize) -> Result<u64, BusError> {
+        let value = match self.decode(offset).ok_or(BusError::DeviceFault(offset))? {
+            PlicRegister::Priority(id) => self.sources.get(id as usize).map_or(0, |source| source.priority as u32),
+            PlicRegister::Pending(word) => self.source_bits(word, |_, source| source.pending),
+            PlicRegister::Enable(target, word) => {
+                let enabled = &self.targets[target].enabled;
+                self.source_bits(word, |index, _| enabled[index])
+            }
+            PlicRegister::Threshold(target) => self.targets[target].threshold as u32,
+            PlicRegister::Claim(target) => self.claim(target).map_or(0, |id| id.0),
+        };
+        Ok(value as u64)
+    }
+
+    fn write(&mut self, offset: u64, _size: AccessSize, value: u64) -> Result<(), BusError> {
+        let value = value as u32;
+        match self.decode(offset).ok_or(BusError::DeviceFault(offset))? {
+            PlicRegister::Priority(id) => {
+                if id != 0 && id < self.config.num_sources {
+                    let _ = self.set_priority(InterruptID(id), value as u8);
+                }
+            }
+            PlicRegister::Pending(_) => {} // Read only
+            PlicRegister::Enable(target, word) => {
+                for bit in 0..32 {
+                    let id = word * 32 + bit;
+                    if id != 0 && id < self.config.num_sources {
+                        self.targets[target].enabled[id as usize] = value & (1 << bit) != 0;
+                    }
                 }
             }
+            PlicRegister::Threshold(target) => self.targets[target].threshold = (value as u8).min(self.config.max_priority),
+            // Completing a source the target never claimed is silently ignored
+            PlicRegister::Claim(target) => {
+                let _ = self.complete(target, InterruptID(value));
+            }
+        }
+        Ok(())
+    }
+
+    fn supports(&self, size: AccessSize) -> bool {
+        size == AccessSize::Word
+    }
+}
+
+enum PlicRegister {
+    Priority(u32),
+    Pending(u32),       // Word index
+    Enable(usize, u32), // Target, word index
+    Threshold(usize),
+    Claim(usize),
+}
+
+impl InterruptController {
+    // Helper methods
+    fn decode(&self, offset: u64) -> Option<PlicRegister> {
+        let source_words = (self.config.num_sources as u64).div_ceil(32);
+        let targets = self.targets.len() as u64;
+        if offset < PLIC_PENDING {
+            return (offset < 4 * self.config.num_sources as u64).then_some(PlicRegister::Priority((offset / 4) as u32));
         }
+        if offset < PLIC_ENABLE {
+            let word = (offset - PLIC_PENDING) / 4;
+            return (word < source_words).then_some(PlicRegister::Pending(word as u32));
+        }
+        if offset < PLIC_CONTEXT {
+            let (target, word) = ((offset - PLIC_ENABLE) / PLIC_ENABLE_STRIDE, (offset - PLIC_ENABLE) % PLIC_ENABLE_STRIDE / 4);
+            return (target < targets && word < source_words).then_some(PlicRegister::Enable(target as usize, word as u32));
+        }
+        let (target, register) = ((offset - PLIC_CONTEXT) / PLIC_CONTEXT_STRIDE, (offset - PLIC_CONTEXT) % PLIC_CONTEXT_STRIDE);
+        match register {
+            _ if target >= targets => None,
+            0 => Some(PlicRegister::Threshold(target as usize)),
+            4 => Some(PlicRegister::Claim(target as usize)),
+            _ => None,
+        }
+    }
+
+    // Bit i of the word is source word * 32 + i
+    fn source_bits(&self, word: u32, bit: impl Fn(usize, &Source) -> bool) -> u32 {
+        (0..32)
+            .map(|offset| (word * 32 + offset) as usize)
+            .filter(|&index| index != 0 && self.sources.get(index).is_some_and(|source| bit(index, source)))
+            .fold(0, |bits, index| bits | 1 << (index % 32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UART: u32 = 10;
+    const DISK: u32 = 33;
+
+    fn word(plic: &mut InterruptController, offset: u64) -> u64 {
+        plic.read(offset, AccessSize::Word).unwrap()
+    }
+
+    #[test]
+    fn drivers_configure_claim_and_complete_through_registers() {
+        let mut plic = InterruptController::new(InterruptConfig { num_targets: 2, ..InterruptConfig::default() });
+        plic.write(PLIC_PRIORITY + 4 * UART as u64, AccessSize::Word, 3).unwrap();
+        plic.write(PLIC_PRIORITY + 4 * DISK as u64, AccessSize::Word, 5).unwrap();
+        plic.write(PLIC_ENABLE + PLIC_ENABLE_STRIDE, AccessSize::Word, 1 << UART).unwrap();
+        plic.write(PLIC_ENABLE + PLIC_ENABLE_STRIDE + 4, AccessSize::Word, 1 << (DISK - 32)).unwrap();
+
+        plic.raise_interrupt(InterruptID(UART)).unwrap();
+        plic.raise_interrupt(InterruptID(DISK)).unwrap();
+        assert_eq!(word(&mut plic, PLIC_PENDING), 1 << UART);
+        assert_eq!(word(&mut plic, PLIC_PENDING + 4), 1 << (DISK - 32));
+
+        // Target 0 enabled nothing; target 1 claims the higher priority first
+        let claim = |target: u64| PLIC_CONTEXT + target * PLIC_CONTEXT_STRIDE + 4;
+        assert_eq!(word(&mut plic, claim(0)), 0);
+        assert_eq!(word(&mut plic, claim(1)), DISK as u64);
+        assert_eq!(word(&mut plic, claim(1)), 0, "the UART doesn't outrank the running disk handler");
+
+        // The handler services the disk, which drops its line, then completes
+        plic.set_level(InterruptID(DISK), false).unwrap();
+        plic.write(claim(1), AccessSize::Word, DISK as u64).unwrap();
+        assert_eq!(word(&mut plic, claim(1)), UART as u64);
+        assert_eq!(plic.in_service(1), vec![InterruptID(UART)]);
+    }
+
+    #[test]
+    fn threshold_masks_low_priorities() {
+        let mut plic = InterruptController::default();
+        plic.write(PLIC_PRIORITY + 4 * UART as u64, AccessSize::Word, 2).unwrap();
+        plic.write(PLIC_ENABLE, AccessSize::Word, 1 << UART).unwrap();
+        plic.write(PLIC_CONTEXT, AccessSize::Word, 2).unwrap();
+        plic.raise_interrupt(InterruptID(UART)).unwrap();
+
+        assert_eq!(word(&mut plic, PLIC_CONTEXT + 4), 0);
+        plic.write(PLIC_CONTEXT, AccessSize::Word, 1).unwrap();
+        assert_eq!(word(&mut plic, PLIC_CONTEXT), 1);
+        assert_eq!(word(&mut plic, PLIC_CONTEXT + 4), UART as u64);
+    }
+
+    #[test]
+    fn reserved_bits_and_bad_completions_are_ignored() {
+        let mut plic = InterruptController::default();
+        plic.write(PLIC_PRIORITY, AccessSize::Word, 7).unwrap();
+        assert_eq!(word(&mut plic, PLIC_PRIORITY), 0);
+        plic.write(PLIC_ENABLE, AccessSize::Word, u32::MAX as u64).unwrap();
+        assert_eq!(word(&mut plic, PLIC_ENABLE), 0xFFFF_FFFE);
+        plic.write(PLIC_PRIORITY + 4, AccessSize::Word, 99).unwrap();
+        assert_eq!(word(&mut plic, PLIC_PRIORITY + 4), 7, "priorities saturate at the maximum");
+
+        plic.write(PLIC_CONTEXT + 4, AccessSize::Word, UART as u64).unwrap();
+        assert_eq!(plic.get_stats().interrupts_completed, 0);
+
+        // Past the last source and the last target there are no registers
+        assert!(plic.read(PLIC_PRIORITY + 4 * 64, AccessSize::Word).is_err());
+        assert!(plic.read(PLIC_CONTEXT + PLIC_CONTEXT_STRIDE, AccessSize::Word).is_err());
+        assert!(plic.read(PLIC_CONTEXT + 8, AccessSize::Word).is_err());
+    }
+
+    #[test]
+    fn reraising_an_active_level_source_is_not_a_new_interrupt() {
+        let mut plic = InterruptController::default();
+        plic.set_trigger(InterruptID(UART), Trigger::Level).unwrap();
+        plic.set_priority(InterruptID(UART), 1).unwrap();
+        plic.enable(0, InterruptID(UART)).unwrap();
+
+        plic.raise_interrupt(InterruptID(UART)).unwrap();
+        assert_eq!(plic.claim(0), Some(InterruptID(UART)));
+
+        // The device pokes its line again while the handler runs
+        plic.raise_interrupt(InterruptID(UART)).unwrap();
+        assert!(!plic.is_pending(InterruptID(UART)));
+        assert_eq!(plic.get_stats().interrupts_raised, 1);
+        assert_eq!(plic.get_stats().coalesced, 1);
+
+        // The line is still up at completion, so the source pends once more
+        plic.complete(0, InterruptID(UART)).unwrap();
+        assert!(plic.is_pending(InterruptID(UART)));
     }
-} 
\ No newline at end of file
+}
@@ -0,0 +1,366 @@
+use super::super::error::{IOError, IOResult};
+
+// Frame Information Structures: the packets host and device exchange over the SATA link
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FisType {
+    RegisterH2D = 0x27,
+    RegisterD2H = 0x34,
+    DmaActivate = 0x39,
+    DmaSetup = 0x41,
+    Data = 0x46,
+    PioSetup = 0x5F,
+    SetDeviceBits = 0xA1,
+}
+
+impl FisType {
+    pub fn from_byte(byte: u8) -> IOResult<Self> {
+        match byte {
+            0x27 => Ok(FisType::RegisterH2D),
+            0x34 => Ok(FisType::RegisterD2H),
+            0x39 => Ok(FisType::DmaActivate),
+            0x41 => Ok(FisType::DmaSetup),
+            0x46 => Ok(FisType::Data),
+            0x5F => Ok(FisType::PioSetup),
+            0xA1 => Ok(FisType::SetDeviceBits),
+            _ => Err(IOError::InvalidFis),
+        }
+    }
+}
+
+// ATA commands the host model understands
+pub mod ata {
+    pub const READ_DMA_EXT: u8 = 0x25;
+    pub const WRITE_DMA_EXT: u8 = 0x35;
+    pub const READ_FPDMA_QUEUED: u8 = 0x60;
+    pub const WRITE_FPDMA_QUEUED: u8 = 0x61;
+    pub const DATA_SET_MANAGEMENT: u8 = 0x06;
+    pub const FLUSH_CACHE_EXT: u8 = 0xEA;
+    pub const IDENTIFY_DEVICE: u8 = 0xEC;
+
+    // Status register
+    pub const STATUS_ERR: u8 = 0x01;
+    pub const STATUS_DRQ: u8 = 0x08;
+    pub const STATUS_DF: u8 = 0x20;
+    pub const STATUS_DRDY: u8 = 0x40;
+    pub const STATUS_BSY: u8 = 0x80;
+
+    // Error register
+    pub const ERROR_ABRT: u8 = 0x04;
+    pub const ERROR_IDNF: u8 = 0x10;
+    pub const ERROR_UNC: u8 = 0x40;
+
+    // Device register: LBA addressing
+    pub const DEVICE_LBA: u8 = 0x40;
+
+    // DSM feature bit selecting TRIM
+    pub const DSM_TRIM: u16 = 0x0001;
+}
+
+// Register FIS - Host to Device: carries a command, or a device control update
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RegisterH2D {
+    pub port_multiplier: u8,
+    pub is_command: bool,
+    pub command: u8,
+    pub features: u16,
+    pub lba: u64,
+    pub device: u8,
+    pub count: u16,
+    pub icc: u8,
+    pub control: u8,
+}
+
+impl RegisterH2D {
+    pub const LENGTH: usize = 20;
+
+    pub fn command(command: u8) -> Self {
+        Self {
+            is_command: true,
+            command,
+            device: ata::DEVICE_LBA,
+            ..Self::default()
+        }
+    }
+
+    pub fn identify() -> Self {
+        let mut fis = Self::command(ata::IDENTIFY_DEVICE);
+        fis.device = 0;
+        fis
+    }
+
+    pub fn flush() -> Self {
+        Self::command(ata::FLUSH_CACHE_EXT)
+    }
+
+    pub fn read_dma_ext(lba: u64, sectors: u16) -> Self {
+        Self { lba, count: sectors, ..Self::command(ata::READ_DMA_EXT) }
+    }
+
+    pub fn write_dma_ext(lba: u64, sectors: u16) -> Self {
+        Self { lba, count: sectors, ..Self::command(ata::WRITE_DMA_EXT) }
+    }
+
+    // NCQ moves the sector count into FEATURES and the tag into COUNT bits 7:3
+    pub fn read_fpdma_queued(lba: u64, sectors: u16, tag: u8) -> Self {
+        Self { lba, features: sectors, count: (tag as u16) << 3, ..Self::command(ata::READ_FPDMA_QUEUED) }
+    }
+
+    pub fn write_fpdma_queued(lba: u64, sectors: u16, tag: u8) -> Self {
+        Self { lba, features: sectors, count: (tag as u16) << 3, ..Self::command(ata::WRITE_FPDMA_QUEUED) }
+    }
+
+    // The range list travels as data; COUNT is its length in 512-byte blocks
+    pub fn trim(blocks: u16) -> Self {
+        Self { features: ata::DSM_TRIM, count: blocks, ..Self::command(ata::DATA_SET_MANAGEMENT) }
+    }
+
+    pub fn is_queued(&self) -> bool {
+        self.command == ata::READ_FPDMA_QUEUED || self.command == ata::WRITE_FPDMA_QUEUED
+    }
+
+    pub fn tag(&self) -> u8 {
+        ((self.count >> 3) & 0x1F) as u8
+    }
+
+    // Sectors moved by a DMA command; zero in the register means the maximum
+    pub fn sector_count(&self) -> u32 {
+        let count = if self.is_queued() { self.features } else { self.count };
+        if count == 0 { 65536 } else { count as u32 }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::LENGTH] {
+        let mut bytes = [0u8; Self::LENGTH];
+        let lba = self.lba.to_le_bytes();
+        bytes[0] = FisType::RegisterH2D as u8;
+        bytes[1] = (self.port_multiplier & 0x0F) | if self.is_command { 0x80 } else { 0 };
+        bytes[2] = self.command;
+        bytes[3] = self.features as u8;
+        bytes[4..7].copy_from_slice(&lba[0..3]);
+        bytes[7] = self.device;
+        bytes[8..11].copy_from_slice(&lba[3..6]);
+        bytes[11] = (self.features >> 8) as u8;
+        bytes[12] = self.count as u8;
+        bytes[13] = (self.count >> 8) as u8;
+        bytes[14] = self.icc;
+        bytes[15] = self.control;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> IOResult<Self> {
+        if bytes.len() < Self::LENGTH || FisType::from_byte(bytes[0])? != FisType::RegisterH2D {
+            return Err(IOError::InvalidFis);
+        }
+        let mut lba = [0u8; 8];
+        lba[0..3].copy_from_slice(&bytes[4..7]);
+        lba[3..6].copy_from_slice(&bytes[8..11]);
+        Ok(Self {
+            port_multiplier: bytes[1] & 0x0F,
+            is_command: bytes[1] & 0x80 != 0,
+            command: bytes[2],
+            features: u16::from_le_bytes([bytes[3], bytes[11]]),
+            lba: u64::from_le_bytes(lba),
+            device: bytes[7],
+            count: u16::from_le_bytes([bytes[12], bytes[13]]),
+            icc: bytes[14],
+            control: bytes[15],
+        })
+    }
+}
+
+// Register FIS - Device to Host: final status of a command
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RegisterD2H {
+    pub interrupt: bool,
+    pub status: u8,
+    pub error: u8,
+    pub lba: u64,
+    pub device: u8,
+    pub count: u16,
+}
+
+impl RegisterD2H {
+    pub const LENGTH: usize = 20;
+
+    pub fn to_bytes(&self) -> [u8; Self::LENGTH] {
+        let mut bytes = [0u8; Self::LENGTH];
+        let lba = self.lba.to_le_bytes();
+        bytes[0] = FisType::RegisterD2H as u8;
+        bytes[1] = if self.interrupt { 0x40 } else { 0 };
+        bytes[2] = self.status;
+        bytes[3] = self.error;
+        bytes[4..7].copy_from_slice(&lba[0..3]);
+        bytes[7] = self.device;
+        bytes[8..11].copy_from_slice(&lba[3..6]);
+        bytes[12] = self.count as u8;
+        bytes[13] = (self.count >> 8) as u8;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> IOResult<Self> {
+        if bytes.len() < Self::LENGTH || FisType::from_byte(bytes[0])? != FisType::RegisterD2H {
+            return Err(IOError::InvalidFis);
+        }
+        let mut lba = [0u8; 8];
+        lba[0..3].copy_from_slice(&bytes[4..7]);
+        lba[3..6].copy_from_slice(&bytes[8..11]);
+        Ok(Self {
+            interrupt: bytes[1] & 0x40 != 0,
+            status: bytes[2],
+            error: bytes[3],
+            lba: u64::from_le_bytes(lba),
+            device: bytes[7],
+            count: u16::from_le_bytes([bytes[12], bytes[13]]),
+        })
+    }
+}
+
+// DMA Setup FIS: the device picks which queued command's buffer the next data phase uses
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DmaSetup {
+    pub direction_to_host: bool,
+    pub interrupt: bool,
+    pub auto_activate: bool,
+    pub buffer_id: u64, // Low 5 bits are the NCQ tag
+    pub buffer_offset: u32,
+    pub transfer_count: u32,
+}
+
+impl DmaSetup {
+    pub const LENGTH: usize = 28;
+
+    pub fn to_bytes(&self) -> [u8; Self::LENGTH] {
+        let mut bytes = [0u8; Self::LENGTH];
+        bytes[0] = FisType::DmaSetup as u8;
+        bytes[1] = (if self.direction_to_host { 0x20 } else { 0 })
+            | (if self.interrupt { 0x40 } else { 0 })
+            | (if self.auto_activate { 0x80 } else { 0 });
+        bytes[4..12].copy_from_slice(&self.buffer_id.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.buffer_offset.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.transfer_count.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> IOResult<Self> {
+        if bytes.len() < Self::LENGTH || FisType::from_byte(bytes[0])? != FisType::DmaSetup {
+            return Err(IOError::InvalidFis);
+        }
+        let word = |offset: usize| u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+        Ok(Self {
+            direction_to_host: bytes[1] & 0x20 != 0,
+            interrupt: bytes[1] & 0x40 != 0,
+            auto_activate: bytes[1] & 0x80 != 0,
+            buffer_id: word(4) as u64 | (word(8) as u64) << 32,
+            buffer_offset: word(16),
+            transfer_count: word(20),
+        })
+    }
+}
+
+// Set Device Bits FIS: completes queued commands by clearing their SActive bits
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SetDeviceBits {
+    pub interrupt: bool,
+    pub status: u8,
+    pub error: u8,
+    pub completed: u32,
+}
+
+impl SetDeviceBits {
+    pub const LENGTH: usize = 8;
+
+    pub fn to_bytes(&self) -> [u8; Self::LENGTH] {
+        let mut bytes = [0u8; Self::LENGTH];
+        bytes[0] = FisType::SetDeviceBits as u8;
+        bytes[1] = if self.interrupt { 0x40 } else { 0 };
+        // Only the high and low status nibbles travel in this FIS
+        bytes[2] = self.status & 0x77;
+        bytes[3] = self.error;
+        bytes[4..8].copy_from_slice(&self.completed.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> IOResult<Self> {
+        if bytes.len() < Self::LENGTH || FisType::from_byte(bytes[0])? != FisType::SetDeviceBits {
+            return Err(IOError::InvalidFis);
+        }
+        Ok(Self {
+            interrupt: bytes[1] & 0x40 != 0,
+            status: bytes[2],
+            error: bytes[3],
+            completed: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_h2d_round_trips_a_queued_command() {
+        let fis = RegisterH2D { port_multiplier: 3, ..RegisterH2D::write_fpdma_queued(0x0000_8765_4321_0FED, 0x1234, 17) };
+        let bytes = fis.to_bytes();
+        assert_eq!(bytes[0], 0x27);
+        assert_eq!(bytes[1], 0x83);
+        assert_eq!(RegisterH2D::from_bytes(&bytes), Ok(fis.clone()));
+
+        let decoded = RegisterH2D::from_bytes(&bytes).unwrap();
+        assert!(decoded.is_queued());
+        assert_eq!((decoded.tag(), decoded.sector_count()), (17, 0x1234));
+        assert_eq!(RegisterH2D::read_dma_ext(0, 0).sector_count(), 65536);
+    }
+
+    #[test]
+    fn register_d2h_round_trips() {
+        let fis = RegisterD2H {
+            interrupt: true,
+            status: ata::STATUS_DRDY | ata::STATUS_ERR,
+            error: ata::ERROR_IDNF,
+            lba: 0xABCD_EF01_2345,
+            device: ata::DEVICE_LBA,
+            count: 0x0102,
+        };
+        let bytes = fis.to_bytes();
+        assert_eq!((bytes[0], bytes[1]), (0x34, 0x40));
+        assert_eq!(RegisterD2H::from_bytes(&bytes), Ok(fis));
+    }
+
+    #[test]
+    fn dma_setup_round_trips() {
+        let fis = DmaSetup {
+            direction_to_host: true,
+            interrupt: false,
+            auto_activate: true,
+            buffer_id: 0x1_0000_001F,
+            buffer_offset: 0x200,
+            transfer_count: 64 * 512,
+        };
+        let bytes = fis.to_bytes();
+        assert_eq!((bytes[0], bytes[1]), (0x41, 0xA0));
+        assert_eq!(DmaSetup::from_bytes(&bytes), Ok(fis));
+    }
+
+    #[test]
+    fn set_device_bits_round_trips_with_only_the_status_nibbles() {
+        let fis = SetDeviceBits { interrupt: true, status: ata::STATUS_DRDY | ata::STATUS_ERR, error: ata::ERROR_UNC, completed: 0x8000_0021 };
+        let bytes = fis.to_bytes();
+        assert_eq!(bytes[0], 0xA1);
+        assert_eq!(SetDeviceBits::from_bytes(&bytes), Ok(fis));
+
+        // BSY and DRQ sit outside the nibbles the FIS carries
+        let busy = SetDeviceBits { status: ata::STATUS_BSY | ata::STATUS_DRQ | ata::STATUS_DRDY, ..SetDeviceBits::default() };
+        assert_eq!(SetDeviceBits::from_bytes(&busy.to_bytes()).unwrap().status, ata::STATUS_DRDY);
+    }
+
+    #[test]
+    fn short_or_mistyped_fises_are_rejected() {
+        let h2d = RegisterH2D::flush().to_bytes();
+        assert_eq!(RegisterH2D::from_bytes(&h2d[..RegisterH2D::LENGTH - 1]), Err(IOError::InvalidFis));
+        assert_eq!(RegisterD2H::from_bytes(&h2d), Err(IOError::InvalidFis));
+        assert_eq!(FisType::from_byte(0x00), Err(IOError::InvalidFis));
+        for fis_type in [FisType::RegisterH2D, FisType::DmaActivate, FisType::PioSetup, FisType::SetDeviceBits] {
+            assert_eq!(FisType::from_byte(fis_type as u8), Ok(fis_type));
+        }
+    }
+}
@@ -1,4 +1,5 @@
 // Export all modules in controllers
+pub mod fis;
 pub mod network;
 pub mod sata;
 pub mod usb;
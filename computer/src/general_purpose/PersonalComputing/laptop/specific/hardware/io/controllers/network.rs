@@ -9,11 +9,11 @@ pub struct NetworkController {
     stats: NetworkStats,
 }
 
-struct NetworkConfig {
-    mac_address: [u8; 6],
-    mtu: u16,
-    buffer_size: usize,
-    link_speed: u32,  // Mbps
+pub struct NetworkConfig {
+    pub mac_address: [u8; 6],
+    pub mtu: u16,
+    pub buffer_size: usize,
+    pub link_speed: u32,  // Mbps
 }
 
 #[derive(Clone)]
@@ -21,33 +21,35 @@ struct Packet {
     data: Vec<u8>,
     size: usize,
     flags: PacketFlags,
-    timestamp: u64,
 }
 
-bitflags! {
-    struct PacketFlags: u8 {
-        const NONE = 0x00;
-        const BROADCAST = 0x01;
-        const MULTICAST = 0x02;
-        const ERROR = 0x04;
-        const VLAN = 0x08;
-    }
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct PacketFlags(u8);
+
+impl PacketFlags {
+    const NONE: Self = Self(0x00);
+    const BROADCAST: Self = Self(0x01);
+    const MULTICAST: Self = Self(0x02);
 }
 
+#[derive(Clone, Copy, PartialEq)]
 enum NetworkState {
     Down,
     Up,
-    Error,
 }
 
-struct NetworkStats {
-    bytes_sent: u64,
-    bytes_received: u64,
-    packets_sent: u64,
-    packets_received: u64,
-    errors: u64,
+#[derive(Debug, Clone, Default)]
+pub struct NetworkStats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub dropped: u64,
+    pub errors: u64,
 }
 
+// The link is a loopback: a transmitted frame comes back in if it's
+// addressed to this MAC, to a multicast group or to everyone
 impl NetworkController {
     pub fn new(config: NetworkConfig) -> Self {
         Self {
@@ -59,6 +61,19 @@ impl NetworkController {
         }
     }
 
+    pub fn link_up(&mut self) {
+        self.state = NetworkState::Up;
+    }
+
+    pub fn link_down(&mut self) {
+        self.state = NetworkState::Down;
+        self.tx_queue.clear();
+    }
+
+    pub fn is_link_up(&self) -> bool {
+        self.state == NetworkState::Up
+    }
+
     pub fn send_packet(&mut self, data: &[u8]) -> IOResult<()> {
         if data.len() > self.config.mtu as usize {
             return Err(IOError::PacketTooLarge);
@@ -67,14 +82,10 @@ impl NetworkController {
         let packet = Packet {
             data: data.to_vec(),
             size: data.len(),
-            flags: PacketFlags::NONE,
-            timestamp: self.get_current_time(),
+            flags: Self::classify(data),
         };
 
         self.tx_queue.push_back(packet);
-        self.stats.packets_sent += 1;
-        self.stats.bytes_sent += data.len() as u64;
-
         Ok(())
     }
 
@@ -91,26 +102,102 @@ impl NetworkController {
     pub fn tick(&mut self) {
         // Process TX queue
         while let Some(packet) = self.tx_queue.pop_front() {
-            if let Err(_) = self.transmit_packet(&packet) {
+            if self.transmit_packet(packet).is_err() {
                 self.stats.errors += 1;
             }
         }
+    }
+
+    pub fn mac_address(&self) -> [u8; 6] {
+        self.config.mac_address
+    }
 
-        // Check for received packets
-        self.poll_receive();
+    pub fn get_stats(&self) -> &NetworkStats {
+        &self.stats
     }
 
-    fn transmit_packet(&mut self, packet: &Packet) -> IOResult<()> {
-        // Actual hardware would transmit here
+    fn transmit_packet(&mut self, packet: Packet) -> IOResult<()> {
+        if self.state != NetworkState::Up {
+            return Err(IOError::LinkDown);
+        }
+        self.stats.packets_sent += 1;
+        self.stats.bytes_sent += packet.size as u64;
+
+        let for_us = packet.data.starts_with(&self.config.mac_address);
+        if for_us || packet.flags != PacketFlags::NONE {
+            if self.rx_queue.len() >= self.config.buffer_size {
+                self.stats.dropped += 1;
+            } else {
+                self.rx_queue.push_back(packet);
+            }
+        }
         Ok(())
     }
 
-    fn poll_receive(&mut self) {
-        // Actual hardware would check for received packets
+    // From the destination address in the first six bytes
+    fn classify(data: &[u8]) -> PacketFlags {
+        match data.get(..6) {
+            Some(destination) if destination.iter().all(|&byte| byte == 0xFF) => PacketFlags::BROADCAST,
+            Some(destination) if destination[0] & 1 != 0 => PacketFlags::MULTICAST,
+            _ => PacketFlags::NONE,
+        }
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            mac_address: [0x02, 0x00, 0x00, 0x00, 0x00, 0x01], // Locally administered
+            mtu: 1514,
+            buffer_size: 256,
+            link_speed: 1000,
+        }
+    }
+}
+
+impl Default for NetworkController {
+    fn default() -> Self {
+        Self::new(NetworkConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(destination: [u8; 6]) -> Vec<u8> {
+        let mut frame = destination.to_vec();
+        frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 0x02, 0x08, 0x00, 0xAB]);
+        frame
+    }
+
+    #[test]
+    fn loops_back_frames_for_this_mac_and_broadcast() {
+        let mut nic = NetworkController::default();
+        nic.link_up();
+        nic.send_packet(&frame(nic.mac_address())).unwrap();
+        nic.send_packet(&frame([0xFF; 6])).unwrap();
+        nic.send_packet(&frame([0x02, 0, 0, 0, 0, 0x09])).unwrap();
+        nic.tick();
+
+        assert_eq!(nic.receive_packet().unwrap(), Some(frame(nic.mac_address())));
+        assert_eq!(nic.receive_packet().unwrap(), Some(frame([0xFF; 6])));
+        assert_eq!(nic.receive_packet().unwrap(), None);
+        assert_eq!(nic.get_stats().packets_sent, 3);
+    }
+
+    #[test]
+    fn counts_an_error_for_frames_sent_with_the_link_down() {
+        let mut nic = NetworkController::default();
+        nic.send_packet(&frame([0xFF; 6])).unwrap();
+        nic.tick();
+        assert_eq!(nic.get_stats().errors, 1);
+        assert_eq!(nic.get_stats().packets_sent, 0);
     }
 
-    fn get_current_time(&self) -> u64 {
-        // Would use actual system time
-        0
+    #[test]
+    fn refuses_frames_over_the_mtu() {
+        let mut nic = NetworkController::default();
+        assert_eq!(nic.send_packet(&[0; 1515]), Err(IOError::PacketTooLarge));
     }
 }
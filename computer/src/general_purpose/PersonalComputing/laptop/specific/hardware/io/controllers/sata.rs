@@ -1,10 +1,90 @@
 use super::super::error::{IOError, IOResult};
+use super::fis::{ata, DmaSetup, FisType, RegisterD2H, RegisterH2D, SetDeviceBits};
+use crate::hardware::memory::physical::PhysicalMemory;
+use crate::hardware::storage::error::StorageError;
+use crate::hardware::storage::{BlockDevice, BlockDeviceType};
 use std::collections::VecDeque;
 
+// AHCI host bus adapter. Software talks to it only through the memory-mapped
+// registers below and the command lists and FIS areas it places in RAM.
+
+// Generic host control registers
+pub const HBA_CAP: u32 = 0x00;
+pub const HBA_GHC: u32 = 0x04;
+pub const HBA_IS: u32 = 0x08;
+pub const HBA_PI: u32 = 0x0C;
+pub const HBA_VS: u32 = 0x10;
+
+// Port registers, at 0x100 + port * 0x80
+pub const PORT_BASE: u32 = 0x100;
+pub const PORT_STRIDE: u32 = 0x80;
+pub const PORT_CLB: u32 = 0x00;
+pub const PORT_CLBU: u32 = 0x04;
+pub const PORT_FB: u32 = 0x08;
+pub const PORT_FBU: u32 = 0x0C;
+pub const PORT_IS: u32 = 0x10;
+pub const PORT_IE: u32 = 0x14;
+pub const PORT_CMD: u32 = 0x18;
+pub const PORT_TFD: u32 = 0x20;
+pub const PORT_SIG: u32 = 0x24;
+pub const PORT_SSTS: u32 = 0x28;
+pub const PORT_SCTL: u32 = 0x2C;
+pub const PORT_SERR: u32 = 0x30;
+pub const PORT_SACT: u32 = 0x34;
+pub const PORT_CI: u32 = 0x38;
+
+// CAP bits
+pub const CAP_S64A: u32 = 1 << 31;
+pub const CAP_SNCQ: u32 = 1 << 30;
+pub const CAP_SAM: u32 = 1 << 18;
+
+// GHC bits
+pub const GHC_AE: u32 = 1 << 31;
+pub const GHC_IE: u32 = 1 << 1;
+pub const GHC_HR: u32 = 1 << 0;
+
+// PxCMD bits
+pub const CMD_CR: u32 = 1 << 15;
+pub const CMD_FR: u32 = 1 << 14;
+pub const CMD_FRE: u32 = 1 << 4;
+pub const CMD_CLO: u32 = 1 << 3;
+pub const CMD_ST: u32 = 1 << 0;
+
+// PxIS bits
+pub const IS_TFES: u32 = 1 << 30;
+pub const IS_HBFS: u32 = 1 << 29;
+pub const IS_OFS: u32 = 1 << 24;
+pub const IS_SDBS: u32 = 1 << 3;
+pub const IS_DSS: u32 = 1 << 2;
+pub const IS_DHRS: u32 = 1 << 0;
+
+// Device signature of an ATA disk, and SStatus for a Gen3 link with the device present
+pub const SIG_ATA: u32 = 0x0000_0101;
+pub const SSTS_GEN3_ACTIVE: u32 = 0x133;
+
+// In-memory layouts
+pub const COMMAND_HEADER_SIZE: u64 = 32;
+pub const COMMAND_LIST_SIZE: u64 = 1024;
+pub const RECEIVED_FIS_SIZE: u64 = 256;
+pub const CFIS_OFFSET: u64 = 0x00;
+pub const PRDT_OFFSET: u64 = 0x80;
+pub const PRD_ENTRY_SIZE: u64 = 16;
+pub const RFIS_DMA_SETUP: u64 = 0x00;
+pub const RFIS_D2H: u64 = 0x40;
+pub const RFIS_SDB: u64 = 0x58;
+
+// A PRD entry moves at most 4 MiB
+pub const PRD_MAX_BYTES: u32 = 1 << 22;
+
+const FIS_TRACE_LIMIT: usize = 1024;
+
 pub struct SataController {
     ports: Vec<SataPort>,
-    command_queue: VecDeque<SataCommand>,
     config: SataConfig,
+    global_control: u32,
+    interrupt_status: u32,
+    fis_trace: VecDeque<FisRecord>,
+    fis_sequence: u64,
     stats: SataStats,
 }
 
@@ -12,7 +92,27 @@ struct SataPort {
     port_number: u8,
     device: Option<SataDevice>,
     state: PortState,
-    status: PortStatus,
+    registers: PortRegisters,
+    // NCQ commands the device has accepted but not yet completed
+    queued: Vec<QueuedCommand>,
+    // Where the device finished its last transfer, for its own reordering
+    last_lba: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PortRegisters {
+    pub command_list_base: u64,
+    pub fis_base: u64,
+    pub interrupt_status: u32,
+    pub interrupt_enable: u32,
+    pub command: u32,
+    pub task_file: u32,
+    pub signature: u32,
+    pub sata_status: u32,
+    pub sata_control: u32,
+    pub sata_error: u32,
+    pub sata_active: u32,
+    pub command_issue: u32,
 }
 
 struct SataDevice {
@@ -21,118 +121,945 @@ struct SataDevice {
     capacity: u64,
     sector_size: u32,
     features: DeviceFeatures,
+    media: Box<dyn BlockDevice>,
 }
 
-struct SataCommand {
-    port: u8,
-    command_type: CommandType,
-    lba: u64,
-    sector_count: u32,
-    data: Option<Vec<u8>>,
-    status: CommandStatus,
-}
-
-enum CommandType {
-    Read,
-    Write,
-    Identify,
-    Flush,
-    Trim,
+#[derive(Debug, Clone)]
+pub struct DeviceFeatures {
+    pub ncq: bool,
+    pub trim: bool,
+    pub queue_depth: u8,
+    pub rotation_rate: u16, // RPM, or 1 for solid state
 }
 
-enum CommandStatus {
-    Pending,
-    InProgress,
-    Completed,
-    Error,
-}
-
-enum PortState {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PortState {
     NotPresent,
     Present,
     Active,
     Error,
 }
 
-struct PortStatus {
-    link_speed: u32,
-    errors: u32,
-    busy: bool,
+// The 32-byte command header a driver fills in for each slot
+#[derive(Debug, Clone, Default)]
+pub struct CommandHeader {
+    pub fis_length: u8, // In dwords
+    pub atapi: bool,
+    pub write: bool,
+    pub prefetchable: bool,
+    pub clear_busy: bool,
+    pub prdt_length: u16,
+    pub prd_byte_count: u32,
+    pub table_base: u64,
+}
+
+struct QueuedCommand {
+    slot: u8,
+    header: CommandHeader,
+    fis: RegisterH2D,
+}
+
+// Why a command did not complete
+enum Failure {
+    Device(u8),
+    HostBus,
+    Overflow,
+}
+
+#[derive(Debug, Clone)]
+pub struct SataConfig {
+    pub num_ports: usize,
+    pub command_slots: usize,
+    pub supports_64bit: bool,
+    pub interface_speed: u8, // 1 = 1.5 Gb/s, 2 = 3 Gb/s, 3 = 6 Gb/s
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SataStats {
+    pub commands: u64,
+    pub queued_commands: u64,
+    pub reordered: u64,
+    pub sectors_read: u64,
+    pub sectors_written: u64,
+    pub fis_received: u64,
+    pub fis_sent: u64,
+    pub interrupts: u64,
+    pub errors: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct FisRecord {
+    pub sequence: u64,
+    pub port: u8,
+    pub fis_type: FisType,
+    pub to_host: bool,
+    pub command: u8,
+    pub tag: Option<u8>,
 }
 
 impl SataController {
     pub fn new(config: SataConfig) -> Self {
-        let ports = (0..config.num_ports)
-            .map(|i| SataPort {
-                port_number: i as u8,
-                device: None,
-                state: PortState::NotPresent,
-                status: PortStatus::default(),
-            })
+        let ports = (0..config.num_ports.clamp(1, 32))
+            .map(|i| SataPort::new(i as u8))
             .collect();
 
         Self {
             ports,
-            command_queue: VecDeque::new(),
             config,
+            global_control: GHC_AE,
+            interrupt_status: 0,
+            fis_trace: VecDeque::new(),
+            fis_sequence: 0,
             stats: SataStats::default(),
         }
     }
 
-    pub fn read_sectors(&mut self, port: u8, lba: u64, count: u32) -> IOResult<Vec<u8>> {
-        let command = SataCommand {
-            port,
-            command_type: CommandType::Read,
-            lba,
-            sector_count: count,
-            data: None,
-            status: CommandStatus::Pending,
-        };
-
-        self.command_queue.push_back(command);
-        self.process_commands()?;
+    // Cables a drive to a port; the link comes up and the device sends its signature
+    pub fn attach(&mut self, port: u8, media: Box<dyn BlockDevice>, model: &str, serial: &str) -> IOResult<()> {
+        let port = self.ports.get_mut(port as usize).ok_or(IOError::InvalidPort)?;
+        let solid_state = media.device_type() != BlockDeviceType::HardDisk;
+        port.device = Some(SataDevice {
+            model: model.to_string(),
+            serial: serial.to_string(),
+            capacity: media.sector_count(),
+            sector_size: media.sector_size() as u32,
+            features: DeviceFeatures {
+                ncq: true,
+                trim: solid_state,
+                queue_depth: 32,
+                rotation_rate: if solid_state { 1 } else { 5400 },
+            },
+            media,
+        });
+        port.link_up();
+        Ok(())
+    }
 
-        Ok(Vec::new()) // Simplified - would actually return data
+    pub fn detach(&mut self, port: u8) -> IOResult<Box<dyn BlockDevice>> {
+        let port = self.ports.get_mut(port as usize).ok_or(IOError::InvalidPort)?;
+        let device = port.device.take().ok_or(IOError::DeviceNotFound)?;
+        port.reset();
+        Ok(device.media)
     }
 
-    pub fn write_sectors(&mut self, port: u8, lba: u64, data: &[u8]) -> IOResult<()> {
-        let command = SataCommand {
-            port,
-            command_type: CommandType::Write,
-            lba,
-            sector_count: (data.len() / 512) as u32,
-            data: Some(data.to_vec()),
-            status: CommandStatus::Pending,
-        };
+    pub fn read_register(&self, offset: u32) -> IOResult<u32> {
+        if !offset.is_multiple_of(4) {
+            return Err(IOError::InvalidRegister);
+        }
+        if offset < PORT_BASE {
+            return match offset {
+                HBA_CAP => Ok(self.capabilities()),
+                HBA_GHC => Ok(self.global_control),
+                HBA_IS => Ok(self.interrupt_status),
+                HBA_PI => Ok(self.ports_implemented()),
+                HBA_VS => Ok(0x0001_0301), // AHCI 1.3.1
+                _ => Ok(0),
+            };
+        }
 
-        self.command_queue.push_back(command);
-        self.process_commands()?;
+        let (index, register) = self.port_register(offset)?;
+        let registers = &self.ports[index].registers;
+        Ok(match register {
+            PORT_CLB => registers.command_list_base as u32,
+            PORT_CLBU => (registers.command_list_base >> 32) as u32,
+            PORT_FB => registers.fis_base as u32,
+            PORT_FBU => (registers.fis_base >> 32) as u32,
+            PORT_IS => registers.interrupt_status,
+            PORT_IE => registers.interrupt_enable,
+            PORT_CMD => registers.command,
+            PORT_TFD => registers.task_file,
+            PORT_SIG => registers.signature,
+            PORT_SSTS => registers.sata_status,
+            PORT_SCTL => registers.sata_control,
+            PORT_SERR => registers.sata_error,
+            PORT_SACT => registers.sata_active,
+            PORT_CI => registers.command_issue,
+            _ => 0,
+        })
+    }
 
+    pub fn write_register(&mut self, offset: u32, value: u32) -> IOResult<()> {
+        if !offset.is_multiple_of(4) {
+            return Err(IOError::InvalidRegister);
+        }
+        if offset < PORT_BASE {
+            match offset {
+                HBA_GHC => {
+                    if value & GHC_HR != 0 {
+                        self.reset();
+                    } else {
+                        // AHCI-only HBAs keep AE stuck at one
+                        self.global_control = (value & GHC_IE) | GHC_AE;
+                    }
+                }
+                HBA_IS => self.interrupt_status &= !value,
+                // Read-only
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        let (index, register) = self.port_register(offset)?;
+        let port = &mut self.ports[index];
+        let registers = &mut port.registers;
+        let running = registers.command & CMD_ST != 0;
+        match register {
+            // The engine must be stopped before its memory areas move
+            PORT_CLB if !running => {
+                registers.command_list_base = (registers.command_list_base & !0xFFFF_FFFF) | (value & !0x3FF) as u64;
+            }
+            PORT_CLBU if !running => {
+                registers.command_list_base = (registers.command_list_base & 0xFFFF_FFFF) | (value as u64) << 32;
+            }
+            PORT_FB if registers.command & CMD_FRE == 0 => {
+                registers.fis_base = (registers.fis_base & !0xFFFF_FFFF) | (value & !0xFF) as u64;
+            }
+            PORT_FBU if registers.command & CMD_FRE == 0 => {
+                registers.fis_base = (registers.fis_base & 0xFFFF_FFFF) | (value as u64) << 32;
+            }
+            PORT_IS => registers.interrupt_status &= !value,
+            PORT_IE => registers.interrupt_enable = value,
+            PORT_CMD => port.write_command(value),
+            PORT_SCTL => port.write_sata_control(value),
+            PORT_SERR => registers.sata_error &= !value,
+            // Software can only set bits; the HBA and device clear them
+            PORT_SACT if running => registers.sata_active |= value,
+            PORT_CI if running => registers.command_issue |= value,
+            _ => {}
+        }
         Ok(())
     }
 
-    fn process_commands(&mut self) -> IOResult<()> {
-        while let Some(mut command) = self.command_queue.pop_front() {
-            let port = &mut self.ports[command.port as usize];
-            
-            match command.command_type {
-                CommandType::Read => {
-                    // Handle read command
-                }
-                CommandType::Write => {
-                    // Handle write command
+    // Lets the HBA and its devices make progress: fetch newly issued commands,
+    // run non-queued ones to completion and complete one queued command per
+    // port. Returns how many commands finished.
+    pub fn process(&mut self, memory: &mut dyn PhysicalMemory) -> usize {
+        let mut completed = 0;
+        for index in 0..self.ports.len() {
+            completed += self.process_port(index, memory);
+        }
+        completed
+    }
+
+    pub fn interrupt_pending(&self) -> bool {
+        self.global_control & GHC_IE != 0 && self.interrupt_status != 0
+    }
+
+    pub fn port_state(&self, port: u8) -> Option<PortState> {
+        self.ports.get(port as usize).map(|port| port.state)
+    }
+
+    pub fn get_stats(&self) -> &SataStats {
+        &self.stats
+    }
+
+    // Methods for visualization system
+    pub fn fis_trace(&self) -> &VecDeque<FisRecord> {
+        &self.fis_trace
+    }
+
+    pub fn port_registers(&self, port: u8) -> Option<&PortRegisters> {
+        self.ports.get(port as usize).map(|port| &port.registers)
+    }
+
+    pub fn outstanding_tags(&self, port: u8) -> Vec<u8> {
+        self.ports
+            .get(port as usize)
+            .map(|port| port.queued.iter().map(|command| command.slot).collect())
+            .unwrap_or_default()
+    }
+
+    // Helper methods
+    fn capabilities(&self) -> u32 {
+        let mut cap = (self.ports.len() as u32 - 1) & 0x1F;
+        cap |= ((self.config.command_slots.clamp(1, 32) as u32 - 1) & 0x1F) << 8;
+        cap |= ((self.config.interface_speed as u32) & 0xF) << 20;
+        cap |= CAP_SNCQ | CAP_SAM;
+        if self.config.supports_64bit {
+            cap |= CAP_S64A;
+        }
+        cap
+    }
+
+    fn ports_implemented(&self) -> u32 {
+        (0..self.ports.len()).fold(0, |mask, port| mask | 1 << port)
+    }
+
+    fn port_register(&self, offset: u32) -> IOResult<(usize, u32)> {
+        let index = ((offset - PORT_BASE) / PORT_STRIDE) as usize;
+        if index >= self.ports.len() {
+            return Err(IOError::InvalidPort);
+        }
+        Ok((index, (offset - PORT_BASE) % PORT_STRIDE))
+    }
+
+    fn reset(&mut self) {
+        for port in &mut self.ports {
+            port.reset();
+        }
+        self.global_control = GHC_AE;
+        self.interrupt_status = 0;
+    }
+
+    fn process_port(&mut self, index: usize, memory: &mut dyn PhysicalMemory) -> usize {
+        let registers = &self.ports[index].registers;
+        if registers.command & CMD_CR == 0 || self.ports[index].state != PortState::Active {
+            return 0;
+        }
+
+        let mut completed = 0;
+        let slots = self.config.command_slots.clamp(1, 32);
+        for slot in 0..slots as u8 {
+            let port = &self.ports[index];
+            let fetched = port.queued.iter().any(|command| command.slot == slot);
+            if port.registers.command_issue & 1 << slot == 0 || fetched {
+                continue;
+            }
+            match self.fetch_command(index, slot, memory) {
+                Ok((header, fis)) if fis.is_queued() => self.accept_queued(index, slot, header, fis, memory),
+                Ok((header, fis)) => {
+                    completed += self.execute_immediate(index, slot, header, fis, memory);
                 }
-                CommandType::Identify => {
-                    // Handle identify command
+                Err(failure) => self.fail(index, None, failure, memory),
+            }
+            if self.ports[index].state != PortState::Active {
+                return completed;
+            }
+        }
+
+        completed + self.complete_queued(index, memory)
+    }
+
+    fn fetch_command(&mut self, index: usize, slot: u8, memory: &mut dyn PhysicalMemory) -> Result<(CommandHeader, RegisterH2D), Failure> {
+        let address = self.ports[index].registers.command_list_base + slot as u64 * COMMAND_HEADER_SIZE;
+        let mut raw = [0u8; COMMAND_HEADER_SIZE as usize];
+        memory.read_physical(address, &mut raw).map_err(|_| Failure::HostBus)?;
+        let header = CommandHeader::from_bytes(&raw);
+
+        let mut cfis = [0u8; RegisterH2D::LENGTH];
+        memory.read_physical(header.table_base + CFIS_OFFSET, &mut cfis).map_err(|_| Failure::HostBus)?;
+        let fis = RegisterH2D::from_bytes(&cfis).map_err(|_| Failure::Device(ata::ERROR_ABRT))?;
+
+        self.stats.fis_received += 1;
+        let tag = if fis.is_queued() { Some(fis.tag()) } else { None };
+        self.record(index, FisType::RegisterH2D, false, fis.command, tag);
+        Ok((header, fis))
+    }
+
+    // The device acknowledges with a D2H FIS that clears BSY; data moves later
+    fn accept_queued(&mut self, index: usize, slot: u8, header: CommandHeader, fis: RegisterH2D, memory: &mut dyn PhysicalMemory) {
+        let port = &self.ports[index];
+        let ncq = port.device.as_ref().map(|device| device.features.ncq).unwrap_or(false);
+        if !ncq || fis.tag() != slot || port.registers.sata_active & 1 << slot == 0 {
+            self.fail(index, Some(slot), Failure::Device(ata::ERROR_ABRT), memory);
+            return;
+        }
+
+        self.ports[index].registers.command_issue &= !(1 << slot);
+        let d2h = RegisterD2H { status: ata::STATUS_DRDY, ..RegisterD2H::default() };
+        if self.post_d2h(index, &d2h, memory).is_err() {
+            self.fail(index, Some(slot), Failure::HostBus, memory);
+            return;
+        }
+        self.ports[index].queued.push(QueuedCommand { slot, header, fis });
+        self.stats.queued_commands += 1;
+    }
+
+    fn execute_immediate(&mut self, index: usize, slot: u8, header: CommandHeader, fis: RegisterH2D, memory: &mut dyn PhysicalMemory) -> usize {
+        // Non-queued commands may not be mixed with outstanding queued ones
+        if !self.ports[index].queued.is_empty() {
+            self.fail(index, Some(slot), Failure::Device(ata::ERROR_ABRT), memory);
+            return 0;
+        }
+
+        match self.execute(index, &header, &fis, memory) {
+            Ok(bytes) => {
+                let d2h = RegisterD2H {
+                    interrupt: true,
+                    status: ata::STATUS_DRDY,
+                    lba: fis.lba,
+                    device: fis.device,
+                    ..RegisterD2H::default()
+                };
+                let posted = self.write_byte_count(index, slot, bytes, memory).and_then(|_| self.post_d2h(index, &d2h, memory));
+                if posted.is_err() {
+                    self.fail(index, Some(slot), Failure::HostBus, memory);
+                    return 0;
                 }
-                CommandType::Flush => {
-                    // Handle flush command
+                self.ports[index].registers.command_issue &= !(1 << slot);
+                self.raise(index, IS_DHRS);
+                self.stats.commands += 1;
+                1
+            }
+            Err(failure) => {
+                self.fail(index, Some(slot), failure, memory);
+                0
+            }
+        }
+    }
+
+    // The device serves whichever queued command is closest to where it is
+    fn complete_queued(&mut self, index: usize, memory: &mut dyn PhysicalMemory) -> usize {
+        let port = &self.ports[index];
+        let position = match port
+            .queued
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, command)| command.fis.lba.abs_diff(port.last_lba))
+        {
+            Some((position, _)) => position,
+            None => return 0,
+        };
+        if position != 0 {
+            self.stats.reordered += 1;
+        }
+        let command = self.ports[index].queued.remove(position);
+        let slot = command.slot;
+
+        let setup = DmaSetup {
+            direction_to_host: command.fis.command == ata::READ_FPDMA_QUEUED,
+            auto_activate: true,
+            buffer_id: slot as u64,
+            transfer_count: command.fis.sector_count() * self.ports[index].sector_size(),
+            ..DmaSetup::default()
+        };
+        if self.post_fis(index, RFIS_DMA_SETUP, &setup.to_bytes(), memory).is_err() {
+            self.fail(index, Some(slot), Failure::HostBus, memory);
+            return 0;
+        }
+        self.stats.fis_sent += 1;
+        self.record(index, FisType::DmaSetup, true, command.fis.command, Some(slot));
+
+        match self.execute(index, &command.header, &command.fis, memory) {
+            Ok(bytes) => {
+                let sdb = SetDeviceBits {
+                    interrupt: true,
+                    status: ata::STATUS_DRDY,
+                    error: 0,
+                    completed: 1 << slot,
+                };
+                let posted = self.write_byte_count(index, slot, bytes, memory)
+                    .and_then(|_| self.post_fis(index, RFIS_SDB, &sdb.to_bytes(), memory));
+                if posted.is_err() {
+                    self.fail(index, Some(slot), Failure::HostBus, memory);
+                    return 0;
                 }
-                CommandType::Trim => {
-                    // Handle trim command
+                self.stats.fis_sent += 1;
+                self.record(index, FisType::SetDeviceBits, true, command.fis.command, Some(slot));
+
+                let port = &mut self.ports[index];
+                port.registers.sata_active &= !(1 << slot);
+                port.last_lba = command.fis.lba + command.fis.sector_count() as u64;
+                self.raise(index, IS_SDBS);
+                self.stats.commands += 1;
+                1
+            }
+            Err(failure) => {
+                self.fail(index, Some(slot), failure, memory);
+                0
+            }
+        }
+    }
+
+    // Runs the ATA command against the drive, moving data through the PRD table
+    fn execute(&mut self, index: usize, header: &CommandHeader, fis: &RegisterH2D, memory: &mut dyn PhysicalMemory) -> Result<u32, Failure> {
+        let regions = read_prdt(header, memory)?;
+        let port = &mut self.ports[index];
+        let device = port.device.as_mut().ok_or(Failure::Device(ata::ERROR_ABRT))?;
+
+        match fis.command {
+            ata::IDENTIFY_DEVICE => {
+                let data = device.identify_data();
+                scatter(&regions, &data, memory)
+            }
+            ata::READ_DMA_EXT | ata::READ_FPDMA_QUEUED => {
+                let count = fis.sector_count();
+                device.check_range(fis.lba, count)?;
+                let mut data = vec![0u8; count as usize * device.sector_size as usize];
+                device.media.read_sectors(fis.lba, count, &mut data).map_err(ata_error)?;
+                self.stats.sectors_read += count as u64;
+                scatter(&regions, &data, memory)
+            }
+            ata::WRITE_DMA_EXT | ata::WRITE_FPDMA_QUEUED => {
+                let count = fis.sector_count();
+                device.check_range(fis.lba, count)?;
+                let data = gather(&regions, count as usize * device.sector_size as usize, memory)?;
+                device.media.write_sectors(fis.lba, &data).map_err(ata_error)?;
+                self.stats.sectors_written += count as u64;
+                Ok(data.len() as u32)
+            }
+            ata::FLUSH_CACHE_EXT => {
+                device.media.flush().map_err(ata_error)?;
+                Ok(0)
+            }
+            ata::DATA_SET_MANAGEMENT if fis.features & ata::DSM_TRIM != 0 && device.features.trim => {
+                let data = gather(&regions, fis.count as usize * 512, memory)?;
+                // Each entry is a 48-bit LBA and a 16-bit length; zero lengths are padding
+                let mut ranges = Vec::new();
+                for entry in data.chunks_exact(8) {
+                    let entry = u64::from_le_bytes(entry.try_into().unwrap_or([0; 8]));
+                    let length = (entry >> 48) as u32;
+                    if length != 0 {
+                        let lba = entry & 0xFFFF_FFFF_FFFF;
+                        device.check_range(lba, length)?;
+                        ranges.push((lba, length));
+                    }
                 }
+                device.media.trim_sectors(&ranges).map_err(ata_error)?;
+                Ok(data.len() as u32)
             }
+            _ => Err(Failure::Device(ata::ERROR_ABRT)),
         }
+    }
+
+    // Errors halt the port with ERR in the task file until software restarts it
+    fn fail(&mut self, index: usize, slot: Option<u8>, failure: Failure, memory: &mut dyn PhysicalMemory) {
+        self.stats.errors += 1;
+        let (cause, error) = match failure {
+            Failure::Device(error) => (IS_TFES, error),
+            Failure::HostBus => (IS_HBFS, 0),
+            Failure::Overflow => (IS_OFS, ata::ERROR_ABRT),
+        };
+
+        let status = ata::STATUS_DRDY | ata::STATUS_ERR;
+        let d2h = RegisterD2H { interrupt: true, status, error, ..RegisterD2H::default() };
+        if cause == IS_TFES {
+            let _ = self.post_d2h(index, &d2h, memory);
+        }
+
+        let port = &mut self.ports[index];
+        port.registers.task_file = (error as u32) << 8 | status as u32;
+        port.state = PortState::Error;
+        if let Some(slot) = slot {
+            // The failed command stays issued so software can see which one it was
+            port.registers.command_issue |= 1 << slot;
+        }
+        self.raise(index, cause);
+    }
+
+    fn post_d2h(&mut self, index: usize, fis: &RegisterD2H, memory: &mut dyn PhysicalMemory) -> Result<(), Failure> {
+        self.post_fis(index, RFIS_D2H, &fis.to_bytes(), memory)?;
+        let port = &mut self.ports[index];
+        port.registers.task_file = (fis.error as u32) << 8 | fis.status as u32;
+        self.stats.fis_sent += 1;
+        self.record(index, FisType::RegisterD2H, true, 0, None);
         Ok(())
     }
+
+    fn post_fis(&mut self, index: usize, offset: u64, bytes: &[u8], memory: &mut dyn PhysicalMemory) -> Result<(), Failure> {
+        let registers = &self.ports[index].registers;
+        // With FIS receive off, the HBA drops FISes rather than writing them
+        if registers.command & CMD_FR == 0 {
+            return Ok(());
+        }
+        memory.write_physical(registers.fis_base + offset, bytes).map_err(|_| Failure::HostBus)
+    }
+
+    fn write_byte_count(&mut self, index: usize, slot: u8, bytes: u32, memory: &mut dyn PhysicalMemory) -> Result<(), Failure> {
+        let address = self.ports[index].registers.command_list_base + slot as u64 * COMMAND_HEADER_SIZE + 4;
+        memory.write_u32(address, bytes).map_err(|_| Failure::HostBus)
+    }
+
+    fn raise(&mut self, index: usize, cause: u32) {
+        let registers = &mut self.ports[index].registers;
+        registers.interrupt_status |= cause;
+        if registers.interrupt_status & registers.interrupt_enable != 0 {
+            if self.interrupt_status & 1 << index == 0 {
+                self.stats.interrupts += 1;
+            }
+            self.interrupt_status |= 1 << index;
+        }
+    }
+
+    fn record(&mut self, index: usize, fis_type: FisType, to_host: bool, command: u8, tag: Option<u8>) {
+        if self.fis_trace.len() == FIS_TRACE_LIMIT {
+            self.fis_trace.pop_front();
+        }
+        self.fis_sequence += 1;
+        self.fis_trace.push_back(FisRecord {
+            sequence: self.fis_sequence,
+            port: index as u8,
+            fis_type,
+            to_host,
+            command,
+            tag,
+        });
+    }
+}
+
+impl SataPort {
+    fn new(port_number: u8) -> Self {
+        Self {
+            port_number,
+            device: None,
+            state: PortState::NotPresent,
+            registers: PortRegisters { signature: 0xFFFF_FFFF, ..PortRegisters::default() },
+            queued: Vec::new(),
+            last_lba: 0,
+        }
+    }
+
+    // Link established: the device's first D2H FIS carries its signature
+    fn link_up(&mut self) {
+        if self.device.is_some() {
+            self.registers.sata_status = SSTS_GEN3_ACTIVE;
+            self.registers.signature = SIG_ATA;
+            self.registers.task_file = ata::STATUS_DRDY as u32;
+            self.state = PortState::Present;
+        }
+    }
+
+    fn reset(&mut self) {
+        let device = self.device.take();
+        *self = SataPort::new(self.port_number);
+        self.device = device;
+        self.link_up();
+    }
+
+    fn write_command(&mut self, value: u32) {
+        let registers = &mut self.registers;
+        let was_running = registers.command & CMD_ST != 0;
+        registers.command = (registers.command & (CMD_CR | CMD_FR)) | (value & (CMD_ST | CMD_FRE));
+
+        if value & CMD_FRE != 0 {
+            registers.command |= CMD_FR;
+        } else {
+            registers.command &= !CMD_FR;
+        }
+
+        // Command list override clears BSY and DRQ so the engine can start
+        if value & CMD_CLO != 0 {
+            registers.task_file &= !((ata::STATUS_BSY | ata::STATUS_DRQ) as u32);
+        }
+
+        if value & CMD_ST != 0 {
+            if !was_running && self.device.is_some() {
+                registers.task_file &= !(ata::STATUS_ERR as u32) & !0xFF00;
+                registers.command |= CMD_CR;
+                self.state = PortState::Active;
+            }
+        } else {
+            // Stopping the engine abandons everything outstanding
+            registers.command &= !CMD_CR;
+            registers.command_issue = 0;
+            registers.sata_active = 0;
+            self.queued.clear();
+            if self.device.is_some() {
+                self.state = PortState::Present;
+            }
+        }
+    }
+
+    // DET=1 holds COMRESET on the wire; releasing it brings the link back up
+    fn write_sata_control(&mut self, value: u32) {
+        let resetting = value & 0xF == 1;
+        let was_resetting = self.registers.sata_control & 0xF == 1;
+        self.registers.sata_control = value;
+        if resetting {
+            self.registers.sata_status = 0;
+            self.registers.task_file = ata::STATUS_BSY as u32;
+            self.queued.clear();
+            self.state = if self.device.is_some() { PortState::Present } else { PortState::NotPresent };
+        } else if was_resetting {
+            self.link_up();
+        }
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.device.as_ref().map(|device| device.sector_size).unwrap_or(512)
+    }
+}
+
+impl SataDevice {
+    fn check_range(&self, lba: u64, count: u32) -> Result<(), Failure> {
+        if lba + count as u64 > self.capacity {
+            return Err(Failure::Device(ata::ERROR_IDNF));
+        }
+        Ok(())
+    }
+
+    // The 512-byte IDENTIFY DEVICE block, as the ATA8-ACS words Linux's libata reads
+    fn identify_data(&self) -> Vec<u8> {
+        let mut words = [0u16; 256];
+        words[0] = 0x0040; // Fixed, non-removable ATA device
+        words[1] = 16383;
+        words[3] = 16;
+        words[6] = 63;
+        put_ata_string(&mut words[10..20], &self.serial);
+        put_ata_string(&mut words[23..27], "1.0");
+        put_ata_string(&mut words[27..47], &self.model);
+        words[47] = 0x8010;
+        words[49] = 0x0300; // LBA and DMA
+        words[53] = 0x0006;
+        let lba28 = self.capacity.min(0x0FFF_FFFF) as u32;
+        words[60] = lba28 as u16;
+        words[61] = (lba28 >> 16) as u16;
+        if self.features.ncq {
+            words[75] = (self.features.queue_depth.clamp(1, 32) - 1) as u16;
+            words[76] = 0x0100;
+        }
+        words[76] |= 0x000E; // Gen1, Gen2 and Gen3 link speeds
+        words[80] = 0x01F0;
+        words[83] = 0x4400; // 48-bit addressing
+        words[84] = 0x4000;
+        words[86] = 0x0400;
+        words[87] = 0x4000;
+        words[88] = 0x007F;
+        for (i, word) in words[100..104].iter_mut().enumerate() {
+            *word = (self.capacity >> (16 * i)) as u16;
+        }
+        words[106] = 0x4000;
+        if self.sector_size != 512 {
+            words[106] |= 0x1000;
+            let per_sector = self.sector_size / 2;
+            words[117] = per_sector as u16;
+            words[118] = (per_sector >> 16) as u16;
+        }
+        if self.features.trim {
+            words[169] = 0x0001;
+        }
+        words[217] = self.features.rotation_rate;
+
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+}
+
+impl CommandHeader {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let word = |offset: usize| u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+        let flags = word(0);
+        Self {
+            fis_length: (flags & 0x1F) as u8,
+            atapi: flags & 1 << 5 != 0,
+            write: flags & 1 << 6 != 0,
+            prefetchable: flags & 1 << 7 != 0,
+            clear_busy: flags & 1 << 10 != 0,
+            prdt_length: (flags >> 16) as u16,
+            prd_byte_count: word(4),
+            table_base: (word(8) & !0x7F) as u64 | (word(12) as u64) << 32,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; COMMAND_HEADER_SIZE as usize] {
+        let mut flags = (self.fis_length & 0x1F) as u32 | (self.prdt_length as u32) << 16;
+        if self.atapi {
+            flags |= 1 << 5;
+        }
+        if self.write {
+            flags |= 1 << 6;
+        }
+        if self.prefetchable {
+            flags |= 1 << 7;
+        }
+        if self.clear_busy {
+            flags |= 1 << 10;
+        }
+        let mut bytes = [0u8; COMMAND_HEADER_SIZE as usize];
+        bytes[0..4].copy_from_slice(&flags.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.prd_byte_count.to_le_bytes());
+        bytes[8..12].copy_from_slice(&(self.table_base as u32).to_le_bytes());
+        bytes[12..16].copy_from_slice(&((self.table_base >> 32) as u32).to_le_bytes());
+        bytes
+    }
+}
+
+impl Default for SataConfig {
+    fn default() -> Self {
+        Self {
+            num_ports: 4,
+            command_slots: 32,
+            supports_64bit: true,
+            interface_speed: 3,
+        }
+    }
+}
+
+// Writes one PRD entry of a command table: a buffer address and its length
+pub fn prd_entry(address: u64, bytes: u32, interrupt: bool) -> [u8; PRD_ENTRY_SIZE as usize] {
+    let mut entry = [0u8; PRD_ENTRY_SIZE as usize];
+    entry[0..8].copy_from_slice(&address.to_le_bytes());
+    let count = (bytes.clamp(1, PRD_MAX_BYTES) - 1) | if interrupt { 1 << 31 } else { 0 };
+    entry[12..16].copy_from_slice(&count.to_le_bytes());
+    entry
+}
+
+fn read_prdt(header: &CommandHeader, memory: &mut dyn PhysicalMemory) -> Result<Vec<(u64, usize)>, Failure> {
+    (0..header.prdt_length as u64)
+        .map(|i| {
+            let address = header.table_base + PRDT_OFFSET + i * PRD_ENTRY_SIZE;
+            let base = memory.read_u64(address).map_err(|_| Failure::HostBus)?;
+            let count = memory.read_u32(address + 12).map_err(|_| Failure::HostBus)?;
+            Ok((base & !1, ((count & (PRD_MAX_BYTES - 1)) + 1) as usize))
+        })
+        .collect()
+}
+
+// Device-to-memory DMA across the PRD regions
+fn scatter(regions: &[(u64, usize)], data: &[u8], memory: &mut dyn PhysicalMemory) -> Result<u32, Failure> {
+    let mut offset = 0;
+    for &(address, length) in regions {
+        if offset == data.len() {
+            break;
+        }
+        let chunk = length.min(data.len() - offset);
+        memory.write_physical(address, &data[offset..offset + chunk]).map_err(|_| Failure::HostBus)?;
+        offset += chunk;
+    }
+    if offset < data.len() {
+        return Err(Failure::Overflow);
+    }
+    Ok(offset as u32)
+}
+
+// Memory-to-device DMA across the PRD regions
+fn gather(regions: &[(u64, usize)], length: usize, memory: &mut dyn PhysicalMemory) -> Result<Vec<u8>, Failure> {
+    let mut data = vec![0u8; length];
+    let mut offset = 0;
+    for &(address, size) in regions {
+        if offset == length {
+            break;
+        }
+        let chunk = size.min(length - offset);
+        memory.read_physical(address, &mut data[offset..offset + chunk]).map_err(|_| Failure::HostBus)?;
+        offset += chunk;
+    }
+    if offset < length {
+        return Err(Failure::Overflow);
+    }
+    Ok(data)
+}
+
+fn ata_error(error: StorageError) -> Failure {
+    match error {
+        StorageError::BadSector | StorageError::UncorrectableError => Failure::Device(ata::ERROR_UNC),
+        StorageError::InvalidAddress => Failure::Device(ata::ERROR_IDNF),
+        _ => Failure::Device(ata::ERROR_ABRT),
+    }
+}
+
+// ATA strings are space padded with the two bytes of each word swapped
+fn put_ata_string(words: &mut [u16], text: &str) {
+    let mut bytes = text.bytes().chain(std::iter::repeat(b' '));
+    for word in words.iter_mut() {
+        let first = bytes.next().unwrap_or(b' ');
+        let second = bytes.next().unwrap_or(b' ');
+        *word = (first as u16) << 8 | second as u16;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::memory::physical::SystemRam;
+    use crate::hardware::storage::MemoryBlockDevice;
+
+    const CLB: u64 = 0x1000;
+    const FB: u64 = 0x2000;
+
+    fn port(register: u32) -> u32 {
+        PORT_BASE + register
+    }
+
+    // One SSD on port 0 with the engine running and every interrupt enabled
+    fn started() -> (SataController, SystemRam) {
+        let mut hba = SataController::new(SataConfig::default());
+        let mut media = MemoryBlockDevice::new(2048, 512);
+        media.write_sectors(8, &[0xA5; 512]).unwrap();
+        hba.attach(0, Box::new(media), "Test SSD", "0001").unwrap();
+
+        hba.write_register(HBA_GHC, GHC_IE).unwrap();
+        hba.write_register(port(PORT_CLB), CLB as u32).unwrap();
+        hba.write_register(port(PORT_FB), FB as u32).unwrap();
+        hba.write_register(port(PORT_IE), u32::MAX).unwrap();
+        hba.write_register(port(PORT_CMD), CMD_FRE | CMD_ST).unwrap();
+        (hba, SystemRam::new(1 << 20))
+    }
+
+    // Fills a slot's command header and table: the FIS plus one PRD entry at a per-slot buffer
+    fn build(memory: &mut SystemRam, slot: u8, fis: &RegisterH2D) -> u64 {
+        let table = 0x4000 + slot as u64 * 0x100;
+        let buffer = 0x10000 + slot as u64 * 0x1000;
+        let header = CommandHeader {
+            fis_length: (RegisterH2D::LENGTH / 4) as u8,
+            prdt_length: 1,
+            table_base: table,
+            ..CommandHeader::default()
+        };
+        memory.write_physical(CLB + slot as u64 * COMMAND_HEADER_SIZE, &header.to_bytes()).unwrap();
+        memory.write_physical(table + CFIS_OFFSET, &fis.to_bytes()).unwrap();
+        memory.write_physical(table + PRDT_OFFSET, &prd_entry(buffer, 0x1000, false)).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn issued_slot_completes_and_clears_its_ci_bit() {
+        let (mut hba, mut memory) = started();
+        let buffer = build(&mut memory, 3, &RegisterH2D::read_dma_ext(8, 1));
+        hba.write_register(port(PORT_CI), 1 << 3).unwrap();
+        assert_eq!(hba.read_register(port(PORT_CI)), Ok(1 << 3));
+
+        assert_eq!(hba.process(&mut memory), 1);
+        assert_eq!(hba.read_register(port(PORT_CI)), Ok(0));
+        assert_eq!(hba.read_register(port(PORT_IS)).unwrap() & IS_DHRS, IS_DHRS);
+        assert!(hba.interrupt_pending());
+
+        let mut data = [0u8; 512];
+        memory.read_physical(buffer, &mut data).unwrap();
+        assert_eq!(data, [0xA5; 512]);
+        assert_eq!(memory.read_u32(CLB + 3 * COMMAND_HEADER_SIZE + 4).unwrap(), 512);
+
+        let mut d2h = [0u8; RegisterD2H::LENGTH];
+        memory.read_physical(FB + RFIS_D2H, &mut d2h).unwrap();
+        assert_eq!(RegisterD2H::from_bytes(&d2h).unwrap().status, ata::STATUS_DRDY);
+
+        // Writing PxIS and IS back acknowledges the interrupt
+        hba.write_register(port(PORT_IS), u32::MAX).unwrap();
+        hba.write_register(HBA_IS, 1).unwrap();
+        assert!(!hba.interrupt_pending());
+    }
+
+    #[test]
+    fn queued_commands_hold_sact_until_set_device_bits() {
+        let (mut hba, mut memory) = started();
+        build(&mut memory, 2, &RegisterH2D::read_fpdma_queued(800, 1, 2));
+        let buffer = build(&mut memory, 5, &RegisterH2D::read_fpdma_queued(8, 1, 5));
+        hba.write_register(port(PORT_SACT), 1 << 2 | 1 << 5).unwrap();
+        hba.write_register(port(PORT_CI), 1 << 2 | 1 << 5).unwrap();
+
+        // Both are fetched at once; the drive finishes the one nearest its head first
+        assert_eq!(hba.process(&mut memory), 1);
+        assert_eq!(hba.read_register(port(PORT_CI)), Ok(0));
+        assert_eq!(hba.read_register(port(PORT_SACT)), Ok(1 << 2));
+        assert_eq!(hba.outstanding_tags(0), vec![2]);
+        assert_eq!(hba.get_stats().reordered, 1);
+
+        let mut sdb = [0u8; SetDeviceBits::LENGTH];
+        memory.read_physical(FB + RFIS_SDB, &mut sdb).unwrap();
+        assert_eq!(SetDeviceBits::from_bytes(&sdb).unwrap().completed, 1 << 5);
+        let mut data = [0u8; 512];
+        memory.read_physical(buffer, &mut data).unwrap();
+        assert_eq!(data, [0xA5; 512]);
+
+        assert_eq!(hba.process(&mut memory), 1);
+        assert_eq!(hba.read_register(port(PORT_SACT)), Ok(0));
+        assert!(hba.outstanding_tags(0).is_empty());
+        assert_eq!(hba.read_register(port(PORT_IS)).unwrap() & IS_SDBS, IS_SDBS);
+
+        let trace: Vec<(FisType, Option<u8>)> = hba.fis_trace().iter().map(|record| (record.fis_type, record.tag)).collect();
+        assert_eq!(&trace[trace.len() - 2..], &[(FisType::DmaSetup, Some(2)), (FisType::SetDeviceBits, Some(2))]);
+    }
+
+    #[test]
+    fn queued_command_without_its_sact_bit_halts_the_port() {
+        let (mut hba, mut memory) = started();
+        build(&mut memory, 1, &RegisterH2D::read_fpdma_queued(8, 1, 1));
+        hba.write_register(port(PORT_CI), 1 << 1).unwrap();
+
+        assert_eq!(hba.process(&mut memory), 0);
+        assert_eq!(hba.port_state(0), Some(PortState::Error));
+        assert_eq!(hba.read_register(port(PORT_CI)), Ok(1 << 1));
+        assert_eq!(hba.read_register(port(PORT_IS)).unwrap() & IS_TFES, IS_TFES);
+        let task_file = hba.read_register(port(PORT_TFD)).unwrap();
+        assert_eq!((task_file as u8 & ata::STATUS_ERR, (task_file >> 8) as u8), (ata::STATUS_ERR, ata::ERROR_ABRT));
+    }
 }
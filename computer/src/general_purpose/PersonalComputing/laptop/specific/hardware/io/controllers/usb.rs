@@ -5,49 +5,76 @@ pub struct UsbController {
     ports: Vec<UsbPort>,
     devices: HashMap<DeviceAddress, UsbDevice>,
     transfer_queue: VecDeque<UsbTransfer>,
-    config: UsbConfig,
+    completed: VecDeque<UsbTransfer>,
+    next_address: u8,
     stats: UsbStats,
 }
 
 struct UsbPort {
-    port_number: u8,
     port_type: PortType,
     status: PortStatus,
+    attached: Option<AttachedDevice>, // Plugged in, waiting for enumeration
     connected_device: Option<DeviceAddress>,
 }
 
-#[derive(Hash, Eq, PartialEq, Clone, Copy)]
-struct DeviceAddress(u8);
+// What sits on the far side of a port before it has an address
+#[derive(Clone)]
+pub struct AttachedDevice {
+    pub descriptor: DeviceDescriptor,
+    pub endpoints: Vec<Endpoint>,
+    pub speed: UsbSpeed,
+}
+
+#[derive(Debug, Hash, Eq, PartialEq, Clone, Copy)]
+pub struct DeviceAddress(pub u8);
 
 struct UsbDevice {
-    address: DeviceAddress,
     descriptor: DeviceDescriptor,
     endpoints: Vec<Endpoint>,
     state: DeviceState,
 }
 
-struct DeviceDescriptor {
-    vendor_id: u16,
-    product_id: u16,
-    device_class: u8,
-    device_subclass: u8,
-    protocol: u8,
-    max_packet_size: u16,
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceDescriptor {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub device_class: u8,
+    pub device_subclass: u8,
+    pub protocol: u8,
+    pub max_packet_size: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoint {
+    pub address: u8,
+    pub attributes: EndpointAttributes,
+    pub max_packet_size: u16,
+    pub interval: u8,
+}
+
+// The transfer type an endpoint was declared with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EndpointAttributes {
+    Control,
+    Bulk,
+    Interrupt,
+    Isochronous,
 }
 
-struct Endpoint {
-    address: u8,
-    attributes: EndpointAttributes,
-    max_packet_size: u16,
-    interval: u8,
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceState {
+    Default,    // Reset, answering at address 0
+    Addressed,
+    Configured,
 }
 
+#[derive(Clone, Copy, PartialEq)]
 enum PortType {
     Usb2,
     Usb3,
-    TypeC,
 }
 
+#[derive(Default)]
 struct PortStatus {
     connected: bool,
     enabled: bool,
@@ -55,36 +82,72 @@ struct PortStatus {
     power_state: PowerState,
 }
 
-enum UsbSpeed {
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum UsbSpeed {
     Low,    // 1.5 Mbps
+    #[default]
     Full,   // 12 Mbps
     High,   // 480 Mbps
     Super,  // 5 Gbps
     Super20, // 10 Gbps
 }
 
-struct UsbTransfer {
-    device: DeviceAddress,
-    endpoint: u8,
-    transfer_type: TransferType,
-    data: Vec<u8>,
-    status: TransferStatus,
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PowerState {
+    #[default]
+    Powered,
+    Suspended,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsbTransfer {
+    pub device: DeviceAddress,
+    pub endpoint: u8,
+    pub transfer_type: TransferType,
+    pub data: Vec<u8>,
+    pub status: TransferStatus,
 }
 
-enum TransferType {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferType {
     Control,
     Bulk,
     Interrupt,
     Isochronous,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferStatus {
+    Pending,
+    Completed,
+    Stalled,     // The endpoint refused it
+    Babble,      // More data than the endpoint's packet size allows
+}
+
+#[derive(Debug, Clone)]
+pub struct UsbConfig {
+    pub num_ports: usize,
+    pub usb3_ports: usize, // The first this many ports are SuperSpeed capable
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UsbStats {
+    pub enumerations: u64,
+    pub transfers: u64,
+    pub bytes_transferred: u64,
+    pub errors: u64,
+}
+
+// Standard control requests the model answers
+const REQUEST_SET_CONFIGURATION: u8 = 0x09;
+
 impl UsbController {
     pub fn new(config: UsbConfig) -> Self {
         let ports = (0..config.num_ports)
             .map(|i| UsbPort {
-                port_number: i as u8,
-                port_type: PortType::Usb3,
+                port_type: if i < config.usb3_ports { PortType::Usb3 } else { PortType::Usb2 },
                 status: PortStatus::default(),
+                attached: None,
                 connected_device: None,
             })
             .collect();
@@ -93,19 +156,38 @@ impl UsbController {
             ports,
             devices: HashMap::new(),
             transfer_queue: VecDeque::new(),
-            config,
+            completed: VecDeque::new(),
+            next_address: 1,
             stats: UsbStats::default(),
         }
     }
 
+    // Plugs a device into a port; it gets an address on the next enumeration
+    pub fn attach(&mut self, port: usize, device: AttachedDevice) -> IOResult<()> {
+        let port = self.ports.get_mut(port).ok_or(IOError::InvalidPort)?;
+        if port.status.connected {
+            return Err(IOError::DeviceBusy);
+        }
+        port.attached = Some(device);
+        Ok(())
+    }
+
+    pub fn detach(&mut self, port: usize) -> IOResult<()> {
+        let port = self.ports.get_mut(port).ok_or(IOError::InvalidPort)?;
+        port.attached = None;
+        Ok(())
+    }
+
     pub fn enumerate_devices(&mut self) -> IOResult<()> {
-        for port in &mut self.ports {
+        for port_number in 0..self.ports.len() {
+            let port = &self.ports[port_number];
             if port.status.connected && port.connected_device.is_none() {
-                let device = self.detect_device(port.port_number)?;
+                let device = self.detect_device(port_number)?;
                 let address = self.assign_address()?;
-                
+
                 self.devices.insert(address, device);
-                port.connected_device = Some(address);
+                self.ports[port_number].connected_device = Some(address);
+                self.stats.enumerations += 1;
             }
         }
         Ok(())
@@ -120,10 +202,15 @@ impl UsbController {
         Ok(())
     }
 
+    // Finished transfers, oldest first, with their status filled in
+    pub fn poll_completed(&mut self) -> Option<UsbTransfer> {
+        self.completed.pop_front()
+    }
+
     pub fn tick(&mut self) {
         // Process pending transfers
         while let Some(transfer) = self.transfer_queue.pop_front() {
-            if let Err(_) = self.process_transfer(transfer) {
+            if self.process_transfer(transfer).is_err() {
                 self.stats.errors += 1;
             }
         }
@@ -132,17 +219,229 @@ impl UsbController {
         self.check_port_changes();
     }
 
-    fn process_transfer(&mut self, transfer: UsbTransfer) -> IOResult<()> {
+    pub fn device_address(&self, port: usize) -> Option<DeviceAddress> {
+        self.ports.get(port)?.connected_device
+    }
+
+    pub fn device_state(&self, address: DeviceAddress) -> Option<DeviceState> {
+        self.devices.get(&address).map(|device| device.state)
+    }
+
+    pub fn descriptor(&self, address: DeviceAddress) -> Option<&DeviceDescriptor> {
+        self.devices.get(&address).map(|device| &device.descriptor)
+    }
+
+    pub fn port_speed(&self, port: usize) -> Option<UsbSpeed> {
+        let port = self.ports.get(port)?;
+        port.status.connected.then_some(port.status.speed)
+    }
+
+    pub fn suspend_port(&mut self, port: usize) -> IOResult<()> {
+        let port = self.ports.get_mut(port).ok_or(IOError::InvalidPort)?;
+        port.status.power_state = PowerState::Suspended;
+        Ok(())
+    }
+
+    pub fn get_stats(&self) -> &UsbStats {
+        &self.stats
+    }
+
+    fn process_transfer(&mut self, mut transfer: UsbTransfer) -> IOResult<()> {
+        let port_enabled = self.ports.iter().any(|port| {
+            port.connected_device == Some(transfer.device)
+                && port.status.enabled
+                && port.status.power_state == PowerState::Powered
+        });
         let device = self.devices.get_mut(&transfer.device)
             .ok_or(IOError::DeviceNotFound)?;
+        if !port_enabled {
+            return Err(IOError::DeviceBusy);
+        }
 
-        match transfer.transfer_type {
-            TransferType::Control => self.handle_control_transfer(device, transfer),
-            TransferType::Bulk => self.handle_bulk_transfer(device, transfer),
-            TransferType::Interrupt => self.handle_interrupt_transfer(device, transfer),
-            TransferType::Isochronous => self.handle_isochronous_transfer(device, transfer),
+        transfer.status = match transfer.transfer_type {
+            TransferType::Control => Self::handle_control_transfer(device, &transfer),
+            TransferType::Bulk => Self::handle_data_transfer(device, &transfer, EndpointAttributes::Bulk),
+            TransferType::Interrupt => Self::handle_data_transfer(device, &transfer, EndpointAttributes::Interrupt),
+            TransferType::Isochronous => Self::handle_data_transfer(device, &transfer, EndpointAttributes::Isochronous),
+        };
+
+        self.stats.transfers += 1;
+        if transfer.status == TransferStatus::Completed {
+            self.stats.bytes_transferred += transfer.data.len() as u64;
+        } else {
+            self.stats.errors += 1;
         }
+        self.completed.push_back(transfer);
+        Ok(())
     }
 
-    // Transfer type handlers...
+    // Endpoint 0 takes an 8-byte setup packet: request type, request, value
+    fn handle_control_transfer(device: &mut UsbDevice, transfer: &UsbTransfer) -> TransferStatus {
+        if transfer.endpoint != 0 || transfer.data.len() < 8 {
+            return TransferStatus::Stalled;
+        }
+        match transfer.data[1] {
+            REQUEST_SET_CONFIGURATION => {
+                device.state = if transfer.data[2] == 0 { DeviceState::Addressed } else { DeviceState::Configured };
+                TransferStatus::Completed
+            }
+            _ => TransferStatus::Stalled,
+        }
+    }
+
+    // Data endpoints only move data once the device is configured, and only
+    // as the transfer type they were declared with
+    fn handle_data_transfer(device: &mut UsbDevice, transfer: &UsbTransfer, kind: EndpointAttributes) -> TransferStatus {
+        if device.state != DeviceState::Configured {
+            return TransferStatus::Stalled;
+        }
+        let Some(endpoint) = device.endpoints.iter().find(|endpoint| endpoint.address == transfer.endpoint) else {
+            return TransferStatus::Stalled;
+        };
+        if endpoint.attributes != kind {
+            return TransferStatus::Stalled;
+        }
+        // Bulk transfers split into packets; the periodic ones get one per interval
+        if kind != EndpointAttributes::Bulk && transfer.data.len() > endpoint.max_packet_size as usize {
+            return TransferStatus::Babble;
+        }
+        TransferStatus::Completed
+    }
+
+    fn detect_device(&mut self, port_number: usize) -> IOResult<UsbDevice> {
+        let attached = self.ports[port_number].attached.clone().ok_or(IOError::DeviceNotFound)?;
+        Ok(UsbDevice {
+            descriptor: attached.descriptor,
+            endpoints: attached.endpoints,
+            state: DeviceState::Default,
+        })
+    }
+
+    // Addresses run 1 to 127; address 0 belongs to devices not yet enumerated
+    fn assign_address(&mut self) -> IOResult<DeviceAddress> {
+        for _ in 1..128 {
+            let address = DeviceAddress(self.next_address);
+            self.next_address = self.next_address % 127 + 1;
+            if !self.devices.contains_key(&address) {
+                return Ok(address);
+            }
+        }
+        Err(IOError::DeviceBusy)
+    }
+
+    // Connects and resets newly attached devices at the speed both ends
+    // support, and forgets devices that were unplugged
+    fn check_port_changes(&mut self) {
+        for port in &mut self.ports {
+            match (&port.attached, port.status.connected) {
+                (Some(device), false) => {
+                    let speed = match (port.port_type, device.speed) {
+                        (PortType::Usb2, UsbSpeed::Super | UsbSpeed::Super20) => UsbSpeed::High,
+                        (_, speed) => speed,
+                    };
+                    port.status = PortStatus { connected: true, enabled: true, speed, power_state: PowerState::Powered };
+                }
+                (None, true) => {
+                    if let Some(address) = port.connected_device.take() {
+                        self.devices.remove(&address);
+                    }
+                    port.status = PortStatus::default();
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Default for UsbConfig {
+    fn default() -> Self {
+        Self { num_ports: 4, usb3_ports: 2 }
+    }
+}
+
+impl Default for UsbController {
+    fn default() -> Self {
+        Self::new(UsbConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyboard() -> AttachedDevice {
+        AttachedDevice {
+            descriptor: DeviceDescriptor {
+                vendor_id: 0x046D,
+                product_id: 0xC31C,
+                device_class: 3,
+                device_subclass: 1,
+                protocol: 1,
+                max_packet_size: 8,
+            },
+            endpoints: vec![Endpoint { address: 0x81, attributes: EndpointAttributes::Interrupt, max_packet_size: 8, interval: 10 }],
+            speed: UsbSpeed::Low,
+        }
+    }
+
+    fn transfer(device: DeviceAddress, endpoint: u8, transfer_type: TransferType, data: Vec<u8>) -> UsbTransfer {
+        UsbTransfer { device, endpoint, transfer_type, data, status: TransferStatus::Pending }
+    }
+
+    fn enumerated(usb: &mut UsbController, port: usize, device: AttachedDevice) -> DeviceAddress {
+        usb.attach(port, device).unwrap();
+        usb.tick();
+        usb.enumerate_devices().unwrap();
+        usb.device_address(port).unwrap()
+    }
+
+    #[test]
+    fn enumerates_attached_devices_with_fresh_addresses() {
+        let mut usb = UsbController::default();
+        let first = enumerated(&mut usb, 0, keyboard());
+        let second = enumerated(&mut usb, 3, keyboard());
+        assert_eq!((first, second), (DeviceAddress(1), DeviceAddress(2)));
+        assert_eq!(usb.device_state(first), Some(DeviceState::Default));
+        assert_eq!(usb.port_speed(0), Some(UsbSpeed::Low));
+    }
+
+    #[test]
+    fn data_endpoints_stall_until_configured() {
+        let mut usb = UsbController::default();
+        let address = enumerated(&mut usb, 0, keyboard());
+
+        usb.submit_transfer(transfer(address, 0x81, TransferType::Interrupt, vec![0; 8])).unwrap();
+        usb.tick();
+        assert_eq!(usb.poll_completed().unwrap().status, TransferStatus::Stalled);
+
+        let set_configuration = vec![0x00, REQUEST_SET_CONFIGURATION, 1, 0, 0, 0, 0, 0];
+        usb.submit_transfer(transfer(address, 0, TransferType::Control, set_configuration)).unwrap();
+        usb.submit_transfer(transfer(address, 0x81, TransferType::Interrupt, vec![0; 8])).unwrap();
+        usb.submit_transfer(transfer(address, 0x81, TransferType::Interrupt, vec![0; 9])).unwrap();
+        usb.tick();
+        assert_eq!(usb.device_state(address), Some(DeviceState::Configured));
+        assert_eq!(usb.poll_completed().unwrap().status, TransferStatus::Completed);
+        assert_eq!(usb.poll_completed().unwrap().status, TransferStatus::Completed);
+        assert_eq!(usb.poll_completed().unwrap().status, TransferStatus::Babble);
+    }
+
+    #[test]
+    fn superspeed_devices_fall_back_on_usb2_ports() {
+        let mut usb = UsbController::default();
+        let mut drive = keyboard();
+        drive.speed = UsbSpeed::Super;
+        usb.attach(3, drive).unwrap();
+        usb.tick();
+        assert_eq!(usb.port_speed(3), Some(UsbSpeed::High));
+    }
+
+    #[test]
+    fn unplugging_forgets_the_device() {
+        let mut usb = UsbController::default();
+        let address = enumerated(&mut usb, 1, keyboard());
+        usb.detach(1).unwrap();
+        usb.tick();
+        assert_eq!(usb.device_state(address), None);
+        assert_eq!(usb.submit_transfer(transfer(address, 0, TransferType::Control, vec![0; 8])), Err(IOError::DeviceNotFound));
+    }
 }
@@ -8,25 +8,28 @@ pub struct DisplayDevice {
     stats: DisplayStats,
 }
 
-struct DisplayConfig {
-    max_resolution: Resolution,
-    supported_modes: Vec<DisplayMode>,
-    features: DisplayFeatures,
+pub struct DisplayConfig {
+    pub max_resolution: Resolution,
+    pub supported_modes: Vec<DisplayMode>,
+    pub features: DisplayFeatures,
 }
 
-struct DisplayMode {
-    resolution: Resolution,
-    refresh_rate: u32,
-    bits_per_pixel: u32,
-    pixel_format: PixelFormat,
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayMode {
+    pub resolution: Resolution,
+    pub refresh_rate: u32,
+    pub bits_per_pixel: u32,
+    pub pixel_format: PixelFormat,
 }
 
-struct Resolution {
-    width: u32,
-    height: u32,
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
 }
 
-enum PixelFormat {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixelFormat {
     RGB565,
     RGB888,
     RGBA8888,
@@ -34,29 +37,36 @@ enum PixelFormat {
     BGRA8888,
 }
 
-bitflags! {
-    struct DisplayFeatures: u32 {
-        const VSYNC = 0x01;
-        const HDMI = 0x02;
-        const DISPLAYPORT = 0x04;
-        const HDR = 0x08;
-        const FREESYNC = 0x10;
-        const GSYNC = 0x20;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DisplayFeatures(u32);
+
+impl DisplayFeatures {
+    pub const VSYNC: Self = Self(0x01);
+    pub const HDMI: Self = Self(0x02);
+    pub const DISPLAYPORT: Self = Self(0x04);
+    pub const HDR: Self = Self(0x08);
+    pub const FREESYNC: Self = Self(0x10);
+    pub const GSYNC: Self = Self(0x20);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
     }
 }
 
-enum DisplayState {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisplayState {
     Off,
     Standby,
     Active,
     Error,
 }
 
-struct DisplayStats {
-    frames_displayed: u64,
-    vsync_events: u64,
-    mode_changes: u64,
-    errors: u64,
+#[derive(Debug, Clone, Default)]
+pub struct DisplayStats {
+    pub frames_displayed: u64,
+    pub vsync_events: u64,
+    pub mode_changes: u64,
+    pub errors: u64,
 }
 
 impl DisplayDevice {
@@ -120,4 +130,19 @@ impl DisplayDevice {
     pub fn get_supported_modes(&self) -> &[DisplayMode] {
         &self.config.supported_modes
     }
+
+    // One refresh of the panel; only a lit panel with vsync signals it
+    pub fn tick(&mut self) {
+        if self.state == DisplayState::Active && self.config.features.contains(DisplayFeatures::VSYNC) {
+            self.stats.vsync_events += 1;
+        }
+    }
+
+    pub fn get_state(&self) -> DisplayState {
+        self.state
+    }
+
+    pub fn get_stats(&self) -> &DisplayStats {
+        &self.stats
+    }
 }
@@ -9,7 +9,8 @@ pub struct InputDevice {
     stats: InputStats,
 }
 
-enum InputType {
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputType {
     Keyboard {
         layout: KeyboardLayout,
         has_numpad: bool,
@@ -29,14 +30,15 @@ enum InputType {
     },
 }
 
-#[derive(Clone)]
-struct InputEvent {
-    timestamp: u64,
-    event_type: EventType,
-    data: EventData,
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputEvent {
+    pub timestamp: u64,
+    pub event_type: EventType,
+    pub data: EventData,
 }
 
-enum EventType {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventType {
     KeyPress,
     KeyRelease,
     MouseMove,
@@ -47,8 +49,8 @@ enum EventType {
     TouchMove,
 }
 
-#[derive(Clone)]
-enum EventData {
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventData {
     Key {
         keycode: u16,
         modifiers: KeyModifiers,
@@ -71,17 +73,31 @@ enum EventData {
     },
 }
 
-bitflags! {
-    struct KeyModifiers: u8 {
-        const NONE = 0x00;
-        const SHIFT = 0x01;
-        const CTRL = 0x02;
-        const ALT = 0x04;
-        const META = 0x08;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KeyModifiers(u8);
+
+impl KeyModifiers {
+    pub const NONE: Self = Self(0x00);
+    pub const SHIFT: Self = Self(0x01);
+    pub const CTRL: Self = Self(0x02);
+    pub const ALT: Self = Self(0x04);
+    pub const META: Self = Self(0x08);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
     }
 }
 
-enum KeyboardLayout {
+impl std::ops::BitOr for KeyModifiers {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyboardLayout {
     US,
     UK,
     DE,
@@ -89,23 +105,24 @@ enum KeyboardLayout {
     // Add more layouts...
 }
 
-struct InputConfig {
-    poll_rate: u32,
-    buffer_size: usize,
-    features: DeviceFeatures,
+pub struct InputConfig {
+    pub poll_rate: u32,
+    pub buffer_size: usize,
 }
 
-enum DeviceState {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceState {
     Disconnected,
     Connected,
     Active,
     Error,
 }
 
-struct InputStats {
-    events_processed: u64,
-    buffer_overflows: u64,
-    errors: u64,
+#[derive(Debug, Clone, Default)]
+pub struct InputStats {
+    pub events_processed: u64,
+    pub buffer_overflows: u64,
+    pub errors: u64,
 }
 
 impl InputDevice {
@@ -138,6 +155,12 @@ impl InputDevice {
         Ok(())
     }
 
+    // The device's interrupt line: held high while events wait to be read,
+    // as a keyboard controller holds IRQ 1 while its output buffer is full
+    pub fn interrupt_asserted(&self) -> bool {
+        !self.event_queue.is_empty()
+    }
+
     pub fn set_state(&mut self, state: DeviceState) {
         self.state = state;
     }
@@ -145,4 +168,21 @@ impl InputDevice {
     pub fn get_type(&self) -> &InputType {
         &self.device_type
     }
+
+    pub fn get_state(&self) -> DeviceState {
+        self.state
+    }
+
+    pub fn get_stats(&self) -> &InputStats {
+        &self.stats
+    }
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            poll_rate: 125,
+            buffer_size: 64,
+        }
+    }
 }
@@ -2,3 +2,5 @@
 pub mod display;
 pub mod input;
 pub mod storage;
+pub mod timer;
+pub mod uart;
@@ -1,15 +1,17 @@
 use super::super::error::{IOError, IOResult};
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 
 pub struct StorageDevice {
     device_type: StorageType,
     command_queue: VecDeque<StorageCommand>,
+    sectors: HashMap<u64, Vec<u8>>, // Written sectors; the rest read as zeros
     config: StorageConfig,
     state: DeviceState,
     stats: StorageStats,
 }
 
-enum StorageType {
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageType {
     HDD {
         capacity: u64,
         rpm: u32,
@@ -28,14 +30,16 @@ enum StorageType {
     },
 }
 
-enum SSDInterface {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SSDInterface {
     SATA2,
     SATA3,
     PCIE3,
     PCIE4,
 }
 
-enum USBVersion {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum USBVersion {
     USB2,
     USB3Gen1,
     USB3Gen2,
@@ -53,37 +57,35 @@ struct StorageCommand {
 enum CommandType {
     Read,
     Write,
-    Flush,
     Trim,
-    Identify,
 }
 
 enum CommandStatus {
     Pending,
     InProgress,
     Completed,
-    Error,
 }
 
-struct StorageConfig {
-    sector_size: u32,
-    max_transfer_size: u32,
-    features: DeviceFeatures,
+pub struct StorageConfig {
+    pub sector_size: u32,
+    pub max_transfer_size: u32,
 }
 
-enum DeviceState {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceState {
     Disconnected,
     Connected,
     Active,
     Error,
 }
 
-struct StorageStats {
-    bytes_read: u64,
-    bytes_written: u64,
-    read_ops: u64,
-    write_ops: u64,
-    errors: u64,
+#[derive(Debug, Clone, Default)]
+pub struct StorageStats {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub read_ops: u64,
+    pub write_ops: u64,
+    pub errors: u64,
 }
 
 impl StorageDevice {
@@ -91,6 +93,7 @@ impl StorageDevice {
         Self {
             device_type,
             command_queue: VecDeque::new(),
+            sectors: HashMap::new(),
             config,
             state: DeviceState::Disconnected,
             stats: StorageStats::default(),
@@ -98,63 +101,172 @@ impl StorageDevice {
     }
 
     pub fn read_sectors(&mut self, lba: u64, count: u32) -> IOResult<Vec<u8>> {
-        let command = StorageCommand {
+        self.check_transfer(lba, count as u64 * self.config.sector_size as u64)?;
+        self.command_queue.push_back(StorageCommand {
             command_type: CommandType::Read,
             lba,
             sector_count: count,
             data: None,
             status: CommandStatus::Pending,
-        };
-
-        self.command_queue.push_back(command);
-        self.process_commands()?;
+        });
+        let data = self.process_commands()?;
 
         self.stats.read_ops += 1;
-        self.stats.bytes_read += (count as u64) * (self.config.sector_size as u64);
-
-        Ok(Vec::new()) // Simplified - would return actual data
+        self.stats.bytes_read += data.len() as u64;
+        Ok(data)
     }
 
     pub fn write_sectors(&mut self, lba: u64, data: &[u8]) -> IOResult<()> {
+        if !data.len().is_multiple_of(self.config.sector_size as usize) {
+            self.stats.errors += 1;
+            return Err(IOError::InvalidBufferSize);
+        }
+        self.check_transfer(lba, data.len() as u64)?;
         let sector_count = (data.len() / self.config.sector_size as usize) as u32;
-        
-        let command = StorageCommand {
+
+        self.command_queue.push_back(StorageCommand {
             command_type: CommandType::Write,
             lba,
             sector_count,
             data: Some(data.to_vec()),
             status: CommandStatus::Pending,
-        };
-
-        self.command_queue.push_back(command);
+        });
         self.process_commands()?;
 
         self.stats.write_ops += 1;
         self.stats.bytes_written += data.len() as u64;
+        Ok(())
+    }
+
+    // Drops sectors the filesystem no longer uses
+    pub fn trim(&mut self, lba: u64, count: u32) -> IOResult<()> {
+        self.check_transfer(lba, 0)?;
+        self.command_queue.push_back(StorageCommand {
+            command_type: CommandType::Trim,
+            lba,
+            sector_count: count,
+            data: None,
+            status: CommandStatus::Pending,
+        });
+        self.process_commands()?;
+        Ok(())
+    }
+
+    pub fn capacity(&self) -> u64 {
+        match self.device_type {
+            StorageType::HDD { capacity, .. }
+            | StorageType::SSD { capacity, .. }
+            | StorageType::NVMe { capacity, .. }
+            | StorageType::USB { capacity, .. } => capacity,
+        }
+    }
+
+    pub fn set_state(&mut self, state: DeviceState) {
+        self.state = state;
+    }
+
+    pub fn get_state(&self) -> DeviceState {
+        self.state
+    }
 
+    pub fn get_type(&self) -> &StorageType {
+        &self.device_type
+    }
+
+    pub fn get_stats(&self) -> &StorageStats {
+        &self.stats
+    }
+
+    fn check_transfer(&mut self, lba: u64, bytes: u64) -> IOResult<()> {
+        if self.state == DeviceState::Disconnected || self.state == DeviceState::Error {
+            self.stats.errors += 1;
+            return Err(IOError::DeviceNotReady);
+        }
+        if bytes > self.config.max_transfer_size as u64 {
+            self.stats.errors += 1;
+            return Err(IOError::InvalidBufferSize);
+        }
+        let end = lba * self.config.sector_size as u64 + bytes;
+        if end > self.capacity() {
+            self.stats.errors += 1;
+            return Err(IOError::InvalidAddress);
+        }
         Ok(())
     }
 
-    fn process_commands(&mut self) -> IOResult<()> {
-        while let Some(command) = self.command_queue.pop_front() {
+    // Runs queued commands to completion; returns what the reads produced
+    fn process_commands(&mut self) -> IOResult<Vec<u8>> {
+        let sector_size = self.config.sector_size as usize;
+        let mut output = Vec::new();
+        while let Some(mut command) = self.command_queue.pop_front() {
+            command.status = CommandStatus::InProgress;
+            let sectors = command.lba..command.lba + command.sector_count as u64;
             match command.command_type {
                 CommandType::Read => {
-                    // Handle read command
+                    for sector in sectors {
+                        match self.sectors.get(&sector) {
+                            Some(data) => output.extend_from_slice(data),
+                            None => output.resize(output.len() + sector_size, 0),
+                        }
+                    }
                 }
                 CommandType::Write => {
-                    // Handle write command
-                }
-                CommandType::Flush => {
-                    // Handle flush command
+                    let data = command.data.take().unwrap_or_default();
+                    for (sector, chunk) in sectors.zip(data.chunks(sector_size)) {
+                        self.sectors.insert(sector, chunk.to_vec());
+                    }
                 }
                 CommandType::Trim => {
-                    // Handle trim command
-                }
-                CommandType::Identify => {
-                    // Handle identify command
+                    for sector in sectors {
+                        self.sectors.remove(&sector);
+                    }
                 }
             }
+            command.status = CommandStatus::Completed;
         }
-        Ok(())
+        Ok(output)
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            sector_size: 512,
+            max_transfer_size: 128 * 1024,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ssd() -> StorageDevice {
+        let mut device = StorageDevice::new(
+            StorageType::SSD { capacity: 1 << 20, interface: SSDInterface::SATA3 },
+            StorageConfig::default(),
+        );
+        device.set_state(DeviceState::Active);
+        device
+    }
+
+    #[test]
+    fn reads_back_written_sectors() {
+        let mut device = ssd();
+        let data: Vec<u8> = (0..1024).map(|i| i as u8).collect();
+        device.write_sectors(4, &data).unwrap();
+        assert_eq!(device.read_sectors(4, 2).unwrap(), data);
+        assert_eq!(device.read_sectors(6, 1).unwrap(), vec![0; 512]);
+
+        device.trim(4, 1).unwrap();
+        assert_eq!(device.read_sectors(4, 1).unwrap(), vec![0; 512]);
+        assert_eq!(device.get_stats().bytes_written, 1024);
+    }
+
+    #[test]
+    fn rejects_transfers_past_the_end() {
+        let mut device = ssd();
+        assert_eq!(device.read_sectors(2047, 2), Err(IOError::InvalidAddress));
+        assert_eq!(device.write_sectors(0, &[0; 100]), Err(IOError::InvalidBufferSize));
     }
 }
@@ -0,0 +1,197 @@
+use crate::hardware::bus::mmio::{AccessSize, MmioDevice};
+use crate::hardware::bus::BusError;
+
+// CLINT-style machine timer: one free-running counter every hart shares, a
+// compare register per hart, and a software-interrupt bit per hart
+pub const TIMER_REGION_SIZE: u64 = 0x1_0000;
+
+// Register offsets
+pub const TIMER_MSIP: u64 = 0x0000;     // 4 bytes per hart
+pub const TIMER_MTIMECMP: u64 = 0x4000; // 8 bytes per hart
+pub const TIMER_MTIME: u64 = 0xBFF8;
+
+// mtime counts at a fixed rate, whatever the core clocks are doing
+pub const TIMEBASE_HZ: u64 = 10_000_000;
+const NS_PER_TICK: u64 = 1_000_000_000 / TIMEBASE_HZ;
+
+pub struct Timer {
+    mtime: u64,
+    mtimecmp: Vec<u64>,
+    msip: Vec<bool>,
+    carry_ns: u64, // Time that didn't make a whole tick yet
+    stats: TimerStats,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TimerStats {
+    pub compare_writes: u64,
+    pub software_interrupts: u64,
+}
+
+impl Timer {
+    pub fn new(harts: usize) -> Self {
+        Self {
+            mtime: 0,
+            // Nothing fires until software arms a compare
+            mtimecmp: vec![u64::MAX; harts],
+            msip: vec![false; harts],
+            carry_ns: 0,
+            stats: TimerStats::default(),
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.mtime = self.mtime.wrapping_add(1);
+    }
+
+    pub fn advance(&mut self, elapsed_ns: u64) {
+        let total = self.carry_ns + elapsed_ns;
+        self.mtime = self.mtime.wrapping_add(total / NS_PER_TICK);
+        self.carry_ns = total % NS_PER_TICK;
+    }
+
+    pub fn now(&self) -> u64 {
+        self.mtime
+    }
+
+    // The level of a hart's timer interrupt line; it stays up until
+    // software moves the compare past mtime
+    pub fn timer_pending(&self, hart: usize) -> bool {
+        self.mtimecmp.get(hart).is_some_and(|&compare| self.mtime >= compare)
+    }
+
+    pub fn software_pending(&self, hart: usize) -> bool {
+        self.msip.get(hart).copied().unwrap_or(false)
+    }
+
+    pub fn harts(&self) -> usize {
+        self.mtimecmp.len()
+    }
+
+    pub fn get_stats(&self) -> &TimerStats {
+        &self.stats
+    }
+
+    // Helper methods
+    // Which 64-bit register an offset falls in, and which hart it belongs to
+    fn decode(&self, offset: u64) -> Option<Register> {
+        let harts = self.harts() as u64;
+        match offset {
+            TIMER_MTIME..=0xBFFF => Some(Register::Mtime),
+            _ if (TIMER_MSIP..TIMER_MSIP + 4 * harts).contains(&offset) => Some(Register::Msip(((offset - TIMER_MSIP) / 4) as usize)),
+            _ if (TIMER_MTIMECMP..TIMER_MTIMECMP + 8 * harts).contains(&offset) => {
+                Some(Register::Mtimecmp(((offset - TIMER_MTIMECMP) / 8) as usize))
+            }
+            _ => None,
+        }
+    }
+}
+
+enum Register {
+    Msip(usize),
+    Mtimecmp(usize),
+    Mtime,
+}
+
+// 64-bit registers take a doubleword or either word half
+impl MmioDevice for Timer {
+    fn read(&mut self, offset: u64, size: AccessSize) -> Result<u64, BusError> {
+        let value = match self.decode(offset).ok_or(BusError::DeviceFault(offset))? {
+            Register::Msip(hart) => return Ok(self.msip[hart] as u64),
+            Register::Mtimecmp(hart) => self.mtimecmp[hart],
+            Register::Mtime => self.mtime,
+        };
+        Ok(value >> half_shift(offset) & size.mask())
+    }
+
+    fn write(&mut self, offset: u64, size: AccessSize, value: u64) -> Result<(), BusError> {
+        match self.decode(offset).ok_or(BusError::DeviceFault(offset))? {
+            Register::Msip(hart) => {
+                let raised = value & 1 != 0;
+                if raised && !self.msip[hart] {
+                    self.stats.software_interrupts += 1;
+                }
+                self.msip[hart] = raised;
+            }
+            Register::Mtimecmp(hart) => {
+                self.mtimecmp[hart] = merge(self.mtimecmp[hart], offset, size, value);
+                self.stats.compare_writes += 1;
+            }
+            Register::Mtime => self.mtime = merge(self.mtime, offset, size, value),
+        }
+        Ok(())
+    }
+
+    fn supports(&self, size: AccessSize) -> bool {
+        matches!(size, AccessSize::Word | AccessSize::Double)
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+fn half_shift(offset: u64) -> u64 {
+    (offset & 4) * 8
+}
+
+// A word write replaces one half of a 64-bit register
+fn merge(current: u64, offset: u64, size: AccessSize, value: u64) -> u64 {
+    let shift = half_shift(offset);
+    let mask = size.mask() << shift;
+    current & !mask | (value << shift) & mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_fires_once_mtime_reaches_it() {
+        let mut timer = Timer::new(2);
+        timer.write(TIMER_MTIMECMP + 8, AccessSize::Double, 5).unwrap();
+        assert!(!timer.timer_pending(1));
+
+        // 400 ns at 10 MHz is four ticks; the 50 ns left over carries
+        timer.advance(450);
+        assert_eq!(timer.now(), 4);
+        timer.advance(50);
+        assert!(timer.timer_pending(1));
+        assert!(!timer.timer_pending(0), "hart 0 never armed its compare");
+
+        // Moving the compare past mtime drops the line
+        timer.write(TIMER_MTIMECMP + 8, AccessSize::Double, 100).unwrap();
+        assert!(!timer.timer_pending(1));
+        assert_eq!(timer.get_stats().compare_writes, 2);
+    }
+
+    #[test]
+    fn word_halves_of_64_bit_registers() {
+        let mut timer = Timer::new(1);
+        timer.write(TIMER_MTIME, AccessSize::Word, 0xDDCC_BBAA).unwrap();
+        timer.write(TIMER_MTIME + 4, AccessSize::Word, 0x1122_3344).unwrap();
+        assert_eq!(timer.read(TIMER_MTIME, AccessSize::Double), Ok(0x1122_3344_DDCC_BBAA));
+        assert_eq!(timer.read(TIMER_MTIME + 4, AccessSize::Word), Ok(0x1122_3344));
+
+        timer.write(TIMER_MTIMECMP, AccessSize::Word, 7).unwrap();
+        assert_eq!(timer.read(TIMER_MTIMECMP, AccessSize::Double), Ok(0xFFFF_FFFF_0000_0007));
+    }
+
+    #[test]
+    fn software_interrupts_and_unknown_offsets() {
+        let mut timer = Timer::new(2);
+        timer.write(TIMER_MSIP + 4, AccessSize::Word, 1).unwrap();
+        assert!(timer.software_pending(1));
+        assert_eq!(timer.read(TIMER_MSIP + 4, AccessSize::Word), Ok(1));
+        timer.write(TIMER_MSIP + 4, AccessSize::Word, 0).unwrap();
+        assert!(!timer.software_pending(1));
+        assert_eq!(timer.get_stats().software_interrupts, 1);
+
+        // A third hart's registers don't exist
+        assert_eq!(timer.read(TIMER_MSIP + 8, AccessSize::Word), Err(BusError::DeviceFault(TIMER_MSIP + 8)));
+        assert_eq!(timer.write(TIMER_MTIMECMP + 16, AccessSize::Double, 0), Err(BusError::DeviceFault(TIMER_MTIMECMP + 16)));
+        assert!(!timer.supports(AccessSize::Byte));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::hardware::bus::mmio::{AccessSize, MmioDevice};
+use crate::hardware::bus::BusError;
+use std::collections::VecDeque;
+
+// 16550-compatible serial port: eight byte-wide registers, the first two
+// doubling as the baud divisor latch while LCR.DLAB is set
+pub const UART_REGION_SIZE: u64 = 8;
+
+// Register offsets
+pub const UART_RBR_THR: u64 = 0; // Receive buffer / transmit holding
+pub const UART_IER: u64 = 1;
+pub const UART_IIR_FCR: u64 = 2; // Interrupt identification / FIFO control
+pub const UART_LCR: u64 = 3;
+pub const UART_MCR: u64 = 4;
+pub const UART_LSR: u64 = 5;
+pub const UART_MSR: u64 = 6;
+pub const UART_SCR: u64 = 7;
+
+// IER bits
+pub const IER_RX_AVAILABLE: u8 = 1 << 0;
+pub const IER_TX_EMPTY: u8 = 1 << 1;
+
+// IIR values, highest priority first
+pub const IIR_NONE: u8 = 0x01;
+pub const IIR_RX_AVAILABLE: u8 = 0x04;
+pub const IIR_TX_EMPTY: u8 = 0x02;
+
+// LSR bits
+pub const LSR_DATA_READY: u8 = 1 << 0;
+pub const LSR_OVERRUN: u8 = 1 << 1;
+pub const LSR_TX_EMPTY: u8 = 1 << 5;
+pub const LSR_IDLE: u8 = 1 << 6;
+
+pub const LCR_DLAB: u8 = 1 << 7;
+
+const RX_FIFO_DEPTH: usize = 16;
+const CLOCK_HZ: u32 = 1_843_200;
+
+// Transmission is instant, so THR is always empty; the "THR empty"
+// interrupt fires once per write and once when it gets enabled
+pub struct Uart {
+    rx_fifo: VecDeque<u8>,
+    output: Vec<u8>,
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    divisor: u16,
+    overrun: bool,
+    tx_interrupt: bool,
+    stats: UartStats,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UartStats {
+    pub bytes_received: u64,
+    pub bytes_transmitted: u64,
+    pub overruns: u64,
+}
+
+impl Uart {
+    pub fn new() -> Self {
+        Self {
+            rx_fifo: VecDeque::new(),
+            output: Vec::new(),
+            ier: 0,
+            lcr: 0x03, // 8N1
+            mcr: 0,
+            scr: 0,
+            divisor: 12, // 9600 baud
+            overrun: false,
+            tx_interrupt: false,
+            stats: UartStats::default(),
+        }
+    }
+
+    // A byte arriving on the wire, e.g. a key typed into the terminal
+    pub fn receive(&mut self, byte: u8) {
+        if self.rx_fifo.len() == RX_FIFO_DEPTH {
+            self.overrun = true;
+            self.stats.overruns += 1;
+            return;
+        }
+        self.rx_fifo.push_back(byte);
+        self.stats.bytes_received += 1;
+    }
+
+    // Everything the driver has transmitted since the last call
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    // The level of the interrupt line
+    pub fn interrupt_pending(&self) -> bool {
+        self.interrupt_id() != IIR_NONE
+    }
+
+    pub fn baud_rate(&self) -> u32 {
+        CLOCK_HZ / (16 * self.divisor.max(1) as u32)
+    }
+
+    pub fn get_stats(&self) -> &UartStats {
+        &self.stats
+    }
+
+    // Helper methods
+    fn interrupt_id(&self) -> u8 {
+        if self.ier & IER_RX_AVAILABLE != 0 && !self.rx_fifo.is_empty() {
+            IIR_RX_AVAILABLE
+        } else if self.ier & IER_TX_EMPTY != 0 && self.tx_interrupt {
+            IIR_TX_EMPTY
+        } else {
+            IIR_NONE
+        }
+    }
+
+    fn dlab(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+}
+
+impl MmioDevice for Uart {
+    fn read(&mut self, offset: u64, _size: AccessSize) -> Result<u64, BusError> {
+        let value = match offset {
+            UART_RBR_THR if self.dlab() => self.divisor as u8,
+            UART_RBR_THR => self.rx_fifo.pop_front().unwrap_or(0),
+            UART_IER if self.dlab() => (self.divisor >> 8) as u8,
+            UART_IER => self.ier,
+            UART_IIR_FCR => {
+                // Reading the identification acknowledges a THR-empty interrupt
+                let id = self.interrupt_id();
+                if id == IIR_TX_EMPTY {
+                    self.tx_interrupt = false;
+                }
+                id | 0xC0 // FIFOs enabled
+            }
+            UART_LCR => self.lcr,
+            UART_MCR => self.mcr,
+            UART_LSR => {
+                let mut lsr = LSR_TX_EMPTY | LSR_IDLE;
+                if !self.rx_fifo.is_empty() {
+                    lsr |= LSR_DATA_READY;
+                }
+                if self.overrun {
+                    lsr |= LSR_OVERRUN;
+                    self.overrun = false;
+                }
+                lsr
+            }
+            UART_MSR => 0xB0, // CTS, DSR and DCD asserted
+            UART_SCR => self.scr,
+            _ => return Err(BusError::DeviceFault(offset)),
+        };
+        Ok(value as u64)
+    }
+
+    fn write(&mut self, offset: u64, _size: AccessSize, value: u64) -> Result<(), BusError> {
+        let value = value as u8;
+        match offset {
+            UART_RBR_THR if self.dlab() => self.divisor = (self.divisor & 0xFF00) | value as u16,
+            UART_RBR_THR => {
+                self.output.push(value);
+                self.stats.bytes_transmitted += 1;
+                self.tx_interrupt = true;
+            }
+            UART_IER if self.dlab() => self.divisor = (self.divisor & 0x00FF) | (value as u16) << 8,
+            UART_IER => {
+                if value & IER_TX_EMPTY != 0 && self.ier & IER_TX_EMPTY == 0 {
+                    self.tx_interrupt = true;
+                }
+                self.ier = value & 0x0F;
+            }
+            UART_IIR_FCR => {
+                // Bit 1 clears the receive FIFO
+                if value & 0x02 != 0 {
+                    self.rx_fifo.clear();
+                }
+            }
+            UART_LCR => self.lcr = value,
+            UART_MCR => self.mcr = value & 0x1F,
+            UART_SCR => self.scr = value,
+            UART_LSR | UART_MSR => {} // Read only; writes are ignored
+            _ => return Err(BusError::DeviceFault(offset)),
+        }
+        Ok(())
+    }
+
+    fn supports(&self, size: AccessSize) -> bool {
+        size == AccessSize::Byte
+    }
+}
+
+impl Default for Uart {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,26 @@
+#[derive(Debug, Clone, PartialEq)]
+pub enum IOError {
+    // Device errors
+    DeviceNotFound,
+    DeviceBusy,
+    DeviceNotReady,
+    Timeout,
+    LinkDown,
+    // ATA status with the error register, as reported in the task file
+    AtaError { status: u8, error: u8 },
+
+    // Buffer errors
+    BufferFull,
+    InvalidBufferSize,
+    PacketTooLarge,
+
+    // Protocol errors
+    UnsupportedMode,
+    InvalidPort,
+    InvalidRegister,
+    InvalidAddress,
+    InvalidFis,
+    DmaFault,
+}
+
+pub type IOResult<T> = Result<T, IOError>;
@@ -2,32 +2,37 @@
 
 pub mod controllers;
 pub mod devices;
+pub mod error;
 
-use super::bus::Bus;
-use super::error::{IOError, IOResult};
-use self::controllers::{NetworkController, SATAController, USBController};
-use self::devices::{DisplayDevice, InputDevice, StorageDevice};
+use self::error::{IOError, IOResult};
+use self::controllers::network::NetworkController;
+use self::controllers::usb::UsbController;
+use self::devices::display::DisplayDevice;
+use self::devices::input::InputDevice;
+use self::devices::storage::StorageDevice;
+use self::devices::uart::Uart;
+use crate::machine::description::Port;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 pub struct IOSystem {
     // Controllers
     network: NetworkController,
-    sata: SATAController,
-    usb: USBController,
+    usb: UsbController,
     
     // Devices
     displays: Vec<DisplayDevice>,
     input_devices: Vec<InputDevice>,
     storage_devices: Vec<StorageDevice>,
-    
-    // System interface
-    bus: *mut Bus,
+    uart: Rc<RefCell<Uart>>, // Console; the bus maps its registers
+    ports: Vec<PortSlot>,    // The connectors on the chassis, in the order given
     
     // State and metrics
     power_state: PowerState,
     stats: IOStats,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PowerState {
     Active,
     Idle,
@@ -35,33 +40,48 @@ pub enum PowerState {
     Sleep,
 }
 
-struct IOStats {
-    bytes_read: u64,
-    bytes_written: u64,
-    network_packets: u64,
-    usb_transfers: u64,
-    power_consumption: f32,
+// A connector, and whatever is plugged into it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PortSlot {
+    pub kind: Port,
+    pub device: Option<DeviceID>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IOStats {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub network_packets: u64,
+    pub usb_transfers: u64,
 }
 
 impl IOSystem {
-    pub fn new(bus: *mut Bus) -> Self {
+    pub fn new() -> Self {
+        Self::with_ports(&[])
+    }
+
+    pub fn with_ports(ports: &[Port]) -> Self {
         Self {
-            network: NetworkController::new(),
-            sata: SATAController::new(),
-            usb: USBController::new(),
+            network: NetworkController::default(),
+            usb: UsbController::default(),
             displays: Vec::new(),
             input_devices: Vec::new(),
             storage_devices: Vec::new(),
-            bus,
+            uart: Rc::new(RefCell::new(Uart::new())),
+            ports: ports.iter().map(|&kind| PortSlot { kind, device: None }).collect(),
             power_state: PowerState::Active,
             stats: IOStats::default(),
         }
     }
 
     pub fn tick(&mut self) {
+        // Controllers are clock gated while the system sleeps
+        if self.power_state == PowerState::Sleep {
+            return;
+        }
+
         // Update controllers
         self.network.tick();
-        self.sata.tick();
         self.usb.tick();
         
         // Update devices
@@ -71,16 +91,20 @@ impl IOSystem {
         self.update_stats();
     }
 
+    // Input and storage devices work on demand; only panels refresh
     fn update_devices(&mut self) {
         for display in &mut self.displays {
             display.tick();
         }
-        for device in &mut self.input_devices {
-            device.tick();
-        }
-        for device in &mut self.storage_devices {
-            device.tick();
-        }
+    }
+
+    fn update_stats(&mut self) {
+        let network = self.network.get_stats();
+        let usb = self.usb.get_stats();
+        self.stats.network_packets = network.packets_sent + network.packets_received;
+        self.stats.usb_transfers = usb.transfers;
+        self.stats.bytes_read = self.storage_devices.iter().map(|device| device.get_stats().bytes_read).sum();
+        self.stats.bytes_written = self.storage_devices.iter().map(|device| device.get_stats().bytes_written).sum();
     }
 
     // Device management methods
@@ -96,17 +120,52 @@ impl IOSystem {
         self.storage_devices.push(device);
     }
 
-    // IO operations
-    pub fn read(&mut self, device: DeviceID, buffer: &mut [u8]) -> IOResult<usize> {
-        // Implement read operation
-        Ok(0)
+    // The input controller's line to the interrupt controller
+    pub fn input_interrupt(&self) -> bool {
+        self.input_devices.iter().any(InputDevice::interrupt_asserted)
     }
 
-    pub fn write(&mut self, device: DeviceID, buffer: &[u8]) -> IOResult<usize> {
-        // Implement write operation
-        Ok(0)
+    pub fn set_power_state(&mut self, state: PowerState) {
+        self.power_state = state;
+    }
+
+    pub fn get_power_state(&self) -> PowerState {
+        self.power_state
+    }
+
+    pub fn get_stats(&self) -> &IOStats {
+        &self.stats
+    }
+
+    pub fn uart(&self) -> Rc<RefCell<Uart>> {
+        Rc::clone(&self.uart)
+    }
+
+    pub fn ports(&self) -> &[PortSlot] {
+        &self.ports
+    }
+
+    // Plugs a device into a free connector
+    pub fn connect(&mut self, port: usize, device: DeviceID) -> IOResult<()> {
+        let slot = self.ports.get_mut(port).ok_or(IOError::InvalidPort)?;
+        if slot.device.is_some() {
+            return Err(IOError::DeviceBusy);
+        }
+        slot.device = Some(device);
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self, port: usize) -> IOResult<DeviceID> {
+        let slot = self.ports.get_mut(port).ok_or(IOError::InvalidPort)?;
+        slot.device.take().ok_or(IOError::DeviceNotFound)
+    }
+}
+
+impl Default for IOSystem {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct DeviceID(pub u64);
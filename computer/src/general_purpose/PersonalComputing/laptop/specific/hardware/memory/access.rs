@@ -0,0 +1,82 @@
+use crate::hardware::clock::{ClockDomain, ClockGenerator};
+use crate::simulation::time::SimTime;
+
+// Where a load or store spent its time on the way down the hierarchy and
+// back. Each hop's cycles cover both directions through that level, counted
+// in the clock of the domain the level lives in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryLevel {
+    TLB,    // Translation, including the page walk on a miss
+    Bus,    // Uncached accesses to device registers
+    Device,
+    L1,
+    L2,
+    L3,
+    MemoryController,
+    DRAMBank { rank: usize, bank: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HopOutcome {
+    Hit,
+    Miss,
+    Scheduled,   // Controller front end, including any wait behind earlier requests
+    RowHit,      // Row already open in the bank's row buffer
+    RowEmpty,    // Bank precharged; activate only
+    RowConflict, // Another row open; precharge, then activate
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Load,
+    Store,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Hop {
+    pub level: MemoryLevel,
+    pub outcome: HopOutcome,
+    pub cycles: u64,
+    pub domain: ClockDomain,
+}
+
+#[derive(Debug, Clone)]
+pub struct AccessTrace {
+    pub kind: AccessKind,
+    pub address: u64,
+    pub value: u32,       // Loaded, or stored
+    pub hops: Vec<Hop>,
+    pub core_cycles: u64, // What the core waited, at the frequency it ran at
+}
+
+impl AccessTrace {
+    pub fn new(kind: AccessKind, address: u64) -> Self {
+        Self {
+            kind,
+            address,
+            value: 0,
+            hops: Vec::new(),
+            core_cycles: 0,
+        }
+    }
+
+    // The deepest level the access had to reach
+    pub fn served_by(&self) -> Option<MemoryLevel> {
+        self.hops
+            .iter()
+            .rev()
+            .find(|hop| hop.outcome != HopOutcome::Miss)
+            .map(|hop| hop.level)
+    }
+
+    // Sums every hop on the global time base, given each domain's frequency
+    pub fn time_with(&self, frequency_of: impl Fn(ClockDomain) -> u64) -> SimTime {
+        self.hops
+            .iter()
+            .fold(SimTime::ZERO, |total, hop| total + SimTime::from_cycles(hop.cycles, frequency_of(hop.domain)))
+    }
+
+    pub fn latency(&self, clock: &ClockGenerator) -> SimTime {
+        self.time_with(|domain| clock.get_frequency(domain).unwrap_or(0))
+    }
+}
@@ -1,78 +0,0 @@
-use super::stats::CacheStats;
-
-pub struct Cache {
-    name: String,
-    sets: Vec<CacheSet>,
-    ways: usize,
-    line_size: usize,
-    stats: CacheStats,
-    replacement_policy: ReplacementPolicy,
-    write_policy: WritePolicy,
-}
-
-struct CacheSet {
-    lines: Vec<CacheLine>,
-}
-
-struct CacheLine {
-    valid: bool,
-    dirty: bool,
-    tag: u64,
-    data: Vec<u8>,
-    last_access: u64,
-    access_count: u64,
-    coherency_state: CoherencyState,
-}
-
-#[derive(Clone, Copy)]
-enum ReplacementPolicy {
-    LRU,
-    PLRU,    // Pseudo-LRU
-    RRIP,    // Re-Reference Interval Prediction
-    Random,
-}
-
-#[derive(Clone, Copy)]
-enum WritePolicy {
-    WriteBack,
-    WriteThrough,
-    WriteBackWithClean,
-}
-
-#[derive(Clone, Copy)]
-enum CoherencyState {
-    Modified,
-    Exclusive,
-    Shared,
-    Invalid,
-}
-
-// src/hardware/memory/cache.rs
-impl Cache {
-    pub fn new(size: usize, ways: usize, name: &str) -> Self {
-        // ... existing initialization ...
-    }
-
-    pub fn read(&mut self, address: u64) -> CacheResult {
-        // Implement realistic cache access with:
-        // - MESI protocol handling
-        // - Cache coherency
-        // - Prefetch handling
-        // - Hit/miss prediction
-    }
-
-    pub fn write(&mut self, address: u64, data: &[u8]) -> CacheResult {
-        // Implement write with:
-        // - Write buffer management
-        // - Coherency updates
-        // - Dirty state tracking
-    }
-
-    pub fn get_visualization_data(&self) -> CacheVisualizationData {
-        // Return data for visualizing:
-        // - Cache line states
-        // - Access patterns
-        // - Hit/miss rates
-        // - Temperature/power data
-    }
-}
\ No newline at end of file
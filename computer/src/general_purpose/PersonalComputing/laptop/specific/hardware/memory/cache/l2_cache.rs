@@ -1,175 +0,0 @@
-use super::coherency::{CoherencyState, CoherencyController};
-use super::replacement::ReplacementPolicy;
-use super::stats::CacheStats;
-use super::super::error::{MemoryError, MemoryResult};
-use super::super::types::PhysicalAddress;
-
-pub struct L2Cache {
-    sets: Vec<CacheSet>,
-    ways: usize,
-    line_size: usize,
-    latency: u8,
-    inclusive: bool,  // Whether L2 is inclusive of L1
-    write_policy: WritePolicy,
-    coherency: CoherencyController,
-    stats: CacheStats,
-}
-
-struct CacheSet {
-    lines: Vec<CacheLine>,
-    replacement: ReplacementPolicy,
-}
-
-struct CacheLine {
-    valid: bool,
-    dirty: bool,
-    tag: u64,
-    data: Vec<u8>,
-    coherency_state: CoherencyState,
-    access_info: AccessInfo,
-    // Track which L1 caches have copies
-    l1_copies: Vec<bool>,
-}
-
-struct AccessInfo {
-    last_access: u64,
-    access_count: u64,
-    reuse_distance: u64,
-}
-
-#[derive(Clone, Copy)]
-enum WritePolicy {
-    WriteBack,
-    WriteThrough,
-}
-
-impl L2Cache {
-    pub fn new(size: usize, ways: usize, inclusive: bool) -> Self {
-        let sets = size / (ways * 64); // 64-byte cache lines
-        Self {
-            sets: (0..sets).map(|_| CacheSet::new(ways)).collect(),
-            ways,
-            line_size: 64,
-            latency: 12,  // ~12 cycles L2 latency
-            inclusive,
-            write_policy: WritePolicy::WriteBack,
-            coherency: CoherencyController::new(true), // Enable snooping
-            stats: CacheStats::default(),
-        }
-    }
-
-    pub fn read(&mut self, address: PhysicalAddress) -> MemoryResult<u32> {
-        let (tag, set_index, offset) = self.decode_address(address.0);
-        let set = &mut self.sets[set_index];
-
-        // Check for hit
-        for line in &mut set.lines {
-            if line.valid && line.tag == tag {
-                self.stats.hits += 1;
-                self.update_access_info(line);
-                return Ok(self.read_from_line(line, offset));
-            }
-        }
-
-        // Cache miss
-        self.stats.misses += 1;
-        self.handle_miss(address, set_index, tag, false)?;
-        
-        // Retry read after handling miss
-        let line = set.lines.iter()
-            .find(|l| l.valid && l.tag == tag)
-            .ok_or(MemoryError::CacheMiss)?;
-            
-        Ok(self.read_from_line(line, offset))
-    }
-
-    pub fn write(&mut self, address: PhysicalAddress, data: u32) -> MemoryResult<()> {
-        let (tag, set_index, offset) = self.decode_address(address.0);
-        let set = &mut self.sets[set_index];
-
-        // Check for hit
-        for line in &mut set.lines {
-            if line.valid && line.tag == tag {
-                self.stats.hits += 1;
-                self.update_access_info(line);
-                self.write_to_line(line, offset, data);
-                return Ok(());
-            }
-        }
-
-        // Cache miss
-        self.stats.misses += 1;
-        self.handle_miss(address, set_index, tag, true)?;
-        
-        // Write data after handling miss
-        let line = set.lines.iter_mut()
-            .find(|l| l.valid && l.tag == tag)
-            .ok_or(MemoryError::CacheMiss)?;
-            
-        self.write_to_line(line, offset, data);
-        Ok(())
-    }
-
-    fn handle_miss(&mut self, address: PhysicalAddress, set_index: usize, 
-                  tag: u64, is_write: bool) -> MemoryResult<()> {
-        let set = &mut self.sets[set_index];
-        
-        // Find victim line
-        let victim_way = set.replacement.get_victim(
-            &set.lines.iter().map(|l| l.valid).collect::<Vec<_>>()
-        );
-
-        let victim_line = &mut set.lines[victim_way];
-        
-        // Handle writeback if necessary
-        if victim_line.valid && victim_line.dirty {
-            self.writeback_line(victim_line)?;
-        }
-
-        // If inclusive, allocate in L2 and potentially L1
-        self.allocate_line(address, set_index, victim_way, is_write)?;
-
-        Ok(())
-    }
-
-    fn writeback_line(&mut self, line: &mut CacheLine) -> MemoryResult<()> {
-        // Write dirty data back to next level
-        // ... implementation ...
-        Ok(())
-    }
-
-    fn allocate_line(&mut self, address: PhysicalAddress, set_index: usize,
-                    way: usize, is_write: bool) -> MemoryResult<()> {
-        // Fetch data from next level
-        // ... implementation ...
-        Ok(())
-    }
-
-    // Helper methods
-    fn decode_address(&self, address: u64) -> (u64, usize, usize) {
-        let offset_bits = (self.line_size as f64).log2() as u32;
-        let index_bits = (self.sets.len() as f64).log2() as u32;
-        
-        let offset = (address & ((1 << offset_bits) - 1)) as usize;
-        let set_index = ((address >> offset_bits) & ((1 << index_bits) - 1)) as usize;
-        let tag = address >> (offset_bits + index_bits);
-
-        (tag, set_index, offset)
-    }
-
-    fn read_from_line(&self, line: &CacheLine, offset: usize) -> u32 {
-        let bytes = &line.data[offset..offset + 4];
-        u32::from_le_bytes(bytes.try_into().unwrap())
-    }
-
-    fn write_to_line(&mut self, line: &mut CacheLine, offset: usize, data: u32) {
-        line.data[offset..offset + 4].copy_from_slice(&data.to_le_bytes());
-        line.dirty = true;
-        line.coherency_state = CoherencyState::Modified;
-    }
-
-    fn update_access_info(&mut self, line: &mut CacheLine) {
-        line.access_info.last_access = self.stats.total_accesses;
-        line.access_info.access_count += 1;
-    }
-}
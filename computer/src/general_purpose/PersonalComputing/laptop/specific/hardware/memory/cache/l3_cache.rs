@@ -1,204 +0,0 @@
-use super::coherency::{CoherencyState, CoherencyController};
-use super::replacement::ReplacementPolicy;
-use super::stats::CacheStats;
-use super::super::error::{MemoryError, MemoryResult};
-use super::super::types::PhysicalAddress;
-use super::super::dram::DRAMController;
-
-pub struct L3Cache {
-    sets: Vec<CacheSet>,
-    ways: usize,
-    line_size: usize,
-    latency: u8,
-    write_policy: WritePolicy,
-    coherency: CoherencyController,
-    dram: *mut DRAMController,  // Connection to main memory
-    stats: CacheStats,
-    prefetcher: Prefetcher,
-}
-
-struct CacheSet {
-    lines: Vec<CacheLine>,
-    replacement: ReplacementPolicy,
-}
-
-struct CacheLine {
-    valid: bool,
-    dirty: bool,
-    tag: u64,
-    data: Vec<u8>,
-    coherency_state: CoherencyState,
-    access_info: AccessInfo,
-    // Track which L2 caches have copies
-    l2_copies: Vec<bool>,
-}
-
-struct AccessInfo {
-    last_access: u64,
-    access_count: u64,
-    reuse_distance: u64,
-    prefetched: bool,
-}
-
-#[derive(Clone, Copy)]
-enum WritePolicy {
-    WriteBack,
-    WriteThrough,
-}
-
-struct Prefetcher {
-    enabled: bool,
-    stride_table: HashMap<u64, i64>,  // Address -> stride pattern
-    confidence_table: HashMap<u64, u8>, // Address -> prediction confidence
-}
-
-impl L3Cache {
-    pub fn new(size: usize, ways: usize, dram: *mut DRAMController) -> Self {
-        let sets = size / (ways * 64);
-        Self {
-            sets: (0..sets).map(|_| CacheSet::new(ways)).collect(),
-            ways,
-            line_size: 64,
-            latency: 40,  // ~40 cycles L3 latency
-            write_policy: WritePolicy::WriteBack,
-            coherency: CoherencyController::new(true),
-            dram,
-            stats: CacheStats::default(),
-            prefetcher: Prefetcher::new(),
-        }
-    }
-
-    pub fn read(&mut self, address: PhysicalAddress) -> MemoryResult<u32> {
-        let (tag, set_index, offset) = self.decode_address(address.0);
-        let set = &mut self.sets[set_index];
-
-        // Check for hit
-        for line in &mut set.lines {
-            if line.valid && line.tag == tag {
-                self.stats.hits += 1;
-                self.update_access_info(line);
-                self.prefetcher.update_pattern(address.0);
-                return Ok(self.read_from_line(line, offset));
-            }
-        }
-
-        // Cache miss
-        self.stats.misses += 1;
-        self.handle_miss(address, set_index, tag, false)?;
-        
-        // Trigger prefetch on miss
-        self.prefetcher.predict_and_prefetch(address.0, self);
-        
-        // Retry read after handling miss
-        let line = set.lines.iter()
-            .find(|l| l.valid && l.tag == tag)
-            .ok_or(MemoryError::CacheMiss)?;
-            
-        Ok(self.read_from_line(line, offset))
-    }
-
-    pub fn write(&mut self, address: PhysicalAddress, data: u32) -> MemoryResult<()> {
-        let (tag, set_index, offset) = self.decode_address(address.0);
-        let set = &mut self.sets[set_index];
-
-        // Check for hit
-        for line in &mut set.lines {
-            if line.valid && line.tag == tag {
-                self.stats.hits += 1;
-                self.update_access_info(line);
-                self.write_to_line(line, offset, data);
-                return Ok(());
-            }
-        }
-
-        // Cache miss
-        self.stats.misses += 1;
-        self.handle_miss(address, set_index, tag, true)?;
-        
-        // Write data after handling miss
-        let line = set.lines.iter_mut()
-            .find(|l| l.valid && l.tag == tag)
-            .ok_or(MemoryError::CacheMiss)?;
-            
-        self.write_to_line(line, offset, data);
-        Ok(())
-    }
-
-    fn handle_miss(&mut self, address: PhysicalAddress, set_index: usize, 
-                  tag: u64, is_write: bool) -> MemoryResult<()> {
-        let set = &mut self.sets[set_index];
-        
-        // Find victim line using replacement policy
-        let victim_way = set.replacement.get_victim(
-            &set.lines.iter().map(|l| l.valid).collect::<Vec<_>>()
-        );
-
-        let victim_line = &mut set.lines[victim_way];
-        
-        // Handle writeback if necessary
-        if victim_line.valid && victim_line.dirty {
-            self.writeback_to_dram(victim_line)?;
-        }
-
-        // Fetch from DRAM
-        self.fetch_from_dram(address, set_index, victim_way)?;
-
-        Ok(())
-    }
-
-    fn writeback_to_dram(&mut self, line: &mut CacheLine) -> MemoryResult<()> {
-        // Write dirty line back to DRAM
-        unsafe {
-            (*self.dram).write(
-                PhysicalAddress(self.get_line_address(line)), 
-                &line.data
-            )?;
-        }
-        line.dirty = false;
-        Ok(())
-    }
-
-    fn fetch_from_dram(&mut self, address: PhysicalAddress, 
-                      set_index: usize, way: usize) -> MemoryResult<()> {
-        // Read line from DRAM
-        let data = unsafe {
-            (*self.dram).read_line(address)?
-        };
-        
-        let line = &mut self.sets[set_index].lines[way];
-        line.data = data;
-        line.valid = true;
-        line.dirty = false;
-        line.tag = self.get_tag(address.0);
-        
-        Ok(())
-    }
-
-    // Helper methods similar to L2Cache...
-}
-
-impl Prefetcher {
-    fn new() -> Self {
-        Self {
-            enabled: true,
-            stride_table: HashMap::new(),
-            confidence_table: HashMap::new(),
-        }
-    }
-
-    fn update_pattern(&mut self, address: u64) {
-        if !self.enabled {
-            return;
-        }
-        // Update stride pattern detection
-        // ... implementation ...
-    }
-
-    fn predict_and_prefetch(&mut self, address: u64, cache: &mut L3Cache) {
-        if !self.enabled {
-            return;
-        }
-        // Predict next addresses and initiate prefetch
-        // ... implementation ...
-    }
-}
@@ -1,153 +0,0 @@
-use super::super::error::MemoryResult;
-use super::super::types::PhysicalAddress;
-use std::collections::HashMap;
-
-pub struct Prefetcher {
-    enabled: bool,
-    strategy: PrefetchStrategy,
-    history: PrefetchHistory,
-    stats: PrefetchStats,
-}
-
-enum PrefetchStrategy {
-    Sequential,
-    Stride,
-    MarkovChain,
-    AdaptivePath,
-}
-
-struct PrefetchHistory {
-    // Stride detection
-    last_addresses: Vec<u64>,
-    stride_table: HashMap<u64, i64>,
-    confidence_table: HashMap<u64, u8>,
-    
-    // Markov chain
-    transition_table: HashMap<u64, Vec<(u64, f32)>>,
-    
-    // Adaptive path
-    path_history: Vec<u64>,
-    path_confidence: HashMap<Vec<u64>, u8>,
-}
-
-#[derive(Default)]
-struct PrefetchStats {
-    requests_issued: u64,
-    hits: u64,
-    misses: u64,
-    accuracy: f32,
-    timeliness: f32,
-}
-
-impl Prefetcher {
-    pub fn new() -> Self {
-        Self {
-            enabled: true,
-            strategy: PrefetchStrategy::Stride,
-            history: PrefetchHistory::new(),
-            stats: PrefetchStats::default(),
-        }
-    }
-
-    pub fn handle_access(&mut self, address: PhysicalAddress) {
-        if !self.enabled {
-            return;
-        }
-
-        // Update history
-        self.history.update(address.0);
-
-        // Generate prefetch candidates
-        let candidates = match self.strategy {
-            PrefetchStrategy::Sequential => self.predict_sequential(address),
-            PrefetchStrategy::Stride => self.predict_stride(address),
-            PrefetchStrategy::MarkovChain => self.predict_markov(address),
-            PrefetchStrategy::AdaptivePath => self.predict_adaptive(address),
-        };
-
-        // Issue prefetch requests
-        for candidate in candidates {
-            if let Ok(_) = self.issue_prefetch(candidate) {
-                self.stats.requests_issued += 1;
-            }
-        }
-    }
-
-    fn predict_sequential(&self, address: PhysicalAddress) -> Vec<PhysicalAddress> {
-        // Simple next-N-lines prediction
-        let mut candidates = Vec::new();
-        for i in 1..=4 {
-            candidates.push(PhysicalAddress(address.0 + i * 64));
-        }
-        candidates
-    }
-
-    fn predict_stride(&self, address: PhysicalAddress) -> Vec<PhysicalAddress> {
-        let mut candidates = Vec::new();
-        if let Some(stride) = self.history.get_confident_stride(address.0) {
-            for i in 1..=2 {
-                candidates.push(PhysicalAddress(address.0 + stride * i as i64 as u64));
-            }
-        }
-        candidates
-    }
-
-    fn predict_markov(&self, address: PhysicalAddress) -> Vec<PhysicalAddress> {
-        // Get most likely next addresses from Markov chain
-        self.history.get_likely_transitions(address.0)
-            .into_iter()
-            .map(PhysicalAddress)
-            .collect()
-    }
-
-    fn predict_adaptive(&self, address: PhysicalAddress) -> Vec<PhysicalAddress> {
-        // Use path confidence to predict next addresses
-        self.history.get_confident_paths(address.0)
-            .into_iter()
-            .map(PhysicalAddress)
-            .collect()
-    }
-
-    fn issue_prefetch(&mut self, address: PhysicalAddress) -> MemoryResult<()> {
-        // Actual prefetch implementation would go here
-        // For now just track statistics
-        Ok(())
-    }
-
-    pub fn handle_prefetch_result(&mut self, hit: bool) {
-        if hit {
-            self.stats.hits += 1;
-        } else {
-            self.stats.misses += 1;
-        }
-        self.update_accuracy();
-    }
-
-    fn update_accuracy(&mut self) {
-        let total = self.stats.hits + self.stats.misses;
-        if total > 0 {
-            self.stats.accuracy = self.stats.hits as f32 / total as f32;
-        }
-    }
-}
-
-impl PrefetchHistory {
-    fn new() -> Self {
-        Self {
-            last_addresses: Vec::new(),
-            stride_table: HashMap::new(),
-            confidence_table: HashMap::new(),
-            transition_table: HashMap::new(),
-            path_history: Vec::new(),
-            path_confidence: HashMap::new(),
-        }
-    }
-
-    fn update(&mut self, address: u64) {
-        self.update_stride(address);
-        self.update_markov(address);
-        self.update_path(address);
-    }
-
-    // Implementation of history tracking methods...
-}
@@ -4,21 +4,31 @@ pub mod queue;
 pub mod scheduler;
 pub mod timing;
 
+use super::access::{Hop, HopOutcome, MemoryLevel};
+use super::dram::bank::RowBuffer;
+use super::dram::{BankAccess, DRAMController};
 use super::error::MemoryResult;
-use super::dram::DRAMController;
 use super::types::PhysicalAddress;
+use self::queue::Command;
+use crate::hardware::clock::ClockDomain;
+
+// Request decode, scheduling and the trip back through the PHY, in memory cycles
+const FRONTEND_CYCLES: u64 = 6;
 
 pub struct MemoryController {
-    dram: *mut DRAMController,
+    dram: DRAMController,
     power_mgr: power::PowerManager,
     cmd_queue: queue::CommandQueue,
     scheduler: scheduler::CommandScheduler,
     timing: timing::TimingController,
     
     // Controller state
+    #[allow(dead_code)]
     active_banks: u32,
+    #[allow(dead_code)]
     refresh_in_progress: bool,
     power_state: PowerState,
+    channel_busy_until: u64, // Memory cycle the data bus frees up
     
     // Statistics
     stats: ControllerStats,
@@ -32,16 +42,17 @@ pub enum PowerState {
     SelfRefresh,
 }
 
-struct ControllerStats {
-    commands_processed: u64,
-    queue_full_events: u64,
-    bank_conflicts: u64,
-    refresh_cycles: u64,
-    power_state_changes: u64,
+#[derive(Debug, Clone, Default)]
+pub struct ControllerStats {
+    pub commands_processed: u64,
+    pub queue_full_events: u64,
+    pub bank_conflicts: u64,
+    pub refresh_cycles: u64,
+    pub power_state_changes: u64,
 }
 
 impl MemoryController {
-    pub fn new(dram: *mut DRAMController) -> Self {
+    pub fn new(dram: DRAMController) -> Self {
         Self {
             dram,
             power_mgr: power::PowerManager::new(),
@@ -52,30 +63,25 @@ impl MemoryController {
             active_banks: 0,
             refresh_in_progress: false,
             power_state: PowerState::Active,
+            channel_busy_until: 0,
             
             stats: ControllerStats::default(),
         }
     }
 
-    pub fn read(&mut self, address: PhysicalAddress) -> MemoryResult<u32> {
-        // Check timing constraints
-        self.timing.check_read_timing(address)?;
-        
-        // Queue read command
-        let cmd = self.scheduler.create_read_command(address);
-        self.cmd_queue.push(cmd)?;
-        
-        // Process command
-        self.process_command()
+    // Cache line fills and writebacks from the last level cache. Returns the
+    // controller's hop and the bank's, so the requester can see where the
+    // time went.
+    pub fn read_line(&mut self, address: u64, buffer: &mut [u8], now: u64) -> MemoryResult<[Hop; 2]> {
+        let start = self.schedule(now);
+        let access = self.dram.read_line(PhysicalAddress(address), buffer, start)?;
+        Ok(self.complete_line(now, start, access))
     }
 
-    pub fn write(&mut self, address: PhysicalAddress, data: u32) -> MemoryResult<()> {
-        self.timing.check_write_timing(address)?;
-        
-        let cmd = self.scheduler.create_write_command(address, data);
-        self.cmd_queue.push(cmd)?;
-        
-        self.process_command()
+    pub fn write_line(&mut self, address: u64, data: &[u8], now: u64) -> MemoryResult<[Hop; 2]> {
+        let start = self.schedule(now);
+        let access = self.dram.write_line(PhysicalAddress(address), data, start)?;
+        Ok(self.complete_line(now, start, access))
     }
 
     pub fn tick(&mut self) {
@@ -96,17 +102,68 @@ impl MemoryController {
         self.update_stats();
     }
 
-    fn process_command(&mut self) -> MemoryResult<()> {
-        // Implementation
-        Ok(())
+    // Methods for visualization system
+    pub fn dram(&self) -> &DRAMController {
+        &self.dram
     }
 
-    fn can_process_command(&self, cmd: &Command) -> bool {
+    pub fn dram_mut(&mut self) -> &mut DRAMController {
+        &mut self.dram
+    }
+
+    // Helper methods
+    // A request waits for the channel's data bus to come free
+    fn schedule(&mut self, now: u64) -> u64 {
+        self.stats.commands_processed += 1;
+        now.max(self.channel_busy_until) + FRONTEND_CYCLES
+    }
+
+    fn complete_line(&mut self, now: u64, start: u64, access: BankAccess) -> [Hop; 2] {
+        self.channel_busy_until = access.ready_cycle;
+        if access.row_buffer == RowBuffer::Conflict {
+            self.stats.bank_conflicts += 1;
+        }
+        let outcome = match access.row_buffer {
+            RowBuffer::Hit => HopOutcome::RowHit,
+            RowBuffer::Empty => HopOutcome::RowEmpty,
+            RowBuffer::Conflict => HopOutcome::RowConflict,
+        };
+        [
+            Hop {
+                level: MemoryLevel::MemoryController,
+                outcome: HopOutcome::Scheduled,
+                cycles: start - now,
+                domain: ClockDomain::Memory,
+            },
+            Hop {
+                level: MemoryLevel::DRAMBank { rank: access.rank, bank: access.bank },
+                outcome,
+                cycles: access.ready_cycle - start,
+                domain: ClockDomain::Memory,
+            },
+        ]
+    }
+
+    fn process_next_command(&mut self) {
+        if let Some(cmd) = self.cmd_queue.pop() {
+            if self.scheduler.schedule_command(&cmd).is_ok() {
+                self.stats.commands_processed += 1;
+            }
+        }
+    }
+
+    fn can_process_command(&self, _cmd: &Command) -> bool {
         // Check timing, power state, bank availability
-        true
+        self.power_state == PowerState::Active
     }
 
     fn update_stats(&mut self) {
-        // Update controller statistics
+        if self.cmd_queue.is_full() {
+            self.stats.queue_full_events += 1;
+        }
+    }
+
+    pub fn get_stats(&self) -> &ControllerStats {
+        &self.stats
     }
 }
@@ -18,6 +18,7 @@ pub enum PowerState {
     DeepPowerDown,
 }
 
+#[derive(Default)]
 struct PowerStats {
     time_in_states: [u64; 5],  // One counter per state
     state_transitions: u64,
@@ -114,3 +115,9 @@ impl PowerManager {
         self.power_budget = budget;
     }
 }
+
+impl Default for PowerManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -36,11 +36,12 @@ pub enum Priority {
     Low,
 }
 
-struct QueueStats {
-    total_commands: u64,
-    queue_full_events: u64,
-    average_latency: f32,
-    max_queue_depth: usize,
+#[derive(Debug, Clone, Default)]
+pub struct QueueStats {
+    pub total_commands: u64,
+    pub queue_full_events: u64,
+    pub average_latency: f32,
+    pub max_queue_depth: usize,
 }
 
 impl CommandQueue {
@@ -87,4 +88,14 @@ impl CommandQueue {
         vec.sort_by_key(|cmd| (std::cmp::Reverse(cmd.priority), cmd.timestamp));
         self.commands.extend(vec);
     }
+
+    pub fn get_stats(&self) -> &QueueStats {
+        &self.stats
+    }
+}
+
+impl Default for CommandQueue {
+    fn default() -> Self {
+        Self::new()
+    }
 }
@@ -5,10 +5,12 @@ use super::super::error::MemoryResult;
 pub struct CommandScheduler {
     current_cycle: u64,
     bank_states: Vec<BankState>,
+    #[allow(dead_code)]
     scheduling_policy: SchedulingPolicy,
     stats: SchedulerStats,
 }
 
+#[derive(Clone)]
 struct BankState {
     active: bool,
     active_row: Option<u32>,
@@ -17,6 +19,7 @@ struct BankState {
 }
 
 #[derive(Clone, Copy)]
+#[allow(dead_code)]
 enum SchedulingPolicy {
     FirstComeFirstServed,
     BankRoundRobin,
@@ -24,6 +27,7 @@ enum SchedulingPolicy {
     PowerAware,
 }
 
+#[derive(Default)]
 struct SchedulerStats {
     row_hits: u64,
     row_misses: u64,
@@ -69,12 +73,11 @@ impl CommandScheduler {
         self.stats.total_commands += 1;
         
         let bank_id = self.get_bank_id(cmd.address);
+        let row = self.get_row(cmd.address);
         let bank = &mut self.bank_states[bank_id];
 
         match cmd.cmd_type {
             CommandType::Read | CommandType::Write => {
-                let row = self.get_row(cmd.address);
-                
                 if !bank.active {
                     // Need to activate first
                     self.stats.row_misses += 1;
@@ -112,6 +115,12 @@ impl CommandScheduler {
     }
 }
 
+impl Default for CommandScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BankState {
     fn new() -> Self {
         Self {
@@ -2,6 +2,8 @@ use super::super::error::{MemoryError, MemoryResult};
 use super::super::types::PhysicalAddress;
 use std::collections::HashMap;
 
+#[allow(non_snake_case)] // JEDEC names for the timing parameters
+#[allow(dead_code)]
 pub struct TimingController {
     // Timing parameters (in cycles)
     tCK: u32,  // Clock cycle time
@@ -25,6 +27,7 @@ pub struct TimingController {
     stats: TimingStats,
 }
 
+#[derive(Default)]
 struct TimingStats {
     timing_violations: u64,
     total_checks: u64,
@@ -120,3 +123,9 @@ impl TimingController {
         *self.stats.violations_by_type.entry(timing_param).or_insert(0) += 1;
     }
 }
+
+impl Default for TimingController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
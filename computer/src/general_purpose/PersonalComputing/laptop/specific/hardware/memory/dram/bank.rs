@@ -1,13 +1,43 @@
-use super::stats::BankStats;
-
 /// Represents a single DRAM bank with rows and columns
 pub struct MemoryBank {
     id: usize,
     rows: Vec<Row>,
     active_row: Option<usize>,
+    timings: BankTimings,
     stats: BankStats,
 }
 
+/// Row timings in memory cycles, as a machine description gives them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BankTimings {
+    pub cl: u64,   // Column access (CAS) latency
+    pub trcd: u64, // Activate to column command
+    pub trp: u64,  // Precharge
+}
+
+/// What an access found in the bank's row buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowBuffer {
+    Hit,
+    Empty,
+    Conflict,
+}
+
+/// What the bank has seen since power-on
+#[derive(Debug, Clone, Default)]
+pub struct BankStats {
+    pub row_activations: u64,
+    pub row_conflicts: u64,
+    pub row_hits: u64,
+    pub precharges: u64,
+    pub refreshes: u64,
+}
+
+/// A 64-bit channel moves 8 bytes per beat, two beats per clock
+const BURST_BYTES_PER_CYCLE: u64 = 16;
+
+const COLUMNS: usize = 1024;
+
 struct Row {
     cells: Vec<u8>,
     last_access: u64,
@@ -18,15 +48,49 @@ impl MemoryBank {
     pub fn new(id: usize) -> Self {
         Self {
             id,
-            // Modern DRAM typically has thousands of rows
+            // Modern DRAM typically has thousands of rows. Their cells are
+            // allocated on first touch, so big geometries stay cheap.
             rows: (0..8192).map(|_| Row {
-                cells: vec![0; 1024], // 1024 columns per row
+                cells: Vec::new(),
                 last_access: 0,
                 access_count: 0,
             }).collect(),
             active_row: None,
-            stats: BankStats::new(),
+            timings: BankTimings::default(),
+            stats: BankStats::default(),
+        }
+    }
+
+    /// Whether a row is open in the row buffer
+    pub fn is_active(&self) -> bool {
+        self.active_row.is_some()
+    }
+
+    /// Open a row ahead of an access; returns the cycle it's ready
+    pub fn activate(&mut self, row: usize, current_cycle: u64) -> u64 {
+        self.activate_row(row, current_cycle)
+    }
+
+    /// Close the open row; returns the cycle the bank can activate again
+    pub fn precharge(&mut self, current_cycle: u64) -> u64 {
+        if self.active_row.take().is_none() {
+            return current_cycle;
         }
+        self.stats.precharges += 1;
+        current_cycle + self.timings.trp
+    }
+
+    /// Refreshing needs the bank precharged, so the row buffer ends up empty
+    pub fn refresh_row(&mut self, row: usize) {
+        self.active_row = None;
+        self.stats.refreshes += 1;
+        if let Some(row) = self.rows.get_mut(row) {
+            row.access_count = 0;
+        }
+    }
+
+    pub fn get_stats(&self) -> &BankStats {
+        &self.stats
     }
 
     /// Simulate row activation delay and power usage
@@ -39,7 +103,7 @@ impl MemoryBank {
                 self.stats.row_conflicts += 1;
                 // Precharge delay + Activation delay
                 self.active_row = Some(row);
-                current_cycle + 24 // tRP + tRCD in memory cycles
+                current_cycle + self.timings.trp + self.timings.trcd
             } else {
                 // Row buffer hit
                 self.stats.row_hits += 1;
@@ -48,26 +112,73 @@ impl MemoryBank {
         } else {
             // No row active, just activation delay
             self.active_row = Some(row);
-            current_cycle + 14 // tRCD in memory cycles
+            current_cycle + self.timings.trcd
         }
     }
 
     /// Read data from the bank, handling row activation
     pub fn read(&mut self, row: usize, col: usize, current_cycle: u64) -> (u8, u64) {
         let ready_cycle = self.activate_row(row, current_cycle);
-        let data = self.rows[row].cells[col];
+        let data = self.row_mut(row).cells[col];
         self.rows[row].access_count += 1;
         self.rows[row].last_access = current_cycle;
-        (data, ready_cycle + 4) // Add CAS latency
+        (data, ready_cycle + self.timings.cl) // Add CAS latency
     }
 
     /// Write data to the bank, handling row activation
     pub fn write(&mut self, row: usize, col: usize, data: u8, current_cycle: u64) -> u64 {
         let ready_cycle = self.activate_row(row, current_cycle);
-        self.rows[row].cells[col] = data;
+        self.row_mut(row).cells[col] = data;
+        self.rows[row].access_count += 1;
+        self.rows[row].last_access = current_cycle;
+        ready_cycle + self.timings.cl // Add CAS latency
+    }
+
+    /// Read a run of bytes from one row with a single activation, as a
+    /// cache line fill does. Returns the row buffer state the access found
+    /// and the cycle the last beat arrives.
+    pub fn read_burst(&mut self, row: usize, col: usize, buffer: &mut [u8], current_cycle: u64) -> (RowBuffer, u64) {
+        let state = self.row_buffer(row);
+        let ready_cycle = self.activate_row(row, current_cycle);
+        buffer.copy_from_slice(&self.row_mut(row).cells[col..col + buffer.len()]);
         self.rows[row].access_count += 1;
         self.rows[row].last_access = current_cycle;
-        ready_cycle + 4 // Add CAS latency
+        (state, ready_cycle + self.timings.cl + Self::burst_cycles(buffer.len()))
+    }
+
+    /// Write a run of bytes into one row with a single activation
+    pub fn write_burst(&mut self, row: usize, col: usize, data: &[u8], current_cycle: u64) -> (RowBuffer, u64) {
+        let state = self.row_buffer(row);
+        let ready_cycle = self.activate_row(row, current_cycle);
+        self.row_mut(row).cells[col..col + data.len()].copy_from_slice(data);
+        self.rows[row].access_count += 1;
+        self.rows[row].last_access = current_cycle;
+        (state, ready_cycle + self.timings.cl + Self::burst_cycles(data.len()))
+    }
+
+    /// Row buffer state an access to `row` would find, without changing it
+    pub fn row_buffer(&self, row: usize) -> RowBuffer {
+        match self.active_row {
+            Some(active) if active == row => RowBuffer::Hit,
+            Some(_) => RowBuffer::Conflict,
+            None => RowBuffer::Empty,
+        }
+    }
+
+    pub fn set_timings(&mut self, timings: BankTimings) {
+        self.timings = timings;
+    }
+
+    fn row_mut(&mut self, row: usize) -> &mut Row {
+        let row = &mut self.rows[row];
+        if row.cells.is_empty() {
+            row.cells.resize(COLUMNS, 0);
+        }
+        row
+    }
+
+    fn burst_cycles(bytes: usize) -> u64 {
+        (bytes as u64).div_ceil(BURST_BYTES_PER_CYCLE)
     }
 
     /// Get visualization data for this bank
@@ -89,4 +200,10 @@ pub struct BankVisualizationData {
     pub active_row: Option<usize>,
     pub row_activity: Vec<(u64, u64)>, // (last_access, access_count)
     pub stats: BankStats,
-} 
\ No newline at end of file
+} 
+
+impl Default for BankTimings {
+    fn default() -> Self {
+        Self { cl: 4, trcd: 14, trp: 10 }
+    }
+}
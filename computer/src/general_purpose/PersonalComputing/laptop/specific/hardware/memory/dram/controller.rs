@@ -1,11 +1,10 @@
-use super::bank::MemoryBank;
-use super::timing::TimingController;
+use super::bank::{BankTimings, MemoryBank, RowBuffer};
+use super::power::PowerController;
+use super::rank::Rank;
 use super::refresh::RefreshController;
-use super::temperature::TempSensor;
-use super::voltage::VoltageController;
+use super::temperature::ThermalController;
 use super::super::error::{MemoryError, MemoryResult};
 use super::super::types::PhysicalAddress;
-use std::collections::VecDeque;
 
 pub struct DRAMController {
     // Memory organization
@@ -15,153 +14,100 @@ pub struct DRAMController {
     col_size: usize,
 
     // Controllers
-    timing: TimingController,
     refresh: RefreshController,
     power: PowerController,
     thermal: ThermalController,
 
-    // Command queue
-    pending_commands: VecDeque<MemoryCommand>,
-    active_commands: Vec<MemoryCommand>,
-
     // Statistics
     stats: DRAMStats,
 }
 
-struct Rank {
-    banks: Vec<MemoryBank>,
-    temperature: TempSensor,
-    voltage: VoltageController,
-    power_state: PowerState,
-}
-
-#[derive(Clone)]
-struct MemoryCommand {
-    cmd_type: CommandType,
-    address: PhysicalAddress,
-    rank: usize,
-    bank: usize,
-    row: usize,
-    col: usize,
-    cycles_remaining: u32,
-    priority: CommandPriority,
-}
-
-#[derive(Clone, Copy)]
-enum CommandType {
-    Activate,
-    Read,
-    Write,
-    Precharge,
-    Refresh,
-    PowerDown,
-    PowerUp,
-}
-
-#[derive(Clone, Copy)]
-enum CommandPriority {
-    Urgent,
-    High,
-    Normal,
-    Low,
+// Where a line access landed and how its bank treated it
+#[derive(Debug, Clone, Copy)]
+pub struct BankAccess {
+    pub rank: usize,
+    pub bank: usize,
+    pub row_buffer: RowBuffer,
+    pub ready_cycle: u64,
 }
 
-#[derive(Clone, Copy)]
-enum PowerState {
-    Active,
-    Precharge,
-    PowerDown,
-    SelfRefresh,
+#[derive(Debug, Clone, Default)]
+pub struct DRAMStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub cycles: u64,
 }
 
 impl DRAMController {
     pub fn new(ranks: usize, banks_per_rank: usize) -> Self {
         Self {
-            ranks: (0..ranks).map(|_| Rank::new(banks_per_rank)).collect(),
+            ranks: (0..ranks).map(|id| Rank::new(id, banks_per_rank)).collect(),
             banks_per_rank,
             row_size: 8192,  // 8K rows
             col_size: 1024,  // 1K columns
-            timing: TimingController::new(),
-            refresh: RefreshController::new(),
+            refresh: RefreshController::new(ranks, banks_per_rank),
             power: PowerController::new(),
             thermal: ThermalController::new(),
-            pending_commands: VecDeque::new(),
-            active_commands: Vec::new(),
             stats: DRAMStats::default(),
         }
     }
 
-    pub fn read(&mut self, address: PhysicalAddress) -> MemoryResult<Vec<u8>> {
-        let (rank_id, bank_id, row, col) = self.decode_address(address);
-        
-        // Check timing constraints
-        if !self.timing.can_read(rank_id, bank_id) {
-            return Err(MemoryError::TimingViolation);
-        }
-
-        // Create and queue read command
-        let cmd = MemoryCommand {
-            cmd_type: CommandType::Read,
-            address,
-            rank: rank_id,
-            bank: bank_id,
-            row,
-            col,
-            cycles_remaining: self.timing.get_read_latency(),
-            priority: CommandPriority::Normal,
-        };
-
-        self.queue_command(cmd)?;
+    // Moves a cache line in or out of the bank that holds it, with the
+    // bank's row buffer deciding the latency. A line never crosses a row.
+    pub fn read_line(&mut self, address: PhysicalAddress, buffer: &mut [u8], current_cycle: u64) -> MemoryResult<BankAccess> {
+        let (rank, bank, row, col) = self.locate(address, buffer.len())?;
+        let (row_buffer, ready_cycle) = self.bank(rank, bank).read_burst(row, col, buffer, current_cycle);
         self.stats.reads += 1;
+        Ok(BankAccess { rank, bank, row_buffer, ready_cycle })
+    }
 
-        // Return data (in real implementation would wait for command completion)
-        Ok(vec![0; 64])  // Return cache line size of data
+    pub fn write_line(&mut self, address: PhysicalAddress, data: &[u8], current_cycle: u64) -> MemoryResult<BankAccess> {
+        let (rank, bank, row, col) = self.locate(address, data.len())?;
+        let (row_buffer, ready_cycle) = self.bank(rank, bank).write_burst(row, col, data, current_cycle);
+        self.stats.writes += 1;
+        Ok(BankAccess { rank, bank, row_buffer, ready_cycle })
     }
 
-    pub fn write(&mut self, address: PhysicalAddress, data: &[u8]) -> MemoryResult<()> {
-        let (rank_id, bank_id, row, col) = self.decode_address(address);
-        
-        if !self.timing.can_write(rank_id, bank_id) {
-            return Err(MemoryError::TimingViolation);
+    pub fn set_timings(&mut self, timings: BankTimings) {
+        for rank in &mut self.ranks {
+            for bank in 0..self.banks_per_rank {
+                if let Some(bank) = rank.get_bank_mut(bank) {
+                    bank.set_timings(timings);
+                }
+            }
         }
+    }
 
-        let cmd = MemoryCommand {
-            cmd_type: CommandType::Write,
-            address,
-            rank: rank_id,
-            bank: bank_id,
-            row,
-            col,
-            cycles_remaining: self.timing.get_write_latency(),
-            priority: CommandPriority::Normal,
-        };
-
-        self.queue_command(cmd)?;
-        self.stats.writes += 1;
-
-        Ok(())
+    pub fn capacity(&self) -> u64 {
+        (self.ranks.len() * self.banks_per_rank * self.row_size * self.col_size) as u64
     }
 
     pub fn tick(&mut self) {
         // Process refresh
-        self.refresh.tick();
-        if self.refresh.needs_refresh() {
-            self.issue_refresh();
-        }
-
-        // Process commands
-        self.process_commands();
+        self.refresh.tick(&mut self.ranks, self.stats.cycles);
 
         // Update power state
         self.power.update_state(&self.ranks);
 
         // Monitor temperature
-        self.thermal.update(&self.ranks);
+        self.thermal.update(&self.ranks, &self.power);
+        let package_temp = self.thermal.get_temperature();
+        for rank in &mut self.ranks {
+            rank.update(package_temp);
+        }
 
         // Update statistics
         self.stats.cycles += 1;
     }
 
+    pub fn get_temperature(&self) -> f32 {
+        self.thermal.get_temperature()
+    }
+
+    pub fn get_stats(&self) -> &DRAMStats {
+        &self.stats
+    }
+
     fn decode_address(&self, address: PhysicalAddress) -> (usize, usize, usize, usize) {
         let addr = address.0;
         let col_bits = (self.col_size as f64).log2() as u32;
@@ -176,51 +122,15 @@ impl DRAMController {
         (rank, bank, row, col)
     }
 
-    fn queue_command(&mut self, cmd: MemoryCommand) -> MemoryResult<()> {
-        if self.pending_commands.len() >= 32 {
-            return Err(MemoryError::CommandQueueFull);
-        }
-        self.pending_commands.push_back(cmd);
-        Ok(())
-    }
-
-    fn process_commands(&mut self) {
-        // Process active commands
-        self.active_commands.retain_mut(|cmd| {
-            if cmd.cycles_remaining > 0 {
-                cmd.cycles_remaining -= 1;
-                true
-            } else {
-                self.complete_command(cmd);
-                false
-            }
-        });
-
-        // Issue new commands if possible
-        while let Some(cmd) = self.pending_commands.pop_front() {
-            if self.can_issue_command(&cmd) {
-                self.active_commands.push(cmd);
-            } else {
-                self.pending_commands.push_front(cmd);
-                break;
-            }
-        }
-    }
-
-    fn can_issue_command(&self, cmd: &MemoryCommand) -> bool {
-        // Check timing constraints
-        match cmd.cmd_type {
-            CommandType::Read => self.timing.can_read(cmd.rank, cmd.bank),
-            CommandType::Write => self.timing.can_write(cmd.rank, cmd.bank),
-            _ => true,
+    fn locate(&self, address: PhysicalAddress, length: usize) -> MemoryResult<(usize, usize, usize, usize)> {
+        let (rank, bank, row, col) = self.decode_address(address);
+        if rank >= self.ranks.len() || col + length > self.col_size {
+            return Err(MemoryError::AddressOutOfRange);
         }
+        Ok((rank, bank, row, col))
     }
 
-    fn complete_command(&mut self, cmd: &MemoryCommand) {
-        match cmd.cmd_type {
-            CommandType::Read => self.stats.completed_reads += 1,
-            CommandType::Write => self.stats.completed_writes += 1,
-            _ => {},
-        }
+    fn bank(&mut self, rank: usize, bank: usize) -> &mut MemoryBank {
+        self.ranks[rank].get_bank_mut(bank).expect("located banks exist")
     }
-} 
\ No newline at end of file
+}
@@ -1,8 +1,13 @@
 // Export all modules in dram
 pub mod bank;
+pub mod controller;
 pub mod ecc;
+pub mod power;
 pub mod rank;
 pub mod refresh;
 pub mod temperature;
 pub mod timing;
 pub mod voltage;
+
+pub use self::bank::BankTimings;
+pub use self::controller::{BankAccess, DRAMController};
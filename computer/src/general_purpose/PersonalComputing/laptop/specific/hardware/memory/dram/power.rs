@@ -1,4 +1,3 @@
-use super::bank::MemoryBank;
 use super::rank::Rank;
 
 pub struct PowerController {
@@ -28,6 +27,7 @@ pub enum PowerState {
     SelfRefresh,
 }
 
+#[derive(Default)]
 struct PowerStats {
     cycles_active: u64,
     cycles_idle: u64,
@@ -121,4 +121,10 @@ impl PowerController {
     pub fn get_temperature_impact(&self) -> f32 {
         self.current_power * self.power_to_temp_factor
     }
-} 
\ No newline at end of file
+} 
+
+impl Default for PowerController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
\ No newline at end of file
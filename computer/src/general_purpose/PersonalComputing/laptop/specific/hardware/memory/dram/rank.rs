@@ -4,6 +4,7 @@ use super::voltage::VoltageController;
 use super::power::PowerState;
 
 pub struct Rank {
+    #[allow(dead_code)]
     id: usize,
     banks: Vec<MemoryBank>,
     temperature: TempSensor,
@@ -14,37 +15,40 @@ pub struct Rank {
     active_banks: u32,
     last_activate: u64,
     last_precharge: u64,
+    refresh_derating: f32,
     
     // Statistics
     stats: RankStats,
 }
 
-struct RankStats {
-    total_activates: u64,
-    total_precharges: u64,
-    bank_conflicts: u64,
-    power_state_transitions: u64,
-    cycles_active: u64,
-    cycles_idle: u64,
-    cycles_power_down: u64,
+#[derive(Debug, Clone, Default)]
+pub struct RankStats {
+    pub total_activates: u64,
+    pub total_precharges: u64,
+    pub bank_conflicts: u64,
+    pub power_state_transitions: u64,
+    pub cycles_active: u64,
+    pub cycles_idle: u64,
+    pub cycles_power_down: u64,
 }
 
 impl Rank {
     pub fn new(id: usize, num_banks: usize) -> Self {
         Self {
             id,
-            banks: (0..num_banks).map(|id| MemoryBank::new(id)).collect(),
+            banks: (0..num_banks).map(MemoryBank::new).collect(),
             temperature: TempSensor::new(),
             voltage: VoltageController::new(),
             power_state: PowerState::Active,
             active_banks: 0,
             last_activate: 0,
             last_precharge: 0,
+            refresh_derating: 1.0,
             stats: RankStats::default(),
         }
     }
 
-    pub fn activate_bank(&mut self, bank_id: usize, current_cycle: u64) -> Result<(), RankError> {
+    pub fn activate_bank(&mut self, bank_id: usize, row: usize, current_cycle: u64) -> Result<(), RankError> {
         if bank_id >= self.banks.len() {
             return Err(RankError::InvalidBank);
         }
@@ -61,7 +65,7 @@ impl Rank {
         }
 
         // Activate the bank
-        self.banks[bank_id].activate(current_cycle)?;
+        self.banks[bank_id].activate(row, current_cycle);
         self.active_banks += 1;
         self.last_activate = current_cycle;
         self.stats.total_activates += 1;
@@ -75,22 +79,17 @@ impl Rank {
         }
 
         // Precharge the bank
-        self.banks[bank_id].precharge(current_cycle)?;
-        self.active_banks -= 1;
+        self.banks[bank_id].precharge(current_cycle);
+        self.active_banks = self.active_banks.saturating_sub(1);
         self.last_precharge = current_cycle;
         self.stats.total_precharges += 1;
 
         Ok(())
     }
 
-    pub fn update(&mut self, current_cycle: u64) {
-        // Update all banks
-        for bank in &mut self.banks {
-            bank.update(current_cycle);
-        }
-
+    pub fn update(&mut self, package_temp: f32) {
         // Update temperature and voltage
-        self.temperature.update();
+        self.temperature.update(package_temp);
         self.voltage.update();
 
         // Update power state
@@ -160,22 +159,22 @@ impl Rank {
         }
 
         // Perform refresh operation
-        self.banks[bank_id].refresh_row(row)?;
+        self.banks[bank_id].refresh_row(row as usize);
         
         Ok(())
     }
 
     pub fn refresh_all(&mut self, row: u32) -> Result<(), RankError> {
         // Precharge all banks if needed
-        for (bank_id, bank) in self.banks.iter().enumerate() {
-            if bank.is_active() {
+        for bank_id in 0..self.banks.len() {
+            if self.banks[bank_id].is_active() {
                 self.precharge_bank(bank_id, self.last_precharge)?;
             }
         }
 
         // Refresh all banks simultaneously
         for bank in &mut self.banks {
-            bank.refresh_row(row)?;
+            bank.refresh_row(row as usize);
         }
 
         Ok(())
@@ -195,13 +194,13 @@ impl Rank {
     }
 
     fn adjust_timing_parameters(&mut self, temperature: f32) {
-        // Adjust timing parameters based on temperature
         // Higher temperatures require more frequent refreshes
-        let temp_factor = (temperature - 85.0).max(0.0) / 10.0; // Adjust every 10°C above 85°C
-        
-        for bank in &mut self.banks {
-            bank.adjust_refresh_rate(temp_factor);
-        }
+        self.refresh_derating = 1.0 + (temperature - 85.0).max(0.0) / 10.0; // Adjust every 10°C above 85°C
+    }
+
+    // How many times the nominal refresh rate the rank needs at its temperature
+    pub fn get_refresh_derating(&self) -> f32 {
+        self.refresh_derating
     }
 
     pub fn enter_power_down(&mut self) -> Result<(), RankError> {
@@ -1,7 +1,14 @@
 use super::rank::Rank;
 use std::collections::VecDeque;
 
+// Rows in every bank, refreshed in turn
+const ROWS: u32 = 8192;
+
 pub struct RefreshController {
+    // Geometry refreshes are spread over
+    ranks: usize,
+    banks_per_rank: usize,
+
     // Basic refresh parameters
     refresh_interval: u32,    // Cycles between refreshes
     rows_per_refresh: u32,    // Number of rows to refresh at once
@@ -26,21 +33,22 @@ struct RefreshCommand {
     bank: u32,
     rank: u32,
     deadline: u64,
-    temperature_triggered: bool,
 }
 
-#[derive(Default)]
-struct RefreshStats {
-    total_refreshes: u64,
-    refresh_cycles: u64,
-    delayed_refreshes: u64,
-    temperature_triggered_refreshes: u64,
-    refresh_energy: f32,
+#[derive(Debug, Clone, Default)]
+pub struct RefreshStats {
+    pub total_refreshes: u64,
+    pub refresh_cycles: u64,
+    pub delayed_refreshes: u64,
+    pub temperature_triggered_refreshes: u64,
+    pub refresh_energy: f32,
 }
 
 impl RefreshController {
     pub fn new(ranks: usize, banks_per_rank: usize) -> Self {
         Self {
+            ranks,
+            banks_per_rank,
             refresh_interval: 7800,     // 64ms/8192 rows ≈ 7.8μs at 1ns cycle time
             rows_per_refresh: 1,        // Refresh one row at a time
             current_row: 0,
@@ -86,54 +94,42 @@ impl RefreshController {
     fn schedule_refreshes(&mut self, current_cycle: u64) {
         let deadline = current_cycle + self.refresh_interval as u64;
         
-        if self.distributed_refresh {
-            // Schedule multiple smaller refreshes
-            for i in 0..8 {
-                self.pending_refreshes.push_back(RefreshCommand {
-                    row: self.current_row + i,
-                    bank: (self.current_row % 8) as u32,
-                    rank: 0,
-                    deadline,
-                    temperature_triggered: false,
-                });
+        for row in self.current_row..self.current_row + self.rows_per_refresh {
+            for rank in 0..self.ranks as u32 {
+                if self.distributed_refresh {
+                    // One smaller refresh per bank
+                    for bank in 0..self.banks_per_rank as u32 {
+                        self.pending_refreshes.push_back(RefreshCommand { row, bank, rank, deadline });
+                    }
+                } else {
+                    // One large refresh per rank
+                    self.pending_refreshes.push_back(RefreshCommand { row, bank: 0, rank, deadline });
+                }
             }
-        } else {
-            // Schedule one large refresh
-            self.pending_refreshes.push_back(RefreshCommand {
-                row: self.current_row,
-                bank: 0,
-                rank: 0,
-                deadline,
-                temperature_triggered: false,
-            });
         }
         
+        self.current_row = (self.current_row + self.rows_per_refresh) % ROWS;
         self.cycles_since_refresh = 0;
     }
 
+    // Issues refreshes in order until a rank can't take one this cycle
     fn process_refreshes(&mut self, ranks: &mut [Rank], current_cycle: u64) {
         while let Some(cmd) = self.pending_refreshes.front() {
+            let issued = match ranks.get_mut(cmd.rank as usize) {
+                Some(rank) if self.per_bank_refresh && self.distributed_refresh => rank.refresh_bank(cmd.bank as usize, cmd.row).is_ok(),
+                Some(rank) => rank.refresh_all(cmd.row).is_ok(),
+                None => true, // Nothing there to refresh
+            };
+            if !issued {
+                break;
+            }
             if current_cycle > cmd.deadline {
                 self.stats.delayed_refreshes += 1;
             }
-            
-            // Try to issue refresh
-            if let Some(rank) = ranks.get_mut(cmd.rank as usize) {
-                if self.per_bank_refresh {
-                    // Refresh specific bank
-                    if let Ok(_) = rank.refresh_bank(cmd.bank as usize, cmd.row) {
-                        self.pending_refreshes.pop_front();
-                        self.stats.total_refreshes += 1;
-                    }
-                } else {
-                    // Refresh entire rank
-                    if let Ok(_) = rank.refresh_all(cmd.row) {
-                        self.pending_refreshes.pop_front();
-                        self.stats.total_refreshes += 1;
-                    }
-                }
-            }
+            self.pending_refreshes.pop_front();
+            self.stats.total_refreshes += 1;
         }
+        self.refresh_in_progress = !self.pending_refreshes.is_empty();
     }
 
     pub fn handle_temperature_alert(&mut self, rank: usize, bank: usize, current_cycle: u64) {
@@ -143,8 +139,11 @@ impl RefreshController {
             bank: bank as u32,
             rank: rank as u32,
             deadline: current_cycle,
-            temperature_triggered: true,
         });
         self.stats.temperature_triggered_refreshes += 1;
     }
+
+    pub fn get_stats(&self) -> &RefreshStats {
+        &self.stats
+    }
 }
@@ -1,6 +1,40 @@
 use super::rank::Rank;
 use super::power::PowerController;
 
+// One rank's on-die sensor
+pub struct TempSensor {
+    temperature: f32,
+    critical_threshold: f32,
+}
+
+impl TempSensor {
+    pub fn new() -> Self {
+        Self {
+            temperature: 45.0,
+            critical_threshold: 95.0,
+        }
+    }
+
+    // Reads the package temperature the thermal controller tracks
+    pub fn update(&mut self, package_temp: f32) {
+        self.temperature = package_temp;
+    }
+
+    pub fn get_temperature(&self) -> f32 {
+        self.temperature
+    }
+
+    pub fn get_critical_threshold(&self) -> f32 {
+        self.critical_threshold
+    }
+}
+
+impl Default for TempSensor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct ThermalController {
     // Temperature thresholds (°C)
     warning_threshold: f32,
@@ -20,6 +54,7 @@ pub struct ThermalController {
     stats: ThermalStats,
 }
 
+#[derive(Default)]
 struct ThermalStats {
     max_temperature: f32,
     time_above_warning: u64,
@@ -37,7 +72,7 @@ impl ThermalController {
             
             current_temp: 45.0,    // Start at reasonable temperature
             ambient_temp: 25.0,    // Room temperature
-            thermal_resistance: 0.5, // °C/W
+            thermal_resistance: 5.0, // °C/W
             
             throttling_active: false,
             throttle_percentage: 0.0,
@@ -47,11 +82,11 @@ impl ThermalController {
     }
 
     pub fn update(&mut self, ranks: &[Rank], power: &PowerController) {
-        // Calculate new temperature based on power consumption and thermal resistance
-        let power_temp = power.get_temperature_impact();
-        let cooling_factor = (self.current_temp - self.ambient_temp) * 0.001;
-        
-        self.current_temp = self.current_temp + power_temp - cooling_factor;
+        // The package settles where the ranks' and the controller's power,
+        // through the thermal resistance, holds it above ambient
+        let watts = power.get_current_power() + ranks.iter().map(Rank::get_power_consumption).sum::<f32>();
+        let settled = self.ambient_temp + watts * self.thermal_resistance;
+        self.current_temp += (settled - self.current_temp) * 0.001;
         
         // Update statistics
         self.stats.max_temperature = self.stats.max_temperature.max(self.current_temp);
@@ -101,3 +136,9 @@ impl ThermalController {
         self.throttle_percentage
     }
 }
+
+impl Default for ThermalController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 
+#[allow(non_snake_case)] // JEDEC names for the timing parameters
+#[allow(dead_code)]
 pub struct TimingController {
     // Timing parameters (in cycles)
     tCK: u32,  // Clock cycle time
@@ -141,3 +143,9 @@ impl TimingController {
         self.tRCD + self.tWR
     }
 }
+
+impl Default for TimingController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -4,10 +4,12 @@ pub struct VoltageController {
     voltage_step: f32,         // Voltage change per step
     min_voltage: f32,          // Minimum allowed voltage
     max_voltage: f32,          // Maximum allowed voltage
+    #[allow(dead_code)]
     voltage_margin: f32,       // Safety margin
     stats: VoltageStats,
 }
 
+#[derive(Default)]
 struct VoltageStats {
     voltage_transitions: u64,
     time_at_min_voltage: u64,
@@ -71,6 +73,12 @@ impl VoltageController {
     }
 }
 
+impl Default for VoltageController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug)]
 pub enum VoltageError {
     OutOfRange,
@@ -1,17 +1,19 @@
 use super::super::types::{VirtualAddress, PhysicalAddress};
 use std::collections::VecDeque;
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::simulation::time::SimTime;
 
 pub struct ErrorLogger {
     logs: VecDeque<ErrorLog>,
     max_logs: usize,
     severity_filter: ErrorSeverity,
     stats: LoggingStats,
+    now: SimTime,
 }
 
 #[derive(Clone, Debug)]
+#[allow(dead_code)]
 pub struct ErrorLog {
-    timestamp: u64,
+    timestamp: u64, // Nanoseconds of simulated time
     error_type: ErrorType,
     severity: ErrorSeverity,
     virtual_addr: Option<VirtualAddress>,
@@ -42,6 +44,7 @@ pub enum ErrorType {
     ThermalError,
 }
 
+#[derive(Default)]
 struct LoggingStats {
     total_errors: u64,
     corrected_errors: u64,
@@ -56,9 +59,15 @@ impl ErrorLogger {
             max_logs: 1000,
             severity_filter: ErrorSeverity::Info,
             stats: LoggingStats::default(),
+            now: SimTime::ZERO,
         }
     }
 
+    // Entries are stamped with simulated time, never the host's clock
+    pub fn set_time(&mut self, now: SimTime) {
+        self.now = now;
+    }
+
     pub fn log_error(&mut self, error_type: ErrorType, severity: ErrorSeverity,
                      virtual_addr: Option<VirtualAddress>,
                      physical_addr: Option<PhysicalAddress>,
@@ -66,10 +75,7 @@ impl ErrorLogger {
     {
         if severity >= self.severity_filter {
             let log = ErrorLog {
-                timestamp: SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs(),
+                timestamp: self.now.as_ns(),
                 error_type,
                 severity,
                 virtual_addr,
@@ -109,3 +115,9 @@ impl ErrorLogger {
         self.logs.clear();
     }
 }
+
+impl Default for ErrorLogger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
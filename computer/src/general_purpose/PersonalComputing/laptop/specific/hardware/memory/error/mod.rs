@@ -1,9 +1,8 @@
 // Export all modules in error
-pub mod ecc;
 pub mod logging;
 pub mod scrubbing;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum MemoryError {
     // Access errors
     AddressOutOfRange,
@@ -32,7 +31,7 @@ pub enum MemoryError {
     HardwareFailure,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ECCError {
     SingleBitError { address: u64, syndrome: u8 },
     DoubleBitError { address: u64, syndrome: u8 },
@@ -64,11 +63,9 @@ impl MemoryError {
     }
 
     pub fn needs_immediate_attention(&self) -> bool {
-        match self {
-            MemoryError::ECCError(ECCError::ChipFailure {..}) => true,
-            MemoryError::HardwareFailure => true,
-            MemoryError::RefreshError => true,
-            _ => false
-        }
+        matches!(
+            self,
+            MemoryError::ECCError(ECCError::ChipFailure { .. }) | MemoryError::HardwareFailure | MemoryError::RefreshError
+        )
     }
 }
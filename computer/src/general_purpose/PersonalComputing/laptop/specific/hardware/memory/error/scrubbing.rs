@@ -1,51 +1,55 @@
-use super::super::types::PhysicalAddress;
+use crate::simulation::time::SimTime;
 use std::collections::HashSet;
-use std::time::{Duration, Instant};
+
+// Patrol scrubbing reads each page through ECC; this is what one costs
+const PAGE_SCRUB_TIME: SimTime = SimTime(640_000); // 4 KiB at 6.4 GB/s
+const PAGES_PER_PASS: u64 = 256;
 
 pub struct MemoryScrubber {
     enabled: bool,
-    interval: Duration,
-    last_scrub: Instant,
+    interval: SimTime,
+    last_scrub: SimTime,
     current_position: u64,
     bad_pages: HashSet<u64>,
     stats: ScrubbingStats,
 }
 
-struct ScrubbingStats {
-    scrubs_completed: u64,
-    errors_found: u64,
-    errors_corrected: u64,
-    pages_scrubbed: u64,
-    total_scrub_time: Duration,
+#[derive(Debug, Clone, Default)]
+pub struct ScrubbingStats {
+    pub scrubs_completed: u64,
+    pub errors_found: u64,
+    pub errors_corrected: u64,
+    pub pages_scrubbed: u64,
+    pub total_scrub_time: SimTime,
 }
 
 impl MemoryScrubber {
-    pub fn new(interval: Duration) -> Self {
+    pub fn new(interval: SimTime) -> Self {
         Self {
             enabled: true,
             interval,
-            last_scrub: Instant::now(),
+            last_scrub: SimTime::ZERO,
             current_position: 0,
             bad_pages: HashSet::new(),
             stats: ScrubbingStats::default(),
         }
     }
 
-    pub fn tick(&mut self) {
-        if !self.enabled || self.last_scrub.elapsed() < self.interval {
+    // Driven by simulated time, never the host's clock
+    pub fn tick(&mut self, now: SimTime) {
+        if !self.enabled || now - self.last_scrub < self.interval {
             return;
         }
 
-        self.start_scrubbing();
+        self.start_scrubbing(now);
     }
 
-    fn start_scrubbing(&mut self) {
-        let start_time = Instant::now();
+    fn start_scrubbing(&mut self, now: SimTime) {
         let mut errors_found = 0;
         let mut errors_corrected = 0;
 
         // Scrub a chunk of memory
-        for page in self.current_position..self.current_position + 256 {
+        for page in self.current_position..self.current_position + PAGES_PER_PASS {
             if let Some((found, corrected)) = self.scrub_page(page) {
                 errors_found += found;
                 errors_corrected += corrected;
@@ -56,21 +60,19 @@ impl MemoryScrubber {
         self.stats.scrubs_completed += 1;
         self.stats.errors_found += errors_found;
         self.stats.errors_corrected += errors_corrected;
-        self.stats.pages_scrubbed += 256;
-        self.stats.total_scrub_time += start_time.elapsed();
+        self.stats.pages_scrubbed += PAGES_PER_PASS;
+        self.stats.total_scrub_time += SimTime(PAGE_SCRUB_TIME.as_ps() * PAGES_PER_PASS);
 
         // Update position
-        self.current_position += 256;
+        self.current_position += PAGES_PER_PASS;
         if self.current_position >= self.get_memory_size() {
             self.current_position = 0;
         }
 
-        self.last_scrub = Instant::now();
+        self.last_scrub = now;
     }
 
     fn scrub_page(&mut self, page: u64) -> Option<(u64, u64)> {
-        let addr = PhysicalAddress(page * 4096);
-        
         // Check if page is marked as bad
         if self.bad_pages.contains(&page) {
             return None;
@@ -103,7 +105,7 @@ impl MemoryScrubber {
         self.enabled = false;
     }
 
-    pub fn set_interval(&mut self, interval: Duration) {
+    pub fn set_interval(&mut self, interval: SimTime) {
         self.interval = interval;
     }
 
@@ -28,11 +28,12 @@ pub struct PageFlags {
     pub global: bool,
 }
 
-struct PageTableStats {
-    page_faults: u64,
-    page_walks: u64,
-    allocations: u64,
-    deallocations: u64,
+#[derive(Debug, Clone, Default)]
+pub struct PageTableStats {
+    pub page_faults: u64,
+    pub page_walks: u64,
+    pub allocations: u64,
+    pub deallocations: u64,
 }
 
 impl PageTable {
@@ -61,12 +62,19 @@ impl PageTable {
             .ok_or(MemoryError::PageFault)
     }
 
+    pub fn get_next_table_mut(&mut self, index: usize) -> MemoryResult<&mut PageTable> {
+        self.next_tables.get_mut(&index)
+            .map(|table| table.as_mut())
+            .ok_or(MemoryError::PageFault)
+    }
+
     pub fn get_or_create_next_table(&mut self, index: usize) -> MemoryResult<&mut PageTable> {
         if !self.next_tables.contains_key(&index) {
+            let frame = self.allocate_frame()?;
             let entry = self.get_entry_mut(index)?;
             if entry.physical_frame.is_none() {
                 // Allocate new physical frame for table
-                entry.physical_frame = Some(self.allocate_frame()?);
+                entry.physical_frame = Some(frame);
             }
             
             let new_table = Box::new(PageTable::new(self.level - 1));
@@ -104,18 +112,18 @@ impl PageTable {
 
     pub fn unmap(&mut self, virtual_addr: VirtualAddress) -> MemoryResult<()> {
         let indices = self.get_page_indices(virtual_addr);
+        self.stats.deallocations += 1;
         let mut current_table = self;
         
         // Walk to leaf entry
         for &index in &indices[1..] {
-            current_table = current_table.get_next_table(index)?;
+            current_table = current_table.get_next_table_mut(index)?;
         }
 
         // Unmap the page
         let entry = current_table.get_entry_mut(indices[0])?;
         entry.physical_frame = None;
         entry.flags.present = false;
-        self.stats.deallocations += 1;
 
         Ok(())
     }
@@ -129,6 +137,10 @@ impl PageTable {
             ((addr >> 39) & 0x1FF) as usize,  // PDP index
         ]
     }
+
+    pub fn get_stats(&self) -> &PageTableStats {
+        &self.stats
+    }
 }
 
 impl PageTableEntry {
@@ -155,6 +167,12 @@ impl PageTableEntry {
     }
 }
 
+impl Default for PageTableEntry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PageFlags {
     pub fn new() -> Self {
         Self {
@@ -216,3 +234,9 @@ impl PageFlags {
         }
     }
 }
+
+impl Default for PageFlags {
+    fn default() -> Self {
+        Self::new()
+    }
+}
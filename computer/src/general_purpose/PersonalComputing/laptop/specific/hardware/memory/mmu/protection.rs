@@ -10,6 +10,7 @@ pub struct MemoryProtection {
     stats: ProtectionStats,
 }
 
+#[derive(Default)]
 struct ProtectionStats {
     permission_violations: u64,
     segmentation_faults: u64,
@@ -92,3 +93,9 @@ impl MemoryProtection {
         self.write_protect = false;
     }
 }
+
+impl Default for MemoryProtection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,48 +1,60 @@
 use std::collections::HashMap;
 use super::super::types::{VirtualAddress, PhysicalAddress};
 use super::paging::PageFlags;
+use crate::simulation::rng::SimRng;
 
 pub struct TLB {
     entries: HashMap<u64, TLBEntry>,
     max_entries: usize,
     replacement_policy: ReplacementPolicy,
+    rng: SimRng, // For Random eviction; forked from the machine seed
     stats: TLBStats,
 }
 
 struct TLBEntry {
+    #[allow(dead_code)]
     virtual_addr: VirtualAddress,
     physical_addr: PhysicalAddress,
+    #[allow(dead_code)]
     flags: PageFlags,
+    #[allow(dead_code)]
     asid: u16,
     last_access: u64,
     access_count: u64,
 }
 
 #[derive(Clone, Copy)]
+#[allow(dead_code)]
 enum ReplacementPolicy {
     LRU,
     FIFO,
     Random,
 }
 
-struct TLBStats {
-    hits: u64,
-    misses: u64,
-    evictions: u64,
-    invalidations: u64,
-    cycles_saved: u64,
+#[derive(Debug, Clone, Default)]
+pub struct TLBStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub invalidations: u64,
+    pub cycles_saved: u64,
 }
 
 impl TLB {
-    pub fn new() -> Self {
+    pub fn new(rng: SimRng) -> Self {
         Self {
             entries: HashMap::with_capacity(64),
             max_entries: 64,  // 64-entry TLB
             replacement_policy: ReplacementPolicy::LRU,
+            rng,
             stats: TLBStats::default(),
         }
     }
 
+    pub fn reseed(&mut self, rng: SimRng) {
+        self.rng = rng;
+    }
+
     pub fn lookup(&mut self, virtual_addr: VirtualAddress) -> Option<PhysicalAddress> {
         let page_addr = self.get_page_addr(virtual_addr);
         
@@ -53,7 +65,8 @@ impl TLB {
             self.stats.hits += 1;
             self.stats.cycles_saved += 20;  // Assume 20 cycles saved per hit
             
-            Some(entry.physical_addr)
+            // Entries hold whichever address filled them; only the frame counts
+            Some(PhysicalAddress((entry.physical_addr.0 & !0xFFF) | (virtual_addr.0 & 0xFFF)))
         } else {
             self.stats.misses += 1;
             None
@@ -115,10 +128,13 @@ impl TLB {
         }
     }
 
+    // HashMap order changes from run to run, so pick from the sorted pages
     fn evict_random(&mut self) {
-        if let Some((&addr, _)) = self.entries.iter()
-            .nth(fastrand::usize(..self.entries.len())) {
-            self.entries.remove(&addr);
+        let mut pages: Vec<u64> = self.entries.keys().copied().collect();
+        pages.sort_unstable();
+        if !pages.is_empty() {
+            let victim = pages[self.rng.range(0, pages.len() as u64) as usize];
+            self.entries.remove(&victim);
         }
     }
 
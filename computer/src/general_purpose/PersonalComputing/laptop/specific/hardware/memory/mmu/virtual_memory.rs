@@ -2,6 +2,8 @@ use super::super::error::{MemoryError, MemoryResult};
 use super::super::types::{VirtualAddress, PhysicalAddress};
 use super::paging::{PageTable, PageTableEntry, PageFlags};
 use super::tlb::TLB;
+use super::super::access::HopOutcome;
+use crate::simulation::rng::SimRng;
 
 pub struct VirtualMemoryManager {
     page_tables: Vec<PageTable>,
@@ -11,6 +13,7 @@ pub struct VirtualMemoryManager {
     stats: VMStats,
 }
 
+#[derive(Default)]
 struct VMStats {
     page_faults: u64,
     tlb_hits: u64,
@@ -20,25 +23,31 @@ struct VMStats {
 }
 
 impl VirtualMemoryManager {
-    pub fn new() -> Self {
+    pub fn new(rng: SimRng) -> Self {
         Self {
             page_tables: Vec::new(),
             current_asid: 0,
-            tlb: TLB::new(),
+            tlb: TLB::new(rng.fork("tlb")),
             enabled: false,
             stats: VMStats::default(),
         }
     }
 
     pub fn translate(&mut self, virtual_addr: VirtualAddress) -> MemoryResult<PhysicalAddress> {
+        self.translate_traced(virtual_addr).map(|(physical_addr, _)| physical_addr)
+    }
+
+    // The translation and whether the TLB had it; no outcome with
+    // translation off, since nothing was looked up
+    pub fn translate_traced(&mut self, virtual_addr: VirtualAddress) -> MemoryResult<(PhysicalAddress, Option<HopOutcome>)> {
         if !self.enabled {
-            return Ok(PhysicalAddress(virtual_addr.0));
+            return Ok((PhysicalAddress(virtual_addr.0), None));
         }
 
         // Check TLB first
         if let Some(physical_addr) = self.tlb.lookup(virtual_addr) {
             self.stats.tlb_hits += 1;
-            return Ok(physical_addr);
+            return Ok((physical_addr, Some(HopOutcome::Hit)));
         }
         self.stats.tlb_misses += 1;
 
@@ -47,8 +56,9 @@ impl VirtualMemoryManager {
         match self.walk_page_table(page_table, virtual_addr) {
             Ok((physical_addr, entry)) => {
                 // Update TLB
-                self.tlb.insert(virtual_addr, physical_addr, entry.flags);
-                Ok(physical_addr)
+                let flags = entry.flags;
+                self.tlb.insert(virtual_addr, physical_addr, flags);
+                Ok((physical_addr, Some(HopOutcome::Miss)))
             }
             Err(e) => {
                 self.stats.page_faults += 1;
@@ -57,8 +67,8 @@ impl VirtualMemoryManager {
         }
     }
 
-    fn walk_page_table(&self, table: &PageTable, addr: VirtualAddress) 
-        -> MemoryResult<(PhysicalAddress, &PageTableEntry)> 
+    fn walk_page_table<'t>(&self, table: &'t PageTable, addr: VirtualAddress) 
+        -> MemoryResult<(PhysicalAddress, &'t PageTableEntry)> 
     {
         let vpn = self.get_page_numbers(addr);
         let mut current_table = table;
@@ -66,33 +76,41 @@ impl VirtualMemoryManager {
         // Walk the page table levels
         for level in (0..4).rev() {
             let index = vpn[level];
-            let entry = current_table.get_entry(index)?;
-
             if level == 0 {
                 // Leaf entry - contains physical address
+                let entry = current_table.get_entry(index)?;
                 if !entry.flags.present {
                     return Err(MemoryError::PageFault);
                 }
                 let physical_addr = self.get_physical_address(entry, addr);
                 return Ok((physical_addr, entry));
             } else {
-                // Non-leaf entry - points to next level table
-                if let Some(next_table) = entry.get_next_table() {
-                    current_table = next_table;
-                } else {
-                    return Err(MemoryError::PageFault);
-                }
+                // Non-leaf entry - points to next level table, as map_page built it
+                current_table = current_table.get_next_table(index)?;
             }
         }
         Err(MemoryError::PageFault)
     }
 
+    // Turns translation on, with an empty address space if none was set up
+    pub fn enable(&mut self) {
+        if self.page_tables.is_empty() {
+            self.page_tables.push(PageTable::new(3));
+        }
+        self.tlb.flush();
+        self.enabled = true;
+    }
+
+    pub fn reseed(&mut self, rng: &SimRng) {
+        self.tlb.reseed(rng.fork("tlb"));
+    }
+
     pub fn map_page(&mut self, virtual_addr: VirtualAddress, physical_addr: PhysicalAddress, 
                     flags: PageFlags) -> MemoryResult<()> 
     {
-        let page_table = self.get_current_page_table()?;
         let vpn = self.get_page_numbers(virtual_addr);
-        
+        let page_table = self.page_tables.get_mut(self.current_asid).ok_or(MemoryError::PageFault)?;
+
         // Allocate page table entries as needed
         let mut current_table = page_table;
         for level in (1..4).rev() {
@@ -114,14 +132,14 @@ impl VirtualMemoryManager {
     }
 
     pub fn unmap_page(&mut self, virtual_addr: VirtualAddress) -> MemoryResult<()> {
-        let page_table = self.get_current_page_table()?;
         let vpn = self.get_page_numbers(virtual_addr);
+        let page_table = self.page_tables.get_mut(self.current_asid).ok_or(MemoryError::PageFault)?;
         
         // Find the leaf entry
         let mut current_table = page_table;
         for level in (1..4).rev() {
             let index = vpn[level];
-            current_table = current_table.get_next_table(index)?;
+            current_table = current_table.get_next_table_mut(index)?;
         }
 
         // Unmap the page
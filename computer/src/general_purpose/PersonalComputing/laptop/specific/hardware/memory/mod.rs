@@ -1,32 +1,30 @@
-use super::bus::Bus;
-use super::error::{MemoryError, MemoryResult};
-use super::types::{PhysicalAddress, VirtualAddress};
-
-pub mod bus;
-pub mod cache;
+pub mod access;
 pub mod controller;
 pub mod dram;
 pub mod mmu;
-pub mod constants;
+pub mod physical;
 pub mod error;
 pub mod types;
 
-use self::cache::{CacheHierarchy, CacheStats};
+use self::access::Hop;
 use self::controller::MemoryController;
 use self::dram::DRAMController;
-use self::mmu::MMU;
+use self::error::MemoryResult;
+use self::physical::PhysicalMemory;
+
+const POWER_DOWN_EXIT_CYCLES: u64 = 8;
 
+// Physical accesses reach DRAM in pieces that never cross a cache line
+const LINE_BYTES: u64 = 64;
+
+// Main memory: the controller and the DRAM behind it. The caches in front
+// of it belong to the cores and the L3 they share.
 pub struct Memory {
-    // Memory hierarchy
-    cache: CacheHierarchy,
-    controller: MemoryController,
-    dram: DRAMController,
-    mmu: MMU,
-    
-    // System bus connection
-    bus: *mut Bus,
-    
+    controller: MemoryController, // Owns the DRAM behind it
+
     // Memory state
+    cycle: u64,     // Memory clock cycles since power-on
+    frequency: u64, // Memory clock, Hz
     total_capacity: u64,
     used_capacity: u64,
     temperature: f32,
@@ -44,27 +42,27 @@ pub enum MemoryPowerState {
     DeepPowerDown,
 }
 
-struct MemoryStats {
-    total_accesses: u64,
-    reads: u64,
-    writes: u64,
-    cache_hits: u64,
-    cache_misses: u64,
-    page_faults: u64,
-    refresh_cycles: u64,
-    power_transitions: u64,
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStats {
+    pub total_accesses: u64,
+    pub reads: u64,
+    pub writes: u64,
+    pub power_transitions: u64,
 }
 
 impl Memory {
-    pub fn new(bus: *mut Bus) -> Self {
+    pub fn new() -> Self {
+        Self::with_dram(DRAMController::new(4, 8), 16 * 1024 * 1024 * 1024) // 4 ranks, 8 banks per rank, 16GB
+    }
+
+    pub fn with_dram(dram: DRAMController, total_capacity: u64) -> Self {
         Self {
-            cache: CacheHierarchy::new(),
-            controller: MemoryController::new(),
-            dram: DRAMController::new(4, 8), // 4 ranks, 8 banks per rank
-            mmu: MMU::new(),
-            bus,
+            controller: MemoryController::new(dram),
+
+            cycle: 0,
+            frequency: 1_600_000_000,
             
-            total_capacity: 16 * 1024 * 1024 * 1024, // 16GB
+            total_capacity,
             used_capacity: 0,
             temperature: 40.0,
             power_state: MemoryPowerState::Active,
@@ -73,52 +71,38 @@ impl Memory {
         }
     }
 
-    pub fn read(&mut self, address: VirtualAddress) -> MemoryResult<u32> {
+    // Line fills and writebacks from the CPU's last level cache, timed from
+    // the current memory cycle. A rank that was powered down pays its exit
+    // latency at the controller.
+    pub fn read_line(&mut self, address: u64, buffer: &mut [u8]) -> MemoryResult<[Hop; 2]> {
+        let exit = self.exit_power_down();
+        let mut hops = self.controller.read_line(address, buffer, self.cycle)?;
+        hops[0].cycles += exit;
         self.stats.total_accesses += 1;
         self.stats.reads += 1;
-
-        // Translate virtual address
-        let physical_addr = self.mmu.translate(address)?;
-
-        // Try cache hierarchy first
-        match self.cache.read(physical_addr) {
-            Ok(data) => {
-                self.stats.cache_hits += 1;
-                Ok(data)
-            }
-            Err(MemoryError::CacheMiss) => {
-                self.stats.cache_misses += 1;
-                // Read from DRAM on cache miss
-                self.read_from_dram(physical_addr)
-            }
-            Err(e) => Err(e),
-        }
+        Ok(hops)
     }
 
-    pub fn write(&mut self, address: VirtualAddress, data: u32) -> MemoryResult<()> {
+    pub fn write_line(&mut self, address: u64, data: &[u8]) -> MemoryResult<[Hop; 2]> {
+        let exit = self.exit_power_down();
+        let mut hops = self.controller.write_line(address, data, self.cycle)?;
+        hops[0].cycles += exit;
         self.stats.total_accesses += 1;
         self.stats.writes += 1;
+        Ok(hops)
+    }
 
-        let physical_addr = self.mmu.translate(address)?;
-
-        // Write to cache
-        match self.cache.write(physical_addr, data) {
-            Ok(()) => Ok(()),
-            Err(MemoryError::CacheMiss) => {
-                self.stats.cache_misses += 1;
-                // Write to DRAM on cache miss
-                self.write_to_dram(physical_addr, data)
-            }
-            Err(e) => Err(e),
-        }
+    // The clock generator owns the frequency; memory just follows it
+    pub fn set_frequency(&mut self, frequency: u64) {
+        self.frequency = frequency;
     }
 
     pub fn tick(&mut self) {
+        self.cycle += 1;
+
         // Update all components
-        self.cache.tick();
         self.controller.tick();
-        self.dram.tick();
-        self.mmu.tick();
+        self.controller.dram_mut().tick();
 
         // Update memory temperature
         self.update_temperature();
@@ -127,37 +111,15 @@ impl Memory {
         self.perform_maintenance();
     }
 
-    fn read_from_dram(&mut self, address: PhysicalAddress) -> MemoryResult<u32> {
-        // Check power state
-        if self.power_state != MemoryPowerState::Active {
-            self.wake_up();
-        }
-
-        self.dram.read(address)
-    }
-
-    fn write_to_dram(&mut self, address: PhysicalAddress, data: u32) -> MemoryResult<()> {
-        if self.power_state != MemoryPowerState::Active {
-            self.wake_up();
-        }
-
-        self.dram.write(address, data)
-    }
-
     fn update_temperature(&mut self) {
         // Calculate memory temperature based on activity and DRAM temperature
-        let dram_temp = self.dram.get_temperature();
+        let dram_temp = self.controller.dram().get_temperature();
         let activity_factor = self.get_activity_factor();
         
         self.temperature = dram_temp * 0.8 + activity_factor * 20.0;
     }
 
     fn perform_maintenance(&mut self) {
-        // Check if we need memory scrubbing
-        if self.needs_scrubbing() {
-            self.scrub_memory();
-        }
-
         // Check if we can enter power saving
         if self.can_power_down() {
             self.enter_power_down();
@@ -171,15 +133,19 @@ impl Memory {
         recent_accesses as f32 / window as f32
     }
 
-    fn needs_scrubbing(&self) -> bool {
-        // Implement scrubbing policy
-        false
-    }
-
     fn can_power_down(&self) -> bool {
         self.get_activity_factor() < 0.1 // Less than 10% activity
     }
 
+    // Cycles spent leaving power-down (tXP), zero if already active
+    fn exit_power_down(&mut self) -> u64 {
+        if self.power_state == MemoryPowerState::Active {
+            return 0;
+        }
+        self.wake_up();
+        POWER_DOWN_EXIT_CYCLES
+    }
+
     fn wake_up(&mut self) {
         self.power_state = MemoryPowerState::Active;
         self.stats.power_transitions += 1;
@@ -199,11 +165,67 @@ impl Memory {
         self.used_capacity as f32 / self.total_capacity as f32
     }
 
-    pub fn get_cache_stats(&self) -> &CacheStats {
-        self.cache.get_stats()
-    }
-
     pub fn get_power_state(&self) -> MemoryPowerState {
         self.power_state
     }
+
+    pub fn get_frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    pub fn get_cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    pub fn get_stats(&self) -> &MemoryStats {
+        &self.stats
+    }
+
+    pub fn get_controller(&self) -> &MemoryController {
+        &self.controller
+    }
+}
+
+// DRAM as a bus master sees it: physical addresses, straight to the
+// controller, with the same bank timing the cores' line fills get
+impl PhysicalMemory for Memory {
+    fn size(&self) -> u64 {
+        self.total_capacity
+    }
+
+    fn read_physical(&mut self, address: u64, buffer: &mut [u8]) -> MemoryResult<()> {
+        let mut done = 0;
+        while done < buffer.len() {
+            let at = address + done as u64;
+            let length = ((LINE_BYTES - at % LINE_BYTES) as usize).min(buffer.len() - done);
+            self.read_line(at, &mut buffer[done..done + length])?;
+            done += length;
+        }
+        Ok(())
+    }
+
+    fn write_physical(&mut self, address: u64, data: &[u8]) -> MemoryResult<()> {
+        let mut done = 0;
+        while done < data.len() {
+            let at = address + done as u64;
+            let length = ((LINE_BYTES - at % LINE_BYTES) as usize).min(data.len() - done);
+            self.write_line(at, &data[done..done + length])?;
+            done += length;
+        }
+        Ok(())
+    }
+
+    fn read_line_traced(&mut self, address: u64, buffer: &mut [u8]) -> MemoryResult<Vec<Hop>> {
+        self.read_line(address, buffer).map(Vec::from)
+    }
+
+    fn frequency(&self) -> u64 {
+        self.frequency
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
@@ -0,0 +1,100 @@
+use super::access::Hop;
+use super::error::{MemoryError, MemoryResult};
+
+// Physical RAM as bus masters see it when they DMA: flat, byte addressed,
+// below the caches and the MMU
+pub trait PhysicalMemory {
+    fn size(&self) -> u64;
+    fn read_physical(&mut self, address: u64, buffer: &mut [u8]) -> MemoryResult<()>;
+    fn write_physical(&mut self, address: u64, data: &[u8]) -> MemoryResult<()>;
+
+    // A line fill for the last level cache, with the hops it took below it,
+    // counted in `frequency()`. RAM without a timing model has none.
+    fn read_line_traced(&mut self, address: u64, buffer: &mut [u8]) -> MemoryResult<Vec<Hop>> {
+        self.read_physical(address, buffer)?;
+        Ok(Vec::new())
+    }
+
+    fn frequency(&self) -> u64 {
+        0
+    }
+
+    // Little-endian helpers for the descriptor structures devices share with drivers
+    fn read_u32(&mut self, address: u64) -> MemoryResult<u32> {
+        let mut bytes = [0u8; 4];
+        self.read_physical(address, &mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn write_u32(&mut self, address: u64, value: u32) -> MemoryResult<()> {
+        self.write_physical(address, &value.to_le_bytes())
+    }
+
+    fn read_u64(&mut self, address: u64) -> MemoryResult<u64> {
+        let mut bytes = [0u8; 8];
+        self.read_physical(address, &mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn write_u64(&mut self, address: u64, value: u64) -> MemoryResult<()> {
+        self.write_physical(address, &value.to_le_bytes())
+    }
+}
+
+// Plain RAM backing for simulations that don't need DRAM timing
+pub struct SystemRam {
+    bytes: Vec<u8>,
+    stats: RamStats,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RamStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+impl SystemRam {
+    pub fn new(size: usize) -> Self {
+        Self {
+            bytes: vec![0; size],
+            stats: RamStats::default(),
+        }
+    }
+
+    pub fn get_stats(&self) -> &RamStats {
+        &self.stats
+    }
+
+    fn range(&self, address: u64, length: usize) -> MemoryResult<std::ops::Range<usize>> {
+        let start = usize::try_from(address).map_err(|_| MemoryError::AddressOutOfRange)?;
+        let end = start.checked_add(length).ok_or(MemoryError::AddressOutOfRange)?;
+        if end > self.bytes.len() {
+            return Err(MemoryError::AddressOutOfRange);
+        }
+        Ok(start..end)
+    }
+}
+
+impl PhysicalMemory for SystemRam {
+    fn size(&self) -> u64 {
+        self.bytes.len() as u64
+    }
+
+    fn read_physical(&mut self, address: u64, buffer: &mut [u8]) -> MemoryResult<()> {
+        let range = self.range(address, buffer.len())?;
+        buffer.copy_from_slice(&self.bytes[range]);
+        self.stats.reads += 1;
+        self.stats.bytes_read += buffer.len() as u64;
+        Ok(())
+    }
+
+    fn write_physical(&mut self, address: u64, data: &[u8]) -> MemoryResult<()> {
+        let range = self.range(address, data.len())?;
+        self.bytes[range].copy_from_slice(data);
+        self.stats.writes += 1;
+        self.stats.bytes_written += data.len() as u64;
+        Ok(())
+    }
+}
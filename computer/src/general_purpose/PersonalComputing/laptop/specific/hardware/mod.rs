@@ -1,71 +1,456 @@
 pub mod bus;
+pub mod clock;
 pub mod cpu;
 pub mod memory;
 pub mod gpu;
 pub mod storage;
 pub mod io;
-pub mod error;
+pub mod dma;
+pub mod interrupt;
+pub mod power;
 
-use self::bus::Bus;
+use self::bus::{Bus, BusError, PLIC_BASE, TIMER_BASE, UART_BASE};
+use self::bus::pci_enumeration::PciFunction;
+use self::clock::dvfs::{DvfsController, OppTable};
+use self::clock::governor::Governor;
+use self::clock::{ClockConfig, ClockDomain, ClockGenerator};
+use self::cpu::cache_controller::CacheController;
 use self::cpu::CPU;
+use self::dma::coherence::CachedMemory;
+use self::dma::DMAController;
+use self::memory::dram::DRAMController;
 use self::memory::Memory;
 use self::gpu::GPU;
+use self::gpu::display::{DISPLAY_REGION_SIZE, DISPLAY_REGISTER_OFFSET};
+use self::interrupt::error::{InterruptError, InterruptResult};
+use self::interrupt::{InterruptConfig, InterruptController, InterruptID, PLIC_REGION_SIZE};
 use self::storage::Storage;
+use self::storage::nvme::controller::NVME_REGION_SIZE;
 use self::io::IOSystem;
+use self::io::devices::timer::{Timer, TIMER_REGION_SIZE};
+use self::io::devices::uart::UART_REGION_SIZE;
+use self::memory::access::AccessTrace;
+use self::memory::error::MemoryResult;
+use self::power::battery::Battery;
+use self::power::regulators::VoltageRegulators;
+use self::power::PowerDomain;
+use crate::machine::description::MachineDescription;
+use crate::machine::error::{MachineError, MachineResult};
+use crate::simulation::time::{SimTime, PS_PER_NS};
+use crate::simulation::{EventId, Scheduler};
+use std::cell::RefCell;
+use std::rc::Rc;
 
 pub struct Hardware {
     bus: Bus,
-    cpu: CPU,
-    memory: Memory,
+    cores: Vec<CPU>,
+    memory: CachedMemory<Memory>, // DRAM, behind the L3 the cores share and DMA has to stay coherent with
+    dma: DMAController,
     gpu: GPU,
     storage: Storage,
     io: IOSystem,
+    plic: Rc<RefCell<InterruptController>>, // Shared with the bus, like every mapped device
+    timer: Rc<RefCell<Timer>>,
+    clock: ClockGenerator,
+    regulators: VoltageRegulators,
+    dvfs: DvfsController,
+    battery: Battery,
+    machine: MachineDescription, // What the machine was built from
 }
 
+const MHZ: u64 = 1_000_000;
+
+// Rough platform power: each DVFS domain switches an effective capacitance
+// at its operating point, scaled by how busy it was, on top of a floor for
+// the panel, memory and everything else
+const SWITCHED_CAPACITANCE_F: f64 = 1.0e-9;
+const PLATFORM_FLOOR_W: f64 = 2.0;
+
 impl Hardware {
-    pub fn new() -> Self {
+    pub fn new() -> MachineResult<Self> {
+        Self::with_description(MachineDescription::default())
+    }
+
+    // Builds the machine a description asks for. Descriptions are expected
+    // to be validated already; MachineBuilder does that before calling here.
+    // Fails if the panel's framebuffer doesn't fit in VRAM, or if the PCI
+    // BARs firmware assigned land on a platform device.
+    pub fn with_description(machine: MachineDescription) -> MachineResult<Self> {
         let mut bus = Bus::new();
+        let clock = ClockGenerator::new(ClockConfig {
+            cpu_freq: machine.cpu.base_frequency_mhz * MHZ,
+            gpu_freq: machine.gpu.frequency_mhz * MHZ,
+            mem_freq: machine.memory.data_rate_mts * MHZ / 2, // Double data rate
+            ..ClockConfig::default()
+        });
+
+        // The cores reach memory through their own L1 and L2 and the L3
+        // they share, and device registers over the bus; both are lent to
+        // them each cycle
+        let cpu_freq = clock.get_frequency(ClockDomain::CPU).unwrap_or(0);
+        let bus_freq = clock.get_frequency(ClockDomain::Bus).unwrap_or(0);
+        let cores: Vec<CPU> = (0..machine.cpu.cores)
+            .map(|index| {
+                let caches = &machine.cpu;
+                let mut core = CPU::with_caches(CacheController::with_levels(
+                    index,
+                    caches.l1.geometry(),
+                    caches.l2.geometry(),
+                ));
+                core.set_frequency(cpu_freq as u32);
+                core.set_bus_frequency(bus_freq);
+                core
+            })
+            .collect();
 
-        // Initialize components and register them with the bus
-        let cpu = CPU::new(&mut bus);
-        let memory = Memory::new(&mut bus);
-        let gpu = GPU::new(&mut bus);
-        let storage = Storage::new(&mut bus);
-        let io = IOSystem::new(&mut bus);
+        let dram_desc = &machine.memory;
+        let mut dram = DRAMController::new(dram_desc.ranks, dram_desc.banks_per_rank);
+        dram.set_timings(dram_desc.timings);
+        let mut memory = Memory::with_dram(dram, dram_desc.capacity_gb * 1024 * 1024 * 1024);
+        memory.set_frequency(clock.get_frequency(ClockDomain::Memory).unwrap_or(0));
+        let memory = CachedMemory::with_l3(memory, machine.cpu.l3.geometry());
 
-        Self {
+        // Initialize the other components and register them with the bus
+        let gpu_desc = &machine.gpu;
+        let panel = &machine.display;
+        let gpu = GPU::with_shape(
+            gpu_desc.shader_cores,
+            gpu_desc.ray_cores,
+            gpu_desc.tensor_cores,
+            panel.width,
+            panel.height,
+            panel.refresh_hz,
+        )
+        .map_err(|_| MachineError::invalid("display.width", format!("a {}x{} framebuffer doesn't fit in VRAM", panel.width, panel.height)))?;
+        let storage = Storage::with_description(&machine.storage);
+        let io = IOSystem::with_ports(&machine.ports);
+        let mut plic = InterruptController::new(InterruptConfig {
+            num_targets: cores.len(),
+            ..InterruptConfig::default()
+        });
+        let dma = DMAController::default();
+        dma.connect(&mut plic);
+        let timer = Timer::new(cores.len());
+
+        // Firmware numbers the PCI buses and places BARs before any OS looks
+        let functions = bus.enumerate_pci();
+
+        let mut dvfs = DvfsController::default();
+        for (domain, rail, table) in [
+            (ClockDomain::CPU, PowerDomain::CPU, OppTable::laptop_cpu().up_to(machine.cpu.max_frequency_mhz * MHZ)),
+            (ClockDomain::GPU, PowerDomain::GPU, OppTable::laptop_gpu().up_to(machine.gpu.frequency_mhz * MHZ)),
+        ] {
+            let boot = clock.get_frequency(domain).unwrap_or(0);
+            dvfs.add_domain(domain, rail, table, Governor::Schedutil, boot);
+        }
+
+        let mut hardware = Self {
             bus,
-            cpu,
+            cores,
             memory,
+            dma,
             gpu,
             storage,
             io,
+            plic: Rc::new(RefCell::new(plic)),
+            timer: Rc::new(RefCell::new(timer)),
+            clock,
+            regulators: VoltageRegulators::new(),
+            dvfs,
+            battery: Battery::new(machine.battery.capacity_wh),
+            machine,
+        };
+        hardware.map_devices(&functions).map_err(MachineError::Bus)?;
+        Ok(hardware)
+    }
+
+    // Runs the machine for a stretch of simulated time. Each component gets
+    // as many ticks as its clock domain produced, so a frequency change
+    // really does speed it up or slow it down.
+    pub fn advance(&mut self, elapsed_ns: u64) {
+        let ticks = self.clock.advance(elapsed_ns);
+        let ticks_for = |domain| ticks.get(&domain).copied().unwrap_or(0);
+
+        // mtime runs off its own timebase, not any of the domain clocks
+        self.timer.borrow_mut().advance(elapsed_ns);
+
+        // Memory ticks are spread between the cores', so a load issued
+        // partway through the quantum sees the memory clock where it is.
+        // The cores share one clock domain and take turns within a cycle.
+        let (cpu_ticks, memory_ticks) = (ticks_for(ClockDomain::CPU), ticks_for(ClockDomain::Memory));
+        let mut memory_done = 0;
+        let mut busy_cycles = 0;
+        for cycle in 0..cpu_ticks {
+            for (index, core) in self.cores.iter_mut().enumerate() {
+                core.tick(&mut self.memory, &mut self.bus);
+                core.check_interrupts(&mut self.plic.borrow_mut(), index);
+                if core.is_busy() {
+                    busy_cycles += 1;
+                }
+            }
+            while memory_done * cpu_ticks < (cycle + 1) * memory_ticks {
+                self.memory.memory_mut().tick();
+                memory_done += 1;
+            }
+        }
+        for _ in memory_done..memory_ticks {
+            self.memory.memory_mut().tick();
+        }
+        for _ in 0..ticks_for(ClockDomain::GPU) {
+            self.gpu.tick();
+        }
+        // The DMA engine is a bus master, so it moves at the bus clock
+        for _ in 0..ticks_for(ClockDomain::Bus) {
+            self.bus.tick();
+            self.dma.tick(&mut self.bus, &mut self.memory, &mut self.plic.borrow_mut());
+            self.plic.borrow_mut().tick();
+        }
+        // Storage commands complete as the host rings the doorbells, so
+        // only the rest of the I/O system needs its clock
+        for _ in 0..ticks_for(ClockDomain::IO) {
+            self.io.tick();
+        }
+
+        // The cores share a rail, so the governor sees their average load
+        let period_ps = self.clock.period_ps(ClockDomain::CPU);
+        let busy_cycles = busy_cycles / self.cores.len().max(1) as u64;
+        self.dvfs.record_busy(ClockDomain::CPU, busy_cycles * period_ps / 1000);
+        self.regulators.update(elapsed_ns);
+        self.dvfs.update(elapsed_ns, &mut self.clock, &mut self.regulators);
+        self.battery.drain(self.power_draw_w(), elapsed_ns);
+        if let Some(frequency) = self.clock.get_frequency(ClockDomain::CPU) {
+            for core in &mut self.cores {
+                core.set_frequency(frequency as u32);
+            }
         }
     }
 
-    pub fn tick(&mut self) {
-        self.cpu.tick();
-        self.memory.tick();
-        self.gpu.tick();
-        self.storage.tick();
-        self.io.tick();
+    // Hands the machine to the event kernel: every `quantum` of simulated
+    // time, each clock domain runs the cycles that fit in it. Components
+    // that pick at random get streams forked off the scheduler's seed.
+    // Sub-nanosecond quanta are carried, so no simulated time is dropped.
+    pub fn attach(&mut self, scheduler: &mut Scheduler<Hardware>, quantum: SimTime) -> EventId {
+        let rng = scheduler.rng().fork("hardware");
+        for (index, core) in self.cores.iter_mut().enumerate() {
+            core.reseed(&rng.fork(&format!("core{}", index)));
+        }
+
+        let quantum = quantum.max(SimTime(1));
+        let mut carry_ps = 0;
+        scheduler.schedule_every(quantum, "hardware.quantum", move |hardware, _| {
+            carry_ps += quantum.as_ps();
+            hardware.advance(carry_ps / PS_PER_NS);
+            carry_ps %= PS_PER_NS;
+            true
+        })
+    }
+
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.bus
+    }
+
+    // Where drivers queue transfers and do their cache maintenance
+    pub fn dma_mut(&mut self) -> &mut DMAController {
+        &mut self.dma
+    }
+
+    pub fn cached_memory_mut(&mut self) -> &mut CachedMemory<Memory> {
+        &mut self.memory
+    }
+
+    pub fn plic(&self) -> Rc<RefCell<InterruptController>> {
+        Rc::clone(&self.plic)
+    }
+
+    pub fn timer(&self) -> Rc<RefCell<Timer>> {
+        Rc::clone(&self.timer)
+    }
+
+    pub fn set_governor(&mut self, domain: ClockDomain, governor: Governor) {
+        self.dvfs.set_governor(domain, governor);
+    }
+
+    // Follows one load from core 0 through its TLB, L1 and L2, the shared
+    // L3, the memory controller and a DRAM bank; the trace's latency() puts
+    // a time on it
+    pub fn load(&mut self, address: u32) -> MemoryResult<AccessTrace> {
+        self.cores[0].load(address, &mut self.memory, &mut self.bus)
+    }
+
+    // A handler's return on one core. Whatever it was masking gets sampled
+    // straight away rather than waiting for the next cycle.
+    pub fn return_from_interrupt(&mut self, core: usize) -> InterruptResult<Option<InterruptID>> {
+        let cpu = self.cores.get_mut(core).ok_or(InterruptError::InvalidTarget(core))?;
+        let mut plic = self.plic.borrow_mut();
+        cpu.return_from_interrupt(&mut plic, core)?;
+        Ok(cpu.check_interrupts(&mut plic, core))
     }
 
+    pub fn core_mut(&mut self, index: usize) -> Option<&mut CPU> {
+        self.cores.get_mut(index)
+    }
+
+    pub fn cores(&self) -> &[CPU] {
+        &self.cores
+    }
+
+    pub fn description(&self) -> &MachineDescription {
+        &self.machine
+    }
+
+    pub fn clock(&self) -> &ClockGenerator {
+        &self.clock
+    }
+
+    pub fn dvfs(&self) -> &DvfsController {
+        &self.dvfs
+    }
+
+    pub fn battery(&self) -> &Battery {
+        &self.battery
+    }
+
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    pub fn io(&self) -> &IOSystem {
+        &self.io
+    }
+
+    // What the platform draws at its current operating points. The CPU
+    // domain's figure is per core.
+    pub fn power_draw_w(&self) -> f64 {
+        let domain_w = |domain: ClockDomain| {
+            self.dvfs.operating_point(domain).map_or(0.0, |point| {
+                let volts = point.voltage_mv as f64 / 1000.0;
+                SWITCHED_CAPACITANCE_F * volts * volts * point.frequency as f64 * self.dvfs.utilization(domain) as f64
+            })
+        };
+        PLATFORM_FLOOR_W + domain_w(ClockDomain::CPU) * self.cores.len() as f64 + domain_w(ClockDomain::GPU)
+    }
+
+    // Busy fractions come from the governors' last sample of each domain
     pub fn get_stats(&self) -> HardwareStats {
         HardwareStats {
-            cpu_utilization: self.cpu.get_utilization(),
-            memory_usage: self.memory.get_usage(),
-            gpu_utilization: self.gpu.get_utilization(),
+            cpu_utilization: self.dvfs.utilization(ClockDomain::CPU),
+            memory_usage: self.memory.memory().get_utilization(),
+            gpu_utilization: self.dvfs.utilization(ClockDomain::GPU),
             storage_activity: self.storage.get_activity(),
-            io_throughput: self.io.get_throughput(),
         }
     }
+
+    // Helper methods
+    // Puts every register file on the bus: platform devices at fixed
+    // addresses, PCI devices inside the BARs firmware just assigned
+    fn map_devices(&mut self, functions: &[PciFunction]) -> Result<(), BusError> {
+        self.bus.map_device("plic", PLIC_BASE, PLIC_REGION_SIZE, self.plic.clone())?;
+        self.bus.map_device("timer", TIMER_BASE, TIMER_REGION_SIZE, self.timer.clone())?;
+        self.bus.map_device("uart", UART_BASE, UART_REGION_SIZE, self.io.uart())?;
+
+        let bar0 = |class, subclass| {
+            functions
+                .iter()
+                .find(|function| function.class.class == class && function.class.subclass == subclass)
+                .and_then(|function| function.bars.iter().find(|bar| bar.index == 0 && bar.base != 0))
+                .map(|bar| bar.base)
+        };
+        if let (Some(base), Some(nvme)) = (bar0(0x01, 0x08), self.storage.nvme()) {
+            self.bus.map_device("nvme", base, NVME_REGION_SIZE, nvme)?;
+        }
+        if let Some(base) = bar0(0x03, 0x00) {
+            self.bus.map_device("display", base + DISPLAY_REGISTER_OFFSET, DISPLAY_REGION_SIZE, self.gpu.display())?;
+        }
+        Ok(())
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct HardwareStats {
-    cpu_utilization: f32,
-    memory_usage: f32,
-    gpu_utilization: f32,
-    storage_activity: f32,
-    io_throughput: f32,
-}
\ No newline at end of file
+    pub cpu_utilization: f32,
+    pub memory_usage: f32,
+    pub gpu_utilization: f32,
+    pub storage_activity: f32,
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::memory::access::{HopOutcome, MemoryLevel};
+    use crate::hardware::memory::mmu::paging::PageFlags;
+    use crate::hardware::memory::types::{PhysicalAddress, VirtualAddress};
+
+    const VIRTUAL: u32 = 0x4000_0000;
+    const PHYSICAL: u64 = 0x0010_0000;
+
+    fn levels(trace: &AccessTrace) -> Vec<(MemoryLevel, HopOutcome)> {
+        trace.hops.iter().map(|hop| (hop.level, hop.outcome)).collect()
+    }
+
+    #[test]
+    fn a_load_goes_down_every_level_and_is_charged_for_each_hop() {
+        let mut hardware = Hardware::new().unwrap();
+        let cpu = hardware.description().cpu.clone();
+        // Lines a stride apart share a set in that level
+        let l1_stride = (cpu.l1.size_kb * 1024 / cpu.l1.ways) as u32;
+        let l2_stride = (cpu.l2.size_kb * 1024 / cpu.l2.ways) as u32;
+        let l1_evictors: Vec<u32> = (1..=cpu.l1.ways as u32).map(|way| VIRTUAL + way * l1_stride).collect();
+        let l2_evictors: Vec<u32> = (1..=2 * cpu.l2.ways as u32).map(|way| VIRTUAL + way * l2_stride).collect();
+        let mmu = hardware.core_mut(0).unwrap().mmu_mut();
+        mmu.enable();
+        for &address in [VIRTUAL].iter().chain(&l1_evictors).chain(&l2_evictors) {
+            let physical = PHYSICAL + (address - VIRTUAL) as u64;
+            mmu.map_page(VirtualAddress(address as u64), PhysicalAddress(physical), PageFlags::kernel_data()).unwrap();
+        }
+
+        // Cold: the page table is walked, every cache misses and a DRAM bank has the line
+        let cold = hardware.load(VIRTUAL).unwrap();
+        let hops = levels(&cold);
+        assert_eq!(hops.len(), 6);
+        assert_eq!(
+            hops[..5],
+            [
+                (MemoryLevel::TLB, HopOutcome::Miss),
+                (MemoryLevel::L1, HopOutcome::Miss),
+                (MemoryLevel::L2, HopOutcome::Miss),
+                (MemoryLevel::L3, HopOutcome::Miss),
+                (MemoryLevel::MemoryController, HopOutcome::Scheduled),
+            ]
+        );
+        assert!(matches!(cold.served_by(), Some(MemoryLevel::DRAMBank { .. })));
+        let cycles: Vec<u64> = cold.hops[1..4].iter().map(|hop| hop.cycles).collect();
+        assert_eq!(cycles, [cpu.l1.latency_cycles, cpu.l2.latency_cycles, cpu.l3.latency_cycles]);
+        assert!(cold.hops[5].cycles > 0);
+        let on_core: u64 = cold.hops[..4].iter().map(|hop| hop.cycles).sum();
+        assert!(cold.core_cycles > on_core);
+
+        // Warm: the TLB and L1 both hit, and that's all the core waits for
+        let warm = hardware.load(VIRTUAL).unwrap();
+        assert_eq!(levels(&warm), [(MemoryLevel::TLB, HopOutcome::Hit), (MemoryLevel::L1, HopOutcome::Hit)]);
+        assert_eq!(warm.served_by(), Some(MemoryLevel::L1));
+        assert!(warm.hops[0].cycles < cold.hops[0].cycles);
+        assert_eq!(warm.core_cycles, warm.hops[0].cycles + cpu.l1.latency_cycles);
+
+        // Pushed out of L1 only, then out of L2 as well
+        for &address in &l1_evictors {
+            hardware.load(address).unwrap();
+        }
+        let from_l2 = hardware.load(VIRTUAL).unwrap();
+        assert_eq!(from_l2.served_by(), Some(MemoryLevel::L2));
+        assert_eq!(from_l2.hops.len(), 3);
+        for &address in &l2_evictors {
+            hardware.load(address).unwrap();
+        }
+        let from_l3 = hardware.load(VIRTUAL).unwrap();
+        assert_eq!(
+            levels(&from_l3),
+            [
+                (MemoryLevel::TLB, HopOutcome::Hit),
+                (MemoryLevel::L1, HopOutcome::Miss),
+                (MemoryLevel::L2, HopOutcome::Miss),
+                (MemoryLevel::L3, HopOutcome::Hit),
+            ]
+        );
+        assert_eq!(from_l3.served_by(), Some(MemoryLevel::L3));
+        assert!(from_l2.core_cycles < from_l3.core_cycles && from_l3.core_cycles < cold.core_cycles);
+    }
+}
@@ -0,0 +1,79 @@
+const NS_PER_HOUR: f64 = 3_600_000_000_000.0;
+
+// The pack the machine runs from when unplugged. Charge is tracked in
+// watt-hours and drawn down by whatever the platform consumes.
+pub struct Battery {
+    capacity_wh: f64,
+    charge_wh: f64,
+    stats: BatteryStats,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BatteryStats {
+    pub drawn_wh: f64,
+    pub peak_w: f64,
+}
+
+impl Battery {
+    // Comes off the charger full
+    pub fn new(capacity_wh: f64) -> Self {
+        Self {
+            capacity_wh,
+            charge_wh: capacity_wh,
+            stats: BatteryStats::default(),
+        }
+    }
+
+    // Draws `watts` for the elapsed time, stopping at empty
+    pub fn drain(&mut self, watts: f64, elapsed_ns: u64) {
+        let energy_wh = (watts * elapsed_ns as f64 / NS_PER_HOUR).min(self.charge_wh);
+        self.charge_wh -= energy_wh;
+        self.stats.drawn_wh += energy_wh;
+        self.stats.peak_w = self.stats.peak_w.max(watts);
+    }
+
+    pub fn charge(&mut self, energy_wh: f64) {
+        self.charge_wh = (self.charge_wh + energy_wh).min(self.capacity_wh);
+    }
+
+    // State of charge, 0.0 to 1.0
+    pub fn level(&self) -> f64 {
+        self.charge_wh / self.capacity_wh
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.charge_wh <= 0.0
+    }
+
+    pub fn capacity_wh(&self) -> f64 {
+        self.capacity_wh
+    }
+
+    pub fn charge_wh(&self) -> f64 {
+        self.charge_wh
+    }
+
+    pub fn get_stats(&self) -> &BatteryStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drains_by_energy_and_stops_at_empty() {
+        let mut battery = Battery::new(50.0);
+        battery.drain(10.0, 30 * 60 * 1_000_000_000); // Half an hour at 10 W
+        assert!((battery.charge_wh() - 45.0).abs() < 1e-9);
+        assert!((battery.level() - 0.9).abs() < 1e-9);
+
+        battery.drain(100.0, 3600 * 1_000_000_000);
+        assert!(battery.is_empty());
+        assert!((battery.get_stats().drawn_wh - 50.0).abs() < 1e-9);
+
+        battery.charge(80.0);
+        assert_eq!(battery.level(), 1.0);
+    }
+}
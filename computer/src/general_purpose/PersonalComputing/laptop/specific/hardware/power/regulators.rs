@@ -0,0 +1,105 @@
+use super::PowerDomain;
+use std::collections::HashMap;
+
+// The board's voltage regulators, one rail per power domain. A rail slews
+// toward its target at a fixed rate, so DVFS must wait for the voltage to
+// arrive before it can raise a frequency.
+pub struct VoltageRegulators {
+    rails: HashMap<PowerDomain, Rail>,
+    stats: RegulatorStats,
+}
+
+struct Rail {
+    voltage_mv: f32,
+    target_mv: f32,
+    min_mv: u32,
+    max_mv: u32,
+    slew_mv_per_us: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegulatorError {
+    InvalidRail,
+    OutOfRange(u32),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RegulatorStats {
+    pub transitions: u64,
+    pub slewing_ns: u64,
+}
+
+impl VoltageRegulators {
+    pub fn new() -> Self {
+        let mut rails = HashMap::new();
+        rails.insert(PowerDomain::CPU, Rail::new(850, 600, 1200, 10.0));
+        rails.insert(PowerDomain::GPU, Rail::new(800, 600, 1100, 10.0));
+        rails.insert(PowerDomain::Memory, Rail::new(1200, 1140, 1260, 5.0));
+        rails.insert(PowerDomain::IO, Rail::new(1000, 900, 1100, 5.0));
+        rails.insert(PowerDomain::Storage, Rail::new(1800, 1700, 1900, 5.0));
+
+        Self {
+            rails,
+            stats: RegulatorStats::default(),
+        }
+    }
+
+    pub fn set_target(&mut self, domain: PowerDomain, millivolts: u32) -> Result<(), RegulatorError> {
+        let rail = self.rails.get_mut(&domain).ok_or(RegulatorError::InvalidRail)?;
+        if millivolts < rail.min_mv || millivolts > rail.max_mv {
+            return Err(RegulatorError::OutOfRange(millivolts));
+        }
+        if rail.target_mv != millivolts as f32 {
+            rail.target_mv = millivolts as f32;
+            self.stats.transitions += 1;
+        }
+        Ok(())
+    }
+
+    pub fn update(&mut self, elapsed_ns: u64) {
+        for rail in self.rails.values_mut() {
+            if rail.is_settled() {
+                continue;
+            }
+            let step = rail.slew_mv_per_us * elapsed_ns as f32 / 1000.0;
+            let diff = rail.target_mv - rail.voltage_mv;
+            rail.voltage_mv += diff.signum() * step.min(diff.abs());
+            self.stats.slewing_ns += elapsed_ns;
+        }
+    }
+
+    pub fn voltage_mv(&self, domain: PowerDomain) -> Option<f32> {
+        self.rails.get(&domain).map(|rail| rail.voltage_mv)
+    }
+
+    pub fn is_settled(&self, domain: PowerDomain) -> bool {
+        self.rails.get(&domain).is_none_or(Rail::is_settled)
+    }
+
+    // Methods for visualization system
+    pub fn get_stats(&self) -> &RegulatorStats {
+        &self.stats
+    }
+}
+
+impl Rail {
+    fn new(voltage_mv: u32, min_mv: u32, max_mv: u32, slew_mv_per_us: f32) -> Self {
+        Self {
+            voltage_mv: voltage_mv as f32,
+            target_mv: voltage_mv as f32,
+            min_mv,
+            max_mv,
+            slew_mv_per_us,
+        }
+    }
+
+    fn is_settled(&self) -> bool {
+        (self.voltage_mv - self.target_mv).abs() < 0.5
+    }
+}
+
+impl Default for VoltageRegulators {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,354 @@
+use super::error::{StorageError, StorageResult};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockDeviceType {
+    HardDisk,
+    SolidState,
+    NVMe,
+    RamDisk,
+}
+
+// Anything that can be addressed in fixed-size sectors: disks, SSDs and disk images
+pub trait BlockDevice {
+    fn device_type(&self) -> BlockDeviceType;
+    fn sector_size(&self) -> usize;
+    fn sector_count(&self) -> u64;
+    fn read_sectors(&mut self, sector: u64, count: u32, buffer: &mut [u8]) -> StorageResult<()>;
+    fn write_sectors(&mut self, sector: u64, data: &[u8]) -> StorageResult<()>;
+
+    fn flush(&mut self) -> StorageResult<()> {
+        Ok(())
+    }
+
+    fn trim_sectors(&mut self, _ranges: &[(u64, u32)]) -> StorageResult<()> {
+        Ok(())
+    }
+
+    // Byte-granular helpers, implemented as read-modify-write of whole sectors
+    fn read_bytes(&mut self, offset: u64, buffer: &mut [u8]) -> StorageResult<()> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let sector_size = self.sector_size() as u64;
+        let first = offset / sector_size;
+        let last = (offset + buffer.len() as u64 - 1) / sector_size;
+        let count = (last - first + 1) as u32;
+
+        let mut sectors = vec![0u8; count as usize * sector_size as usize];
+        self.read_sectors(first, count, &mut sectors)?;
+
+        let start = (offset - first * sector_size) as usize;
+        buffer.copy_from_slice(&sectors[start..start + buffer.len()]);
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, offset: u64, data: &[u8]) -> StorageResult<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let sector_size = self.sector_size() as u64;
+        let first = offset / sector_size;
+        let last = (offset + data.len() as u64 - 1) / sector_size;
+        let count = (last - first + 1) as u32;
+
+        let mut sectors = vec![0u8; count as usize * sector_size as usize];
+        let start = (offset - first * sector_size) as usize;
+        // Only partial head/tail sectors need their old contents
+        if start != 0 || !data.len().is_multiple_of(sector_size as usize) {
+            self.read_sectors(first, count, &mut sectors)?;
+        }
+        sectors[start..start + data.len()].copy_from_slice(data);
+        self.write_sectors(first, &sectors)
+    }
+}
+
+impl<D: BlockDevice + ?Sized> BlockDevice for &mut D {
+    fn device_type(&self) -> BlockDeviceType {
+        (**self).device_type()
+    }
+
+    fn sector_size(&self) -> usize {
+        (**self).sector_size()
+    }
+
+    fn sector_count(&self) -> u64 {
+        (**self).sector_count()
+    }
+
+    fn read_sectors(&mut self, sector: u64, count: u32, buffer: &mut [u8]) -> StorageResult<()> {
+        (**self).read_sectors(sector, count, buffer)
+    }
+
+    fn write_sectors(&mut self, sector: u64, data: &[u8]) -> StorageResult<()> {
+        (**self).write_sectors(sector, data)
+    }
+
+    fn flush(&mut self) -> StorageResult<()> {
+        (**self).flush()
+    }
+
+    fn trim_sectors(&mut self, ranges: &[(u64, u32)]) -> StorageResult<()> {
+        (**self).trim_sectors(ranges)
+    }
+}
+
+impl<D: BlockDevice + ?Sized> BlockDevice for Box<D> {
+    fn device_type(&self) -> BlockDeviceType {
+        (**self).device_type()
+    }
+
+    fn sector_size(&self) -> usize {
+        (**self).sector_size()
+    }
+
+    fn sector_count(&self) -> u64 {
+        (**self).sector_count()
+    }
+
+    fn read_sectors(&mut self, sector: u64, count: u32, buffer: &mut [u8]) -> StorageResult<()> {
+        (**self).read_sectors(sector, count, buffer)
+    }
+
+    fn write_sectors(&mut self, sector: u64, data: &[u8]) -> StorageResult<()> {
+        (**self).write_sectors(sector, data)
+    }
+
+    fn flush(&mut self) -> StorageResult<()> {
+        (**self).flush()
+    }
+
+    fn trim_sectors(&mut self, ranges: &[(u64, u32)]) -> StorageResult<()> {
+        (**self).trim_sectors(ranges)
+    }
+}
+
+// A disk image held in RAM, e.g. one produced by mkfs.fat or mkfs.ext4
+pub struct MemoryBlockDevice {
+    data: Vec<u8>,
+    sector_size: usize,
+    stats: DeviceStats,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DeviceStats {
+    pub sectors_read: u64,
+    pub sectors_written: u64,
+    pub read_commands: u64,
+    pub write_commands: u64,
+}
+
+impl MemoryBlockDevice {
+    pub fn new(sector_count: u64, sector_size: usize) -> Self {
+        Self {
+            data: vec![0; sector_count as usize * sector_size],
+            sector_size,
+            stats: DeviceStats::default(),
+        }
+    }
+
+    pub fn from_image(data: Vec<u8>, sector_size: usize) -> StorageResult<Self> {
+        if sector_size == 0 || !data.len().is_multiple_of(sector_size) {
+            return Err(StorageError::InvalidData);
+        }
+        Ok(Self {
+            data,
+            sector_size,
+            stats: DeviceStats::default(),
+        })
+    }
+
+    pub fn open(path: &Path) -> StorageResult<Self> {
+        let mut data = fs::read(path).map_err(|_| StorageError::NotFound)?;
+        // Pad a truncated image up to a whole sector
+        let remainder = data.len() % 512;
+        if remainder != 0 {
+            data.resize(data.len() + 512 - remainder, 0);
+        }
+        Self::from_image(data, 512)
+    }
+
+    pub fn save(&self, path: &Path) -> StorageResult<()> {
+        fs::write(path, &self.data).map_err(|_| StorageError::NotReady)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn get_stats(&self) -> &DeviceStats {
+        &self.stats
+    }
+
+    fn byte_range(&self, sector: u64, len: usize) -> StorageResult<std::ops::Range<usize>> {
+        let start = sector as usize * self.sector_size;
+        let end = start + len;
+        if !len.is_multiple_of(self.sector_size) || end > self.data.len() {
+            return Err(StorageError::InvalidAddress);
+        }
+        Ok(start..end)
+    }
+}
+
+impl BlockDevice for MemoryBlockDevice {
+    fn device_type(&self) -> BlockDeviceType {
+        BlockDeviceType::RamDisk
+    }
+
+    fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    fn sector_count(&self) -> u64 {
+        (self.data.len() / self.sector_size) as u64
+    }
+
+    fn read_sectors(&mut self, sector: u64, count: u32, buffer: &mut [u8]) -> StorageResult<()> {
+        let len = count as usize * self.sector_size;
+        if buffer.len() < len {
+            return Err(StorageError::InvalidData);
+        }
+        let range = self.byte_range(sector, len)?;
+        buffer[..len].copy_from_slice(&self.data[range]);
+        self.stats.sectors_read += count as u64;
+        self.stats.read_commands += 1;
+        Ok(())
+    }
+
+    fn write_sectors(&mut self, sector: u64, data: &[u8]) -> StorageResult<()> {
+        let range = self.byte_range(sector, data.len())?;
+        self.data[range].copy_from_slice(data);
+        self.stats.sectors_written += (data.len() / self.sector_size) as u64;
+        self.stats.write_commands += 1;
+        Ok(())
+    }
+
+    fn trim_sectors(&mut self, ranges: &[(u64, u32)]) -> StorageResult<()> {
+        for &(sector, count) in ranges {
+            let range = self.byte_range(sector, count as usize * self.sector_size)?;
+            self.data[range].fill(0);
+        }
+        Ok(())
+    }
+}
+
+// A drive of any size that only keeps the sectors written to it. The rest
+// read back as zeros, as on a new or fully trimmed drive, so a machine can
+// have a terabyte disk without the host giving it a terabyte.
+pub struct SparseBlockDevice {
+    device_type: BlockDeviceType,
+    sector_size: usize,
+    sector_count: u64,
+    sectors: HashMap<u64, Vec<u8>>,
+    stats: DeviceStats,
+}
+
+impl SparseBlockDevice {
+    pub fn new(device_type: BlockDeviceType, sector_count: u64, sector_size: usize) -> Self {
+        Self {
+            device_type,
+            sector_size,
+            sector_count,
+            sectors: HashMap::new(),
+            stats: DeviceStats::default(),
+        }
+    }
+
+    // Sectors the host actually holds
+    pub fn resident_sectors(&self) -> usize {
+        self.sectors.len()
+    }
+
+    pub fn get_stats(&self) -> &DeviceStats {
+        &self.stats
+    }
+
+    fn check_range(&self, sector: u64, count: u64) -> StorageResult<()> {
+        match sector.checked_add(count) {
+            Some(end) if end <= self.sector_count => Ok(()),
+            _ => Err(StorageError::InvalidAddress),
+        }
+    }
+}
+
+impl BlockDevice for SparseBlockDevice {
+    fn device_type(&self) -> BlockDeviceType {
+        self.device_type
+    }
+
+    fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sector_count
+    }
+
+    fn read_sectors(&mut self, sector: u64, count: u32, buffer: &mut [u8]) -> StorageResult<()> {
+        let len = count as usize * self.sector_size;
+        if buffer.len() < len {
+            return Err(StorageError::InvalidData);
+        }
+        self.check_range(sector, count as u64)?;
+        for (index, chunk) in buffer[..len].chunks_mut(self.sector_size).enumerate() {
+            match self.sectors.get(&(sector + index as u64)) {
+                Some(data) => chunk.copy_from_slice(data),
+                None => chunk.fill(0),
+            }
+        }
+        self.stats.sectors_read += count as u64;
+        self.stats.read_commands += 1;
+        Ok(())
+    }
+
+    fn write_sectors(&mut self, sector: u64, data: &[u8]) -> StorageResult<()> {
+        if !data.len().is_multiple_of(self.sector_size) {
+            return Err(StorageError::InvalidAddress);
+        }
+        let count = (data.len() / self.sector_size) as u64;
+        self.check_range(sector, count)?;
+        for (index, chunk) in data.chunks(self.sector_size).enumerate() {
+            self.sectors.insert(sector + index as u64, chunk.to_vec());
+        }
+        self.stats.sectors_written += count;
+        self.stats.write_commands += 1;
+        Ok(())
+    }
+
+    fn trim_sectors(&mut self, ranges: &[(u64, u32)]) -> StorageResult<()> {
+        for &(sector, count) in ranges {
+            self.check_range(sector, count as u64)?;
+            for index in sector..sector + count as u64 {
+                self.sectors.remove(&index);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sparse_device_holds_only_written_sectors() {
+        // 1 TB of 512-byte sectors
+        let mut drive = SparseBlockDevice::new(BlockDeviceType::NVMe, 1 << 31, 512);
+        drive.write_bytes(1000, b"hello").unwrap();
+        assert_eq!(drive.resident_sectors(), 1);
+
+        let mut buffer = [0xFFu8; 8];
+        drive.read_bytes(998, &mut buffer).unwrap();
+        assert_eq!(&buffer, b"\0\0hello\0");
+
+        let mut sector = [0xFFu8; 512];
+        drive.read_sectors((1 << 31) - 1, 1, &mut sector).unwrap();
+        assert!(sector.iter().all(|&byte| byte == 0));
+        assert_eq!(drive.read_sectors(1 << 31, 1, &mut sector), Err(StorageError::InvalidAddress));
+
+        drive.trim_sectors(&[(1, 1)]).unwrap();
+        assert_eq!(drive.resident_sectors(), 0);
+    }
+}
@@ -1,17 +1,19 @@
-use super::super::error::{StorageError, StorageResult};
+use super::super::error::StorageResult;
 use std::collections::{HashMap, VecDeque};
 
 pub struct DiskCache {
     read_cache: HashMap<CacheKey, CacheEntry>,
     write_buffer: VecDeque<WriteBufferEntry>,
     config: CacheConfig,
+    clock: u64,
     stats: CacheStats,
 }
 
-struct CacheKey {
-    surface: u32,
-    track: u32,
-    sector: u32,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub surface: u32,
+    pub track: u32,
+    pub sector: u32,
 }
 
 struct CacheEntry {
@@ -22,24 +24,29 @@ struct CacheEntry {
 }
 
 struct WriteBufferEntry {
+    #[allow(dead_code)]
     key: CacheKey,
+    #[allow(dead_code)]
     data: Vec<u8>,
+    #[allow(dead_code)]
     timestamp: u64,
 }
 
-struct CacheConfig {
-    read_cache_size: usize,
-    write_buffer_size: usize,
-    write_back_delay: u64,
-    prefetch_size: u32,
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    pub read_cache_size: usize,
+    pub write_buffer_size: usize,
+    pub write_back_delay: u64,
+    pub prefetch_size: u32,
 }
 
-struct CacheStats {
-    hits: u64,
-    misses: u64,
-    evictions: u64,
-    write_backs: u64,
-    prefetches: u64,
+#[derive(Debug, Clone, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub write_backs: u64,
+    pub prefetches: u64,
 }
 
 impl DiskCache {
@@ -48,6 +55,7 @@ impl DiskCache {
             read_cache: HashMap::with_capacity(config.read_cache_size),
             write_buffer: VecDeque::with_capacity(config.write_buffer_size),
             config,
+            clock: 0,
             stats: CacheStats::default(),
         }
     }
@@ -55,7 +63,8 @@ impl DiskCache {
     pub fn read(&mut self, key: CacheKey) -> StorageResult<Option<Vec<u8>>> {
         if let Some(entry) = self.read_cache.get_mut(&key) {
             entry.access_count += 1;
-            entry.last_access = self.get_current_time();
+            self.clock += 1;
+            entry.last_access = self.clock;
             self.stats.hits += 1;
             return Ok(Some(entry.data.clone()));
         }
@@ -64,19 +73,42 @@ impl DiskCache {
         Ok(None)
     }
 
+    // Fills the read cache with data that just came off (or went onto) the platter
+    pub fn insert(&mut self, key: CacheKey, data: Vec<u8>) -> StorageResult<()> {
+        if self.config.read_cache_size == 0 {
+            return Ok(());
+        }
+        if !self.read_cache.contains_key(&key) {
+            self.evict()?;
+        }
+        self.clock += 1;
+        self.read_cache.insert(key, CacheEntry {
+            data,
+            access_count: 0,
+            last_access: self.clock,
+            dirty: false,
+        });
+        Ok(())
+    }
+
+    pub fn get_stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
     pub fn write(&mut self, key: CacheKey, data: Vec<u8>) -> StorageResult<()> {
         // Update read cache if present
         if let Some(entry) = self.read_cache.get_mut(&key) {
             entry.data = data.clone();
             entry.dirty = true;
-            entry.last_access = self.get_current_time();
+            self.clock += 1;
+            entry.last_access = self.clock;
         }
 
         // Add to write buffer
         self.write_buffer.push_back(WriteBufferEntry {
             key,
             data,
-            timestamp: self.get_current_time(),
+            timestamp: self.clock,
         });
 
         // Handle write buffer overflow
@@ -142,7 +174,7 @@ impl DiskCache {
         Ok(())
     }
 
-    fn flush_entry(&mut self, entry: &WriteBufferEntry) -> StorageResult<()> {
+    fn flush_entry(&mut self, _entry: &WriteBufferEntry) -> StorageResult<()> {
         // Write to disk
         self.stats.write_backs += 1;
         Ok(())
@@ -151,7 +183,7 @@ impl DiskCache {
     fn find_lru_entry(&self) -> Option<CacheKey> {
         self.read_cache.iter()
             .min_by_key(|(_, entry)| entry.last_access)
-            .map(|(key, _)| key.clone())
+            .map(|(key, _)| *key)
     }
 
     fn next_sequential_key(&self, key: CacheKey) -> CacheKey {
@@ -162,9 +194,4 @@ impl DiskCache {
             sector: key.sector + 1,
         }
     }
-
-    fn get_current_time(&self) -> u64 {
-        // Implementation would use actual system time
-        0
-    }
 }
@@ -1,144 +1,581 @@
-use super::head::DiskHead;
-use super::platter::Platter;
-use super::cache::DiskCache;
+use super::head::{DiskHead, HeadConfig};
+use super::platter::{Platter, PlatterConfig};
+use super::cache::{CacheConfig, CacheKey, DiskCache};
+use super::scheduler::{ArmState, IOOperation, IORequest, IOScheduler, SchedulingAlgorithm, SchedulerStats};
+use super::super::device::{BlockDevice, BlockDeviceType};
 use super::super::error::{StorageError, StorageResult};
+use std::collections::VecDeque;
 
+// Arm movements kept for the visualizer to replay
+const ARM_TRACE_LIMIT: usize = 4096;
+
+// All times are nanoseconds of simulated time
 pub struct DiskController {
-    heads: Vec<DiskHead>,
+    head: DiskHead,
     platters: Vec<Platter>,
     cache: DiskCache,
     scheduler: IOScheduler,
+    geometry: DiskGeometry,
+    clock: u64,
+    next_id: u64,
+    completions: VecDeque<IOCompletion>,
+    arm_trace: VecDeque<ArmMovement>,
     stats: ControllerStats,
 }
 
-struct IOScheduler {
-    queue: Vec<IORequest>,
-    current_request: Option<IORequest>,
-    algorithm: SchedulingAlgorithm,
-    stats: SchedulerStats,
+#[derive(Debug, Clone, Copy)]
+struct DiskGeometry {
+    cylinders: u32,
+    heads: u32,
+    surfaces_per_platter: u32,
+    sectors_per_track: u32,
+    bytes_per_sector: u32,
+    revolution_time: f64,
+    track_skew: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiskConfig {
+    pub num_platters: u32,
+    pub rpm: u32,
+    // Sectors each track's start is rotated by, so a transfer running onto the
+    // next track finds its first sector arriving just after the head switch
+    pub track_skew: u32,
+    pub queue_depth: usize,
+    pub head_config: HeadConfig,
+    pub platter_config: PlatterConfig,
+    pub cache_config: CacheConfig,
+    pub scheduler_algorithm: SchedulingAlgorithm,
+}
+
+// Where one request's time went
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RequestTiming {
+    pub queued: u64,
+    pub seek: u64,
+    pub rotation: u64,
+    pub transfer: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct IOCompletion {
+    pub id: u64,
+    pub surface: u32,
+    pub track: u32,
+    pub sector: u32,
+    pub count: u32,
+    pub write: bool,
+    pub data: Vec<u8>,
+    pub timing: RequestTiming,
+    pub completed_at: u64,
 }
 
-#[derive(Clone)]
-struct IORequest {
-    surface: u32,
-    track: u32,
-    sector: u32,
-    operation: IOOperation,
-    priority: u8,
-    timestamp: u64,
+// One sweep of the arm; request is None while sweeping to an edge without serving
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArmMovement {
+    pub start: u64,
+    pub duration: u64,
+    pub from_cylinder: u32,
+    pub to_cylinder: u32,
+    pub request: Option<u64>,
 }
 
-enum IOOperation {
-    Read(Vec<u8>),
-    Write(Vec<u8>),
-    Seek(u32),
+#[derive(Debug, Clone, Default)]
+pub struct ControllerStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub seeks: u64,
+    pub cache_hits: u64,
+    pub completed: u64,
+    pub total_seek_time: u64,
+    pub total_rotation_time: u64,
+    pub total_transfer_time: u64,
+    pub total_response_time: u64,
+    pub max_response_time: u64,
+    pub avg_response_time: f32,
 }
 
-enum SchedulingAlgorithm {
-    FCFS,    // First Come First Served
-    SSTF,    // Shortest Seek Time First
-    SCAN,    // Elevator Algorithm
-    CSCAN,   // Circular SCAN
+impl RequestTiming {
+    pub fn service_time(&self) -> u64 {
+        self.seek + self.rotation + self.transfer
+    }
+
+    pub fn response_time(&self) -> u64 {
+        self.queued + self.service_time()
+    }
 }
 
-struct ControllerStats {
-    reads: u64,
-    writes: u64,
-    seeks: u64,
-    total_seek_time: u64,
-    avg_response_time: f32,
+impl DiskConfig {
+    // A 2.5" 5400 rpm laptop drive of about 20 GB
+    pub fn laptop_5400rpm() -> Self {
+        Self {
+            num_platters: 2,
+            rpm: 5400,
+            track_skew: 48,
+            queue_depth: 32,
+            head_config: HeadConfig {
+                track_to_track_time: 1_000,
+                full_stroke_time: 22_000,
+                head_switch_time: 1_000,
+            },
+            platter_config: PlatterConfig {
+                surfaces: 2,
+                tracks_per_surface: 20_000,
+                sectors_per_track: 500,
+                bytes_per_sector: 512,
+            },
+            cache_config: CacheConfig {
+                read_cache_size: 16_384,
+                write_buffer_size: 0,
+                write_back_delay: 0,
+                prefetch_size: 0,
+            },
+            scheduler_algorithm: SchedulingAlgorithm::NCQ,
+        }
+    }
 }
 
 impl DiskController {
     pub fn new(config: DiskConfig) -> Self {
+        let geometry = DiskGeometry {
+            cylinders: config.platter_config.tracks_per_surface,
+            heads: config.num_platters * config.platter_config.surfaces,
+            surfaces_per_platter: config.platter_config.surfaces,
+            sectors_per_track: config.platter_config.sectors_per_track,
+            bytes_per_sector: config.platter_config.bytes_per_sector,
+            revolution_time: 60_000_000_000.0 / config.rpm.max(1) as f64,
+            track_skew: config.track_skew,
+        };
+
         Self {
-            heads: (0..config.num_heads).map(|_| DiskHead::new(config.head_config.clone())).collect(),
+            head: DiskHead::new(config.head_config, geometry.cylinders),
             platters: (0..config.num_platters).map(|_| Platter::new(config.platter_config.clone())).collect(),
             cache: DiskCache::new(config.cache_config),
-            scheduler: IOScheduler::new(config.scheduler_algorithm),
+            scheduler: IOScheduler::new(config.scheduler_algorithm, config.queue_depth),
+            geometry,
+            clock: 0,
+            next_id: 0,
+            completions: VecDeque::new(),
+            arm_trace: VecDeque::new(),
             stats: ControllerStats::default(),
         }
     }
 
-    pub fn read_sector(&mut self, surface: u32, track: u32, sector: u32) -> StorageResult<Vec<u8>> {
-        // Try cache first
-        let key = CacheKey { surface, track, sector };
-        if let Some(data) = self.cache.read(key)? {
-            return Ok(data);
+    // Queues a transfer starting at a physical address; sectors run on across
+    // heads and then cylinders, like consecutive LBAs
+    pub fn submit(&mut self, surface: u32, track: u32, sector: u32, count: u32, operation: IOOperation) -> StorageResult<u64> {
+        let geometry = self.geometry;
+        if surface >= geometry.heads || track >= geometry.cylinders || sector >= geometry.sectors_per_track || count == 0 {
+            return Err(StorageError::InvalidAddress);
+        }
+        if self.lba(surface, track, sector) + count as u64 > self.sector_count() {
+            return Err(StorageError::InvalidAddress);
+        }
+        if let IOOperation::Write(data) = &operation {
+            if data.len() != count as usize * geometry.bytes_per_sector as usize {
+                return Err(StorageError::InvalidData);
+            }
         }
 
-        // Schedule read request
+        let id = self.next_id;
+        self.next_id += 1;
         let request = IORequest {
+            id,
+            lba: self.lba(surface, track, sector),
             surface,
             track,
             sector,
-            operation: IOOperation::Read(Vec::new()),
+            count,
+            operation,
             priority: 0,
-            timestamp: self.get_current_time(),
+            timestamp: self.clock,
         };
 
-        self.scheduler.queue.push(request);
-        self.process_io()?;
+        // Reads the drive buffer can satisfy never reach the queue, unless a
+        // queued write would change what they return
+        if let IOOperation::Read = request.operation {
+            let cached = match self.scheduler.conflicts_with(request.lba, count) {
+                true => None,
+                false => self.cached(&request),
+            };
+            if let Some(data) = cached {
+                self.stats.cache_hits += 1;
+                self.complete(&request, false, data, RequestTiming::default());
+                return Ok(id);
+            }
+        }
 
-        Ok(Vec::new()) // Simplified - would actually return data
+        self.scheduler.push(request);
+        Ok(id)
     }
 
-    pub fn write_sector(&mut self, surface: u32, track: u32, sector: u32, data: Vec<u8>) -> StorageResult<()> {
-        // Write to cache
-        let key = CacheKey { surface, track, sector };
-        self.cache.write(key, data.clone())?;
+    pub fn submit_lba(&mut self, lba: u64, count: u32, operation: IOOperation) -> StorageResult<u64> {
+        if lba >= self.sector_count() {
+            return Err(StorageError::InvalidAddress);
+        }
+        let (surface, track, sector) = self.chs(lba);
+        self.submit(surface, track, sector, count, operation)
+    }
 
-        // Schedule write request
-        let request = IORequest {
-            surface,
-            track,
-            sector,
-            operation: IOOperation::Write(data),
-            priority: 0,
-            timestamp: self.get_current_time(),
+    // Serves the request the scheduler picks next and returns its id. The
+    // completion itself goes on the queue, for wait() or take_completions().
+    pub fn service_next(&mut self) -> StorageResult<Option<u64>> {
+        let arm = ArmState {
+            cylinder: self.head.get_current_position().0,
+            max_cylinder: self.geometry.cylinders - 1,
+            now: self.clock,
+        };
+        let selection = {
+            let (head, geometry, clock) = (&self.head, self.geometry, self.clock);
+            self.scheduler.next_request(arm, |request| positioning_time(head, geometry, clock, request))
+        };
+        let selection = match selection {
+            Some(selection) => selection,
+            None => return Ok(None),
         };
 
-        self.scheduler.queue.push(request);
-        self.process_io()?;
+        let mut timing = RequestTiming { queued: self.clock - selection.request.timestamp, ..Default::default() };
+        for cylinder in selection.sweep {
+            timing.seek += self.move_arm(cylinder, None)?;
+        }
+        let id = selection.request.id;
+        self.execute(selection.request, timing)?;
+        Ok(Some(id))
+    }
 
-        Ok(())
+    // Drains the queue in scheduling order
+    pub fn run(&mut self) -> StorageResult<Vec<IOCompletion>> {
+        while self.service_next()?.is_some() {}
+        Ok(self.completions.drain(..).collect())
     }
 
-    fn process_io(&mut self) -> StorageResult<()> {
-        while let Some(request) = self.scheduler.next_request() {
-            let head = &mut self.heads[request.surface as usize];
-            
-            // Perform seek if needed
-            if head.get_current_position().0 != request.track {
-                let seek_time = head.seek(request.track)?;
-                self.stats.total_seek_time += seek_time as u64;
-                self.stats.seeks += 1;
+    // Services requests until the given one has finished
+    pub fn wait(&mut self, id: u64) -> StorageResult<IOCompletion> {
+        loop {
+            if let Some(index) = self.completions.iter().position(|completion| completion.id == id) {
+                if let Some(completion) = self.completions.remove(index) {
+                    return Ok(completion);
+                }
+            }
+            if self.service_next()?.is_none() {
+                return Err(StorageError::NotFound);
             }
+        }
+    }
 
-            // Process request
-            match request.operation {
-                IOOperation::Read(_) => {
-                    let data = self.platters[request.surface as usize]
-                        .read_sector(request.surface, request.track, request.sector)?;
-                    self.stats.reads += 1;
-                }
-                IOOperation::Write(data) => {
-                    self.platters[request.surface as usize]
-                        .write_sector(request.surface, request.track, request.sector, &data)?;
-                    self.stats.writes += 1;
-                }
-                IOOperation::Seek(cylinder) => {
-                    head.seek(cylinder)?;
-                    self.stats.seeks += 1;
+    pub fn take_completions(&mut self) -> Vec<IOCompletion> {
+        self.completions.drain(..).collect()
+    }
+
+    pub fn read_sector(&mut self, surface: u32, track: u32, sector: u32) -> StorageResult<Vec<u8>> {
+        let id = self.submit(surface, track, sector, 1, IOOperation::Read)?;
+        Ok(self.wait(id)?.data)
+    }
+
+    pub fn write_sector(&mut self, surface: u32, track: u32, sector: u32, data: Vec<u8>) -> StorageResult<()> {
+        let id = self.submit(surface, track, sector, 1, IOOperation::Write(data))?;
+        self.wait(id).map(|_| ())
+    }
+
+    // Time passes with the drive idle; the platters keep spinning
+    pub fn advance(&mut self, nanoseconds: u64) {
+        self.clock += nanoseconds;
+    }
+
+    pub fn now(&self) -> u64 {
+        self.clock
+    }
+
+    pub fn set_algorithm(&mut self, algorithm: SchedulingAlgorithm) {
+        self.scheduler.set_algorithm(algorithm);
+    }
+
+    pub fn mark_bad_sector(&mut self, surface: u32, track: u32, sector: u32) -> StorageResult<()> {
+        let (platter, side) = self.platter_of(surface)?;
+        self.platters[platter].mark_bad_sector(side, track, sector)
+    }
+
+    pub fn get_stats(&self) -> &ControllerStats {
+        &self.stats
+    }
+
+    pub fn scheduler_stats(&self) -> &SchedulerStats {
+        self.scheduler.get_stats()
+    }
+
+    // Methods for visualization system
+    pub fn cylinders(&self) -> u32 {
+        self.geometry.cylinders
+    }
+
+    pub fn arm_trace(&self) -> &VecDeque<ArmMovement> {
+        &self.arm_trace
+    }
+
+    // Cylinder under the arm at any past instant, interpolated mid-seek
+    pub fn arm_position_at(&self, time: u64) -> f64 {
+        let mut position = self.arm_trace.front().map_or(self.head.get_current_position().0, |first| first.from_cylinder) as f64;
+        for movement in &self.arm_trace {
+            if time < movement.start {
+                break;
+            }
+            let end = movement.start + movement.duration;
+            if time >= end || movement.duration == 0 {
+                position = movement.to_cylinder as f64;
+                continue;
+            }
+            let progress = (time - movement.start) as f64 / movement.duration as f64;
+            let from = movement.from_cylinder as f64;
+            return from + (movement.to_cylinder as f64 - from) * progress;
+        }
+        position
+    }
+
+    // Fraction of a revolution the spindle has turned through, 0.0 to 1.0
+    pub fn platter_angle_at(&self, time: u64) -> f64 {
+        (time as f64 % self.geometry.revolution_time) / self.geometry.revolution_time
+    }
+
+    pub fn pending_requests(&self) -> &[IORequest] {
+        self.scheduler.pending()
+    }
+
+    pub fn get_current_position(&self) -> (u32, u32) {
+        self.head.get_current_position()
+    }
+
+    // Helper methods
+
+    fn execute(&mut self, request: IORequest, mut timing: RequestTiming) -> StorageResult<()> {
+        let geometry = self.geometry;
+        let bytes_per_sector = geometry.bytes_per_sector as usize;
+        let write = matches!(request.operation, IOOperation::Write(_));
+        let mut data = Vec::new();
+        let (mut surface, mut track, mut sector) = (request.surface, request.track, request.sector);
+        let mut done = 0;
+
+        while done < request.count {
+            // A head switch overlaps the seek, so only its excess costs time
+            let seek = self.move_arm(track, Some(request.id))?;
+            let switch = self.head.switch_head(surface)? as u64 * 1_000;
+            let settle = switch.saturating_sub(seek);
+            self.clock += settle;
+            timing.seek += seek + settle;
+
+            let wait = rotational_wait(geometry, self.clock, surface, track, sector);
+            self.clock += wait;
+            timing.rotation += wait;
+
+            let run = (request.count - done).min(geometry.sectors_per_track - sector);
+            let (platter, side) = self.platter_of(surface)?;
+            for offset in 0..run {
+                let key = CacheKey { surface, track, sector: sector + offset };
+                match &request.operation {
+                    IOOperation::Read => {
+                        let bytes = self.platters[platter].read_sector(side, track, sector + offset)?;
+                        self.cache.insert(key, bytes.clone())?;
+                        data.extend_from_slice(&bytes);
+                    }
+                    IOOperation::Write(source) => {
+                        let start = (done + offset) as usize * bytes_per_sector;
+                        let bytes = &source[start..start + bytes_per_sector];
+                        self.platters[platter].write_sector(side, track, sector + offset, bytes)?;
+                        self.cache.insert(key, bytes.to_vec())?;
+                    }
                 }
             }
+            let transfer = (run as f64 * geometry.revolution_time / geometry.sectors_per_track as f64) as u64;
+            self.clock += transfer;
+            timing.transfer += transfer;
+
+            done += run;
+            sector = 0;
+            surface += 1;
+            if surface == geometry.heads {
+                surface = 0;
+                track += 1;
+            }
+        }
+
+        if write {
+            self.stats.writes += 1;
+        } else {
+            self.stats.reads += 1;
+        }
+        self.stats.total_seek_time += timing.seek;
+        self.stats.total_rotation_time += timing.rotation;
+        self.stats.total_transfer_time += timing.transfer;
+        self.complete(&request, write, data, timing);
+        Ok(())
+    }
+
+    fn complete(&mut self, request: &IORequest, write: bool, data: Vec<u8>, timing: RequestTiming) {
+        let completion = IOCompletion {
+            id: request.id,
+            surface: request.surface,
+            track: request.track,
+            sector: request.sector,
+            count: request.count,
+            write,
+            data,
+            timing,
+            completed_at: self.clock,
+        };
+
+        let response = timing.response_time();
+        self.stats.completed += 1;
+        self.stats.total_response_time += response;
+        self.stats.max_response_time = self.stats.max_response_time.max(response);
+        self.stats.avg_response_time = self.stats.total_response_time as f32 / self.stats.completed as f32;
+        self.completions.push_back(completion);
+    }
+
+    // Seeks and records the movement; returns the time it took
+    fn move_arm(&mut self, cylinder: u32, request: Option<u64>) -> StorageResult<u64> {
+        let from = self.head.get_current_position().0;
+        let duration = self.head.seek(cylinder)? as u64 * 1_000;
+        if from != cylinder {
+            if self.arm_trace.len() == ARM_TRACE_LIMIT {
+                self.arm_trace.pop_front();
+            }
+            self.arm_trace.push_back(ArmMovement {
+                start: self.clock,
+                duration,
+                from_cylinder: from,
+                to_cylinder: cylinder,
+                request,
+            });
+            self.stats.seeks += 1;
+        }
+        self.clock += duration;
+        Ok(duration)
+    }
+
+    fn cached(&mut self, request: &IORequest) -> Option<Vec<u8>> {
+        let mut data = Vec::new();
+        for lba in 0..request.count as u64 {
+            let (surface, track, sector) = self.chs(request.lba + lba);
+            data.extend(self.cache.read(CacheKey { surface, track, sector }).ok()??);
+        }
+        Some(data)
+    }
+
+    fn platter_of(&self, surface: u32) -> StorageResult<(usize, u32)> {
+        if surface >= self.geometry.heads {
+            return Err(StorageError::InvalidSurface);
         }
+        let per_platter = self.geometry.surfaces_per_platter;
+        Ok(((surface / per_platter) as usize, surface % per_platter))
+    }
+
+    fn lba(&self, surface: u32, track: u32, sector: u32) -> u64 {
+        let geometry = self.geometry;
+        (track as u64 * geometry.heads as u64 + surface as u64) * geometry.sectors_per_track as u64 + sector as u64
+    }
+
+    fn chs(&self, lba: u64) -> (u32, u32, u32) {
+        let geometry = self.geometry;
+        let track_number = lba / geometry.sectors_per_track as u64;
+        (
+            (track_number % geometry.heads as u64) as u32,
+            (track_number / geometry.heads as u64) as u32,
+            (lba % geometry.sectors_per_track as u64) as u32,
+        )
+    }
+}
 
+// The hard disk is a block device like any other, with LBAs mapped onto cylinders
+impl BlockDevice for DiskController {
+    fn device_type(&self) -> BlockDeviceType {
+        BlockDeviceType::HardDisk
+    }
+
+    fn sector_size(&self) -> usize {
+        self.geometry.bytes_per_sector as usize
+    }
+
+    fn sector_count(&self) -> u64 {
+        let geometry = self.geometry;
+        geometry.cylinders as u64 * geometry.heads as u64 * geometry.sectors_per_track as u64
+    }
+
+    fn read_sectors(&mut self, sector: u64, count: u32, buffer: &mut [u8]) -> StorageResult<()> {
+        let length = count as usize * self.sector_size();
+        if buffer.len() < length {
+            return Err(StorageError::InvalidData);
+        }
+        let id = self.submit_lba(sector, count, IOOperation::Read)?;
+        let completion = self.wait(id)?;
+        buffer[..length].copy_from_slice(&completion.data);
         Ok(())
     }
 
-    fn get_current_time(&self) -> u64 {
-        // Implementation would use actual system time
-        0
+    // An empty write transfers nothing, so it never reaches the queue
+    fn write_sectors(&mut self, sector: u64, data: &[u8]) -> StorageResult<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if !data.len().is_multiple_of(self.sector_size()) {
+            return Err(StorageError::InvalidData);
+        }
+        let count = (data.len() / self.sector_size()) as u32;
+        let id = self.submit_lba(sector, count, IOOperation::Write(data.to_vec()))?;
+        self.wait(id).map(|_| ())
+    }
+
+    fn flush(&mut self) -> StorageResult<()> {
+        self.run().map(|_| ())
+    }
+}
+
+// Time until the start of a sector passes under the head
+fn rotational_wait(geometry: DiskGeometry, time: u64, surface: u32, track: u32, sector: u32) -> u64 {
+    let sectors = geometry.sectors_per_track as f64;
+    let sector_time = geometry.revolution_time / sectors;
+    let track_number = track as u64 * geometry.heads as u64 + surface as u64;
+    let slot = ((sector as u64 + track_number * geometry.track_skew as u64) % geometry.sectors_per_track as u64) as f64;
+    let position = (time as f64 % geometry.revolution_time) / sector_time;
+    ((slot - position).rem_euclid(sectors) * sector_time) as u64
+}
+
+// Seek plus rotational wait if this request were started now, for NCQ
+fn positioning_time(head: &DiskHead, geometry: DiskGeometry, clock: u64, request: &IORequest) -> u64 {
+    let (cylinder, current_head) = head.get_current_position();
+    let seek = head.seek_time_to(request.track) as u64 * 1_000;
+    let switch = if request.surface != current_head { head.head_switch_time() as u64 * 1_000 } else { 0 };
+    let ready = clock + if cylinder == request.track { switch } else { seek.max(switch) };
+    ready - clock + rotational_wait(geometry, ready, request.surface, request.track, request.sector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_completion_is_reported_once() {
+        let mut disk = DiskController::new(DiskConfig::laptop_5400rpm());
+        let first = disk.submit_lba(0, 1, IOOperation::Read).unwrap();
+        let second = disk.submit_lba(500_000, 1, IOOperation::Read).unwrap();
+
+        let mut served = Vec::new();
+        while let Some(id) = disk.service_next().unwrap() {
+            served.push(id);
+        }
+        served.sort_unstable();
+        assert_eq!(served, vec![first, second]);
+
+        let completions = disk.take_completions();
+        assert_eq!(completions.len(), 2);
+        assert!(disk.take_completions().is_empty());
+        assert_eq!(disk.get_stats().completed, 2);
+    }
+
+    #[test]
+    fn empty_write_never_reaches_the_queue() {
+        let mut disk = DiskController::new(DiskConfig::laptop_5400rpm());
+        disk.write_sectors(10, &[]).unwrap();
+        assert!(disk.pending_requests().is_empty());
+        assert!(disk.take_completions().is_empty());
+        assert_eq!(disk.get_stats().writes, 0);
     }
 }
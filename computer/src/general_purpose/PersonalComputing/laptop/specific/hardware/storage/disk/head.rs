@@ -1,96 +1,100 @@
 use super::super::error::{StorageError, StorageResult};
 
+// The actuator. Every head rides the same arm, so a seek moves them all and
+// switching heads only changes which surface is being read.
 pub struct DiskHead {
     current_cylinder: u32,
     current_head: u32,
-    seek_time: u32,        // Time to move between cylinders
-    rotational_delay: u32, // Average rotational latency
-    transfer_rate: u32,    // Data transfer rate
+    cylinders: u32,
+    config: HeadConfig,
     stats: HeadStats,
 }
 
-struct HeadStats {
-    seeks: u64,
-    sectors_read: u64,
-    sectors_written: u64,
-    total_seek_time: u64,
-    total_rotational_delay: u64,
+#[derive(Debug, Clone, Default)]
+pub struct HeadStats {
+    pub seeks: u64,
+    pub head_switches: u64,
+    pub cylinders_travelled: u64,
+    pub total_seek_time: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct HeadConfig {
+    pub track_to_track_time: u32, // One-cylinder seek including settle, microseconds
+    pub full_stroke_time: u32,    // Outermost to innermost cylinder, microseconds
+    pub head_switch_time: u32,    // Settling onto another surface, microseconds
 }
 
 impl DiskHead {
-    pub fn new(config: HeadConfig) -> Self {
+    pub fn new(config: HeadConfig, cylinders: u32) -> Self {
         Self {
             current_cylinder: 0,
             current_head: 0,
-            seek_time: config.seek_time,
-            rotational_delay: config.rotational_delay,
-            transfer_rate: config.transfer_rate,
+            cylinders: cylinders.max(1),
+            config,
             stats: HeadStats::default(),
         }
     }
 
     pub fn seek(&mut self, target_cylinder: u32) -> StorageResult<u32> {
+        if target_cylinder >= self.cylinders {
+            return Err(StorageError::InvalidAddress);
+        }
         if target_cylinder == self.current_cylinder {
             return Ok(0);
         }
 
-        let distance = (target_cylinder as i64 - self.current_cylinder as i64).abs() as u32;
+        let distance = target_cylinder.abs_diff(self.current_cylinder);
         let seek_time = self.calculate_seek_time(distance);
 
         self.current_cylinder = target_cylinder;
         self.stats.seeks += 1;
+        self.stats.cylinders_travelled += distance as u64;
         self.stats.total_seek_time += seek_time as u64;
 
         Ok(seek_time)
     }
 
-    pub fn switch_head(&mut self, head: u32) -> StorageResult<()> {
+    // What a seek would cost from here, for schedulers weighing their options
+    pub fn seek_time_to(&self, target_cylinder: u32) -> u32 {
+        self.calculate_seek_time(target_cylinder.abs_diff(self.current_cylinder))
+    }
+
+    pub fn switch_head(&mut self, head: u32) -> StorageResult<u32> {
         if head == self.current_head {
-            return Ok(());
+            return Ok(0);
         }
 
         self.current_head = head;
-        Ok(())
+        self.stats.head_switches += 1;
+        Ok(self.config.head_switch_time)
     }
 
-    pub fn read_sector(&mut self, sector: u32) -> StorageResult<Vec<u8>> {
-        let delay = self.calculate_rotational_delay();
-        self.stats.sectors_read += 1;
-        self.stats.total_rotational_delay += delay as u64;
-
-        // Simulate reading sector data
-        Ok(vec![0; 512])
-    }
-
-    pub fn write_sector(&mut self, sector: u32, data: &[u8]) -> StorageResult<()> {
-        let delay = self.calculate_rotational_delay();
-        self.stats.sectors_written += 1;
-        self.stats.total_rotational_delay += delay as u64;
-
-        Ok(())
+    pub fn head_switch_time(&self) -> u32 {
+        self.config.head_switch_time
     }
 
     fn calculate_seek_time(&self, distance: u32) -> u32 {
-        // Simple seek time model: base time + distance factor
-        self.seek_time + (distance * 100) // microseconds
-    }
-
-    fn calculate_rotational_delay(&self) -> u32 {
-        // Average rotational delay is half a revolution
-        self.rotational_delay / 2
+        if distance == 0 {
+            return 0;
+        }
+        // Short seeks are dominated by acceleration, so time grows with the
+        // square root of distance until the arm reaches full stroke
+        let span = (self.cylinders - 1).max(1) as f64;
+        let fraction = ((distance - 1) as f64 / span).sqrt();
+        let extra = self.config.full_stroke_time.saturating_sub(self.config.track_to_track_time);
+        self.config.track_to_track_time + (extra as f64 * fraction) as u32
     }
 
     pub fn get_current_position(&self) -> (u32, u32) {
         (self.current_cylinder, self.current_head)
     }
 
+    pub fn cylinders(&self) -> u32 {
+        self.cylinders
+    }
+
     pub fn get_stats(&self) -> &HeadStats {
         &self.stats
     }
 }
-
-pub struct HeadConfig {
-    seek_time: u32,        // Base seek time in microseconds
-    rotational_delay: u32, // Full rotation time in microseconds
-    transfer_rate: u32,    // Bytes per second
-}
@@ -3,3 +3,4 @@ pub mod cache;
 pub mod controller;
 pub mod head;
 pub mod platter;
+pub mod scheduler;
@@ -7,21 +7,24 @@ pub struct Platter {
     stats: PlatterStats,
 }
 
+// Tracks and sectors are only allocated once written; unwritten media reads as zeros
 struct Surface {
-    tracks: Vec<Track>,
+    tracks: HashMap<u32, Track>,
     defect_map: HashMap<(u32, u32), bool>, // (track, sector) -> is_defective
 }
 
 struct Track {
-    sectors: Vec<Sector>,
+    sectors: HashMap<u32, Sector>,
+    #[allow(dead_code)]
     track_id: u32,
 }
 
 struct Sector {
     data: Vec<u8>,
+    #[allow(dead_code)]
     sector_id: u32,
     error_correction: ECC,
-    flags: SectorFlags,
+    flags: u8,
 }
 
 struct ECC {
@@ -29,34 +32,25 @@ struct ECC {
     syndrome: Option<u32>,
 }
 
-bitflags! {
-    struct SectorFlags: u8 {
-        const VALID = 0x01;
-        const BAD = 0x02;
-        const REMAPPED = 0x04;
-        const PENDING_REMAP = 0x08;
-    }
-}
-
-struct PlatterConfig {
-    surfaces: u32,
-    tracks_per_surface: u32,
-    sectors_per_track: u32,
-    bytes_per_sector: u32,
+#[derive(Debug, Clone)]
+pub struct PlatterConfig {
+    pub surfaces: u32,
+    pub tracks_per_surface: u32,
+    pub sectors_per_track: u32,
+    pub bytes_per_sector: u32,
 }
 
-struct PlatterStats {
-    read_errors: u64,
-    write_errors: u64,
-    corrected_errors: u64,
-    remapped_sectors: u64,
+#[derive(Debug, Clone, Default)]
+pub struct PlatterStats {
+    pub read_errors: u64,
+    pub write_errors: u64,
+    pub corrected_errors: u64,
+    pub remapped_sectors: u64,
 }
 
 impl Platter {
     pub fn new(config: PlatterConfig) -> Self {
-        let surfaces = (0..config.surfaces)
-            .map(|_| Surface::new(config.tracks_per_surface, config.sectors_per_track, config.bytes_per_sector))
-            .collect();
+        let surfaces = (0..config.surfaces).map(|_| Surface::new()).collect();
 
         Self {
             surfaces,
@@ -66,11 +60,20 @@ impl Platter {
     }
 
     pub fn read_sector(&mut self, surface: u32, track: u32, sector: u32) -> StorageResult<Vec<u8>> {
-        let surface = self.get_surface(surface)?;
-        let track = surface.get_track(track)?;
-        let sector = track.get_sector(sector)?;
+        self.check_address(track, sector)?;
+        let bytes_per_sector = self.config.bytes_per_sector as usize;
+        // Borrow the field directly so the error counters stay writable
+        let surface = self.surfaces.get(surface as usize).ok_or(StorageError::InvalidSurface)?;
+        if surface.defect_map.get(&(track, sector)).copied().unwrap_or(false) {
+            self.stats.read_errors += 1;
+            return Err(StorageError::BadSector);
+        }
+        let sector = match surface.tracks.get(&track).and_then(|track| track.sectors.get(&sector)) {
+            Some(sector) => sector,
+            None => return Ok(vec![0; bytes_per_sector]),
+        };
 
-        if sector.flags.contains(SectorFlags::BAD) {
+        if sector.has_flag(Sector::BAD) {
             self.stats.read_errors += 1;
             return Err(StorageError::BadSector);
         }
@@ -89,33 +92,47 @@ impl Platter {
     }
 
     pub fn write_sector(&mut self, surface: u32, track: u32, sector: u32, data: &[u8]) -> StorageResult<()> {
-        let surface = self.get_surface_mut(surface)?;
-        let track = surface.get_track_mut(track)?;
-        let sector = track.get_sector_mut(sector)?;
+        self.check_address(track, sector)?;
+        if data.len() != self.config.bytes_per_sector as usize {
+            return Err(StorageError::InvalidData);
+        }
+        let bytes_per_sector = self.config.bytes_per_sector as usize;
+        let surface = self.surfaces.get_mut(surface as usize).ok_or(StorageError::InvalidSurface)?;
+        if surface.defect_map.get(&(track, sector)).copied().unwrap_or(false) {
+            self.stats.write_errors += 1;
+            return Err(StorageError::BadSector);
+        }
+        let sector = surface.sector_mut(track, sector, bytes_per_sector);
 
-        if sector.flags.contains(SectorFlags::BAD) {
+        if sector.has_flag(Sector::BAD) {
             self.stats.write_errors += 1;
             return Err(StorageError::BadSector);
         }
 
         sector.data.copy_from_slice(data);
         sector.error_correction.update(&sector.data);
+        sector.flags |= Sector::VALID;
         Ok(())
     }
 
     pub fn mark_bad_sector(&mut self, surface: u32, track: u32, sector: u32) -> StorageResult<()> {
+        self.check_address(track, sector)?;
         let surface = self.get_surface_mut(surface)?;
-        let track = surface.get_track_mut(track)?;
-        let sector = track.get_sector_mut(sector)?;
+        surface.defect_map.insert((track, sector), true);
 
-        sector.flags.insert(SectorFlags::BAD);
         self.stats.remapped_sectors += 1;
         Ok(())
     }
 
-    fn get_surface(&self, index: u32) -> StorageResult<&Surface> {
-        self.surfaces.get(index as usize)
-            .ok_or(StorageError::InvalidSurface)
+    pub fn get_stats(&self) -> &PlatterStats {
+        &self.stats
+    }
+
+    fn check_address(&self, track: u32, sector: u32) -> StorageResult<()> {
+        if track >= self.config.tracks_per_surface || sector >= self.config.sectors_per_track {
+            return Err(StorageError::InvalidAddress);
+        }
+        Ok(())
     }
 
     fn get_surface_mut(&mut self, index: u32) -> StorageResult<&mut Surface> {
@@ -123,3 +140,83 @@ impl Platter {
             .ok_or(StorageError::InvalidSurface)
     }
 }
+
+impl Surface {
+    fn new() -> Self {
+        Self {
+            tracks: HashMap::new(),
+            defect_map: HashMap::new(),
+        }
+    }
+
+    fn sector_mut(&mut self, track: u32, sector: u32, bytes_per_sector: usize) -> &mut Sector {
+        self.tracks
+            .entry(track)
+            .or_insert_with(|| Track { sectors: HashMap::new(), track_id: track })
+            .sectors
+            .entry(sector)
+            .or_insert_with(|| Sector::new(sector, bytes_per_sector))
+    }
+}
+
+impl Sector {
+    const VALID: u8 = 0x01;
+    const BAD: u8 = 0x02;
+
+    fn new(sector_id: u32, bytes_per_sector: usize) -> Self {
+        let data = vec![0; bytes_per_sector];
+        Self {
+            error_correction: ECC::new(&data),
+            data,
+            sector_id,
+            flags: 0,
+        }
+    }
+
+    fn check_ecc(&self) -> Option<u32> {
+        self.error_correction.check(&self.data)
+    }
+
+    fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+}
+
+impl ECC {
+    fn new(data: &[u8]) -> Self {
+        Self { code: Self::compute(data), syndrome: None }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.code = Self::compute(data);
+        self.syndrome = None;
+    }
+
+    // Nonzero syndrome means the data no longer matches its code
+    fn check(&self, data: &[u8]) -> Option<u32> {
+        let syndrome = Self::compute(data)
+            .iter()
+            .zip(&self.code)
+            .fold(0u32, |acc, (a, b)| (acc << 8) | (a ^ b) as u32);
+        if syndrome == 0 {
+            None
+        } else {
+            Some(syndrome)
+        }
+    }
+
+    // A detecting code only; real drives use Reed-Solomon or LDPC to also correct
+    fn correct_error(&self, _syndrome: u32) -> bool {
+        false
+    }
+
+    // Fletcher-16 style checksum, stored as two bytes
+    fn compute(data: &[u8]) -> Vec<u8> {
+        let (mut low, mut high) = (0u16, 0u16);
+        for byte in data {
+            low = (low + *byte as u16) % 255;
+            high = (high + low) % 255;
+        }
+        vec![high as u8, low as u8]
+    }
+}
@@ -0,0 +1,372 @@
+use super::super::error::{StorageError, StorageResult};
+
+// NCQ lets a drive hold at most 32 tagged commands
+pub const NCQ_MAX_DEPTH: usize = 32;
+
+// NCQ serves a request that has waited this long regardless of position, nanoseconds
+const NCQ_STARVATION_LIMIT: u64 = 250_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedulingAlgorithm {
+    FCFS,  // First Come First Served
+    SSTF,  // Shortest Seek Time First
+    SCAN,  // Elevator Algorithm
+    CSCAN, // Circular SCAN
+    LOOK,  // SCAN that turns at the last request instead of the disk edge
+    NCQ,   // Shortest positioning time, rotation included, over the tagged window
+}
+
+// Inward is towards higher cylinder numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inward,
+    Outward,
+}
+
+#[derive(Debug, Clone)]
+pub struct IORequest {
+    pub id: u64,
+    pub lba: u64,
+    pub surface: u32,
+    pub track: u32,
+    pub sector: u32,
+    pub count: u32,
+    pub operation: IOOperation,
+    pub priority: u8,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone)]
+pub enum IOOperation {
+    Read,
+    Write(Vec<u8>),
+}
+
+// The request to serve next, plus any cylinders the arm must sweep to first
+// (the disk edge for SCAN, the edge and back to zero for C-SCAN)
+pub struct Selection {
+    pub request: IORequest,
+    pub sweep: Vec<u32>,
+}
+
+// Where the arm is when a decision is made
+#[derive(Debug, Clone, Copy)]
+pub struct ArmState {
+    pub cylinder: u32,
+    pub max_cylinder: u32,
+    pub now: u64,
+}
+
+pub struct IOScheduler {
+    queue: Vec<IORequest>,
+    algorithm: SchedulingAlgorithm,
+    direction: Direction,
+    queue_depth: usize,
+    stats: SchedulerStats,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SchedulerStats {
+    pub submitted: u64,
+    pub dispatched: u64,
+    pub reordered: u64,
+    pub reversals: u64,
+    pub edge_sweeps: u64,
+    pub starvation_overrides: u64,
+}
+
+impl IOScheduler {
+    pub fn new(algorithm: SchedulingAlgorithm, queue_depth: usize) -> Self {
+        Self {
+            queue: Vec::new(),
+            algorithm,
+            direction: Direction::Inward,
+            queue_depth: queue_depth.clamp(1, NCQ_MAX_DEPTH),
+            stats: SchedulerStats::default(),
+        }
+    }
+
+    pub fn push(&mut self, request: IORequest) {
+        self.queue.push(request);
+        self.stats.submitted += 1;
+    }
+
+    // Picks the next request. `positioning` estimates seek plus rotational wait
+    // for a request and is only consulted by NCQ.
+    pub fn next_request<F>(&mut self, arm: ArmState, positioning: F) -> Option<Selection>
+    where
+        F: Fn(&IORequest) -> u64,
+    {
+        if self.queue.is_empty() {
+            return None;
+        }
+
+        let eligible = self.eligible();
+        let (index, sweep) = match self.algorithm {
+            SchedulingAlgorithm::FCFS => (0, Vec::new()),
+            SchedulingAlgorithm::SSTF => (self.nearest(arm.cylinder, &eligible), Vec::new()),
+            SchedulingAlgorithm::SCAN => self.elevator(arm, true, &eligible),
+            SchedulingAlgorithm::LOOK => self.elevator(arm, false, &eligible),
+            SchedulingAlgorithm::CSCAN => self.circular(arm, &eligible),
+            SchedulingAlgorithm::NCQ => (self.shortest_positioning(arm, positioning, &eligible), Vec::new()),
+        };
+
+        if index != 0 {
+            self.stats.reordered += 1;
+        }
+        self.stats.dispatched += 1;
+        Some(Selection { request: self.queue.remove(index), sweep })
+    }
+
+    pub fn pending(&self) -> &[IORequest] {
+        &self.queue
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn algorithm(&self) -> SchedulingAlgorithm {
+        self.algorithm
+    }
+
+    pub fn set_algorithm(&mut self, algorithm: SchedulingAlgorithm) {
+        self.algorithm = algorithm;
+    }
+
+    pub fn set_queue_depth(&mut self, depth: usize) -> StorageResult<()> {
+        if depth == 0 || depth > NCQ_MAX_DEPTH {
+            return Err(StorageError::InvalidQueue);
+        }
+        self.queue_depth = depth;
+        Ok(())
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    pub fn get_stats(&self) -> &SchedulerStats {
+        &self.stats
+    }
+
+    // True if a queued write overlaps the range
+    pub fn conflicts_with(&self, lba: u64, count: u32) -> bool {
+        self.queue.iter().any(|request| {
+            matches!(request.operation, IOOperation::Write(_)) && overlaps(request, lba, count)
+        })
+    }
+
+    // Helper methods
+
+    // A request may not pass an earlier overlapping one when either of them writes
+    fn eligible(&self) -> Vec<bool> {
+        (0..self.queue.len())
+            .map(|index| {
+                let request = &self.queue[index];
+                let writes = matches!(request.operation, IOOperation::Write(_));
+                !self.queue[..index].iter().any(|earlier| {
+                    (writes || matches!(earlier.operation, IOOperation::Write(_)))
+                        && overlaps(earlier, request.lba, request.count)
+                })
+            })
+            .collect()
+    }
+
+    // Ties go to the oldest request, which min_by_key guarantees by keeping the first
+    fn nearest(&self, cylinder: u32, eligible: &[bool]) -> usize {
+        self.queue
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| eligible[*index])
+            .min_by_key(|(_, request)| request.track.abs_diff(cylinder))
+            .map(|(index, _)| index)
+            .unwrap_or(0)
+    }
+
+    // The closest request at or beyond the arm in the current direction
+    fn ahead(&self, cylinder: u32, direction: Direction, eligible: &[bool]) -> Option<usize> {
+        self.queue
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| eligible[*index])
+            .filter(|(_, request)| match direction {
+                Direction::Inward => request.track >= cylinder,
+                Direction::Outward => request.track <= cylinder,
+            })
+            .min_by_key(|(_, request)| request.track.abs_diff(cylinder))
+            .map(|(index, _)| index)
+    }
+
+    fn elevator(&mut self, arm: ArmState, to_edge: bool, eligible: &[bool]) -> (usize, Vec<u32>) {
+        if let Some(index) = self.ahead(arm.cylinder, self.direction, eligible) {
+            return (index, Vec::new());
+        }
+
+        // Nothing left this way: SCAN carries on to the edge before turning, LOOK turns now
+        let edge = match self.direction {
+            Direction::Inward => arm.max_cylinder,
+            Direction::Outward => 0,
+        };
+        let mut sweep = Vec::new();
+        if to_edge && arm.cylinder != edge {
+            sweep.push(edge);
+            self.stats.edge_sweeps += 1;
+        }
+        self.direction = match self.direction {
+            Direction::Inward => Direction::Outward,
+            Direction::Outward => Direction::Inward,
+        };
+        self.stats.reversals += 1;
+
+        let from = if sweep.is_empty() { arm.cylinder } else { edge };
+        (self.ahead(from, self.direction, eligible).unwrap_or(0), sweep)
+    }
+
+    // Serves inward only; at the edge the arm flies back to cylinder zero without serving
+    fn circular(&mut self, arm: ArmState, eligible: &[bool]) -> (usize, Vec<u32>) {
+        self.direction = Direction::Inward;
+        if let Some(index) = self.ahead(arm.cylinder, Direction::Inward, eligible) {
+            return (index, Vec::new());
+        }
+
+        let mut sweep = Vec::new();
+        if arm.cylinder != arm.max_cylinder {
+            sweep.push(arm.max_cylinder);
+        }
+        sweep.push(0);
+        self.stats.edge_sweeps += 1;
+        (self.ahead(0, Direction::Inward, eligible).unwrap_or(0), sweep)
+    }
+
+    // Only tagged commands are visible to the drive, and aged ones jump the line
+    fn shortest_positioning<F>(&mut self, arm: ArmState, positioning: F, eligible: &[bool]) -> usize
+    where
+        F: Fn(&IORequest) -> u64,
+    {
+        let window = self.queue.len().min(self.queue_depth);
+        let oldest = (0..window).min_by_key(|&index| self.queue[index].timestamp).unwrap_or(0);
+        if arm.now.saturating_sub(self.queue[oldest].timestamp) >= NCQ_STARVATION_LIMIT {
+            self.stats.starvation_overrides += 1;
+            return oldest;
+        }
+
+        (0..window)
+            .filter(|&index| eligible[index])
+            .min_by_key(|&index| positioning(&self.queue[index]))
+            .unwrap_or(0)
+    }
+}
+
+fn overlaps(request: &IORequest, lba: u64, count: u32) -> bool {
+    request.lba < lba.saturating_add(count as u64) && lba < request.lba.saturating_add(request.count as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_CYLINDER: u32 = 199;
+
+    fn request(id: u64, track: u32, lba: u64, operation: IOOperation, timestamp: u64) -> IORequest {
+        IORequest { id, lba, surface: 0, track, sector: 0, count: 8, operation, priority: 0, timestamp }
+    }
+
+    fn read(id: u64, track: u32) -> IORequest {
+        request(id, track, id * 1000, IOOperation::Read, id)
+    }
+
+    fn arm(cylinder: u32, now: u64) -> ArmState {
+        ArmState { cylinder, max_cylinder: MAX_CYLINDER, now }
+    }
+
+    fn next(scheduler: &mut IOScheduler, cylinder: u32) -> Selection {
+        scheduler.next_request(arm(cylinder, 0), |_| 0).unwrap()
+    }
+
+    #[test]
+    fn scan_runs_to_the_edge_before_turning_and_look_does_not() {
+        for (algorithm, sweep) in [(SchedulingAlgorithm::SCAN, vec![MAX_CYLINDER]), (SchedulingAlgorithm::LOOK, vec![])] {
+            let mut scheduler = IOScheduler::new(algorithm, NCQ_MAX_DEPTH);
+            scheduler.push(read(1, 10));
+            scheduler.push(read(2, 30));
+
+            let selection = next(&mut scheduler, 50);
+            assert_eq!((selection.request.id, selection.sweep), (2, sweep));
+            assert_eq!(scheduler.direction(), Direction::Outward);
+            assert_eq!(scheduler.get_stats().reversals, 1);
+            assert!(next(&mut scheduler, 30).sweep.is_empty());
+        }
+    }
+
+    #[test]
+    fn cscan_flies_back_to_cylinder_zero() {
+        let mut scheduler = IOScheduler::new(SchedulingAlgorithm::CSCAN, NCQ_MAX_DEPTH);
+        scheduler.push(read(1, 60));
+        scheduler.push(read(2, 20));
+        scheduler.push(read(3, 150));
+
+        assert_eq!(next(&mut scheduler, 100).request.id, 3);
+        let selection = next(&mut scheduler, 150);
+        assert_eq!((selection.request.id, selection.sweep), (2, vec![MAX_CYLINDER, 0]));
+        assert_eq!(scheduler.direction(), Direction::Inward);
+        assert_eq!(next(&mut scheduler, 20).request.id, 1);
+    }
+
+    #[test]
+    fn sstf_breaks_ties_in_favour_of_the_oldest_request() {
+        for (first, second) in [(40, 60), (60, 40)] {
+            let mut scheduler = IOScheduler::new(SchedulingAlgorithm::SSTF, NCQ_MAX_DEPTH);
+            scheduler.push(read(1, first));
+            scheduler.push(read(2, second));
+            scheduler.push(read(3, 51));
+            assert_eq!(next(&mut scheduler, 50).request.id, 3);
+            assert_eq!(next(&mut scheduler, 50).request.id, 1);
+        }
+    }
+
+    #[test]
+    fn ncq_serves_a_starved_request_regardless_of_position() {
+        let far = request(1, 190, 0, IOOperation::Read, 1_000);
+        let near = request(2, 10, 5_000, IOOperation::Read, 2_000);
+        let positioning = |request: &IORequest| request.track as u64;
+
+        let mut scheduler = IOScheduler::new(SchedulingAlgorithm::NCQ, NCQ_MAX_DEPTH);
+        scheduler.push(far.clone());
+        scheduler.push(near.clone());
+        let early = scheduler.next_request(arm(0, 1_000 + NCQ_STARVATION_LIMIT - 1), positioning).unwrap();
+        assert_eq!(early.request.id, 2);
+        assert_eq!(scheduler.get_stats().starvation_overrides, 0);
+
+        scheduler.push(near);
+        let late = scheduler.next_request(arm(0, 1_000 + NCQ_STARVATION_LIMIT), positioning).unwrap();
+        assert_eq!(late.request.id, 1);
+        assert_eq!(scheduler.get_stats().starvation_overrides, 1);
+    }
+
+    #[test]
+    fn reads_never_pass_an_earlier_overlapping_write() {
+        let mut scheduler = IOScheduler::new(SchedulingAlgorithm::SSTF, NCQ_MAX_DEPTH);
+        scheduler.push(request(1, 150, 100, IOOperation::Write(vec![0; 4096]), 1));
+        scheduler.push(request(2, 50, 104, IOOperation::Read, 2));
+        scheduler.push(request(3, 60, 900, IOOperation::Read, 3));
+
+        // The disjoint read may jump ahead, the overlapping one waits for the write
+        assert!(scheduler.conflicts_with(107, 1));
+        assert_eq!(next(&mut scheduler, 50).request.id, 3);
+        assert_eq!(next(&mut scheduler, 60).request.id, 1);
+        assert_eq!(next(&mut scheduler, 150).request.id, 2);
+    }
+
+    #[test]
+    fn ranges_at_the_end_of_the_lba_space_do_not_overflow() {
+        let mut scheduler = IOScheduler::new(SchedulingAlgorithm::FCFS, NCQ_MAX_DEPTH);
+        scheduler.push(request(1, 0, u64::MAX - 4, IOOperation::Write(Vec::new()), 1));
+        assert!(scheduler.conflicts_with(u64::MAX - 1, 8));
+        assert!(!scheduler.conflicts_with(0, u32::MAX));
+    }
+}
@@ -0,0 +1,47 @@
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageError {
+    // Media errors
+    BadSector,
+    BadBlock,
+    BlockWearout,
+    UncorrectableError,
+    InvalidSurface,
+    InvalidAddress,
+    InvalidData,
+    PageNotFree,
+    NoFreeBlocks,
+
+    // Controller errors
+    NotReady,
+    IdentifyFailed,
+    InvalidQueue,
+    QueueFull,
+    QueueEmpty,
+
+    // Filesystem errors
+    InvalidFileSystem(String),
+    Corrupted(String),
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    InvalidName,
+    NoSpace,
+    ReadOnly,
+    Unsupported(String),
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+impl StorageError {
+    pub fn is_media_error(&self) -> bool {
+        matches!(
+            self,
+            StorageError::BadSector
+                | StorageError::BadBlock
+                | StorageError::BlockWearout
+                | StorageError::UncorrectableError
+        )
+    }
+}
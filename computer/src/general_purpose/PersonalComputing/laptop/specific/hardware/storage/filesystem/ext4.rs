@@ -1,123 +1,1492 @@
+use super::super::device::BlockDevice;
 use super::super::error::{StorageError, StorageResult};
 use std::collections::HashMap;
 
-pub struct Ext4FileSystem {
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const EXT4_MAGIC: u16 = 0xEF53;
+const EXTENT_MAGIC: u16 = 0xF30A;
+const JBD2_MAGIC: u32 = 0xC03B_3998;
+const ROOT_INODE: u32 = 2;
+const MAX_SYMLINK_DEPTH: u32 = 40;
+
+pub struct Ext4FileSystem<D: BlockDevice> {
+    device: D,
     superblock: Superblock,
     block_groups: Vec<BlockGroup>,
     inode_table: HashMap<u32, Inode>,
-    journal: Journal,
-    cache: FSCache,
+    journal: Option<Journal>,
+    stats: Ext4Stats,
 }
 
 struct Superblock {
-    block_size: u32,
+    inodes_count: u32,
     blocks_count: u64,
     free_blocks_count: u64,
-    inodes_count: u32,
     free_inodes_count: u32,
-    mtime: u64,
+    first_data_block: u32,
+    block_size: u32,
+    blocks_per_group: u32,
+    inodes_per_group: u32,
+    inode_size: u16,
+    desc_size: u16,
+    mtime: u32,
+    journal_inode: u32,
+    hash_seed: [u32; 4],
+    unsigned_hash: bool,
+    volume_name: String,
     features: Features,
 }
 
-struct BlockGroup {
-    block_bitmap: Vec<u8>,
-    inode_bitmap: Vec<u8>,
-    inode_table_blocks: Vec<u32>,
-    free_blocks_count: u32,
-    free_inodes_count: u32,
-    directories_count: u16,
+#[derive(Clone, Copy)]
+struct Features {
+    compat: u32,
+    incompat: u32,
+    ro_compat: u32,
 }
 
+#[derive(Debug, Clone)]
+pub struct BlockGroup {
+    pub block_bitmap: u64,
+    pub inode_bitmap: u64,
+    pub inode_table: u64,
+    pub free_blocks_count: u32,
+    pub free_inodes_count: u32,
+    pub directories_count: u32,
+}
+
+#[derive(Clone)]
 struct Inode {
     mode: u16,
+    uid: u32,
+    gid: u32,
     size: u64,
-    atime: u64,
-    ctime: u64,
-    mtime: u64,
-    blocks: Vec<u32>,
-    flags: InodeFlags,
-    extended_attrs: HashMap<String, Vec<u8>>,
+    atime: u32,
+    ctime: u32,
+    mtime: u32,
+    links_count: u16,
+    flags: u32,
+    block: [u8; 60],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Extent {
+    pub logical_block: u32,
+    pub physical_block: u64,
+    pub length: u16,
+    pub initialized: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub inode: u32,
+    pub file_type: FileType,
 }
 
+#[derive(Debug, Clone)]
+pub struct InodeInfo {
+    pub inode: u32,
+    pub file_type: FileType,
+    pub permissions: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub links: u16,
+    pub atime: u32,
+    pub ctime: u32,
+    pub mtime: u32,
+}
+
+// jbd2 journal state, as found in the journal superblock
 struct Journal {
-    transactions: VecDeque<Transaction>,
-    current_transaction: Option<Transaction>,
-    checkpoint_interval: u32,
+    inode: u32,
+    block_size: u32,
+    max_len: u32,
+    first: u32,
+    sequence: u32,
+    start: u32,
+    incompat: u32,
     stats: JournalStats,
 }
 
-impl Ext4FileSystem {
-    pub fn new(device: &mut DiskController) -> StorageResult<Self> {
-        // Read superblock
-        let superblock = Self::read_superblock(device)?;
-        
-        // Initialize filesystem structures
+#[derive(Debug, Clone, Default)]
+pub struct JournalStats {
+    pub transactions_replayed: u64,
+    pub blocks_replayed: u64,
+    pub blocks_revoked: u64,
+}
+
+// A committed transaction found while scanning the log
+struct Transaction {
+    sequence: u32,
+    blocks: Vec<JournalBlock>,
+    revoked: Vec<u64>,
+}
+
+struct JournalBlock {
+    target: u64,
+    log_block: u32,
+    escaped: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Ext4Stats {
+    pub inodes_read: u64,
+    pub blocks_read: u64,
+    pub extent_lookups: u64,
+    pub htree_lookups: u64,
+    pub linear_lookups: u64,
+}
+
+impl Features {
+    const COMPAT_HAS_JOURNAL: u32 = 0x0004;
+    const COMPAT_DIR_INDEX: u32 = 0x0020;
+
+    const INCOMPAT_COMPRESSION: u32 = 0x0001;
+    const INCOMPAT_FILETYPE: u32 = 0x0002;
+    const INCOMPAT_RECOVER: u32 = 0x0004;
+    const INCOMPAT_JOURNAL_DEV: u32 = 0x0008;
+    const INCOMPAT_META_BG: u32 = 0x0010;
+    const INCOMPAT_64BIT: u32 = 0x0080;
+    const INCOMPAT_ENCRYPT: u32 = 0x10000;
+    const INCOMPAT_CASEFOLD: u32 = 0x20000;
+
+    const RO_COMPAT_METADATA_CSUM: u32 = 0x0400;
+
+    // Anything that changes how blocks or names are laid out must be understood to mount
+    const INCOMPAT_UNSUPPORTED: u32 = Self::INCOMPAT_COMPRESSION
+        | Self::INCOMPAT_JOURNAL_DEV
+        | Self::INCOMPAT_META_BG
+        | Self::INCOMPAT_ENCRYPT
+        | Self::INCOMPAT_CASEFOLD;
+
+    fn has_incompat(&self, flag: u32) -> bool {
+        self.incompat & flag != 0
+    }
+}
+
+impl<D: BlockDevice> Ext4FileSystem<D> {
+    pub fn mount(mut device: D) -> StorageResult<Self> {
+        let mut raw = vec![0u8; 1024];
+        device.read_bytes(SUPERBLOCK_OFFSET, &mut raw)?;
+        let superblock = Superblock::parse(&raw)?;
+
         let mut fs = Self {
+            device,
             superblock,
             block_groups: Vec::new(),
             inode_table: HashMap::new(),
-            journal: Journal::new(),
-            cache: FSCache::new(),
+            journal: None,
+            stats: Ext4Stats::default(),
         };
 
         // Load block groups
-        fs.load_block_groups(device)?;
-        
+        fs.load_block_groups()?;
+
+        if fs.superblock.features.compat & Features::COMPAT_HAS_JOURNAL != 0 && fs.superblock.journal_inode != 0 {
+            fs.journal = Some(fs.load_journal()?);
+            if fs.superblock.features.has_incompat(Features::INCOMPAT_RECOVER) {
+                fs.replay_journal()?;
+            }
+        }
         Ok(fs)
     }
 
-    pub fn create_file(&mut self, path: &str, mode: u16) -> StorageResult<u32> {
-        // Allocate new inode
-        let inode_num = self.allocate_inode()?;
-        
-        // Initialize inode
-        let inode = Inode {
-            mode,
-            size: 0,
-            atime: self.get_current_time(),
-            ctime: self.get_current_time(),
-            mtime: self.get_current_time(),
-            blocks: Vec::new(),
-            flags: InodeFlags::empty(),
-            extended_attrs: HashMap::new(),
+    pub fn unmount(mut self) -> StorageResult<D> {
+        self.device.flush()?;
+        Ok(self.device)
+    }
+
+    pub fn block_size(&self) -> u32 {
+        self.superblock.block_size
+    }
+
+    pub fn volume_name(&self) -> &str {
+        &self.superblock.volume_name
+    }
+
+    // Unix time of the last mount, as recorded by whoever mounted it last
+    pub fn last_mounted(&self) -> u32 {
+        self.superblock.mtime
+    }
+
+    pub fn block_groups(&self) -> &[BlockGroup] {
+        &self.block_groups
+    }
+
+    pub fn get_stats(&self) -> &Ext4Stats {
+        &self.stats
+    }
+
+    pub fn journal_stats(&self) -> Option<&JournalStats> {
+        self.journal.as_ref().map(|journal| &journal.stats)
+    }
+
+    pub fn free_blocks(&self) -> u64 {
+        self.superblock.free_blocks_count
+    }
+
+    pub fn free_inodes(&self) -> u32 {
+        self.superblock.free_inodes_count
+    }
+
+    pub fn read_dir(&mut self, path: &str) -> StorageResult<Vec<DirEntry>> {
+        let ino = self.resolve(path, true)?;
+        self.read_dir_inode(ino)
+    }
+
+    pub fn metadata(&mut self, path: &str) -> StorageResult<InodeInfo> {
+        let ino = self.resolve(path, true)?;
+        self.inode_metadata(ino)
+    }
+
+    pub fn read_file(&mut self, path: &str) -> StorageResult<Vec<u8>> {
+        let ino = self.resolve(path, true)?;
+        let inode = self.get_inode(ino)?;
+        let mut data = vec![0u8; inode.size as usize];
+        self.read_inode_data(&inode, 0, &mut data)?;
+        Ok(data)
+    }
+
+    pub fn read_at(&mut self, path: &str, offset: u64, buffer: &mut [u8]) -> StorageResult<usize> {
+        let ino = self.resolve(path, true)?;
+        self.read_inode_at(ino, offset, buffer)
+    }
+
+    pub fn read_link(&mut self, path: &str) -> StorageResult<String> {
+        let ino = self.resolve(path, false)?;
+        self.read_link_inode(ino)
+    }
+
+    // Inode-level access, for callers such as the VFS that walk paths themselves
+
+    pub fn root_inode(&self) -> u32 {
+        ROOT_INODE
+    }
+
+    pub fn lookup_inode(&mut self, dir_ino: u32, name: &str) -> StorageResult<Option<u32>> {
+        self.lookup(dir_ino, name)
+    }
+
+    pub fn read_dir_inode(&mut self, ino: u32) -> StorageResult<Vec<DirEntry>> {
+        let inode = self.get_inode(ino)?;
+        if inode.file_type() != FileType::Directory {
+            return Err(StorageError::NotADirectory);
+        }
+
+        // Hashed directories still keep every entry in ordinary leaf blocks
+        let mut entries = Vec::new();
+        for block in 0..inode.block_count(self.superblock.block_size) {
+            let data = self.read_logical_block(&inode, block)?;
+            for entry in parse_dir_block(&data, self.has_filetype()) {
+                if entry.name != "." && entry.name != ".." {
+                    entries.push(entry);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    pub fn inode_metadata(&mut self, ino: u32) -> StorageResult<InodeInfo> {
+        let inode = self.get_inode(ino)?;
+        Ok(InodeInfo {
+            inode: ino,
+            file_type: inode.file_type(),
+            permissions: inode.mode & 0o7777,
+            uid: inode.uid,
+            gid: inode.gid,
+            size: inode.size,
+            links: inode.links_count,
+            atime: inode.atime,
+            ctime: inode.ctime,
+            mtime: inode.mtime,
+        })
+    }
+
+    pub fn read_inode_at(&mut self, ino: u32, offset: u64, buffer: &mut [u8]) -> StorageResult<usize> {
+        let inode = self.get_inode(ino)?;
+        if inode.file_type() == FileType::Directory {
+            return Err(StorageError::IsADirectory);
+        }
+        self.read_inode_data(&inode, offset, buffer)
+    }
+
+    pub fn read_link_inode(&mut self, ino: u32) -> StorageResult<String> {
+        let inode = self.get_inode(ino)?;
+        if inode.file_type() != FileType::Symlink {
+            return Err(StorageError::InvalidName);
+        }
+        self.symlink_target(&inode)
+    }
+
+    // The extent map of a file, for the visualizer to draw where its data lives
+    pub fn extents(&mut self, path: &str) -> StorageResult<Vec<Extent>> {
+        let ino = self.resolve(path, true)?;
+        let inode = self.get_inode(ino)?;
+        if !inode.has_flag(Inode::EXTENTS_FL) {
+            return Err(StorageError::Unsupported("inode uses indirect block maps".into()));
+        }
+        let mut extents = Vec::new();
+        self.collect_extents(&inode.block, &mut extents, 0)?;
+        Ok(extents)
+    }
+
+    // Helper methods
+
+    fn has_filetype(&self) -> bool {
+        self.superblock.features.has_incompat(Features::INCOMPAT_FILETYPE)
+    }
+
+    fn read_block(&mut self, block: u64) -> StorageResult<Vec<u8>> {
+        if block >= self.superblock.blocks_count {
+            return Err(StorageError::Corrupted(format!("block {} beyond end of filesystem", block)));
+        }
+        let size = self.superblock.block_size as usize;
+        let mut data = vec![0u8; size];
+        self.device.read_bytes(block * size as u64, &mut data)?;
+        self.stats.blocks_read += 1;
+        Ok(data)
+    }
+
+    fn load_block_groups(&mut self) -> StorageResult<()> {
+        let sb = &self.superblock;
+        let group_count = (sb.blocks_count - sb.first_data_block as u64).div_ceil(sb.blocks_per_group as u64) as usize;
+        let desc_size = sb.desc_size as usize;
+        let table_offset = (sb.first_data_block as u64 + 1) * sb.block_size as u64;
+
+        let mut table = vec![0u8; group_count * desc_size];
+        self.device.read_bytes(table_offset, &mut table)?;
+
+        let wide = desc_size >= 64;
+        let hi32 = |raw: &[u8], offset: usize| if wide { (read_u32(raw, offset) as u64) << 32 } else { 0 };
+        let hi16 = |raw: &[u8], offset: usize| if wide { (read_u16(raw, offset) as u32) << 16 } else { 0 };
+        self.block_groups = table
+            .chunks_exact(desc_size)
+            .map(|raw| BlockGroup {
+                block_bitmap: read_u32(raw, 0x00) as u64 | hi32(raw, 0x20),
+                inode_bitmap: read_u32(raw, 0x04) as u64 | hi32(raw, 0x24),
+                inode_table: read_u32(raw, 0x08) as u64 | hi32(raw, 0x28),
+                free_blocks_count: read_u16(raw, 0x0C) as u32 | hi16(raw, 0x2C),
+                free_inodes_count: read_u16(raw, 0x0E) as u32 | hi16(raw, 0x2E),
+                directories_count: read_u16(raw, 0x10) as u32 | hi16(raw, 0x30),
+            })
+            .collect();
+        Ok(())
+    }
+
+    fn get_inode(&mut self, ino: u32) -> StorageResult<Inode> {
+        if let Some(inode) = self.inode_table.get(&ino) {
+            return Ok(inode.clone());
+        }
+        if ino == 0 || ino > self.superblock.inodes_count {
+            return Err(StorageError::Corrupted(format!("inode {} out of range", ino)));
+        }
+
+        let group = ((ino - 1) / self.superblock.inodes_per_group) as usize;
+        let index = ((ino - 1) % self.superblock.inodes_per_group) as u64;
+        let table = self
+            .block_groups
+            .get(group)
+            .ok_or_else(|| StorageError::Corrupted(format!("inode {} has no block group", ino)))?
+            .inode_table;
+        let offset = table * self.superblock.block_size as u64 + index * self.superblock.inode_size as u64;
+
+        let mut raw = [0u8; 128];
+        self.device.read_bytes(offset, &mut raw)?;
+        let inode = Inode::parse(&raw);
+        self.stats.inodes_read += 1;
+        self.inode_table.insert(ino, inode.clone());
+        Ok(inode)
+    }
+
+    fn read_inode_data(&mut self, inode: &Inode, offset: u64, buffer: &mut [u8]) -> StorageResult<usize> {
+        if inode.has_flag(Inode::INLINE_DATA_FL) {
+            return Err(StorageError::Unsupported("inline data".into()));
+        }
+        if offset >= inode.size {
+            return Ok(0);
+        }
+
+        let block_size = self.superblock.block_size as u64;
+        let length = buffer.len().min((inode.size - offset) as usize);
+        let mut done = 0usize;
+        while done < length {
+            let position = offset + done as u64;
+            let within = (position % block_size) as usize;
+            let chunk = (block_size as usize - within).min(length - done);
+            let data = self.read_logical_block(inode, (position / block_size) as u32)?;
+            buffer[done..done + chunk].copy_from_slice(&data[within..within + chunk]);
+            done += chunk;
+        }
+        Ok(length)
+    }
+
+    // Holes and unwritten extents read back as zeros
+    fn read_logical_block(&mut self, inode: &Inode, logical: u32) -> StorageResult<Vec<u8>> {
+        match self.map_block(inode, logical)? {
+            Some(physical) => self.read_block(physical),
+            None => Ok(vec![0u8; self.superblock.block_size as usize]),
+        }
+    }
+
+    fn map_block(&mut self, inode: &Inode, logical: u32) -> StorageResult<Option<u64>> {
+        if inode.has_flag(Inode::EXTENTS_FL) {
+            self.stats.extent_lookups += 1;
+            self.map_extent(&inode.block, logical, 0)
+        } else {
+            self.map_indirect(inode, logical)
+        }
+    }
+
+    fn map_extent(&mut self, node: &[u8], logical: u32, depth: u32) -> StorageResult<Option<u64>> {
+        let header = ExtentHeader::parse(node)?;
+        if depth > 5 {
+            return Err(StorageError::Corrupted("extent tree too deep".into()));
+        }
+
+        if header.depth == 0 {
+            for i in 0..header.entries as usize {
+                let extent = parse_extent(&node[12 + i * 12..]);
+                let start = extent.logical_block;
+                if logical >= start && logical < start + extent.length as u32 {
+                    if !extent.initialized {
+                        return Ok(None);
+                    }
+                    return Ok(Some(extent.physical_block + (logical - start) as u64));
+                }
+            }
+            return Ok(None);
+        }
+
+        // Descend into the last index whose first block is <= the target
+        let mut child = None;
+        for i in 0..header.entries as usize {
+            let entry = &node[12 + i * 12..];
+            if read_u32(entry, 0) > logical {
+                break;
+            }
+            child = Some(read_u32(entry, 4) as u64 | (read_u16(entry, 8) as u64) << 32);
+        }
+        match child {
+            Some(block) => {
+                let data = self.read_block(block)?;
+                self.map_extent(&data, logical, depth + 1)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn collect_extents(&mut self, node: &[u8], extents: &mut Vec<Extent>, depth: u32) -> StorageResult<()> {
+        let header = ExtentHeader::parse(node)?;
+        if depth > 5 {
+            return Err(StorageError::Corrupted("extent tree too deep".into()));
+        }
+        for i in 0..header.entries as usize {
+            let entry = &node[12 + i * 12..];
+            if header.depth == 0 {
+                extents.push(parse_extent(entry));
+            } else {
+                let block = read_u32(entry, 4) as u64 | (read_u16(entry, 8) as u64) << 32;
+                let data = self.read_block(block)?;
+                self.collect_extents(&data, extents, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    // ext2/3-style maps: 12 direct pointers, then single, double and triple indirect blocks
+    fn map_indirect(&mut self, inode: &Inode, logical: u32) -> StorageResult<Option<u64>> {
+        let per_block = self.superblock.block_size / 4;
+        let pointer = |index: u32| read_u32(&inode.block, index as usize * 4);
+
+        if logical < 12 {
+            return Ok(Some(pointer(logical) as u64).filter(|&block| block != 0));
+        }
+        let mut remaining = logical - 12;
+        let (root, levels) = if remaining < per_block {
+            (pointer(12), 1)
+        } else if remaining - per_block < per_block * per_block {
+            remaining -= per_block;
+            (pointer(13), 2)
+        } else {
+            remaining -= per_block + per_block * per_block;
+            (pointer(14), 3)
+        };
+
+        let mut block = root;
+        for level in (0..levels).rev() {
+            if block == 0 {
+                return Ok(None);
+            }
+            let data = self.read_block(block as u64)?;
+            let index = (remaining / per_block.pow(level)) % per_block;
+            block = read_u32(&data, index as usize * 4);
+        }
+        Ok(Some(block as u64).filter(|&block| block != 0))
+    }
+
+    fn symlink_target(&mut self, inode: &Inode) -> StorageResult<String> {
+        if inode.file_type() != FileType::Symlink {
+            return Err(StorageError::InvalidData);
+        }
+        // Short targets are stored directly in i_block ("fast" symlinks)
+        if inode.size < 60 && !inode.has_flag(Inode::EXTENTS_FL) {
+            return Ok(String::from_utf8_lossy(&inode.block[..inode.size as usize]).to_string());
+        }
+        let mut data = vec![0u8; inode.size as usize];
+        self.read_inode_data(inode, 0, &mut data)?;
+        Ok(String::from_utf8_lossy(&data).to_string())
+    }
+
+    fn lookup(&mut self, dir_ino: u32, name: &str) -> StorageResult<Option<u32>> {
+        let dir = self.get_inode(dir_ino)?;
+        if dir.file_type() != FileType::Directory {
+            return Err(StorageError::NotADirectory);
+        }
+
+        // An index we can read is authoritative: a miss there is a miss
+        let indexed = dir.has_flag(Inode::INDEX_FL)
+            && self.superblock.features.compat & Features::COMPAT_DIR_INDEX != 0;
+        if indexed {
+            let root = self.read_logical_block(&dir, 0)?;
+            if DxRoot::parse(&root).is_some() {
+                return self.htree_lookup(&dir, root, name);
+            }
+        }
+
+        self.stats.linear_lookups += 1;
+        for block in 0..dir.block_count(self.superblock.block_size) {
+            let data = self.read_logical_block(&dir, block)?;
+            if let Some(entry) = self.find_in_leaf(&data, name) {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    // Walk the hash index down to the one leaf block that can hold `name`,
+    // then on into following leaves only while they continue the same hash
+    fn htree_lookup(&mut self, dir: &Inode, root_block: Vec<u8>, name: &str) -> StorageResult<Option<u32>> {
+        let root = DxRoot::parse(&root_block).ok_or(StorageError::InvalidData)?;
+        self.stats.htree_lookups += 1;
+
+        let version = if self.superblock.unsigned_hash && root.hash_version <= 2 {
+            root.hash_version + 3
+        } else {
+            root.hash_version
+        };
+        let hash = dx_hash(name.as_bytes(), version, &self.superblock.hash_seed).ok_or(StorageError::Unsupported(
+            format!("htree hash version {}", root.hash_version),
+        ))?;
+
+        // One frame per index level, root first
+        let mut frames = vec![DxFrame::parse(root_block, root.entries_offset)?];
+        frames[0].seek(hash);
+        while frames.len() <= root.levels {
+            let node = self.read_logical_block(dir, frames[frames.len() - 1].block())?;
+            let mut frame = DxFrame::parse(node, 8)?;
+            frame.seek(hash);
+            frames.push(frame);
+        }
+
+        let mut leaf = frames[frames.len() - 1].block();
+        loop {
+            let data = self.read_logical_block(dir, leaf)?;
+            if let Some(entry) = self.find_in_leaf(&data, name) {
+                return Ok(Some(entry));
+            }
+            match self.htree_next_leaf(dir, &mut frames, hash)? {
+                Some(next) => leaf = next,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    // Names whose hashes collide can spill past the leaf the index chose.
+    // The index entry of the next leaf then carries the same hash with the
+    // low "continuation" bit set; any other next entry ends the search.
+    fn htree_next_leaf(&mut self, dir: &Inode, frames: &mut Vec<DxFrame>, hash: u32) -> StorageResult<Option<u32>> {
+        let depth = frames.len();
+        let Some(level) = frames.iter().rposition(|frame| frame.at + 1 < frame.count) else {
+            return Ok(None);
         };
+        frames.truncate(level + 1);
+        let frame = &mut frames[level];
+        frame.at += 1;
+        let next_hash = frame.hash();
+        if next_hash & 1 == 0 || next_hash & !1 != hash {
+            return Ok(None);
+        }
+
+        // Descend along the first entry of each lower level to the leaf
+        while frames.len() < depth {
+            let node = self.read_logical_block(dir, frames[frames.len() - 1].block())?;
+            frames.push(DxFrame::parse(node, 8)?);
+        }
+        Ok(Some(frames[depth - 1].block()))
+    }
+
+    fn find_in_leaf(&self, data: &[u8], name: &str) -> Option<u32> {
+        parse_dir_block(data, self.has_filetype())
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.inode)
+    }
+
+    fn resolve(&mut self, path: &str, follow_last: bool) -> StorageResult<u32> {
+        let mut depth = 0;
+        self.resolve_from(ROOT_INODE, path, follow_last, &mut depth)
+    }
+
+    fn resolve_from(&mut self, start: u32, path: &str, follow_last: bool, depth: &mut u32) -> StorageResult<u32> {
+        let mut current = if path.starts_with('/') { ROOT_INODE } else { start };
+        let components: Vec<&str> = path.split('/').filter(|part| !part.is_empty() && *part != ".").collect();
+
+        for (i, component) in components.iter().enumerate() {
+            let parent = current;
+            current = self.lookup(current, component)?.ok_or(StorageError::NotFound)?;
+
+            let is_last = i + 1 == components.len();
+            let inode = self.get_inode(current)?;
+            if inode.file_type() == FileType::Symlink && (!is_last || follow_last) {
+                *depth += 1;
+                if *depth > MAX_SYMLINK_DEPTH {
+                    return Err(StorageError::Corrupted("too many levels of symbolic links".into()));
+                }
+                let target = self.symlink_target(&inode)?;
+                current = self.resolve_from(parent, &target, true, depth)?;
+            }
+        }
+        Ok(current)
+    }
+
+    fn load_journal(&mut self) -> StorageResult<Journal> {
+        let ino = self.superblock.journal_inode;
+        let inode = self.get_inode(ino)?;
+        let raw = self.read_logical_block(&inode, 0)?;
+        if read_be32(&raw, 0) != JBD2_MAGIC {
+            return Err(StorageError::Corrupted("bad journal superblock magic".into()));
+        }
+
+        let block_type = read_be32(&raw, 4);
+        Ok(Journal {
+            inode: ino,
+            block_size: read_be32(&raw, 12),
+            max_len: read_be32(&raw, 16),
+            first: read_be32(&raw, 20),
+            sequence: read_be32(&raw, 24),
+            start: read_be32(&raw, 28),
+            // v1 superblocks (type 3) have no feature fields
+            incompat: if block_type == 4 { read_be32(&raw, 40) } else { 0 },
+            stats: JournalStats::default(),
+        })
+    }
+
+    // Scan the log for committed transactions, then write their blocks home unless revoked later
+    fn replay_journal(&mut self) -> StorageResult<()> {
+        let mut journal = self.journal.take().ok_or(StorageError::NotReady)?;
+        let inode = self.get_inode(journal.inode)?;
+
+        if journal.start != 0 {
+            if journal.block_size != self.superblock.block_size {
+                return Err(StorageError::Unsupported("journal block size differs from filesystem".into()));
+            }
+            let transactions = self.scan_journal(&journal, &inode)?;
 
-        // Start journal transaction
-        let mut transaction = self.journal.start_transaction();
-        
-        // Update directory entries
-        self.update_directory_entry(path, inode_num, &mut transaction)?;
-        
-        // Commit transaction
-        self.journal.commit_transaction(transaction)?;
-        
-        Ok(inode_num)
-    }
-
-    pub fn write(&mut self, inode: u32, offset: u64, data: &[u8]) -> StorageResult<usize> {
-        let mut inode = self.get_inode(inode)?;
-        
-        // Allocate blocks if needed
-        while inode.size < offset + data.len() as u64 {
-            let block = self.allocate_block()?;
-            inode.blocks.push(block);
-        }
-
-        // Start journal transaction
-        let mut transaction = self.journal.start_transaction();
-        
-        // Write data blocks
-        self.write_blocks(&inode, offset, data, &mut transaction)?;
-        
-        // Update inode
-        inode.size = offset + data.len() as u64;
-        inode.mtime = self.get_current_time();
-        
-        // Commit transaction
-        self.journal.commit_transaction(transaction)?;
-        
-        Ok(data.len())
-    }
-
-    // Helper methods...
+            let mut revoked: HashMap<u64, u32> = HashMap::new();
+            for transaction in &transactions {
+                for &block in &transaction.revoked {
+                    let sequence = revoked.entry(block).or_insert(transaction.sequence);
+                    *sequence = (*sequence).max(transaction.sequence);
+                }
+            }
+
+            for transaction in &transactions {
+                for block in &transaction.blocks {
+                    if revoked.get(&block.target).is_some_and(|&sequence| sequence >= transaction.sequence) {
+                        journal.stats.blocks_revoked += 1;
+                        continue;
+                    }
+                    let mut data = self.read_logical_block(&inode, block.log_block)?;
+                    if block.escaped {
+                        data[0..4].copy_from_slice(&JBD2_MAGIC.to_be_bytes());
+                    }
+                    self.device.write_bytes(block.target * self.superblock.block_size as u64, &data)?;
+                    journal.stats.blocks_replayed += 1;
+                }
+                journal.stats.transactions_replayed += 1;
+            }
+
+            // Mark the log empty so the same transactions are never replayed twice
+            if let Some(last) = transactions.last() {
+                journal.sequence = last.sequence + 1;
+            }
+            journal.start = 0;
+            self.write_journal_superblock(&journal, &inode)?;
+        }
+
+        // Replay may have rewritten the superblock and descriptors themselves
+        self.clear_recover_flag()?;
+        let mut raw = vec![0u8; 1024];
+        self.device.read_bytes(SUPERBLOCK_OFFSET, &mut raw)?;
+        self.superblock = Superblock::parse(&raw)?;
+        self.inode_table.clear();
+        self.load_block_groups()?;
+        self.journal = Some(journal);
+        self.device.flush()
+    }
+
+    fn scan_journal(&mut self, journal: &Journal, inode: &Inode) -> StorageResult<Vec<Transaction>> {
+        let csum_v3 = journal.incompat & 0x10 != 0;
+        let csum_v2 = journal.incompat & 0x08 != 0;
+        let wide = journal.incompat & 0x02 != 0;
+        let tag_bytes = if csum_v3 {
+            16
+        } else {
+            8 + if csum_v2 { 2 } else { 0 } + if wide { 4 } else { 0 }
+        };
+        let tail_bytes = if csum_v2 || csum_v3 { 4 } else { 0 };
+        let block_size = journal.block_size as usize;
+
+        let next = |block: u32| if block + 1 >= journal.max_len { journal.first } else { block + 1 };
+
+        let mut transactions = Vec::new();
+        let mut current = Transaction { sequence: journal.sequence, blocks: Vec::new(), revoked: Vec::new() };
+        let mut block = journal.start;
+        for _ in 0..journal.max_len {
+            let data = self.read_logical_block(inode, block)?;
+            if read_be32(&data, 0) != JBD2_MAGIC || read_be32(&data, 8) != current.sequence {
+                break;
+            }
+
+            match read_be32(&data, 4) {
+                // Descriptor: tags naming the home location of each following log block
+                1 => {
+                    let mut offset = 12;
+                    let mut log_block = block;
+                    while offset + tag_bytes <= block_size - tail_bytes {
+                        let (target_lo, flags) = if csum_v3 {
+                            (read_be32(&data, offset), read_be32(&data, offset + 4))
+                        } else {
+                            (read_be32(&data, offset), read_be16(&data, offset + 6) as u32)
+                        };
+                        let target_hi = if wide { read_be32(&data, offset + 8) } else { 0 };
+                        log_block = next(log_block);
+                        current.blocks.push(JournalBlock {
+                            target: target_lo as u64 | (target_hi as u64) << 32,
+                            log_block,
+                            escaped: flags & 0x1 != 0,
+                        });
+                        offset += tag_bytes;
+                        if flags & 0x2 == 0 {
+                            offset += 16; // UUID follows tags without SAME_UUID
+                        }
+                        if flags & 0x8 != 0 {
+                            break;
+                        }
+                    }
+                    block = log_block;
+                }
+                // Commit: everything since the previous commit is durable
+                2 => {
+                    let sequence = current.sequence;
+                    transactions.push(std::mem::replace(
+                        &mut current,
+                        Transaction { sequence: sequence + 1, blocks: Vec::new(), revoked: Vec::new() },
+                    ));
+                }
+                // Revoke: blocks that must not be replayed from earlier transactions
+                5 => {
+                    let used = (read_be32(&data, 12) as usize).min(block_size);
+                    let record = if wide { 8 } else { 4 };
+                    let mut offset = 16;
+                    while offset + record <= used {
+                        let target = if wide { read_be64(&data, offset) } else { read_be32(&data, offset) as u64 };
+                        current.revoked.push(target);
+                        offset += record;
+                    }
+                }
+                _ => break,
+            }
+            block = next(block);
+        }
+        Ok(transactions)
+    }
+
+    fn write_journal_superblock(&mut self, journal: &Journal, inode: &Inode) -> StorageResult<()> {
+        let physical = self
+            .map_block(inode, 0)?
+            .ok_or_else(|| StorageError::Corrupted("journal superblock not mapped".into()))?;
+        let offset = physical * self.superblock.block_size as u64;
+        let mut raw = vec![0u8; 1024];
+        self.device.read_bytes(offset, &mut raw)?;
+        raw[24..28].copy_from_slice(&journal.sequence.to_be_bytes());
+        raw[28..32].copy_from_slice(&journal.start.to_be_bytes());
+
+        // v2/v3 checksums cover the superblock with its checksum field zeroed
+        if journal.incompat & 0x18 != 0 {
+            raw[0xFC..0x100].fill(0);
+            let checksum = crc32c(!0, &raw);
+            raw[0xFC..0x100].copy_from_slice(&checksum.to_be_bytes());
+        }
+        self.device.write_bytes(offset, &raw)
+    }
+
+    fn clear_recover_flag(&mut self) -> StorageResult<()> {
+        let mut raw = vec![0u8; 1024];
+        self.device.read_bytes(SUPERBLOCK_OFFSET, &mut raw)?;
+        let incompat = read_u32(&raw, 0x60) & !Features::INCOMPAT_RECOVER;
+        write_u32(&mut raw, 0x60, incompat);
+        if self.superblock.features.ro_compat & Features::RO_COMPAT_METADATA_CSUM != 0 {
+            let checksum = crc32c(!0, &raw[..0x3FC]);
+            write_u32(&mut raw, 0x3FC, checksum);
+        }
+        self.device.write_bytes(SUPERBLOCK_OFFSET, &raw)
+    }
+}
+
+impl Superblock {
+    fn parse(raw: &[u8]) -> StorageResult<Self> {
+        if read_u16(raw, 0x38) != EXT4_MAGIC {
+            return Err(StorageError::InvalidFileSystem("bad ext4 superblock magic".into()));
+        }
+
+        let features = Features {
+            compat: read_u32(raw, 0x5C),
+            incompat: read_u32(raw, 0x60),
+            ro_compat: read_u32(raw, 0x64),
+        };
+        if features.incompat & Features::INCOMPAT_UNSUPPORTED != 0 {
+            return Err(StorageError::Unsupported(format!("incompatible features {:#x}", features.incompat)));
+        }
+
+        let wide = features.has_incompat(Features::INCOMPAT_64BIT);
+        let hi = |offset: usize| if wide { (read_u32(raw, offset) as u64) << 32 } else { 0 };
+        let log_block_size = read_u32(raw, 0x18);
+        if log_block_size > 6 {
+            return Err(StorageError::InvalidFileSystem("bad block size".into()));
+        }
+
+        // Revision 0 filesystems have fixed 128-byte inodes
+        let inode_size = if read_u32(raw, 0x4C) == 0 { 128 } else { read_u16(raw, 0x58) };
+        let desc_size = if wide { read_u16(raw, 0xFE).max(32) } else { 32 };
+        let mut hash_seed = [0u32; 4];
+        for (i, word) in hash_seed.iter_mut().enumerate() {
+            *word = read_u32(raw, 0xEC + i * 4);
+        }
+        let name = &raw[0x78..0x88];
+        let name_end = name.iter().position(|&byte| byte == 0).unwrap_or(name.len());
+
+        let superblock = Self {
+            inodes_count: read_u32(raw, 0x00),
+            blocks_count: read_u32(raw, 0x04) as u64 | hi(0x150),
+            free_blocks_count: read_u32(raw, 0x0C) as u64 | hi(0x158),
+            free_inodes_count: read_u32(raw, 0x10),
+            first_data_block: read_u32(raw, 0x14),
+            block_size: 1024 << log_block_size,
+            blocks_per_group: read_u32(raw, 0x20),
+            inodes_per_group: read_u32(raw, 0x28),
+            inode_size,
+            desc_size,
+            mtime: read_u32(raw, 0x2C),
+            journal_inode: read_u32(raw, 0xE0),
+            hash_seed,
+            unsigned_hash: read_u32(raw, 0x160) & 0x2 != 0,
+            volume_name: String::from_utf8_lossy(&name[..name_end]).to_string(),
+            features,
+        };
+        if superblock.blocks_per_group == 0 || superblock.inodes_per_group == 0 || superblock.inode_size < 128 {
+            return Err(StorageError::InvalidFileSystem("inconsistent superblock".into()));
+        }
+        Ok(superblock)
+    }
+}
+
+impl Inode {
+    const INDEX_FL: u32 = 0x0000_1000;
+    const EXTENTS_FL: u32 = 0x0008_0000;
+    const INLINE_DATA_FL: u32 = 0x1000_0000;
+
+    fn parse(raw: &[u8]) -> Self {
+        let mut block = [0u8; 60];
+        block.copy_from_slice(&raw[0x28..0x64]);
+        Self {
+            mode: read_u16(raw, 0x00),
+            uid: read_u16(raw, 0x02) as u32 | (read_u16(raw, 0x78) as u32) << 16,
+            gid: read_u16(raw, 0x18) as u32 | (read_u16(raw, 0x7A) as u32) << 16,
+            size: read_u32(raw, 0x04) as u64 | (read_u32(raw, 0x6C) as u64) << 32,
+            atime: read_u32(raw, 0x08),
+            ctime: read_u32(raw, 0x0C),
+            mtime: read_u32(raw, 0x10),
+            links_count: read_u16(raw, 0x1A),
+            flags: read_u32(raw, 0x20),
+            block,
+        }
+    }
+
+    fn file_type(&self) -> FileType {
+        match self.mode & 0xF000 {
+            0x8000 => FileType::Regular,
+            0x4000 => FileType::Directory,
+            0xA000 => FileType::Symlink,
+            0x2000 => FileType::CharDevice,
+            0x6000 => FileType::BlockDevice,
+            0x1000 => FileType::Fifo,
+            0xC000 => FileType::Socket,
+            _ => FileType::Unknown,
+        }
+    }
+
+    fn has_flag(&self, flag: u32) -> bool {
+        self.flags & flag != 0
+    }
+
+    fn block_count(&self, block_size: u32) -> u32 {
+        self.size.div_ceil(block_size as u64) as u32
+    }
+}
+
+// dx_root_info, which follows the fake "." and ".." entries of block 0
+struct DxRoot {
+    hash_version: u8,
+    levels: usize,
+    entries_offset: usize,
+}
+
+// One node of the hash index, with the entry the lookup took
+struct DxFrame {
+    node: Vec<u8>,
+    entries_offset: usize,
+    count: usize,
+    at: usize,
+}
+
+struct ExtentHeader {
+    entries: u16,
+    depth: u16,
+}
+
+impl DxRoot {
+    // None for layouts we don't know, which fall back to a linear scan
+    fn parse(block: &[u8]) -> Option<Self> {
+        let info_length = block[0x1D] as usize;
+        let levels = block[0x1E] as usize;
+        if info_length != 8 || levels > 2 {
+            return None;
+        }
+        Some(Self {
+            hash_version: block[0x1C],
+            levels,
+            entries_offset: 0x18 + info_length,
+        })
+    }
+}
+
+impl DxFrame {
+    fn parse(node: Vec<u8>, entries_offset: usize) -> StorageResult<Self> {
+        // Entry 0 has no hash; its slot holds limit/count instead
+        let count = read_u16(&node, entries_offset + 2) as usize;
+        if count == 0 || entries_offset + count * 8 > node.len() {
+            return Err(StorageError::Corrupted("bad htree node entry count".into()));
+        }
+        Ok(Self { node, entries_offset, count, at: 0 })
+    }
+
+    // The last entry whose hash is at or below `hash`
+    fn seek(&mut self, hash: u32) {
+        self.at = (1..self.count)
+            .take_while(|&i| read_u32(&self.node, self.entries_offset + i * 8) <= hash)
+            .last()
+            .unwrap_or(0);
+    }
+
+    fn hash(&self) -> u32 {
+        read_u32(&self.node, self.entries_offset + self.at * 8)
+    }
+
+    fn block(&self) -> u32 {
+        read_u32(&self.node, self.entries_offset + self.at * 8 + 4)
+    }
+}
+
+impl ExtentHeader {
+    fn parse(node: &[u8]) -> StorageResult<Self> {
+        if read_u16(node, 0) != EXTENT_MAGIC {
+            return Err(StorageError::Corrupted("bad extent header magic".into()));
+        }
+        let header = Self { entries: read_u16(node, 2), depth: read_u16(node, 6) };
+        if 12 + header.entries as usize * 12 > node.len() {
+            return Err(StorageError::Corrupted("extent node overflows its block".into()));
+        }
+        Ok(header)
+    }
+}
+
+fn parse_extent(entry: &[u8]) -> Extent {
+    // Lengths above 32768 mark preallocated, not yet written extents
+    let raw_length = read_u16(entry, 4);
+    let (length, initialized) = if raw_length > 32768 { (raw_length - 32768, false) } else { (raw_length, true) };
+    Extent {
+        logical_block: read_u32(entry, 0),
+        physical_block: read_u32(entry, 8) as u64 | (read_u16(entry, 6) as u64) << 32,
+        length,
+        initialized,
+    }
+}
+
+fn parse_dir_block(data: &[u8], has_filetype: bool) -> Vec<DirEntry> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset + 8 <= data.len() {
+        let inode = read_u32(data, offset);
+        let rec_len = read_u16(data, offset + 4) as usize;
+        let name_len = data[offset + 6] as usize;
+        if rec_len < 8 || offset + rec_len > data.len() || 8 + name_len > rec_len {
+            break;
+        }
+
+        // Unused records and the metadata_csum tail have inode 0
+        if inode != 0 && name_len > 0 {
+            let file_type = if has_filetype {
+                match data[offset + 7] {
+                    1 => FileType::Regular,
+                    2 => FileType::Directory,
+                    3 => FileType::CharDevice,
+                    4 => FileType::BlockDevice,
+                    5 => FileType::Fifo,
+                    6 => FileType::Socket,
+                    7 => FileType::Symlink,
+                    _ => FileType::Unknown,
+                }
+            } else {
+                FileType::Unknown
+            };
+            entries.push(DirEntry {
+                name: String::from_utf8_lossy(&data[offset + 8..offset + 8 + name_len]).to_string(),
+                inode,
+                file_type,
+            });
+        }
+        offset += rec_len;
+    }
+    entries
+}
+
+// Directory index hashes, following fs/ext4/hash.c
+fn dx_hash(name: &[u8], version: u8, seed: &[u32; 4]) -> Option<u32> {
+    let mut buf = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476];
+    if seed.iter().any(|&word| word != 0) {
+        buf = *seed;
+    }
+
+    let hash = match version {
+        0 => dx_hack_hash(name, true),
+        3 => dx_hack_hash(name, false),
+        1 | 4 => {
+            for chunk_start in (0..name.len().max(1)).step_by(32) {
+                let input = str_to_hash_buf(&name[chunk_start.min(name.len())..], 8, version == 1);
+                half_md4_transform(&mut buf, &input);
+            }
+            buf[1]
+        }
+        2 | 5 => {
+            for chunk_start in (0..name.len().max(1)).step_by(16) {
+                let input = str_to_hash_buf(&name[chunk_start.min(name.len())..], 4, version == 2);
+                tea_transform(&mut buf, &input);
+            }
+            buf[0]
+        }
+        _ => return None,
+    };
+
+    let hash = hash & !1;
+    Some(if hash == 0x7FFF_FFFF << 1 { 0x7FFF_FFFE << 1 } else { hash })
+}
+
+fn dx_hack_hash(name: &[u8], signed: bool) -> u32 {
+    let (mut hash0, mut hash1) = (0x12A3_FE2Du32, 0x37AB_E8F9u32);
+    for &byte in name {
+        let value = if signed { byte as i8 as i32 } else { byte as i32 };
+        let mut hash = hash1.wrapping_add(hash0 ^ (value.wrapping_mul(7_152_373) as u32));
+        if hash & 0x8000_0000 != 0 {
+            hash = hash.wrapping_sub(0x7FFF_FFFF);
+        }
+        hash1 = hash0;
+        hash0 = hash;
+    }
+    hash0 << 1
+}
+
+fn str_to_hash_buf(message: &[u8], words: usize, signed: bool) -> Vec<u32> {
+    let length = message.len() as u32;
+    let mut pad = length | (length << 8);
+    pad |= pad << 16;
+
+    let mut output = Vec::with_capacity(words);
+    let mut value = pad;
+    for (i, &byte) in message.iter().take(words * 4).enumerate() {
+        let byte = if signed { byte as i8 as i32 as u32 } else { byte as u32 };
+        value = byte.wrapping_add(value << 8);
+        if i % 4 == 3 {
+            output.push(value);
+            value = pad;
+        }
+    }
+    if output.len() < words {
+        output.push(value);
+    }
+    output.resize(words, pad);
+    output
+}
+
+fn half_md4_transform(buf: &mut [u32; 4], input: &[u32]) {
+    let f = |x: u32, y: u32, z: u32| z ^ (x & (y ^ z));
+    let g = |x: u32, y: u32, z: u32| (x & y).wrapping_add((x ^ y) & z);
+    let h = |x: u32, y: u32, z: u32| x ^ y ^ z;
+    const K2: u32 = 0x5A82_7999;
+    const K3: u32 = 0x6ED9_EBA1;
+
+    let [mut a, mut b, mut c, mut d] = *buf;
+    macro_rules! round {
+        ($f:ident, $a:ident, $b:ident, $c:ident, $d:ident, $x:expr, $s:expr) => {
+            $a = $a.wrapping_add($f($b, $c, $d)).wrapping_add($x).rotate_left($s);
+        };
+    }
+
+    round!(f, a, b, c, d, input[0], 3);
+    round!(f, d, a, b, c, input[1], 7);
+    round!(f, c, d, a, b, input[2], 11);
+    round!(f, b, c, d, a, input[3], 19);
+    round!(f, a, b, c, d, input[4], 3);
+    round!(f, d, a, b, c, input[5], 7);
+    round!(f, c, d, a, b, input[6], 11);
+    round!(f, b, c, d, a, input[7], 19);
+
+    round!(g, a, b, c, d, input[1].wrapping_add(K2), 3);
+    round!(g, d, a, b, c, input[3].wrapping_add(K2), 5);
+    round!(g, c, d, a, b, input[5].wrapping_add(K2), 9);
+    round!(g, b, c, d, a, input[7].wrapping_add(K2), 13);
+    round!(g, a, b, c, d, input[0].wrapping_add(K2), 3);
+    round!(g, d, a, b, c, input[2].wrapping_add(K2), 5);
+    round!(g, c, d, a, b, input[4].wrapping_add(K2), 9);
+    round!(g, b, c, d, a, input[6].wrapping_add(K2), 13);
+
+    round!(h, a, b, c, d, input[3].wrapping_add(K3), 3);
+    round!(h, d, a, b, c, input[7].wrapping_add(K3), 9);
+    round!(h, c, d, a, b, input[2].wrapping_add(K3), 11);
+    round!(h, b, c, d, a, input[6].wrapping_add(K3), 15);
+    round!(h, a, b, c, d, input[1].wrapping_add(K3), 3);
+    round!(h, d, a, b, c, input[5].wrapping_add(K3), 9);
+    round!(h, c, d, a, b, input[0].wrapping_add(K3), 11);
+    round!(h, b, c, d, a, input[4].wrapping_add(K3), 15);
+
+    buf[0] = buf[0].wrapping_add(a);
+    buf[1] = buf[1].wrapping_add(b);
+    buf[2] = buf[2].wrapping_add(c);
+    buf[3] = buf[3].wrapping_add(d);
+}
+
+fn tea_transform(buf: &mut [u32; 4], input: &[u32]) {
+    const DELTA: u32 = 0x9E37_79B9;
+    let (mut sum, mut b0, mut b1) = (0u32, buf[0], buf[1]);
+    let (a, b, c, d) = (input[0], input[1], input[2], input[3]);
+    for _ in 0..16 {
+        sum = sum.wrapping_add(DELTA);
+        b0 = b0.wrapping_add(((b1 << 4).wrapping_add(a)) ^ b1.wrapping_add(sum) ^ ((b1 >> 5).wrapping_add(b)));
+        b1 = b1.wrapping_add(((b0 << 4).wrapping_add(c)) ^ b0.wrapping_add(sum) ^ ((b0 >> 5).wrapping_add(d)));
+    }
+    buf[0] = buf[0].wrapping_add(b0);
+    buf[1] = buf[1].wrapping_add(b1);
+}
+
+// Castagnoli CRC without the final inversion, as used for ext4 and jbd2 checksums
+fn crc32c(seed: u32, data: &[u8]) -> u32 {
+    let mut crc = seed;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x82F6_3B78 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+// The journal is big-endian on disk
+fn read_be16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_be32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn read_be64(bytes: &[u8], offset: usize) -> u64 {
+    ((read_be32(bytes, offset) as u64) << 32) | read_be32(bytes, offset + 4) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::device::MemoryBlockDevice;
+    use super::*;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    const BIG_DIRECTORY_FILES: usize = 6000;
+
+    // Builds an image with mkfs.ext4 -d from a host directory tree and
+    // indexes its directories. Needs mkfs.ext4 and e2fsck from e2fsprogs.
+    fn mkfs_image(name: &str, populate: impl FnOnce(&Path)) -> Ext4FileSystem<MemoryBlockDevice> {
+        let work = std::env::temp_dir().join(format!("ext4-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&work);
+        let root = work.join("root");
+        fs::create_dir_all(&root).unwrap();
+        populate(&root);
+
+        let image: PathBuf = work.join("image");
+        let made = Command::new("mkfs.ext4")
+            .args(["-q", "-F", "-b", "1024", "-N", "8192", "-O", "dir_index", "-d"])
+            .arg(&root)
+            .arg(&image)
+            .arg("32M")
+            .output()
+            .expect("mkfs.ext4 not found");
+        assert!(made.status.success(), "{}", String::from_utf8_lossy(&made.stderr));
+
+        // mkfs -d links directories linearly; e2fsck -D rebuilds them as
+        // hash trees. Exit code 1 means it changed something.
+        let indexed = Command::new("e2fsck").arg("-fyD").arg(&image).output().expect("e2fsck not found");
+        assert!(indexed.status.code().is_some_and(|code| code <= 1), "{}", String::from_utf8_lossy(&indexed.stdout));
+        let device = MemoryBlockDevice::open(&image).unwrap();
+        let _ = fs::remove_dir_all(&work);
+        Ext4FileSystem::mount(device).unwrap()
+    }
+
+    // A 1 KiB file whose only data block the journal tests overwrite
+    fn journal_image(name: &str) -> (Ext4FileSystem<MemoryBlockDevice>, u64) {
+        let mut fs = mkfs_image(name, |root| {
+            fs::write(root.join("target"), [b'o'; 1024]).unwrap();
+        });
+        let target = fs.extents("/target").unwrap()[0].physical_block;
+        (fs, target)
+    }
+
+    fn journal_header(block_type: u32, sequence: u32) -> Vec<u8> {
+        let mut block = vec![0u8; 1024];
+        block[0..4].copy_from_slice(&JBD2_MAGIC.to_be_bytes());
+        block[4..8].copy_from_slice(&block_type.to_be_bytes());
+        block[8..12].copy_from_slice(&sequence.to_be_bytes());
+        block
+    }
+
+    // One descriptor tag per target; the log blocks follow in the same order
+    fn descriptor(sequence: u32, targets: &[u64]) -> Vec<u8> {
+        let mut block = journal_header(1, sequence);
+        for (i, &target) in targets.iter().enumerate() {
+            let offset = 12 + i * 8;
+            let mut flags = 0x2u16; // SAME_UUID
+            if i + 1 == targets.len() {
+                flags |= 0x8; // LAST_TAG
+            }
+            block[offset..offset + 4].copy_from_slice(&(target as u32).to_be_bytes());
+            block[offset + 6..offset + 8].copy_from_slice(&flags.to_be_bytes());
+        }
+        block
+    }
+
+    fn revoke(sequence: u32, targets: &[u64]) -> Vec<u8> {
+        let mut block = journal_header(5, sequence);
+        let used = 16 + targets.len() * 4;
+        block[12..16].copy_from_slice(&(used as u32).to_be_bytes());
+        for (i, &target) in targets.iter().enumerate() {
+            block[16 + i * 4..20 + i * 4].copy_from_slice(&(target as u32).to_be_bytes());
+        }
+        block
+    }
+
+    // Writes `log` into the journal from its first log block, points the
+    // journal superblock at it and sets the filesystem's recover flag, as
+    // a crash between commit and checkpoint would leave them
+    fn crash_with_log(mut fs: Ext4FileSystem<MemoryBlockDevice>, sequence: u32, log: &[Vec<u8>]) -> MemoryBlockDevice {
+        let journal = fs.superblock.journal_inode;
+        let inode = fs.get_inode(journal).unwrap();
+        let first = fs.journal.as_ref().unwrap().first;
+        let block_size = fs.superblock.block_size as u64;
+        let home = |fs: &mut Ext4FileSystem<MemoryBlockDevice>, logical: u32| {
+            fs.map_block(&inode, logical).unwrap().unwrap() * block_size
+        };
+
+        for (i, block) in log.iter().chain([&vec![0u8; 1024]]).enumerate() {
+            let offset = home(&mut fs, first + i as u32);
+            fs.device.write_bytes(offset, block).unwrap();
+        }
+
+        // Tags are written in the plain 32-bit, checksum-free layout
+        let offset = home(&mut fs, 0);
+        let mut raw = vec![0u8; 1024];
+        fs.device.read_bytes(offset, &mut raw).unwrap();
+        raw[24..28].copy_from_slice(&sequence.to_be_bytes());
+        raw[28..32].copy_from_slice(&first.to_be_bytes());
+        raw[40..44].fill(0);
+        fs.device.write_bytes(offset, &raw).unwrap();
+
+        fs.device.read_bytes(SUPERBLOCK_OFFSET, &mut raw).unwrap();
+        let incompat = read_u32(&raw, 0x60) | Features::INCOMPAT_RECOVER;
+        write_u32(&mut raw, 0x60, incompat);
+        if fs.superblock.features.ro_compat & Features::RO_COMPAT_METADATA_CSUM != 0 {
+            let checksum = crc32c(!0, &raw[..0x3FC]);
+            write_u32(&mut raw, 0x3FC, checksum);
+        }
+        fs.device.write_bytes(SUPERBLOCK_OFFSET, &raw).unwrap();
+        fs.unmount().unwrap()
+    }
+
+    fn big_name(index: usize) -> String {
+        format!("a-rather-long-file-name-{}", index)
+    }
+
+    #[test]
+    fn reads_files_and_symlinks_from_mkfs_image() {
+        let mut fs = mkfs_image("files", |root| {
+            fs::create_dir(root.join("etc")).unwrap();
+            fs::write(root.join("etc/hostname"), "laptop\n").unwrap();
+            let big: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+            fs::write(root.join("blob"), big).unwrap();
+            std::os::unix::fs::symlink("etc/hostname", root.join("link")).unwrap();
+        });
+
+        assert_eq!(fs.read_file("/etc/hostname").unwrap(), b"laptop\n");
+        assert_eq!(fs.read_file("/link").unwrap(), b"laptop\n");
+        assert_eq!(fs.read_link("/link").unwrap(), "etc/hostname");
+        let blob = fs.read_file("/blob").unwrap();
+        assert_eq!(blob.len(), 300_000);
+        assert!(blob.iter().enumerate().all(|(i, &byte)| byte == (i % 251) as u8));
+        assert!(!fs.extents("/blob").unwrap().is_empty());
+        assert_eq!(fs.read_file("/etc/missing"), Err(StorageError::NotFound));
+    }
+
+    #[test]
+    fn htree_lookups_never_fall_back_to_a_scan() {
+        let mut fs = mkfs_image("htree", |root| {
+            fs::create_dir(root.join("big")).unwrap();
+            for index in 0..BIG_DIRECTORY_FILES {
+                fs::write(root.join("big").join(big_name(index)), index.to_string()).unwrap();
+            }
+        });
+
+        let big = fs.lookup_inode(fs.root_inode(), "big").unwrap().unwrap();
+        let linear_before = fs.get_stats().linear_lookups;
+        for index in (0..BIG_DIRECTORY_FILES).step_by(7) {
+            let ino = fs.lookup_inode(big, &big_name(index)).unwrap().expect("indexed name not found");
+            let mut buffer = [0u8; 8];
+            let read = fs.read_inode_at(ino, 0, &mut buffer).unwrap();
+            assert_eq!(&buffer[..read], index.to_string().as_bytes());
+        }
+        assert_eq!(fs.lookup_inode(big, "not-there").unwrap(), None);
+        assert_eq!(fs.get_stats().linear_lookups, linear_before);
+        assert!(fs.get_stats().htree_lookups > 0);
+    }
+
+    #[test]
+    fn replays_a_committed_transaction() {
+        let (fs, target) = journal_image("commit");
+        let device = crash_with_log(
+            fs,
+            7,
+            &[descriptor(7, &[target]), vec![b'n'; 1024], journal_header(2, 7)],
+        );
+
+        let mut fs = Ext4FileSystem::mount(device).unwrap();
+        assert_eq!(fs.read_file("/target").unwrap(), vec![b'n'; 1024]);
+        let stats = fs.journal_stats().unwrap();
+        assert_eq!((stats.transactions_replayed, stats.blocks_replayed), (1, 1));
+        assert!(!fs.superblock.features.has_incompat(Features::INCOMPAT_RECOVER));
+
+        // The log is marked empty, so a second mount replays nothing
+        let fs = Ext4FileSystem::mount(fs.unmount().unwrap()).unwrap();
+        assert_eq!(fs.journal_stats().unwrap().transactions_replayed, 0);
+    }
+
+    #[test]
+    fn skips_a_transaction_without_a_commit_block() {
+        let (fs, target) = journal_image("uncommitted");
+        let device = crash_with_log(
+            fs,
+            3,
+            &[
+                descriptor(3, &[target]),
+                vec![b'a'; 1024],
+                journal_header(2, 3),
+                descriptor(4, &[target]),
+                vec![b'b'; 1024],
+            ],
+        );
+
+        let mut fs = Ext4FileSystem::mount(device).unwrap();
+        assert_eq!(fs.read_file("/target").unwrap(), vec![b'a'; 1024]);
+        let stats = fs.journal_stats().unwrap();
+        assert_eq!((stats.transactions_replayed, stats.blocks_replayed), (1, 1));
+    }
+
+    #[test]
+    fn revoke_records_suppress_earlier_copies_only() {
+        let (fs, target) = journal_image("revoke");
+        let device = crash_with_log(
+            fs,
+            10,
+            &[
+                descriptor(10, &[target]),
+                vec![b'x'; 1024],
+                journal_header(2, 10),
+                revoke(11, &[target]),
+                journal_header(2, 11),
+            ],
+        );
+        let mut fs = Ext4FileSystem::mount(device).unwrap();
+        assert_eq!(fs.read_file("/target").unwrap(), vec![b'o'; 1024]);
+        let stats = fs.journal_stats().unwrap();
+        assert_eq!((stats.blocks_replayed, stats.blocks_revoked), (0, 1));
+
+        // A copy logged after the revoke is still written home
+        let (fs, target) = journal_image("revoke-later");
+        let device = crash_with_log(
+            fs,
+            10,
+            &[
+                revoke(10, &[target]),
+                journal_header(2, 10),
+                descriptor(11, &[target]),
+                vec![b'y'; 1024],
+                journal_header(2, 11),
+            ],
+        );
+        let mut fs = Ext4FileSystem::mount(device).unwrap();
+        assert_eq!(fs.read_file("/target").unwrap(), vec![b'y'; 1024]);
+        let stats = fs.journal_stats().unwrap();
+        assert_eq!((stats.transactions_replayed, stats.blocks_replayed, stats.blocks_revoked), (2, 1, 0));
+    }
 }
@@ -1,15 +1,33 @@
+use super::super::device::BlockDevice;
 use super::super::error::{StorageError, StorageResult};
-use std::collections::HashMap;
+use std::collections::BTreeSet;
 
-pub struct FatFileSystem {
+const DIR_ENTRY_SIZE: usize = 32;
+const LFN_CHARS_PER_ENTRY: usize = 13;
+const DELETED_MARKER: u8 = 0xE5;
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FSINFO_TRAIL_SIGNATURE: u32 = 0xAA55_0000;
+const FSINFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+pub struct FatFileSystem<D: BlockDevice> {
+    device: D,
     boot_sector: BootSector,
-    fat_table: Vec<FatEntry>,
-    root_directory: Directory,
-    current_directory: Directory,
-    cache: FatCache,
+    geometry: Geometry,
+    fat_table: FatTable,
+    fs_info: Option<FsInfo>,
+    current_directory: DirectoryLocation,
+    clock: FatDateTime,
     stats: FatStats,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
 struct BootSector {
     bytes_per_sector: u16,
     sectors_per_cluster: u8,
@@ -18,11 +36,37 @@ struct BootSector {
     root_entries: u16,
     total_sectors: u32,
     media_descriptor: u8,
-    sectors_per_fat: u16,
+    sectors_per_fat: u32,
+    // FAT32 extended BPB
+    ext_flags: u16,
+    root_cluster: u32,
+    fs_info_sector: u16,
+    volume_id: u32,
+    volume_label: [u8; 11],
+}
+
+// Byte offsets of each on-disk region, derived from the boot sector
+struct Geometry {
+    fat_type: FatType,
+    fat_offset: u64,
+    fat_size: u64,
+    root_dir_offset: u64,
+    root_dir_size: u64,
+    data_offset: u64,
+    cluster_size: usize,
+    cluster_count: u32,
+}
+
+// The whole allocation table is kept in memory; dirty sectors are written back on flush
+struct FatTable {
+    bytes: Vec<u8>,
+    fat_type: FatType,
+    sector_size: usize,
+    dirty_sectors: BTreeSet<usize>,
 }
 
-#[derive(Clone, Copy)]
-enum FatEntry {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FatEntry {
     Free,
     Reserved,
     Bad,
@@ -30,16 +74,39 @@ enum FatEntry {
     Next(u32),
 }
 
-struct Directory {
-    entries: Vec<DirectoryEntry>,
-    first_cluster: u32,
-    parent_cluster: Option<u32>,
+struct FsInfo {
+    free_count: u32,
+    next_free: u32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum DirectoryLocation {
+    // FAT12/16 root directory lives in a fixed region before the data area
+    FixedRoot,
+    Chain(u32),
+}
+
+struct LoadedDirectory {
+    location: DirectoryLocation,
+    clusters: Vec<u32>,
+    data: Vec<u8>,
+}
+
+// A short entry together with the long name assembled from the LFN entries before it
+struct DirectorySlot {
+    entry: DirectoryEntry,
+    name: String,
+    index: usize,
+    lfn_count: usize,
 }
 
+#[derive(Clone)]
 struct DirectoryEntry {
     name: [u8; 8],
     extension: [u8; 3],
     attributes: FileAttributes,
+    case_flags: u8,
+    create_time_tenths: u8,
     create_time: u16,
     create_date: u16,
     last_access_date: u16,
@@ -50,96 +117,1578 @@ struct DirectoryEntry {
     file_size: u32,
 }
 
-bitflags! {
-    struct FileAttributes: u8 {
-        const READ_ONLY = 0x01;
-        const HIDDEN = 0x02;
-        const SYSTEM = 0x04;
-        const VOLUME_ID = 0x08;
-        const DIRECTORY = 0x10;
-        const ARCHIVE = 0x20;
-    }
-}
-
-impl FatFileSystem {
-    pub fn new(device: &mut DiskController) -> StorageResult<Self> {
-        // Read boot sector
-        let boot_sector = Self::read_boot_sector(device)?;
-        
-        // Initialize FAT
-        let fat_size = boot_sector.sectors_per_fat as usize * boot_sector.bytes_per_sector as usize;
-        let mut fat_table = vec![FatEntry::Free; fat_size / 4];
-        
-        // Read FAT table
-        Self::read_fat_table(device, &mut fat_table)?;
-        
-        // Read root directory
-        let root_directory = Self::read_root_directory(device, &boot_sector)?;
-        
-        Ok(Self {
+// Attribute byte of a directory entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FileAttributes(u8);
+
+impl FileAttributes {
+    pub const READ_ONLY: Self = Self(0x01);
+    pub const HIDDEN: Self = Self(0x02);
+    pub const SYSTEM: Self = Self(0x04);
+    pub const VOLUME_ID: Self = Self(0x08);
+    pub const DIRECTORY: Self = Self(0x10);
+    pub const ARCHIVE: Self = Self(0x20);
+    pub const LONG_NAME: Self = Self(0x0F);
+
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    // The top two bits are reserved and dropped
+    pub fn from_bits_truncate(bits: u8) -> Self {
+        Self(bits & 0x3F)
+    }
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+impl std::ops::BitOr for FileAttributes {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FatDateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileInfo {
+    pub name: String,
+    pub short_name: String,
+    pub attributes: FileAttributes,
+    pub size: u32,
+    pub first_cluster: u32,
+    pub created: FatDateTime,
+    pub modified: FatDateTime,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FatStats {
+    pub clusters_read: u64,
+    pub clusters_written: u64,
+    pub clusters_allocated: u64,
+    pub clusters_freed: u64,
+    pub fat_sectors_flushed: u64,
+    pub directory_lookups: u64,
+}
+
+impl<D: BlockDevice> FatFileSystem<D> {
+    pub fn mount(mut device: D) -> StorageResult<Self> {
+        let mut sector = [0u8; 512];
+        device.read_bytes(0, &mut sector)?;
+        let boot_sector = BootSector::parse(&sector)?;
+        let geometry = Geometry::from_boot_sector(&boot_sector)?;
+
+        if geometry.data_offset + geometry.cluster_count as u64 * geometry.cluster_size as u64
+            > device.sector_count() * device.sector_size() as u64
+        {
+            return Err(StorageError::InvalidFileSystem("image smaller than volume".into()));
+        }
+
+        // Read the active FAT copy
+        let active_fat = if boot_sector.ext_flags & 0x80 != 0 {
+            (boot_sector.ext_flags & 0x0F) as u64
+        } else {
+            0
+        };
+        let mut bytes = vec![0u8; geometry.fat_size as usize];
+        device.read_bytes(geometry.fat_offset + active_fat * geometry.fat_size, &mut bytes)?;
+        let fat_table = FatTable {
+            bytes,
+            fat_type: geometry.fat_type,
+            sector_size: boot_sector.bytes_per_sector as usize,
+            dirty_sectors: BTreeSet::new(),
+        };
+
+        let current_directory = match geometry.fat_type {
+            FatType::Fat32 => DirectoryLocation::Chain(boot_sector.root_cluster),
+            _ => DirectoryLocation::FixedRoot,
+        };
+
+        let mut fs = Self {
+            device,
             boot_sector,
+            geometry,
             fat_table,
-            root_directory: root_directory.clone(),
-            current_directory: root_directory,
-            cache: FatCache::new(),
+            fs_info: None,
+            current_directory,
+            clock: FatDateTime::DOS_EPOCH,
             stats: FatStats::default(),
-        })
+        };
+        fs.fs_info = fs.read_fs_info()?;
+        Ok(fs)
     }
 
-    pub fn create_file(&mut self, name: &str, attributes: FileAttributes) -> StorageResult<()> {
-        // Find free directory entry
-        let entry_index = self.find_free_directory_entry()?;
-        
-        // Allocate first cluster
-        let first_cluster = self.allocate_cluster()?;
-        
-        // Create directory entry
-        let entry = DirectoryEntry {
-            name: Self::format_filename(name)?,
-            extension: Self::format_extension(name)?,
-            attributes,
-            create_time: self.get_current_time(),
-            create_date: self.get_current_date(),
-            last_access_date: self.get_current_date(),
-            first_cluster_high: (first_cluster >> 16) as u16,
-            write_time: self.get_current_time(),
-            write_date: self.get_current_date(),
-            first_cluster_low: (first_cluster & 0xFFFF) as u16,
-            file_size: 0,
+    // Write a fresh, empty filesystem over the whole device, like mkfs.fat
+    pub fn format(mut device: D, fat_type: Option<FatType>, label: &str) -> StorageResult<Self> {
+        let total_bytes = device.sector_count() * device.sector_size() as u64;
+        let total_sectors = (total_bytes / 512).min(u32::MAX as u64) as u32;
+        let fat_type = fat_type.unwrap_or(match total_bytes {
+            b if b < 16 * 1024 * 1024 => FatType::Fat12,
+            b if b < 512 * 1024 * 1024 => FatType::Fat16,
+            _ => FatType::Fat32,
+        });
+
+        let (reserved_sectors, root_entries) = match fat_type {
+            FatType::Fat32 => (32u16, 0u16),
+            _ => (1, 512),
+        };
+        let root_dir_sectors = (root_entries as u32 * DIR_ENTRY_SIZE as u32).div_ceil(512);
+        let (min_clusters, max_clusters) = fat_type.cluster_count_range();
+
+        // Pick the smallest cluster size whose cluster count fits this FAT type
+        let mut layout = None;
+        for shift in 0..8 {
+            let sectors_per_cluster = 1u32 << shift;
+            let mut sectors_per_fat = 1u32;
+            loop {
+                let overhead = reserved_sectors as u32 + 2 * sectors_per_fat + root_dir_sectors;
+                if overhead >= total_sectors {
+                    break;
+                }
+                let clusters = (total_sectors - overhead) / sectors_per_cluster;
+                let needed = ((clusters as u64 + 2) * fat_type.entry_bits() as u64).div_ceil(8 * 512) as u32;
+                if needed <= sectors_per_fat {
+                    if (min_clusters..=max_clusters).contains(&clusters) {
+                        layout = Some((sectors_per_cluster as u8, sectors_per_fat));
+                    }
+                    break;
+                }
+                sectors_per_fat = needed;
+            }
+            if layout.is_some() {
+                break;
+            }
+        }
+        let (sectors_per_cluster, sectors_per_fat) = layout.ok_or_else(|| {
+            StorageError::InvalidFileSystem(format!("device size does not suit {:?}", fat_type))
+        })?;
+
+        let mut volume_label = [b' '; 11];
+        for (slot, byte) in volume_label.iter_mut().zip(label.to_ascii_uppercase().bytes()) {
+            *slot = byte;
+        }
+        let boot_sector = BootSector {
+            bytes_per_sector: 512,
+            sectors_per_cluster,
+            reserved_sectors,
+            number_of_fats: 2,
+            root_entries,
+            total_sectors,
+            media_descriptor: 0xF8,
+            sectors_per_fat,
+            ext_flags: 0,
+            root_cluster: if fat_type == FatType::Fat32 { 2 } else { 0 },
+            fs_info_sector: if fat_type == FatType::Fat32 { 1 } else { 0 },
+            volume_id: 0x1234_5678 ^ total_sectors,
+            volume_label,
+        };
+        let geometry = Geometry::from_boot_sector(&boot_sector)?;
+
+        // Clear reserved area, both FATs and the fixed root directory
+        let metadata_size = geometry.data_offset as usize;
+        device.write_bytes(0, &vec![0u8; metadata_size])?;
+        let boot = boot_sector.serialize(fat_type);
+        device.write_bytes(0, &boot)?;
+        if fat_type == FatType::Fat32 {
+            device.write_bytes(6 * 512, &boot)?;
+        }
+
+        let mut fat_table = FatTable {
+            bytes: vec![0u8; geometry.fat_size as usize],
+            fat_type,
+            sector_size: 512,
+            dirty_sectors: BTreeSet::new(),
+        };
+        fat_table.set_raw(0, fat_type.media_entry(boot_sector.media_descriptor));
+        fat_table.set_raw(1, fat_type.end_of_chain());
+
+        let (fs_info, current_directory) = if fat_type == FatType::Fat32 {
+            fat_table.set(2, FatEntry::EndOfChain);
+            (
+                Some(FsInfo { free_count: geometry.cluster_count - 1, next_free: 3 }),
+                DirectoryLocation::Chain(2),
+            )
+        } else {
+            (None, DirectoryLocation::FixedRoot)
+        };
+
+        let mut fs = Self {
+            device,
+            boot_sector,
+            geometry,
+            fat_table,
+            fs_info,
+            current_directory,
+            clock: FatDateTime::DOS_EPOCH,
+            stats: FatStats::default(),
+        };
+        if let DirectoryLocation::Chain(root) = current_directory {
+            fs.zero_cluster(root)?;
+        }
+        if !label.is_empty() {
+            let entry = DirectoryEntry::new(fs.boot_sector.volume_label, FileAttributes::VOLUME_ID, 0, fs.clock);
+            let mut root = fs.load_directory(fs.root_location())?;
+            fs.write_slots(&mut root, 0, &[entry.to_bytes()])?;
+        }
+        fs.flush()?;
+        Ok(fs)
+    }
+
+    pub fn unmount(mut self) -> StorageResult<D> {
+        self.flush()?;
+        Ok(self.device)
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.geometry.fat_type
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.geometry.cluster_size
+    }
+
+    pub fn volume_label(&self) -> String {
+        String::from_utf8_lossy(&self.boot_sector.volume_label).trim_end().to_string()
+    }
+
+    pub fn get_stats(&self) -> &FatStats {
+        &self.stats
+    }
+
+    // Timestamps written into directory entries come from this clock rather than the host
+    pub fn set_clock(&mut self, now: FatDateTime) {
+        self.clock = now;
+    }
+
+    pub fn free_clusters(&mut self) -> u32 {
+        if let Some(info) = &self.fs_info {
+            if info.free_count != FSINFO_UNKNOWN {
+                return info.free_count;
+            }
+        }
+        let free = self.count_free_clusters();
+        if let Some(info) = &mut self.fs_info {
+            info.free_count = free;
+        }
+        free
+    }
+
+    pub fn read_dir(&mut self, path: &str) -> StorageResult<Vec<FileInfo>> {
+        let location = self.resolve_directory(path)?;
+        let directory = self.load_directory(location)?;
+        Ok(self
+            .parse_slots(&directory)
+            .into_iter()
+            .filter(|slot| !slot.entry.attributes.contains(FileAttributes::VOLUME_ID))
+            .filter(|slot| slot.name != "." && slot.name != "..")
+            .map(|slot| slot.to_file_info())
+            .collect())
+    }
+
+    pub fn metadata(&mut self, path: &str) -> StorageResult<FileInfo> {
+        let (_, slot) = self.find_entry(path)?;
+        Ok(slot.to_file_info())
+    }
+
+    pub fn change_directory(&mut self, path: &str) -> StorageResult<()> {
+        self.current_directory = self.resolve_directory(path)?;
+        Ok(())
+    }
+
+    pub fn create_file(&mut self, path: &str, attributes: FileAttributes) -> StorageResult<()> {
+        let (parent_path, name) = split_parent(path)?;
+        let parent = self.resolve_directory(parent_path)?;
+        let mut directory = self.load_directory(parent)?;
+        if self.lookup(&directory, name).is_some() {
+            return Err(StorageError::AlreadyExists);
+        }
+
+        // Empty files own no clusters until the first write
+        let entry = DirectoryEntry::new([b' '; 11], attributes | FileAttributes::ARCHIVE, 0, self.clock);
+        self.insert_entry(&mut directory, name, entry)?;
+        Ok(())
+    }
+
+    pub fn create_dir(&mut self, path: &str) -> StorageResult<()> {
+        let (parent_path, name) = split_parent(path)?;
+        let parent = self.resolve_directory(parent_path)?;
+        let mut directory = self.load_directory(parent)?;
+        if self.lookup(&directory, name).is_some() {
+            return Err(StorageError::AlreadyExists);
+        }
+
+        let cluster = self.allocate_cluster(None)?;
+        self.zero_cluster(cluster)?;
+
+        // "." points at the new directory, ".." at its parent (0 means the root)
+        let parent_cluster = match parent {
+            DirectoryLocation::Chain(cluster) if parent != self.root_location() => cluster,
+            _ => 0,
         };
-        
-        // Write directory entry
-        self.write_directory_entry(entry_index, entry)?;
-        
+        let dot = DirectoryEntry::new(*b".          ", FileAttributes::DIRECTORY, cluster, self.clock);
+        let dot_dot = DirectoryEntry::new(*b"..         ", FileAttributes::DIRECTORY, parent_cluster, self.clock);
+        let mut new_directory = self.load_directory(DirectoryLocation::Chain(cluster))?;
+        self.write_slots(&mut new_directory, 0, &[dot.to_bytes(), dot_dot.to_bytes()])?;
+
+        let entry = DirectoryEntry::new([b' '; 11], FileAttributes::DIRECTORY, cluster, self.clock);
+        self.insert_entry(&mut directory, name, entry)?;
         Ok(())
     }
 
+    pub fn read_file(&mut self, path: &str) -> StorageResult<Vec<u8>> {
+        let (_, slot) = self.find_entry(path)?;
+        let mut data = vec![0u8; slot.entry.file_size as usize];
+        self.read_at(path, 0, &mut data)?;
+        Ok(data)
+    }
+
+    pub fn read_at(&mut self, path: &str, offset: u64, buffer: &mut [u8]) -> StorageResult<usize> {
+        let (_, slot) = self.find_entry(path)?;
+        if slot.entry.is_directory() {
+            return Err(StorageError::IsADirectory);
+        }
+
+        let size = slot.entry.file_size as u64;
+        if offset >= size {
+            return Ok(0);
+        }
+        let length = buffer.len().min((size - offset) as usize);
+        let chain = self.cluster_chain(slot.entry.first_cluster())?;
+        self.read_chain(&chain, offset, &mut buffer[..length])?;
+        Ok(length)
+    }
+
+    // Replace the whole contents of a file
     pub fn write(&mut self, path: &str, data: &[u8]) -> StorageResult<usize> {
-        // Find file entry
-        let mut entry = self.find_file(path)?;
-        
-        // Allocate clusters if needed
-        let clusters_needed = (data.len() + self.cluster_size() - 1) / self.cluster_size();
-        let mut current_cluster = self.get_first_cluster(&entry);
-        
-        for _ in 1..clusters_needed {
-            let next_cluster = self.allocate_cluster()?;
-            self.set_fat_entry(current_cluster, FatEntry::Next(next_cluster));
-            current_cluster = next_cluster;
-        }
-        self.set_fat_entry(current_cluster, FatEntry::EndOfChain);
-        
-        // Write data
-        self.write_clusters(self.get_first_cluster(&entry), data)?;
-        
-        // Update directory entry
-        entry.file_size = data.len() as u32;
-        entry.write_time = self.get_current_time();
-        entry.write_date = self.get_current_date();
-        self.update_directory_entry(&entry)?;
-        
+        self.truncate(path, 0)?;
+        self.write_at(path, 0, data)
+    }
+
+    pub fn write_at(&mut self, path: &str, offset: u64, data: &[u8]) -> StorageResult<usize> {
+        let (mut directory, mut slot) = self.find_entry(path)?;
+        if slot.entry.is_directory() {
+            return Err(StorageError::IsADirectory);
+        }
+        if slot.entry.attributes.contains(FileAttributes::READ_ONLY) {
+            return Err(StorageError::ReadOnly);
+        }
+
+        let old_size = slot.entry.file_size as u64;
+        let end = offset + data.len() as u64;
+        if end > u32::MAX as u64 {
+            return Err(StorageError::NoSpace);
+        }
+
+        let chain = self.extend_chain(&mut slot.entry, end)?;
+
+        // Writing past EOF leaves a hole that must read back as zeros
+        if offset > old_size {
+            let zeros = vec![0u8; (offset - old_size) as usize];
+            self.write_chain(&chain, old_size, &zeros)?;
+        }
+        self.write_chain(&chain, offset, data)?;
+
+        slot.entry.file_size = old_size.max(end) as u32;
+        slot.entry.touch(self.clock);
+        self.write_slots(&mut directory, slot.index, &[slot.entry.to_bytes()])?;
         Ok(data.len())
     }
 
-    // Helper methods...
+    pub fn truncate(&mut self, path: &str, size: u64) -> StorageResult<()> {
+        let (mut directory, mut slot) = self.find_entry(path)?;
+        if slot.entry.is_directory() {
+            return Err(StorageError::IsADirectory);
+        }
+
+        let old_size = slot.entry.file_size as u64;
+        if size > old_size {
+            return self.write_at(path, old_size, &vec![0u8; (size - old_size) as usize]).map(|_| ());
+        }
+
+        let keep = size.div_ceil(self.geometry.cluster_size as u64) as usize;
+        let chain = self.cluster_chain(slot.entry.first_cluster())?;
+        if keep < chain.len() {
+            self.free_chain(&chain[keep..]);
+            if keep == 0 {
+                slot.entry.set_first_cluster(0);
+            } else {
+                self.fat_table.set(chain[keep - 1], FatEntry::EndOfChain);
+            }
+        }
+
+        slot.entry.file_size = size as u32;
+        slot.entry.touch(self.clock);
+        self.write_slots(&mut directory, slot.index, &[slot.entry.to_bytes()])
+    }
+
+    pub fn remove(&mut self, path: &str) -> StorageResult<()> {
+        let (mut directory, slot) = self.find_entry(path)?;
+        if slot.name == "." || slot.name == ".." {
+            return Err(StorageError::InvalidName);
+        }
+
+        if slot.entry.is_directory() {
+            let contents = self.load_directory(DirectoryLocation::Chain(slot.entry.first_cluster()))?;
+            let occupied = self
+                .parse_slots(&contents)
+                .iter()
+                .any(|child| child.name != "." && child.name != "..");
+            if occupied {
+                return Err(StorageError::DirectoryNotEmpty);
+            }
+        }
+
+        let chain = self.cluster_chain(slot.entry.first_cluster())?;
+        self.free_chain(&chain);
+        self.delete_slots(&mut directory, &slot)
+    }
+
+    // Move an entry to a new name and/or directory without copying its data.
+    // An existing file at the destination is replaced, as is an empty directory.
+    pub fn rename(&mut self, from: &str, to: &str) -> StorageResult<()> {
+        let (source, slot) = self.find_entry(from)?;
+        if slot.name == "." || slot.name == ".." {
+            return Err(StorageError::InvalidName);
+        }
+        let (parent_path, name) = split_parent(to)?;
+        validate_long_name(name)?;
+        let target_location = self.resolve_directory(parent_path)?;
+
+        // A directory cannot move underneath itself
+        if slot.entry.is_directory() && self.is_inside(target_location, slot.entry.first_cluster())? {
+            return Err(StorageError::InvalidName);
+        }
+
+        let target = self.load_directory(target_location)?;
+        if let Some(existing) = self.lookup(&target, name) {
+            let same_entry = target.location == source.location && existing.index == slot.index;
+            if !same_entry {
+                match (slot.entry.is_directory(), existing.entry.is_directory()) {
+                    (false, true) => return Err(StorageError::IsADirectory),
+                    (true, false) => return Err(StorageError::NotADirectory),
+                    _ => self.remove(to)?,
+                }
+            }
+        }
+
+        // Reload both sides: replacing the destination may have rewritten either
+        let (mut source, slot) = self.find_entry(from)?;
+        self.delete_slots(&mut source, &slot)?;
+        let mut target = self.load_directory(target_location)?;
+        let mut entry = slot.entry.clone();
+        entry.case_flags = 0;
+        self.insert_entry(&mut target, name, entry)?;
+
+        // A moved directory's ".." must follow it to the new parent
+        if slot.entry.is_directory() && target_location != source.location {
+            let parent_cluster = match target_location {
+                DirectoryLocation::Chain(cluster) if target_location != self.root_location() => cluster,
+                _ => 0,
+            };
+            let mut moved = self.load_directory(DirectoryLocation::Chain(slot.entry.first_cluster()))?;
+            if let Some(mut dot_dot) = self.parse_slots(&moved).into_iter().find(|child| child.name == "..") {
+                dot_dot.entry.set_first_cluster(parent_cluster);
+                self.write_slots(&mut moved, dot_dot.index, &[dot_dot.entry.to_bytes()])?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> StorageResult<()> {
+        // Mirror every dirty FAT sector into all FAT copies
+        let sector_size = self.fat_table.sector_size;
+        let mirrored = self.boot_sector.ext_flags & 0x80 == 0;
+        let copies: Vec<u64> = if mirrored {
+            (0..self.boot_sector.number_of_fats as u64).collect()
+        } else {
+            vec![(self.boot_sector.ext_flags & 0x0F) as u64]
+        };
+        let dirty: Vec<usize> = std::mem::take(&mut self.fat_table.dirty_sectors).into_iter().collect();
+        for sector in dirty {
+            let start = sector * sector_size;
+            let bytes = &self.fat_table.bytes[start..start + sector_size];
+            for copy in &copies {
+                let offset = self.geometry.fat_offset + copy * self.geometry.fat_size + start as u64;
+                self.device.write_bytes(offset, bytes)?;
+            }
+            self.stats.fat_sectors_flushed += 1;
+        }
+
+        self.write_fs_info()?;
+        self.device.flush()
+    }
+
+    // Helper methods
+
+    fn root_location(&self) -> DirectoryLocation {
+        match self.geometry.fat_type {
+            FatType::Fat32 => DirectoryLocation::Chain(self.boot_sector.root_cluster),
+            _ => DirectoryLocation::FixedRoot,
+        }
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.geometry.data_offset + (cluster as u64 - 2) * self.geometry.cluster_size as u64
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.geometry.cluster_count + 2
+    }
+
+    fn cluster_chain(&self, first: u32) -> StorageResult<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+        while cluster != 0 {
+            if !self.is_valid_cluster(cluster) || chain.len() > self.geometry.cluster_count as usize {
+                return Err(StorageError::Corrupted(format!("bad cluster chain at {}", cluster)));
+            }
+            chain.push(cluster);
+            cluster = match self.fat_table.get(cluster) {
+                FatEntry::Next(next) => next,
+                FatEntry::EndOfChain => 0,
+                entry => {
+                    return Err(StorageError::Corrupted(format!("cluster {} is {:?} inside a chain", cluster, entry)));
+                }
+            };
+        }
+        Ok(chain)
+    }
+
+    fn allocate_cluster(&mut self, previous: Option<u32>) -> StorageResult<u32> {
+        let total = self.geometry.cluster_count;
+        let hint = self
+            .fs_info
+            .as_ref()
+            .map(|info| info.next_free)
+            .filter(|&next| self.is_valid_cluster(next))
+            .unwrap_or(2);
+
+        let cluster = (0..total)
+            .map(|step| 2 + (hint - 2 + step) % total)
+            .find(|&cluster| self.fat_table.get(cluster) == FatEntry::Free)
+            .ok_or(StorageError::NoSpace)?;
+
+        self.fat_table.set(cluster, FatEntry::EndOfChain);
+        if let Some(previous) = previous {
+            self.fat_table.set(previous, FatEntry::Next(cluster));
+        }
+        if let Some(info) = &mut self.fs_info {
+            if info.free_count != FSINFO_UNKNOWN {
+                info.free_count = info.free_count.saturating_sub(1);
+            }
+            info.next_free = cluster + 1;
+        }
+        self.stats.clusters_allocated += 1;
+        Ok(cluster)
+    }
+
+    fn free_chain(&mut self, chain: &[u32]) {
+        for &cluster in chain {
+            self.fat_table.set(cluster, FatEntry::Free);
+            self.stats.clusters_freed += 1;
+        }
+        if let Some(info) = &mut self.fs_info {
+            if info.free_count != FSINFO_UNKNOWN {
+                info.free_count = (info.free_count + chain.len() as u32).min(self.geometry.cluster_count);
+            }
+        }
+    }
+
+    fn count_free_clusters(&self) -> u32 {
+        (2..self.geometry.cluster_count + 2)
+            .filter(|&cluster| self.fat_table.get(cluster) == FatEntry::Free)
+            .count() as u32
+    }
+
+    // Grow a file's chain so that it covers `size` bytes, returning the full chain
+    fn extend_chain(&mut self, entry: &mut DirectoryEntry, size: u64) -> StorageResult<Vec<u32>> {
+        let mut chain = self.cluster_chain(entry.first_cluster())?;
+        let needed = size.div_ceil(self.geometry.cluster_size as u64) as usize;
+        while chain.len() < needed {
+            let cluster = self.allocate_cluster(chain.last().copied())?;
+            if chain.is_empty() {
+                entry.set_first_cluster(cluster);
+            }
+            chain.push(cluster);
+        }
+        Ok(chain)
+    }
+
+    fn read_chain(&mut self, chain: &[u32], offset: u64, buffer: &mut [u8]) -> StorageResult<()> {
+        for (device_offset, range) in self.chain_extents(chain, offset, buffer.len())? {
+            self.device.read_bytes(device_offset, &mut buffer[range])?;
+            self.stats.clusters_read += 1;
+        }
+        Ok(())
+    }
+
+    fn write_chain(&mut self, chain: &[u32], offset: u64, data: &[u8]) -> StorageResult<()> {
+        for (device_offset, range) in self.chain_extents(chain, offset, data.len())? {
+            self.device.write_bytes(device_offset, &data[range])?;
+            self.stats.clusters_written += 1;
+        }
+        Ok(())
+    }
+
+    // Device offset of each per-cluster piece of `length` bytes starting at
+    // byte `offset` of `chain`, with the piece's range in the caller's buffer
+    fn chain_extents(&self, chain: &[u32], offset: u64, length: usize) -> StorageResult<Vec<(u64, std::ops::Range<usize>)>> {
+        let cluster_size = self.geometry.cluster_size as u64;
+        let mut extents = Vec::new();
+        let mut done = 0usize;
+        while done < length {
+            let position = offset + done as u64;
+            let cluster = *chain
+                .get((position / cluster_size) as usize)
+                .ok_or_else(|| StorageError::Corrupted("chain shorter than file size".into()))?;
+            let within = position % cluster_size;
+            let piece = ((cluster_size - within) as usize).min(length - done);
+            extents.push((self.cluster_offset(cluster) + within, done..done + piece));
+            done += piece;
+        }
+        Ok(extents)
+    }
+
+    fn zero_cluster(&mut self, cluster: u32) -> StorageResult<()> {
+        let zeros = vec![0u8; self.geometry.cluster_size];
+        self.device.write_bytes(self.cluster_offset(cluster), &zeros)
+    }
+
+    fn load_directory(&mut self, location: DirectoryLocation) -> StorageResult<LoadedDirectory> {
+        match location {
+            DirectoryLocation::FixedRoot => {
+                let mut data = vec![0u8; self.geometry.root_dir_size as usize];
+                self.device.read_bytes(self.geometry.root_dir_offset, &mut data)?;
+                Ok(LoadedDirectory { location, clusters: Vec::new(), data })
+            }
+            DirectoryLocation::Chain(first) => {
+                let clusters = self.cluster_chain(first)?;
+                let mut data = vec![0u8; clusters.len() * self.geometry.cluster_size];
+                self.read_chain(&clusters, 0, &mut data)?;
+                Ok(LoadedDirectory { location, clusters, data })
+            }
+        }
+    }
+
+    fn slot_offset(&self, directory: &LoadedDirectory, index: usize) -> u64 {
+        let byte = index * DIR_ENTRY_SIZE;
+        match directory.location {
+            DirectoryLocation::FixedRoot => self.geometry.root_dir_offset + byte as u64,
+            DirectoryLocation::Chain(_) => {
+                let cluster = directory.clusters[byte / self.geometry.cluster_size];
+                self.cluster_offset(cluster) + (byte % self.geometry.cluster_size) as u64
+            }
+        }
+    }
+
+    fn write_slots(&mut self, directory: &mut LoadedDirectory, first: usize, slots: &[[u8; DIR_ENTRY_SIZE]]) -> StorageResult<()> {
+        for (i, raw) in slots.iter().enumerate() {
+            let index = first + i;
+            let offset = self.slot_offset(directory, index);
+            self.device.write_bytes(offset, raw)?;
+            directory.data[index * DIR_ENTRY_SIZE..(index + 1) * DIR_ENTRY_SIZE].copy_from_slice(raw);
+        }
+        Ok(())
+    }
+
+    fn parse_slots(&self, directory: &LoadedDirectory) -> Vec<DirectorySlot> {
+        let mut slots = Vec::new();
+        let mut long_name: Vec<(u8, [u16; LFN_CHARS_PER_ENTRY])> = Vec::new();
+        let mut long_checksum = 0u8;
+
+        for (index, raw) in directory.data.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+            match raw[0] {
+                0x00 => break,
+                DELETED_MARKER => {
+                    long_name.clear();
+                    continue;
+                }
+                _ => {}
+            }
+
+            if raw[11] & 0x3F == FileAttributes::LONG_NAME.bits() {
+                let order = raw[0];
+                if order & 0x40 != 0 {
+                    long_name.clear();
+                    long_checksum = raw[13];
+                }
+                long_name.push((order & 0x1F, lfn_chars(raw)));
+                continue;
+            }
+
+            let entry = DirectoryEntry::from_bytes(raw);
+            // Orphaned or stale LFN entries are ignored, as Windows does
+            let valid_lfn = !long_name.is_empty()
+                && long_checksum == entry.checksum()
+                && long_name.iter().rev().enumerate().all(|(i, (order, _))| *order as usize == i + 1);
+            let (name, lfn_count) = if valid_lfn {
+                let mut units = Vec::new();
+                for (_, chars) in long_name.iter().rev() {
+                    units.extend_from_slice(chars);
+                }
+                let end = units.iter().position(|&unit| unit == 0x0000).unwrap_or(units.len());
+                (String::from_utf16_lossy(&units[..end]), long_name.len())
+            } else {
+                (entry.display_name(), 0)
+            };
+            long_name.clear();
+
+            slots.push(DirectorySlot { entry, name, index, lfn_count });
+        }
+        slots
+    }
+
+    fn lookup(&mut self, directory: &LoadedDirectory, name: &str) -> Option<DirectorySlot> {
+        self.stats.directory_lookups += 1;
+        self.parse_slots(directory).into_iter().find(|slot| {
+            !slot.entry.attributes.contains(FileAttributes::VOLUME_ID)
+                && (slot.name.eq_ignore_ascii_case(name) || slot.entry.short_name().eq_ignore_ascii_case(name))
+        })
+    }
+
+    fn start_location(&self, path: &str) -> DirectoryLocation {
+        if path.starts_with('/') || path.starts_with('\\') {
+            self.root_location()
+        } else {
+            self.current_directory
+        }
+    }
+
+    fn resolve_directory(&mut self, path: &str) -> StorageResult<DirectoryLocation> {
+        let mut location = self.start_location(path);
+        for component in components(path) {
+            let directory = self.load_directory(location)?;
+            let slot = match self.lookup(&directory, component) {
+                Some(slot) => slot,
+                // The root directory has no ".." entry of its own
+                None if component == ".." && location == self.root_location() => continue,
+                None => return Err(StorageError::NotFound),
+            };
+            if !slot.entry.is_directory() {
+                return Err(StorageError::NotADirectory);
+            }
+            location = match slot.entry.first_cluster() {
+                0 => self.root_location(),
+                cluster => DirectoryLocation::Chain(cluster),
+            };
+        }
+        Ok(location)
+    }
+
+    fn find_entry(&mut self, path: &str) -> StorageResult<(LoadedDirectory, DirectorySlot)> {
+        let (parent_path, name) = split_parent(path)?;
+        let parent = self.resolve_directory(parent_path)?;
+        let directory = self.load_directory(parent)?;
+        let slot = self.lookup(&directory, name).ok_or(StorageError::NotFound)?;
+        Ok((directory, slot))
+    }
+
+    // Link `entry` into a directory under `name`, giving it a fresh short name
+    fn insert_entry(&mut self, directory: &mut LoadedDirectory, name: &str, mut entry: DirectoryEntry) -> StorageResult<usize> {
+        validate_long_name(name)?;
+        let existing = self.parse_slots(directory);
+        let (short, needs_lfn) = generate_short_name(name, &existing);
+
+        entry.name.copy_from_slice(&short[..8]);
+        entry.extension.copy_from_slice(&short[8..]);
+        let mut slots = if needs_lfn { build_lfn_entries(name, entry.checksum()) } else { Vec::new() };
+        slots.push(entry.to_bytes());
+
+        let first = match find_free_run(&directory.data, slots.len()) {
+            Some(first) => first,
+            None => self.grow_directory(directory, slots.len())?,
+        };
+        self.write_slots(directory, first, &slots)?;
+        Ok(first + slots.len() - 1)
+    }
+
+    // Mark the short entry and every LFN entry in front of it as deleted
+    fn delete_slots(&mut self, directory: &mut LoadedDirectory, slot: &DirectorySlot) -> StorageResult<()> {
+        let first = slot.index - slot.lfn_count;
+        let mut deleted = Vec::with_capacity(slot.lfn_count + 1);
+        for index in first..=slot.index {
+            let mut raw = [0u8; DIR_ENTRY_SIZE];
+            raw.copy_from_slice(&directory.data[index * DIR_ENTRY_SIZE..(index + 1) * DIR_ENTRY_SIZE]);
+            raw[0] = DELETED_MARKER;
+            deleted.push(raw);
+        }
+        self.write_slots(directory, first, &deleted)
+    }
+
+    // Whether `location` is the directory starting at `cluster` or one of its descendants
+    fn is_inside(&mut self, mut location: DirectoryLocation, cluster: u32) -> StorageResult<bool> {
+        for _ in 0..self.geometry.cluster_count {
+            match location {
+                DirectoryLocation::Chain(current) if current == cluster => return Ok(true),
+                _ if location == self.root_location() => return Ok(false),
+                DirectoryLocation::FixedRoot => return Ok(false),
+                DirectoryLocation::Chain(_) => {}
+            }
+            let directory = self.load_directory(location)?;
+            location = match self.parse_slots(&directory).into_iter().find(|child| child.name == "..") {
+                Some(parent) if parent.entry.first_cluster() != 0 => DirectoryLocation::Chain(parent.entry.first_cluster()),
+                _ => self.root_location(),
+            };
+        }
+        Err(StorageError::Corrupted("directory parent links form a loop".into()))
+    }
+
+    // Append zeroed clusters to a directory until it has a run of `needed` free slots
+    fn grow_directory(&mut self, directory: &mut LoadedDirectory, needed: usize) -> StorageResult<usize> {
+        if directory.location == DirectoryLocation::FixedRoot {
+            return Err(StorageError::NoSpace);
+        }
+        loop {
+            let cluster = self.allocate_cluster(directory.clusters.last().copied())?;
+            self.zero_cluster(cluster)?;
+            directory.clusters.push(cluster);
+            directory.data.resize(directory.data.len() + self.geometry.cluster_size, 0);
+            if let Some(first) = find_free_run(&directory.data, needed) {
+                return Ok(first);
+            }
+        }
+    }
+
+    fn read_fs_info(&mut self) -> StorageResult<Option<FsInfo>> {
+        if self.geometry.fat_type != FatType::Fat32 || self.boot_sector.fs_info_sector == 0 {
+            return Ok(None);
+        }
+        let mut sector = [0u8; 512];
+        let offset = self.boot_sector.fs_info_sector as u64 * self.boot_sector.bytes_per_sector as u64;
+        self.device.read_bytes(offset, &mut sector)?;
+        if read_u32(&sector, 0) != FSINFO_LEAD_SIGNATURE || read_u32(&sector, 484) != FSINFO_STRUCT_SIGNATURE {
+            return Ok(None);
+        }
+
+        // The counts are only hints. Zero is treated as unknown too, since a
+        // stale zero would otherwise underflow on the next allocation.
+        let mut free_count = read_u32(&sector, 488);
+        if free_count == 0 || free_count > self.geometry.cluster_count {
+            free_count = FSINFO_UNKNOWN;
+        }
+        Ok(Some(FsInfo { free_count, next_free: read_u32(&sector, 492) }))
+    }
+
+    fn write_fs_info(&mut self) -> StorageResult<()> {
+        if self.fs_info.is_none() {
+            return Ok(());
+        }
+        let free_count = self.free_clusters();
+        let next_free = self.fs_info.as_ref().map_or(FSINFO_UNKNOWN, |info| info.next_free);
+
+        let mut sector = [0u8; 512];
+        write_u32(&mut sector, 0, FSINFO_LEAD_SIGNATURE);
+        write_u32(&mut sector, 484, FSINFO_STRUCT_SIGNATURE);
+        write_u32(&mut sector, 488, free_count);
+        write_u32(&mut sector, 492, next_free);
+        write_u32(&mut sector, 508, FSINFO_TRAIL_SIGNATURE);
+        let offset = self.boot_sector.fs_info_sector as u64 * self.boot_sector.bytes_per_sector as u64;
+        self.device.write_bytes(offset, &sector)
+    }
+}
+
+impl FatType {
+    fn entry_bits(&self) -> u32 {
+        match self {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        }
+    }
+
+    fn cluster_count_range(&self) -> (u32, u32) {
+        match self {
+            FatType::Fat12 => (1, 4084),
+            FatType::Fat16 => (4085, 65524),
+            FatType::Fat32 => (65525, 0x0FFF_FFF5),
+        }
+    }
+
+    fn end_of_chain(&self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+
+    fn bad_cluster(&self) -> u32 {
+        self.end_of_chain() - 8
+    }
+
+    fn media_entry(&self, media: u8) -> u32 {
+        (self.end_of_chain() & !0xFF) | media as u32
+    }
+}
+
+impl BootSector {
+    fn parse(sector: &[u8]) -> StorageResult<Self> {
+        if sector[510] != 0x55 || sector[511] != 0xAA {
+            return Err(StorageError::InvalidFileSystem("missing boot signature".into()));
+        }
+
+        let bytes_per_sector = read_u16(sector, 11);
+        let sectors_per_cluster = sector[13];
+        if !bytes_per_sector.is_power_of_two() || !(512..=4096).contains(&bytes_per_sector) {
+            return Err(StorageError::InvalidFileSystem("bad bytes per sector".into()));
+        }
+        if !sectors_per_cluster.is_power_of_two() {
+            return Err(StorageError::InvalidFileSystem("bad sectors per cluster".into()));
+        }
+
+        let total_sectors = match read_u16(sector, 19) {
+            0 => read_u32(sector, 32),
+            count => count as u32,
+        };
+        let sectors_per_fat_16 = read_u16(sector, 22);
+        let is_fat32 = sectors_per_fat_16 == 0;
+        let sectors_per_fat = if is_fat32 { read_u32(sector, 36) } else { sectors_per_fat_16 as u32 };
+
+        // The extended boot record sits at a different offset on FAT32
+        let ebr = if is_fat32 { 64 } else { 36 };
+        let mut volume_label = [0u8; 11];
+        volume_label.copy_from_slice(&sector[ebr + 7..ebr + 18]);
+
+        let boot_sector = Self {
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors: read_u16(sector, 14),
+            number_of_fats: sector[16],
+            root_entries: read_u16(sector, 17),
+            total_sectors,
+            media_descriptor: sector[21],
+            sectors_per_fat,
+            ext_flags: if is_fat32 { read_u16(sector, 40) } else { 0 },
+            root_cluster: if is_fat32 { read_u32(sector, 44) } else { 0 },
+            fs_info_sector: if is_fat32 { read_u16(sector, 48) } else { 0 },
+            volume_id: read_u32(sector, ebr + 3),
+            volume_label,
+        };
+
+        if boot_sector.reserved_sectors == 0 || boot_sector.number_of_fats == 0 || sectors_per_fat == 0 {
+            return Err(StorageError::InvalidFileSystem("inconsistent BPB".into()));
+        }
+        Ok(boot_sector)
+    }
+
+    fn serialize(&self, fat_type: FatType) -> [u8; 512] {
+        let mut sector = [0u8; 512];
+        let is_fat32 = fat_type == FatType::Fat32;
+        sector[..3].copy_from_slice(if is_fat32 { &[0xEB, 0x58, 0x90] } else { &[0xEB, 0x3C, 0x90] });
+        sector[3..11].copy_from_slice(b"MSWIN4.1");
+        write_u16(&mut sector, 11, self.bytes_per_sector);
+        sector[13] = self.sectors_per_cluster;
+        write_u16(&mut sector, 14, self.reserved_sectors);
+        sector[16] = self.number_of_fats;
+        write_u16(&mut sector, 17, self.root_entries);
+        if self.total_sectors < 0x10000 {
+            write_u16(&mut sector, 19, self.total_sectors as u16);
+        } else {
+            write_u32(&mut sector, 32, self.total_sectors);
+        }
+        sector[21] = self.media_descriptor;
+        write_u16(&mut sector, 24, 32); // sectors per track
+        write_u16(&mut sector, 26, 64); // heads
+
+        let ebr = if is_fat32 {
+            write_u32(&mut sector, 36, self.sectors_per_fat);
+            write_u16(&mut sector, 40, self.ext_flags);
+            write_u32(&mut sector, 44, self.root_cluster);
+            write_u16(&mut sector, 48, self.fs_info_sector);
+            write_u16(&mut sector, 50, 6); // backup boot sector
+            64
+        } else {
+            write_u16(&mut sector, 22, self.sectors_per_fat as u16);
+            36
+        };
+        sector[ebr] = 0x80; // drive number
+        sector[ebr + 2] = 0x29; // extended boot signature
+        write_u32(&mut sector, ebr + 3, self.volume_id);
+        sector[ebr + 7..ebr + 18].copy_from_slice(&self.volume_label);
+        let fs_type: &[u8; 8] = match fat_type {
+            FatType::Fat12 => b"FAT12   ",
+            FatType::Fat16 => b"FAT16   ",
+            FatType::Fat32 => b"FAT32   ",
+        };
+        sector[ebr + 18..ebr + 26].copy_from_slice(fs_type);
+        sector[510] = 0x55;
+        sector[511] = 0xAA;
+        sector
+    }
+}
+
+impl Geometry {
+    fn from_boot_sector(boot: &BootSector) -> StorageResult<Self> {
+        let sector_size = boot.bytes_per_sector as u64;
+        let root_dir_sectors = (boot.root_entries as u64 * DIR_ENTRY_SIZE as u64).div_ceil(sector_size);
+        let fat_sectors = boot.number_of_fats as u64 * boot.sectors_per_fat as u64;
+        let data_start = boot.reserved_sectors as u64 + fat_sectors + root_dir_sectors;
+        if data_start >= boot.total_sectors as u64 {
+            return Err(StorageError::InvalidFileSystem("no data region".into()));
+        }
+
+        // The FAT type is decided purely by the number of data clusters
+        let cluster_count = ((boot.total_sectors as u64 - data_start) / boot.sectors_per_cluster as u64) as u32;
+        let fat_type = match cluster_count {
+            0..=4084 => FatType::Fat12,
+            4085..=65524 => FatType::Fat16,
+            _ => FatType::Fat32,
+        };
+        if (fat_type == FatType::Fat32) != (boot.root_entries == 0) {
+            return Err(StorageError::InvalidFileSystem("root directory does not match FAT type".into()));
+        }
+
+        let fat_size = boot.sectors_per_fat as u64 * sector_size;
+        if (cluster_count as u64 + 2) * fat_type.entry_bits() as u64 > fat_size * 8 {
+            return Err(StorageError::InvalidFileSystem("FAT too small for cluster count".into()));
+        }
+
+        let fat_offset = boot.reserved_sectors as u64 * sector_size;
+        let root_dir_offset = fat_offset + fat_sectors * sector_size;
+        Ok(Self {
+            fat_type,
+            fat_offset,
+            fat_size,
+            root_dir_offset,
+            root_dir_size: root_dir_sectors * sector_size,
+            data_offset: data_start * sector_size,
+            cluster_size: boot.sectors_per_cluster as usize * sector_size as usize,
+            cluster_count,
+        })
+    }
+}
+
+impl FatTable {
+    fn get_raw(&self, cluster: u32) -> u32 {
+        let index = cluster as usize;
+        match self.fat_type {
+            FatType::Fat12 => {
+                let offset = index + index / 2;
+                let word = read_u16(&self.bytes, offset) as u32;
+                if index & 1 == 1 { word >> 4 } else { word & 0x0FFF }
+            }
+            FatType::Fat16 => read_u16(&self.bytes, index * 2) as u32,
+            FatType::Fat32 => read_u32(&self.bytes, index * 4) & 0x0FFF_FFFF,
+        }
+    }
+
+    fn set_raw(&mut self, cluster: u32, value: u32) {
+        let index = cluster as usize;
+        let (offset, width) = match self.fat_type {
+            FatType::Fat12 => {
+                let offset = index + index / 2;
+                let word = read_u16(&self.bytes, offset);
+                let word = if index & 1 == 1 {
+                    (word & 0x000F) | ((value as u16) << 4)
+                } else {
+                    (word & 0xF000) | (value as u16 & 0x0FFF)
+                };
+                write_u16(&mut self.bytes, offset, word);
+                (offset, 2)
+            }
+            FatType::Fat16 => {
+                write_u16(&mut self.bytes, index * 2, value as u16);
+                (index * 2, 2)
+            }
+            FatType::Fat32 => {
+                // The top four bits are reserved and must be preserved
+                let old = read_u32(&self.bytes, index * 4);
+                write_u32(&mut self.bytes, index * 4, (old & 0xF000_0000) | (value & 0x0FFF_FFFF));
+                (index * 4, 4)
+            }
+        };
+        // A FAT12 entry can straddle two sectors
+        self.dirty_sectors.insert(offset / self.sector_size);
+        self.dirty_sectors.insert((offset + width - 1) / self.sector_size);
+    }
+
+    fn get(&self, cluster: u32) -> FatEntry {
+        let raw = self.get_raw(cluster);
+        let end_of_chain = self.fat_type.end_of_chain();
+        match raw {
+            0 => FatEntry::Free,
+            1 => FatEntry::Reserved,
+            raw if raw == self.fat_type.bad_cluster() => FatEntry::Bad,
+            raw if raw >= end_of_chain - 7 => FatEntry::EndOfChain,
+            raw if raw > end_of_chain - 16 => FatEntry::Reserved,
+            raw => FatEntry::Next(raw),
+        }
+    }
+
+    fn set(&mut self, cluster: u32, entry: FatEntry) {
+        let value = match entry {
+            FatEntry::Free => 0,
+            FatEntry::Reserved => 1,
+            FatEntry::Bad => self.fat_type.bad_cluster(),
+            FatEntry::EndOfChain => self.fat_type.end_of_chain(),
+            FatEntry::Next(next) => next,
+        };
+        self.set_raw(cluster, value);
+    }
+}
+
+impl DirectoryEntry {
+    fn new(short: [u8; 11], attributes: FileAttributes, first_cluster: u32, now: FatDateTime) -> Self {
+        let mut name = [0u8; 8];
+        let mut extension = [0u8; 3];
+        name.copy_from_slice(&short[..8]);
+        extension.copy_from_slice(&short[8..]);
+        let (date, time) = now.to_dos();
+        let mut entry = Self {
+            name,
+            extension,
+            attributes,
+            case_flags: 0,
+            create_time_tenths: 0,
+            create_time: time,
+            create_date: date,
+            last_access_date: date,
+            first_cluster_high: 0,
+            write_time: time,
+            write_date: date,
+            first_cluster_low: 0,
+            file_size: 0,
+        };
+        entry.set_first_cluster(first_cluster);
+        entry
+    }
+
+    fn from_bytes(raw: &[u8]) -> Self {
+        let mut name = [0u8; 8];
+        let mut extension = [0u8; 3];
+        name.copy_from_slice(&raw[0..8]);
+        extension.copy_from_slice(&raw[8..11]);
+        // 0x05 stands in for a leading 0xE5 byte in real names
+        if name[0] == 0x05 {
+            name[0] = DELETED_MARKER;
+        }
+        Self {
+            name,
+            extension,
+            attributes: FileAttributes::from_bits_truncate(raw[11]),
+            case_flags: raw[12],
+            create_time_tenths: raw[13],
+            create_time: read_u16(raw, 14),
+            create_date: read_u16(raw, 16),
+            last_access_date: read_u16(raw, 18),
+            first_cluster_high: read_u16(raw, 20),
+            write_time: read_u16(raw, 22),
+            write_date: read_u16(raw, 24),
+            first_cluster_low: read_u16(raw, 26),
+            file_size: read_u32(raw, 28),
+        }
+    }
+
+    fn to_bytes(&self) -> [u8; DIR_ENTRY_SIZE] {
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        raw[0..8].copy_from_slice(&self.name);
+        raw[8..11].copy_from_slice(&self.extension);
+        if raw[0] == DELETED_MARKER {
+            raw[0] = 0x05;
+        }
+        raw[11] = self.attributes.bits();
+        raw[12] = self.case_flags;
+        raw[13] = self.create_time_tenths;
+        write_u16(&mut raw, 14, self.create_time);
+        write_u16(&mut raw, 16, self.create_date);
+        write_u16(&mut raw, 18, self.last_access_date);
+        write_u16(&mut raw, 20, self.first_cluster_high);
+        write_u16(&mut raw, 22, self.write_time);
+        write_u16(&mut raw, 24, self.write_date);
+        write_u16(&mut raw, 26, self.first_cluster_low);
+        write_u32(&mut raw, 28, self.file_size);
+        raw
+    }
+
+    fn first_cluster(&self) -> u32 {
+        ((self.first_cluster_high as u32) << 16) | self.first_cluster_low as u32
+    }
+
+    fn set_first_cluster(&mut self, cluster: u32) {
+        self.first_cluster_high = (cluster >> 16) as u16;
+        self.first_cluster_low = (cluster & 0xFFFF) as u16;
+    }
+
+    fn is_directory(&self) -> bool {
+        self.attributes.contains(FileAttributes::DIRECTORY)
+    }
+
+    fn touch(&mut self, now: FatDateTime) {
+        let (date, time) = now.to_dos();
+        self.write_date = date;
+        self.write_time = time;
+        self.last_access_date = date;
+        self.attributes.insert(FileAttributes::ARCHIVE);
+    }
+
+    fn raw_name(&self) -> [u8; 11] {
+        let mut raw = [0u8; 11];
+        raw[..8].copy_from_slice(&self.name);
+        raw[8..].copy_from_slice(&self.extension);
+        raw
+    }
+
+    // The LFN checksum ties long-name entries to their short entry
+    fn checksum(&self) -> u8 {
+        self.raw_name()
+            .iter()
+            .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+    }
+
+    fn short_name(&self) -> String {
+        let base = String::from_utf8_lossy(&self.name).trim_end().to_string();
+        let extension = String::from_utf8_lossy(&self.extension).trim_end().to_string();
+        if extension.is_empty() {
+            base
+        } else {
+            format!("{}.{}", base, extension)
+        }
+    }
+
+    // Honour the NT lowercase flags so "readme.txt" without an LFN still reads back lowercase
+    fn display_name(&self) -> String {
+        let mut base = String::from_utf8_lossy(&self.name).trim_end().to_string();
+        let mut extension = String::from_utf8_lossy(&self.extension).trim_end().to_string();
+        if self.case_flags & 0x08 != 0 {
+            base = base.to_ascii_lowercase();
+        }
+        if self.case_flags & 0x10 != 0 {
+            extension = extension.to_ascii_lowercase();
+        }
+        if extension.is_empty() {
+            base
+        } else {
+            format!("{}.{}", base, extension)
+        }
+    }
+}
+
+impl DirectorySlot {
+    fn to_file_info(&self) -> FileInfo {
+        FileInfo {
+            name: self.name.clone(),
+            short_name: self.entry.short_name(),
+            attributes: self.entry.attributes,
+            size: self.entry.file_size,
+            first_cluster: self.entry.first_cluster(),
+            created: FatDateTime::from_dos(self.entry.create_date, self.entry.create_time),
+            modified: FatDateTime::from_dos(self.entry.write_date, self.entry.write_time),
+        }
+    }
+}
+
+impl FatDateTime {
+    pub const DOS_EPOCH: FatDateTime = FatDateTime { year: 1980, month: 1, day: 1, hour: 0, minute: 0, second: 0 };
+
+    // Seconds since 1970-01-01, treating the stored local time as UTC
+    pub fn to_unix(self) -> u64 {
+        let (year, month) = if self.month <= 2 { (self.year as i64 - 1, self.month as i64 + 9) } else { (self.year as i64, self.month as i64 - 3) };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * month + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+        (days * 86_400 + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64).max(0) as u64
+    }
+
+    fn to_dos(self) -> (u16, u16) {
+        let date = ((self.year.saturating_sub(1980) & 0x7F) << 9) | ((self.month as u16) << 5) | self.day as u16;
+        let time = ((self.hour as u16) << 11) | ((self.minute as u16) << 5) | (self.second as u16 / 2);
+        (date, time)
+    }
+
+    fn from_dos(date: u16, time: u16) -> Self {
+        Self {
+            year: 1980 + (date >> 9),
+            month: ((date >> 5) & 0x0F) as u8,
+            day: (date & 0x1F) as u8,
+            hour: (time >> 11) as u8,
+            minute: ((time >> 5) & 0x3F) as u8,
+            second: ((time & 0x1F) * 2) as u8,
+        }
+    }
+}
+
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split(['/', '\\']).filter(|part| !part.is_empty() && *part != ".")
+}
+
+fn split_parent(path: &str) -> StorageResult<(&str, &str)> {
+    let trimmed = path.trim_end_matches(['/', '\\']);
+    match trimmed.rfind(['/', '\\']) {
+        Some(split) => {
+            let parent = if split == 0 { &trimmed[..1] } else { &trimmed[..split] };
+            Ok((parent, &trimmed[split + 1..]))
+        }
+        None if trimmed.is_empty() => Err(StorageError::InvalidName),
+        None => Ok(("", trimmed)),
+    }
+}
+
+fn validate_long_name(name: &str) -> StorageResult<()> {
+    let invalid = |c: char| c < ' ' || "\"*/:<>?\\|".contains(c);
+    if name.is_empty() || name == "." || name == ".." || name.encode_utf16().count() > 255 || name.contains(invalid) {
+        return Err(StorageError::InvalidName);
+    }
+    Ok(())
+}
+
+fn find_free_run(data: &[u8], needed: usize) -> Option<usize> {
+    let mut run = 0;
+    for (index, raw) in data.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+        if raw[0] == 0x00 || raw[0] == DELETED_MARKER {
+            run += 1;
+            if run == needed {
+                return Some(index + 1 - needed);
+            }
+        } else {
+            run = 0;
+        }
+    }
+    None
+}
+
+// Build a unique 8.3 alias; the bool says whether LFN entries are needed to keep the real name
+fn generate_short_name(name: &str, existing: &[DirectorySlot]) -> ([u8; 11], bool) {
+    let valid = |c: char| c.is_ascii_alphanumeric() || "$%'-_@~`!(){}^#&".contains(c);
+    let clean = |part: &str, lossy: &mut bool| -> String {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| {
+                let upper = c.to_ascii_uppercase();
+                if valid(upper) {
+                    upper
+                } else {
+                    *lossy = true;
+                    '_'
+                }
+            })
+            .collect()
+    };
+
+    let trimmed = name.trim_start_matches('.');
+    let (base, extension) = match trimmed.rfind('.') {
+        Some(dot) => (&trimmed[..dot], &trimmed[dot + 1..]),
+        None => (trimmed, ""),
+    };
+    let mut lossy = trimmed.len() != name.len() || base.contains(['.', ' ']) || extension.contains(' ');
+    let base = clean(base, &mut lossy);
+    let extension = clean(extension, &mut lossy);
+    lossy |= base.is_empty() || base.len() > 8 || extension.len() > 3;
+
+    let taken = |candidate: &[u8; 11]| existing.iter().any(|slot| slot.entry.raw_name() == *candidate);
+    let pack = |base: &str, extension: &str| {
+        let mut raw = [b' '; 11];
+        for (slot, byte) in raw[..8].iter_mut().zip(base.bytes()) {
+            *slot = byte;
+        }
+        for (slot, byte) in raw[8..].iter_mut().zip(extension.bytes()) {
+            *slot = byte;
+        }
+        raw
+    };
+
+    let extension: String = extension.chars().take(3).collect();
+    if !lossy {
+        let candidate = pack(&base, &extension);
+        if !taken(&candidate) {
+            // Exact upper-case 8.3 names need no long-name entries
+            let exact = name == candidate_display(&base, &extension);
+            return (candidate, !exact);
+        }
+    }
+
+    let base = if base.is_empty() { "_".to_string() } else { base };
+    for tail in 1..1_000_000u32 {
+        let suffix = format!("~{}", tail);
+        let keep = 8 - suffix.len();
+        let stem: String = base.chars().take(keep).collect();
+        let candidate = pack(&format!("{}{}", stem, suffix), &extension);
+        if !taken(&candidate) {
+            return (candidate, true);
+        }
+    }
+    (pack(&base, &extension), true)
+}
+
+fn candidate_display(base: &str, extension: &str) -> String {
+    if extension.is_empty() {
+        base.to_string()
+    } else {
+        format!("{}.{}", base, extension)
+    }
+}
+
+fn build_lfn_entries(name: &str, checksum: u8) -> Vec<[u8; DIR_ENTRY_SIZE]> {
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    // Names that don't fill the last entry get a NUL terminator, then 0xFFFF padding
+    if !units.len().is_multiple_of(LFN_CHARS_PER_ENTRY) {
+        units.push(0x0000);
+        while !units.len().is_multiple_of(LFN_CHARS_PER_ENTRY) {
+            units.push(0xFFFF);
+        }
+    }
+
+    let count = units.len() / LFN_CHARS_PER_ENTRY;
+    (0..count)
+        .rev()
+        .map(|part| {
+            let mut raw = [0u8; DIR_ENTRY_SIZE];
+            raw[0] = (part + 1) as u8 | if part + 1 == count { 0x40 } else { 0 };
+            raw[11] = FileAttributes::LONG_NAME.bits();
+            raw[13] = checksum;
+            let chars = &units[part * LFN_CHARS_PER_ENTRY..(part + 1) * LFN_CHARS_PER_ENTRY];
+            for (i, &unit) in chars.iter().enumerate() {
+                let offset = LFN_OFFSETS[i];
+                write_u16(&mut raw, offset, unit);
+            }
+            raw
+        })
+        .collect()
+}
+
+// Byte positions of the 13 UCS-2 characters inside an LFN entry
+const LFN_OFFSETS: [usize; LFN_CHARS_PER_ENTRY] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+fn lfn_chars(raw: &[u8]) -> [u16; LFN_CHARS_PER_ENTRY] {
+    let mut chars = [0u16; LFN_CHARS_PER_ENTRY];
+    for (i, &offset) in LFN_OFFSETS.iter().enumerate() {
+        chars[i] = read_u16(raw, offset);
+    }
+    chars
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::device::MemoryBlockDevice;
+    use super::*;
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    fn formatted(fat_type: FatType, megabytes: u64) -> FatFileSystem<MemoryBlockDevice> {
+        let device = MemoryBlockDevice::new(megabytes * 2048, 512);
+        FatFileSystem::format(device, Some(fat_type), "TEST").unwrap()
+    }
+
+    fn temp_image(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fat-{}-{}.img", name, std::process::id()))
+    }
+
+    fn populate(fs: &mut FatFileSystem<MemoryBlockDevice>) {
+        fs.create_dir("/docs").unwrap();
+        fs.create_file("/docs/A rather long file name.txt", FileAttributes::empty()).unwrap();
+        fs.write("/docs/A rather long file name.txt", b"hello, fat").unwrap();
+        fs.create_file("/big.bin", FileAttributes::empty()).unwrap();
+        let big: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        fs.write("/big.bin", &big).unwrap();
+        fs.flush().unwrap();
+    }
+
+    #[test]
+    fn files_survive_a_remount() {
+        for fat_type in [FatType::Fat12, FatType::Fat16, FatType::Fat32] {
+            let megabytes = if fat_type == FatType::Fat32 { 64 } else { 8 };
+            let mut fs = formatted(fat_type, megabytes);
+            populate(&mut fs);
+
+            let mut fs = FatFileSystem::mount(fs.unmount().unwrap()).unwrap();
+            assert_eq!(fs.fat_type(), fat_type);
+            assert_eq!(fs.read_file("/docs/A rather long file name.txt").unwrap(), b"hello, fat");
+            let big = fs.read_file("/big.bin").unwrap();
+            assert_eq!(big.len(), 100_000);
+            assert!(big.iter().enumerate().all(|(i, &byte)| byte == i as u8));
+        }
+    }
+
+    #[test]
+    fn write_past_end_reads_back_zeros() {
+        let mut fs = formatted(FatType::Fat16, 8);
+        fs.create_file("/sparse", FileAttributes::empty()).unwrap();
+        fs.write_at("/sparse", 5000, b"tail").unwrap();
+        let data = fs.read_file("/sparse").unwrap();
+        assert_eq!(data.len(), 5004);
+        assert!(data[..5000].iter().all(|&byte| byte == 0));
+        assert_eq!(&data[5000..], b"tail");
+    }
+
+    #[test]
+    fn stale_zero_free_count_is_recounted() {
+        let mut fs = formatted(FatType::Fat32, 64);
+        fs.flush().unwrap();
+        let expected = fs.free_clusters();
+
+        // Zero the FSInfo free count, as a crashed writer can leave it
+        let mut device = fs.unmount().unwrap();
+        let mut sector = [0u8; 512];
+        device.read_bytes(512, &mut sector).unwrap();
+        write_u32(&mut sector, 488, 0);
+        device.write_bytes(512, &sector).unwrap();
+
+        let mut fs = FatFileSystem::mount(device).unwrap();
+        assert_eq!(fs.free_clusters(), expected);
+        fs.create_file("/after", FileAttributes::empty()).unwrap();
+        fs.write("/after", b"x").unwrap();
+        assert_eq!(fs.free_clusters(), expected - 1);
+    }
+
+    // Needs mkfs.fat and fsck.fat from dosfstools
+    #[test]
+    fn mkfs_fat_image_round_trips_through_fsck() {
+        for (bits, megabytes) in [("12", 4), ("16", 32), ("32", 64)] {
+            let path = temp_image(bits);
+            let _ = std::fs::remove_file(&path);
+            let made = Command::new("mkfs.fat")
+                .args(["-C", "-F", bits, "-n", "SIMTEST"])
+                .arg(&path)
+                .arg((megabytes * 1024).to_string())
+                .output()
+                .expect("mkfs.fat not found");
+            assert!(made.status.success(), "{}", String::from_utf8_lossy(&made.stderr));
+
+            let mut fs = FatFileSystem::mount(MemoryBlockDevice::open(&path).unwrap()).unwrap();
+            assert_eq!(fs.volume_label(), "SIMTEST");
+            populate(&mut fs);
+            fs.unmount().unwrap().save(&path).unwrap();
+
+            let checked = Command::new("fsck.fat").arg("-n").arg(&path).output().expect("fsck.fat not found");
+            assert!(checked.status.success(), "{}", String::from_utf8_lossy(&checked.stdout));
+            let _ = std::fs::remove_file(&path);
+        }
+    }
 }
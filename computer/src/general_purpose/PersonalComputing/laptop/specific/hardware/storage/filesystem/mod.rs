@@ -2,3 +2,33 @@
 pub mod ext4;
 pub mod fat;
 pub mod ntfs;
+
+// On-disk formats a volume can be formatted with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileSystemKind {
+    APFS,
+    NTFS,
+    Ext4,
+    FAT32,
+}
+
+impl FileSystemKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "APFS" => Some(FileSystemKind::APFS),
+            "NTFS" => Some(FileSystemKind::NTFS),
+            "ext4" => Some(FileSystemKind::Ext4),
+            "FAT32" => Some(FileSystemKind::FAT32),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileSystemKind::APFS => "APFS",
+            FileSystemKind::NTFS => "NTFS",
+            FileSystemKind::Ext4 => "ext4",
+            FileSystemKind::FAT32 => "FAT32",
+        }
+    }
+}
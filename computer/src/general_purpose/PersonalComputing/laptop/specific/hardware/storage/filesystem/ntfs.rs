@@ -1,134 +1,1631 @@
+use super::super::device::BlockDevice;
 use super::super::error::{StorageError, StorageResult};
+use std::cmp::Ordering;
 use std::collections::HashMap;
 
-pub struct NtfsFileSystem {
+// Well-known MFT record numbers
+const MFT_RECORD_VOLUME: u64 = 3;
+const MFT_RECORD_ROOT: u64 = 5;
+const MFT_RECORD_BITMAP: u64 = 6;
+const MFT_RECORD_UPCASE: u64 = 10;
+const FIRST_USER_RECORD: u64 = 16;
+
+// $MFTMirr holds copies of the first records, through $Volume
+const MFT_MIRROR_RECORDS: u64 = 4;
+
+// Fixups protect every 512-byte stride of a multi-sector record
+const FIXUP_STRIDE: usize = 512;
+
+// Bounds on geometry read from the boot sector; Windows formats at most
+// 2 MiB clusters, and MFT and index records are 1-4 KiB in practice
+const MAX_CLUSTER_SIZE: usize = 2 << 20;
+const MAX_RECORD_SIZE: usize = 64 << 10;
+
+pub struct NtfsFileSystem<D: BlockDevice> {
+    device: D,
     boot_sector: BootSector,
     mft: MasterFileTable,
     bitmap: Bitmap,
     volume_info: VolumeInfo,
-    cache: NtfsCache,
+    upcase: Vec<u16>,
     stats: NtfsStats,
 }
 
 struct BootSector {
     bytes_per_sector: u16,
-    sectors_per_cluster: u8,
+    sectors_per_cluster: u32,
+    total_sectors: u64,
     mft_cluster: u64,
     mft_mirror_cluster: u64,
-    clusters_per_mft_record: i8,
-    clusters_per_index_record: i8,
+    mft_record_size: usize,
+    index_record_size: usize,
     volume_serial: u64,
 }
 
 struct MasterFileTable {
     entries: HashMap<u64, MftEntry>,
-    free_entries: Vec<u64>,
-    next_record_number: u64,
+    // Runs of $MFT's own unnamed $DATA stream, so any record can be located
+    runs: Vec<DataRun>,
+    record_count: u64,
 }
 
+#[derive(Clone)]
 struct MftEntry {
     header: MftHeader,
     attributes: Vec<NtfsAttribute>,
-    reference_count: u16,
-    base_reference: Option<u64>,
 }
 
+#[derive(Clone)]
 struct MftHeader {
     sequence_number: u16,
     link_count: u16,
     first_attribute_offset: u16,
-    flags: MftFlags,
+    flags: u16,
     used_size: u32,
     allocated_size: u32,
+    base_reference: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AttributeType {
+    StandardInformation,
+    AttributeList,
+    FileName,
+    VolumeName,
+    VolumeInformation,
+    Data,
+    IndexRoot,
+    IndexAllocation,
+    Bitmap,
+    Other(u32),
 }
 
-enum NtfsAttribute {
-    StandardInfo(StandardInfo),
-    FileName(FileName),
-    Data(DataAttribute),
-    IndexRoot(IndexRoot),
-    IndexAllocation(IndexAllocation),
-    Bitmap(BitmapAttribute),
-}
-
-struct StandardInfo {
-    creation_time: u64,
-    modification_time: u64,
-    mft_modification_time: u64,
-    access_time: u64,
-    file_attributes: FileAttributes,
-}
-
-struct FileName {
-    parent_directory: u64,
-    creation_time: u64,
-    modification_time: u64,
-    mft_modification_time: u64,
-    access_time: u64,
-    allocated_size: u64,
-    real_size: u64,
-    flags: FileFlags,
+#[derive(Clone)]
+struct NtfsAttribute {
+    kind: AttributeType,
     name: String,
+    flags: u16,
+    value: AttributeValue,
+}
+
+#[derive(Clone)]
+enum AttributeValue {
+    Resident(Vec<u8>),
+    NonResident(NonResidentValue),
+}
+
+#[derive(Clone)]
+struct NonResidentValue {
+    starting_vcn: u64,
+    runs: Vec<DataRun>,
+    data_size: u64,
+    initialized_size: u64,
+}
+
+// A contiguous stretch of clusters; `lcn` is None for sparse runs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DataRun {
+    pub vcn: u64,
+    pub lcn: Option<u64>,
+    pub length: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct StandardInfo {
+    pub creation_time: u64,
+    pub modification_time: u64,
+    pub mft_modification_time: u64,
+    pub access_time: u64,
+    pub file_attributes: FileAttributes,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileName {
+    pub parent_directory: u64,
+    pub creation_time: u64,
+    pub modification_time: u64,
+    pub mft_modification_time: u64,
+    pub access_time: u64,
+    pub allocated_size: u64,
+    pub real_size: u64,
+    pub flags: FileAttributes,
+    pub namespace: FileNamespace,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileNamespace {
+    Posix,
+    Win32,
+    Dos,
+    Win32AndDos,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FileAttributes(u32);
+
+impl FileAttributes {
+    pub const READ_ONLY: Self = Self(0x0001);
+    pub const HIDDEN: Self = Self(0x0002);
+    pub const SYSTEM: Self = Self(0x0004);
+    pub const ARCHIVE: Self = Self(0x0020);
+    pub const SPARSE: Self = Self(0x0200);
+    pub const REPARSE_POINT: Self = Self(0x0400);
+    pub const COMPRESSED: Self = Self(0x0800);
+    pub const ENCRYPTED: Self = Self(0x4000);
+    pub const DIRECTORY: Self = Self(0x1000_0000);
+
+    const KNOWN: u32 = 0x1000_4E27;
+
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    // Attribute bits this driver doesn't model are dropped
+    pub fn from_bits_truncate(bits: u32) -> Self {
+        Self(bits & Self::KNOWN)
+    }
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectoryEntry {
+    pub record: u64,
+    pub name: String,
+    pub is_directory: bool,
+    pub size: u64,
+    pub modification_time: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordInfo {
+    pub record: u64,
+    pub is_directory: bool,
+    pub size: u64,
+    pub link_count: u16,
+    pub modification_time: u64,
+    pub file_attributes: FileAttributes,
+}
+
+#[derive(Debug, Clone)]
+pub struct VolumeInfo {
+    pub name: String,
+    pub major_version: u8,
+    pub minor_version: u8,
+    pub flags: u16,
+    pub serial_number: u64,
+}
+
+// Cluster allocation bitmap from the $Bitmap metafile
+pub struct Bitmap {
+    bits: Vec<u8>,
+    cluster_count: u64,
 }
 
-impl NtfsFileSystem {
-    pub fn new(device: &mut DiskController) -> StorageResult<Self> {
+#[derive(Debug, Clone, Default)]
+pub struct NtfsStats {
+    pub records_read: u64,
+    pub record_cache_hits: u64,
+    pub clusters_read: u64,
+    pub index_blocks_read: u64,
+    pub fixups_applied: u64,
+    pub mirror_reads: u64,
+}
+
+// Index node as found in $INDEX_ROOT or an INDX block
+struct IndexEntry {
+    reference: u64,
+    file_name: Option<FileName>,
+    child_vcn: Option<u64>,
+}
+
+impl<D: BlockDevice> NtfsFileSystem<D> {
+    pub fn mount(mut device: D) -> StorageResult<Self> {
         // Read boot sector
-        let boot_sector = Self::read_boot_sector(device)?;
-        
-        // Initialize MFT
-        let mft = Self::initialize_mft(device, &boot_sector)?;
-        
-        // Read volume information
-        let volume_info = Self::read_volume_info(&mft)?;
-        
-        // Read bitmap
-        let bitmap = Self::read_bitmap(&mft)?;
-        
-        Ok(Self {
+        let mut sector = [0u8; 512];
+        device.read_bytes(0, &mut sector)?;
+        let boot_sector = BootSector::parse(&sector)?;
+
+        // Bootstrap the MFT from record 0, which describes $MFT itself
+        let mft_offset = boot_sector
+            .mft_cluster
+            .checked_mul(boot_sector.cluster_size())
+            .ok_or_else(|| StorageError::InvalidFileSystem("$MFT cluster out of range".into()))?;
+        let mut raw = vec![0u8; boot_sector.mft_record_size];
+        device.read_bytes(mft_offset, &mut raw)?;
+        let mut stats = NtfsStats::default();
+        match apply_fixups(&mut raw, b"FILE") {
+            Ok(strides) => stats.fixups_applied += strides as u64,
+            Err(error) => raw = read_mirror(&mut device, &boot_sector, 0, &mut stats).map_err(|_| error)?,
+        }
+        let mft_record = MftEntry::parse(&raw)?;
+        let (runs, data_size) = match mft_record.find(AttributeType::Data, "").map(|attribute| &attribute.value) {
+            Some(AttributeValue::NonResident(value)) => (value.runs.clone(), value.data_size),
+            _ => return Err(StorageError::InvalidFileSystem("$MFT has no non-resident $DATA".into())),
+        };
+
+        let mft = MasterFileTable {
+            entries: HashMap::new(),
+            record_count: data_size / boot_sector.mft_record_size as u64,
+            runs,
+        };
+        let mut fs = Self {
+            device,
             boot_sector,
             mft,
-            bitmap,
-            volume_info,
-            cache: NtfsCache::new(),
-            stats: NtfsStats::default(),
+            bitmap: Bitmap { bits: Vec::new(), cluster_count: 0 },
+            volume_info: VolumeInfo { name: String::new(), major_version: 0, minor_version: 0, flags: 0, serial_number: 0 },
+            upcase: Vec::new(),
+            stats,
+        };
+
+        // $MFT may itself be fragmented across extension records
+        let full_mft = fs.load_record(0)?;
+        if let Some(runs) = fs.collect_runs(&full_mft, AttributeType::Data, "")? {
+            fs.mft.runs = runs;
+        }
+
+        fs.volume_info = fs.read_volume_info()?;
+        fs.upcase = fs.read_upcase()?;
+        fs.bitmap = fs.read_bitmap()?;
+        Ok(fs)
+    }
+
+    pub fn unmount(self) -> D {
+        self.device
+    }
+
+    pub fn volume_info(&self) -> &VolumeInfo {
+        &self.volume_info
+    }
+
+    pub fn cluster_size(&self) -> u64 {
+        self.boot_sector.cluster_size()
+    }
+
+    pub fn get_stats(&self) -> &NtfsStats {
+        &self.stats
+    }
+
+    pub fn bitmap(&self) -> &Bitmap {
+        &self.bitmap
+    }
+
+    pub fn read_dir(&mut self, path: &str) -> StorageResult<Vec<DirectoryEntry>> {
+        let record = self.resolve(path)?;
+        self.read_dir_record(record)
+    }
+
+    pub fn standard_info(&mut self, path: &str) -> StorageResult<StandardInfo> {
+        let record = self.resolve(path)?;
+        self.record_standard_info(record)
+    }
+
+    pub fn file_size(&mut self, path: &str) -> StorageResult<u64> {
+        let record = self.resolve(path)?;
+        let entry = self.load_record(record)?;
+        Ok(entry.find(AttributeType::Data, "").map_or(0, |attribute| attribute.data_size()))
+    }
+
+    pub fn read_file(&mut self, path: &str) -> StorageResult<Vec<u8>> {
+        self.read_stream(path, "")
+    }
+
+    // Read a named alternate data stream, e.g. "Zone.Identifier"
+    pub fn read_stream(&mut self, path: &str, stream: &str) -> StorageResult<Vec<u8>> {
+        let record = self.resolve(path)?;
+        let entry = self.load_record(record)?;
+        if entry.header.has_flag(MftHeader::DIRECTORY) && stream.is_empty() {
+            return Err(StorageError::IsADirectory);
+        }
+        self.read_attribute(&entry, AttributeType::Data, stream)
+    }
+
+    pub fn read_at(&mut self, path: &str, offset: u64, buffer: &mut [u8]) -> StorageResult<usize> {
+        let record = self.resolve(path)?;
+        self.read_record_at(record, offset, buffer)
+    }
+
+    // Data runs of a file, for the visualizer to draw fragmentation
+    pub fn data_runs(&mut self, path: &str) -> StorageResult<Vec<DataRun>> {
+        let record = self.resolve(path)?;
+        let entry = self.load_record(record)?;
+        Ok(self.collect_runs(&entry, AttributeType::Data, "")?.unwrap_or_default())
+    }
+
+    // Record-number based access, for the VFS where MFT records stand in for inodes
+    pub fn root_record(&self) -> u64 {
+        MFT_RECORD_ROOT
+    }
+
+    pub fn lookup_record(&mut self, directory: u64, name: &str) -> StorageResult<Option<u64>> {
+        self.lookup(directory, name)
+    }
+
+    pub fn record_metadata(&mut self, record: u64) -> StorageResult<RecordInfo> {
+        let entry = self.load_record(record)?;
+        let info = self.record_standard_info(record)?;
+        Ok(RecordInfo {
+            record,
+            is_directory: entry.header.has_flag(MftHeader::DIRECTORY),
+            size: entry.find(AttributeType::Data, "").map_or(0, |attribute| attribute.data_size()),
+            link_count: entry.header.link_count,
+            modification_time: info.modification_time,
+            file_attributes: info.file_attributes,
+        })
+    }
+
+    // Reads only the clusters under the requested range of the unnamed $DATA stream
+    pub fn read_record_at(&mut self, record: u64, offset: u64, buffer: &mut [u8]) -> StorageResult<usize> {
+        let entry = self.load_record(record)?;
+        if entry.header.has_flag(MftHeader::DIRECTORY) {
+            return Err(StorageError::IsADirectory);
+        }
+        self.read_attribute_at(&entry, AttributeType::Data, "", offset, buffer)
+    }
+
+    pub fn read_dir_record(&mut self, record: u64) -> StorageResult<Vec<DirectoryEntry>> {
+        let entry = self.load_record(record)?;
+        if !entry.header.has_flag(MftHeader::DIRECTORY) {
+            return Err(StorageError::NotADirectory);
+        }
+
+        let (root, mut context) = self.index_root(&entry)?;
+        let mut index_entries = Vec::new();
+        self.list_node(root, &mut context, &mut index_entries)?;
+
+        // Skip DOS 8.3 aliases, which duplicate the Win32 name, and the root's "." link
+        let mut listing = Vec::new();
+        for index_entry in index_entries {
+            let Some(file_name) = index_entry.file_name else { continue };
+            if file_name.namespace == FileNamespace::Dos || file_name.name == "." {
+                continue;
+            }
+            let entry_record = reference_record(index_entry.reference);
+            if record == MFT_RECORD_ROOT && entry_record < FIRST_USER_RECORD {
+                continue;
+            }
+            listing.push(DirectoryEntry {
+                record: entry_record,
+                is_directory: file_name.flags.contains(FileAttributes::DIRECTORY),
+                size: file_name.real_size,
+                modification_time: file_name.modification_time,
+                name: file_name.name,
+            });
+        }
+        Ok(listing)
+    }
+
+    // Helper methods
+
+    fn record_standard_info(&mut self, record: u64) -> StorageResult<StandardInfo> {
+        let entry = self.load_record(record)?;
+        entry
+            .find(AttributeType::StandardInformation, "")
+            .and_then(|attribute| attribute.resident_value())
+            .map(StandardInfo::parse)
+            .ok_or_else(|| StorageError::Corrupted(format!("record {} has no $STANDARD_INFORMATION", record)))
+    }
+
+    fn read_record_raw(&mut self, record: u64) -> StorageResult<Vec<u8>> {
+        let record_size = self.boot_sector.mft_record_size as u64;
+        if record >= self.mft.record_count {
+            return Err(StorageError::Corrupted(format!("MFT record {} out of range", record)));
+        }
+        let mut raw = vec![0u8; record_size as usize];
+        let cluster_size = self.boot_sector.cluster_size();
+        let runs = self.mft.runs.clone();
+        read_runs(&mut self.device, &runs, cluster_size, record * record_size, &mut raw)?;
+        match apply_fixups(&mut raw, b"FILE") {
+            Ok(strides) => self.stats.fixups_applied += strides as u64,
+            // A torn copy of a mirrored record can still be read from $MFTMirr
+            Err(error) if record < MFT_MIRROR_RECORDS => {
+                raw = read_mirror(&mut self.device, &self.boot_sector, record, &mut self.stats).map_err(|_| error)?;
+            }
+            Err(error) => return Err(error),
+        }
+        self.stats.records_read += 1;
+        Ok(raw)
+    }
+
+    // Loads the record a file reference names, refusing references made
+    // before the record was freed and reused
+    fn load_reference(&mut self, reference: u64) -> StorageResult<MftEntry> {
+        let entry = self.load_record(reference_record(reference))?;
+        check_sequence(reference, entry.header.sequence_number)?;
+        Ok(entry)
+    }
+
+    // Load a base record, merging in attributes stored in extension records
+    fn load_record(&mut self, record: u64) -> StorageResult<MftEntry> {
+        if let Some(entry) = self.mft.entries.get(&record) {
+            self.stats.record_cache_hits += 1;
+            return Ok(entry.clone());
+        }
+
+        let raw = self.read_record_raw(record)?;
+        let mut entry = MftEntry::parse(&raw)?;
+        if !entry.header.has_flag(MftHeader::IN_USE) {
+            return Err(StorageError::NotFound);
+        }
+
+        if let Some(list) = entry.find(AttributeType::AttributeList, "").cloned() {
+            let data = self.attribute_bytes(&list)?;
+            let mut extensions: Vec<u64> = parse_attribute_list(&data)
+                .into_iter()
+                .filter(|&reference| reference_record(reference) != record)
+                .collect();
+            extensions.sort_unstable();
+            extensions.dedup();
+
+            // Keep only the base record's own attributes; list entries point at the rest
+            for extension in extensions {
+                let raw = self.read_record_raw(reference_record(extension))?;
+                let extension_entry = MftEntry::parse(&raw)?;
+                check_sequence(extension, extension_entry.header.sequence_number)?;
+                let base = extension_entry.header.base_reference;
+                if reference_record(base) != record {
+                    return Err(StorageError::Corrupted(format!("record {} does not extend {}", reference_record(extension), record)));
+                }
+                check_sequence(base, entry.header.sequence_number)?;
+                entry.attributes.extend(extension_entry.attributes);
+            }
+        }
+
+        self.mft.entries.insert(record, entry.clone());
+        Ok(entry)
+    }
+
+    // Combine every piece of a non-resident attribute, ordered by starting VCN
+    fn collect_runs(&self, entry: &MftEntry, kind: AttributeType, name: &str) -> StorageResult<Option<Vec<DataRun>>> {
+        let mut pieces: Vec<&NonResidentValue> = entry
+            .attributes
+            .iter()
+            .filter(|attribute| attribute.kind == kind && attribute.name == name)
+            .filter_map(|attribute| match &attribute.value {
+                AttributeValue::NonResident(value) => Some(value),
+                AttributeValue::Resident(_) => None,
+            })
+            .collect();
+        if pieces.is_empty() {
+            return Ok(None);
+        }
+        pieces.sort_by_key(|piece| piece.starting_vcn);
+        Ok(Some(pieces.iter().flat_map(|piece| piece.runs.iter().copied()).collect()))
+    }
+
+    fn read_attribute(&mut self, entry: &MftEntry, kind: AttributeType, name: &str) -> StorageResult<Vec<u8>> {
+        let attribute = entry.find(kind, name).ok_or(StorageError::NotFound)?;
+        if let AttributeValue::NonResident(_) = attribute.value {
+            let runs = self.collect_runs(entry, kind, name)?.unwrap_or_default();
+            self.check_mapped(&runs, attribute.data_size())?;
+        }
+        let mut data = vec![0u8; attribute.data_size() as usize];
+        let length = self.read_attribute_at(entry, kind, name, 0, &mut data)?;
+        data.truncate(length);
+        Ok(data)
+    }
+
+    fn read_attribute_at(&mut self, entry: &MftEntry, kind: AttributeType, name: &str, offset: u64, buffer: &mut [u8]) -> StorageResult<usize> {
+        let attribute = entry.find(kind, name).ok_or(StorageError::NotFound)?;
+        if attribute.flags & 0x0001 != 0 || attribute.flags & 0x4000 != 0 {
+            return Err(StorageError::Unsupported("compressed or encrypted stream".into()));
+        }
+
+        match &attribute.value {
+            AttributeValue::Resident(data) => Ok(copy_from(data, offset, buffer)),
+            AttributeValue::NonResident(_) => {
+                // Sizes live only in the first (VCN 0) piece of the attribute
+                let first = entry
+                    .attributes
+                    .iter()
+                    .filter(|a| a.kind == kind && a.name == name)
+                    .find_map(|a| match &a.value {
+                        AttributeValue::NonResident(value) if value.starting_vcn == 0 => Some(value.clone()),
+                        _ => None,
+                    })
+                    .ok_or_else(|| StorageError::Corrupted("non-resident attribute without VCN 0".into()))?;
+                let runs = self.collect_runs(entry, kind, name)?.unwrap_or_default();
+                self.read_non_resident(&runs, first.data_size, first.initialized_size, offset, buffer)
+            }
+        }
+    }
+
+    fn attribute_bytes(&mut self, attribute: &NtfsAttribute) -> StorageResult<Vec<u8>> {
+        match &attribute.value {
+            AttributeValue::Resident(data) => Ok(data.clone()),
+            AttributeValue::NonResident(value) => {
+                self.check_mapped(&value.runs, value.data_size)?;
+                let mut data = vec![0u8; value.data_size as usize];
+                let runs = value.runs.clone();
+                self.read_non_resident(&runs, value.data_size, value.initialized_size, 0, &mut data)?;
+                Ok(data)
+            }
+        }
+    }
+
+    // A stream's size comes off the disk; before buffering all of it, make
+    // sure its runs actually cover that many bytes
+    fn check_mapped(&self, runs: &[DataRun], data_size: u64) -> StorageResult<()> {
+        let cluster_size = self.boot_sector.cluster_size();
+        let mapped = runs.iter().fold(0u64, |total, run| total.saturating_add(run.length.saturating_mul(cluster_size)));
+        if data_size > mapped {
+            return Err(StorageError::Corrupted(format!("stream of {} bytes mapped by only {}", data_size, mapped)));
+        }
+        Ok(())
+    }
+
+    // Only the runs under [offset, offset + buffer.len()) are touched. Bytes
+    // past the initialized size were never written and read back as zeros.
+    fn read_non_resident(&mut self, runs: &[DataRun], data_size: u64, initialized_size: u64, offset: u64, buffer: &mut [u8]) -> StorageResult<usize> {
+        if offset >= data_size {
+            return Ok(0);
+        }
+        let length = ((data_size - offset) as usize).min(buffer.len());
+        let initialized = (initialized_size.min(data_size).saturating_sub(offset) as usize).min(length);
+        let cluster_size = self.boot_sector.cluster_size();
+        let clusters = read_runs(&mut self.device, runs, cluster_size, offset, &mut buffer[..initialized])?;
+        buffer[initialized..length].fill(0);
+        self.stats.clusters_read += clusters;
+        Ok(length)
+    }
+
+    fn resolve(&mut self, path: &str) -> StorageResult<u64> {
+        let mut record = MFT_RECORD_ROOT;
+        for component in path.split(['/', '\\']).filter(|part| !part.is_empty() && *part != ".") {
+            if component == ".." {
+                let entry = self.load_record(record)?;
+                let parent = entry.file_names().first().map_or(MFT_RECORD_ROOT, |name| name.parent_directory);
+                self.load_reference(parent)?;
+                record = reference_record(parent);
+                continue;
+            }
+            record = self.lookup(record, component)?.ok_or(StorageError::NotFound)?;
+        }
+        Ok(record)
+    }
+
+    // Descend the $I30 B+tree using the volume's upcase collation
+    fn lookup(&mut self, directory: u64, name: &str) -> StorageResult<Option<u64>> {
+        let entry = self.load_record(directory)?;
+        if !entry.header.has_flag(MftHeader::DIRECTORY) {
+            return Err(StorageError::NotADirectory);
+        }
+        let key: Vec<u16> = name.encode_utf16().collect();
+        let (root, mut context) = self.index_root(&entry)?;
+        let Some(reference) = self.search_node(root, &key, &mut context)? else {
+            return Ok(None);
+        };
+        self.load_reference(reference)?;
+        Ok(Some(reference_record(reference)))
+    }
+
+    fn index_root(&mut self, directory: &MftEntry) -> StorageResult<(Vec<IndexEntry>, IndexWalk)> {
+        let root = directory
+            .find(AttributeType::IndexRoot, "$I30")
+            .and_then(|attribute| attribute.resident_value())
+            .ok_or_else(|| StorageError::Corrupted("directory without $INDEX_ROOT".into()))?;
+        if root.len() < 0x20 {
+            return Err(StorageError::Corrupted("truncated $INDEX_ROOT".into()));
+        }
+        let record_size = match read_u32(root, 8) as usize {
+            0 => self.boot_sector.index_record_size,
+            size if valid_record_size(size) => size,
+            size => return Err(StorageError::Corrupted(format!("bad index record size {}", size))),
+        };
+        let entries = parse_index_node(root, 0x10)?;
+        let runs = self.collect_runs(directory, AttributeType::IndexAllocation, "$I30")?.unwrap_or_default();
+        Ok((entries, IndexWalk { runs, record_size, depth: 0 }))
+    }
+
+    // In-order traversal: a sub-node holds everything that sorts before its entry
+    fn list_node(&mut self, entries: Vec<IndexEntry>, context: &mut IndexWalk, listing: &mut Vec<IndexEntry>) -> StorageResult<()> {
+        for entry in entries {
+            if let Some(vcn) = entry.child_vcn {
+                let child = self.read_index_block(vcn, context)?;
+                context.depth += 1;
+                self.list_node(child, context, listing)?;
+                context.depth -= 1;
+            }
+            if entry.file_name.is_some() {
+                listing.push(entry);
+            }
+        }
+        Ok(())
+    }
+
+    fn search_node(&mut self, entries: Vec<IndexEntry>, key: &[u16], context: &mut IndexWalk) -> StorageResult<Option<u64>> {
+        for entry in entries {
+            let ordering = match &entry.file_name {
+                Some(file_name) => {
+                    let units: Vec<u16> = file_name.name.encode_utf16().collect();
+                    collate(key, &units, &self.upcase)
+                }
+                None => Ordering::Less,
+            };
+            match ordering {
+                Ordering::Equal => return Ok(Some(entry.reference)),
+                Ordering::Greater => continue,
+                Ordering::Less => {
+                    let Some(vcn) = entry.child_vcn else { return Ok(None) };
+                    let child = self.read_index_block(vcn, context)?;
+                    context.depth += 1;
+                    let found = self.search_node(child, key, context);
+                    context.depth -= 1;
+                    return found;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn read_index_block(&mut self, vcn: u64, context: &IndexWalk) -> StorageResult<Vec<IndexEntry>> {
+        if context.depth > 32 {
+            return Err(StorageError::Corrupted("index tree too deep".into()));
+        }
+        let cluster_size = self.boot_sector.cluster_size();
+        // Index blocks smaller than a cluster are addressed in 512-byte units
+        let offset = if context.record_size as u64 >= cluster_size { vcn * cluster_size } else { vcn * 512 };
+        let mut raw = vec![0u8; context.record_size];
+        read_runs(&mut self.device, &context.runs, cluster_size, offset, &mut raw)?;
+        self.stats.fixups_applied += apply_fixups(&mut raw, b"INDX")? as u64;
+        self.stats.index_blocks_read += 1;
+        parse_index_node(&raw, 0x18)
+    }
+
+    fn read_volume_info(&mut self) -> StorageResult<VolumeInfo> {
+        let entry = self.load_record(MFT_RECORD_VOLUME)?;
+        let name = entry
+            .find(AttributeType::VolumeName, "")
+            .and_then(|attribute| attribute.resident_value())
+            .map(utf16_to_string)
+            .unwrap_or_default();
+        let information = entry
+            .find(AttributeType::VolumeInformation, "")
+            .and_then(|attribute| attribute.resident_value())
+            .filter(|value| value.len() >= 12);
+        Ok(VolumeInfo {
+            name,
+            major_version: information.map_or(0, |value| value[8]),
+            minor_version: information.map_or(0, |value| value[9]),
+            flags: information.map_or(0, |value| read_u16(value, 10)),
+            serial_number: self.boot_sector.volume_serial,
         })
     }
 
-    pub fn create_file(&mut self, path: &str, attributes: FileAttributes) -> StorageResult<u64> {
-        // Allocate new MFT entry
-        let record_number = self.allocate_mft_record()?;
-        
-        // Create file record
-        let mut entry = MftEntry::new(record_number);
-        
-        // Add standard information attribute
-        entry.add_attribute(NtfsAttribute::StandardInfo(StandardInfo {
-            creation_time: self.get_current_time(),
-            modification_time: self.get_current_time(),
-            mft_modification_time: self.get_current_time(),
-            access_time: self.get_current_time(),
-            file_attributes: attributes,
-        }));
-        
-        // Add filename attribute
-        entry.add_attribute(NtfsAttribute::FileName(FileName {
-            parent_directory: self.get_parent_directory(path)?,
-            creation_time: self.get_current_time(),
-            modification_time: self.get_current_time(),
-            mft_modification_time: self.get_current_time(),
-            access_time: self.get_current_time(),
-            allocated_size: 0,
-            real_size: 0,
-            flags: FileFlags::empty(),
-            name: Self::format_filename(path)?,
-        }));
-        
-        // Write MFT entry
-        self.write_mft_entry(record_number, &entry)?;
-        
-        Ok(record_number)
-    }
-
-    // Helper methods...
+    fn read_upcase(&mut self) -> StorageResult<Vec<u16>> {
+        let entry = self.load_record(MFT_RECORD_UPCASE)?;
+        let data = self.read_attribute(&entry, AttributeType::Data, "")?;
+        Ok(data.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect())
+    }
+
+    fn read_bitmap(&mut self) -> StorageResult<Bitmap> {
+        let entry = self.load_record(MFT_RECORD_BITMAP)?;
+        let bits = self.read_attribute(&entry, AttributeType::Data, "")?;
+        Ok(Bitmap {
+            bits,
+            cluster_count: self.boot_sector.total_sectors / self.boot_sector.sectors_per_cluster as u64,
+        })
+    }
+}
+
+struct IndexWalk {
+    runs: Vec<DataRun>,
+    record_size: usize,
+    depth: u32,
+}
+
+impl Bitmap {
+    pub fn cluster_count(&self) -> u64 {
+        self.cluster_count
+    }
+
+    pub fn is_allocated(&self, cluster: u64) -> bool {
+        self.bits
+            .get((cluster / 8) as usize)
+            .is_some_and(|byte| byte & (1 << (cluster % 8)) != 0)
+    }
+
+    pub fn free_clusters(&self) -> u64 {
+        (0..self.cluster_count).filter(|&cluster| !self.is_allocated(cluster)).count() as u64
+    }
+}
+
+impl BootSector {
+    fn parse(sector: &[u8]) -> StorageResult<Self> {
+        if &sector[3..11] != b"NTFS    " || sector[510] != 0x55 || sector[511] != 0xAA {
+            return Err(StorageError::InvalidFileSystem("not an NTFS boot sector".into()));
+        }
+
+        let bytes_per_sector = read_u16(sector, 0x0B);
+        if !bytes_per_sector.is_power_of_two() || bytes_per_sector < 256 {
+            return Err(StorageError::InvalidFileSystem("bad NTFS geometry".into()));
+        }
+        // Values above 0x80 encode cluster sizes larger than 64 KiB as a negative shift
+        let sectors_per_cluster = match sector[0x0D] {
+            raw if raw > 0x80 => 1u32.checked_shl(256 - raw as u32).unwrap_or(0),
+            raw => raw as u32,
+        };
+        let cluster_size = bytes_per_sector as usize * sectors_per_cluster as usize;
+        if sectors_per_cluster == 0 || cluster_size > MAX_CLUSTER_SIZE {
+            return Err(StorageError::InvalidFileSystem("bad NTFS geometry".into()));
+        }
+
+        // Positive counts are clusters, negative ones are log2 of a byte size
+        let record_size = |raw: u8| {
+            let size = match raw as i8 {
+                count if count > 0 => count as usize * cluster_size,
+                shift => 1usize.checked_shl(-(shift as i32) as u32).unwrap_or(0),
+            };
+            if !valid_record_size(size) {
+                return Err(StorageError::InvalidFileSystem(format!("bad NTFS record size {:#x}", raw)));
+            }
+            Ok(size)
+        };
+
+        Ok(Self {
+            bytes_per_sector,
+            sectors_per_cluster,
+            total_sectors: read_u64(sector, 0x28),
+            mft_cluster: read_u64(sector, 0x30),
+            mft_mirror_cluster: read_u64(sector, 0x38),
+            mft_record_size: record_size(sector[0x40])?,
+            index_record_size: record_size(sector[0x44])?,
+            volume_serial: read_u64(sector, 0x48),
+        })
+    }
+
+    fn cluster_size(&self) -> u64 {
+        self.bytes_per_sector as u64 * self.sectors_per_cluster as u64
+    }
+
+    fn mirror_offset(&self, record: u64) -> StorageResult<u64> {
+        self.mft_mirror_cluster
+            .checked_mul(self.cluster_size())
+            .and_then(|offset| offset.checked_add(record * self.mft_record_size as u64))
+            .ok_or_else(|| StorageError::InvalidFileSystem("$MFTMirr cluster out of range".into()))
+    }
+}
+
+impl MftHeader {
+    const IN_USE: u16 = 0x0001;
+    const DIRECTORY: u16 = 0x0002;
+
+    fn has_flag(&self, flag: u16) -> bool {
+        self.flags & flag != 0
+    }
+}
+
+impl MftEntry {
+    fn parse(raw: &[u8]) -> StorageResult<Self> {
+        let header = MftHeader {
+            sequence_number: read_u16(raw, 0x10),
+            link_count: read_u16(raw, 0x12),
+            first_attribute_offset: read_u16(raw, 0x14),
+            flags: read_u16(raw, 0x16),
+            used_size: read_u32(raw, 0x18),
+            allocated_size: read_u32(raw, 0x1C),
+            base_reference: read_u64(raw, 0x20),
+        };
+
+        if header.used_size > header.allocated_size || header.allocated_size as usize > raw.len() {
+            return Err(StorageError::Corrupted("record uses more than it allocates".into()));
+        }
+        let end = header.used_size as usize;
+        let mut attributes = Vec::new();
+        let mut offset = header.first_attribute_offset as usize;
+        while offset + 8 <= end {
+            let type_code = read_u32(raw, offset);
+            if type_code == 0xFFFF_FFFF {
+                break;
+            }
+            let length = read_u32(raw, offset + 4) as usize;
+            if length < 0x18 || offset + length > end {
+                return Err(StorageError::Corrupted("attribute overruns its record".into()));
+            }
+            attributes.push(NtfsAttribute::parse(&raw[offset..offset + length])?);
+            offset += length;
+        }
+        Ok(Self { header, attributes })
+    }
+
+    fn find(&self, kind: AttributeType, name: &str) -> Option<&NtfsAttribute> {
+        // Prefer the piece that starts at VCN 0 when an attribute is split
+        self.attributes
+            .iter()
+            .filter(|attribute| attribute.kind == kind && attribute.name == name)
+            .min_by_key(|attribute| match &attribute.value {
+                AttributeValue::NonResident(value) => value.starting_vcn,
+                AttributeValue::Resident(_) => 0,
+            })
+    }
+
+    fn file_names(&self) -> Vec<FileName> {
+        self.attributes
+            .iter()
+            .filter(|attribute| attribute.kind == AttributeType::FileName)
+            .filter_map(|attribute| attribute.resident_value())
+            .filter_map(FileName::parse)
+            .collect()
+    }
+}
+
+impl AttributeType {
+    fn from_code(code: u32) -> Self {
+        match code {
+            0x10 => AttributeType::StandardInformation,
+            0x20 => AttributeType::AttributeList,
+            0x30 => AttributeType::FileName,
+            0x60 => AttributeType::VolumeName,
+            0x70 => AttributeType::VolumeInformation,
+            0x80 => AttributeType::Data,
+            0x90 => AttributeType::IndexRoot,
+            0xA0 => AttributeType::IndexAllocation,
+            0xB0 => AttributeType::Bitmap,
+            other => AttributeType::Other(other),
+        }
+    }
+}
+
+impl NtfsAttribute {
+    fn parse(raw: &[u8]) -> StorageResult<Self> {
+        let non_resident = raw[8] != 0;
+        let name_length = raw[9] as usize;
+        let name_offset = read_u16(raw, 0x0A) as usize;
+        let name = if name_length > 0 && name_offset + name_length * 2 <= raw.len() {
+            utf16_to_string(&raw[name_offset..name_offset + name_length * 2])
+        } else {
+            String::new()
+        };
+
+        let value = if non_resident {
+            if raw.len() < 0x40 {
+                return Err(StorageError::Corrupted("truncated non-resident attribute".into()));
+            }
+            let starting_vcn = read_u64(raw, 0x10);
+            let runs_offset = read_u16(raw, 0x20) as usize;
+            AttributeValue::NonResident(NonResidentValue {
+                starting_vcn,
+                runs: decode_data_runs(raw.get(runs_offset..).unwrap_or(&[]), starting_vcn)?,
+                data_size: read_u64(raw, 0x30),
+                initialized_size: read_u64(raw, 0x38),
+            })
+        } else {
+            let length = read_u32(raw, 0x10) as usize;
+            let offset = read_u16(raw, 0x14) as usize;
+            let value = raw
+                .get(offset..offset + length)
+                .ok_or_else(|| StorageError::Corrupted("resident value overruns attribute".into()))?;
+            AttributeValue::Resident(value.to_vec())
+        };
+
+        Ok(Self {
+            kind: AttributeType::from_code(read_u32(raw, 0)),
+            name,
+            flags: read_u16(raw, 0x0C),
+            value,
+        })
+    }
+
+    fn resident_value(&self) -> Option<&[u8]> {
+        match &self.value {
+            AttributeValue::Resident(data) => Some(data),
+            AttributeValue::NonResident(_) => None,
+        }
+    }
+
+    fn data_size(&self) -> u64 {
+        match &self.value {
+            AttributeValue::Resident(data) => data.len() as u64,
+            AttributeValue::NonResident(value) => value.data_size,
+        }
+    }
+}
+
+impl StandardInfo {
+    fn parse(value: &[u8]) -> Self {
+        Self {
+            creation_time: read_u64(value, 0x00),
+            modification_time: read_u64(value, 0x08),
+            mft_modification_time: read_u64(value, 0x10),
+            access_time: read_u64(value, 0x18),
+            file_attributes: FileAttributes::from_bits_truncate(read_u32(value, 0x20)),
+        }
+    }
+}
+
+impl FileName {
+    fn parse(value: &[u8]) -> Option<Self> {
+        if value.len() < 0x42 {
+            return None;
+        }
+        let length = value[0x40] as usize;
+        let name = value.get(0x42..0x42 + length * 2)?;
+        Some(Self {
+            parent_directory: read_u64(value, 0x00),
+            creation_time: read_u64(value, 0x08),
+            modification_time: read_u64(value, 0x10),
+            mft_modification_time: read_u64(value, 0x18),
+            access_time: read_u64(value, 0x20),
+            allocated_size: read_u64(value, 0x28),
+            real_size: read_u64(value, 0x30),
+            flags: FileAttributes::from_bits_truncate(read_u32(value, 0x38)),
+            namespace: match value[0x41] {
+                0 => FileNamespace::Posix,
+                1 => FileNamespace::Win32,
+                2 => FileNamespace::Dos,
+                _ => FileNamespace::Win32AndDos,
+            },
+            name: utf16_to_string(name),
+        })
+    }
+}
+
+// Records are whole fixup strides, so every stride has a sequence number to check
+fn valid_record_size(size: usize) -> bool {
+    (FIXUP_STRIDE..=MAX_RECORD_SIZE).contains(&size) && size.is_multiple_of(FIXUP_STRIDE)
+}
+
+// Copy what's left of `data` from `offset` on, returning how much fit
+fn copy_from(data: &[u8], offset: u64, buffer: &mut [u8]) -> usize {
+    let start = (offset as usize).min(data.len());
+    let length = (data.len() - start).min(buffer.len());
+    buffer[..length].copy_from_slice(&data[start..start + length]);
+    length
+}
+
+// File references pack a 48-bit record number with the sequence number the
+// record had when the reference was made
+fn reference_record(reference: u64) -> u64 {
+    reference & 0x0000_FFFF_FFFF_FFFF
+}
+
+// Freeing a record bumps its sequence number, so a mismatch means the
+// reference outlived what it pointed at; zero leaves it unchecked
+fn check_sequence(reference: u64, sequence: u16) -> StorageResult<()> {
+    let expected = (reference >> 48) as u16;
+    if expected != 0 && expected != sequence {
+        return Err(StorageError::Corrupted(format!("stale reference to record {}", reference_record(reference))));
+    }
+    Ok(())
+}
+
+fn read_mirror<D: BlockDevice>(device: &mut D, boot_sector: &BootSector, record: u64, stats: &mut NtfsStats) -> StorageResult<Vec<u8>> {
+    let mut raw = vec![0u8; boot_sector.mft_record_size];
+    device.read_bytes(boot_sector.mirror_offset(record)?, &mut raw)?;
+    stats.fixups_applied += apply_fixups(&mut raw, b"FILE")? as u64;
+    stats.mirror_reads += 1;
+    Ok(raw)
+}
+
+// Check and undo the update sequence array; returns how many strides were fixed up
+fn apply_fixups(record: &mut [u8], magic: &[u8; 4]) -> StorageResult<usize> {
+    if &record[0..4] != magic {
+        return Err(StorageError::Corrupted(format!("bad {} record magic", String::from_utf8_lossy(magic))));
+    }
+    let usa_offset = read_u16(record, 4) as usize;
+    let usa_count = read_u16(record, 6) as usize;
+    if usa_count == 0 || usa_offset + usa_count * 2 > record.len() || (usa_count - 1) * FIXUP_STRIDE > record.len() {
+        return Err(StorageError::Corrupted("bad update sequence array".into()));
+    }
+
+    let sequence = [record[usa_offset], record[usa_offset + 1]];
+    for stride in 1..usa_count {
+        let end = stride * FIXUP_STRIDE;
+        // A mismatch means the record was torn by an interrupted multi-sector write
+        if record[end - 2..end] != sequence {
+            return Err(StorageError::Corrupted("torn record: update sequence mismatch".into()));
+        }
+        let saved = usa_offset + stride * 2;
+        record[end - 2] = record[saved];
+        record[end - 1] = record[saved + 1];
+    }
+    Ok(usa_count - 1)
+}
+
+// Each run header packs the byte widths of its length (low nibble) and signed LCN delta (high nibble)
+pub fn decode_data_runs(bytes: &[u8], starting_vcn: u64) -> StorageResult<Vec<DataRun>> {
+    let mut runs = Vec::new();
+    let mut offset = 0;
+    let mut vcn = starting_vcn;
+    let mut lcn: i64 = 0;
+
+    while let Some(&header) = bytes.get(offset) {
+        if header == 0 {
+            break;
+        }
+        let length_size = (header & 0x0F) as usize;
+        let offset_size = (header >> 4) as usize;
+        if length_size == 0 || length_size > 8 || offset_size > 8 || offset + 1 + length_size + offset_size > bytes.len() {
+            return Err(StorageError::Corrupted("malformed data run".into()));
+        }
+
+        let field = &bytes[offset + 1..];
+        let length = (0..length_size).fold(0u64, |value, i| value | (field[i] as u64) << (8 * i));
+        let run_lcn = if offset_size == 0 {
+            None
+        } else {
+            let mut delta = (0..offset_size).fold(0i64, |value, i| value | (field[length_size + i] as i64) << (8 * i));
+            // Sign-extend the delta from its stored width
+            let shift = 64 - 8 * offset_size as u32;
+            delta = (delta << shift) >> shift;
+            lcn = lcn.checked_add(delta).ok_or_else(|| StorageError::Corrupted("data run LCN overflows".into()))?;
+            if lcn < 0 {
+                return Err(StorageError::Corrupted("data run before start of volume".into()));
+            }
+            Some(lcn as u64)
+        };
+
+        runs.push(DataRun { vcn, lcn: run_lcn, length });
+        vcn = vcn.checked_add(length).ok_or_else(|| StorageError::Corrupted("data run VCN overflows".into()))?;
+        offset += 1 + length_size + offset_size;
+    }
+    Ok(runs)
+}
+
+// Copy bytes at `offset` within a run-mapped stream into `buffer`, returning clusters touched
+fn read_runs<D: BlockDevice>(device: &mut D, runs: &[DataRun], cluster_size: u64, offset: u64, buffer: &mut [u8]) -> StorageResult<u64> {
+    let mut done = 0usize;
+    let mut clusters = 0;
+    while done < buffer.len() {
+        let position = offset + done as u64;
+        let vcn = position / cluster_size;
+        let run = runs
+            .iter()
+            .find(|run| vcn >= run.vcn && vcn < run.vcn + run.length)
+            .ok_or_else(|| StorageError::Corrupted(format!("VCN {} not mapped by any data run", vcn)))?;
+
+        let run_end = (run.vcn + run.length) * cluster_size;
+        let length = ((run_end - position) as usize).min(buffer.len() - done);
+        let piece = &mut buffer[done..done + length];
+        match run.lcn {
+            Some(lcn) => {
+                let device_offset = lcn
+                    .checked_add(vcn - run.vcn)
+                    .and_then(|cluster| cluster.checked_mul(cluster_size))
+                    .ok_or_else(|| StorageError::Corrupted(format!("LCN {} beyond any device", lcn)))?
+                    + position % cluster_size;
+                device.read_bytes(device_offset, piece)?;
+                clusters += length.div_ceil(cluster_size as usize) as u64;
+            }
+            None => piece.fill(0),
+        }
+        done += length;
+    }
+    Ok(clusters)
+}
+
+fn parse_index_node(raw: &[u8], node_offset: usize) -> StorageResult<Vec<IndexEntry>> {
+    if node_offset + 8 > raw.len() {
+        return Err(StorageError::Corrupted("truncated index node header".into()));
+    }
+    let entries_offset = node_offset + read_u32(raw, node_offset) as usize;
+    let entries_end = (node_offset + read_u32(raw, node_offset + 4) as usize).min(raw.len());
+
+    let mut entries = Vec::new();
+    let mut offset = entries_offset;
+    while offset + 0x10 <= entries_end {
+        let length = read_u16(raw, offset + 8) as usize;
+        let key_length = read_u16(raw, offset + 10) as usize;
+        let flags = read_u32(raw, offset + 12);
+        let has_child = flags & 0x1 != 0;
+        let last = flags & 0x2 != 0;
+        // The key, and the child VCN in an entry's last 8 bytes, must fit inside it
+        let key_end = 0x10 + if last { 0 } else { key_length };
+        let needed = if has_child { key_end + 8 } else { key_end };
+        if length < 0x10 || offset + length > entries_end || needed > length {
+            return Err(StorageError::Corrupted("index entry overruns node".into()));
+        }
+
+        entries.push(IndexEntry {
+            reference: read_u64(raw, offset),
+            file_name: if last { None } else { FileName::parse(&raw[offset + 0x10..offset + key_end]) },
+            child_vcn: if has_child { Some(read_u64(raw, offset + length - 8)) } else { None },
+        });
+        if last {
+            break;
+        }
+        offset += length;
+    }
+    Ok(entries)
+}
+
+fn parse_attribute_list(data: &[u8]) -> Vec<u64> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset + 0x1A <= data.len() {
+        let length = read_u16(data, offset + 4) as usize;
+        if length == 0 {
+            break;
+        }
+        records.push(read_u64(data, offset + 0x10));
+        offset += length;
+    }
+    records
+}
+
+// NTFS file-name collation: compare code units after mapping through $UpCase
+fn collate(a: &[u16], b: &[u16], upcase: &[u16]) -> Ordering {
+    let up = |unit: u16| upcase.get(unit as usize).copied().unwrap_or(unit);
+    for (&x, &y) in a.iter().zip(b) {
+        match up(x).cmp(&up(y)) {
+            Ordering::Equal => continue,
+            other => return other,
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+fn utf16_to_string(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut word = [0u8; 8];
+    word.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::device::MemoryBlockDevice;
+    use super::*;
+
+    const CLUSTER: u64 = 4096;
+
+    // A small synthetic volume: 4 KiB clusters, 1 KiB MFT records, 4 KiB
+    // index records. The root directory's $I30 tree has one entry in
+    // $INDEX_ROOT and two INDX leaves, so lookups have to descend.
+    const VOLUME_CLUSTERS: u64 = 64;
+    const MFT_LCN: u64 = 4;
+    const MFT_RECORDS: u64 = 32;
+    const RECORD: usize = 1024;
+    const INDEX_LCN: u64 = 20;
+    const BIG_LCN: u64 = 30;
+    const BIG_SIZE: usize = 6000;
+    const README_SIZE: usize = 600;
+
+    fn put(buffer: &mut [u8], offset: usize, bytes: &[u8]) {
+        buffer[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn utf16(name: &str) -> Vec<u8> {
+        name.encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect()
+    }
+
+    fn align8(length: usize) -> usize {
+        length.div_ceil(8) * 8
+    }
+
+    // Save the last two bytes of every 512-byte stride into the update
+    // sequence array and stamp the sequence number over them, as a write does
+    fn protect(record: &mut [u8], usa_offset: usize, sequence: u16) {
+        let strides = record.len() / FIXUP_STRIDE;
+        put(record, 4, &(usa_offset as u16).to_le_bytes());
+        put(record, 6, &(strides as u16 + 1).to_le_bytes());
+        put(record, usa_offset, &sequence.to_le_bytes());
+        for stride in 1..=strides {
+            let end = stride * FIXUP_STRIDE;
+            let saved = [record[end - 2], record[end - 1]];
+            put(record, usa_offset + stride * 2, &saved);
+            put(record, end - 2, &sequence.to_le_bytes());
+        }
+    }
+
+    fn resident(type_code: u32, name: &str, value: &[u8]) -> Vec<u8> {
+        let name = utf16(name);
+        let value_offset = align8(0x18 + name.len());
+        let mut attribute = vec![0u8; align8(value_offset + value.len())];
+        let length = attribute.len() as u32;
+        put(&mut attribute, 0, &type_code.to_le_bytes());
+        put(&mut attribute, 4, &length.to_le_bytes());
+        attribute[9] = (name.len() / 2) as u8;
+        put(&mut attribute, 0x0A, &0x18u16.to_le_bytes());
+        put(&mut attribute, 0x10, &(value.len() as u32).to_le_bytes());
+        put(&mut attribute, 0x14, &(value_offset as u16).to_le_bytes());
+        put(&mut attribute, 0x18, &name);
+        put(&mut attribute, value_offset, value);
+        attribute
+    }
+
+    // One run of `clusters` clusters at `lcn`
+    fn non_resident(type_code: u32, name: &str, lcn: u64, clusters: u64, size: u64) -> Vec<u8> {
+        let name = utf16(name);
+        let runs_offset = align8(0x40 + name.len());
+        let runs = [0x11, clusters as u8, lcn as u8, 0];
+        let mut attribute = vec![0u8; align8(runs_offset + runs.len())];
+        let length = attribute.len() as u32;
+        put(&mut attribute, 0, &type_code.to_le_bytes());
+        put(&mut attribute, 4, &length.to_le_bytes());
+        attribute[8] = 1;
+        attribute[9] = (name.len() / 2) as u8;
+        put(&mut attribute, 0x0A, &0x40u16.to_le_bytes());
+        put(&mut attribute, 0x18, &(clusters - 1).to_le_bytes());
+        put(&mut attribute, 0x20, &(runs_offset as u16).to_le_bytes());
+        put(&mut attribute, 0x28, &(clusters * CLUSTER).to_le_bytes());
+        put(&mut attribute, 0x30, &size.to_le_bytes());
+        put(&mut attribute, 0x38, &size.to_le_bytes());
+        put(&mut attribute, 0x40, &name);
+        put(&mut attribute, runs_offset, &runs);
+        attribute
+    }
+
+    fn standard_information(attributes: FileAttributes) -> Vec<u8> {
+        let mut value = vec![0u8; 0x48];
+        put(&mut value, 0x08, &1000u64.to_le_bytes());
+        put(&mut value, 0x20, &attributes.bits().to_le_bytes());
+        value
+    }
+
+    fn file_name(parent: u64, name: &str, flags: FileAttributes, size: u64) -> Vec<u8> {
+        let units = utf16(name);
+        let mut value = vec![0u8; 0x42 + units.len()];
+        put(&mut value, 0, &parent.to_le_bytes());
+        put(&mut value, 0x30, &size.to_le_bytes());
+        put(&mut value, 0x38, &flags.bits().to_le_bytes());
+        value[0x40] = (units.len() / 2) as u8;
+        value[0x41] = 1; // Win32
+        put(&mut value, 0x42, &units);
+        value
+    }
+
+    fn mft_record(flags: u16, attributes: &[Vec<u8>]) -> Vec<u8> {
+        let mut record = vec![0u8; RECORD];
+        put(&mut record, 0, b"FILE");
+        put(&mut record, 0x12, &1u16.to_le_bytes());
+        put(&mut record, 0x14, &0x38u16.to_le_bytes());
+        put(&mut record, 0x16, &flags.to_le_bytes());
+        let mut offset = 0x38;
+        for attribute in attributes {
+            put(&mut record, offset, attribute);
+            offset += attribute.len();
+        }
+        put(&mut record, offset, &0xFFFF_FFFFu32.to_le_bytes());
+        put(&mut record, 0x18, &(offset as u32 + 8).to_le_bytes());
+        put(&mut record, 0x1C, &(RECORD as u32).to_le_bytes());
+        protect(&mut record, 0x30, 7);
+        record
+    }
+
+    fn index_entry(record: u64, name: Option<&str>, child: Option<u64>) -> Vec<u8> {
+        let key = name.map_or_else(Vec::new, |name| file_name(MFT_RECORD_ROOT, name, FileAttributes::empty(), 0));
+        let mut length = align8(0x10 + key.len());
+        if child.is_some() {
+            length += 8;
+        }
+        let mut entry = vec![0u8; length];
+        put(&mut entry, 0, &record.to_le_bytes());
+        put(&mut entry, 8, &(length as u16).to_le_bytes());
+        put(&mut entry, 10, &(key.len() as u16).to_le_bytes());
+        let flags = child.map_or(0, |_| 0x1) | if name.is_none() { 0x2 } else { 0 };
+        put(&mut entry, 12, &(flags as u32).to_le_bytes());
+        put(&mut entry, 0x10, &key);
+        if let Some(vcn) = child {
+            put(&mut entry, length - 8, &vcn.to_le_bytes());
+        }
+        entry
+    }
+
+    // Node header followed by its entries, the last of which ends the node
+    fn index_node(entries: &[Vec<u8>], header_size: usize) -> Vec<u8> {
+        let mut node = vec![0u8; header_size];
+        for entry in entries {
+            node.extend_from_slice(entry);
+        }
+        let length = node.len() as u32;
+        put(&mut node, 0, &(header_size as u32).to_le_bytes());
+        put(&mut node, 4, &length.to_le_bytes());
+        put(&mut node, 8, &length.to_le_bytes());
+        node
+    }
+
+    fn indx_block(vcn: u64, entries: &[(u64, &str)]) -> Vec<u8> {
+        let mut entries: Vec<Vec<u8>> = entries.iter().map(|&(record, name)| index_entry(record, Some(name), None)).collect();
+        entries.push(index_entry(0, None, None));
+        let node = index_node(&entries, 0x28);
+        let mut block = vec![0u8; CLUSTER as usize];
+        put(&mut block, 0, b"INDX");
+        put(&mut block, 0x10, &vcn.to_le_bytes());
+        put(&mut block, 0x18, &node);
+        protect(&mut block, 0x28, 3);
+        block
+    }
+
+    fn file(name: &str, data: Vec<u8>) -> Vec<u8> {
+        let size = data.len() as u64;
+        mft_record(
+            MftHeader::IN_USE,
+            &[
+                resident(0x10, "", &standard_information(FileAttributes::ARCHIVE)),
+                resident(0x30, "", &file_name(MFT_RECORD_ROOT, name, FileAttributes::ARCHIVE, size)),
+                resident(0x80, "", &data),
+            ],
+        )
+    }
+
+    fn readme() -> Vec<u8> {
+        (0..README_SIZE).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn synthetic_volume() -> MemoryBlockDevice {
+        let mut device = MemoryBlockDevice::new(VOLUME_CLUSTERS * CLUSTER / 512, 512);
+
+        let mut boot = [0u8; 512];
+        put(&mut boot, 3, b"NTFS    ");
+        put(&mut boot, 0x0B, &512u16.to_le_bytes());
+        boot[0x0D] = 8;
+        put(&mut boot, 0x28, &(VOLUME_CLUSTERS * 8).to_le_bytes());
+        put(&mut boot, 0x30, &MFT_LCN.to_le_bytes());
+        boot[0x40] = 0xF6; // 2^10 bytes per MFT record
+        boot[0x44] = 0xF4; // 2^12 bytes per index record
+        put(&mut boot, 0x48, &0x0123_4567_89AB_CDEFu64.to_le_bytes());
+        boot[510] = 0x55;
+        boot[511] = 0xAA;
+        device.write_bytes(0, &boot).unwrap();
+
+        // Clusters 0-11 hold the boot area and $MFT, 20-21 the INDX blocks, 30-31 "m-file"
+        let mut bitmap = vec![0u8; (VOLUME_CLUSTERS / 8) as usize];
+        for cluster in (0..MFT_LCN + 8).chain(INDEX_LCN..INDEX_LCN + 2).chain(BIG_LCN..BIG_LCN + 2) {
+            bitmap[(cluster / 8) as usize] |= 1 << (cluster % 8);
+        }
+        let upcase: Vec<u8> = (0u16..256).map(|unit| (unit as u8).to_ascii_uppercase() as u16).flat_map(|unit| unit.to_le_bytes()).collect();
+        let mut volume_information = vec![0u8; 12];
+        volume_information[8] = 3;
+        volume_information[9] = 1;
+
+        let mft_size = MFT_RECORDS * RECORD as u64;
+        let directory = FileAttributes::DIRECTORY;
+        let root_entries = [index_entry(17, Some("m-file"), Some(0)), index_entry(0, None, Some(1))];
+        let mut index_root = vec![0u8; 0x10];
+        put(&mut index_root, 0, &0x30u32.to_le_bytes());
+        put(&mut index_root, 4, &1u32.to_le_bytes());
+        put(&mut index_root, 8, &(CLUSTER as u32).to_le_bytes());
+        index_root[0x0C] = 1;
+        index_root.extend(index_node(&root_entries, 0x10));
+        index_root[0x10 + 12] = 1; // has sub-nodes
+
+        let records = [
+            (0, mft_record(MftHeader::IN_USE, &[non_resident(0x80, "", MFT_LCN, 8, mft_size)])),
+            (
+                MFT_RECORD_VOLUME,
+                mft_record(MftHeader::IN_USE, &[resident(0x60, "", &utf16("TEST")), resident(0x70, "", &volume_information)]),
+            ),
+            (
+                MFT_RECORD_ROOT,
+                mft_record(
+                    MftHeader::IN_USE | MftHeader::DIRECTORY,
+                    &[
+                        resident(0x10, "", &standard_information(directory)),
+                        resident(0x30, "", &file_name(MFT_RECORD_ROOT, ".", directory, 0)),
+                        resident(0x90, "$I30", &index_root),
+                        non_resident(0xA0, "$I30", INDEX_LCN, 2, 2 * CLUSTER),
+                    ],
+                ),
+            ),
+            (MFT_RECORD_BITMAP, mft_record(MftHeader::IN_USE, &[resident(0x80, "", &bitmap)])),
+            (MFT_RECORD_UPCASE, mft_record(MftHeader::IN_USE, &[resident(0x80, "", &upcase)])),
+            (16, file("alpha", b"first".to_vec())),
+            (
+                17,
+                mft_record(
+                    MftHeader::IN_USE,
+                    &[
+                        resident(0x10, "", &standard_information(FileAttributes::ARCHIVE)),
+                        resident(0x30, "", &file_name(MFT_RECORD_ROOT, "m-file", FileAttributes::ARCHIVE, BIG_SIZE as u64)),
+                        non_resident(0x80, "", BIG_LCN, 2, BIG_SIZE as u64),
+                    ],
+                ),
+            ),
+            (18, file("Beta", b"second".to_vec())),
+            (19, file("readme.txt", readme())),
+            (20, file("zeta", b"last".to_vec())),
+        ];
+        for (number, record) in records {
+            device.write_bytes(MFT_LCN * CLUSTER + number * RECORD as u64, &record).unwrap();
+        }
+
+        // Names sort through $UpCase, so "Beta" falls between "alpha" and "m-file"
+        device.write_bytes(INDEX_LCN * CLUSTER, &indx_block(0, &[(16, "alpha"), (18, "Beta")])).unwrap();
+        device.write_bytes((INDEX_LCN + 1) * CLUSTER, &indx_block(1, &[(19, "readme.txt"), (20, "zeta")])).unwrap();
+        let big: Vec<u8> = (0..BIG_SIZE).map(|i| (i % 7) as u8).collect();
+        device.write_bytes(BIG_LCN * CLUSTER, &big).unwrap();
+        device
+    }
+
+    #[test]
+    fn data_runs_decode_negative_deltas_and_sparse_runs() {
+        // 0x10 clusters at LCN 0x100, 4 sparse, then 8 at 0x100 - 0x20
+        let bytes = [0x21, 0x10, 0x00, 0x01, 0x01, 0x04, 0x11, 0x08, 0xE0, 0x00];
+        let runs = decode_data_runs(&bytes, 0).unwrap();
+        assert_eq!(
+            runs,
+            vec![
+                DataRun { vcn: 0, lcn: Some(0x100), length: 0x10 },
+                DataRun { vcn: 0x10, lcn: None, length: 4 },
+                DataRun { vcn: 0x14, lcn: Some(0xE0), length: 8 },
+            ]
+        );
+    }
+
+    #[test]
+    fn run_reads_touch_only_the_requested_clusters() {
+        let mut device = MemoryBlockDevice::new(64 * CLUSTER / 512, 512);
+        for cluster in 0..64u64 {
+            device.write_bytes(cluster * CLUSTER, &vec![cluster as u8; CLUSTER as usize]).unwrap();
+        }
+        let runs = [
+            DataRun { vcn: 0, lcn: Some(40), length: 2 },
+            DataRun { vcn: 2, lcn: None, length: 2 },
+            DataRun { vcn: 4, lcn: Some(10), length: 4 },
+        ];
+        let before = device.get_stats().sectors_read;
+
+        // Straddles the end of the sparse run and the start of the last one
+        let mut buffer = vec![0xFFu8; 2 * CLUSTER as usize];
+        let clusters = read_runs(&mut device, &runs, CLUSTER, 3 * CLUSTER + CLUSTER / 2, &mut buffer).unwrap();
+        assert_eq!(clusters, 2);
+        assert!(buffer[..CLUSTER as usize / 2].iter().all(|&byte| byte == 0));
+        assert!(buffer[CLUSTER as usize / 2..3 * CLUSTER as usize / 2].iter().all(|&byte| byte == 10));
+        assert!(buffer[3 * CLUSTER as usize / 2..].iter().all(|&byte| byte == 11));
+        assert_eq!(device.get_stats().sectors_read - before, 3 * CLUSTER / 2 / 512);
+
+        let mut beyond = [0u8; 16];
+        assert!(read_runs(&mut device, &runs, CLUSTER, 8 * CLUSTER, &mut beyond).is_err());
+    }
+
+    #[test]
+    fn update_sequence_fixups_restore_stride_tails() {
+        let mut record = vec![0u8; RECORD];
+        put(&mut record, 0, b"FILE");
+        for (i, byte) in record.iter_mut().enumerate().skip(0x40) {
+            *byte = i as u8;
+        }
+        let original = record.clone();
+        protect(&mut record, 0x30, 0x1234);
+        assert_eq!(&record[510..512], &0x1234u16.to_le_bytes());
+
+        let mut fixed = record.clone();
+        assert_eq!(apply_fixups(&mut fixed, b"FILE").unwrap(), 2);
+        assert_eq!(fixed[0x40..], original[0x40..]);
+
+        // A stride that kept its old tail was never rewritten
+        let mut torn = record.clone();
+        put(&mut torn, 1022, &[0xAA, 0xBB]);
+        assert!(matches!(apply_fixups(&mut torn, b"FILE"), Err(StorageError::Corrupted(_))));
+        assert!(apply_fixups(&mut record, b"INDX").is_err());
+
+        // Readme's data crosses its record's first stride tail
+        let mut fs = NtfsFileSystem::mount(synthetic_volume()).unwrap();
+        assert_eq!(fs.read_file("/readme.txt").unwrap(), readme());
+        assert!(fs.get_stats().fixups_applied > 0);
+    }
+
+    #[test]
+    fn i30_lookups_descend_into_index_blocks() {
+        let mut fs = NtfsFileSystem::mount(synthetic_volume()).unwrap();
+        assert_eq!(fs.volume_info().name, "TEST");
+        assert_eq!(fs.volume_info().serial_number, 0x0123_4567_89AB_CDEF);
+        let root = fs.root_record();
+
+        let before = fs.get_stats().index_blocks_read;
+        assert_eq!(fs.lookup_record(root, "m-file").unwrap(), Some(17));
+        assert_eq!(fs.get_stats().index_blocks_read, before);
+
+        assert_eq!(fs.lookup_record(root, "alpha").unwrap(), Some(16));
+        assert_eq!(fs.lookup_record(root, "README.TXT").unwrap(), Some(19));
+        assert_eq!(fs.lookup_record(root, "beta").unwrap(), Some(18));
+        assert_eq!(fs.lookup_record(root, "nothing").unwrap(), None);
+        assert_eq!(fs.get_stats().index_blocks_read - before, 4);
+
+        let names: Vec<String> = fs.read_dir("/").unwrap().into_iter().map(|entry| entry.name).collect();
+        assert_eq!(names, ["alpha", "Beta", "m-file", "readme.txt", "zeta"]);
+        assert_eq!(fs.read_file("/zeta").unwrap(), b"last");
+        let big = fs.read_file("/m-file").unwrap();
+        assert_eq!(big.len(), BIG_SIZE);
+        assert!(big.iter().enumerate().all(|(i, &byte)| byte == (i % 7) as u8));
+    }
+
+    #[test]
+    fn bitmap_reports_allocated_clusters() {
+        let fs = NtfsFileSystem::mount(synthetic_volume()).unwrap();
+        let bitmap = fs.bitmap();
+        assert_eq!(bitmap.cluster_count(), VOLUME_CLUSTERS);
+        assert!(bitmap.is_allocated(0));
+        assert!(bitmap.is_allocated(MFT_LCN + 7));
+        assert!(!bitmap.is_allocated(MFT_LCN + 8));
+        assert!(bitmap.is_allocated(INDEX_LCN + 1));
+        assert!(bitmap.is_allocated(BIG_LCN));
+        assert!(!bitmap.is_allocated(VOLUME_CLUSTERS - 1));
+        assert_eq!(bitmap.free_clusters(), VOLUME_CLUSTERS - 16);
+    }
+
+    #[test]
+    fn corrupt_geometry_and_index_entries_are_errors() {
+        let mut boot = [0u8; 512];
+        synthetic_volume().read_bytes(0, &mut boot).unwrap();
+        for (offset, value) in [(0x0D, 0xE0), (0x0D, 0x00), (0x40, 0x80), (0x40, 0x00), (0x44, 0xC0), (0x44, 0x7F)] {
+            let mut bad = boot;
+            bad[offset] = value;
+            assert!(BootSector::parse(&bad).is_err(), "{:#x} at {:#x}", value, offset);
+        }
+
+        // A key longer than its entry
+        let mut entry = index_entry(16, Some("alpha"), None);
+        put(&mut entry, 10, &0x400u16.to_le_bytes());
+        let node = index_node(&[entry, index_entry(0, None, None)], 0x10);
+        assert!(parse_index_node(&node, 0).is_err());
+        assert!(parse_index_node(&node[..4], 0).is_err());
+
+        // A stream claiming far more bytes than its runs map
+        let mut device = synthetic_volume();
+        let record = mft_record(
+            MftHeader::IN_USE,
+            &[
+                resident(0x10, "", &standard_information(FileAttributes::ARCHIVE)),
+                non_resident(0x80, "", BIG_LCN, 2, u64::MAX / 2),
+            ],
+        );
+        device.write_bytes(MFT_LCN * CLUSTER + 17 * RECORD as u64, &record).unwrap();
+        let mut fs = NtfsFileSystem::mount(device).unwrap();
+        assert!(matches!(fs.read_file("/m-file"), Err(StorageError::Corrupted(_))));
+    }
+
+    #[test]
+    fn references_to_reused_records_are_refused() {
+        // Record 16 was freed and reused after "alpha"'s entry was written
+        let with_reference = |reference: u64| {
+            let mut device = synthetic_volume();
+            device.write_bytes(MFT_LCN * CLUSTER + 16 * RECORD as u64 + 0x10, &2u16.to_le_bytes()).unwrap();
+            device.write_bytes(INDEX_LCN * CLUSTER, &indx_block(0, &[(reference, "alpha"), (18, "Beta")])).unwrap();
+            NtfsFileSystem::mount(device).unwrap()
+        };
+
+        let mut fs = with_reference(1 << 48 | 16);
+        let root = fs.root_record();
+        assert!(matches!(fs.lookup_record(root, "alpha"), Err(StorageError::Corrupted(_))));
+        assert!(fs.read_file("/alpha").is_err());
+
+        let mut fs = with_reference(2 << 48 | 16);
+        assert_eq!(fs.lookup_record(root, "alpha").unwrap(), Some(16));
+        assert_eq!(fs.read_file("/alpha").unwrap(), b"first");
+    }
+
+    #[test]
+    fn torn_records_fall_back_to_the_mft_mirror() {
+        const MIRROR_LCN: u64 = 14;
+        let mut device = synthetic_volume();
+        let mut boot = [0u8; 512];
+        device.read_bytes(0, &mut boot).unwrap();
+        put(&mut boot, 0x38, &MIRROR_LCN.to_le_bytes());
+        device.write_bytes(0, &boot).unwrap();
+        let mut mirror = vec![0u8; MFT_MIRROR_RECORDS as usize * RECORD];
+        device.read_bytes(MFT_LCN * CLUSTER, &mut mirror).unwrap();
+        device.write_bytes(MIRROR_LCN * CLUSTER, &mirror).unwrap();
+
+        // Tear $MFT's and $Volume's primary copies, and the root's, which isn't mirrored
+        for record in [0, MFT_RECORD_VOLUME, MFT_RECORD_ROOT] {
+            device.write_bytes(MFT_LCN * CLUSTER + record * RECORD as u64 + 1022, &[0xAA, 0xBB]).unwrap();
+        }
+        let mut fs = NtfsFileSystem::mount(device).unwrap();
+        assert_eq!(fs.volume_info().name, "TEST");
+        // $MFT is read once to bootstrap and once more to load its extensions
+        assert_eq!(fs.get_stats().mirror_reads, 3);
+        assert!(matches!(fs.read_dir("/"), Err(StorageError::Corrupted(_))));
+    }
 }
@@ -1,5 +1,84 @@
 // Export all modules in storage
+pub mod device;
 pub mod disk;
+pub mod error;
 pub mod ssd;
 pub mod filesystem;
-pub mod nvme;
\ No newline at end of file
+pub mod nvme;
+
+pub use device::{BlockDevice, BlockDeviceType, MemoryBlockDevice, SparseBlockDevice};
+
+use self::filesystem::FileSystemKind;
+use self::nvme::controller::NVMeController;
+use crate::machine::description::{MachineDescription, StorageDescription, StorageKind};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const SECTOR_SIZE: usize = 512;
+
+// The machine's boot drive, at the capacity and with the root filesystem
+// the machine description gives. An NVMe drive gets a controller, which
+// is shared with the bus to map its registers at the BAR firmware gave it.
+pub struct Storage {
+    drive: SparseBlockDevice,
+    filesystem: FileSystemKind,
+    nvme: Option<Rc<RefCell<NVMeController>>>,
+}
+
+impl Storage {
+    pub fn new() -> Self {
+        Self::with_description(&MachineDescription::default().storage)
+    }
+
+    pub fn with_description(storage: &StorageDescription) -> Self {
+        let device_type = match storage.kind {
+            StorageKind::NVMe => BlockDeviceType::NVMe,
+            StorageKind::SataSsd | StorageKind::EMMC => BlockDeviceType::SolidState,
+            StorageKind::HDD => BlockDeviceType::HardDisk,
+        };
+        let sectors = storage.capacity_gb * (1 << 30) / SECTOR_SIZE as u64;
+        Self {
+            drive: SparseBlockDevice::new(device_type, sectors, SECTOR_SIZE),
+            filesystem: storage.filesystem,
+            nvme: (storage.kind == StorageKind::NVMe).then(|| Rc::new(RefCell::new(NVMeController::default()))),
+        }
+    }
+
+    pub fn nvme(&self) -> Option<Rc<RefCell<NVMeController>>> {
+        self.nvme.as_ref().map(Rc::clone)
+    }
+
+    pub fn drive_mut(&mut self) -> &mut SparseBlockDevice {
+        &mut self.drive
+    }
+
+    pub fn capacity_bytes(&self) -> u64 {
+        self.drive.sector_count() * SECTOR_SIZE as u64
+    }
+
+    // What the OS formats and mounts as its root volume
+    pub fn filesystem(&self) -> FileSystemKind {
+        self.filesystem
+    }
+
+    // How full the I/O submission queues are, across every pair
+    pub fn get_activity(&self) -> f32 {
+        let Some(nvme) = &self.nvme else {
+            return 0.0;
+        };
+        let pairs = nvme.borrow().queue_pairs();
+        let capacity: usize = pairs.iter().map(|pair| pair.size as usize).sum();
+        let submitted: usize = pairs.iter().map(|pair| pair.submitted).sum();
+        if capacity == 0 {
+            0.0
+        } else {
+            submitted as f32 / capacity as f32
+        }
+    }
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
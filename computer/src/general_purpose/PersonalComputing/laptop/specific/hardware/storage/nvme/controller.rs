@@ -1,6 +1,11 @@
-use super::protocol::{NVMeCommand, NVMeCompletion, NVMeStatus};
+use super::protocol::{
+    CommandOpcode, NVMeCommand, NVMeCompletion, NVMeStatus, STATUS_COMPLETION_QUEUE_INVALID,
+    STATUS_INVALID_FIELD, STATUS_INVALID_OPCODE, STATUS_INVALID_QUEUE_ID, STATUS_INVALID_QUEUE_SIZE,
+};
 use super::queue::{SubmissionQueue, CompletionQueue};
 use super::super::error::{StorageError, StorageResult};
+use crate::hardware::bus::mmio::{AccessSize, MmioDevice};
+use crate::hardware::bus::BusError;
 use std::collections::HashMap;
 
 pub struct NVMeController {
@@ -11,26 +16,62 @@ pub struct NVMeController {
     
     // Controller configuration
     config: NVMeConfig,
+    #[allow(dead_code)]
     features: ControllerFeatures,
     
     // State tracking
     state: ControllerState,
+    registers: ControllerRegisters,
     stats: NVMeStats,
 }
 
+// Controller properties the host programs through BAR0, and the last value
+// written to each doorbell
+#[derive(Default)]
+struct ControllerRegisters {
+    intms: u32,
+    cc: u32,
+    csts: u32,
+    aqa: u32,
+    asq: u64,
+    acq: u64,
+    sq_tails: HashMap<u16, u16>,
+    cq_heads: HashMap<u16, u16>,
+}
+
+// One I/O queue pair as the visualizer shows it
+#[derive(Debug, Clone, Copy)]
+pub struct QueuePairDepth {
+    pub id: u16,
+    pub size: u16,
+    pub submitted: usize,
+    pub completed: usize,
+}
+
 struct AdminQueue {
     submission: SubmissionQueue,
     completion: CompletionQueue,
 }
 
-struct NVMeConfig {
-    max_queues: u16,
-    queue_size: u16,
-    max_transfers: u32,
-    sector_size: u32,
-    max_prp_list: u16,
+impl AdminQueue {
+    fn new(size: u16) -> Self {
+        Self {
+            submission: SubmissionQueue::new(0, size),
+            completion: CompletionQueue::new(0, size),
+        }
+    }
+}
+
+pub struct NVMeConfig {
+    pub max_queues: u16,
+    pub queue_size: u16,
+    pub max_transfers: u32,
+    pub sector_size: u32,
+    pub max_prp_list: u16,
 }
 
+#[derive(Default)]
+#[allow(dead_code)]
 struct ControllerFeatures {
     namespace_mgmt: bool,
     security: bool,
@@ -40,6 +81,7 @@ struct ControllerFeatures {
 }
 
 #[derive(Clone, Copy, PartialEq)]
+#[allow(dead_code)]
 enum ControllerState {
     Disabled,
     Enabled,
@@ -47,12 +89,13 @@ enum ControllerState {
     Failed,
 }
 
-struct NVMeStats {
-    commands_submitted: u64,
-    commands_completed: u64,
-    read_bytes: u64,
-    written_bytes: u64,
-    errors: u64,
+#[derive(Debug, Clone, Default)]
+pub struct NVMeStats {
+    pub commands_submitted: u64,
+    pub commands_completed: u64,
+    pub read_bytes: u64,
+    pub written_bytes: u64,
+    pub errors: u64,
 }
 
 impl NVMeController {
@@ -60,10 +103,11 @@ impl NVMeController {
         Self {
             submission_queues: HashMap::new(),
             completion_queues: HashMap::new(),
-            admin_queue: AdminQueue::new(),
+            admin_queue: AdminQueue::new(config.queue_size),
             config,
             features: ControllerFeatures::default(),
             state: ControllerState::Disabled,
+            registers: ControllerRegisters::default(),
             stats: NVMeStats::default(),
         }
     }
@@ -85,12 +129,11 @@ impl NVMeController {
         Ok(())
     }
 
-    pub fn submit_command(&mut self, command: NVMeCommand) -> StorageResult<()> {
+    pub fn submit_command(&mut self, queue_id: u16, command: NVMeCommand) -> StorageResult<()> {
         if self.state != ControllerState::Ready {
             return Err(StorageError::NotReady);
         }
 
-        let queue_id = command.get_queue_id();
         let sq = self.submission_queues.get_mut(&queue_id)
             .ok_or(StorageError::InvalidQueue)?;
 
@@ -111,62 +154,134 @@ impl NVMeController {
         // Process I/O queue completions
         for cq in self.completion_queues.values_mut() {
             while let Some(completion) = cq.poll()? {
-                completions.push(completion);
                 self.stats.commands_completed += 1;
-
                 if !completion.is_success() {
                     self.stats.errors += 1;
                 }
+                completions.push(completion);
             }
         }
 
         Ok(completions)
     }
 
-    fn setup_admin_queues(&mut self) -> StorageResult<()> {
-        self.admin_queue = AdminQueue::new();
-        
-        // Configure admin submission queue
-        let asq_base = self.allocate_queue_memory(self.config.queue_size)?;
-        self.write_register(REG_ASQ_BASE, asq_base);
-        
-        // Configure admin completion queue
-        let acq_base = self.allocate_queue_memory(self.config.queue_size)?;
-        self.write_register(REG_ACQ_BASE, acq_base);
+    // I/O queue pairs in id order, each with its own doorbells and depth
+    pub fn queue_pairs(&self) -> Vec<QueuePairDepth> {
+        let mut pairs: Vec<QueuePairDepth> = self.submission_queues
+            .values()
+            .map(|sq| QueuePairDepth {
+                id: sq.id(),
+                size: sq.size(),
+                submitted: sq.occupancy(),
+                completed: self.completion_queues.get(&sq.id()).map(|cq| cq.occupancy()).unwrap_or(0),
+            })
+            .collect();
+        pairs.sort_by_key(|pair| pair.id);
+        pairs
+    }
 
+    // An I/O queue pair, created the way a driver does it: the completion
+    // queue first, then the submission queue that posts to it
+    pub fn create_io_queue_pair(&mut self, queue_id: u16, size: u16) -> StorageResult<()> {
+        for opcode in [CommandOpcode::CreateIOCQ, CommandOpcode::CreateIOSQ] {
+            let completion = self.submit_admin_command(NVMeCommand::new_create_queue(opcode, queue_id, size))?;
+            if !completion.is_success() {
+                return Err(StorageError::InvalidQueue);
+            }
+        }
         Ok(())
     }
 
-    fn create_io_queue_pair(&mut self, queue_id: u16, size: u16) -> StorageResult<()> {
-        // Create submission queue
-        let sq = SubmissionQueue::new(queue_id, size);
-        self.submission_queues.insert(queue_id, sq);
-
-        // Create completion queue
-        let cq = CompletionQueue::new(queue_id, size);
-        self.completion_queues.insert(queue_id, cq);
-
-        // Submit create queue commands
-        self.create_completion_queue(queue_id, size)?;
-        self.create_submission_queue(queue_id, size)?;
-
+    // Helper methods
+    fn setup_admin_queues(&mut self) -> StorageResult<()> {
+        // The admin rings live in the model, so only their sizes are
+        // programmed; AQA holds them zero based
+        let size = self.config.queue_size;
+        self.admin_queue = AdminQueue::new(size);
+        let entries = size.saturating_sub(1) as u32 & 0xFFF;
+        self.write_register(REG_AQA, entries << 16 | entries);
         Ok(())
     }
 
     fn identify_controller(&mut self) -> StorageResult<()> {
-        let mut cmd = NVMeCommand::new_identify();
-        cmd.set_identify_controller();
-        
-        let completion = self.submit_admin_command(cmd)?;
+        let completion = self.submit_admin_command(NVMeCommand::new_identify())?;
         if !completion.is_success() {
             return Err(StorageError::IdentifyFailed);
         }
-
-        // Parse controller data structure
-        self.parse_controller_data()?;
         Ok(())
     }
 
+    // Admin commands run as soon as they're submitted; the completion is
+    // posted to the admin completion queue and reaped straight back
+    fn submit_admin_command(&mut self, mut command: NVMeCommand) -> StorageResult<NVMeCompletion> {
+        if self.registers.csts & CSTS_READY == 0 {
+            return Err(StorageError::NotReady);
+        }
+        command.set_command_id(self.stats.commands_submitted as u16);
+        self.admin_queue.submission.submit(command)?;
+        self.stats.commands_submitted += 1;
+
+        let command = self.admin_queue.submission.consume().ok_or(StorageError::QueueEmpty)?;
+        let status = self.execute_admin(&command);
+        let head = self.admin_queue.submission.head();
+        self.admin_queue.completion.push_completion(NVMeCompletion::new(command.command_id(), 0, head, status))?;
+
+        let completion = self.admin_queue.completion.poll()?.ok_or(StorageError::QueueEmpty)?;
+        self.stats.commands_completed += 1;
+        if !completion.is_success() {
+            self.stats.errors += 1;
+        }
+        Ok(completion)
+    }
+
+    fn execute_admin(&mut self, command: &NVMeCommand) -> NVMeStatus {
+        let cdw10 = command.dword(0);
+        let (queue_id, size) = (cdw10 as u16, (cdw10 >> 16) as u16 + 1);
+        let queue_id_valid = queue_id != 0 && queue_id < self.config.max_queues;
+        match command.opcode() {
+            CommandOpcode::Identify | CommandOpcode::GetFeatures | CommandOpcode::SetFeatures => NVMeStatus::success(),
+            CommandOpcode::CreateIOCQ | CommandOpcode::CreateIOSQ if !queue_id_valid => {
+                NVMeStatus::command_specific(STATUS_INVALID_QUEUE_ID)
+            }
+            CommandOpcode::CreateIOCQ | CommandOpcode::CreateIOSQ if size < 2 || size > self.config.queue_size => {
+                NVMeStatus::command_specific(STATUS_INVALID_QUEUE_SIZE)
+            }
+            CommandOpcode::CreateIOCQ => {
+                self.completion_queues.insert(queue_id, CompletionQueue::new(queue_id, size));
+                NVMeStatus::success()
+            }
+            CommandOpcode::CreateIOSQ => {
+                // Each submission queue posts to the completion queue of the same id
+                if (command.dword(1) >> 16) as u16 != queue_id || !self.completion_queues.contains_key(&queue_id) {
+                    return NVMeStatus::command_specific(STATUS_COMPLETION_QUEUE_INVALID);
+                }
+                self.submission_queues.insert(queue_id, SubmissionQueue::new(queue_id, size));
+                NVMeStatus::success()
+            }
+            CommandOpcode::DeleteIOSQ => match self.submission_queues.remove(&queue_id) {
+                Some(_) => NVMeStatus::success(),
+                None => NVMeStatus::command_specific(STATUS_INVALID_QUEUE_ID),
+            },
+            CommandOpcode::DeleteIOCQ if self.submission_queues.contains_key(&queue_id) => {
+                NVMeStatus::generic(STATUS_INVALID_FIELD)
+            }
+            CommandOpcode::DeleteIOCQ => match self.completion_queues.remove(&queue_id) {
+                Some(_) => NVMeStatus::success(),
+                None => NVMeStatus::command_specific(STATUS_INVALID_QUEUE_ID),
+            },
+            _ => NVMeStatus::generic(STATUS_INVALID_OPCODE),
+        }
+    }
+
+    fn wait_ready(&self, ready: bool) -> StorageResult<()> {
+        // CSTS.RDY follows CC.EN at once, so there's nothing to poll for
+        if (self.registers.csts & CSTS_READY != 0) == ready {
+            Ok(())
+        } else {
+            Err(StorageError::NotReady)
+        }
+    }
+
     fn reset(&mut self) -> StorageResult<()> {
         // Disable controller
         self.write_register(REG_CC, 0);
@@ -192,21 +307,222 @@ impl NVMeController {
         Ok(())
     }
 
-    // Register access methods
+    // Register access methods: the driver side of the controller goes
+    // through the same properties the host sees over the bus
     fn read_register(&self, reg: u32) -> u32 {
-        // Implementation would access actual hardware
-        0
+        self.read_property(reg as u64, AccessSize::Word).unwrap_or(0) as u32
     }
 
     fn write_register(&mut self, reg: u32, value: u32) {
-        // Implementation would access actual hardware
+        // Internal writes only touch registers that exist
+        let _ = self.write_property(reg as u64, AccessSize::Word, value as u64);
+    }
+
+    fn capabilities(&self) -> u64 {
+        (self.config.queue_size.saturating_sub(1) as u64) // MQES, zero based
+            | CAP_CQR
+            | CAP_TIMEOUT << 24
+            | CAP_CSS_NVM
+    }
+
+    // 64-bit properties take a doubleword or either half; the rest are 32 bits
+    fn read_property(&self, offset: u64, size: AccessSize) -> Option<u64> {
+        let half = (offset & 4) * 8;
+        let wide = matches!(offset as u32, REG_CAP | REG_ASQ_BASE | REG_ACQ_BASE);
+        if size == AccessSize::Double && !wide {
+            return None;
+        }
+        let value = match offset as u32 {
+            REG_CAP | REG_CAP_HIGH => self.capabilities() >> half,
+            REG_VS => NVME_VERSION as u64,
+            REG_INTMS | REG_INTMC => self.registers.intms as u64,
+            REG_CC => self.registers.cc as u64,
+            REG_CSTS => self.registers.csts as u64,
+            REG_AQA => self.registers.aqa as u64,
+            REG_ASQ_BASE | REG_ASQ_HIGH => self.registers.asq >> half,
+            REG_ACQ_BASE | REG_ACQ_HIGH => self.registers.acq >> half,
+            // Doorbells are write only
+            reg if reg >= REG_DOORBELL && size == AccessSize::Word => 0,
+            _ => return None,
+        };
+        Some(value & size.mask())
+    }
+
+    fn write_property(&mut self, offset: u64, size: AccessSize, value: u64) -> Result<(), BusError> {
+        let wide = matches!(offset as u32, REG_ASQ_BASE | REG_ACQ_BASE);
+        if size == AccessSize::Double && !wide {
+            return Err(BusError::UnsupportedAccessSize(offset));
+        }
+        match offset as u32 {
+            REG_INTMS => self.registers.intms |= value as u32,
+            REG_INTMC => self.registers.intms &= !(value as u32),
+            REG_CC => self.set_configuration(value as u32),
+            REG_AQA => self.registers.aqa = value as u32,
+            REG_ASQ_BASE | REG_ASQ_HIGH => self.registers.asq = merge_half(self.registers.asq, offset, size, value),
+            REG_ACQ_BASE | REG_ACQ_HIGH => self.registers.acq = merge_half(self.registers.acq, offset, size, value),
+            REG_CAP | REG_CAP_HIGH | REG_VS | REG_CSTS => {} // Read only
+            reg if reg >= REG_DOORBELL => return self.ring_doorbell(offset, value as u16),
+            _ => return Err(BusError::DeviceFault(offset)),
+        }
+        Ok(())
+    }
+
+    // CC.EN going up makes the controller ready at once; going down resets
+    // it, dropping every I/O queue. A shutdown request completes immediately.
+    fn set_configuration(&mut self, cc: u32) {
+        let was_enabled = self.registers.cc & CC_ENABLE != 0;
+        self.registers.cc = cc;
+        if cc & CC_ENABLE != 0 && !was_enabled {
+            self.registers.csts |= CSTS_READY;
+            self.state = ControllerState::Enabled;
+        } else if cc & CC_ENABLE == 0 && was_enabled {
+            self.registers.csts = 0;
+            self.registers.sq_tails.clear();
+            self.registers.cq_heads.clear();
+            self.submission_queues.clear();
+            self.completion_queues.clear();
+            self.state = ControllerState::Disabled;
+        }
+        self.registers.csts &= !CSTS_SHST_MASK;
+        if cc & CC_SHN_MASK != 0 {
+            self.registers.csts |= CSTS_SHST_COMPLETE;
+        }
+    }
+
+    // Queue y's submission tail doorbell is at 0x1000 + 8y, its completion
+    // head doorbell 4 bytes later. Ringing one for a queue that doesn't
+    // exist, or past the end of the queue, is refused.
+    fn ring_doorbell(&mut self, offset: u64, value: u16) -> Result<(), BusError> {
+        let index = (offset - REG_DOORBELL as u64) / 4;
+        let (queue, completion) = ((index / 2) as u16, index % 2 == 1);
+        let size = match queue {
+            // AQA holds zero-based sizes: submission in 11:0, completion in 27:16
+            0 if completion => (self.registers.aqa >> 16 & 0xFFF) + 1,
+            0 => (self.registers.aqa & 0xFFF) + 1,
+            _ => self.submission_queues.get(&queue).map_or(0, |sq| sq.size() as u32),
+        };
+        if self.registers.cc & CC_ENABLE == 0 || value as u32 >= size {
+            return Err(BusError::DeviceFault(offset));
+        }
+        if completion {
+            self.registers.cq_heads.insert(queue, value);
+        } else {
+            self.registers.sq_tails.insert(queue, value);
+        }
+        Ok(())
+    }
+
+    pub fn get_stats(&self) -> &NVMeStats {
+        &self.stats
+    }
+}
+
+impl MmioDevice for NVMeController {
+    fn read(&mut self, offset: u64, size: AccessSize) -> Result<u64, BusError> {
+        self.read_property(offset, size).ok_or(BusError::DeviceFault(offset))
+    }
+
+    fn write(&mut self, offset: u64, size: AccessSize, value: u64) -> Result<(), BusError> {
+        self.write_property(offset, size, value)
+    }
+
+    fn supports(&self, size: AccessSize) -> bool {
+        matches!(size, AccessSize::Word | AccessSize::Double)
+    }
+}
+
+impl Default for NVMeController {
+    fn default() -> Self {
+        Self::new(NVMeConfig::default())
+    }
+}
+
+impl Default for NVMeConfig {
+    fn default() -> Self {
+        Self {
+            max_queues: 64,
+            queue_size: 1024,
+            max_transfers: 256,
+            sector_size: 512,
+            max_prp_list: 512,
+        }
     }
 }
 
+// A word write replaces one half of a 64-bit property
+fn merge_half(current: u64, offset: u64, size: AccessSize, value: u64) -> u64 {
+    let shift = (offset & 4) * 8;
+    let mask = size.mask() << shift;
+    current & !mask | (value << shift) & mask
+}
+
+// BAR0: properties, then a doorbell pair per queue
+pub const NVME_REGION_SIZE: u64 = 16 << 10;
+
 // Register definitions
+const REG_CAP: u32 = 0x00;     // Controller Capabilities
+const REG_CAP_HIGH: u32 = 0x04;
+const REG_VS: u32 = 0x08;      // Version
+const REG_INTMS: u32 = 0x0C;   // Interrupt Mask Set
+const REG_INTMC: u32 = 0x10;   // Interrupt Mask Clear
 const REG_CC: u32 = 0x14;      // Controller Configuration
 const REG_CSTS: u32 = 0x1C;    // Controller Status
+const REG_AQA: u32 = 0x24;     // Admin Queue Attributes
 const REG_ASQ_BASE: u32 = 0x28; // Admin Submission Queue Base
+const REG_ASQ_HIGH: u32 = 0x2C;
 const REG_ACQ_BASE: u32 = 0x30; // Admin Completion Queue Base
+const REG_ACQ_HIGH: u32 = 0x34;
+const REG_DOORBELL: u32 = 0x1000; // Doorbell stride of 4 bytes (CAP.DSTRD = 0)
+
+const NVME_VERSION: u32 = 0x0001_0400; // 1.4
+
+const CAP_CQR: u64 = 1 << 16;     // Queues must be physically contiguous
+const CAP_TIMEOUT: u64 = 20;      // Ready timeout, in 500 ms units
+const CAP_CSS_NVM: u64 = 1 << 37; // NVM command set
 
 const CC_ENABLE: u32 = 0x1;    // Controller Enable bit
+const CC_SHN_MASK: u32 = 0b11 << 14; // Shutdown notification
+
+const CSTS_READY: u32 = 0x1;
+const CSTS_SHST_MASK: u32 = 0b11 << 2;
+const CSTS_SHST_COMPLETE: u32 = 0b10 << 2;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initialize_brings_the_controller_ready() {
+        let mut nvme = NVMeController::default();
+        nvme.initialize().unwrap();
+        assert_eq!(nvme.read_register(REG_CSTS) & CSTS_READY, CSTS_READY);
+        assert_eq!(nvme.read_register(REG_AQA), 1023 << 16 | 1023);
+    }
+
+    #[test]
+    fn creates_io_queue_pairs_through_admin_commands() {
+        let mut nvme = NVMeController::default();
+        nvme.initialize().unwrap();
+        nvme.create_io_queue_pair(1, 64).unwrap();
+        nvme.submit_command(1, NVMeCommand::new_read(0, 8)).unwrap();
+
+        let pairs = nvme.queue_pairs();
+        assert_eq!(pairs.len(), 1);
+        assert_eq!((pairs[0].id, pairs[0].size, pairs[0].submitted), (1, 64, 1));
+    }
+
+    #[test]
+    fn refuses_bad_queue_ids_and_sizes() {
+        let mut nvme = NVMeController::default();
+        nvme.initialize().unwrap();
+        assert_eq!(nvme.create_io_queue_pair(0, 64), Err(StorageError::InvalidQueue));
+        assert_eq!(nvme.create_io_queue_pair(2, 4096), Err(StorageError::InvalidQueue));
+        assert_eq!(nvme.submit_command(2, NVMeCommand::new_read(0, 1)), Err(StorageError::InvalidQueue));
+    }
+
+    #[test]
+    fn admin_commands_need_an_enabled_controller() {
+        let mut nvme = NVMeController::default();
+        assert_eq!(nvme.create_io_queue_pair(1, 64), Err(StorageError::NotReady));
+    }
+}
@@ -1,18 +1,18 @@
-use super::super::error::{StorageError, StorageResult};
-
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct NVMeCommand {
     opcode: CommandOpcode,
+    #[allow(dead_code)]
     namespace_id: u32,
     command_id: u16,
     flags: CommandFlags,
+    #[allow(dead_code)]
     metadata_ptr: u64,
     prp1: u64,
     prp2: u64,
     command_specific: [u32; 6],
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct NVMeCompletion {
     command_id: u16,
     status: NVMeStatus,
@@ -22,38 +22,55 @@ pub struct NVMeCompletion {
     command_specific: u32,
 }
 
-#[derive(Clone, Copy)]
+// Admin and I/O commands share opcode values; the queue a command is
+// submitted to says which set it belongs to
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CommandOpcode {
     // Admin Commands
-    DeleteIOSQ = 0x00,
-    CreateIOSQ = 0x01,
-    DeleteIOCQ = 0x04,
-    CreateIOCQ = 0x05,
-    Identify = 0x06,
-    GetFeatures = 0x0A,
-    SetFeatures = 0x09,
+    DeleteIOSQ,
+    CreateIOSQ,
+    DeleteIOCQ,
+    CreateIOCQ,
+    Identify,
+    GetFeatures,
+    SetFeatures,
     
     // IO Commands
-    Flush = 0x00,
-    Write = 0x01,
-    Read = 0x02,
-    WriteUncorrectable = 0x04,
-    Compare = 0x05,
-    WriteZeroes = 0x08,
-    DatasetManagement = 0x09,
+    Flush,
+    Write,
+    Read,
+    WriteUncorrectable,
+    Compare,
+    WriteZeroes,
+    DatasetManagement,
 }
 
-bitflags! {
-    pub struct CommandFlags: u16 {
-        const NONE = 0x0;
-        const PRP_EXTENDED = 0x1;
-        const SGL_EXTENDED = 0x2;
-        const FUSED_FIRST = 0x4;
-        const FUSED_SECOND = 0x8;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CommandFlags(u16);
+
+impl CommandFlags {
+    pub const NONE: Self = Self(0x0);
+    pub const PRP_EXTENDED: Self = Self(0x1);
+    pub const SGL_EXTENDED: Self = Self(0x2);
+    pub const FUSED_FIRST: Self = Self(0x4);
+    pub const FUSED_SECOND: Self = Self(0x8);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
     }
 }
 
-#[derive(Clone, Copy)]
+// Generic command status codes (status code type 0)
+pub const STATUS_SUCCESS: u8 = 0x00;
+pub const STATUS_INVALID_OPCODE: u8 = 0x01;
+pub const STATUS_INVALID_FIELD: u8 = 0x02;
+
+// Command specific status codes (status code type 1)
+pub const STATUS_INVALID_QUEUE_ID: u8 = 0x01;
+pub const STATUS_INVALID_QUEUE_SIZE: u8 = 0x02;
+pub const STATUS_COMPLETION_QUEUE_INVALID: u8 = 0x00;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct NVMeStatus {
     phase_tag: bool,
     status_code: u8,
@@ -103,7 +120,115 @@ impl NVMeCommand {
         self.prp2 = prp2;
     }
 
-    pub fn get_queue_id(&self) -> u16 {
-        (self.command_specific[0] >> 16) as u16
+    // CDW10 carries the zero-based size in its upper half and the queue id
+    // in its lower; an I/O submission queue names its completion queue in
+    // CDW11's upper half
+    pub fn new_create_queue(opcode: CommandOpcode, queue_id: u16, size: u16) -> Self {
+        let mut cmd = Self::new(opcode);
+        cmd.command_specific[0] = (size.saturating_sub(1) as u32) << 16 | queue_id as u32;
+        if opcode == CommandOpcode::CreateIOSQ {
+            cmd.command_specific[1] = (queue_id as u32) << 16;
+        }
+        cmd
+    }
+
+    pub fn opcode(&self) -> CommandOpcode {
+        self.opcode
+    }
+
+    pub fn command_id(&self) -> u16 {
+        self.command_id
+    }
+
+    pub fn set_command_id(&mut self, command_id: u16) {
+        self.command_id = command_id;
+    }
+
+    pub fn flags(&self) -> CommandFlags {
+        self.flags
+    }
+
+    pub fn prp_entries(&self) -> (u64, u64) {
+        (self.prp1, self.prp2)
+    }
+
+    pub fn dword(&self, index: usize) -> u32 {
+        self.command_specific[index]
+    }
+}
+
+impl NVMeCompletion {
+    pub fn new(command_id: u16, sq_id: u16, sq_head_ptr: u16, status: NVMeStatus) -> Self {
+        Self {
+            command_id,
+            status,
+            phase_bit: status.phase_tag,
+            sq_head_ptr,
+            sq_id,
+            command_specific: 0,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status.is_success()
+    }
+
+    pub fn command_id(&self) -> u16 {
+        self.command_id
+    }
+
+    pub fn status(&self) -> NVMeStatus {
+        self.status
+    }
+
+    pub fn sq_id(&self) -> u16 {
+        self.sq_id
+    }
+
+    pub fn sq_head(&self) -> u16 {
+        self.sq_head_ptr
+    }
+
+    pub fn phase(&self) -> bool {
+        self.phase_bit
+    }
+
+    pub fn set_phase(&mut self, phase: bool) {
+        self.phase_bit = phase;
+        self.status.phase_tag = phase;
+    }
+
+    pub fn result(&self) -> u32 {
+        self.command_specific
+    }
+}
+
+impl NVMeStatus {
+    pub fn success() -> Self {
+        Self::generic(STATUS_SUCCESS)
+    }
+
+    pub fn generic(status_code: u8) -> Self {
+        Self { phase_tag: false, status_code, status_code_type: 0, more: false, do_not_retry: status_code != STATUS_SUCCESS }
+    }
+
+    pub fn command_specific(status_code: u8) -> Self {
+        Self { phase_tag: false, status_code, status_code_type: 1, more: false, do_not_retry: true }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status_code_type == 0 && self.status_code == STATUS_SUCCESS
+    }
+
+    pub fn code(&self) -> (u8, u8) {
+        (self.status_code_type, self.status_code)
+    }
+
+    pub fn more(&self) -> bool {
+        self.more
+    }
+
+    pub fn do_not_retry(&self) -> bool {
+        self.do_not_retry
     }
 }
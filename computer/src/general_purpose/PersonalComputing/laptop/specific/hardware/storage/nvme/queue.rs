@@ -8,6 +8,7 @@ pub struct SubmissionQueue {
     head: u16,
     tail: u16,
     size: u16,
+    #[allow(dead_code)]
     phase: bool,
     stats: QueueStats,
 }
@@ -22,11 +23,12 @@ pub struct CompletionQueue {
     stats: QueueStats,
 }
 
-struct QueueStats {
-    entries_submitted: u64,
-    entries_completed: u64,
-    overflows: u64,
-    underflows: u64,
+#[derive(Debug, Clone, Default)]
+pub struct QueueStats {
+    pub entries_submitted: u64,
+    pub entries_completed: u64,
+    pub overflows: u64,
+    pub underflows: u64,
 }
 
 impl SubmissionQueue {
@@ -55,6 +57,17 @@ impl SubmissionQueue {
         Ok(())
     }
 
+    // The controller fetches the oldest command
+    pub fn consume(&mut self) -> Option<NVMeCommand> {
+        let command = self.entries.pop_front()?;
+        self.head = (self.head + 1) % self.size;
+        Some(command)
+    }
+
+    pub fn head(&self) -> u16 {
+        self.head
+    }
+
     pub fn update_head(&mut self, new_head: u16) {
         self.head = new_head;
         // Remove completed entries
@@ -64,9 +77,26 @@ impl SubmissionQueue {
         }
     }
 
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    // Commands submitted but not yet consumed by the controller
+    pub fn occupancy(&self) -> usize {
+        self.entries.len()
+    }
+
     fn is_full(&self) -> bool {
         ((self.tail + 1) % self.size) == self.head
     }
+
+    pub fn get_stats(&self) -> &QueueStats {
+        &self.stats
+    }
 }
 
 impl CompletionQueue {
@@ -111,6 +141,15 @@ impl CompletionQueue {
         Ok(())
     }
 
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    // Completions posted but not yet reaped by the host
+    pub fn occupancy(&self) -> usize {
+        self.entries.len()
+    }
+
     fn is_empty(&self) -> bool {
         self.head == self.tail
     }
@@ -118,4 +157,8 @@ impl CompletionQueue {
     fn is_full(&self) -> bool {
         ((self.tail + 1) % self.size) == self.head
     }
+
+    pub fn get_stats(&self) -> &QueueStats {
+        &self.stats
+    }
 }
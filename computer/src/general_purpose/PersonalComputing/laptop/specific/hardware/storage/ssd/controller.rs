@@ -1,44 +1,263 @@
-use super::nand::NANDFlash;
-use super::wear_leveling::WearLeveler;
-use super::garbage_collection::GarbageCollector;
+use super::nand::{NANDConfig, NANDFlash, PageAddress, PageState, Relocation};
+use super::wear_leveling::{WearConfig, WearLeveler};
+use super::garbage_collection::{GCConfig, GarbageCollector};
+use super::super::error::{StorageError, StorageResult};
+use std::collections::HashMap;
 
+// A flash translation layer over one NAND chip. Logical pages are written
+// out of place at the write frontier; garbage collection and wear leveling
+// move valid pages behind the host's back, and the mapping follows them.
 pub struct SSDController {
-    nand_chips: Vec<NANDFlash>,
+    nand: NANDFlash,
     wear_leveler: WearLeveler,
     garbage_collector: GarbageCollector,
-    write_cache: WriteCache,
-    read_cache: ReadCache,
     mapping_table: FTL,
     stats: SSDStats,
 }
 
 struct FTL {
-    logical_to_physical: HashMap<u64, PhysicalAddress>,
-    physical_to_logical: HashMap<PhysicalAddress, u64>,
-    free_blocks: Vec<BlockInfo>,
+    logical_to_physical: HashMap<u64, PageAddress>,
+    physical_to_logical: HashMap<PageAddress, u64>,
+    frontier: Option<PageAddress>, // Next page the host writes into
 }
 
-struct BlockInfo {
-    address: PhysicalAddress,
-    erase_count: u32,
-    valid_pages: u32,
-    invalid_pages: u32,
+#[derive(Debug, Clone, Default)]
+pub struct SSDStats {
+    pub host_reads: u64,
+    pub host_writes: u64,
+    pub trimmed_pages: u64,
+    pub relocated_pages: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SSDHealthInfo {
+    pub total_erases: u64,
+    pub max_erase_count: u32,
+    pub min_erase_count: u32,
+    pub bad_blocks: u32,
+    pub percent_used: f32,        // Wear of the most erased block against its rated cycles
+    pub write_amplification: f32, // NAND page writes per host page write
 }
 
 impl SSDController {
-    pub fn read(&mut self, logical_address: u64) -> Result<Vec<u8>, SSDError> {
-        // Handle read with caching and wear leveling
+    pub fn new(nand: NANDConfig, gc: GCConfig, wear: WearConfig) -> Self {
+        Self {
+            nand: NANDFlash::new(nand),
+            wear_leveler: WearLeveler::new(wear),
+            garbage_collector: GarbageCollector::new(gc),
+            mapping_table: FTL {
+                logical_to_physical: HashMap::new(),
+                physical_to_logical: HashMap::new(),
+                frontier: None,
+            },
+            stats: SSDStats::default(),
+        }
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.nand.page_size()
     }
 
-    pub fn write(&mut self, logical_address: u64, data: &[u8]) -> Result<(), SSDError> {
-        // Handle write with wear leveling and garbage collection
+    // Unmapped and trimmed pages read back as zeros
+    pub fn read(&mut self, logical_address: u64) -> StorageResult<Vec<u8>> {
+        self.stats.host_reads += 1;
+        match self.mapping_table.logical_to_physical.get(&logical_address) {
+            Some(physical) => self.nand.read_page(physical.block, physical.page),
+            None => Ok(vec![0; self.nand.page_size()]),
+        }
     }
 
-    pub fn trim(&mut self, logical_address: u64, length: u64) -> Result<(), SSDError> {
-        // Handle TRIM command
+    pub fn write(&mut self, logical_address: u64, data: &[u8]) -> StorageResult<()> {
+        if data.len() > self.nand.page_size() {
+            return Err(StorageError::InvalidData);
+        }
+
+        let target = match self.allocate_page() {
+            Ok(target) => target,
+            Err(StorageError::NoFreeBlocks) => {
+                // Out of erased pages: reclaim stale ones and try once more
+                self.collect_garbage()?;
+                self.allocate_page().map_err(|_| StorageError::NoSpace)?
+            }
+            Err(error) => return Err(error),
+        };
+        self.nand.write_page(target.block, target.page, data)?;
+        self.unmap(logical_address)?;
+        self.map(logical_address, target);
+        self.stats.host_writes += 1;
+
+        self.collect_garbage()?;
+        let moved = self.wear_leveler.check_wear(&mut self.nand)?;
+        self.follow(&moved);
+        Ok(())
+    }
+
+    // Drops `length` logical pages from the mapping so GC can reclaim them
+    pub fn trim(&mut self, logical_address: u64, length: u64) -> StorageResult<()> {
+        for address in logical_address..logical_address.saturating_add(length) {
+            if self.unmap(address)? {
+                self.stats.trimmed_pages += 1;
+            }
+        }
+        Ok(())
     }
 
     pub fn get_health_info(&self) -> SSDHealthInfo {
-        // Return drive health statistics
+        let statuses = self.nand.get_block_statuses();
+        let nand_writes = self.nand.get_stats().writes;
+        let max_erase_count = statuses.iter().map(|status| status.erase_count).max().unwrap_or(0);
+        SSDHealthInfo {
+            total_erases: statuses.iter().map(|status| status.erase_count as u64).sum(),
+            max_erase_count,
+            min_erase_count: statuses.iter().map(|status| status.erase_count).min().unwrap_or(0),
+            bad_blocks: statuses.iter().filter(|status| status.bad_block).count() as u32,
+            percent_used: max_erase_count as f32 / self.nand.max_erase_cycles() as f32 * 100.0,
+            write_amplification: if self.stats.host_writes == 0 {
+                0.0
+            } else {
+                nand_writes as f32 / self.stats.host_writes as f32
+            },
+        }
+    }
+
+    pub fn get_stats(&self) -> &SSDStats {
+        &self.stats
+    }
+
+    pub fn nand(&self) -> &NANDFlash {
+        &self.nand
+    }
+
+    fn collect_garbage(&mut self) -> StorageResult<()> {
+        let moved = self.garbage_collector.check_space(&mut self.nand)?;
+        self.follow(&moved);
+        Ok(())
+    }
+
+    // The next erased page: on along the frontier's block, then the first
+    // block with an erased page
+    fn allocate_page(&mut self) -> StorageResult<PageAddress> {
+        let pages_per_block = self.nand.get_pages_per_block();
+        if let Some(frontier) = self.mapping_table.frontier {
+            for page in frontier.page..pages_per_block {
+                if self.nand.page_state(frontier.block, page)? == PageState::Free {
+                    return Ok(self.advance(PageAddress { block: frontier.block, page }));
+                }
+            }
+        }
+
+        let statuses = self.nand.get_block_statuses();
+        for (block, status) in statuses.iter().enumerate() {
+            if status.bad_block || status.free_pages == 0 {
+                continue;
+            }
+            for page in 0..pages_per_block {
+                if self.nand.page_state(block, page)? == PageState::Free {
+                    return Ok(self.advance(PageAddress { block, page }));
+                }
+            }
+        }
+        Err(StorageError::NoFreeBlocks)
     }
-} 
\ No newline at end of file
+
+    fn advance(&mut self, address: PageAddress) -> PageAddress {
+        self.mapping_table.frontier = Some(PageAddress { block: address.block, page: address.page + 1 });
+        address
+    }
+
+    fn map(&mut self, logical_address: u64, physical: PageAddress) {
+        self.mapping_table.logical_to_physical.insert(logical_address, physical);
+        self.mapping_table.physical_to_logical.insert(physical, logical_address);
+    }
+
+    // Returns whether the address was mapped
+    fn unmap(&mut self, logical_address: u64) -> StorageResult<bool> {
+        let Some(physical) = self.mapping_table.logical_to_physical.remove(&logical_address) else {
+            return Ok(false);
+        };
+        self.mapping_table.physical_to_logical.remove(&physical);
+        self.nand.invalidate_page(physical.block, physical.page)?;
+        Ok(true)
+    }
+
+    // Points logical pages at where GC or wear leveling moved them. Swaps
+    // move pages both ways between two blocks, so every old mapping is
+    // dropped before any new one is added.
+    fn follow(&mut self, relocations: &[Relocation]) {
+        let owners: Vec<Option<u64>> = relocations.iter()
+            .map(|relocation| self.mapping_table.physical_to_logical.remove(&relocation.from))
+            .collect();
+        for (relocation, owner) in relocations.iter().zip(owners) {
+            if let Some(logical_address) = owner {
+                self.map(logical_address, relocation.to);
+                self.stats.relocated_pages += 1;
+            }
+        }
+    }
+}
+
+impl Default for SSDController {
+    fn default() -> Self {
+        Self::new(NANDConfig::default(), GCConfig::default(), WearConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 8 blocks of 4 pages: small enough that GC has to run
+    fn small_ssd() -> SSDController {
+        let nand = NANDConfig { page_size: 16, pages_per_block: 4, blocks_per_chip: 8, max_erase_cycles: 3000 };
+        SSDController::new(nand, GCConfig::default(), WearConfig::default())
+    }
+
+    fn page(byte: u8) -> Vec<u8> {
+        vec![byte; 16]
+    }
+
+    #[test]
+    fn reads_back_the_latest_write() {
+        let mut ssd = small_ssd();
+        ssd.write(3, &page(1)).unwrap();
+        ssd.write(3, &page(2)).unwrap();
+        assert_eq!(ssd.read(3).unwrap(), page(2));
+        assert_eq!(ssd.read(4).unwrap(), vec![0; 16]);
+    }
+
+    #[test]
+    fn garbage_collection_keeps_overwritten_data_readable() {
+        // Far more writes than the chip has pages
+        let mut ssd = small_ssd();
+        for round in 0..50u8 {
+            for logical in 0..6 {
+                ssd.write(logical, &page(round.wrapping_mul(7).wrapping_add(logical as u8))).unwrap();
+            }
+        }
+        for logical in 0..6 {
+            assert_eq!(ssd.read(logical).unwrap(), page(49u8.wrapping_mul(7).wrapping_add(logical as u8)));
+        }
+        let health = ssd.get_health_info();
+        assert!(health.total_erases > 0);
+        assert!(health.write_amplification >= 1.0);
+    }
+
+    #[test]
+    fn trim_unmaps_and_frees_pages() {
+        let mut ssd = small_ssd();
+        for logical in 0..4 {
+            ssd.write(logical, &page(9)).unwrap();
+        }
+        ssd.trim(1, 2).unwrap();
+        assert_eq!(ssd.read(1).unwrap(), vec![0; 16]);
+        assert_eq!(ssd.read(0).unwrap(), page(9));
+        assert_eq!(ssd.get_stats().trimmed_pages, 2);
+        assert_eq!(ssd.nand().get_block_status(0).unwrap().invalid_pages, 2);
+    }
+
+    #[test]
+    fn rejects_writes_larger_than_a_page() {
+        let mut ssd = small_ssd();
+        assert_eq!(ssd.write(0, &[0; 17]), Err(StorageError::InvalidData));
+    }
+}
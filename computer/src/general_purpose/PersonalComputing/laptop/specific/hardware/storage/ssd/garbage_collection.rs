@@ -1,4 +1,4 @@
-use super::nand::{NANDFlash, Block, BlockStatus, PageState};
+use super::nand::{NANDFlash, PageAddress, Relocation};
 use super::super::error::{StorageError, StorageResult};
 use std::collections::{BinaryHeap, HashMap};
 
@@ -6,6 +6,7 @@ pub struct GarbageCollector {
     block_info: HashMap<usize, BlockInfo>,
     candidates: BinaryHeap<GCCandidate>,
     config: GCConfig,
+    clock: u64, // Advances once per check
     stats: GCStats,
 }
 
@@ -20,21 +21,21 @@ struct BlockInfo {
 struct GCCandidate {
     block_id: usize,
     score: f32,
-    valid_ratio: f32,
 }
 
-struct GCConfig {
-    threshold_ratio: f32,      // When to start GC
-    target_free_blocks: u32,   // How many blocks to maintain free
-    max_valid_ratio: f32,      // Max valid pages ratio for collection
-    collection_batch: usize,   // How many blocks to collect at once
+#[derive(Debug, Clone)]
+pub struct GCConfig {
+    pub threshold_ratio: f32,      // When to start GC
+    pub target_free_blocks: u32,   // How many blocks to maintain free
+    pub max_valid_ratio: f32,      // Max valid pages ratio for collection
+    pub collection_batch: usize,   // How many blocks to collect at once
 }
 
-struct GCStats {
-    collections: u64,
-    pages_moved: u64,
-    blocks_reclaimed: u64,
-    time_spent: u64,
+#[derive(Debug, Clone, Default)]
+pub struct GCStats {
+    pub collections: u64,
+    pub pages_moved: u64,
+    pub blocks_reclaimed: u64,
 }
 
 impl GarbageCollector {
@@ -43,34 +44,52 @@ impl GarbageCollector {
             block_info: HashMap::new(),
             candidates: BinaryHeap::new(),
             config,
+            clock: 0,
             stats: GCStats::default(),
         }
     }
 
-    pub fn check_space(&mut self, nand: &NANDFlash) -> StorageResult<()> {
+    // Collects if free space is low; returns the valid pages it moved
+    pub fn check_space(&mut self, nand: &mut NANDFlash) -> StorageResult<Vec<Relocation>> {
+        self.clock += 1;
+
         // Update block information
         self.update_block_info(nand);
 
         // Check if GC is needed
         if self.needs_collection() {
-            self.perform_collection(nand)?;
+            return self.perform_collection(nand);
         }
 
-        Ok(())
+        Ok(Vec::new())
+    }
+
+    pub fn get_stats(&self) -> &GCStats {
+        &self.stats
     }
 
     fn update_block_info(&mut self, nand: &NANDFlash) {
-        self.block_info.clear();
         self.candidates.clear();
 
         for (block_id, status) in nand.get_block_statuses().iter().enumerate() {
+            if status.bad_block {
+                self.block_info.remove(&block_id);
+                continue;
+            }
+            let last_collection = self.block_info.get(&block_id).map_or(0, |info| info.last_collection);
             let info = BlockInfo {
                 valid_pages: status.valid_pages,
                 invalid_pages: status.invalid_pages,
                 free_pages: status.free_pages,
-                last_collection: 0,
+                last_collection,
             };
 
+            // Only blocks with stale pages are worth collecting
+            if info.invalid_pages == 0 {
+                self.block_info.insert(block_id, info);
+                continue;
+            }
+
             // Calculate collection score
             let valid_ratio = info.valid_pages as f32 / 
                 (info.valid_pages + info.invalid_pages) as f32;
@@ -80,7 +99,6 @@ impl GarbageCollector {
                 self.candidates.push(GCCandidate {
                     block_id,
                     score,
-                    valid_ratio,
                 });
             }
 
@@ -89,60 +107,77 @@ impl GarbageCollector {
     }
 
     fn needs_collection(&self) -> bool {
-        let free_ratio = self.get_free_space_ratio();
-        free_ratio < self.config.threshold_ratio
+        let free_blocks = self.block_info.values()
+            .filter(|info| info.valid_pages + info.invalid_pages == 0)
+            .count() as u32;
+        self.get_free_space_ratio() < self.config.threshold_ratio
+            || free_blocks < self.config.target_free_blocks
     }
 
-    fn perform_collection(&mut self, nand: &mut NANDFlash) -> StorageResult<()> {
-        let start_time = self.get_current_time();
-        let mut pages_moved = 0;
-        let mut blocks_reclaimed = 0;
+    fn perform_collection(&mut self, nand: &mut NANDFlash) -> StorageResult<Vec<Relocation>> {
+        let mut relocations = Vec::new();
 
         // Process most promising candidates
         for _ in 0..self.config.collection_batch {
-            if let Some(candidate) = self.candidates.pop() {
-                if self.collect_block(nand, candidate.block_id)? {
-                    blocks_reclaimed += 1;
-                    pages_moved += candidate.valid_ratio * nand.get_pages_per_block() as f32;
-                }
-            } else {
+            let Some(candidate) = self.candidates.pop() else {
                 break;
-            }
+            };
+            // Blocks with valid pages need an erased block to take them;
+            // once none is left, the rest wait for the next check
+            let moved = match self.collect_block(nand, candidate.block_id) {
+                Err(StorageError::NoFreeBlocks) => break,
+                result => result?,
+            };
+            self.stats.blocks_reclaimed += 1;
+            self.stats.pages_moved += moved.len() as u64;
+            relocations.extend(moved);
         }
 
-        // Update statistics
         self.stats.collections += 1;
-        self.stats.pages_moved += pages_moved as u64;
-        self.stats.blocks_reclaimed += blocks_reclaimed;
-        self.stats.time_spent += self.get_current_time() - start_time;
-
-        Ok(())
+        Ok(relocations)
     }
 
-    fn collect_block(&mut self, nand: &mut NANDFlash, block_id: usize) -> StorageResult<bool> {
+    fn collect_block(&mut self, nand: &mut NANDFlash, block_id: usize) -> StorageResult<Vec<Relocation>> {
         // Read valid pages
         let valid_data = self.read_valid_pages(nand, block_id)?;
-
-        // Find target block for valid data
-        let target_block = self.find_free_block(nand)?;
-
-        // Write valid data to new location
-        for (page_offset, data) in valid_data {
-            nand.write_page(target_block, page_offset, &data)?;
+        let mut relocations = Vec::with_capacity(valid_data.len());
+
+        if !valid_data.is_empty() {
+            // Find target block for valid data
+            let target_block = self.find_free_block(nand, block_id)?;
+
+            // Write valid data to new location
+            for (page_offset, data) in valid_data {
+                nand.write_page(target_block, page_offset, &data)?;
+                relocations.push(Relocation {
+                    from: PageAddress { block: block_id, page: page_offset },
+                    to: PageAddress { block: target_block, page: page_offset },
+                });
+            }
+            if let Some(info) = self.block_info.get_mut(&target_block) {
+                info.free_pages -= relocations.len() as u32;
+                info.valid_pages += relocations.len() as u32;
+            }
         }
 
         // Erase the collected block
         nand.erase_block(block_id)?;
+        if let Some(info) = self.block_info.get_mut(&block_id) {
+            info.free_pages += info.valid_pages + info.invalid_pages;
+            info.valid_pages = 0;
+            info.invalid_pages = 0;
+            info.last_collection = self.clock;
+        }
 
-        Ok(true)
+        Ok(relocations)
     }
 
     fn calculate_gc_score(&self, info: &BlockInfo) -> f32 {
         let valid_ratio = info.valid_pages as f32 / 
             (info.valid_pages + info.invalid_pages) as f32;
         
-        // Score based on invalid pages and age
-        let age_factor = 1.0 - (self.get_current_time() - info.last_collection) as f32 / 1000.0;
+        // Score based on invalid pages, preferring blocks left alone longest
+        let age_factor = 1.0 + (self.clock - info.last_collection) as f32 / 1000.0;
         
         (1.0 - valid_ratio) * age_factor
     }
@@ -152,13 +187,17 @@ impl GarbageCollector {
             .map(|info| info.free_pages)
             .sum();
 
-        let total_pages: u32 = self.block_info.len() as u32 * 
-            self.config.target_free_blocks;
+        let total_pages: u32 = self.block_info.values()
+            .map(|info| info.free_pages + info.valid_pages + info.invalid_pages)
+            .sum();
+        if total_pages == 0 {
+            return 0.0;
+        }
 
         total_free_pages as f32 / total_pages as f32
     }
 
-    fn read_valid_pages(&self, nand: &NANDFlash, block_id: usize) 
+    fn read_valid_pages(&self, nand: &mut NANDFlash, block_id: usize) 
         -> StorageResult<Vec<(usize, Vec<u8>)>> 
     {
         let mut valid_data = Vec::new();
@@ -174,31 +213,36 @@ impl GarbageCollector {
         Ok(valid_data)
     }
 
-    fn find_free_block(&self, nand: &NANDFlash) -> StorageResult<usize> {
-        // Find a completely free block
-        for (block_id, info) in &self.block_info {
-            if info.free_pages as usize == nand.get_pages_per_block() {
-                return Ok(*block_id);
-            }
-        }
-        Err(StorageError::NoFreeBlocks)
+    fn find_free_block(&self, nand: &NANDFlash, victim: usize) -> StorageResult<usize> {
+        // Find a completely free block, lowest numbered first so runs repeat
+        (0..nand.block_count())
+            .filter(|&block_id| block_id != victim)
+            .find(|block_id| self.block_info.get(block_id)
+                .is_some_and(|info| info.free_pages as usize == nand.get_pages_per_block()))
+            .ok_or(StorageError::NoFreeBlocks)
     }
+}
 
-    fn get_current_time(&self) -> u64 {
-        // Implementation would use actual system time
-        0
+impl Default for GCConfig {
+    fn default() -> Self {
+        Self {
+            threshold_ratio: 0.1,
+            target_free_blocks: 2,
+            max_valid_ratio: 0.9,
+            collection_batch: 4,
+        }
     }
 }
 
 impl PartialOrd for GCCandidate {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.score.partial_cmp(&other.score)
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for GCCandidate {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap_or(std::cmp::Ordering::Equal)
+        self.score.partial_cmp(&other.score).unwrap_or(std::cmp::Ordering::Equal)
     }
 }
 
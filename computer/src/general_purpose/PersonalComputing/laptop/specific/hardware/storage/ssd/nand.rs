@@ -1,4 +1,3 @@
-use std::collections::HashMap;
 use super::super::error::{StorageError, StorageResult};
 
 pub struct NANDFlash {
@@ -13,37 +12,57 @@ pub struct Block {
     bad_block: bool,
 }
 
+#[derive(Clone)]
 pub struct Page {
     data: Vec<u8>,
-    metadata: PageMetadata,
     state: PageState,
 }
 
-struct PageMetadata {
-    logical_address: Option<u64>,
-    write_timestamp: u64,
-    error_correction: ECC,
-}
-
-#[derive(Clone, Copy, PartialEq)]
-enum PageState {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PageState {
     Free,
     Valid,
     Invalid,
 }
 
-struct NANDConfig {
-    page_size: usize,
-    pages_per_block: usize,
-    blocks_per_chip: usize,
-    max_erase_cycles: u32,
+// Where a page lives on the chip
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PageAddress {
+    pub block: usize,
+    pub page: usize,
+}
+
+// A valid page that garbage collection or wear leveling moved; the FTL
+// must follow it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Relocation {
+    pub from: PageAddress,
+    pub to: PageAddress,
 }
 
-struct NANDStats {
-    reads: u64,
-    writes: u64,
-    erases: u64,
-    errors: u64,
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockStatus {
+    pub erase_count: u32,
+    pub bad_block: bool,
+    pub free_pages: u32,
+    pub valid_pages: u32,
+    pub invalid_pages: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct NANDConfig {
+    pub page_size: usize,
+    pub pages_per_block: usize,
+    pub blocks_per_chip: usize,
+    pub max_erase_cycles: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NANDStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub erases: u64,
+    pub errors: u64,
 }
 
 impl NANDFlash {
@@ -61,6 +80,7 @@ impl NANDFlash {
 
     pub fn read_page(&mut self, block: usize, page: usize) -> StorageResult<Vec<u8>> {
         if block >= self.blocks.len() || self.blocks[block].bad_block {
+            self.stats.errors += 1;
             return Err(StorageError::BadBlock);
         }
 
@@ -71,14 +91,29 @@ impl NANDFlash {
 
     pub fn write_page(&mut self, block: usize, page: usize, data: &[u8]) -> StorageResult<()> {
         if block >= self.blocks.len() || self.blocks[block].bad_block {
+            self.stats.errors += 1;
             return Err(StorageError::BadBlock);
         }
+        if data.len() > self.config.page_size {
+            return Err(StorageError::InvalidData);
+        }
 
         self.blocks[block].write_page(page, data)?;
         self.stats.writes += 1;
         Ok(())
     }
 
+    // NAND can't overwrite in place; the FTL marks the old copy stale
+    pub fn invalidate_page(&mut self, block: usize, page: usize) -> StorageResult<()> {
+        let page = self.blocks.get_mut(block)
+            .and_then(|block| block.pages.get_mut(page))
+            .ok_or(StorageError::InvalidAddress)?;
+        if page.state == PageState::Valid {
+            page.state = PageState::Invalid;
+        }
+        Ok(())
+    }
+
     pub fn erase_block(&mut self, block: usize) -> StorageResult<()> {
         if block >= self.blocks.len() {
             return Err(StorageError::InvalidAddress);
@@ -96,17 +131,43 @@ impl NANDFlash {
     }
 
     pub fn get_block_status(&self, block: usize) -> StorageResult<BlockStatus> {
-        if block >= self.blocks.len() {
-            return Err(StorageError::InvalidAddress);
-        }
+        let block = self.blocks.get(block).ok_or(StorageError::InvalidAddress)?;
+        Ok(block.status())
+    }
+
+    pub fn get_block_statuses(&self) -> Vec<BlockStatus> {
+        self.blocks.iter().map(Block::status).collect()
+    }
+
+    pub fn page_state(&self, block: usize, page: usize) -> StorageResult<PageState> {
+        self.blocks.get(block)
+            .and_then(|block| block.pages.get(page))
+            .map(|page| page.state)
+            .ok_or(StorageError::InvalidAddress)
+    }
+
+    pub fn is_page_valid(&self, block: usize, page: usize) -> StorageResult<bool> {
+        Ok(self.page_state(block, page)? == PageState::Valid)
+    }
+
+    pub fn get_pages_per_block(&self) -> usize {
+        self.config.pages_per_block
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.config.page_size
+    }
+
+    pub fn max_erase_cycles(&self) -> u32 {
+        self.config.max_erase_cycles
+    }
 
-        Ok(BlockStatus {
-            erase_count: self.blocks[block].erase_count,
-            bad_block: self.blocks[block].bad_block,
-            free_pages: self.blocks[block].count_free_pages(),
-            valid_pages: self.blocks[block].count_valid_pages(),
-            invalid_pages: self.blocks[block].count_invalid_pages(),
-        })
+    pub fn get_stats(&self) -> &NANDStats {
+        &self.stats
     }
 }
 
@@ -140,7 +201,8 @@ impl Block {
             return Err(StorageError::PageNotFree);
         }
 
-        self.pages[page].write(data)
+        self.pages[page].write(data);
+        Ok(())
     }
 
     fn erase(&mut self) -> StorageResult<()> {
@@ -151,4 +213,45 @@ impl Block {
         self.erase_count += 1;
         Ok(())
     }
+
+    fn status(&self) -> BlockStatus {
+        BlockStatus {
+            erase_count: self.erase_count,
+            bad_block: self.bad_block,
+            free_pages: self.count_pages(PageState::Free),
+            valid_pages: self.count_pages(PageState::Valid),
+            invalid_pages: self.count_pages(PageState::Invalid),
+        }
+    }
+
+    fn count_pages(&self, state: PageState) -> u32 {
+        self.pages.iter().filter(|page| page.state == state).count() as u32
+    }
+}
+
+impl Page {
+    // Erased flash reads back as all ones
+    fn new(page_size: usize) -> Self {
+        Self {
+            data: vec![0xFF; page_size],
+            state: PageState::Free,
+        }
+    }
+
+    // Short writes leave the rest of the page erased
+    fn write(&mut self, data: &[u8]) {
+        self.data[..data.len()].copy_from_slice(data);
+        self.state = PageState::Valid;
+    }
+}
+
+impl Default for NANDConfig {
+    fn default() -> Self {
+        Self {
+            page_size: 4096,
+            pages_per_block: 64,
+            blocks_per_chip: 256,
+            max_erase_cycles: 3000,
+        }
+    }
 }
@@ -1,5 +1,5 @@
-use super::nand::{NANDFlash, Block, BlockStatus};
-use super::super::error::{StorageError, StorageResult};
+use super::nand::{NANDFlash, PageAddress, Relocation};
+use super::super::error::StorageResult;
 use std::collections::{BinaryHeap, HashMap};
 
 pub struct WearLeveler {
@@ -7,9 +7,11 @@ pub struct WearLeveler {
     hot_data: BinaryHeap<BlockRanking>,
     cold_data: BinaryHeap<BlockRanking>,
     config: WearConfig,
+    clock: u64, // Advances once per check
     stats: WearStats,
 }
 
+#[derive(Default)]
 struct BlockUsageStats {
     erase_count: u32,
     last_write: u64,
@@ -17,30 +19,34 @@ struct BlockUsageStats {
     temperature: BlockTemperature,
 }
 
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Default)]
 enum BlockTemperature {
     Hot,
     Warm,
+    #[default]
     Cold,
 }
 
+#[derive(Clone, PartialEq, Eq)]
 struct BlockRanking {
     block_id: usize,
     score: i32,
 }
 
-struct WearConfig {
-    wear_threshold: u32,
-    temperature_threshold: f32,
-    migration_batch_size: usize,
-    leveling_interval: u64,
+#[derive(Debug, Clone)]
+pub struct WearConfig {
+    pub wear_threshold: u32,          // Erase count spread that triggers leveling
+    pub temperature_threshold: f32,   // Erases per check above which a block is warm
+    pub migration_batch_size: usize,
+    pub leveling_interval: u64,       // Checks between leveling passes
 }
 
-struct WearStats {
-    migrations: u64,
-    wear_delta: f32,
-    hot_cold_swaps: u64,
-    blocks_retired: u64,
+#[derive(Debug, Clone, Default)]
+pub struct WearStats {
+    pub migrations: u64,
+    pub wear_delta: u32,      // Erase count spread after the last pass
+    pub hot_cold_swaps: u64,
+    pub blocks_retired: u64,
 }
 
 impl WearLeveler {
@@ -50,30 +56,49 @@ impl WearLeveler {
             hot_data: BinaryHeap::new(),
             cold_data: BinaryHeap::new(),
             config,
+            clock: 0,
             stats: WearStats::default(),
         }
     }
 
-    pub fn check_wear(&mut self, nand: &NANDFlash) -> StorageResult<()> {
+    // Swaps hot and cold blocks once the erase counts drift too far apart;
+    // returns the valid pages it moved
+    pub fn check_wear(&mut self, nand: &mut NANDFlash) -> StorageResult<Vec<Relocation>> {
+        self.clock += 1;
+
         // Update block statistics
         self.update_block_stats(nand);
 
         // Check wear leveling conditions
-        if self.needs_leveling() {
-            self.perform_wear_leveling(nand)?;
+        if self.clock.is_multiple_of(self.config.leveling_interval) && self.needs_leveling() {
+            return self.perform_wear_leveling(nand);
         }
 
-        Ok(())
+        Ok(Vec::new())
+    }
+
+    pub fn get_stats(&self) -> &WearStats {
+        &self.stats
     }
 
     fn update_block_stats(&mut self, nand: &NANDFlash) {
         for (block_id, status) in nand.get_block_statuses().iter().enumerate() {
-            let stats = self.block_stats.entry(block_id)
-                .or_insert_with(BlockUsageStats::default);
-            
+            if status.bad_block {
+                if self.block_stats.remove(&block_id).is_some() {
+                    self.stats.blocks_retired += 1;
+                }
+                continue;
+            }
+
+            let write_frequency = self.calculate_write_frequency(block_id, status.erase_count);
+            let temperature = self.determine_temperature(write_frequency);
+            let stats = self.block_stats.entry(block_id).or_default();
+            if status.erase_count != stats.erase_count {
+                stats.last_write = self.clock;
+            }
             stats.erase_count = status.erase_count;
-            stats.write_frequency = self.calculate_write_frequency(block_id);
-            stats.temperature = self.determine_temperature(stats.write_frequency);
+            stats.write_frequency = write_frequency;
+            stats.temperature = temperature;
         }
 
         // Update rankings
@@ -88,28 +113,42 @@ impl WearLeveler {
         }
     }
 
-    fn perform_wear_leveling(&mut self, nand: &mut NANDFlash) -> StorageResult<()> {
+    fn max_erase_count(&self) -> Option<u32> {
+        self.block_stats.values().map(|stats| stats.erase_count).max()
+    }
+
+    fn min_erase_count(&self) -> Option<u32> {
+        self.block_stats.values().map(|stats| stats.erase_count).min()
+    }
+
+    fn perform_wear_leveling(&mut self, nand: &mut NANDFlash) -> StorageResult<Vec<Relocation>> {
         // Find hot blocks with high erase counts
-        let hot_blocks: Vec<_> = self.hot_data.iter()
+        let hot_blocks: Vec<usize> = self.hot_data.clone().into_sorted_vec().iter().rev()
             .take(self.config.migration_batch_size)
+            .map(|ranking| ranking.block_id)
             .collect();
 
         // Find cold blocks with low erase counts
-        let cold_blocks: Vec<_> = self.cold_data.iter()
+        let cold_blocks: Vec<usize> = self.cold_data.clone().into_sorted_vec().iter()
             .take(self.config.migration_batch_size)
+            .map(|ranking| ranking.block_id)
             .collect();
 
         // Perform hot-cold data swaps
-        for (hot, cold) in hot_blocks.iter().zip(cold_blocks.iter()) {
-            self.swap_blocks(nand, hot.block_id, cold.block_id)?;
+        let mut relocations = Vec::new();
+        for (hot, cold) in hot_blocks.into_iter().zip(cold_blocks) {
+            relocations.extend(self.swap_blocks(nand, hot, cold)?);
             self.stats.hot_cold_swaps += 1;
         }
 
         self.stats.migrations += 1;
-        Ok(())
+        self.stats.wear_delta = self.max_erase_count().unwrap_or(0) - self.min_erase_count().unwrap_or(0);
+        Ok(relocations)
     }
 
-    fn swap_blocks(&mut self, nand: &mut NANDFlash, hot_id: usize, cold_id: usize) -> StorageResult<()> {
+    // Cold data moves onto the worn block, which it will rarely rewrite,
+    // and hot data onto the fresh one
+    fn swap_blocks(&mut self, nand: &mut NANDFlash, hot_id: usize, cold_id: usize) -> StorageResult<Vec<Relocation>> {
         // Read hot block data
         let hot_data = self.read_block_data(nand, hot_id)?;
         
@@ -121,22 +160,57 @@ impl WearLeveler {
         nand.erase_block(cold_id)?;
 
         // Write data to swapped locations
-        self.write_block_data(nand, cold_id, &hot_data)?;
-        self.write_block_data(nand, hot_id, &cold_data)?;
+        let mut relocations = self.write_block_data(nand, hot_id, cold_id, &hot_data)?;
+        relocations.extend(self.write_block_data(nand, cold_id, hot_id, &cold_data)?);
 
         // Update mappings and stats
-        self.update_after_swap(hot_id, cold_id);
+        self.update_after_swap(nand, hot_id, cold_id);
 
-        Ok(())
+        Ok(relocations)
+    }
+
+    fn read_block_data(&self, nand: &mut NANDFlash, block_id: usize) -> StorageResult<Vec<(usize, Vec<u8>)>> {
+        let mut data = Vec::new();
+        for page in 0..nand.get_pages_per_block() {
+            if nand.is_page_valid(block_id, page)? {
+                data.push((page, nand.read_page(block_id, page)?));
+            }
+        }
+        Ok(data)
     }
 
-    fn calculate_write_frequency(&self, block_id: usize) -> f32 {
+    // Pages keep their offset within the block
+    fn write_block_data(&self, nand: &mut NANDFlash, from: usize, to: usize, data: &[(usize, Vec<u8>)]) -> StorageResult<Vec<Relocation>> {
+        let mut relocations = Vec::with_capacity(data.len());
+        for (page, bytes) in data {
+            nand.write_page(to, *page, bytes)?;
+            relocations.push(Relocation {
+                from: PageAddress { block: from, page: *page },
+                to: PageAddress { block: to, page: *page },
+            });
+        }
+        Ok(relocations)
+    }
+
+    fn update_after_swap(&mut self, nand: &NANDFlash, hot_id: usize, cold_id: usize) {
+        for block_id in [hot_id, cold_id] {
+            if let (Some(stats), Ok(status)) = (self.block_stats.get_mut(&block_id), nand.get_block_status(block_id)) {
+                stats.erase_count = status.erase_count;
+                stats.last_write = self.clock;
+                stats.write_frequency = 0.0;
+                stats.temperature = BlockTemperature::Cold;
+            }
+        }
+        self.update_rankings();
+    }
+
+    // Erases per check since the block was last erased before this check
+    fn calculate_write_frequency(&self, block_id: usize, erase_count: u32) -> f32 {
         if let Some(stats) = self.block_stats.get(&block_id) {
             // Calculate write frequency based on history
-            let current_time = self.get_current_time();
-            let time_delta = current_time - stats.last_write;
+            let time_delta = self.clock - stats.last_write;
             if time_delta > 0 {
-                stats.erase_count as f32 / time_delta as f32
+                erase_count as f32 / time_delta as f32
             } else {
                 0.0
             }
@@ -160,7 +234,7 @@ impl WearLeveler {
         self.cold_data.clear();
 
         for (&block_id, stats) in &self.block_stats {
-            let score = self.calculate_block_score(stats);
+            let score = Self::calculate_block_score(stats);
             let ranking = BlockRanking { block_id, score };
 
             match stats.temperature {
@@ -171,7 +245,7 @@ impl WearLeveler {
         }
     }
 
-    fn calculate_block_score(&self, stats: &BlockUsageStats) -> i32 {
+    fn calculate_block_score(stats: &BlockUsageStats) -> i32 {
         // Score based on erase count and write frequency
         (stats.erase_count as f32 * 100.0 + stats.write_frequency * 50.0) as i32
     }
@@ -188,3 +262,14 @@ impl Ord for BlockRanking {
         self.score.cmp(&other.score)
     }
 }
+
+impl Default for WearConfig {
+    fn default() -> Self {
+        Self {
+            wear_threshold: 100,
+            temperature_threshold: 0.5,
+            migration_batch_size: 2,
+            leveling_interval: 16,
+        }
+    }
+}
@@ -1,4 +1,10 @@
+// Hardware names keep their acronyms (ALU, TLB, PCI), as the datasheets spell them
+#![allow(clippy::upper_case_acronyms)]
+
 pub mod hardware;
+pub mod simulation;
+#[path = "software/os/mod.rs"]
 pub mod os;
+#[path = "software/apps/mod.rs"]
 pub mod apps;
-pub mod visualization;
\ No newline at end of file
+pub mod machine;
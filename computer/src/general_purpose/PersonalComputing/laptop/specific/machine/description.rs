@@ -0,0 +1,489 @@
+use super::error::{MachineError, MachineResult};
+use super::toml::Document;
+use crate::hardware::cpu::cache_controller::CacheGeometry;
+use crate::hardware::memory::dram::BankTimings;
+use crate::hardware::storage::filesystem::FileSystemKind;
+
+const LINE_SIZE: usize = 64;
+
+// A laptop model as data. Machine files fill one of these in, validation
+// checks it against what the hardware model can build, and the builder
+// turns it into a Hardware.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachineDescription {
+    pub name: String,
+    pub cpu: CpuDescription,
+    pub memory: MemoryDescription,
+    pub storage: StorageDescription,
+    pub gpu: GpuDescription,
+    pub battery: BatteryDescription,
+    pub display: DisplayDescription,
+    pub ports: Vec<Port>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuDescription {
+    pub cores: usize,
+    pub base_frequency_mhz: u64, // What the cores boot at
+    pub max_frequency_mhz: u64,  // Top of the DVFS table
+    pub l1: CacheDescription,
+    pub l2: CacheDescription,
+    pub l3: CacheDescription,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheDescription {
+    pub size_kb: usize,
+    pub ways: usize,
+    pub latency_cycles: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryDescription {
+    pub dram_type: DramType,
+    pub capacity_gb: u64,
+    pub data_rate_mts: u64, // Transfers per second; the memory clock is half this
+    pub ranks: usize,
+    pub banks_per_rank: usize,
+    pub timings: BankTimings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DramType {
+    DDR4,
+    DDR5,
+    LPDDR4X,
+    LPDDR5,
+    LPDDR5X,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageDescription {
+    pub kind: StorageKind,
+    pub capacity_gb: u64,
+    pub filesystem: FileSystemKind, // What the OS mounts as its root volume
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageKind {
+    NVMe,
+    SataSsd,
+    EMMC,
+    HDD,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpuDescription {
+    pub shader_cores: u32,
+    pub ray_cores: u32,
+    pub tensor_cores: u32,
+    pub frequency_mhz: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatteryDescription {
+    pub capacity_wh: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayDescription {
+    pub width: u32,
+    pub height: u32,
+    pub refresh_hz: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    UsbA,
+    UsbC,
+    Thunderbolt,
+    HDMI,
+    Headphone,
+    MagSafe,
+    Ethernet,
+    SdCard,
+}
+
+impl MachineDescription {
+    // Parses and validates a machine file. Unknown keys are errors, so a
+    // typo can't silently fall back to a default.
+    pub fn from_toml(source: &str) -> MachineResult<Self> {
+        let mut doc = Document::parse(source)?;
+        let description = Self::read(&mut doc)?;
+        doc.finish()?;
+        description.validate()?;
+        Ok(description)
+    }
+
+    // Limits of the hardware model, and of physics where the model has none
+    pub fn validate(&self) -> MachineResult<()> {
+        if self.name.trim().is_empty() {
+            return Err(MachineError::invalid("name", "must not be empty"));
+        }
+
+        let cpu = &self.cpu;
+        check_range("cpu.cores", cpu.cores as u64, 1, 64)?;
+        check_range("cpu.base_frequency_mhz", cpu.base_frequency_mhz, 100, 6000)?;
+        check_range("cpu.max_frequency_mhz", cpu.max_frequency_mhz, cpu.base_frequency_mhz, 6000)?;
+        cpu.l1.validate("cpu.l1")?;
+        cpu.l2.validate("cpu.l2")?;
+        cpu.l3.validate("cpu.l3")?;
+        if cpu.l2.size_kb < cpu.l1.size_kb {
+            return Err(MachineError::invalid("cpu.l2.size_kb", "must be at least the size of L1"));
+        }
+        if cpu.l3.size_kb < cpu.l2.size_kb {
+            return Err(MachineError::invalid("cpu.l3.size_kb", "must be at least the size of L2"));
+        }
+
+        let memory = &self.memory;
+        let (slowest, fastest) = memory.dram_type.data_rates();
+        if memory.data_rate_mts < slowest || memory.data_rate_mts > fastest {
+            return Err(MachineError::invalid(
+                "memory.data_rate_mts",
+                format!("{} runs at {} to {} MT/s", memory.dram_type.name(), slowest, fastest),
+            ));
+        }
+        check_range("memory.capacity_gb", memory.capacity_gb, 1, 256)?;
+        check_power_of_two("memory.ranks", memory.ranks, 8)?;
+        check_power_of_two("memory.banks_per_rank", memory.banks_per_rank, 32)?;
+        check_range("memory.timings.cl", memory.timings.cl, 1, 100)?;
+        check_range("memory.timings.trcd", memory.timings.trcd, 1, 100)?;
+        check_range("memory.timings.trp", memory.timings.trp, 1, 100)?;
+
+        check_range("storage.capacity_gb", self.storage.capacity_gb, 1, 16 * 1024)?;
+
+        let gpu = &self.gpu;
+        check_range("gpu.shader_cores", gpu.shader_cores as u64, 1, 1024)?;
+        check_range("gpu.ray_cores", gpu.ray_cores as u64, 0, 256)?;
+        check_range("gpu.tensor_cores", gpu.tensor_cores as u64, 0, 1024)?;
+        check_range("gpu.frequency_mhz", gpu.frequency_mhz, 100, 4000)?;
+
+        // Airlines cap laptop batteries at 100 Wh, so no laptop ships larger
+        let capacity = self.battery.capacity_wh;
+        if !(capacity > 0.0 && capacity <= 100.0) {
+            return Err(MachineError::invalid("battery.capacity_wh", "must be above 0 and at most 100 Wh"));
+        }
+
+        check_range("display.width", self.display.width as u64, 320, 8192)?;
+        check_range("display.height", self.display.height as u64, 200, 8192)?;
+        check_range("display.refresh_hz", self.display.refresh_hz as u64, 24, 500)?;
+        Ok(())
+    }
+
+    // Helper methods
+    fn read(doc: &mut Document) -> MachineResult<Self> {
+        Ok(Self {
+            name: doc.string("name")?,
+            cpu: CpuDescription {
+                cores: count(doc, "cpu.cores")?,
+                base_frequency_mhz: unsigned(doc, "cpu.base_frequency_mhz")?,
+                max_frequency_mhz: unsigned(doc, "cpu.max_frequency_mhz")?,
+                l1: CacheDescription::read(doc, "cpu.l1")?,
+                l2: CacheDescription::read(doc, "cpu.l2")?,
+                l3: CacheDescription::read(doc, "cpu.l3")?,
+            },
+            memory: MemoryDescription {
+                dram_type: parse_name(doc, "memory.type", DramType::from_name)?,
+                capacity_gb: unsigned(doc, "memory.capacity_gb")?,
+                data_rate_mts: unsigned(doc, "memory.data_rate_mts")?,
+                ranks: count(doc, "memory.ranks")?,
+                banks_per_rank: count(doc, "memory.banks_per_rank")?,
+                timings: BankTimings {
+                    cl: unsigned(doc, "memory.timings.cl")?,
+                    trcd: unsigned(doc, "memory.timings.trcd")?,
+                    trp: unsigned(doc, "memory.timings.trp")?,
+                },
+            },
+            storage: StorageDescription {
+                kind: parse_name(doc, "storage.type", StorageKind::from_name)?,
+                capacity_gb: unsigned(doc, "storage.capacity_gb")?,
+                filesystem: parse_name(doc, "storage.filesystem", FileSystemKind::from_name)?,
+            },
+            gpu: GpuDescription {
+                shader_cores: narrow(doc, "gpu.shader_cores")?,
+                ray_cores: narrow(doc, "gpu.ray_cores")?,
+                tensor_cores: narrow(doc, "gpu.tensor_cores")?,
+                frequency_mhz: unsigned(doc, "gpu.frequency_mhz")?,
+            },
+            battery: BatteryDescription {
+                capacity_wh: doc.float("battery.capacity_wh")?,
+            },
+            display: DisplayDescription {
+                width: narrow(doc, "display.width")?,
+                height: narrow(doc, "display.height")?,
+                refresh_hz: narrow(doc, "display.refresh_hz")?,
+            },
+            ports: doc
+                .strings("io.ports")?
+                .iter()
+                .map(|name| Port::from_name(name).ok_or_else(|| unknown_name("io.ports", name)))
+                .collect::<MachineResult<_>>()?,
+        })
+    }
+}
+
+impl CacheDescription {
+    pub fn geometry(&self) -> CacheGeometry {
+        CacheGeometry {
+            size: self.size_kb * 1024,
+            ways: self.ways,
+            latency: self.latency_cycles,
+        }
+    }
+
+    fn read(doc: &mut Document, table: &str) -> MachineResult<Self> {
+        Ok(Self {
+            size_kb: count(doc, &format!("{}.size_kb", table))?,
+            ways: count(doc, &format!("{}.ways", table))?,
+            latency_cycles: unsigned(doc, &format!("{}.latency_cycles", table))?,
+        })
+    }
+
+    // The cache indexes sets with address bits, so the set count has to be
+    // a whole power of two
+    fn validate(&self, table: &str) -> MachineResult<()> {
+        check_range(&format!("{}.ways", table), self.ways as u64, 1, 32)?;
+        check_range(&format!("{}.latency_cycles", table), self.latency_cycles, 1, 200)?;
+        let bytes = self
+            .size_kb
+            .checked_mul(1024)
+            .ok_or_else(|| MachineError::invalid(&format!("{}.size_kb", table), "is too large to address"))?;
+        let set_bytes = self.ways * LINE_SIZE;
+        if bytes == 0 || !bytes.is_multiple_of(set_bytes) || !(bytes / set_bytes).is_power_of_two() {
+            return Err(MachineError::invalid(
+                &format!("{}.size_kb", table),
+                format!("{} KB in {} ways of 64-byte lines doesn't give a power-of-two set count", self.size_kb, self.ways),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl DramType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "DDR4" => Some(DramType::DDR4),
+            "DDR5" => Some(DramType::DDR5),
+            "LPDDR4X" => Some(DramType::LPDDR4X),
+            "LPDDR5" => Some(DramType::LPDDR5),
+            "LPDDR5X" => Some(DramType::LPDDR5X),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DramType::DDR4 => "DDR4",
+            DramType::DDR5 => "DDR5",
+            DramType::LPDDR4X => "LPDDR4X",
+            DramType::LPDDR5 => "LPDDR5",
+            DramType::LPDDR5X => "LPDDR5X",
+        }
+    }
+
+    // JEDEC speed grades, slowest and fastest, in MT/s
+    pub fn data_rates(&self) -> (u64, u64) {
+        match self {
+            DramType::DDR4 => (1600, 3200),
+            DramType::DDR5 => (4000, 8400),
+            DramType::LPDDR4X => (3200, 4266),
+            DramType::LPDDR5 => (5500, 6400),
+            DramType::LPDDR5X => (6400, 8533),
+        }
+    }
+}
+
+impl StorageKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "NVMe" => Some(StorageKind::NVMe),
+            "SATA SSD" => Some(StorageKind::SataSsd),
+            "eMMC" => Some(StorageKind::EMMC),
+            "HDD" => Some(StorageKind::HDD),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StorageKind::NVMe => "NVMe",
+            StorageKind::SataSsd => "SATA SSD",
+            StorageKind::EMMC => "eMMC",
+            StorageKind::HDD => "HDD",
+        }
+    }
+}
+
+impl Port {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "USB-A" => Some(Port::UsbA),
+            "USB-C" => Some(Port::UsbC),
+            "Thunderbolt" => Some(Port::Thunderbolt),
+            "HDMI" => Some(Port::HDMI),
+            "Headphone" => Some(Port::Headphone),
+            "MagSafe" => Some(Port::MagSafe),
+            "Ethernet" => Some(Port::Ethernet),
+            "SD card" => Some(Port::SdCard),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Port::UsbA => "USB-A",
+            Port::UsbC => "USB-C",
+            Port::Thunderbolt => "Thunderbolt",
+            Port::HDMI => "HDMI",
+            Port::Headphone => "Headphone",
+            Port::MagSafe => "MagSafe",
+            Port::Ethernet => "Ethernet",
+            Port::SdCard => "SD card",
+        }
+    }
+}
+
+// The machine Hardware::new() builds when no description is given
+impl Default for MachineDescription {
+    fn default() -> Self {
+        Self {
+            name: "Generic laptop".to_string(),
+            cpu: CpuDescription {
+                cores: 1,
+                base_frequency_mhz: 2400,
+                max_frequency_mhz: 3600,
+                l1: CacheDescription { size_kb: 32, ways: 8, latency_cycles: 4 },
+                l2: CacheDescription { size_kb: 256, ways: 8, latency_cycles: 12 },
+                l3: CacheDescription { size_kb: 2048, ways: 16, latency_cycles: 40 },
+            },
+            memory: MemoryDescription {
+                dram_type: DramType::DDR4,
+                capacity_gb: 16,
+                data_rate_mts: 3200,
+                ranks: 4,
+                banks_per_rank: 8,
+                timings: BankTimings::default(),
+            },
+            storage: StorageDescription {
+                kind: StorageKind::NVMe,
+                capacity_gb: 512,
+                filesystem: FileSystemKind::Ext4,
+            },
+            gpu: GpuDescription {
+                shader_cores: 32,
+                ray_cores: 4,
+                tensor_cores: 8,
+                frequency_mhz: 1200,
+            },
+            battery: BatteryDescription { capacity_wh: 56.0 },
+            display: DisplayDescription { width: 1920, height: 1080, refresh_hz: 60 },
+            ports: vec![Port::UsbC, Port::UsbC, Port::UsbA, Port::HDMI, Port::Headphone],
+        }
+    }
+}
+
+// Helper functions
+fn unsigned(doc: &mut Document, key: &str) -> MachineResult<u64> {
+    let value = doc.integer(key)?;
+    u64::try_from(value).map_err(|_| MachineError::invalid(key, "must not be negative"))
+}
+
+fn count(doc: &mut Document, key: &str) -> MachineResult<usize> {
+    unsigned(doc, key).map(|value| value as usize)
+}
+
+// Saturates, so an oversized value still fails its range check
+fn narrow(doc: &mut Document, key: &str) -> MachineResult<u32> {
+    unsigned(doc, key).map(|value| value.min(u32::MAX as u64) as u32)
+}
+
+fn parse_name<T>(doc: &mut Document, key: &str, from_name: fn(&str) -> Option<T>) -> MachineResult<T> {
+    let name = doc.string(key)?;
+    from_name(&name).ok_or_else(|| unknown_name(key, &name))
+}
+
+fn unknown_name(key: &str, name: &str) -> MachineError {
+    MachineError::invalid(key, format!("`{}` is not a known name", name))
+}
+
+fn check_range(key: &str, value: u64, min: u64, max: u64) -> MachineResult<()> {
+    if value < min || value > max {
+        return Err(MachineError::invalid(key, format!("{} is outside {}..={}", value, min, max)));
+    }
+    Ok(())
+}
+
+fn check_power_of_two(key: &str, value: usize, max: usize) -> MachineResult<()> {
+    if !value.is_power_of_two() || value > max {
+        return Err(MachineError::invalid(key, format!("must be a power of two up to {}", max)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MACBOOK_AIR: &str = include_str!("../machines/macbook_air.toml");
+
+    fn invalid_key<T>(result: MachineResult<T>) -> Option<String> {
+        match result {
+            Err(MachineError::Invalid { key, .. }) => Some(key),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn the_default_machine_is_valid() {
+        assert_eq!(MachineDescription::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn schema_errors_name_the_key() {
+        let wrong_type = MACBOOK_AIR.replace("cores = 8", "cores = \"8\"");
+        assert_eq!(
+            MachineDescription::from_toml(&wrong_type),
+            Err(MachineError::WrongType { key: "cpu.cores".to_string(), expected: "an integer" })
+        );
+
+        let missing = MACBOOK_AIR.replace("refresh_hz = 60", "");
+        assert_eq!(MachineDescription::from_toml(&missing), Err(MachineError::MissingKey("display.refresh_hz".to_string())));
+
+        let typo = MACBOOK_AIR.replace("[battery]\ncapacity_wh = 52.6", "[battery]\ncapacity_wh = 52.6\ncapacity_mwh = 52600");
+        assert!(matches!(
+            MachineDescription::from_toml(&typo),
+            Err(MachineError::UnknownKey { key, .. }) if key == "battery.capacity_mwh"
+        ));
+
+        let negative = MACBOOK_AIR.replace("capacity_gb = 512", "capacity_gb = -512");
+        assert_eq!(invalid_key(MachineDescription::from_toml(&negative)).as_deref(), Some("storage.capacity_gb"));
+
+        let port = MACBOOK_AIR.replace("\"MagSafe\"", "\"FireWire\"");
+        assert_eq!(invalid_key(MachineDescription::from_toml(&port)).as_deref(), Some("io.ports"));
+    }
+
+    #[test]
+    fn caches_need_an_addressable_power_of_two_set_count() {
+        let mut description = MachineDescription::default();
+        description.cpu.l1 = CacheDescription { size_kb: 48, ways: 8, latency_cycles: 4 };
+        assert_eq!(invalid_key(description.validate()).as_deref(), Some("cpu.l1.size_kb"));
+
+        description.cpu.l1.size_kb = usize::MAX;
+        assert_eq!(invalid_key(description.validate()).as_deref(), Some("cpu.l1.size_kb"));
+
+        let mut description = MachineDescription::default();
+        description.cpu.l3.size_kb = 128;
+        assert_eq!(invalid_key(description.validate()).as_deref(), Some("cpu.l3.size_kb"));
+    }
+
+    #[test]
+    fn memory_must_run_at_a_speed_its_type_supports() {
+        let mut description = MachineDescription::default();
+        description.memory.data_rate_mts = 6400; // DDR4 tops out at 3200
+        assert_eq!(invalid_key(description.validate()).as_deref(), Some("memory.data_rate_mts"));
+
+        let mut description = MachineDescription::default();
+        description.memory.banks_per_rank = 6;
+        assert_eq!(invalid_key(description.validate()).as_deref(), Some("memory.banks_per_rank"));
+    }
+}
@@ -0,0 +1,33 @@
+use crate::hardware::bus::BusError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MachineError {
+    // Reading the file
+    Io(String),
+    Syntax { line: usize, message: String },
+    DuplicateKey { line: usize, key: String },
+
+    // Matching it against the schema, with the full dotted key
+    MissingKey(String),
+    UnknownKey { line: usize, key: String }, // Usually a typo
+    WrongType { key: String, expected: &'static str },
+
+    // A well-formed value the hardware model can't be built with
+    Invalid { key: String, reason: String },
+    UnknownPreset(String),
+
+    // Putting it together: device register windows that collided once
+    // firmware had placed the PCI BARs
+    Bus(BusError),
+}
+
+pub type MachineResult<T> = Result<T, MachineError>;
+
+impl MachineError {
+    pub fn invalid(key: &str, reason: impl Into<String>) -> Self {
+        MachineError::Invalid {
+            key: key.to_string(),
+            reason: reason.into(),
+        }
+    }
+}
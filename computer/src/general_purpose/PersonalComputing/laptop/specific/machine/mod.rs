@@ -0,0 +1,191 @@
+pub mod description;
+pub mod error;
+pub mod toml;
+
+use self::description::MachineDescription;
+use self::error::{MachineError, MachineResult};
+use crate::hardware::clock::dvfs::OppTable;
+use crate::hardware::clock::{ClockDomain, ClockError, ClockGenerator};
+use crate::hardware::Hardware;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MHZ: u64 = 1_000_000;
+
+// Machine files shipped with the simulator, by the name the menu uses
+const PRESETS: [(&str, &str); 3] = [
+    ("macbook_air", include_str!("../machines/macbook_air.toml")),
+    ("windows11", include_str!("../machines/windows11.toml")),
+    ("linux_laptop", include_str!("../machines/linux_laptop.toml")),
+];
+
+// Turns a machine description into a running hardware model. The
+// description's own checks run first, then the ones that depend on what the
+// clock tree and DVFS tables can actually do.
+pub struct MachineBuilder {
+    description: MachineDescription,
+}
+
+impl MachineBuilder {
+    pub fn new(description: MachineDescription) -> Self {
+        Self { description }
+    }
+
+    pub fn from_toml(source: &str) -> MachineResult<Self> {
+        MachineDescription::from_toml(source).map(Self::new)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> MachineResult<Self> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .map_err(|err| MachineError::Io(format!("{}: {}", path.display(), err)))?;
+        Self::from_toml(&source)
+    }
+
+    pub fn preset(name: &str) -> MachineResult<Self> {
+        let (_, source) = PRESETS
+            .iter()
+            .find(|(preset, _)| *preset == name)
+            .ok_or_else(|| MachineError::UnknownPreset(name.to_string()))?;
+        Self::from_toml(source)
+    }
+
+    pub fn preset_names() -> impl Iterator<Item = &'static str> {
+        PRESETS.iter().map(|(name, _)| *name)
+    }
+
+    // Every *.toml in a directory, each with its own result so one broken
+    // file doesn't hide the others
+    pub fn load_directory(dir: impl AsRef<Path>) -> MachineResult<Vec<(PathBuf, MachineResult<Self>)>> {
+        let dir = dir.as_ref();
+        let entries = fs::read_dir(dir)
+            .map_err(|err| MachineError::Io(format!("{}: {}", dir.display(), err)))?;
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .collect();
+        paths.sort();
+        Ok(paths
+            .into_iter()
+            .map(|path| {
+                let builder = Self::from_file(&path);
+                (path, builder)
+            })
+            .collect())
+    }
+
+    pub fn build(self) -> MachineResult<Hardware> {
+        self.description.validate()?;
+        self.check_clocks()?;
+        Hardware::with_description(self.description)
+    }
+
+    pub fn description(&self) -> &MachineDescription {
+        &self.description
+    }
+
+    pub fn description_mut(&mut self) -> &mut MachineDescription {
+        &mut self.description
+    }
+
+    // Helper methods
+    // Each domain's PLL has to reach its frequency, and the DVFS tables need
+    // at least one operating point at or below each part's maximum
+    fn check_clocks(&self) -> MachineResult<()> {
+        let description = &self.description;
+        let mut clock = ClockGenerator::default();
+        for (domain, key, frequency) in [
+            (ClockDomain::CPU, "cpu.base_frequency_mhz", description.cpu.base_frequency_mhz * MHZ),
+            (ClockDomain::CPU, "cpu.max_frequency_mhz", description.cpu.max_frequency_mhz * MHZ),
+            (ClockDomain::GPU, "gpu.frequency_mhz", description.gpu.frequency_mhz * MHZ),
+            (ClockDomain::Memory, "memory.data_rate_mts", description.memory.data_rate_mts * MHZ / 2),
+        ] {
+            if let Err(ClockError::Unreachable(_)) = clock.set_frequency(domain, frequency) {
+                return Err(MachineError::invalid(key, "no PLL setting reaches this frequency"));
+            }
+        }
+
+        for (key, table, frequency) in [
+            ("cpu.max_frequency_mhz", OppTable::laptop_cpu(), description.cpu.max_frequency_mhz),
+            ("gpu.frequency_mhz", OppTable::laptop_gpu(), description.gpu.frequency_mhz),
+        ] {
+            let lowest = table.min().frequency / MHZ;
+            if frequency < lowest {
+                return Err(MachineError::invalid(key, format!("must be at least the lowest operating point, {} MHz", lowest)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid_key(result: MachineResult<Hardware>) -> Option<String> {
+        match result {
+            Err(MachineError::Invalid { key, .. }) => Some(key),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn every_preset_builds_the_machine_it_describes() {
+        for name in MachineBuilder::preset_names() {
+            let builder = MachineBuilder::preset(name).unwrap();
+            let description = builder.description().clone();
+            let hardware = builder.build().unwrap_or_else(|err| panic!("{}: {:?}", name, err));
+
+            assert_eq!(hardware.battery().capacity_wh(), description.battery.capacity_wh);
+            let storage_bytes = description.storage.capacity_gb << 30;
+            assert_eq!(hardware.storage().capacity_bytes(), storage_bytes, "{}", name);
+            assert_eq!(hardware.storage().filesystem(), description.storage.filesystem);
+            let ports: Vec<_> = hardware.io().ports().iter().map(|slot| slot.kind).collect();
+            assert_eq!(ports, description.ports);
+        }
+        assert_eq!(MachineBuilder::preset("typewriter").err(), Some(MachineError::UnknownPreset("typewriter".to_string())));
+    }
+
+    #[test]
+    fn build_revalidates_edited_descriptions() {
+        let mut builder = MachineBuilder::preset("linux_laptop").unwrap();
+        builder.description_mut().cpu.cores = 0;
+        assert_eq!(invalid_key(builder.build()).as_deref(), Some("cpu.cores"));
+
+        let mut builder = MachineBuilder::preset("linux_laptop").unwrap();
+        builder.description_mut().cpu.l1.size_kb = usize::MAX;
+        assert_eq!(invalid_key(builder.build()).as_deref(), Some("cpu.l1.size_kb"));
+
+        let mut builder = MachineBuilder::preset("linux_laptop").unwrap();
+        builder.description_mut().battery.capacity_wh = 120.0;
+        assert_eq!(invalid_key(builder.build()).as_deref(), Some("battery.capacity_wh"));
+    }
+
+    #[test]
+    fn build_checks_what_the_clocks_and_dvfs_tables_can_do() {
+        // In the description's range, but past what the CPU PLL can lock to
+        let mut builder = MachineBuilder::preset("windows11").unwrap();
+        builder.description_mut().cpu.max_frequency_mhz = 5000;
+        assert_eq!(invalid_key(builder.build()).as_deref(), Some("cpu.max_frequency_mhz"));
+
+        // Below the GPU's lowest operating point
+        let mut builder = MachineBuilder::preset("windows11").unwrap();
+        builder.description_mut().gpu.frequency_mhz = 200;
+        assert_eq!(invalid_key(builder.build()).as_deref(), Some("gpu.frequency_mhz"));
+    }
+
+    #[test]
+    fn a_broken_file_in_a_directory_does_not_hide_the_others() {
+        let dir = std::env::temp_dir().join(format!("machines-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("good.toml"), PRESETS[0].1).unwrap();
+        fs::write(dir.join("broken.toml"), PRESETS[0].1.replace("cores = 8", "cores = 0x8")).unwrap();
+        fs::write(dir.join("notes.txt"), "not a machine").unwrap();
+
+        let loaded = MachineBuilder::load_directory(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert!(matches!(loaded[0].1, Err(MachineError::Syntax { .. })));
+        assert!(loaded[1].1.is_ok());
+    }
+}
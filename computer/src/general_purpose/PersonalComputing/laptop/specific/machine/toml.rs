@@ -0,0 +1,427 @@
+use super::error::{MachineError, MachineResult};
+use std::collections::{BTreeMap, HashSet};
+
+// The slice of TOML machine files use, and nothing more:
+//
+// - `#` comments and blank lines
+// - `[table]` and `[dotted.table]` headers, each at most once
+// - bare keys (letters, digits, `_` and `-`), one `key = value` per line
+// - basic "strings" with the \n \t \" \\ escapes
+// - decimal integers and floats, with `_` only between digits
+// - true and false
+// - arrays of the above, which may run over several lines
+//
+// Anything else TOML allows is rejected with a syntax error rather than
+// misread: quoted or dotted keys, inline tables, arrays of tables, literal
+// and multi-line strings, hex, octal and binary integers, inf and nan, and
+// dates.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub value: Value,
+    pub line: usize,
+}
+
+// Every key in a file under its full dotted name. The schema takes the keys
+// it knows; whatever is left over is reported as unknown.
+pub struct Document {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Document {
+    pub fn parse(source: &str) -> MachineResult<Self> {
+        let mut entries = BTreeMap::new();
+        let mut tables = HashSet::new();
+        let mut table = String::new();
+        let mut lines = source.lines().enumerate();
+
+        while let Some((index, raw)) = lines.next() {
+            let line = index + 1;
+            let text = strip_comment(raw).trim();
+            if text.is_empty() {
+                continue;
+            }
+
+            if text.starts_with("[[") {
+                return Err(syntax(line, "arrays of tables aren't supported"));
+            }
+            if let Some(header) = text.strip_prefix('[').filter(|_| !text.contains('=')) {
+                let name = header
+                    .strip_suffix(']')
+                    .ok_or_else(|| syntax(line, "table header is missing its closing `]`"))?
+                    .trim();
+                if !name.split('.').all(is_bare_key) {
+                    return Err(syntax(line, format!("`{}` is not a valid table name", name)));
+                }
+                if !tables.insert(name.to_string()) {
+                    return Err(MachineError::DuplicateKey { line, key: name.to_string() });
+                }
+                table = name.to_string();
+                continue;
+            }
+
+            let (key, value) = text
+                .split_once('=')
+                .ok_or_else(|| syntax(line, "expected `key = value` or a [table] header"))?;
+            let key = key.trim();
+            if key.starts_with(['"', '\'']) {
+                return Err(syntax(line, format!("quoted key {} isn't supported; use a bare key", key)));
+            }
+            if key.contains('.') {
+                return Err(syntax(line, format!("dotted key `{}` isn't supported; put it under a [table]", key)));
+            }
+            if !is_bare_key(key) {
+                return Err(syntax(line, format!("`{}` is not a valid key", key)));
+            }
+
+            // Arrays may run over several lines
+            let mut value = value.trim().to_string();
+            while value.starts_with('[') && bracket_depth(&value) > 0 {
+                let (_, next) = lines
+                    .next()
+                    .ok_or_else(|| syntax(line, "array is missing its closing `]`"))?;
+                value.push(' ');
+                value.push_str(strip_comment(next).trim());
+            }
+
+            let value = parse_value(&value).map_err(|message| syntax(line, message))?;
+            let key = if table.is_empty() { key.to_string() } else { format!("{}.{}", table, key) };
+            if entries.contains_key(&key) {
+                return Err(MachineError::DuplicateKey { line, key });
+            }
+            entries.insert(key, Entry { value, line });
+        }
+
+        Ok(Self { entries })
+    }
+
+    pub fn take(&mut self, key: &str) -> Option<Entry> {
+        self.entries.remove(key)
+    }
+
+    pub fn integer(&mut self, key: &str) -> MachineResult<i64> {
+        match self.require(key)? {
+            Value::Integer(value) => Ok(value),
+            _ => Err(wrong_type(key, "an integer")),
+        }
+    }
+
+    // Floats accept integers too, so `capacity_wh = 50` reads naturally
+    pub fn float(&mut self, key: &str) -> MachineResult<f64> {
+        match self.require(key)? {
+            Value::Float(value) => Ok(value),
+            Value::Integer(value) => Ok(value as f64),
+            _ => Err(wrong_type(key, "a number")),
+        }
+    }
+
+    pub fn string(&mut self, key: &str) -> MachineResult<String> {
+        match self.require(key)? {
+            Value::String(value) => Ok(value),
+            _ => Err(wrong_type(key, "a string")),
+        }
+    }
+
+    pub fn strings(&mut self, key: &str) -> MachineResult<Vec<String>> {
+        match self.require(key)? {
+            Value::Array(items) => items
+                .into_iter()
+                .map(|item| match item {
+                    Value::String(value) => Ok(value),
+                    _ => Err(wrong_type(key, "an array of strings")),
+                })
+                .collect(),
+            _ => Err(wrong_type(key, "an array of strings")),
+        }
+    }
+
+    // Fails on the first key the schema didn't take, in file order
+    pub fn finish(self) -> MachineResult<()> {
+        match self.entries.into_iter().min_by_key(|(_, entry)| entry.line) {
+            Some((key, entry)) => Err(MachineError::UnknownKey { line: entry.line, key }),
+            None => Ok(()),
+        }
+    }
+
+    // Helper methods
+    fn require(&mut self, key: &str) -> MachineResult<Value> {
+        self.take(key)
+            .map(|entry| entry.value)
+            .ok_or_else(|| MachineError::MissingKey(key.to_string()))
+    }
+}
+
+fn syntax(line: usize, message: impl Into<String>) -> MachineError {
+    MachineError::Syntax { line, message: message.into() }
+}
+
+fn wrong_type(key: &str, expected: &'static str) -> MachineError {
+    MachineError::WrongType { key: key.to_string(), expected }
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// Everything before a `#` that isn't inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+// Unclosed `[` outside strings
+fn bracket_depth(text: &str) -> i32 {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in text.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '[' if !in_string => depth += 1,
+            ']' if !in_string => depth -= 1,
+            _ => {}
+        }
+    }
+    depth
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    let text = text.trim();
+    if text.starts_with("\"\"\"") || text.starts_with('\'') {
+        return Err("only basic \"strings\" are supported".to_string());
+    }
+    if text.starts_with('"') {
+        return parse_string(text).map(Value::String);
+    }
+    if text.starts_with('{') {
+        return Err("inline tables aren't supported; use a [table]".to_string());
+    }
+    if let Some(inner) = text.strip_prefix('[') {
+        let inner = inner
+            .strip_suffix(']')
+            .ok_or_else(|| format!("`{}` has text after the array's closing `]`", text))?;
+        return split_items(inner)
+            .ok_or_else(|| format!("`{}` has an empty item", text))?
+            .into_iter()
+            .map(parse_value)
+            .collect::<Result<_, _>>()
+            .map(Value::Array);
+    }
+    match text {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => {}
+    }
+
+    let unsigned = text.strip_prefix(['+', '-']).unwrap_or(text);
+    if ["0x", "0o", "0b"].iter().any(|prefix| unsigned.starts_with(prefix)) {
+        return Err(format!("`{}`: only decimal integers are supported", text));
+    }
+    match parse_number(text) {
+        Some(Value::Float(value)) if !value.is_finite() => Err(format!("`{}` is out of range for a float", text)),
+        Some(value) => Ok(value),
+        None => Err(format!("`{}` is not a string, number, boolean or array", text)),
+    }
+}
+
+// Decimal only: an optional sign, then digit runs joined by single
+// underscores, with an optional fraction and exponent for floats. No
+// leading zeros, so `010` can't be mistaken for octal.
+fn parse_number(text: &str) -> Option<Value> {
+    let unsigned = text.strip_prefix(['+', '-']).unwrap_or(text);
+    let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (unsigned, None),
+    };
+    let (whole, fraction) = match mantissa.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (mantissa, None),
+    };
+
+    let valid = is_digit_run(whole)
+        && (whole == "0" || !whole.starts_with('0'))
+        && fraction.is_none_or(is_digit_run)
+        && exponent.is_none_or(|exponent| is_digit_run(exponent.strip_prefix(['+', '-']).unwrap_or(exponent)));
+    if !valid {
+        return None;
+    }
+
+    let digits = text.replace('_', "");
+    if fraction.is_some() || exponent.is_some() {
+        digits.parse().map(Value::Float).ok()
+    } else {
+        digits.parse().map(Value::Integer).ok()
+    }
+}
+
+fn is_digit_run(text: &str) -> bool {
+    !text.is_empty()
+        && text.split('_').all(|run| !run.is_empty() && run.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn parse_string(text: &str) -> Result<String, String> {
+    let mut value = String::new();
+    let mut chars = text[1..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                return match chars.as_str().trim() {
+                    "" => Ok(value),
+                    rest => Err(format!("unexpected `{}` after string", rest)),
+                };
+            }
+            '\\' => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('"') => value.push('"'),
+                Some('\\') => value.push('\\'),
+                other => return Err(format!("unsupported escape `\\{}`", other.unwrap_or(' '))),
+            },
+            _ => value.push(c),
+        }
+    }
+    Err("string is missing its closing quote".to_string())
+}
+
+// Splits an array body on top-level commas. One trailing comma is
+// allowed; None if any other item is empty.
+fn split_items(inner: &str) -> Option<Vec<&str>> {
+    let mut items = Vec::new();
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (index, c) in inner.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '[' if !in_string => depth += 1,
+            ']' if !in_string => depth -= 1,
+            ',' if !in_string && depth == 0 => {
+                items.push(&inner[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&inner[start..]);
+    // What follows the last comma, or the whole of `[]`
+    if items.last().is_some_and(|item| item.trim().is_empty()) {
+        items.pop();
+    }
+    if items.iter().any(|item| item.trim().is_empty()) {
+        return None;
+    }
+    Some(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn syntax_line(source: &str) -> Option<usize> {
+        match Document::parse(source) {
+            Err(MachineError::Syntax { line, .. }) => Some(line),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn reads_the_supported_subset() {
+        let source = "
+            name = \"Test \\\"laptop\\\"\" # trailing comment
+            [cpu.l1]
+            size_kb = 1_024
+            scale = -2.5e-1
+            fanless = true
+            ports = [
+                \"USB-C\", # left side
+                \"HDMI\",
+            ]
+        ";
+        let mut doc = Document::parse(source).unwrap();
+        assert_eq!(doc.string("name").unwrap(), "Test \"laptop\"");
+        assert_eq!(doc.integer("cpu.l1.size_kb").unwrap(), 1024);
+        assert_eq!(doc.float("cpu.l1.scale").unwrap(), -0.25);
+        assert_eq!(doc.take("cpu.l1.fanless").unwrap().value, Value::Boolean(true));
+        assert_eq!(doc.strings("cpu.l1.ports").unwrap(), ["USB-C", "HDMI"]);
+        assert!(doc.finish().is_ok());
+
+        let mut doc = Document::parse("empty = [ ]
+nested = [[1, 2,], []]
+tiny = 1e-400").unwrap();
+        assert_eq!(doc.take("empty").unwrap().value, Value::Array(Vec::new()));
+        let pair = Value::Array(vec![Value::Integer(1), Value::Integer(2)]);
+        assert_eq!(doc.take("nested").unwrap().value, Value::Array(vec![pair, Value::Array(Vec::new())]));
+        assert_eq!(doc.float("tiny").unwrap(), 0.0, "underflow is still a finite float");
+    }
+
+    #[test]
+    fn rejects_toml_outside_the_subset() {
+        for source in [
+            "cpu = { cores = 4 }",
+            "\"cores\" = 4",
+            "cpu.cores = 4",
+            "[[port]]",
+            "size = 0x40",
+            "size = 0o17",
+            "size = 0b101",
+            "size = 010",
+            "size = 1__0",
+            "size = _10",
+            "size = 10_",
+            "scale = .5",
+            "scale = 1.",
+            "scale = inf",
+            "scale = nan",
+            "name = 'literal'",
+            "name = \"\"\"multi\"\"\"",
+            "built = 2024-01-01",
+            "ports = [1,,2]",
+            "ports = [,]",
+            "ports = [1,,]",
+            "ports = [1, [], [,]]",
+            "scale = 1e400",
+            "scale = -1e400",
+        ] {
+            assert_eq!(syntax_line(source), Some(1), "{}", source);
+        }
+    }
+
+    #[test]
+    fn reports_duplicates_and_leftover_keys_by_line() {
+        assert_eq!(
+            Document::parse("[cpu]\ncores = 4\ncores = 8").err(),
+            Some(MachineError::DuplicateKey { line: 3, key: "cpu.cores".to_string() })
+        );
+        assert_eq!(
+            Document::parse("[cpu]\n[gpu]\n[cpu]").err(),
+            Some(MachineError::DuplicateKey { line: 3, key: "cpu".to_string() })
+        );
+
+        let mut doc = Document::parse("a = 1\nb = 2\nc = 3").unwrap();
+        doc.take("a");
+        assert_eq!(doc.finish(), Err(MachineError::UnknownKey { line: 2, key: "b".to_string() }));
+    }
+}
@@ -14,9 +14,17 @@
 
 // so it would have a src and visualization folder. we already have the folders in this dir but not working toghther. the src is for the actual source code, for example, in this case, its for figuring out how a speicifc laptop works and displaying it on the application (its software (os, application), hardware (cpu, and so on).) do you get it?
 
+use crate::hardware::gpu::display::Display;
+use crate::hardware::gpu::memory::{VRAMConfig, VRAMController};
 use minifb::{Key, Window, WindowOptions};
 use std::time::Duration;
 
+// The GPU's rendered frame, shown as a window on the software view's desktop
+const SCENE_WIDTH: usize = 320;
+const SCENE_HEIGHT: usize = 240;
+const SCENE_X: usize = 440;
+const SCENE_Y: usize = 120;
+
 pub fn main(laptop_type: &str) {
     let window_title = format!("Specific Laptop Visualization - {}", laptop_type);
     
@@ -35,6 +43,17 @@ pub fn main(laptop_type: &str) {
     let mut power_on = false;
     let mut view_mode = ViewMode::External;
 
+    // Just enough VRAM for the scene's framebuffer
+    let mut vram = VRAMController::with_config(VRAMConfig {
+        total_size: 16 * 1024 * 1024,
+        page_size: 4096,
+        num_banks: 32,
+        bank_width: 256,
+    });
+    let mut display = Display::new(SCENE_WIDTH as u32, SCENE_HEIGHT as u32, &mut vram).unwrap();
+    let mut wireframe = false;
+    let mut frame: u64 = 0;
+
     while window.is_open() && !window.is_key_down(Key::Escape) {
         // Clear the buffer
         for i in buffer.iter_mut() {
@@ -52,7 +71,16 @@ pub fn main(laptop_type: &str) {
             match view_mode {
                 ViewMode::External => draw_laptop_external(&mut buffer, laptop_type),
                 ViewMode::Hardware => draw_laptop_hardware(&mut buffer, laptop_type),
-                ViewMode::Software => draw_laptop_software(&mut buffer, laptop_type),
+                ViewMode::Software => {
+                    draw_laptop_software(&mut buffer, laptop_type);
+                    draw_gpu_scene(&mut buffer, &mut display, frame);
+                }
+            }
+
+            // Toggle the scene between filled and wireframe
+            if window.is_key_pressed(Key::W, minifb::KeyRepeat::No) {
+                wireframe = !wireframe;
+                display.set_wireframe(wireframe);
             }
 
             // Toggle view mode
@@ -69,12 +97,23 @@ pub fn main(laptop_type: &str) {
         let instructions = if !power_on {
             "Press 'P' to power on the laptop"
         } else {
-            "Press 'Tab' to switch views, 'Escape' to exit"
+            "Press 'Tab' to switch views, 'W' for wireframe, 'Escape' to exit"
         };
         draw_text(&mut buffer, instructions, 20, HEIGHT - 30, 0xFFFFFFFF);
 
         // Update the window
         window.update_with_buffer(&buffer, WIDTH, HEIGHT).unwrap();
+        frame += 1;
+    }
+}
+
+// Runs the rasterizer for this frame and copies the result onto the desktop.
+// Time comes from the frame count, so the animation is the same on every run.
+fn draw_gpu_scene(buffer: &mut Vec<u32>, display: &mut Display, frame: u64) {
+    draw_text(buffer, "GPU pipeline - press 'W' for wireframe", SCENE_X, SCENE_Y - 16, 0xFFFFFFFF);
+    match display.render_scene(frame as f32 / 60.0) {
+        Ok(()) => display.blit(buffer, WIDTH, SCENE_X, SCENE_Y),
+        Err(error) => draw_text(buffer, &format!("GPU fault: {:?}", error), SCENE_X, SCENE_Y, 0x00FF4040),
     }
 }
 
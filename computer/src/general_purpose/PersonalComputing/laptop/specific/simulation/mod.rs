@@ -0,0 +1,257 @@
+use self::rng::SimRng;
+use self::time::SimTime;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+
+pub mod rng;
+pub mod time;
+
+const TRACE_LIMIT: usize = 1024;
+
+// An event callback gets the world being simulated and the scheduler, so it
+// can change state and schedule what happens next
+pub type Callback<W> = Box<dyn FnOnce(&mut W, &mut Scheduler<W>)>;
+
+// The discrete-event kernel. Nothing here reads the wall clock: time only
+// moves when the next event is taken, and events due at the same picosecond
+// run in the order they were scheduled, so a run is a pure function of its
+// seed and inputs.
+pub struct Scheduler<W> {
+    now: SimTime,
+    queue: BinaryHeap<Reverse<(SimTime, u64)>>, // Due time, then sequence number
+    events: HashMap<u64, ScheduledEvent<W>>,    // By sequence number; cancelled ones are removed
+    next_sequence: u64,
+    series: HashMap<u64, u64>, // Periodic events by series id, to the occurrence now queued
+    rng: SimRng,
+    seed: u64,
+    stats: SchedulerStats,
+    trace: VecDeque<TraceEntry>,
+}
+
+struct ScheduledEvent<W> {
+    label: &'static str,
+    callback: Callback<W>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EventId(u64);
+
+// One executed event, for lessons that replay what happened when
+#[derive(Debug, Clone, Copy)]
+pub struct TraceEntry {
+    pub time: SimTime,
+    pub label: &'static str,
+    pub id: EventId,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SchedulerStats {
+    pub scheduled: u64,
+    pub executed: u64,
+    pub cancelled: u64,
+    pub max_pending: usize,
+}
+
+impl<W: 'static> Scheduler<W> {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            now: SimTime::ZERO,
+            queue: BinaryHeap::new(),
+            events: HashMap::new(),
+            next_sequence: 0,
+            series: HashMap::new(),
+            rng: SimRng::new(seed),
+            seed,
+            stats: SchedulerStats::default(),
+            trace: VecDeque::new(),
+        }
+    }
+
+    pub fn now(&self) -> SimTime {
+        self.now
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn rng(&mut self) -> &mut SimRng {
+        &mut self.rng
+    }
+
+    // Events can't be scheduled in the past; those are due now
+    pub fn schedule_at(&mut self, time: SimTime, label: &'static str, callback: Callback<W>) -> EventId {
+        let time = time.max(self.now);
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.queue.push(Reverse((time, sequence)));
+        self.events.insert(sequence, ScheduledEvent { label, callback });
+        self.stats.scheduled += 1;
+        self.stats.max_pending = self.stats.max_pending.max(self.events.len());
+        EventId(sequence)
+    }
+
+    pub fn schedule_in(&mut self, delay: SimTime, label: &'static str, callback: Callback<W>) -> EventId {
+        self.schedule_at(self.now + delay, label, callback)
+    }
+
+    // Runs `callback` every `period` for as long as it returns true. The id
+    // names the whole series: cancelling it stops every later occurrence,
+    // even from inside the callback.
+    pub fn schedule_every<F>(&mut self, period: SimTime, label: &'static str, callback: F) -> EventId
+    where
+        F: FnMut(&mut W, &mut Scheduler<W>) -> bool + 'static,
+    {
+        let series = self.next_sequence;
+        self.schedule_occurrence(series, period.max(SimTime(1)), label, callback);
+        EventId(series)
+    }
+
+    pub fn cancel(&mut self, id: EventId) -> bool {
+        // The heap entry stays behind and is skipped when it comes up
+        let series = self.series.remove(&id.0);
+        let sequence = series.unwrap_or(id.0);
+        let cancelled = self.events.remove(&sequence).is_some() || series.is_some();
+        if cancelled {
+            self.stats.cancelled += 1;
+        }
+        cancelled
+    }
+
+    // Runs the next event. Returns false when there is nothing left to run.
+    pub fn step(&mut self, world: &mut W) -> bool {
+        while let Some(Reverse((time, sequence))) = self.queue.pop() {
+            let Some(event) = self.events.remove(&sequence) else {
+                continue;
+            };
+            self.now = time;
+            self.stats.executed += 1;
+            if self.trace.len() == TRACE_LIMIT {
+                self.trace.pop_front();
+            }
+            self.trace.push_back(TraceEntry { time, label: event.label, id: EventId(sequence) });
+            (event.callback)(world, self);
+            return true;
+        }
+        false
+    }
+
+    // Runs every event due up to and including `deadline`, then leaves the
+    // clock at the deadline
+    pub fn run_until(&mut self, world: &mut W, deadline: SimTime) {
+        while let Some(next) = self.next_due() {
+            if next > deadline {
+                break;
+            }
+            self.step(world);
+        }
+        self.now = self.now.max(deadline);
+    }
+
+    pub fn run_for(&mut self, world: &mut W, duration: SimTime) {
+        let deadline = self.now + duration;
+        self.run_until(world, deadline);
+    }
+
+    // When the next live event is due
+    pub fn next_due(&mut self) -> Option<SimTime> {
+        while let Some(&Reverse((time, sequence))) = self.queue.peek() {
+            if self.events.contains_key(&sequence) {
+                return Some(time);
+            }
+            self.queue.pop();
+        }
+        None
+    }
+
+    pub fn pending(&self) -> usize {
+        self.events.len()
+    }
+
+    // Methods for visualization system
+    pub fn get_stats(&self) -> &SchedulerStats {
+        &self.stats
+    }
+
+    pub fn trace(&self) -> &VecDeque<TraceEntry> {
+        &self.trace
+    }
+
+    // Helper methods
+    // Queues the next run of a series, unless it was cancelled meanwhile
+    fn schedule_occurrence<F>(&mut self, series: u64, period: SimTime, label: &'static str, mut callback: F)
+    where
+        F: FnMut(&mut W, &mut Scheduler<W>) -> bool + 'static,
+    {
+        let id = self.schedule_in(period, label, Box::new(move |world, scheduler| {
+            if callback(world, scheduler) && scheduler.series.contains_key(&series) {
+                scheduler.schedule_occurrence(series, period, label, callback);
+            } else {
+                scheduler.series.remove(&series);
+            }
+        }));
+        self.series.insert(series, id.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn cancelling_a_series_stops_every_later_occurrence() {
+        let mut scheduler = Scheduler::<Vec<u64>>::new(1);
+        let mut runs = Vec::new();
+        let id = scheduler.schedule_every(SimTime::from_ns(10), "tick", |runs: &mut Vec<u64>, scheduler| {
+            runs.push(scheduler.now().as_ns());
+            true
+        });
+        scheduler.run_until(&mut runs, SimTime::from_ns(25));
+        assert_eq!(runs, vec![10, 20]);
+
+        // The id is the first occurrence's, long since run, yet it still cancels
+        assert!(scheduler.cancel(id));
+        scheduler.run_until(&mut runs, SimTime::from_ns(100));
+        assert_eq!(runs, vec![10, 20]);
+        assert_eq!(scheduler.pending(), 0);
+        assert!(!scheduler.cancel(id));
+    }
+
+    #[test]
+    fn a_series_can_cancel_itself() {
+        let mut scheduler = Scheduler::<u32>::new(1);
+        let mut count = 0;
+        let id = Rc::new(Cell::new(None));
+        let own = Rc::clone(&id);
+        let series = scheduler.schedule_every(SimTime::from_ns(5), "self", move |count: &mut u32, scheduler| {
+            *count += 1;
+            if *count == 3 {
+                scheduler.cancel(own.get().unwrap());
+            }
+            true
+        });
+        id.set(Some(series));
+        scheduler.run_until(&mut count, SimTime::from_ns(100));
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn same_seed_same_run() {
+        let run = |seed| {
+            let mut scheduler = Scheduler::<Vec<u64>>::new(seed);
+            let mut draws = Vec::new();
+            for i in 0..4 {
+                let delay = SimTime::from_ns(scheduler.rng().range(1, 100));
+                scheduler.schedule_in(delay, "draw", Box::new(move |draws: &mut Vec<u64>, scheduler| {
+                    draws.push(i * 1000 + scheduler.now().as_ns());
+                }));
+            }
+            scheduler.run_until(&mut draws, SimTime::from_us(1));
+            draws
+        };
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+}
@@ -0,0 +1,85 @@
+// xoshiro256** seeded through SplitMix64. Small, fast, and the same on every
+// platform, which is all a reproducible simulation needs from it.
+#[derive(Debug, Clone)]
+pub struct SimRng {
+    seed: u64, // What this stream was created from; forks derive from it
+    state: [u64; 4],
+}
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        let mut mix = seed;
+        let mut state = [0u64; 4];
+        for word in state.iter_mut() {
+            *word = splitmix64(&mut mix);
+        }
+        Self { seed, state }
+    }
+
+    // An independent stream for one component, derived from this one's seed
+    // and a stable name, so adding a component doesn't shift anyone else's
+    // random numbers. How much has been drawn from this stream doesn't
+    // matter; the same name always gets the same child.
+    pub fn fork(&self, stream: &str) -> SimRng {
+        let mut hash = 0xcbf2_9ce4_8422_2325u64; // FNV-1a
+        for byte in stream.bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        SimRng::new(self.seed ^ hash.rotate_left(17))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let result = self.state[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = self.state[1] << 17;
+        self.state[2] ^= self.state[0];
+        self.state[3] ^= self.state[1];
+        self.state[1] ^= self.state[2];
+        self.state[0] ^= self.state[3];
+        self.state[2] ^= t;
+        self.state[3] = self.state[3].rotate_left(45);
+        result
+    }
+
+    // Uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Uniform in [low, high)
+    pub fn range(&mut self, low: u64, high: u64) -> u64 {
+        if high <= low {
+            return low;
+        }
+        low + self.next_u64() % (high - low)
+    }
+
+    pub fn chance(&mut self, probability: f64) -> bool {
+        self.next_f64() < probability
+    }
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forks_depend_on_the_seed_and_name_only() {
+        let parent = SimRng::new(42);
+        let mut drawn = parent.clone();
+        for _ in 0..10 {
+            drawn.next_u64();
+        }
+        assert_eq!(parent.fork("tlb").next_u64(), drawn.fork("tlb").next_u64());
+        assert_ne!(parent.fork("tlb").next_u64(), parent.fork("l2").next_u64());
+        assert_ne!(parent.fork("tlb").next_u64(), SimRng::new(43).fork("tlb").next_u64());
+    }
+}
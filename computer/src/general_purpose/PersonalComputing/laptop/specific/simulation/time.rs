@@ -0,0 +1,100 @@
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub};
+
+pub const PS_PER_NS: u64 = 1_000;
+pub const PS_PER_US: u64 = 1_000_000;
+pub const PS_PER_MS: u64 = 1_000_000_000;
+pub const PS_PER_SECOND: u64 = 1_000_000_000_000;
+
+// The machine's one time base: picoseconds since power-on. A u64 of them
+// lasts about 213 days of simulated time, and a picosecond is fine enough
+// to hold a 5 GHz clock period without rounding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SimTime(pub u64);
+
+impl SimTime {
+    pub const ZERO: SimTime = SimTime(0);
+
+    pub fn from_ps(ps: u64) -> Self {
+        SimTime(ps)
+    }
+
+    pub fn from_ns(ns: u64) -> Self {
+        SimTime(ns.saturating_mul(PS_PER_NS))
+    }
+
+    pub fn from_us(us: u64) -> Self {
+        SimTime(us.saturating_mul(PS_PER_US))
+    }
+
+    pub fn from_ms(ms: u64) -> Self {
+        SimTime(ms.saturating_mul(PS_PER_MS))
+    }
+
+    // How long `cycles` take at `frequency` Hz, rounded up to a whole picosecond
+    pub fn from_cycles(cycles: u64, frequency: u64) -> Self {
+        if frequency == 0 {
+            return SimTime(u64::MAX);
+        }
+        let ps = (cycles as u128 * PS_PER_SECOND as u128).div_ceil(frequency as u128);
+        SimTime(ps.min(u64::MAX as u128) as u64)
+    }
+
+    // Whole cycles of a `frequency` Hz clock that fit in this much time
+    pub fn to_cycles(self, frequency: u64) -> u64 {
+        (self.0 as u128 * frequency as u128 / PS_PER_SECOND as u128) as u64
+    }
+
+    pub fn as_ps(self) -> u64 {
+        self.0
+    }
+
+    pub fn as_ns(self) -> u64 {
+        self.0 / PS_PER_NS
+    }
+
+    pub fn as_secs_f64(self) -> f64 {
+        self.0 as f64 / PS_PER_SECOND as f64
+    }
+
+    pub fn saturating_sub(self, other: SimTime) -> SimTime {
+        SimTime(self.0.saturating_sub(other.0))
+    }
+}
+
+impl Add for SimTime {
+    type Output = SimTime;
+
+    fn add(self, other: SimTime) -> SimTime {
+        SimTime(self.0.saturating_add(other.0))
+    }
+}
+
+impl AddAssign for SimTime {
+    fn add_assign(&mut self, other: SimTime) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for SimTime {
+    type Output = SimTime;
+
+    fn sub(self, other: SimTime) -> SimTime {
+        self.saturating_sub(other)
+    }
+}
+
+impl fmt::Display for SimTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ps = self.0;
+        if ps >= PS_PER_MS {
+            write!(f, "{:.3} ms", ps as f64 / PS_PER_MS as f64)
+        } else if ps >= PS_PER_US {
+            write!(f, "{:.3} µs", ps as f64 / PS_PER_US as f64)
+        } else if ps >= PS_PER_NS {
+            write!(f, "{:.3} ns", ps as f64 / PS_PER_NS as f64)
+        } else {
+            write!(f, "{} ps", ps)
+        }
+    }
+}
@@ -1,37 +1,53 @@
 use super::error::{AppError, AppResult};
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 
+// The machine has no route to a real network, so pages come from the
+// browser's own table of hosted sites
 pub struct WebBrowser {
     current_page: Option<WebPage>,
     history: VecDeque<WebPage>,
     bookmarks: Vec<Bookmark>,
     downloads: Vec<Download>,
+    sites: HashMap<String, Site>,
     config: BrowserConfig,
 }
 
-struct WebPage {
-    url: String,
+pub struct BrowserConfig {
+    pub home_page: String,
+    pub max_history: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebPage {
+    pub url: String,
+    pub title: String,
+    pub content: String,
+    pub scroll_position: (f32, f32),
+    pub zoom_level: f32,
+}
+
+struct Site {
     title: String,
     content: String,
-    scroll_position: (f32, f32),
-    zoom_level: f32,
 }
 
-struct Bookmark {
-    title: String,
-    url: String,
-    icon: Option<Icon>,
-    tags: Vec<String>,
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bookmark {
+    pub title: String,
+    pub url: String,
+    pub tags: Vec<String>,
 }
 
-struct Download {
-    url: String,
-    path: String,
-    progress: f32,
-    status: DownloadStatus,
+#[derive(Debug, Clone, PartialEq)]
+pub struct Download {
+    pub url: String,
+    pub path: String,
+    pub progress: f32,
+    pub status: DownloadStatus,
 }
 
-enum DownloadStatus {
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadStatus {
     Pending,
     Downloading,
     Completed,
@@ -40,31 +56,45 @@ enum DownloadStatus {
 
 impl WebBrowser {
     pub fn new(config: BrowserConfig) -> Self {
+        let mut sites = HashMap::new();
+        sites.insert("about:blank".to_string(), Site { title: String::new(), content: String::new() });
         Self {
             current_page: None,
-            history: VecDeque::with_capacity(100),
+            history: VecDeque::with_capacity(config.max_history),
             bookmarks: Vec::new(),
             downloads: Vec::new(),
+            sites,
             config,
         }
     }
 
-    pub fn navigate(&mut self, url: &str) -> AppResult<()> {
-        // Save current page to history
-        if let Some(page) = self.current_page.take() {
-            self.history.push_back(page);
-        }
+    // Serves `content` at `url` from now on
+    pub fn host(&mut self, url: &str, title: &str, content: &str) {
+        self.sites.insert(url.to_string(), Site { title: title.to_string(), content: content.to_string() });
+    }
 
+    pub fn navigate(&mut self, url: &str) -> AppResult<()> {
         // Load new page
         let page = self.load_page(url)?;
-        self.current_page = Some(page);
+
+        // Save current page to history
+        if let Some(previous) = self.current_page.replace(page) {
+            if self.history.len() == self.config.max_history {
+                self.history.pop_front();
+            }
+            self.history.push_back(previous);
+        }
         Ok(())
     }
 
+    pub fn go_home(&mut self) -> AppResult<()> {
+        let home = self.config.home_page.clone();
+        self.navigate(&home)
+    }
+
     pub fn go_back(&mut self) -> AppResult<()> {
         if let Some(current) = self.current_page.take() {
             if let Some(previous) = self.history.pop_back() {
-                self.history.push_front(current);
                 self.current_page = Some(previous);
                 Ok(())
             } else {
@@ -76,13 +106,12 @@ impl WebBrowser {
         }
     }
 
-    pub fn add_bookmark(&mut self) -> AppResult<()> {
+    pub fn add_bookmark(&mut self, tags: &[&str]) -> AppResult<()> {
         if let Some(page) = &self.current_page {
             let bookmark = Bookmark {
                 title: page.title.clone(),
                 url: page.url.clone(),
-                icon: None,
-                tags: Vec::new(),
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
             };
             self.bookmarks.push(bookmark);
             Ok(())
@@ -99,17 +128,82 @@ impl WebBrowser {
             status: DownloadStatus::Pending,
         };
         self.downloads.push(download);
-        self.start_download(self.downloads.len() - 1)?;
-        Ok(())
+        self.start_download(self.downloads.len() - 1)
+    }
+
+    pub fn current_page(&self) -> Option<&WebPage> {
+        self.current_page.as_ref()
+    }
+
+    pub fn bookmarks(&self) -> &[Bookmark] {
+        &self.bookmarks
+    }
+
+    pub fn downloads(&self) -> &[Download] {
+        &self.downloads
     }
 
     fn load_page(&self, url: &str) -> AppResult<WebPage> {
-        // Fetch and parse webpage
-        unimplemented!()
+        let site = self.sites.get(url).ok_or(AppError::PageNotFound)?;
+        Ok(WebPage {
+            url: url.to_string(),
+            title: site.title.clone(),
+            content: site.content.clone(),
+            scroll_position: (0.0, 0.0),
+            zoom_level: 1.0,
+        })
     }
 
+    // Hosted sites are local, so a download finishes as soon as it starts
     fn start_download(&mut self, index: usize) -> AppResult<()> {
-        // Start async download
-        unimplemented!()
+        let found = self.sites.contains_key(&self.downloads[index].url);
+        let download = &mut self.downloads[index];
+        download.status = DownloadStatus::Downloading;
+        if found {
+            download.progress = 1.0;
+            download.status = DownloadStatus::Completed;
+            Ok(())
+        } else {
+            download.status = DownloadStatus::Failed("not found".to_string());
+            Err(AppError::PageNotFound)
+        }
+    }
+}
+
+impl Default for BrowserConfig {
+    fn default() -> Self {
+        Self {
+            home_page: "about:blank".to_string(),
+            max_history: 100,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn navigates_and_goes_back() {
+        let mut browser = WebBrowser::new(BrowserConfig::default());
+        browser.host("http://example.test/", "Example", "<p>hi</p>");
+        browser.go_home().unwrap();
+        browser.navigate("http://example.test/").unwrap();
+        assert_eq!(browser.current_page().unwrap().title, "Example");
+
+        browser.go_back().unwrap();
+        assert_eq!(browser.current_page().unwrap().url, "about:blank");
+        assert_eq!(browser.go_back(), Err(AppError::NoHistory));
+        assert_eq!(browser.navigate("http://missing.test/"), Err(AppError::PageNotFound));
+    }
+
+    #[test]
+    fn downloads_fail_for_unknown_urls() {
+        let mut browser = WebBrowser::new(BrowserConfig::default());
+        browser.host("http://example.test/file", "File", "data");
+        browser.download("http://example.test/file", "/tmp/file").unwrap();
+        assert!(browser.download("http://missing.test/", "/tmp/missing").is_err());
+        assert_eq!(browser.downloads()[0].status, DownloadStatus::Completed);
+        assert!(matches!(browser.downloads()[1].status, DownloadStatus::Failed(_)));
     }
 }
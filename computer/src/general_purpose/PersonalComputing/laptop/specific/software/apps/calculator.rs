@@ -8,7 +8,12 @@ pub struct Calculator {
     config: CalculatorConfig,
 }
 
-enum Operation {
+pub struct CalculatorConfig {
+    pub precision: usize, // Decimal places shown
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
     Add,
     Subtract,
     Multiply,
@@ -29,18 +34,19 @@ impl Calculator {
     }
 
     pub fn input_digit(&mut self, digit: char) -> AppResult<()> {
-        if digit.is_digit(10) {
-            if self.display == "0" {
-                self.display = digit.to_string();
-            } else {
-                self.display.push(digit);
-            }
+        if !digit.is_ascii_digit() && (digit != '.' || self.display.contains('.')) {
+            return Err(AppError::InvalidInput);
+        }
+        if self.display == "0" && digit != '.' {
+            self.display = digit.to_string();
+        } else {
+            self.display.push(digit);
         }
         Ok(())
     }
 
     pub fn operation(&mut self, op: Operation) -> AppResult<()> {
-        if let Some(value) = self.display.parse::<f64>().ok() {
+        if let Ok(value) = self.display.parse::<f64>() {
             if let Some(current_op) = self.current_op.take() {
                 self.apply_operation(current_op, value)?;
             } else {
@@ -52,8 +58,28 @@ impl Calculator {
         Ok(())
     }
 
+    pub fn clear(&mut self) {
+        self.display = String::from("0");
+        self.current_op = None;
+        self.last_value = None;
+    }
+
+    pub fn display(&self) -> &str {
+        &self.display
+    }
+
+    pub fn memory_add(&mut self) {
+        if let Ok(value) = self.display.parse::<f64>() {
+            self.memory += value;
+        }
+    }
+
+    pub fn memory_recall(&mut self) {
+        self.display = self.format(self.memory);
+    }
+
     pub fn equals(&mut self) -> AppResult<()> {
-        if let (Some(op), Some(last)) = (self.current_op.take(), self.last_value) {
+        if let Some(op) = self.current_op.take() {
             if let Ok(value) = self.display.parse::<f64>() {
                 self.apply_operation(op, value)?;
             }
@@ -76,9 +102,57 @@ impl Calculator {
                 Operation::Power => last.powf(value),
                 Operation::Root => value.powf(1.0 / last),
             };
-            self.display = result.to_string();
+            self.display = self.format(result);
             self.last_value = Some(result);
         }
         Ok(())
     }
+
+    // Rounds to the configured precision and drops trailing zeros
+    fn format(&self, value: f64) -> String {
+        let text = format!("{:.*}", self.config.precision, value);
+        if text.contains('.') {
+            text.trim_end_matches('0').trim_end_matches('.').to_string()
+        } else {
+            text
+        }
+    }
+}
+
+impl Default for CalculatorConfig {
+    fn default() -> Self {
+        Self { precision: 10 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enter(calculator: &mut Calculator, number: &str) {
+        for digit in number.chars() {
+            calculator.input_digit(digit).unwrap();
+        }
+    }
+
+    #[test]
+    fn chains_operations() {
+        let mut calculator = Calculator::new(CalculatorConfig::default());
+        enter(&mut calculator, "12");
+        calculator.operation(Operation::Add).unwrap();
+        enter(&mut calculator, "3");
+        calculator.operation(Operation::Multiply).unwrap();
+        enter(&mut calculator, "0.5");
+        calculator.equals().unwrap();
+        assert_eq!(calculator.display(), "7.5");
+    }
+
+    #[test]
+    fn refuses_to_divide_by_zero() {
+        let mut calculator = Calculator::new(CalculatorConfig::default());
+        enter(&mut calculator, "1");
+        calculator.operation(Operation::Divide).unwrap();
+        enter(&mut calculator, "0");
+        assert_eq!(calculator.equals(), Err(AppError::DivisionByZero));
+    }
 } 
\ No newline at end of file
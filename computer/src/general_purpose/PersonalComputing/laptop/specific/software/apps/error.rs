@@ -0,0 +1,18 @@
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppError {
+    // Editing errors
+    NothingToUndo,
+    NothingToRedo,
+    InvalidPosition,
+
+    // Calculator errors
+    DivisionByZero,
+    InvalidInput,
+
+    // Browser errors
+    NoHistory,
+    NoCurrentPage,
+    PageNotFound,
+}
+
+pub type AppResult<T> = Result<T, AppError>;
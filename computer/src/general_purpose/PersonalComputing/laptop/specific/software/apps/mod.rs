@@ -1,5 +1,4 @@
+pub mod error;
 pub mod browser;
-pub mod email;
 pub mod text_editor;
 pub mod calculator;
-pub mod game;
\ No newline at end of file
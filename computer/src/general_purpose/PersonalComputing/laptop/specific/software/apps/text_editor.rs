@@ -1,4 +1,4 @@
-use super::super::error::{AppError, AppResult};
+use super::error::{AppError, AppResult};
 use std::collections::VecDeque;
 
 pub struct TextEditor {
@@ -10,34 +10,47 @@ pub struct TextEditor {
     state: EditorState,
 }
 
+pub struct EditorSettings {
+    pub tab_width: usize,
+    pub insert_spaces: bool,
+    pub max_history: usize,
+    pub line_ending: LineEnding,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EditorState {
+    pub modified: bool,
+    pub edits: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+// Lines are kept without their endings; columns count chars, not bytes
 struct TextBuffer {
     lines: Vec<String>,
-    line_endings: LineEnding,
-    encoding: TextEncoding,
 }
 
+#[derive(Default)]
 struct Cursor {
     line: usize,
     column: usize,
-    preferred_column: usize,
-    visible: bool,
+    preferred_column: usize, // Where vertical moves try to land
 }
 
-struct Selection {
-    start: TextPosition,
-    end: TextPosition,
-    mode: SelectionMode,
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Selection {
+    pub start: TextPosition,
+    pub end: TextPosition,
 }
 
-struct TextPosition {
-    line: usize,
-    column: usize,
-}
-
-enum SelectionMode {
-    Normal,
-    Line,
-    Block,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TextPosition {
+    pub line: usize,
+    pub column: usize,
 }
 
 struct EditHistory {
@@ -46,6 +59,7 @@ struct EditHistory {
     max_history: usize,
 }
 
+#[derive(Clone)]
 enum EditOperation {
     Insert {
         position: TextPosition,
@@ -68,83 +82,334 @@ impl TextEditor {
             buffer: TextBuffer::new(),
             cursor: Cursor::default(),
             selection: None,
-            history: EditHistory::new(100),
+            history: EditHistory::new(settings.max_history),
             settings,
             state: EditorState::default(),
         }
     }
 
     pub fn insert(&mut self, text: &str) -> AppResult<()> {
-        // Create edit operation
-        let operation = EditOperation::Insert {
-            position: self.cursor.get_position(),
-            text: text.to_string(),
+        let text = self.expand_tabs(text);
+        let position = match self.selection.take() {
+            Some(selection) => {
+                // Typing over a selection replaces it
+                let old_text = self.buffer.get_text(selection.start, selection.end)?;
+                self.commit(EditOperation::Replace { position: selection.start, old_text, new_text: text.clone() })?;
+                selection.start
+            }
+            None => {
+                let position = self.cursor.get_position();
+                self.commit(EditOperation::Insert { position, text: text.clone() })?;
+                position
+            }
         };
 
-        // Apply the operation
-        self.apply_operation(operation)?;
-
-        // Update cursor
-        self.cursor.advance(text.len());
-
+        self.cursor.set_position(TextBuffer::end_of(position, &text));
         Ok(())
     }
 
+    // Deletes the selection, or the character under the cursor
     pub fn delete(&mut self) -> AppResult<()> {
-        if let Some(selection) = &self.selection {
-            // Delete selection
+        if let Some(selection) = self.selection.take() {
             let text = self.buffer.get_text(selection.start, selection.end)?;
+            self.commit(EditOperation::Delete { position: selection.start, text })?;
+            self.cursor.set_position(selection.start);
+        } else if let Some(ch) = self.buffer.get_char_at(self.cursor.get_position()) {
             let operation = EditOperation::Delete {
-                position: selection.start,
-                text,
+                position: self.cursor.get_position(),
+                text: ch.to_string(),
             };
-            self.apply_operation(operation)?;
-            self.selection = None;
-        } else {
-            // Delete single character
-            if let Some(ch) = self.buffer.get_char_at_cursor(&self.cursor) {
-                let operation = EditOperation::Delete {
-                    position: self.cursor.get_position(),
-                    text: ch.to_string(),
-                };
-                self.apply_operation(operation)?;
-            }
+            self.commit(operation)?;
         }
         Ok(())
     }
 
     pub fn undo(&mut self) -> AppResult<()> {
-        if let Some(operation) = self.history.undo_stack.pop_back() {
-            self.revert_operation(operation)?;
-            Ok(())
-        } else {
-            Err(AppError::NothingToUndo)
-        }
+        let operation = self.history.undo_stack.pop_back().ok_or(AppError::NothingToUndo)?;
+        let position = self.revert_operation(&operation)?;
+        self.history.redo_stack.push_back(operation);
+        self.cursor.set_position(position);
+        self.state.modified = true;
+        Ok(())
     }
 
     pub fn redo(&mut self) -> AppResult<()> {
-        if let Some(operation) = self.history.redo_stack.pop_back() {
-            self.apply_operation(operation)?;
-            Ok(())
+        let operation = self.history.redo_stack.pop_back().ok_or(AppError::NothingToRedo)?;
+        let position = self.apply_operation(&operation)?;
+        self.history.record(operation);
+        self.cursor.set_position(position);
+        self.state.modified = true;
+        Ok(())
+    }
+
+    pub fn select(&mut self, start: TextPosition, end: TextPosition) -> AppResult<()> {
+        self.buffer.check(start)?;
+        self.buffer.check(end)?;
+        self.selection = Some(Selection { start: start.min(end), end: start.max(end) });
+        Ok(())
+    }
+
+    pub fn move_to(&mut self, position: TextPosition) -> AppResult<()> {
+        self.buffer.check(position)?;
+        self.selection = None;
+        self.cursor.set_position(position);
+        Ok(())
+    }
+
+    // Vertical moves keep to the column the cursor was last put at
+    pub fn move_vertically(&mut self, lines: isize) {
+        let line = self.cursor.line.saturating_add_signed(lines).min(self.buffer.lines.len() - 1);
+        self.cursor.line = line;
+        self.cursor.column = self.cursor.preferred_column.min(self.buffer.line_length(line));
+    }
+
+    pub fn cursor(&self) -> TextPosition {
+        self.cursor.get_position()
+    }
+
+    pub fn selection(&self) -> Option<Selection> {
+        self.selection
+    }
+
+    // The whole document with the configured line endings
+    pub fn text(&self) -> String {
+        let ending = match self.settings.line_ending {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        };
+        self.buffer.lines.join(ending)
+    }
+
+    pub fn get_state(&self) -> &EditorState {
+        &self.state
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.state.modified = false;
+    }
+
+    fn expand_tabs(&self, text: &str) -> String {
+        if self.settings.insert_spaces {
+            text.replace('\t', &" ".repeat(self.settings.tab_width))
         } else {
-            Err(AppError::NothingToRedo)
+            text.to_string()
         }
     }
 
-    fn apply_operation(&mut self, operation: EditOperation) -> AppResult<()> {
-        match &operation {
+    // A fresh edit: applies it, records it and forgets what was undone
+    fn commit(&mut self, operation: EditOperation) -> AppResult<()> {
+        self.apply_operation(&operation)?;
+        self.history.record(operation);
+        self.history.redo_stack.clear();
+        self.state.modified = true;
+        self.state.edits += 1;
+        Ok(())
+    }
+
+    // Returns where the cursor belongs afterwards
+    fn apply_operation(&mut self, operation: &EditOperation) -> AppResult<TextPosition> {
+        match operation {
+            EditOperation::Insert { position, text } => {
+                self.buffer.insert_text(*position, text)?;
+                Ok(TextBuffer::end_of(*position, text))
+            }
+            EditOperation::Delete { position, text } => {
+                self.buffer.delete_text(*position, TextBuffer::end_of(*position, text))?;
+                Ok(*position)
+            }
+            EditOperation::Replace { position, old_text, new_text } => {
+                self.buffer.delete_text(*position, TextBuffer::end_of(*position, old_text))?;
+                self.buffer.insert_text(*position, new_text)?;
+                Ok(TextBuffer::end_of(*position, new_text))
+            }
+        }
+    }
+
+    fn revert_operation(&mut self, operation: &EditOperation) -> AppResult<TextPosition> {
+        match operation {
             EditOperation::Insert { position, text } => {
-                self.buffer.insert_text(position, text)?;
+                self.buffer.delete_text(*position, TextBuffer::end_of(*position, text))?;
+                Ok(*position)
             }
             EditOperation::Delete { position, text } => {
-                self.buffer.delete_text(position, text.len())?;
+                self.buffer.insert_text(*position, text)?;
+                Ok(*position)
             }
             EditOperation::Replace { position, old_text, new_text } => {
-                self.buffer.replace_text(position, old_text.len(), new_text)?;
+                self.buffer.delete_text(*position, TextBuffer::end_of(*position, new_text))?;
+                self.buffer.insert_text(*position, old_text)?;
+                Ok(*position)
             }
         }
+    }
+}
+
+impl TextBuffer {
+    fn new() -> Self {
+        Self { lines: vec![String::new()] }
+    }
+
+    fn line_length(&self, line: usize) -> usize {
+        self.lines[line].chars().count()
+    }
+
+    fn check(&self, position: TextPosition) -> AppResult<()> {
+        if position.line < self.lines.len() && position.column <= self.line_length(position.line) {
+            Ok(())
+        } else {
+            Err(AppError::InvalidPosition)
+        }
+    }
+
+    // Where the cursor lands after `text` is inserted at `position`
+    fn end_of(position: TextPosition, text: &str) -> TextPosition {
+        match text.rsplit_once('\n') {
+            Some((head, tail)) => TextPosition {
+                line: position.line + head.matches('\n').count() + 1,
+                column: tail.chars().count(),
+            },
+            None => TextPosition { line: position.line, column: position.column + text.chars().count() },
+        }
+    }
 
-        self.history.push(operation);
+    fn byte_offset(&self, position: TextPosition) -> usize {
+        let line = &self.lines[position.line];
+        line.char_indices().nth(position.column).map_or(line.len(), |(offset, _)| offset)
+    }
+
+    // The character under the cursor; a line break at the end of a line
+    fn get_char_at(&self, position: TextPosition) -> Option<char> {
+        match self.lines[position.line].chars().nth(position.column) {
+            Some(ch) => Some(ch),
+            None if position.line + 1 < self.lines.len() => Some('\n'),
+            None => None,
+        }
+    }
+
+    fn get_text(&self, start: TextPosition, end: TextPosition) -> AppResult<String> {
+        self.check(start)?;
+        self.check(end)?;
+        if start.line == end.line {
+            let line = &self.lines[start.line];
+            return Ok(line[self.byte_offset(start)..self.byte_offset(end)].to_string());
+        }
+
+        let mut text = self.lines[start.line][self.byte_offset(start)..].to_string();
+        for line in &self.lines[start.line + 1..end.line] {
+            text.push('\n');
+            text.push_str(line);
+        }
+        text.push('\n');
+        text.push_str(&self.lines[end.line][..self.byte_offset(end)]);
+        Ok(text)
+    }
+
+    fn insert_text(&mut self, position: TextPosition, text: &str) -> AppResult<()> {
+        self.check(position)?;
+        let offset = self.byte_offset(position);
+        let tail = self.lines[position.line].split_off(offset);
+
+        let mut pieces = text.split('\n');
+        self.lines[position.line].push_str(pieces.next().unwrap_or_default());
+        let mut line = position.line;
+        for piece in pieces {
+            line += 1;
+            self.lines.insert(line, piece.to_string());
+        }
+        self.lines[line].push_str(&tail);
         Ok(())
     }
-} 
\ No newline at end of file
+
+    fn delete_text(&mut self, start: TextPosition, end: TextPosition) -> AppResult<()> {
+        self.check(start)?;
+        self.check(end)?;
+        let tail = self.lines[end.line][self.byte_offset(end)..].to_string();
+        let offset = self.byte_offset(start);
+        self.lines[start.line].truncate(offset);
+        self.lines[start.line].push_str(&tail);
+        self.lines.drain(start.line + 1..=end.line);
+        Ok(())
+    }
+}
+
+impl Cursor {
+    fn get_position(&self) -> TextPosition {
+        TextPosition { line: self.line, column: self.column }
+    }
+
+    fn set_position(&mut self, position: TextPosition) {
+        self.line = position.line;
+        self.column = position.column;
+        self.preferred_column = position.column;
+    }
+}
+
+impl EditHistory {
+    fn new(max_history: usize) -> Self {
+        Self {
+            undo_stack: VecDeque::new(),
+            redo_stack: VecDeque::new(),
+            max_history,
+        }
+    }
+
+    fn record(&mut self, operation: EditOperation) {
+        if self.undo_stack.len() == self.max_history {
+            self.undo_stack.pop_front();
+        }
+        self.undo_stack.push_back(operation);
+    }
+}
+
+impl Default for EditorSettings {
+    fn default() -> Self {
+        Self {
+            tab_width: 4,
+            insert_spaces: true,
+            max_history: 100,
+            line_ending: LineEnding::Lf,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(line: usize, column: usize) -> TextPosition {
+        TextPosition { line, column }
+    }
+
+    #[test]
+    fn edits_across_lines_and_undoes_them() {
+        let mut editor = TextEditor::new(EditorSettings::default());
+        editor.insert("hello\nworld").unwrap();
+        assert_eq!(editor.cursor(), at(1, 5));
+
+        editor.select(at(0, 3), at(1, 2)).unwrap();
+        editor.insert("p, ").unwrap();
+        assert_eq!(editor.text(), "help, rld");
+
+        editor.undo().unwrap();
+        assert_eq!(editor.text(), "hello\nworld");
+        editor.redo().unwrap();
+        assert_eq!(editor.text(), "help, rld");
+        editor.undo().unwrap();
+        editor.undo().unwrap();
+        assert_eq!(editor.text(), "");
+        assert_eq!(editor.undo(), Err(AppError::NothingToUndo));
+    }
+
+    #[test]
+    fn delete_joins_lines_at_a_line_end() {
+        let mut editor = TextEditor::new(EditorSettings::default());
+        editor.insert("ab\ncd").unwrap();
+        editor.move_to(at(0, 2)).unwrap();
+        editor.delete().unwrap();
+        assert_eq!(editor.text(), "abcd");
+
+        editor.insert("\té").unwrap();
+        assert_eq!(editor.text(), "ab    écd");
+        assert_eq!(editor.move_to(at(3, 0)), Err(AppError::InvalidPosition));
+    }
+}
@@ -0,0 +1,579 @@
+use crate::hardware::io::controllers::fis::{ata, RegisterH2D};
+use crate::hardware::io::controllers::sata::{
+    prd_entry, CommandHeader, SataController, CMD_CR, CMD_FR, CMD_FRE, CMD_ST, COMMAND_HEADER_SIZE,
+    COMMAND_LIST_SIZE, HBA_CAP, HBA_GHC, HBA_IS, HBA_PI, IS_DHRS, IS_HBFS, IS_OFS, IS_SDBS, IS_TFES,
+    PORT_BASE, PORT_CI, PORT_CLB, PORT_CLBU, PORT_CMD, PORT_FB, PORT_FBU, PORT_IE, PORT_IS, PORT_SACT,
+    PORT_SERR, PORT_SIG, PORT_SSTS, PORT_STRIDE, PORT_TFD, PRDT_OFFSET, RECEIVED_FIS_SIZE, SIG_ATA,
+    CAP_SNCQ, GHC_AE, GHC_HR, GHC_IE,
+};
+use crate::hardware::io::error::{IOError, IOResult};
+use crate::hardware::memory::physical::PhysicalMemory;
+use crate::hardware::storage::error::{StorageError, StorageResult};
+use crate::hardware::storage::{BlockDevice, BlockDeviceType};
+
+// Per-port DMA area: command list, received FIS, one command table and one
+// bounce buffer per slot, the same carve-up Linux's ahci driver makes
+const COMMAND_TABLE_SIZE: u64 = 256;
+const BUFFER_SIZE: u64 = 64 * 1024;
+const MAX_SLOTS: u64 = 32;
+const FIS_AREA: u64 = COMMAND_LIST_SIZE;
+const TABLE_AREA: u64 = FIS_AREA + RECEIVED_FIS_SIZE;
+const BUFFER_AREA: u64 = TABLE_AREA + MAX_SLOTS * COMMAND_TABLE_SIZE;
+pub const PORT_DMA_SIZE: u64 = BUFFER_AREA + MAX_SLOTS * BUFFER_SIZE;
+
+// Polls of the HBA before a command is declared lost
+const COMMAND_TIMEOUT_POLLS: usize = 1000;
+
+const PORT_INTERRUPTS: u32 = IS_DHRS | IS_SDBS | IS_TFES | IS_HBFS | IS_OFS;
+const PORT_ERRORS: u32 = IS_TFES | IS_HBFS | IS_OFS;
+
+// Drives an AHCI HBA through its registers and a region of physical memory it
+// owns for command lists, FIS areas and DMA buffers
+pub struct AhciDriver<M: PhysicalMemory> {
+    hba: SataController,
+    memory: M,
+    dma_base: u64,
+    ports: Vec<AhciPort>,
+    active: usize,
+    ncq: bool,
+    slots: u32,
+    stats: AhciStats,
+}
+
+struct AhciPort {
+    number: u8,
+    base: u64,
+    info: DiskInfo,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiskInfo {
+    pub port: u8,
+    pub model: String,
+    pub serial: String,
+    pub sectors: u64,
+    pub sector_size: u32,
+    pub queue_depth: u32,
+    pub trim: bool,
+    pub rotation_rate: u16,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AhciStats {
+    pub commands: u64,
+    pub queued_commands: u64,
+    pub max_outstanding: u32,
+    pub interrupts_handled: u64,
+    pub errors: u64,
+    pub recoveries: u64,
+}
+
+impl<M: PhysicalMemory> AhciDriver<M> {
+    // Resets the HBA, brings up every port with a disk behind it and identifies the disks
+    pub fn new(hba: SataController, memory: M, dma_base: u64) -> IOResult<Self> {
+        let mut driver = Self {
+            hba,
+            memory,
+            dma_base,
+            ports: Vec::new(),
+            active: 0,
+            ncq: false,
+            slots: 1,
+            stats: AhciStats::default(),
+        };
+        driver.probe()?;
+        Ok(driver)
+    }
+
+    pub fn disks(&self) -> Vec<&DiskInfo> {
+        self.ports.iter().map(|port| &port.info).collect()
+    }
+
+    // Chooses which disk the BlockDevice interface talks to
+    pub fn select_port(&mut self, port: u8) -> IOResult<()> {
+        self.active = self.ports
+            .iter()
+            .position(|candidate| candidate.number == port)
+            .ok_or(IOError::DeviceNotFound)?;
+        Ok(())
+    }
+
+    pub fn read(&mut self, port: u8, lba: u64, count: u32, buffer: &mut [u8]) -> IOResult<()> {
+        let index = self.port_index(port)?;
+        let sector_size = self.ports[index].info.sector_size as usize;
+        if buffer.len() < count as usize * sector_size {
+            return Err(IOError::InvalidBufferSize);
+        }
+        self.transfer(index, lba, count, Transfer::Read(&mut buffer[..count as usize * sector_size]))
+    }
+
+    pub fn write(&mut self, port: u8, lba: u64, data: &[u8]) -> IOResult<()> {
+        let index = self.port_index(port)?;
+        let sector_size = self.ports[index].info.sector_size as usize;
+        if !data.len().is_multiple_of(sector_size) {
+            return Err(IOError::InvalidBufferSize);
+        }
+        let count = (data.len() / sector_size) as u32;
+        self.transfer(index, lba, count, Transfer::Write(data))
+    }
+
+    pub fn flush(&mut self, port: u8) -> IOResult<()> {
+        let index = self.port_index(port)?;
+        self.execute(index, RegisterH2D::flush(), None)
+    }
+
+    // Ranges become DSM TRIM entries, 64 to a 512-byte block
+    pub fn trim(&mut self, port: u8, ranges: &[(u64, u32)]) -> IOResult<()> {
+        let index = self.port_index(port)?;
+        if !self.ports[index].info.trim {
+            return Ok(());
+        }
+        let mut entries = Vec::new();
+        for &(lba, count) in ranges {
+            let mut remaining = count as u64;
+            let mut start = lba;
+            while remaining > 0 {
+                let length = remaining.min(0xFFFF);
+                entries.extend_from_slice(&(start | length << 48).to_le_bytes());
+                start += length;
+                remaining -= length;
+            }
+        }
+        for block in entries.chunks(BUFFER_SIZE as usize) {
+            let mut data = block.to_vec();
+            data.resize(data.len().div_ceil(512) * 512, 0);
+            let fis = RegisterH2D::trim((data.len() / 512) as u16);
+            self.execute(index, fis, Some(Transfer::Write(&data)))?;
+        }
+        Ok(())
+    }
+
+    pub fn hba(&self) -> &SataController {
+        &self.hba
+    }
+
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
+
+    pub fn get_stats(&self) -> &AhciStats {
+        &self.stats
+    }
+
+    // Helper methods
+    fn probe(&mut self) -> IOResult<()> {
+        // Reset the HBA and put it in AHCI mode
+        self.hba.write_register(HBA_GHC, GHC_AE)?;
+        self.hba.write_register(HBA_GHC, GHC_HR)?;
+        if self.hba.read_register(HBA_GHC)? & GHC_HR != 0 {
+            return Err(IOError::Timeout);
+        }
+        self.hba.write_register(HBA_GHC, GHC_AE)?;
+
+        let cap = self.hba.read_register(HBA_CAP)?;
+        self.ncq = cap & CAP_SNCQ != 0;
+        self.slots = ((cap >> 8) & 0x1F) + 1;
+        let implemented = self.hba.read_register(HBA_PI)?;
+
+        for number in (0..32u8).filter(|number| implemented & 1 << number != 0) {
+            self.stop_engine(number)?;
+
+            // DET=3: device present and link up; anything but an ATA disk is skipped
+            let present = self.read_port(number, PORT_SSTS)? & 0xF == 3;
+            if !present || self.read_port(number, PORT_SIG)? != SIG_ATA {
+                continue;
+            }
+
+            // Only ports with a disk get a slice of the DMA region
+            let base = self.dma_base + self.ports.len() as u64 * PORT_DMA_SIZE;
+            if base + PORT_DMA_SIZE > self.memory.size() {
+                return Err(IOError::BufferFull);
+            }
+            self.write_port(number, PORT_CLB, base as u32)?;
+            self.write_port(number, PORT_CLBU, (base >> 32) as u32)?;
+            self.write_port(number, PORT_FB, (base + FIS_AREA) as u32)?;
+            self.write_port(number, PORT_FBU, ((base + FIS_AREA) >> 32) as u32)?;
+            self.write_port(number, PORT_SERR, 0xFFFF_FFFF)?;
+            self.write_port(number, PORT_IS, 0xFFFF_FFFF)?;
+            self.write_port(number, PORT_IE, PORT_INTERRUPTS)?;
+            self.start_engine(number)?;
+            self.ports.push(AhciPort { number, base, info: DiskInfo::default_for(number) });
+            let index = self.ports.len() - 1;
+            self.identify(index)?;
+        }
+
+        self.hba.write_register(HBA_GHC, GHC_AE | GHC_IE)?;
+        if self.ports.is_empty() {
+            return Err(IOError::DeviceNotFound);
+        }
+        Ok(())
+    }
+
+    fn identify(&mut self, index: usize) -> IOResult<()> {
+        let mut data = vec![0u8; 512];
+        self.execute(index, RegisterH2D::identify(), Some(Transfer::Read(&mut data)))?;
+        let words: Vec<u16> = data.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+
+        let info = &mut self.ports[index].info;
+        info.serial = ata_string(&words[10..20]);
+        info.model = ata_string(&words[27..47]);
+        info.sectors = if words[83] & 0x0400 != 0 {
+            (0..4).fold(0u64, |sectors, i| sectors | (words[100 + i] as u64) << (16 * i))
+        } else {
+            words[60] as u64 | (words[61] as u64) << 16
+        };
+        info.sector_size = if words[106] & 0xC000 == 0x4000 && words[106] & 0x1000 != 0 {
+            (words[117] as u32 | (words[118] as u32) << 16) * 2
+        } else {
+            512
+        };
+        info.queue_depth = if self.ncq && words[76] & 0x0100 != 0 {
+            ((words[75] & 0x1F) as u32 + 1).min(self.slots)
+        } else {
+            1
+        };
+        info.trim = words[169] & 0x0001 != 0;
+        info.rotation_rate = words[217];
+        Ok(())
+    }
+
+    // Splits a transfer into bounce-buffer sized commands and keeps up to the
+    // queue depth of them in flight as NCQ tags
+    fn transfer(&mut self, index: usize, lba: u64, count: u32, mut transfer: Transfer) -> IOResult<()> {
+        let info = &self.ports[index].info;
+        if lba + count as u64 > info.sectors {
+            return Err(IOError::AtaError { status: ata::STATUS_DRDY | ata::STATUS_ERR, error: ata::ERROR_IDNF });
+        }
+        let sector_size = info.sector_size as u64;
+        let per_command = (BUFFER_SIZE / sector_size).min(65535) as u32;
+        let depth = info.queue_depth;
+
+        if depth == 1 {
+            let mut done = 0;
+            while done < count {
+                let sectors = per_command.min(count - done);
+                let range = done as usize * sector_size as usize..(done + sectors) as usize * sector_size as usize;
+                let start = lba + done as u64;
+                let command = match &mut transfer {
+                    Transfer::Read(buffer) => {
+                        let fis = RegisterH2D::read_dma_ext(start, sectors as u16);
+                        self.execute(index, fis, Some(Transfer::Read(&mut buffer[range])))
+                    }
+                    Transfer::Write(data) => {
+                        let fis = RegisterH2D::write_dma_ext(start, sectors as u16);
+                        self.execute(index, fis, Some(Transfer::Write(&data[range])))
+                    }
+                };
+                command?;
+                done += sectors;
+            }
+            return Ok(());
+        }
+
+        let mut done = 0;
+        while done < count {
+            // Fill the tags, then let the drive complete them in whatever order it likes
+            let mut batch = Vec::new();
+            let mut mask = 0u32;
+            let mut issued = done;
+            for tag in 0..depth as u8 {
+                if issued == count {
+                    break;
+                }
+                let sectors = per_command.min(count - issued);
+                let start = lba + issued as u64;
+                let (fis, write) = match &transfer {
+                    Transfer::Read(_) => (RegisterH2D::read_fpdma_queued(start, sectors as u16, tag), None),
+                    Transfer::Write(data) => {
+                        let offset = issued as usize * sector_size as usize;
+                        let length = sectors as usize * sector_size as usize;
+                        (RegisterH2D::write_fpdma_queued(start, sectors as u16, tag), Some(&data[offset..offset + length]))
+                    }
+                };
+                self.prepare(index, tag, &fis, sectors as u64 * sector_size, write)?;
+                batch.push((tag, issued, sectors));
+                mask |= 1 << tag;
+                issued += sectors;
+            }
+
+            // SActive must be set before the command is issued
+            let number = self.ports[index].number;
+            self.write_port(number, PORT_SACT, mask)?;
+            self.write_port(number, PORT_CI, mask)?;
+            self.stats.queued_commands += batch.len() as u64;
+            self.stats.max_outstanding = self.stats.max_outstanding.max(batch.len() as u32);
+            self.wait(index, mask, PORT_SACT)?;
+
+            if let Transfer::Read(buffer) = &mut transfer {
+                for &(tag, offset, sectors) in &batch {
+                    let start = offset as usize * sector_size as usize;
+                    let end = start + sectors as usize * sector_size as usize;
+                    let address = self.buffer_address(index, tag);
+                    self.memory.read_physical(address, &mut buffer[start..end]).map_err(|_| IOError::DmaFault)?;
+                }
+            }
+            done = issued;
+        }
+        Ok(())
+    }
+
+    // Issues one non-queued command in slot 0 and waits for it
+    fn execute(&mut self, index: usize, fis: RegisterH2D, transfer: Option<Transfer>) -> IOResult<()> {
+        let bytes = match &transfer {
+            Some(Transfer::Read(buffer)) => buffer.len() as u64,
+            Some(Transfer::Write(data)) => data.len() as u64,
+            None => 0,
+        };
+        if bytes > BUFFER_SIZE {
+            return Err(IOError::InvalidBufferSize);
+        }
+        let write = match &transfer {
+            Some(Transfer::Write(data)) => Some(*data),
+            _ => None,
+        };
+        self.prepare(index, 0, &fis, bytes, write)?;
+
+        let number = self.ports[index].number;
+        self.write_port(number, PORT_CI, 1)?;
+        self.stats.commands += 1;
+        self.wait(index, 1, PORT_CI)?;
+
+        if let Some(Transfer::Read(buffer)) = transfer {
+            let address = self.buffer_address(index, 0);
+            self.memory.read_physical(address, buffer).map_err(|_| IOError::DmaFault)?;
+        }
+        Ok(())
+    }
+
+    // Builds the command header, command table and PRD entry for a slot
+    fn prepare(&mut self, index: usize, slot: u8, fis: &RegisterH2D, bytes: u64, write: Option<&[u8]>) -> IOResult<()> {
+        let base = self.ports[index].base;
+        let table = base + TABLE_AREA + slot as u64 * COMMAND_TABLE_SIZE;
+        let buffer = self.buffer_address(index, slot);
+
+        if let Some(data) = write {
+            self.memory.write_physical(buffer, data).map_err(|_| IOError::DmaFault)?;
+        }
+        let header = CommandHeader {
+            fis_length: (RegisterH2D::LENGTH / 4) as u8,
+            write: write.is_some(),
+            prdt_length: if bytes > 0 { 1 } else { 0 },
+            table_base: table,
+            ..CommandHeader::default()
+        };
+        let memory = &mut self.memory;
+        memory.write_physical(base + slot as u64 * COMMAND_HEADER_SIZE, &header.to_bytes()).map_err(|_| IOError::DmaFault)?;
+        memory.write_physical(table, &fis.to_bytes()).map_err(|_| IOError::DmaFault)?;
+        if bytes > 0 {
+            memory.write_physical(table + PRDT_OFFSET, &prd_entry(buffer, bytes as u32, true)).map_err(|_| IOError::DmaFault)?;
+        }
+        Ok(())
+    }
+
+    // Polls the HBA as an interrupt handler would until the slots in `mask`
+    // clear from the given register (CI for plain commands, SACT for NCQ)
+    fn wait(&mut self, index: usize, mask: u32, register: u32) -> IOResult<()> {
+        let number = self.ports[index].number;
+        for _ in 0..COMMAND_TIMEOUT_POLLS {
+            self.hba.process(&mut self.memory);
+            let status = self.handle_interrupt(number)?;
+            if status & PORT_ERRORS != 0 {
+                return Err(self.recover(number, status)?);
+            }
+            if self.read_port(number, register)? & mask == 0 {
+                return Ok(());
+            }
+        }
+        self.recover(number, 0)?;
+        Err(IOError::Timeout)
+    }
+
+    // Acknowledges the port's interrupt causes, then the port's bit in the HBA
+    fn handle_interrupt(&mut self, number: u8) -> IOResult<u32> {
+        let status = self.read_port(number, PORT_IS)?;
+        if status == 0 {
+            return Ok(0);
+        }
+        self.write_port(number, PORT_IS, status)?;
+        if self.hba.interrupt_pending() {
+            self.hba.write_register(HBA_IS, 1 << number)?;
+            self.stats.interrupts_handled += 1;
+        }
+        Ok(status)
+    }
+
+    // Error handling as libata does it: capture the task file, restart the engine
+    fn recover(&mut self, number: u8, status: u32) -> IOResult<IOError> {
+        self.stats.errors += 1;
+        let task_file = self.read_port(number, PORT_TFD)?;
+        self.stop_engine(number)?;
+        self.write_port(number, PORT_SERR, 0xFFFF_FFFF)?;
+        self.write_port(number, PORT_IS, 0xFFFF_FFFF)?;
+        self.start_engine(number)?;
+        self.stats.recoveries += 1;
+
+        if status & (IS_HBFS | IS_OFS) != 0 {
+            return Ok(IOError::DmaFault);
+        }
+        Ok(IOError::AtaError { status: task_file as u8, error: (task_file >> 8) as u8 })
+    }
+
+    fn stop_engine(&mut self, number: u8) -> IOResult<()> {
+        let command = self.read_port(number, PORT_CMD)?;
+        self.write_port(number, PORT_CMD, command & !CMD_ST)?;
+        let command = self.read_port(number, PORT_CMD)?;
+        self.write_port(number, PORT_CMD, command & !(CMD_ST | CMD_FRE))?;
+        if self.read_port(number, PORT_CMD)? & (CMD_CR | CMD_FR) != 0 {
+            return Err(IOError::DeviceBusy);
+        }
+        Ok(())
+    }
+
+    // FIS receive goes on first, so the device's reply has somewhere to land
+    fn start_engine(&mut self, number: u8) -> IOResult<()> {
+        let command = self.read_port(number, PORT_CMD)?;
+        self.write_port(number, PORT_CMD, command | CMD_FRE)?;
+        self.write_port(number, PORT_CMD, command | CMD_FRE | CMD_ST)?;
+        if self.read_port(number, PORT_CMD)? & CMD_CR == 0 {
+            return Err(IOError::DeviceNotFound);
+        }
+        Ok(())
+    }
+
+    fn port_index(&self, port: u8) -> IOResult<usize> {
+        self.ports.iter().position(|candidate| candidate.number == port).ok_or(IOError::DeviceNotFound)
+    }
+
+    fn buffer_address(&self, index: usize, slot: u8) -> u64 {
+        self.ports[index].base + BUFFER_AREA + slot as u64 * BUFFER_SIZE
+    }
+
+    fn read_port(&self, number: u8, register: u32) -> IOResult<u32> {
+        self.hba.read_register(PORT_BASE + number as u32 * PORT_STRIDE + register)
+    }
+
+    fn write_port(&mut self, number: u8, register: u32, value: u32) -> IOResult<()> {
+        self.hba.write_register(PORT_BASE + number as u32 * PORT_STRIDE + register, value)
+    }
+}
+
+enum Transfer<'a> {
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+impl DiskInfo {
+    fn default_for(port: u8) -> Self {
+        Self {
+            port,
+            model: String::new(),
+            serial: String::new(),
+            sectors: 0,
+            sector_size: 512,
+            queue_depth: 1,
+            trim: false,
+            rotation_rate: 0,
+        }
+    }
+}
+
+// The selected disk as a block device, so filesystems mount straight over AHCI
+impl<M: PhysicalMemory> BlockDevice for AhciDriver<M> {
+    fn device_type(&self) -> BlockDeviceType {
+        match self.ports.get(self.active) {
+            Some(port) if port.info.rotation_rate == 1 => BlockDeviceType::SolidState,
+            _ => BlockDeviceType::HardDisk,
+        }
+    }
+
+    fn sector_size(&self) -> usize {
+        self.ports.get(self.active).map(|port| port.info.sector_size as usize).unwrap_or(512)
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.ports.get(self.active).map(|port| port.info.sectors).unwrap_or(0)
+    }
+
+    fn read_sectors(&mut self, sector: u64, count: u32, buffer: &mut [u8]) -> StorageResult<()> {
+        let port = self.ports.get(self.active).ok_or(StorageError::NotReady)?.number;
+        self.read(port, sector, count, buffer).map_err(storage_error)
+    }
+
+    fn write_sectors(&mut self, sector: u64, data: &[u8]) -> StorageResult<()> {
+        let port = self.ports.get(self.active).ok_or(StorageError::NotReady)?.number;
+        self.write(port, sector, data).map_err(storage_error)
+    }
+
+    fn flush(&mut self) -> StorageResult<()> {
+        let port = self.ports.get(self.active).ok_or(StorageError::NotReady)?.number;
+        AhciDriver::flush(self, port).map_err(storage_error)
+    }
+
+    fn trim_sectors(&mut self, ranges: &[(u64, u32)]) -> StorageResult<()> {
+        let port = self.ports.get(self.active).ok_or(StorageError::NotReady)?.number;
+        self.trim(port, ranges).map_err(storage_error)
+    }
+}
+
+fn storage_error(error: IOError) -> StorageError {
+    match error {
+        IOError::AtaError { error, .. } if error & ata::ERROR_UNC != 0 => StorageError::UncorrectableError,
+        IOError::AtaError { error, .. } if error & ata::ERROR_IDNF != 0 => StorageError::InvalidAddress,
+        IOError::InvalidBufferSize => StorageError::InvalidData,
+        IOError::DeviceNotFound | IOError::Timeout | IOError::DeviceBusy => StorageError::NotReady,
+        other => StorageError::Unsupported(format!("{:?}", other)),
+    }
+}
+
+fn ata_string(words: &[u16]) -> String {
+    let bytes: Vec<u8> = words.iter().flat_map(|word| [(word >> 8) as u8, *word as u8]).collect();
+    String::from_utf8_lossy(&bytes).trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::io::controllers::sata::SataConfig;
+    use crate::hardware::memory::physical::SystemRam;
+    use crate::hardware::storage::device::MemoryBlockDevice;
+
+    fn driver_with_disk(port: u8, sectors: u64) -> AhciDriver<SystemRam> {
+        let mut hba = SataController::new(SataConfig::default());
+        hba.attach(port, Box::new(MemoryBlockDevice::new(sectors, 512)), "SIM SSD", "0001").unwrap();
+        AhciDriver::new(hba, SystemRam::new(PORT_DMA_SIZE as usize + 4096), 4096).unwrap()
+    }
+
+    #[test]
+    fn probe_identifies_only_ports_with_disks() {
+        let driver = driver_with_disk(2, 8192);
+        let disks = driver.disks();
+        assert_eq!(disks.len(), 1);
+        assert_eq!(disks[0].port, 2);
+        assert_eq!(disks[0].model, "SIM SSD");
+        assert_eq!(disks[0].sectors, 8192);
+        assert_eq!(disks[0].queue_depth, 32);
+    }
+
+    #[test]
+    fn large_transfers_round_trip_through_ncq_tags() {
+        let mut driver = driver_with_disk(0, 8192);
+        let data: Vec<u8> = (0..600 * 512).map(|i| (i % 251) as u8).collect();
+        driver.write(0, 100, &data).unwrap();
+
+        let mut read_back = vec![0u8; data.len()];
+        driver.read(0, 100, 600, &mut read_back).unwrap();
+        assert_eq!(read_back, data);
+
+        // 600 sectors at 128 per bounce buffer is five commands in flight at once
+        assert_eq!(driver.get_stats().max_outstanding, 5);
+        assert!(driver.hba().outstanding_tags(0).is_empty());
+    }
+
+    #[test]
+    fn out_of_range_reads_fail_as_invalid_addresses() {
+        let mut driver = driver_with_disk(0, 1024);
+        let mut buffer = vec![0u8; 512];
+        assert_eq!(driver.read_sectors(1024, 1, &mut buffer), Err(StorageError::InvalidAddress));
+        assert_eq!(driver.read_sectors(1023, 1, &mut buffer), Ok(()));
+    }
+}
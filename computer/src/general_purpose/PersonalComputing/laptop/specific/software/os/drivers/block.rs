@@ -1,24 +1,24 @@
-use super::error::{DriverError, DriverResult};
+use crate::hardware::storage::error::StorageResult;
 use crate::hardware::storage::{BlockDevice, BlockDeviceType};
+use std::collections::VecDeque;
 
-pub struct BlockDriver {
-    device: BlockDevice,
+// Uncached: file data is cached once, in the kernel page cache above the filesystems
+pub struct BlockDriver<D: BlockDevice> {
+    device: D,
     queue: CommandQueue,
-    cache: BlockCache,
     stats: BlockStats,
 }
 
 struct CommandQueue {
     pending: VecDeque<BlockCommand>,
-    in_progress: Option<BlockCommand>,
-    completed: VecDeque<BlockCompletion>,
+    // Data finished reads hand back, in submission order
+    completed: VecDeque<Vec<u8>>,
 }
 
 enum BlockCommand {
     Read {
         sector: u64,
         count: u32,
-        buffer: Vec<u8>,
     },
     Write {
         sector: u64,
@@ -30,66 +30,108 @@ enum BlockCommand {
     },
 }
 
-impl BlockDriver {
-    pub fn new(device: BlockDevice) -> Self {
+#[derive(Debug, Clone, Default)]
+pub struct BlockStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub flushes: u64,
+    pub trims: u64,
+    pub sectors_read: u64,
+    pub sectors_written: u64,
+}
+
+impl<D: BlockDevice> BlockDriver<D> {
+    pub fn new(device: D) -> Self {
         Self {
             device,
             queue: CommandQueue::new(),
-            cache: BlockCache::new(),
             stats: BlockStats::default(),
         }
     }
 
-    pub fn read_sector(&mut self, sector: u64) -> DriverResult<Vec<u8>> {
-        // Check cache first
-        if let Some(data) = self.cache.get(sector) {
-            return Ok(data);
-        }
+    pub fn into_inner(self) -> D {
+        self.device
+    }
 
-        // Queue read command
-        let cmd = BlockCommand::Read {
-            sector,
-            count: 1,
-            buffer: vec![0; self.device.sector_size()],
-        };
-        self.queue.push(cmd);
-        self.process_queue()?;
+    pub fn device_type(&self) -> BlockDeviceType {
+        self.device.device_type()
+    }
 
-        Ok(Vec::new()) // Simplified
+    pub fn sector_size(&self) -> usize {
+        self.device.sector_size()
     }
 
-    pub fn write_sector(&mut self, sector: u64, data: &[u8]) -> DriverResult<()> {
-        // Update cache
-        self.cache.insert(sector, data);
+    pub fn read_sector(&mut self, sector: u64) -> StorageResult<Vec<u8>> {
+        self.read_sectors(sector, 1)
+    }
+
+    pub fn read_sectors(&mut self, sector: u64, count: u32) -> StorageResult<Vec<u8>> {
+        self.queue.push(BlockCommand::Read { sector, count });
+        self.process_queue()?;
+        Ok(self.queue.completed.pop_front().unwrap_or_default())
+    }
 
-        // Queue write command
-        let cmd = BlockCommand::Write {
+    pub fn write_sector(&mut self, sector: u64, data: &[u8]) -> StorageResult<()> {
+        self.queue.push(BlockCommand::Write {
             sector,
             data: data.to_vec(),
-        };
-        self.queue.push(cmd);
-        self.process_queue()?;
+        });
+        self.process_queue()
+    }
 
-        Ok(())
+    pub fn flush(&mut self) -> StorageResult<()> {
+        self.queue.push(BlockCommand::Flush);
+        self.process_queue()
+    }
+
+    pub fn trim(&mut self, ranges: &[(u64, u32)]) -> StorageResult<()> {
+        self.queue.push(BlockCommand::Trim { ranges: ranges.to_vec() });
+        self.process_queue()
     }
 
-    fn process_queue(&mut self) -> DriverResult<()> {
-        while let Some(cmd) = self.queue.next_command() {
+    pub fn get_stats(&self) -> &BlockStats {
+        &self.stats
+    }
+
+    // Helper methods
+    fn process_queue(&mut self) -> StorageResult<()> {
+        while let Some(cmd) = self.queue.pending.pop_front() {
             match cmd {
-                BlockCommand::Read { sector, count, buffer } => {
-                    self.device.read_sectors(sector, count, &mut buffer)?;
+                BlockCommand::Read { sector, count } => {
+                    let mut data = vec![0; count as usize * self.device.sector_size()];
+                    self.device.read_sectors(sector, count, &mut data)?;
+                    self.stats.reads += 1;
+                    self.stats.sectors_read += count as u64;
+                    self.queue.completed.push_back(data);
                 }
                 BlockCommand::Write { sector, data } => {
                     self.device.write_sectors(sector, &data)?;
+                    self.stats.writes += 1;
+                    self.stats.sectors_written += (data.len() / self.device.sector_size()) as u64;
                 }
                 BlockCommand::Flush => {
                     self.device.flush()?;
+                    self.stats.flushes += 1;
                 }
                 BlockCommand::Trim { ranges } => {
                     self.device.trim_sectors(&ranges)?;
+                    self.stats.trims += 1;
                 }
             }
         }
         Ok(())
     }
-} 
\ No newline at end of file
+}
+
+impl CommandQueue {
+    fn new() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            completed: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, cmd: BlockCommand) {
+        self.pending.push_back(cmd);
+    }
+}
@@ -1,2 +1,4 @@
 // Will be implemented later
+pub mod ahci;
+pub mod block;
 pub mod video;
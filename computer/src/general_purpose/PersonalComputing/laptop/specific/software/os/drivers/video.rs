@@ -1,4 +1,158 @@
-// Video driver implementation will go here
-pub struct VideoDriver {
-    // TODO: Implement video driver
+use std::sync::Arc;
+use crate::hardware::gpu::command::processor::{
+    CommandProcessor, GPUCommand, CP_RING_BASE_HI, CP_RING_BASE_LO, CP_RING_SIZE, CP_RPTR, CP_WPTR,
+};
+use crate::hardware::gpu::error::{GPUError, GPUResult};
+use crate::hardware::gpu::scheduler::dispatcher::Dispatcher;
+use crate::hardware::gpu::sync::GPUFence;
+use crate::hardware::memory::physical::PhysicalMemory;
+
+// GPU steps a fence wait may take before the driver gives up on the GPU
+const WATCHDOG_STEPS: u64 = 1_000_000;
+
+// Feeds the GPU command processor: writes packets into a ring in physical
+// memory, rings the doorbell and waits on fences the way a kernel driver does.
+// The GPU is stepped from here because nothing else in the machine clocks it yet.
+pub struct VideoDriver<M: PhysicalMemory> {
+    processor: CommandProcessor,
+    dispatcher: Dispatcher,
+    memory: M,
+    ring_base: u64,
+    ring_size: u32,
+    wptr: u32,
+    stats: VideoStats,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VideoStats {
+    pub submissions: u64,
+    pub packets: u64,
+    pub dwords_written: u64,
+    pub ring_full_stalls: u64,
+    pub fence_waits: u64,
+    pub wait_steps: u64,
+}
+
+impl<M: PhysicalMemory> VideoDriver<M> {
+    // Points the CP at a ring of ring_size bytes at ring_base
+    pub fn new(processor: CommandProcessor, dispatcher: Dispatcher, memory: M, ring_base: u64, ring_size: u32) -> GPUResult<Self> {
+        let mut driver = Self {
+            processor,
+            dispatcher,
+            memory,
+            ring_base,
+            ring_size,
+            wptr: 0,
+            stats: VideoStats::default(),
+        };
+        driver.processor.write_register(CP_RING_SIZE, ring_size)?;
+        driver.processor.write_register(CP_RING_BASE_LO, ring_base as u32)?;
+        driver.processor.write_register(CP_RING_BASE_HI, (ring_base >> 32) as u32)?;
+        Ok(driver)
+    }
+
+    pub fn create_fence(&mut self) -> (u32, Arc<GPUFence>) {
+        self.processor.create_fence()
+    }
+
+    // Writes the packets behind the GPU's read pointer and rings the doorbell
+    // once for the whole batch. A full ring stalls the CPU until the GPU has
+    // consumed enough of it.
+    pub fn submit(&mut self, commands: &[GPUCommand]) -> GPUResult<()> {
+        let dwords: Vec<u32> = commands.iter().flat_map(|command| command.encode()).collect();
+        let bytes = dwords.len() as u32 * 4;
+        // One dword stays free so a full ring never looks empty
+        if bytes > self.ring_size - 4 {
+            return Err(GPUError::RingFull);
+        }
+
+        let mut steps = 0;
+        while self.free_space()? < bytes {
+            if steps == WATCHDOG_STEPS {
+                return Err(GPUError::Timeout);
+            }
+            // Make whatever is already written visible so the GPU can drain it
+            self.ring_doorbell()?;
+            self.step();
+            self.stats.ring_full_stalls += 1;
+            steps += 1;
+        }
+
+        for dword in &dwords {
+            self.memory
+                .write_u32(self.ring_base + self.wptr as u64, *dword)
+                .map_err(|_| GPUError::AccessViolation)?;
+            self.wptr = (self.wptr + 4) & (self.ring_size - 1);
+        }
+        self.ring_doorbell()?;
+
+        self.stats.submissions += 1;
+        self.stats.packets += commands.len() as u64;
+        self.stats.dwords_written += dwords.len() as u64;
+        Ok(())
+    }
+
+    // Steps the GPU until the fence reaches value
+    pub fn wait_fence(&mut self, fence: &GPUFence, value: u64) -> GPUResult<()> {
+        self.stats.fence_waits += 1;
+        let mut processor_steps = 0;
+        let result = {
+            let processor = &mut self.processor;
+            let memory = &mut self.memory;
+            let dispatcher = &mut self.dispatcher;
+            fence.wait_with(value, WATCHDOG_STEPS, || {
+                processor.tick(memory, dispatcher);
+                processor_steps += 1;
+            })
+        };
+        self.stats.wait_steps += processor_steps;
+        result.map(|_| ())
+    }
+
+    // Runs the GPU until the ring drains or blocks on a fence
+    pub fn flush(&mut self) -> GPUResult<()> {
+        self.processor.process_commands(&mut self.memory, &mut self.dispatcher)
+    }
+
+    // One GPU clock
+    pub fn step(&mut self) {
+        self.processor.tick(&mut self.memory, &mut self.dispatcher);
+    }
+
+    pub fn processor(&self) -> &CommandProcessor {
+        &self.processor
+    }
+
+    pub fn processor_mut(&mut self) -> &mut CommandProcessor {
+        &mut self.processor
+    }
+
+    pub fn dispatcher(&self) -> &Dispatcher {
+        &self.dispatcher
+    }
+
+    pub fn dispatcher_mut(&mut self) -> &mut Dispatcher {
+        &mut self.dispatcher
+    }
+
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.memory
+    }
+
+    pub fn get_stats(&self) -> &VideoStats {
+        &self.stats
+    }
+
+    // Helper methods
+    fn free_space(&self) -> GPUResult<u32> {
+        let rptr = self.processor.read_register(CP_RPTR)?;
+        Ok((rptr.wrapping_sub(self.wptr).wrapping_sub(4)) & (self.ring_size - 1))
+    }
+
+    fn ring_doorbell(&mut self) -> GPUResult<()> {
+        if self.processor.read_register(CP_WPTR)? != self.wptr {
+            self.processor.write_register(CP_WPTR, self.wptr)?;
+        }
+        Ok(())
+    }
 }
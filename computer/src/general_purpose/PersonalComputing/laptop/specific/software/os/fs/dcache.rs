@@ -0,0 +1,131 @@
+use super::inode::InodeId;
+use std::collections::HashMap;
+
+// Name lookups cached per (mount, parent directory, name); None records a miss
+pub struct DentryCache {
+    entries: HashMap<DentryKey, Dentry>,
+    capacity: usize,
+    clock: u64,
+    stats: DentryStats,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct DentryKey {
+    mount: u32,
+    parent: InodeId,
+    name: String,
+}
+
+struct Dentry {
+    inode: Option<InodeId>,
+    last_used: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DentryStats {
+    pub hits: u64,
+    pub negative_hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub invalidations: u64,
+}
+
+impl DentryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            capacity: capacity.max(1),
+            clock: 0,
+            stats: DentryStats::default(),
+        }
+    }
+
+    // Some(Some(inode)) on a hit, Some(None) on a cached miss, None if unknown
+    pub fn get(&mut self, mount: u32, parent: InodeId, name: &str) -> Option<Option<InodeId>> {
+        self.clock += 1;
+        let key = DentryKey { mount, parent, name: name.to_string() };
+        match self.entries.get_mut(&key) {
+            Some(dentry) => {
+                dentry.last_used = self.clock;
+                match dentry.inode {
+                    Some(_) => self.stats.hits += 1,
+                    None => self.stats.negative_hits += 1,
+                }
+                Some(dentry.inode)
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, mount: u32, parent: InodeId, name: &str, inode: Option<InodeId>) {
+        let key = DentryKey { mount, parent, name: name.to_string() };
+        // Updating a name already cached doesn't need room for a new one
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            self.evict();
+        }
+        self.clock += 1;
+        self.entries.insert(key, Dentry { inode, last_used: self.clock });
+    }
+
+    // Drop every name cached under a directory whose contents changed
+    pub fn invalidate_dir(&mut self, mount: u32, parent: InodeId) {
+        let before = self.entries.len();
+        self.entries.retain(|key, _| key.mount != mount || key.parent != parent);
+        self.stats.invalidations += (before - self.entries.len()) as u64;
+    }
+
+    pub fn invalidate_mount(&mut self, mount: u32) {
+        let before = self.entries.len();
+        self.entries.retain(|key, _| key.mount != mount);
+        self.stats.invalidations += (before - self.entries.len()) as u64;
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get_stats(&self) -> &DentryStats {
+        &self.stats
+    }
+
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, dentry)| dentry.last_used)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+            self.stats.evictions += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updating_a_cached_name_evicts_nothing() {
+        let mut cache = DentryCache::new(2);
+        cache.insert(0, 1, "a", Some(10));
+        cache.insert(0, 1, "b", Some(11));
+        cache.insert(0, 1, "a", None);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get_stats().evictions, 0);
+        assert_eq!(cache.get(0, 1, "a"), Some(None));
+        assert_eq!(cache.get(0, 1, "b"), Some(Some(11)));
+
+        // A new name still pushes out the least recently used one
+        cache.insert(0, 1, "c", Some(12));
+        assert_eq!(cache.get_stats().evictions, 1);
+        assert_eq!(cache.get(0, 1, "a"), None);
+    }
+}
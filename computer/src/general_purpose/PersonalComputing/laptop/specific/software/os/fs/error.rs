@@ -0,0 +1,49 @@
+use crate::hardware::storage::error::StorageError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsError {
+    // Path errors
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    InvalidPath,
+    TooManySymlinks,
+
+    // Descriptor errors
+    BadFileDescriptor,
+    TooManyOpenFiles,
+    InvalidSeek,
+    NoSuchProcess,
+
+    // Mount errors
+    AlreadyMounted,
+    NotMounted,
+    Busy,
+    CrossDevice,
+
+    // Filesystem errors
+    ReadOnly,
+    NoSpace,
+    InvalidArgument,
+    Io(String),
+}
+
+pub type FsResult<T> = Result<T, FsError>;
+
+impl From<StorageError> for FsError {
+    fn from(error: StorageError) -> Self {
+        match error {
+            StorageError::NotFound => FsError::NotFound,
+            StorageError::AlreadyExists => FsError::AlreadyExists,
+            StorageError::NotADirectory => FsError::NotADirectory,
+            StorageError::IsADirectory => FsError::IsADirectory,
+            StorageError::DirectoryNotEmpty => FsError::DirectoryNotEmpty,
+            StorageError::InvalidName => FsError::InvalidPath,
+            StorageError::NoSpace | StorageError::NoFreeBlocks => FsError::NoSpace,
+            StorageError::ReadOnly => FsError::ReadOnly,
+            other => FsError::Io(format!("{:?}", other)),
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use super::error::{FsError, FsResult};
+use super::inode::{DirEntry, FileSystem, FileType, InodeId, Metadata};
+use crate::hardware::storage::filesystem::ext4::{self, Ext4FileSystem};
+use crate::hardware::storage::BlockDevice;
+
+// Mounts an ext4 volume in the VFS. The driver is read-only, so every
+// mutating call keeps the trait's ReadOnly default.
+pub struct Ext4Volume<D: BlockDevice> {
+    fs: Ext4FileSystem<D>,
+}
+
+impl<D: BlockDevice> Ext4Volume<D> {
+    pub fn new(fs: Ext4FileSystem<D>) -> Self {
+        Self { fs }
+    }
+
+    pub fn into_inner(self) -> Ext4FileSystem<D> {
+        self.fs
+    }
+}
+
+impl<D: BlockDevice> FileSystem for Ext4Volume<D> {
+    fn name(&self) -> &str {
+        "ext4"
+    }
+
+    fn root(&self) -> InodeId {
+        self.fs.root_inode() as InodeId
+    }
+
+    fn lookup(&mut self, dir: InodeId, name: &str) -> FsResult<InodeId> {
+        let found = self.fs.lookup_inode(dir as u32, name)?;
+        found.map(|ino| ino as InodeId).ok_or(FsError::NotFound)
+    }
+
+    fn getattr(&mut self, inode: InodeId) -> FsResult<Metadata> {
+        let info = self.fs.inode_metadata(inode as u32)?;
+        Ok(Metadata {
+            inode,
+            device: 0,
+            file_type: file_type(info.file_type),
+            size: info.size,
+            permissions: info.permissions,
+            links: info.links as u32,
+            modified: info.mtime as u64,
+        })
+    }
+
+    fn readdir(&mut self, dir: InodeId) -> FsResult<Vec<DirEntry>> {
+        Ok(self
+            .fs
+            .read_dir_inode(dir as u32)?
+            .into_iter()
+            .map(|entry| DirEntry {
+                name: entry.name,
+                inode: entry.inode as InodeId,
+                file_type: file_type(entry.file_type),
+            })
+            .collect())
+    }
+
+    fn read(&mut self, inode: InodeId, offset: u64, buffer: &mut [u8]) -> FsResult<usize> {
+        Ok(self.fs.read_inode_at(inode as u32, offset, buffer)?)
+    }
+
+    fn readlink(&mut self, inode: InodeId) -> FsResult<String> {
+        Ok(self.fs.read_link_inode(inode as u32)?)
+    }
+}
+
+// Device nodes, FIFOs and sockets have no VFS counterpart yet and read as plain files
+fn file_type(file_type: ext4::FileType) -> FileType {
+    match file_type {
+        ext4::FileType::Directory => FileType::Directory,
+        ext4::FileType::Symlink => FileType::Symlink,
+        _ => FileType::Regular,
+    }
+}
@@ -0,0 +1,270 @@
+use super::error::{FsError, FsResult};
+use super::inode::{DirEntry, FileSystem, FileType, InodeId, Metadata};
+use crate::hardware::storage::filesystem::fat::{FatFileSystem, FileAttributes, FileInfo};
+use crate::hardware::storage::BlockDevice;
+use std::collections::HashMap;
+
+const ROOT_INODE: InodeId = 1;
+
+// Mounts a FAT volume in the VFS. FAT has no inode numbers, so every path gets
+// one when first looked up and keeps it until it is removed.
+pub struct FatVolume<D: BlockDevice> {
+    fs: FatFileSystem<D>,
+    paths: HashMap<InodeId, String>,
+    // Keyed by upper-cased path, since FAT names are case-insensitive. Only
+    // ASCII letters fold, matching how the driver compares names.
+    inodes: HashMap<String, InodeId>,
+    next_inode: InodeId,
+}
+
+impl<D: BlockDevice> FatVolume<D> {
+    pub fn new(fs: FatFileSystem<D>) -> Self {
+        let mut volume = Self {
+            fs,
+            paths: HashMap::new(),
+            inodes: HashMap::new(),
+            next_inode: ROOT_INODE,
+        };
+        volume.inode_for("/".to_string());
+        volume
+    }
+
+    pub fn into_inner(self) -> FatFileSystem<D> {
+        self.fs
+    }
+
+    fn path(&self, inode: InodeId) -> FsResult<String> {
+        self.paths.get(&inode).cloned().ok_or(FsError::NotFound)
+    }
+
+    fn inode_for(&mut self, path: String) -> InodeId {
+        if let Some(&inode) = self.inodes.get(&path.to_ascii_uppercase()) {
+            return inode;
+        }
+        let inode = self.next_inode;
+        self.next_inode += 1;
+        self.inodes.insert(path.to_ascii_uppercase(), inode);
+        self.paths.insert(inode, path);
+        inode
+    }
+
+    // Drop the numbers of a removed path and everything below it
+    fn forget(&mut self, path: &str) {
+        let key = path.to_ascii_uppercase();
+        let prefix = format!("{}/", key);
+        let doomed: Vec<String> = self
+            .inodes
+            .keys()
+            .filter(|candidate| **candidate == key || candidate.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for candidate in doomed {
+            if let Some(inode) = self.inodes.remove(&candidate) {
+                self.paths.remove(&inode);
+            }
+        }
+    }
+
+    // Keep inode numbers stable when a file or directory tree is renamed.
+    // The part below `old` is found by counting components.
+    fn rebase(&mut self, old: &str, new: &str) {
+        let key = old.to_ascii_uppercase();
+        let prefix = format!("{}/", key);
+        let depth = old.split('/').count();
+        let moved: Vec<(String, InodeId)> = self
+            .inodes
+            .iter()
+            .filter(|(candidate, _)| **candidate == key || candidate.starts_with(&prefix))
+            .map(|(candidate, &inode)| (candidate.clone(), inode))
+            .collect();
+        for (candidate, inode) in moved {
+            self.inodes.remove(&candidate);
+            let path = self.paths[&inode]
+                .split('/')
+                .skip(depth)
+                .fold(new.to_string(), |path, component| format!("{}/{}", path, component));
+            self.inodes.insert(path.to_ascii_uppercase(), inode);
+            self.paths.insert(inode, path);
+        }
+    }
+
+    fn metadata_from(inode: InodeId, info: &FileInfo) -> Metadata {
+        let is_directory = info.attributes.contains(FileAttributes::DIRECTORY);
+        let read_only = info.attributes.contains(FileAttributes::READ_ONLY);
+        Metadata {
+            inode,
+            device: 0,
+            file_type: if is_directory { FileType::Directory } else { FileType::Regular },
+            size: info.size as u64,
+            permissions: match (is_directory, read_only) {
+                (true, _) => 0o755,
+                (false, true) => 0o444,
+                (false, false) => 0o644,
+            },
+            links: 1,
+            modified: info.modified.to_unix(),
+        }
+    }
+}
+
+impl<D: BlockDevice> FileSystem for FatVolume<D> {
+    fn name(&self) -> &str {
+        "vfat"
+    }
+
+    fn root(&self) -> InodeId {
+        ROOT_INODE
+    }
+
+    fn lookup(&mut self, dir: InodeId, name: &str) -> FsResult<InodeId> {
+        let parent = self.path(dir)?;
+        let info = self.fs.metadata(&child_path(&parent, name))?;
+        // Record the name as stored on disk, whatever case it was looked up with
+        Ok(self.inode_for(child_path(&parent, &info.name)))
+    }
+
+    fn getattr(&mut self, inode: InodeId) -> FsResult<Metadata> {
+        let path = self.path(inode)?;
+        if inode == ROOT_INODE {
+            return Ok(Metadata {
+                inode,
+                device: 0,
+                file_type: FileType::Directory,
+                size: 0,
+                permissions: 0o755,
+                links: 1,
+                modified: 0,
+            });
+        }
+        let info = self.fs.metadata(&path)?;
+        Ok(Self::metadata_from(inode, &info))
+    }
+
+    fn readdir(&mut self, dir: InodeId) -> FsResult<Vec<DirEntry>> {
+        let parent = self.path(dir)?;
+        let listing = self.fs.read_dir(&parent)?;
+        Ok(listing
+            .into_iter()
+            .map(|info| DirEntry {
+                inode: self.inode_for(child_path(&parent, &info.name)),
+                file_type: if info.attributes.contains(FileAttributes::DIRECTORY) {
+                    FileType::Directory
+                } else {
+                    FileType::Regular
+                },
+                name: info.name,
+            })
+            .collect())
+    }
+
+    fn read(&mut self, inode: InodeId, offset: u64, buffer: &mut [u8]) -> FsResult<usize> {
+        let path = self.path(inode)?;
+        Ok(self.fs.read_at(&path, offset, buffer)?)
+    }
+
+    fn write(&mut self, inode: InodeId, offset: u64, data: &[u8]) -> FsResult<usize> {
+        let path = self.path(inode)?;
+        Ok(self.fs.write_at(&path, offset, data)?)
+    }
+
+    fn truncate(&mut self, inode: InodeId, size: u64) -> FsResult<()> {
+        let path = self.path(inode)?;
+        Ok(self.fs.truncate(&path, size)?)
+    }
+
+    fn create(&mut self, dir: InodeId, name: &str, file_type: FileType) -> FsResult<InodeId> {
+        let path = child_path(&self.path(dir)?, name);
+        match file_type {
+            FileType::Regular => self.fs.create_file(&path, FileAttributes::empty())?,
+            FileType::Directory => self.fs.create_dir(&path)?,
+            FileType::Symlink => return Err(FsError::InvalidArgument),
+        }
+        Ok(self.inode_for(path))
+    }
+
+    // FAT cannot store symbolic links
+    fn symlink(&mut self, _dir: InodeId, _name: &str, _target: &str) -> FsResult<InodeId> {
+        Err(FsError::InvalidArgument)
+    }
+
+    fn unlink(&mut self, dir: InodeId, name: &str) -> FsResult<()> {
+        let path = child_path(&self.path(dir)?, name);
+        self.fs.remove(&path)?;
+        self.forget(&path);
+        Ok(())
+    }
+
+    fn rmdir(&mut self, dir: InodeId, name: &str) -> FsResult<()> {
+        self.unlink(dir, name)
+    }
+
+    fn rename(&mut self, old_dir: InodeId, old_name: &str, new_dir: InodeId, new_name: &str) -> FsResult<()> {
+        let old_parent = self.path(old_dir)?;
+        let old = child_path(&old_parent, &self.fs.metadata(&child_path(&old_parent, old_name))?.name);
+        let new = child_path(&self.path(new_dir)?, new_name);
+        self.fs.rename(&old, &new)?;
+        if !old.eq_ignore_ascii_case(&new) {
+            self.forget(&new);
+        }
+        self.rebase(&old, &new);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> FsResult<()> {
+        Ok(self.fs.flush()?)
+    }
+}
+
+fn child_path(parent: &str, name: &str) -> String {
+    if parent == "/" {
+        format!("/{}", name)
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::storage::device::MemoryBlockDevice;
+    use crate::hardware::storage::filesystem::fat::FatType;
+
+    #[test]
+    fn renaming_a_non_ascii_tree_keeps_inode_numbers() {
+        let device = MemoryBlockDevice::new(8 * 2048, 512);
+        let mut volume = FatVolume::new(FatFileSystem::format(device, Some(FatType::Fat16), "TEST").unwrap());
+        let root = volume.root();
+        let dir = volume.create(root, "Straße", FileType::Directory).unwrap();
+        let nested = volume.create(dir, "Übersicht", FileType::Directory).unwrap();
+        let file = volume.create(nested, "naïve.txt", FileType::Regular).unwrap();
+        volume.write(file, 0, "grüße".as_bytes()).unwrap();
+
+        volume.rename(root, "Straße", root, "ß-Ä").unwrap();
+        let dir_again = volume.lookup(root, "ß-Ä").unwrap();
+        let nested_again = volume.lookup(dir_again, "Übersicht").unwrap();
+        assert_eq!((dir_again, nested_again), (dir, nested));
+        assert_eq!(volume.lookup(nested_again, "naïve.txt").unwrap(), file);
+
+        let mut buffer = [0u8; 16];
+        let length = volume.read(file, 0, &mut buffer).unwrap();
+        assert_eq!(&buffer[..length], "grüße".as_bytes());
+    }
+
+    #[test]
+    fn only_ascii_letters_fold_when_keying_names() {
+        let device = MemoryBlockDevice::new(8 * 2048, 512);
+        let mut volume = FatVolume::new(FatFileSystem::format(device, Some(FatType::Fat16), "TEST").unwrap());
+        let root = volume.root();
+
+        // Unicode upper-casing would give both the key "STRASSE"
+        let sharp = volume.create(root, "straße", FileType::Regular).unwrap();
+        let plain = volume.create(root, "STRASSE", FileType::Regular).unwrap();
+        assert_ne!(sharp, plain);
+        assert_eq!(volume.lookup(root, "STRAßE").unwrap(), sharp);
+        assert_eq!(volume.lookup(root, "strasse").unwrap(), plain);
+
+        let accented = volume.create(root, "Übersicht", FileType::Regular).unwrap();
+        assert_eq!(volume.lookup(root, "ÜBERSICHT").unwrap(), accented);
+        assert_eq!(volume.lookup(root, "übersicht"), Err(FsError::NotFound));
+    }
+}
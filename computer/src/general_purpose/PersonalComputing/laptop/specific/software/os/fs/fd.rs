@@ -0,0 +1,143 @@
+use super::error::{FsError, FsResult};
+use super::inode::InodeId;
+
+pub type Fd = usize;
+
+const DEFAULT_FD_LIMIT: usize = 1024;
+
+// Identifies an open file description, which dup'd and inherited descriptors share
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileHandle(pub u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OpenFlags(u32);
+
+impl OpenFlags {
+    pub const READ: Self = Self(0x0001);
+    pub const WRITE: Self = Self(0x0002);
+    pub const CREATE: Self = Self(0x0040);
+    pub const EXCLUSIVE: Self = Self(0x0080);
+    pub const TRUNCATE: Self = Self(0x0200);
+    pub const APPEND: Self = Self(0x0400);
+    pub const DIRECTORY: Self = Self(0x1_0000);
+    pub const NO_FOLLOW: Self = Self(0x2_0000);
+    pub const READ_WRITE: Self = Self(Self::READ.0 | Self::WRITE.0);
+
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for OpenFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+// A (mount, inode) pair naming one file anywhere in the namespace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Location {
+    pub mount: u32,
+    pub inode: InodeId,
+}
+
+pub struct OpenFile {
+    pub location: Location,
+    pub path: String,
+    pub flags: OpenFlags,
+    pub offset: u64,
+    pub references: u32,
+    // Set once the file is unlinked while open; the VFS holds its data from then on
+    pub orphan: Option<u64>,
+}
+
+// Per-process descriptor table and working directory
+#[derive(Clone)]
+pub struct FdTable {
+    slots: Vec<Option<FileHandle>>,
+    limit: usize,
+    pub cwd: String,
+}
+
+impl OpenFile {
+    pub fn new(location: Location, path: String, flags: OpenFlags) -> Self {
+        Self { location, path, flags, offset: 0, references: 1, orphan: None }
+    }
+}
+
+impl FdTable {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            limit: DEFAULT_FD_LIMIT,
+            cwd: "/".to_string(),
+        }
+    }
+
+    // POSIX hands out the lowest free descriptor
+    pub fn allocate(&mut self, handle: FileHandle) -> FsResult<Fd> {
+        if let Some(fd) = self.slots.iter().position(|slot| slot.is_none()) {
+            self.slots[fd] = Some(handle);
+            return Ok(fd);
+        }
+        if self.slots.len() >= self.limit {
+            return Err(FsError::TooManyOpenFiles);
+        }
+        self.slots.push(Some(handle));
+        Ok(self.slots.len() - 1)
+    }
+
+    pub fn get(&self, fd: Fd) -> FsResult<FileHandle> {
+        self.slots.get(fd).copied().flatten().ok_or(FsError::BadFileDescriptor)
+    }
+
+    pub fn release(&mut self, fd: Fd) -> FsResult<FileHandle> {
+        let handle = self.get(fd)?;
+        self.slots[fd] = None;
+        Ok(handle)
+    }
+
+    pub fn handles(&self) -> impl Iterator<Item = FileHandle> + '_ {
+        self.slots.iter().flatten().copied()
+    }
+
+    pub fn open_count(&self) -> usize {
+        self.slots.iter().flatten().count()
+    }
+}
+
+impl Default for FdTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn descriptors_come_from_the_lowest_free_slot_up_to_the_limit() {
+        let mut table = FdTable::new();
+        for fd in 0..DEFAULT_FD_LIMIT {
+            assert_eq!(table.allocate(FileHandle(fd as u64)).unwrap(), fd);
+        }
+        assert_eq!(table.allocate(FileHandle(0)), Err(FsError::TooManyOpenFiles));
+
+        assert_eq!(table.release(7).unwrap(), FileHandle(7));
+        assert_eq!(table.release(7), Err(FsError::BadFileDescriptor));
+        assert_eq!(table.open_count(), DEFAULT_FD_LIMIT - 1);
+        assert_eq!(table.allocate(FileHandle(99)).unwrap(), 7);
+        assert_eq!(table.get(7).unwrap(), FileHandle(99));
+    }
+}
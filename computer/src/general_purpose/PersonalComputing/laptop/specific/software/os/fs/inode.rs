@@ -0,0 +1,82 @@
+use super::error::{FsError, FsResult};
+
+pub type InodeId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub inode: InodeId,
+    pub device: u32,
+    pub file_type: FileType,
+    pub size: u64,
+    pub permissions: u16,
+    pub links: u32,
+    pub modified: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirEntry {
+    pub name: String,
+    pub inode: InodeId,
+    pub file_type: FileType,
+}
+
+// What a concrete filesystem implements to be mounted in the VFS. Everything is
+// addressed by inode number; path walking, symlinks and mounts are the VFS's job.
+pub trait FileSystem {
+    fn name(&self) -> &str;
+    fn root(&self) -> InodeId;
+    fn lookup(&mut self, dir: InodeId, name: &str) -> FsResult<InodeId>;
+    fn getattr(&mut self, inode: InodeId) -> FsResult<Metadata>;
+    fn readdir(&mut self, dir: InodeId) -> FsResult<Vec<DirEntry>>;
+    fn read(&mut self, inode: InodeId, offset: u64, buffer: &mut [u8]) -> FsResult<usize>;
+
+    // Filesystems whose data already lives in RAM, or is generated on read, opt out
+    fn uses_page_cache(&self) -> bool {
+        true
+    }
+
+    fn readlink(&mut self, _inode: InodeId) -> FsResult<String> {
+        Err(FsError::InvalidArgument)
+    }
+
+    // Mutating operations default to a read-only filesystem
+    fn write(&mut self, _inode: InodeId, _offset: u64, _data: &[u8]) -> FsResult<usize> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn truncate(&mut self, _inode: InodeId, _size: u64) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn create(&mut self, _dir: InodeId, _name: &str, _file_type: FileType) -> FsResult<InodeId> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn symlink(&mut self, _dir: InodeId, _name: &str, _target: &str) -> FsResult<InodeId> {
+        Err(FsError::ReadOnly)
+    }
+
+    // Removes a non-directory; the VFS checks the type first
+    fn unlink(&mut self, _dir: InodeId, _name: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn rmdir(&mut self, _dir: InodeId, _name: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn rename(&mut self, _old_dir: InodeId, _old_name: &str, _new_dir: InodeId, _new_name: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn sync(&mut self) -> FsResult<()> {
+        Ok(())
+    }
+}
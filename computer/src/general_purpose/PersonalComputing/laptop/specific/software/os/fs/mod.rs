@@ -1,7 +1,42 @@
+pub mod dcache;
+pub mod error;
+pub mod ext4;
+pub mod fat;
+pub mod fd;
+pub mod inode;
+pub mod ntfs;
+pub mod page_cache;
+pub mod procfs;
+pub mod tmpfs;
 pub mod vfs;
 
+pub use error::{FsError, FsResult};
+pub use fd::{Fd, FileHandle, OpenFlags};
+pub use inode::{DirEntry, FileSystem, FileType, InodeId, Metadata};
+pub use page_cache::{PageCache, PageCacheStats, PAGE_SIZE};
 pub use vfs::VirtualFileSystem;
 
+use self::ext4::Ext4Volume;
+use self::fat::FatVolume;
+use self::ntfs::NtfsVolume;
+use crate::hardware::storage::filesystem::ext4::Ext4FileSystem;
+use crate::hardware::storage::filesystem::fat::FatFileSystem;
+use crate::hardware::storage::filesystem::ntfs::NtfsFileSystem;
+use crate::hardware::storage::filesystem::FileSystemKind;
+use crate::hardware::storage::BlockDevice;
+
+// Mounts a volume with the driver for its on-disk format, e.g. an NTFS
+// root volume on a Windows laptop
+pub fn mount_volume<D: BlockDevice + 'static>(kind: FileSystemKind, device: D) -> FsResult<Box<dyn FileSystem>> {
+    match kind {
+        FileSystemKind::NTFS => Ok(Box::new(NtfsVolume::new(NtfsFileSystem::mount(device)?))),
+        FileSystemKind::Ext4 => Ok(Box::new(Ext4Volume::new(Ext4FileSystem::mount(device)?))),
+        FileSystemKind::FAT32 => Ok(Box::new(FatVolume::new(FatFileSystem::mount(device)?))),
+        // There is no APFS driver to mount with
+        FileSystemKind::APFS => Err(FsError::InvalidArgument),
+    }
+}
+
 #[derive(Debug)]
 pub enum FileSystemError {
     FileNotFound,
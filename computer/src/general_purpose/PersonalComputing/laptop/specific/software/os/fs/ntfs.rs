@@ -0,0 +1,73 @@
+use super::error::{FsError, FsResult};
+use super::inode::{DirEntry, FileSystem, FileType, InodeId, Metadata};
+use crate::hardware::storage::filesystem::ntfs::{FileAttributes, NtfsFileSystem};
+use crate::hardware::storage::BlockDevice;
+
+// NTFS timestamps count 100 ns ticks from 1601-01-01
+const TICKS_PER_SECOND: u64 = 10_000_000;
+const SECONDS_1601_TO_1970: u64 = 11_644_473_600;
+
+// Mounts an NTFS volume in the VFS, with MFT record numbers as inode numbers.
+// The driver is read-only, so every mutating call keeps the trait's ReadOnly default.
+pub struct NtfsVolume<D: BlockDevice> {
+    fs: NtfsFileSystem<D>,
+}
+
+impl<D: BlockDevice> NtfsVolume<D> {
+    pub fn new(fs: NtfsFileSystem<D>) -> Self {
+        Self { fs }
+    }
+
+    pub fn into_inner(self) -> NtfsFileSystem<D> {
+        self.fs
+    }
+}
+
+impl<D: BlockDevice> FileSystem for NtfsVolume<D> {
+    fn name(&self) -> &str {
+        "ntfs"
+    }
+
+    fn root(&self) -> InodeId {
+        self.fs.root_record()
+    }
+
+    fn lookup(&mut self, dir: InodeId, name: &str) -> FsResult<InodeId> {
+        self.fs.lookup_record(dir, name)?.ok_or(FsError::NotFound)
+    }
+
+    fn getattr(&mut self, inode: InodeId) -> FsResult<Metadata> {
+        let info = self.fs.record_metadata(inode)?;
+        let read_only = info.file_attributes.contains(FileAttributes::READ_ONLY);
+        Ok(Metadata {
+            inode,
+            device: 0,
+            file_type: if info.is_directory { FileType::Directory } else { FileType::Regular },
+            size: info.size,
+            permissions: match (info.is_directory, read_only) {
+                (true, _) => 0o755,
+                (false, true) => 0o444,
+                (false, false) => 0o644,
+            },
+            links: info.link_count as u32,
+            modified: (info.modification_time / TICKS_PER_SECOND).saturating_sub(SECONDS_1601_TO_1970),
+        })
+    }
+
+    fn readdir(&mut self, dir: InodeId) -> FsResult<Vec<DirEntry>> {
+        Ok(self
+            .fs
+            .read_dir_record(dir)?
+            .into_iter()
+            .map(|entry| DirEntry {
+                name: entry.name,
+                inode: entry.record,
+                file_type: if entry.is_directory { FileType::Directory } else { FileType::Regular },
+            })
+            .collect())
+    }
+
+    fn read(&mut self, inode: InodeId, offset: u64, buffer: &mut [u8]) -> FsResult<usize> {
+        Ok(self.fs.read_record_at(inode, offset, buffer)?)
+    }
+}
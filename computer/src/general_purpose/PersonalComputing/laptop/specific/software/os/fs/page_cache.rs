@@ -0,0 +1,565 @@
+use super::error::{FsError, FsResult};
+use super::fd::Location;
+use super::inode::FileSystem;
+use std::collections::{BTreeMap, HashMap};
+
+pub const PAGE_SIZE: usize = 4096;
+
+// Sequential readers get a window that doubles from MIN up to MAX pages
+const READAHEAD_MIN_PAGES: u64 = 4;
+const READAHEAD_MAX_PAGES: u64 = 32;
+
+// The flusher wakes every interval and writes back pages dirty for longer than expire
+const WRITEBACK_INTERVAL_MS: u64 = 5_000;
+const DIRTY_EXPIRE_MS: u64 = 30_000;
+
+// Writers are throttled into writeback once this share of the cache is dirty
+const DIRTY_RATIO_PERCENT: usize = 20;
+
+// File data cached in RAM, keyed by (mount, inode, page index). Sits between the
+// VFS and every filesystem, so the block layer below only ever sees misses and writeback.
+pub struct PageCache {
+    pages: HashMap<PageKey, Page>,
+    files: HashMap<Location, CachedFile>,
+    // Least recently used first
+    lru: BTreeMap<u64, PageKey>,
+    capacity: usize,
+    dirty: usize,
+    clock: u64,
+    // Milliseconds of simulated time, advanced by the VFS
+    now: u64,
+    last_writeback: u64,
+    stats: PageCacheStats,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct PageKey {
+    location: Location,
+    index: u64,
+}
+
+struct Page {
+    data: Vec<u8>,
+    dirty: bool,
+    dirtied_at: u64,
+    last_used: u64,
+    // Brought in by readahead and not yet read
+    readahead: bool,
+}
+
+// While a file has pages cached its size lives here, ahead of the filesystem's copy
+struct CachedFile {
+    size: u64,
+    pages: usize,
+    next_offset: u64,
+    window: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PageCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub readahead_pages: u64,
+    pub readahead_hits: u64,
+    pub pages_read: u64,
+    pub pages_written: u64,
+    pub writeback_runs: u64,
+    pub evictions: u64,
+    pub invalidations: u64,
+    pub bytes_from_cache: u64,
+    pub bytes_from_device: u64,
+}
+
+impl PageCacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+impl PageCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            pages: HashMap::new(),
+            files: HashMap::new(),
+            lru: BTreeMap::new(),
+            capacity: capacity.max(1),
+            dirty: 0,
+            clock: 0,
+            now: 0,
+            last_writeback: 0,
+            stats: PageCacheStats::default(),
+        }
+    }
+
+    pub fn read(&mut self, fs: &mut dyn FileSystem, location: Location, offset: u64, buffer: &mut [u8]) -> FsResult<usize> {
+        let size = self.load_file(fs, location)?;
+        if offset >= size || buffer.is_empty() {
+            return Ok(0);
+        }
+        let end = size.min(offset + buffer.len() as u64);
+        let sequential = self.files.get(&location).is_some_and(|file| file.next_offset == offset) || offset == 0;
+
+        let mut position = offset;
+        while position < end {
+            let key = PageKey { location, index: position / PAGE_SIZE as u64 };
+            let start = (position % PAGE_SIZE as u64) as usize;
+            let count = (PAGE_SIZE - start).min((end - position) as usize);
+
+            if self.touch(key) {
+                self.stats.hits += 1;
+                self.stats.bytes_from_cache += count as u64;
+            } else {
+                self.stats.misses += 1;
+                self.stats.bytes_from_device += count as u64;
+                self.fill(fs, key, size, false)?;
+            }
+            let page = &self.pages[&key];
+            let done = (position - offset) as usize;
+            buffer[done..done + count].copy_from_slice(&page.data[start..start + count]);
+            position += count as u64;
+        }
+
+        if let Some(file) = self.files.get_mut(&location) {
+            file.next_offset = end;
+        }
+        if sequential {
+            self.readahead(fs, location, (end - 1) / PAGE_SIZE as u64 + 1, size)?;
+        } else if let Some(file) = self.files.get_mut(&location) {
+            file.window = 0;
+        }
+        Ok((end - offset) as usize)
+    }
+
+    // Writes land in the cache only; the filesystem sees them at writeback
+    pub fn write(&mut self, fs: &mut dyn FileSystem, location: Location, offset: u64, data: &[u8]) -> FsResult<usize> {
+        let size = self.load_file(fs, location)?;
+        let end = offset + data.len() as u64;
+
+        let mut position = offset;
+        while position < end {
+            let key = PageKey { location, index: position / PAGE_SIZE as u64 };
+            let start = (position % PAGE_SIZE as u64) as usize;
+            let count = (PAGE_SIZE - start).min((end - position) as usize);
+
+            if !self.touch(key) {
+                // Partial pages holding existing data must be read before they are modified
+                let page_start = key.index * PAGE_SIZE as u64;
+                let whole = start == 0 && count == PAGE_SIZE;
+                self.fill(fs, key, size, whole || page_start >= size)?;
+            }
+            let now = self.now;
+            let page = self.pages.get_mut(&key).ok_or_else(|| FsError::Io("page lost after fill".to_string()))?;
+            let done = (position - offset) as usize;
+            page.data[start..start + count].copy_from_slice(&data[done..done + count]);
+            if !page.dirty {
+                page.dirty = true;
+                page.dirtied_at = now;
+                self.dirty += 1;
+            }
+            position += count as u64;
+            // Grown page by page, so a page evicted mid-write is written back in full
+            if let Some(file) = self.files.get_mut(&location) {
+                file.size = file.size.max(position);
+            }
+        }
+        Ok(data.len())
+    }
+
+    // Drops pages past the new end and zeroes the tail of the last one. The
+    // caller truncates the filesystem too so both agree on the size.
+    pub fn truncate(&mut self, location: Location, size: u64) {
+        let doomed: Vec<PageKey> = self
+            .pages
+            .keys()
+            .filter(|key| key.location == location && key.index * PAGE_SIZE as u64 >= size)
+            .copied()
+            .collect();
+        for key in doomed {
+            self.remove_page(key);
+        }
+
+        let tail = (size % PAGE_SIZE as u64) as usize;
+        let key = PageKey { location, index: size / PAGE_SIZE as u64 };
+        if let Some(page) = self.pages.get_mut(&key) {
+            page.data[tail..].iter_mut().for_each(|byte| *byte = 0);
+        }
+        if let Some(file) = self.files.get_mut(&location) {
+            file.size = size;
+        }
+    }
+
+    pub fn cached_size(&self, location: Location) -> Option<u64> {
+        self.files.get(&location).map(|file| file.size)
+    }
+
+    // Writes back a mount's dirty pages, or only those older than the expire time
+    pub fn writeback(&mut self, mount: u32, fs: &mut dyn FileSystem, expired_only: bool) -> FsResult<usize> {
+        let now = self.now;
+        let mut keys: Vec<PageKey> = self
+            .pages
+            .iter()
+            .filter(|(key, page)| key.location.mount == mount && page.dirty)
+            .filter(|(_, page)| !expired_only || now - page.dirtied_at >= DIRTY_EXPIRE_MS)
+            .map(|(key, _)| *key)
+            .collect();
+        // In file order, so the block layer sees sequential writes
+        keys.sort_by_key(|key| (key.location.inode, key.index));
+        self.stats.writeback_runs += 1;
+
+        let mut written = 0;
+        for key in keys {
+            if self.write_back_page(fs, key)? {
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+
+    // Advances simulated time; true when the periodic flusher is due
+    pub fn advance(&mut self, elapsed_ms: u64) -> bool {
+        self.now += elapsed_ms;
+        if self.now - self.last_writeback >= WRITEBACK_INTERVAL_MS {
+            self.last_writeback = self.now;
+            return true;
+        }
+        false
+    }
+
+    // True once writers should be pushed into writeback
+    pub fn over_dirty_limit(&self) -> bool {
+        self.dirty * 100 > self.capacity * DIRTY_RATIO_PERCENT
+    }
+
+    // Reclaims up to the given number of clean pages, oldest first
+    pub fn shrink(&mut self, pages: usize) -> usize {
+        let victims: Vec<PageKey> = self
+            .lru
+            .values()
+            .filter(|key| !self.pages[key].dirty)
+            .take(pages)
+            .copied()
+            .collect();
+        for key in &victims {
+            self.remove_page(*key);
+        }
+        self.stats.evictions += victims.len() as u64;
+        victims.len()
+    }
+
+    // Forgets a file entirely, dirty data included
+    pub fn invalidate(&mut self, location: Location) {
+        let doomed: Vec<PageKey> = self.pages.keys().filter(|key| key.location == location).copied().collect();
+        self.stats.invalidations += doomed.len() as u64;
+        for key in doomed {
+            self.remove_page(key);
+        }
+        self.files.remove(&location);
+    }
+
+    pub fn invalidate_mount(&mut self, mount: u32) {
+        let doomed: Vec<Location> = self.files.keys().filter(|location| location.mount == mount).copied().collect();
+        for location in doomed {
+            self.invalidate(location);
+        }
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        let excess = self.pages.len().saturating_sub(self.capacity);
+        self.shrink(excess);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    pub fn dirty_pages(&self) -> usize {
+        self.dirty
+    }
+
+    pub fn get_stats(&self) -> &PageCacheStats {
+        &self.stats
+    }
+
+    // Helper methods
+
+    fn load_file(&mut self, fs: &mut dyn FileSystem, location: Location) -> FsResult<u64> {
+        if let Some(file) = self.files.get(&location) {
+            return Ok(file.size);
+        }
+        let size = fs.getattr(location.inode)?.size;
+        self.files.insert(location, CachedFile { size, pages: 0, next_offset: 0, window: 0 });
+        Ok(size)
+    }
+
+    // Marks a cached page as used; false on a miss
+    fn touch(&mut self, key: PageKey) -> bool {
+        self.clock += 1;
+        let clock = self.clock;
+        let page = match self.pages.get_mut(&key) {
+            Some(page) => page,
+            None => return false,
+        };
+        self.lru.remove(&page.last_used);
+        self.lru.insert(clock, key);
+        page.last_used = clock;
+        if page.readahead {
+            page.readahead = false;
+            self.stats.readahead_hits += 1;
+        }
+        true
+    }
+
+    // Loads a page from the filesystem, or starts it zeroed when nothing there is worth reading
+    fn fill(&mut self, fs: &mut dyn FileSystem, key: PageKey, size: u64, zeroed: bool) -> FsResult<()> {
+        self.make_room(fs, key.location.mount)?;
+        // Making room may have dropped this file's last page and its size with it
+        self.load_file(fs, key.location)?;
+
+        let mut data = vec![0; PAGE_SIZE];
+        let start = key.index * PAGE_SIZE as u64;
+        if !zeroed && start < size {
+            let length = (size - start).min(PAGE_SIZE as u64) as usize;
+            let mut done = 0;
+            while done < length {
+                let count = fs.read(key.location.inode, start + done as u64, &mut data[done..length])?;
+                if count == 0 {
+                    break;
+                }
+                done += count;
+            }
+            self.stats.pages_read += 1;
+        }
+
+        self.clock += 1;
+        self.lru.insert(self.clock, key);
+        self.pages.insert(key, Page { data, dirty: false, dirtied_at: 0, last_used: self.clock, readahead: false });
+        if let Some(file) = self.files.get_mut(&key.location) {
+            file.pages += 1;
+        }
+        Ok(())
+    }
+
+    // Keeps the window ahead of a sequential reader filled, growing it each time it has to fetch
+    fn readahead(&mut self, fs: &mut dyn FileSystem, location: Location, from: u64, size: u64) -> FsResult<()> {
+        let last = size.div_ceil(PAGE_SIZE as u64);
+        let window = match self.files.get(&location) {
+            Some(file) => file.window.max(READAHEAD_MIN_PAGES),
+            None => return Ok(()),
+        };
+
+        let mut fetched = false;
+        for index in from..(from + window).min(last) {
+            let key = PageKey { location, index };
+            if self.pages.contains_key(&key) {
+                continue;
+            }
+            self.fill(fs, key, size, false)?;
+            if let Some(page) = self.pages.get_mut(&key) {
+                page.readahead = true;
+            }
+            self.stats.readahead_pages += 1;
+            fetched = true;
+        }
+
+        if let Some(file) = self.files.get_mut(&location) {
+            if fetched {
+                file.window = (window * 2).min(READAHEAD_MAX_PAGES);
+            }
+        }
+        Ok(())
+    }
+
+    // A full cache drops its oldest clean page. When every page is dirty the
+    // oldest one on this mount is written back first; with none on this mount
+    // there is nothing this filesystem can flush, and the caller gets Busy.
+    fn make_room(&mut self, fs: &mut dyn FileSystem, mount: u32) -> FsResult<()> {
+        if self.pages.len() < self.capacity || self.shrink(1) > 0 {
+            return Ok(());
+        }
+        let victim = self
+            .lru
+            .values()
+            .find(|key| key.location.mount == mount)
+            .copied()
+            .ok_or(FsError::Busy)?;
+        self.write_back_page(fs, victim)?;
+        self.remove_page(victim);
+        self.stats.evictions += 1;
+        Ok(())
+    }
+
+    // Writes a dirty page through to the filesystem and marks it clean; true if any bytes went out
+    fn write_back_page(&mut self, fs: &mut dyn FileSystem, key: PageKey) -> FsResult<bool> {
+        if !self.pages.get(&key).is_some_and(|page| page.dirty) {
+            return Ok(false);
+        }
+        let size = self.files.get(&key.location).map_or(0, |file| file.size);
+        let start = key.index * PAGE_SIZE as u64;
+        let length = (size.saturating_sub(start)).min(PAGE_SIZE as u64) as usize;
+        let mut written = false;
+        if length > 0 {
+            match fs.write(key.location.inode, start, &self.pages[&key].data[..length]) {
+                Ok(_) => {}
+                // The file was removed underneath us; its data has nowhere to go
+                Err(FsError::NotFound) => {
+                    self.invalidate(key.location);
+                    return Ok(false);
+                }
+                Err(error) => return Err(error),
+            }
+            self.stats.pages_written += 1;
+            written = true;
+        }
+        if let Some(page) = self.pages.get_mut(&key) {
+            page.dirty = false;
+            self.dirty -= 1;
+        }
+        Ok(written)
+    }
+
+    fn remove_page(&mut self, key: PageKey) {
+        if let Some(page) = self.pages.remove(&key) {
+            self.lru.remove(&page.last_used);
+            if page.dirty {
+                self.dirty -= 1;
+            }
+            if let Some(file) = self.files.get_mut(&key.location) {
+                file.pages -= 1;
+                // With no pages left the filesystem's size is current again
+                if file.pages == 0 {
+                    self.files.remove(&key.location);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::inode::FileType;
+    use super::super::tmpfs::TmpFs;
+    use super::*;
+
+    fn file(fs: &mut TmpFs, mount: u32, name: &str) -> Location {
+        let root = fs.root();
+        Location { mount, inode: fs.create(root, name, FileType::Regular).unwrap() }
+    }
+
+    #[test]
+    fn all_dirty_cache_writes_back_instead_of_growing() {
+        let mut fs = TmpFs::new(1 << 20);
+        let location = file(&mut fs, 0, "log");
+        let mut cache = PageCache::new(4);
+
+        let data: Vec<u8> = (0..16 * PAGE_SIZE).map(|i| (i / PAGE_SIZE) as u8).collect();
+        cache.write(&mut fs, location, 0, &data).unwrap();
+        assert_eq!(cache.len(), 4);
+        assert!(cache.get_stats().pages_written >= 12);
+
+        let mut back = vec![0u8; data.len()];
+        assert_eq!(cache.read(&mut fs, location, 0, &mut back).unwrap(), data.len());
+        assert_eq!(back, data);
+        assert!(cache.len() <= 4);
+    }
+
+    #[test]
+    fn readahead_window_doubles_for_sequential_reads_and_resets_on_a_seek() {
+        let mut fs = TmpFs::new(1 << 20);
+        let location = file(&mut fs, 0, "movie");
+        fs.write(location.inode, 0, &vec![7; 64 * PAGE_SIZE]).unwrap();
+        let mut cache = PageCache::new(128);
+        let mut page = vec![0u8; PAGE_SIZE];
+
+        // Each sequential read fetches the window ahead of it, then doubles it: 4, 8, 16 pages
+        let mut expected = Vec::new();
+        for index in 0..3 {
+            cache.read(&mut fs, location, index * PAGE_SIZE as u64, &mut page).unwrap();
+            expected.push(cache.get_stats().readahead_pages);
+        }
+        assert_eq!(expected, vec![4, 9, 18]);
+        assert_eq!(cache.get_stats().readahead_hits, 2);
+
+        // A seek drops the window, and the next sequential read starts again from the minimum
+        cache.read(&mut fs, location, 40 * PAGE_SIZE as u64, &mut page).unwrap();
+        assert_eq!(cache.get_stats().readahead_pages, 18);
+        cache.read(&mut fs, location, 41 * PAGE_SIZE as u64, &mut page).unwrap();
+        assert_eq!(cache.get_stats().readahead_pages, 18 + READAHEAD_MIN_PAGES);
+    }
+
+    #[test]
+    fn periodic_flusher_writes_back_only_expired_pages() {
+        let mut fs = TmpFs::new(1 << 20);
+        let (old, young) = (file(&mut fs, 0, "old"), file(&mut fs, 0, "young"));
+        let mut cache = PageCache::new(16);
+        cache.write(&mut fs, old, 0, b"written first").unwrap();
+
+        assert!(!cache.advance(WRITEBACK_INTERVAL_MS - 1));
+        assert!(cache.advance(1));
+        assert_eq!(cache.writeback(0, &mut fs, true).unwrap(), 0);
+
+        let mut flushed = 0;
+        while cache.now < DIRTY_EXPIRE_MS {
+            if cache.now == 20_000 {
+                cache.write(&mut fs, young, 0, b"written later").unwrap();
+            }
+            assert!(cache.advance(WRITEBACK_INTERVAL_MS));
+            flushed += cache.writeback(0, &mut fs, true).unwrap();
+        }
+        assert_eq!(flushed, 1);
+        assert_eq!(cache.dirty_pages(), 1);
+        assert_eq!(fs.getattr(old.inode).unwrap().size, 13);
+        assert_eq!(fs.getattr(young.inode).unwrap().size, 0);
+
+        let mut buffer = [0u8; 13];
+        fs.read(old.inode, 0, &mut buffer).unwrap();
+        assert_eq!(&buffer, b"written first");
+    }
+
+    #[test]
+    fn hits_and_misses_are_counted_per_page() {
+        let mut fs = TmpFs::new(1 << 20);
+        let location = file(&mut fs, 0, "notes");
+        fs.write(location.inode, 0, &[1; 3000]).unwrap();
+        let mut cache = PageCache::new(16);
+        let mut buffer = [0u8; 1000];
+
+        cache.read(&mut fs, location, 0, &mut buffer).unwrap();
+        cache.read(&mut fs, location, 0, &mut buffer).unwrap();
+        let mut tail = [0u8; PAGE_SIZE];
+        assert_eq!(cache.read(&mut fs, location, 2000, &mut tail).unwrap(), 1000);
+
+        let stats = cache.get_stats();
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+        assert_eq!((stats.bytes_from_device, stats.bytes_from_cache), (1000, 2000));
+        assert_eq!(stats.pages_read, 1);
+        assert!((stats.hit_rate() - 2.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn dirty_pages_of_another_mount_push_back_on_the_caller() {
+        let (mut first, mut second) = (TmpFs::new(1 << 20), TmpFs::new(1 << 20));
+        let (a, b) = (file(&mut first, 0, "a"), file(&mut second, 1, "b"));
+        let mut cache = PageCache::new(2);
+
+        cache.write(&mut first, a, 0, &[1; 2 * PAGE_SIZE]).unwrap();
+        assert_eq!(cache.write(&mut second, b, 0, &[2; PAGE_SIZE]), Err(FsError::Busy));
+
+        cache.writeback(0, &mut first, false).unwrap();
+        cache.write(&mut second, b, 0, &[2; PAGE_SIZE]).unwrap();
+        assert_eq!(cache.len(), 2);
+    }
+}
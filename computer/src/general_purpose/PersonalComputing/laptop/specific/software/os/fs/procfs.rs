@@ -0,0 +1,157 @@
+use super::error::{FsError, FsResult};
+use super::inode::{DirEntry, FileSystem, FileType, InodeId, Metadata};
+use std::collections::{BTreeMap, HashMap};
+
+const ROOT_INODE: InodeId = 1;
+
+// Generates a file's text each time it is read
+pub type ProcGenerator = Box<dyn Fn() -> String>;
+
+// Synthetic files whose contents come from the running system, as in /proc
+pub struct ProcFs {
+    nodes: HashMap<InodeId, ProcNode>,
+    next_inode: InodeId,
+}
+
+enum ProcNode {
+    Directory(BTreeMap<String, InodeId>),
+    File(ProcGenerator),
+}
+
+impl ProcFs {
+    pub fn new() -> Self {
+        let mut nodes = HashMap::new();
+        nodes.insert(ROOT_INODE, ProcNode::Directory(BTreeMap::new()));
+        Self { nodes, next_inode: ROOT_INODE + 1 }
+    }
+
+    // Publish a file such as "meminfo" or "1/status", creating its directories
+    pub fn register(&mut self, path: &str, generator: ProcGenerator) -> FsResult<()> {
+        let parts: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+        let (name, directories) = parts.split_last().ok_or(FsError::InvalidPath)?;
+
+        let mut dir = ROOT_INODE;
+        for part in directories {
+            dir = match self.child(dir, part)? {
+                Some(existing) => existing,
+                None => self.insert(dir, part, ProcNode::Directory(BTreeMap::new()))?,
+            };
+        }
+        if self.child(dir, name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        self.insert(dir, name, ProcNode::File(generator))?;
+        Ok(())
+    }
+
+    // Remove a file or a whole directory, e.g. when a process exits
+    pub fn unregister(&mut self, path: &str) -> FsResult<()> {
+        let parts: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+        let (name, directories) = parts.split_last().ok_or(FsError::InvalidPath)?;
+        let mut dir = ROOT_INODE;
+        for part in directories {
+            dir = self.child(dir, part)?.ok_or(FsError::NotFound)?;
+        }
+
+        let inode = match self.nodes.get_mut(&dir) {
+            Some(ProcNode::Directory(children)) => children.remove(*name).ok_or(FsError::NotFound)?,
+            _ => return Err(FsError::NotADirectory),
+        };
+        let mut doomed = vec![inode];
+        while let Some(inode) = doomed.pop() {
+            if let Some(ProcNode::Directory(children)) = self.nodes.remove(&inode) {
+                doomed.extend(children.values());
+            }
+        }
+        Ok(())
+    }
+
+    fn child(&self, dir: InodeId, name: &str) -> FsResult<Option<InodeId>> {
+        match self.nodes.get(&dir) {
+            Some(ProcNode::Directory(children)) => Ok(children.get(name).copied()),
+            Some(ProcNode::File(_)) => Err(FsError::NotADirectory),
+            None => Err(FsError::NotFound),
+        }
+    }
+
+    fn insert(&mut self, dir: InodeId, name: &str, node: ProcNode) -> FsResult<InodeId> {
+        let inode = self.next_inode;
+        self.next_inode += 1;
+        self.nodes.insert(inode, node);
+        if let Some(ProcNode::Directory(children)) = self.nodes.get_mut(&dir) {
+            children.insert(name.to_string(), inode);
+        }
+        Ok(inode)
+    }
+
+    fn generate(&self, inode: InodeId) -> FsResult<String> {
+        match self.nodes.get(&inode) {
+            Some(ProcNode::File(generator)) => Ok(generator()),
+            Some(ProcNode::Directory(_)) => Err(FsError::IsADirectory),
+            None => Err(FsError::NotFound),
+        }
+    }
+}
+
+impl Default for ProcFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileSystem for ProcFs {
+    fn name(&self) -> &str {
+        "proc"
+    }
+
+    // Contents are generated on every read, so caching them would serve stale text
+    fn uses_page_cache(&self) -> bool {
+        false
+    }
+
+    fn root(&self) -> InodeId {
+        ROOT_INODE
+    }
+
+    fn lookup(&mut self, dir: InodeId, name: &str) -> FsResult<InodeId> {
+        self.child(dir, name)?.ok_or(FsError::NotFound)
+    }
+
+    // Sizes are reported as 0, as Linux does for generated files
+    fn getattr(&mut self, inode: InodeId) -> FsResult<Metadata> {
+        let (file_type, permissions) = match self.nodes.get(&inode).ok_or(FsError::NotFound)? {
+            ProcNode::Directory(_) => (FileType::Directory, 0o555),
+            ProcNode::File(_) => (FileType::Regular, 0o444),
+        };
+        Ok(Metadata { inode, device: 0, file_type, size: 0, permissions, links: 1, modified: 0 })
+    }
+
+    fn readdir(&mut self, dir: InodeId) -> FsResult<Vec<DirEntry>> {
+        let Some(ProcNode::Directory(children)) = self.nodes.get(&dir) else {
+            return Err(FsError::NotADirectory);
+        };
+        Ok(children
+            .iter()
+            .map(|(name, &inode)| DirEntry {
+                name: name.clone(),
+                inode,
+                file_type: match self.nodes.get(&inode) {
+                    Some(ProcNode::Directory(_)) => FileType::Directory,
+                    _ => FileType::Regular,
+                },
+            })
+            .collect())
+    }
+
+    fn read(&mut self, inode: InodeId, offset: u64, buffer: &mut [u8]) -> FsResult<usize> {
+        let text = self.generate(inode)?;
+        let bytes = text.as_bytes();
+        if offset >= bytes.len() as u64 {
+            return Ok(0);
+        }
+        let start = offset as usize;
+        let count = buffer.len().min(bytes.len() - start);
+        buffer[..count].copy_from_slice(&bytes[start..start + count]);
+        Ok(count)
+    }
+}
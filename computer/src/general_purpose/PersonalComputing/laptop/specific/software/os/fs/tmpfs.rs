@@ -0,0 +1,306 @@
+use super::error::{FsError, FsResult};
+use super::inode::{DirEntry, FileSystem, FileType, InodeId, Metadata};
+use std::collections::{BTreeMap, HashMap};
+
+const ROOT_INODE: InodeId = 1;
+
+// A filesystem that lives entirely in memory, like /tmp or /dev/shm
+pub struct TmpFs {
+    nodes: HashMap<InodeId, TmpNode>,
+    next_inode: InodeId,
+    capacity: u64,
+    used: u64,
+    // Logical clock stamped into modification times
+    clock: u64,
+}
+
+struct TmpNode {
+    contents: NodeContents,
+    parent: InodeId,
+    links: u32,
+    permissions: u16,
+    modified: u64,
+}
+
+enum NodeContents {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, InodeId>),
+    Symlink(String),
+}
+
+impl TmpFs {
+    pub fn new(capacity: u64) -> Self {
+        let mut nodes = HashMap::new();
+        nodes.insert(
+            ROOT_INODE,
+            TmpNode {
+                contents: NodeContents::Directory(BTreeMap::new()),
+                parent: ROOT_INODE,
+                links: 2,
+                permissions: 0o1777,
+                modified: 0,
+            },
+        );
+        Self { nodes, next_inode: ROOT_INODE + 1, capacity, used: 0, clock: 0 }
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.used
+    }
+
+    fn node(&self, inode: InodeId) -> FsResult<&TmpNode> {
+        self.nodes.get(&inode).ok_or(FsError::NotFound)
+    }
+
+    fn node_mut(&mut self, inode: InodeId) -> FsResult<&mut TmpNode> {
+        self.nodes.get_mut(&inode).ok_or(FsError::NotFound)
+    }
+
+    fn children(&self, dir: InodeId) -> FsResult<&BTreeMap<String, InodeId>> {
+        match &self.node(dir)?.contents {
+            NodeContents::Directory(children) => Ok(children),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn children_mut(&mut self, dir: InodeId) -> FsResult<&mut BTreeMap<String, InodeId>> {
+        match &mut self.node_mut(dir)?.contents {
+            NodeContents::Directory(children) => Ok(children),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn insert(&mut self, dir: InodeId, name: &str, contents: NodeContents) -> FsResult<InodeId> {
+        validate_name(name)?;
+        if self.children(dir)?.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let inode = self.next_inode;
+        self.next_inode += 1;
+        let is_directory = matches!(contents, NodeContents::Directory(_));
+        let modified = self.tick();
+        self.nodes.insert(
+            inode,
+            TmpNode {
+                contents,
+                parent: dir,
+                links: if is_directory { 2 } else { 1 },
+                permissions: if is_directory { 0o755 } else { 0o644 },
+                modified,
+            },
+        );
+        self.children_mut(dir)?.insert(name.to_string(), inode);
+        let parent = self.node_mut(dir)?;
+        parent.modified = modified;
+        if is_directory {
+            parent.links += 1;
+        }
+        Ok(inode)
+    }
+
+    // Drop one link to an inode, freeing it with the last one
+    fn release(&mut self, inode: InodeId) {
+        let Some(node) = self.nodes.get_mut(&inode) else { return };
+        node.links = node.links.saturating_sub(1);
+        let is_directory = matches!(node.contents, NodeContents::Directory(_));
+        if node.links == 0 || is_directory {
+            if let Some(TmpNode { contents: NodeContents::File(data), .. }) = self.nodes.remove(&inode) {
+                self.used -= data.len() as u64;
+            }
+        }
+    }
+
+    fn resize(&mut self, inode: InodeId, size: u64) -> FsResult<()> {
+        let used = self.used;
+        let capacity = self.capacity;
+        let modified = self.tick();
+        let node = self.node_mut(inode)?;
+        let NodeContents::File(data) = &mut node.contents else {
+            return Err(FsError::IsADirectory);
+        };
+        let old = data.len() as u64;
+        if size > old && used + (size - old) > capacity {
+            return Err(FsError::NoSpace);
+        }
+        data.resize(size as usize, 0);
+        node.modified = modified;
+        self.used = used + size - old;
+        Ok(())
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &str {
+        "tmpfs"
+    }
+
+    // Data is already held in RAM; caching it again would only double it
+    fn uses_page_cache(&self) -> bool {
+        false
+    }
+
+    fn root(&self) -> InodeId {
+        ROOT_INODE
+    }
+
+    fn lookup(&mut self, dir: InodeId, name: &str) -> FsResult<InodeId> {
+        if name == ".." {
+            return Ok(self.node(dir)?.parent);
+        }
+        self.children(dir)?.get(name).copied().ok_or(FsError::NotFound)
+    }
+
+    fn getattr(&mut self, inode: InodeId) -> FsResult<Metadata> {
+        let node = self.node(inode)?;
+        let (file_type, size) = match &node.contents {
+            NodeContents::File(data) => (FileType::Regular, data.len() as u64),
+            NodeContents::Directory(children) => (FileType::Directory, children.len() as u64),
+            NodeContents::Symlink(target) => (FileType::Symlink, target.len() as u64),
+        };
+        Ok(Metadata {
+            inode,
+            device: 0,
+            file_type,
+            size,
+            permissions: node.permissions,
+            links: node.links,
+            modified: node.modified,
+        })
+    }
+
+    fn readdir(&mut self, dir: InodeId) -> FsResult<Vec<DirEntry>> {
+        let children = self.children(dir)?;
+        let mut entries = Vec::with_capacity(children.len());
+        for (name, &inode) in children {
+            let file_type = match self.node(inode)?.contents {
+                NodeContents::File(_) => FileType::Regular,
+                NodeContents::Directory(_) => FileType::Directory,
+                NodeContents::Symlink(_) => FileType::Symlink,
+            };
+            entries.push(DirEntry { name: name.clone(), inode, file_type });
+        }
+        Ok(entries)
+    }
+
+    fn read(&mut self, inode: InodeId, offset: u64, buffer: &mut [u8]) -> FsResult<usize> {
+        let NodeContents::File(data) = &self.node(inode)?.contents else {
+            return Err(FsError::IsADirectory);
+        };
+        if offset >= data.len() as u64 {
+            return Ok(0);
+        }
+        let start = offset as usize;
+        let count = buffer.len().min(data.len() - start);
+        buffer[..count].copy_from_slice(&data[start..start + count]);
+        Ok(count)
+    }
+
+    fn readlink(&mut self, inode: InodeId) -> FsResult<String> {
+        match &self.node(inode)?.contents {
+            NodeContents::Symlink(target) => Ok(target.clone()),
+            _ => Err(FsError::InvalidArgument),
+        }
+    }
+
+    fn write(&mut self, inode: InodeId, offset: u64, data: &[u8]) -> FsResult<usize> {
+        let end = offset + data.len() as u64;
+        let size = self.getattr(inode)?.size;
+        if end > size {
+            self.resize(inode, end)?;
+        }
+        let modified = self.tick();
+        let node = self.node_mut(inode)?;
+        if let NodeContents::File(contents) = &mut node.contents {
+            contents[offset as usize..end as usize].copy_from_slice(data);
+        }
+        node.modified = modified;
+        Ok(data.len())
+    }
+
+    fn truncate(&mut self, inode: InodeId, size: u64) -> FsResult<()> {
+        self.resize(inode, size)
+    }
+
+    fn create(&mut self, dir: InodeId, name: &str, file_type: FileType) -> FsResult<InodeId> {
+        let contents = match file_type {
+            FileType::Regular => NodeContents::File(Vec::new()),
+            FileType::Directory => NodeContents::Directory(BTreeMap::new()),
+            FileType::Symlink => return Err(FsError::InvalidArgument),
+        };
+        self.insert(dir, name, contents)
+    }
+
+    fn symlink(&mut self, dir: InodeId, name: &str, target: &str) -> FsResult<InodeId> {
+        self.insert(dir, name, NodeContents::Symlink(target.to_string()))
+    }
+
+    fn unlink(&mut self, dir: InodeId, name: &str) -> FsResult<()> {
+        let inode = self.lookup(dir, name)?;
+        if matches!(self.node(inode)?.contents, NodeContents::Directory(_)) {
+            return Err(FsError::IsADirectory);
+        }
+        self.children_mut(dir)?.remove(name);
+        let modified = self.tick();
+        self.node_mut(dir)?.modified = modified;
+        self.release(inode);
+        Ok(())
+    }
+
+    fn rmdir(&mut self, dir: InodeId, name: &str) -> FsResult<()> {
+        let inode = self.lookup(dir, name)?;
+        if !self.children(inode)?.is_empty() {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+        self.children_mut(dir)?.remove(name);
+        let modified = self.tick();
+        let parent = self.node_mut(dir)?;
+        parent.links -= 1;
+        parent.modified = modified;
+        self.release(inode);
+        Ok(())
+    }
+
+    fn rename(&mut self, old_dir: InodeId, old_name: &str, new_dir: InodeId, new_name: &str) -> FsResult<()> {
+        validate_name(new_name)?;
+        let inode = self.lookup(old_dir, old_name)?;
+        let is_directory = matches!(self.node(inode)?.contents, NodeContents::Directory(_));
+
+        // POSIX rename replaces an existing target of the same kind
+        if let Some(&existing) = self.children(new_dir)?.get(new_name) {
+            if existing == inode {
+                return Ok(());
+            }
+            match (is_directory, matches!(self.node(existing)?.contents, NodeContents::Directory(_))) {
+                (false, true) => return Err(FsError::IsADirectory),
+                (true, false) => return Err(FsError::NotADirectory),
+                (true, true) => self.rmdir(new_dir, new_name)?,
+                (false, false) => self.unlink(new_dir, new_name)?,
+            }
+        }
+
+        self.children_mut(old_dir)?.remove(old_name);
+        self.children_mut(new_dir)?.insert(new_name.to_string(), inode);
+        let modified = self.tick();
+        if is_directory && old_dir != new_dir {
+            self.node_mut(old_dir)?.links -= 1;
+            self.node_mut(new_dir)?.links += 1;
+            self.node_mut(inode)?.parent = new_dir;
+        }
+        self.node_mut(old_dir)?.modified = modified;
+        self.node_mut(new_dir)?.modified = modified;
+        Ok(())
+    }
+}
+
+fn validate_name(name: &str) -> FsResult<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\0') {
+        return Err(FsError::InvalidPath);
+    }
+    Ok(())
+}
@@ -1,27 +1,995 @@
+use super::dcache::{DentryCache, DentryStats};
 use super::error::{FsError, FsResult};
-use std::collections::HashMap;
+use super::fd::{Fd, FdTable, FileHandle, Location, OpenFile, OpenFlags};
+use super::inode::{DirEntry, FileSystem, FileType, Metadata};
+use super::page_cache::{PageCache, PageCacheStats};
+use std::collections::{HashMap, VecDeque};
+use std::io::SeekFrom;
 use std::path::{Path, PathBuf};
 
+const MAX_SYMLINK_DEPTH: u32 = 40;
+const DENTRY_CACHE_SIZE: usize = 4096;
+// 32 MiB of 4 KiB pages
+const PAGE_CACHE_PAGES: usize = 8192;
+
 pub struct VirtualFileSystem {
-    mount_points: HashMap<PathBuf, Box<dyn FileSystem>>,
+    mounts: Vec<Mount>,
     open_files: HashMap<FileHandle, OpenFile>,
-    cache: FileCache,
+    fd_tables: HashMap<u32, FdTable>,
+    dentries: DentryCache,
+    pages: PageCache,
+    orphans: HashMap<u64, Orphan>,
+    next_mount_id: u32,
+    next_handle: u64,
+    next_orphan: u64,
+    stats: VfsStats,
+}
+
+struct Mount {
+    id: u32,
+    // Canonical absolute path, free of symlinks
+    path: PathBuf,
+    fs: Box<dyn FileSystem>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MountInfo {
+    pub device: u32,
+    pub path: PathBuf,
+    pub filesystem: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VfsStats {
+    pub path_walks: u64,
+    pub components_walked: u64,
+    pub symlinks_followed: u64,
+    pub mount_crossings: u64,
+    pub opens: u64,
+    pub reads: u64,
+    pub writes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+// A file unlinked while descriptors still refer to it. The filesystem has
+// already dropped its name and blocks, so the data lives here until the
+// last open file lets go.
+struct Orphan {
+    metadata: Metadata,
+    data: Vec<u8>,
+    open_files: u32,
+}
+
+// Where a path walk ended, plus the canonical components that lead there
+struct Walk {
+    location: Location,
+    components: Vec<String>,
 }
 
 impl VirtualFileSystem {
+    pub fn new(root: Box<dyn FileSystem>) -> Self {
+        Self {
+            mounts: vec![Mount { id: 0, path: PathBuf::from("/"), fs: root }],
+            open_files: HashMap::new(),
+            orphans: HashMap::new(),
+            fd_tables: HashMap::new(),
+            dentries: DentryCache::new(DENTRY_CACHE_SIZE),
+            pages: PageCache::new(PAGE_CACHE_PAGES),
+            next_mount_id: 1,
+            next_handle: 0,
+            next_orphan: 0,
+            stats: VfsStats::default(),
+        }
+    }
+
     pub fn mount(&mut self, path: &Path, fs: Box<dyn FileSystem>) -> FsResult<()> {
-        if self.mount_points.contains_key(path) {
+        let walk = self.walk_from("/", path_str(path)?, true)?;
+        if self.getattr(walk.location)?.file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        let path = canonical_path(&walk.components);
+        if self.mounts.iter().any(|mount| mount.path == path) {
             return Err(FsError::AlreadyMounted);
         }
-        self.mount_points.insert(path.to_path_buf(), fs);
+
+        let id = self.next_mount_id;
+        self.next_mount_id += 1;
+        self.mounts.push(Mount { id, path, fs });
+        Ok(())
+    }
+
+    pub fn umount(&mut self, path: &Path) -> FsResult<Box<dyn FileSystem>> {
+        let walk = self.walk_from("/", path_str(path)?, true)?;
+        let path = canonical_path(&walk.components);
+        let index = self
+            .mounts
+            .iter()
+            .position(|mount| mount.path == path)
+            .ok_or(FsError::NotMounted)?;
+        if index == 0 {
+            return Err(FsError::Busy);
+        }
+
+        // Open files and nested mounts pin a filesystem
+        let id = self.mounts[index].id;
+        let nested = self.mounts.iter().any(|mount| mount.id != id && mount.path.starts_with(&path));
+        let in_use = self.open_files.values().any(|file| file.location.mount == id);
+        if nested || in_use {
+            return Err(FsError::Busy);
+        }
+
+        let mount = &mut self.mounts[index];
+        self.pages.writeback(id, mount.fs.as_mut(), false)?;
+        mount.fs.sync()?;
+        let mount = self.mounts.remove(index);
+        self.pages.invalidate_mount(id);
+        self.dentries.invalidate_mount(id);
+        Ok(mount.fs)
+    }
+
+    pub fn mounts(&self) -> Vec<MountInfo> {
+        self.mounts
+            .iter()
+            .map(|mount| MountInfo {
+                device: mount.id,
+                path: mount.path.clone(),
+                filesystem: mount.fs.name().to_string(),
+            })
+            .collect()
+    }
+
+    // Process lifecycle
+
+    pub fn create_process(&mut self, pid: u32) {
+        self.fd_tables.insert(pid, FdTable::new());
+    }
+
+    // The child inherits every descriptor, sharing offsets with the parent
+    pub fn fork_process(&mut self, parent: u32, child: u32) -> FsResult<()> {
+        let table = self.table(parent)?.clone();
+        for handle in table.handles() {
+            if let Some(file) = self.open_files.get_mut(&handle) {
+                file.references += 1;
+            }
+        }
+        self.fd_tables.insert(child, table);
+        Ok(())
+    }
+
+    pub fn exit_process(&mut self, pid: u32) -> FsResult<()> {
+        let table = self.fd_tables.remove(&pid).ok_or(FsError::NoSuchProcess)?;
+        for handle in table.handles() {
+            self.release_handle(handle);
+        }
+        Ok(())
+    }
+
+    // File descriptor calls
+
+    pub fn open(&mut self, pid: u32, path: &Path, flags: OpenFlags) -> FsResult<Fd> {
+        let path = path_str(path)?;
+        let walk = match self.walk(pid, path, !flags.contains(OpenFlags::NO_FOLLOW)) {
+            Ok(_) if flags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) => return Err(FsError::AlreadyExists),
+            Ok(walk) => walk,
+            Err(FsError::NotFound) if flags.contains(OpenFlags::CREATE) => self.create_at(pid, path, FileType::Regular)?,
+            Err(error) => return Err(error),
+        };
+
+        match self.getattr(walk.location)?.file_type {
+            FileType::Symlink => return Err(FsError::TooManySymlinks),
+            FileType::Directory if flags.contains(OpenFlags::WRITE) => return Err(FsError::IsADirectory),
+            FileType::Regular if flags.contains(OpenFlags::DIRECTORY) => return Err(FsError::NotADirectory),
+            FileType::Regular if flags.contains(OpenFlags::WRITE | OpenFlags::TRUNCATE) => {
+                self.fs_mut(walk.location.mount)?.truncate(walk.location.inode, 0)?;
+                self.pages.truncate(walk.location, 0);
+            }
+            _ => {}
+        }
+
+        let handle = FileHandle(self.next_handle);
+        let fd = self.table_mut(pid)?.allocate(handle)?;
+        self.next_handle += 1;
+        let file = OpenFile::new(walk.location, canonical_path(&walk.components).to_string_lossy().into_owned(), flags);
+        self.open_files.insert(handle, file);
+        self.stats.opens += 1;
+        Ok(fd)
+    }
+
+    pub fn close(&mut self, pid: u32, fd: Fd) -> FsResult<()> {
+        let handle = self.table_mut(pid)?.release(fd)?;
+        self.release_handle(handle);
+        Ok(())
+    }
+
+    pub fn dup(&mut self, pid: u32, fd: Fd) -> FsResult<Fd> {
+        let handle = self.table(pid)?.get(fd)?;
+        let new_fd = self.table_mut(pid)?.allocate(handle)?;
+        if let Some(file) = self.open_files.get_mut(&handle) {
+            file.references += 1;
+        }
+        Ok(new_fd)
+    }
+
+    pub fn read(&mut self, pid: u32, fd: Fd, buffer: &mut [u8]) -> FsResult<usize> {
+        let handle = self.table(pid)?.get(fd)?;
+        let file = self.open_files.get(&handle).ok_or(FsError::BadFileDescriptor)?;
+        if !file.flags.contains(OpenFlags::READ) {
+            return Err(FsError::BadFileDescriptor);
+        }
+        let (location, offset, orphan) = (file.location, file.offset, file.orphan);
+
+        let count = match orphan.and_then(|id| self.orphans.get(&id)) {
+            Some(orphan) => {
+                let start = (offset as usize).min(orphan.data.len());
+                let count = buffer.len().min(orphan.data.len() - start);
+                buffer[..count].copy_from_slice(&orphan.data[start..start + count]);
+                count
+            }
+            None => {
+                if self.getattr(location)?.file_type == FileType::Directory {
+                    return Err(FsError::IsADirectory);
+                }
+                self.read_at(location, offset, buffer)?
+            }
+        };
+        if let Some(file) = self.open_files.get_mut(&handle) {
+            file.offset += count as u64;
+        }
+        self.stats.reads += 1;
+        self.stats.bytes_read += count as u64;
+        Ok(count)
+    }
+
+    pub fn write(&mut self, pid: u32, fd: Fd, data: &[u8]) -> FsResult<usize> {
+        let handle = self.table(pid)?.get(fd)?;
+        let file = self.open_files.get(&handle).ok_or(FsError::BadFileDescriptor)?;
+        if !file.flags.contains(OpenFlags::WRITE) {
+            return Err(FsError::BadFileDescriptor);
+        }
+        let (location, flags, mut offset, orphan) = (file.location, file.flags, file.offset, file.orphan);
+        if let Some(orphan) = orphan.and_then(|id| self.orphans.get_mut(&id)) {
+            if flags.contains(OpenFlags::APPEND) {
+                offset = orphan.data.len() as u64;
+            }
+            let end = offset as usize + data.len();
+            if orphan.data.len() < end {
+                orphan.data.resize(end, 0);
+            }
+            orphan.data[offset as usize..end].copy_from_slice(data);
+            if let Some(file) = self.open_files.get_mut(&handle) {
+                file.offset = end as u64;
+            }
+            self.stats.writes += 1;
+            self.stats.bytes_written += data.len() as u64;
+            return Ok(data.len());
+        }
+        if flags.contains(OpenFlags::APPEND) {
+            offset = self.getattr(location)?.size;
+        }
+
+        let fs = mount_fs(&mut self.mounts, location.mount)?;
+        let count = if fs.uses_page_cache() {
+            match self.pages.write(fs.as_mut(), location, offset, data) {
+                Err(FsError::Busy) => {
+                    self.writeback_all()?;
+                    let fs = mount_fs(&mut self.mounts, location.mount)?;
+                    self.pages.write(fs.as_mut(), location, offset, data)?
+                }
+                result => result?,
+            }
+        } else {
+            fs.write(location.inode, offset, data)?
+        };
+        if let Some(file) = self.open_files.get_mut(&handle) {
+            file.offset = offset + count as u64;
+        }
+        // A writer that dirtied too much of the cache pays for writing it back
+        if self.pages.over_dirty_limit() {
+            let fs = mount_fs(&mut self.mounts, location.mount)?;
+            self.pages.writeback(location.mount, fs.as_mut(), false)?;
+        }
+        self.stats.writes += 1;
+        self.stats.bytes_written += count as u64;
+        Ok(count)
+    }
+
+    pub fn lseek(&mut self, pid: u32, fd: Fd, position: SeekFrom) -> FsResult<u64> {
+        let handle = self.table(pid)?.get(fd)?;
+        let file = self.open_files.get(&handle).ok_or(FsError::BadFileDescriptor)?;
+        let offset = file.offset;
+
+        let target = match position {
+            SeekFrom::Start(offset) => offset as i128,
+            SeekFrom::Current(delta) => offset as i128 + delta as i128,
+            SeekFrom::End(delta) => self.handle_attr(handle)?.size as i128 + delta as i128,
+        };
+        if target < 0 || target > u64::MAX as i128 {
+            return Err(FsError::InvalidSeek);
+        }
+        if let Some(file) = self.open_files.get_mut(&handle) {
+            file.offset = target as u64;
+        }
+        Ok(target as u64)
+    }
+
+    pub fn fstat(&mut self, pid: u32, fd: Fd) -> FsResult<Metadata> {
+        let handle = self.table(pid)?.get(fd)?;
+        self.handle_attr(handle)
+    }
+
+    // Path calls
+
+    pub fn stat(&mut self, pid: u32, path: &Path) -> FsResult<Metadata> {
+        let walk = self.walk(pid, path_str(path)?, true)?;
+        self.getattr(walk.location)
+    }
+
+    pub fn lstat(&mut self, pid: u32, path: &Path) -> FsResult<Metadata> {
+        let walk = self.walk(pid, path_str(path)?, false)?;
+        self.getattr(walk.location)
+    }
+
+    pub fn readdir(&mut self, pid: u32, path: &Path) -> FsResult<Vec<DirEntry>> {
+        let walk = self.walk(pid, path_str(path)?, true)?;
+        if self.getattr(walk.location)?.file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        self.fs_mut(walk.location.mount)?.readdir(walk.location.inode)
+    }
+
+    pub fn readlink(&mut self, pid: u32, path: &Path) -> FsResult<String> {
+        let walk = self.walk(pid, path_str(path)?, false)?;
+        self.fs_mut(walk.location.mount)?.readlink(walk.location.inode)
+    }
+
+    pub fn mkdir(&mut self, pid: u32, path: &Path) -> FsResult<()> {
+        self.create_at(pid, path_str(path)?, FileType::Directory).map(|_| ())
+    }
+
+    pub fn symlink(&mut self, pid: u32, target: &str, path: &Path) -> FsResult<()> {
+        let (parent, name, _) = self.walk_parent(pid, path_str(path)?)?;
+        self.fs_mut(parent.location.mount)?.symlink(parent.location.inode, &name, target)?;
+        self.dentries.invalidate_dir(parent.location.mount, parent.location.inode);
+        Ok(())
+    }
+
+    pub fn unlink(&mut self, pid: u32, path: &Path) -> FsResult<()> {
+        self.remove_at(pid, path_str(path)?, false)
+    }
+
+    pub fn rmdir(&mut self, pid: u32, path: &Path) -> FsResult<()> {
+        self.remove_at(pid, path_str(path)?, true)
+    }
+
+    pub fn rename(&mut self, pid: u32, from: &Path, to: &Path) -> FsResult<()> {
+        let (source_parent, source_name, source) = self.walk_parent(pid, path_str(from)?)?;
+        let (target_parent, target_name, target) = self.walk_parent(pid, path_str(to)?)?;
+        if source_parent.location.mount != target_parent.location.mount {
+            return Err(FsError::CrossDevice);
+        }
+        let source_location = self.lookup(source_parent.location, &source_name)?;
+
+        // Mount paths are fixed strings, so nothing mounted may move
+        let source_path = canonical_path(&source);
+        let target_path = canonical_path(&target);
+        if self.mounts.iter().any(|mount| mount.path.starts_with(&source_path) || mount.path == target_path) {
+            return Err(FsError::Busy);
+        }
+        if target_path.starts_with(&source_path) && target_path != source_path {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let mount = source_parent.location.mount;
+        let replaced = self
+            .lookup(target_parent.location, &target_name)
+            .ok()
+            .filter(|location| *location != source_location);
+        let orphan = match replaced {
+            Some(replaced) => self.snapshot_if_open(replaced)?,
+            None => None,
+        };
+        self.fs_mut(mount)?
+            .rename(source_parent.location.inode, &source_name, target_parent.location.inode, &target_name)?;
+        if let Some(replaced) = replaced {
+            self.pages.invalidate(replaced);
+            if let Some(orphan) = orphan {
+                self.adopt_orphan(replaced, orphan);
+            }
+        }
+        self.dentries.invalidate_dir(mount, source_parent.location.inode);
+        self.dentries.invalidate_dir(mount, target_parent.location.inode);
+        Ok(())
+    }
+
+    pub fn chdir(&mut self, pid: u32, path: &Path) -> FsResult<()> {
+        let walk = self.walk(pid, path_str(path)?, true)?;
+        if self.getattr(walk.location)?.file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        self.table_mut(pid)?.cwd = canonical_path(&walk.components).to_string_lossy().into_owned();
+        Ok(())
+    }
+
+    pub fn getcwd(&self, pid: u32) -> FsResult<String> {
+        Ok(self.table(pid)?.cwd.clone())
+    }
+
+    pub fn sync(&mut self) -> FsResult<()> {
+        for mount in &mut self.mounts {
+            self.pages.writeback(mount.id, mount.fs.as_mut(), false)?;
+            mount.fs.sync()?;
+        }
+        Ok(())
+    }
+
+    // Page cache
+
+    // Advances simulated time and runs the periodic flusher when it is due
+    pub fn tick(&mut self, elapsed_ms: u64) -> FsResult<()> {
+        if self.pages.advance(elapsed_ms) {
+            for mount in &mut self.mounts {
+                self.pages.writeback(mount.id, mount.fs.as_mut(), true)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Memory pressure: drop clean pages first, writing back dirty ones if that is not enough
+    pub fn shrink_page_cache(&mut self, pages: usize) -> FsResult<usize> {
+        let mut reclaimed = self.pages.shrink(pages);
+        if reclaimed < pages {
+            self.writeback_all()?;
+            reclaimed += self.pages.shrink(pages - reclaimed);
+        }
+        Ok(reclaimed)
+    }
+
+    pub fn set_page_cache_limit(&mut self, pages: usize) {
+        self.pages.set_capacity(pages);
+    }
+
+    pub fn page_cache_stats(&self) -> &PageCacheStats {
+        self.pages.get_stats()
+    }
+
+    pub fn open_file_count(&self) -> usize {
+        self.open_files.len()
+    }
+
+    pub fn get_stats(&self) -> &VfsStats {
+        &self.stats
+    }
+
+    pub fn dentry_stats(&self) -> &DentryStats {
+        self.dentries.get_stats()
+    }
+
+    // Helper methods
+
+    fn table(&self, pid: u32) -> FsResult<&FdTable> {
+        self.fd_tables.get(&pid).ok_or(FsError::NoSuchProcess)
+    }
+
+    fn table_mut(&mut self, pid: u32) -> FsResult<&mut FdTable> {
+        self.fd_tables.get_mut(&pid).ok_or(FsError::NoSuchProcess)
+    }
+
+    fn release_handle(&mut self, handle: FileHandle) {
+        if let Some(file) = self.open_files.get_mut(&handle) {
+            file.references -= 1;
+            if file.references == 0 {
+                let orphan = file.orphan;
+                self.open_files.remove(&handle);
+                if let Some(id) = orphan {
+                    self.release_orphan(id);
+                }
+            }
+        }
+    }
+
+    fn release_orphan(&mut self, id: u64) {
+        if let Some(orphan) = self.orphans.get_mut(&id) {
+            orphan.open_files -= 1;
+            if orphan.open_files == 0 {
+                self.orphans.remove(&id);
+            }
+        }
+    }
+
+    // Copies out a file that is about to lose its last name while still
+    // open, since the filesystem frees its blocks on unlink
+    fn snapshot_if_open(&mut self, location: Location) -> FsResult<Option<Orphan>> {
+        let open = self.open_files.values().any(|file| file.location == location && file.orphan.is_none());
+        if !open {
+            return Ok(None);
+        }
+        let mut metadata = self.getattr(location)?;
+        if metadata.links > 1 {
+            return Ok(None);
+        }
+        let mut data = vec![0; metadata.size as usize];
+        let mut filled = 0;
+        while filled < data.len() {
+            let count = self.read_at(location, filled as u64, &mut data[filled..])?;
+            if count == 0 {
+                data.truncate(filled);
+                break;
+            }
+            filled += count;
+        }
+        metadata.links = 0;
+        Ok(Some(Orphan { metadata, data, open_files: 0 }))
+    }
+
+    fn adopt_orphan(&mut self, location: Location, mut orphan: Orphan) {
+        let id = self.next_orphan;
+        self.next_orphan += 1;
+        for file in self.open_files.values_mut() {
+            if file.location == location && file.orphan.is_none() {
+                file.orphan = Some(id);
+                orphan.open_files += 1;
+            }
+        }
+        self.orphans.insert(id, orphan);
+    }
+
+    fn handle_attr(&mut self, handle: FileHandle) -> FsResult<Metadata> {
+        let file = self.open_files.get(&handle).ok_or(FsError::BadFileDescriptor)?;
+        if let Some(orphan) = file.orphan.and_then(|id| self.orphans.get(&id)) {
+            let mut metadata = orphan.metadata.clone();
+            metadata.size = orphan.data.len() as u64;
+            return Ok(metadata);
+        }
+        let location = file.location;
+        self.getattr(location)
+    }
+
+    fn read_at(&mut self, location: Location, offset: u64, buffer: &mut [u8]) -> FsResult<usize> {
+        let fs = mount_fs(&mut self.mounts, location.mount)?;
+        if !fs.uses_page_cache() {
+            return fs.read(location.inode, offset, buffer);
+        }
+        match self.pages.read(fs.as_mut(), location, offset, buffer) {
+            // The cache is full of other mounts' dirty pages
+            Err(FsError::Busy) => {
+                self.writeback_all()?;
+                let fs = mount_fs(&mut self.mounts, location.mount)?;
+                self.pages.read(fs.as_mut(), location, offset, buffer)
+            }
+            result => result,
+        }
+    }
+
+    fn writeback_all(&mut self) -> FsResult<()> {
+        for mount in &mut self.mounts {
+            self.pages.writeback(mount.id, mount.fs.as_mut(), false)?;
+        }
         Ok(())
     }
 
-    pub fn open(&mut self, path: &Path, flags: OpenFlags) -> FsResult<FileHandle> {
-        let (fs, relative_path) = self.resolve_path(path)?;
-        let file = fs.open(&relative_path, flags)?;
-        let handle = self.allocate_handle();
-        self.open_files.insert(handle, OpenFile::new(file, fs));
-        Ok(handle)
+    fn fs_mut(&mut self, mount: u32) -> FsResult<&mut Box<dyn FileSystem>> {
+        mount_fs(&mut self.mounts, mount)
+    }
+
+    // Sizes of files with cached writes are ahead of what the filesystem reports
+    fn getattr(&mut self, location: Location) -> FsResult<Metadata> {
+        let mut metadata = self.fs_mut(location.mount)?.getattr(location.inode)?;
+        metadata.device = location.mount;
+        if let Some(size) = self.pages.cached_size(location) {
+            metadata.size = size;
+        }
+        Ok(metadata)
+    }
+
+    fn root_location(&self) -> Location {
+        Location { mount: self.mounts[0].id, inode: self.mounts[0].fs.root() }
+    }
+
+    // The mount owning a canonical path is the one with the longest matching prefix
+    fn resolve_mount(&self, path: &Path) -> (usize, PathBuf) {
+        let (index, mount) = self
+            .mounts
+            .iter()
+            .enumerate()
+            .filter(|(_, mount)| path.starts_with(&mount.path))
+            .max_by_key(|(index, mount)| (mount.path.components().count(), *index))
+            .unwrap_or((0, &self.mounts[0]));
+        let relative = path.strip_prefix(&mount.path).unwrap_or(path).to_path_buf();
+        (index, relative)
+    }
+
+    fn lookup(&mut self, parent: Location, name: &str) -> FsResult<Location> {
+        match self.dentries.get(parent.mount, parent.inode, name) {
+            Some(Some(inode)) => return Ok(Location { mount: parent.mount, inode }),
+            Some(None) => return Err(FsError::NotFound),
+            None => {}
+        }
+        match self.fs_mut(parent.mount)?.lookup(parent.inode, name) {
+            Ok(inode) => {
+                self.dentries.insert(parent.mount, parent.inode, name, Some(inode));
+                Ok(Location { mount: parent.mount, inode })
+            }
+            Err(FsError::NotFound) => {
+                self.dentries.insert(parent.mount, parent.inode, name, None);
+                Err(FsError::NotFound)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    fn walk(&mut self, pid: u32, path: &str, follow_last: bool) -> FsResult<Walk> {
+        let cwd = self.table(pid)?.cwd.clone();
+        self.walk_from(&cwd, path, follow_last)
+    }
+
+    // Resolve one component at a time. The stack of visited locations makes ".." exact
+    // even across symlinks and mount points.
+    fn walk_from(&mut self, cwd: &str, path: &str, follow_last: bool) -> FsResult<Walk> {
+        if path.is_empty() {
+            return Err(FsError::NotFound);
+        }
+        self.stats.path_walks += 1;
+
+        let mut pending: VecDeque<String> = VecDeque::new();
+        if !path.starts_with('/') {
+            pending.extend(split_components(cwd));
+        }
+        pending.extend(split_components(path));
+
+        let root = self.root_location();
+        let mut stack: Vec<(String, Location)> = Vec::new();
+        let mut depth = 0;
+
+        while let Some(component) = pending.pop_front() {
+            if component == "." {
+                continue;
+            }
+            if component == ".." {
+                stack.pop();
+                continue;
+            }
+
+            self.stats.components_walked += 1;
+            let parent = stack.last().map_or(root, |(_, location)| *location);
+            let mut location = self.lookup(parent, &component)?;
+            let metadata = self.getattr(location)?;
+            let is_last = pending.iter().all(|rest| rest == ".");
+
+            if metadata.file_type == FileType::Symlink && (!is_last || follow_last) {
+                depth += 1;
+                if depth > MAX_SYMLINK_DEPTH {
+                    return Err(FsError::TooManySymlinks);
+                }
+                let target = self.fs_mut(location.mount)?.readlink(location.inode)?;
+                if target.starts_with('/') {
+                    stack.clear();
+                }
+                for part in split_components(&target).into_iter().rev() {
+                    pending.push_front(part);
+                }
+                self.stats.symlinks_followed += 1;
+                continue;
+            }
+            if !is_last && metadata.file_type != FileType::Directory {
+                return Err(FsError::NotADirectory);
+            }
+
+            // Stepping onto a mount point enters the mounted filesystem's root
+            let mut components: Vec<String> = stack.iter().map(|(name, _)| name.clone()).collect();
+            components.push(component.clone());
+            let (index, relative) = self.resolve_mount(&canonical_path(&components));
+            if relative.as_os_str().is_empty() {
+                let mount = &self.mounts[index];
+                location = Location { mount: mount.id, inode: mount.fs.root() };
+                self.stats.mount_crossings += 1;
+            }
+            stack.push((component, location));
+        }
+
+        Ok(Walk {
+            location: stack.last().map_or(root, |(_, location)| *location),
+            components: stack.into_iter().map(|(name, _)| name).collect(),
+        })
+    }
+
+    // Resolve everything but the last component, which must be a plain name
+    fn walk_parent(&mut self, pid: u32, path: &str) -> FsResult<(Walk, String, Vec<String>)> {
+        let trimmed = path.trim_end_matches('/');
+        let (parent_path, name) = match trimmed.rfind('/') {
+            Some(0) => ("/", &trimmed[1..]),
+            Some(split) => (&trimmed[..split], &trimmed[split + 1..]),
+            None => (".", trimmed),
+        };
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(FsError::InvalidPath);
+        }
+
+        let parent = self.walk(pid, parent_path, true)?;
+        if self.getattr(parent.location)?.file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        let mut full = parent.components.clone();
+        full.push(name.to_string());
+        Ok((parent, name.to_string(), full))
+    }
+
+    fn create_at(&mut self, pid: u32, path: &str, file_type: FileType) -> FsResult<Walk> {
+        let (parent, name, components) = self.walk_parent(pid, path)?;
+        match self.lookup(parent.location, &name) {
+            Ok(_) => return Err(FsError::AlreadyExists),
+            Err(FsError::NotFound) => {}
+            Err(error) => return Err(error),
+        }
+
+        let mount = parent.location.mount;
+        let inode = self.fs_mut(mount)?.create(parent.location.inode, &name, file_type)?;
+        self.dentries.invalidate_dir(mount, parent.location.inode);
+        Ok(Walk { location: Location { mount, inode }, components })
+    }
+
+    fn remove_at(&mut self, pid: u32, path: &str, directory: bool) -> FsResult<()> {
+        let (parent, name, components) = self.walk_parent(pid, path)?;
+        let target = self.lookup(parent.location, &name)?;
+        let is_directory = self.getattr(target)?.file_type == FileType::Directory;
+        match (directory, is_directory) {
+            (false, true) => return Err(FsError::IsADirectory),
+            (true, false) => return Err(FsError::NotADirectory),
+            _ => {}
+        }
+        if self.mounts.iter().any(|mount| mount.path == canonical_path(&components)) {
+            return Err(FsError::Busy);
+        }
+
+        let mount = parent.location.mount;
+        if directory {
+            self.fs_mut(mount)?.rmdir(parent.location.inode, &name)?;
+        } else {
+            let orphan = self.snapshot_if_open(target)?;
+            self.fs_mut(mount)?.unlink(parent.location.inode, &name)?;
+            self.pages.invalidate(target);
+            if let Some(orphan) = orphan {
+                self.adopt_orphan(target, orphan);
+            }
+        }
+        self.dentries.invalidate_dir(mount, parent.location.inode);
+        Ok(())
+    }
+}
+
+// Split out of fs_mut so callers can borrow the page cache alongside
+fn mount_fs(mounts: &mut [Mount], mount: u32) -> FsResult<&mut Box<dyn FileSystem>> {
+    mounts
+        .iter_mut()
+        .find(|candidate| candidate.id == mount)
+        .map(|candidate| &mut candidate.fs)
+        .ok_or(FsError::NotMounted)
+}
+
+fn path_str(path: &Path) -> FsResult<&str> {
+    path.to_str().ok_or(FsError::InvalidPath)
+}
+
+fn split_components(path: &str) -> Vec<String> {
+    path.split('/').filter(|part| !part.is_empty()).map(str::to_string).collect()
+}
+
+fn canonical_path(components: &[String]) -> PathBuf {
+    let mut path = PathBuf::from("/");
+    path.extend(components);
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::procfs::ProcFs;
+    use super::super::tmpfs::TmpFs;
+    use super::*;
+
+    const PID: u32 = 1;
+
+    fn tmpfs() -> Box<dyn FileSystem> {
+        Box::new(TmpFs::new(1 << 20))
+    }
+
+    // A tmpfs root with a second tmpfs mounted at /mnt/data
+    fn two_mounts() -> VirtualFileSystem {
+        let mut vfs = VirtualFileSystem::new(tmpfs());
+        vfs.create_process(PID);
+        vfs.mkdir(PID, Path::new("/mnt")).unwrap();
+        vfs.mkdir(PID, Path::new("/mnt/data")).unwrap();
+        vfs.mount(Path::new("/mnt/data"), tmpfs()).unwrap();
+        vfs.mkdir(PID, Path::new("/mnt/data/sub")).unwrap();
+        vfs
+    }
+
+    fn write_file(vfs: &mut VirtualFileSystem, path: &str, data: &[u8]) {
+        let fd = vfs.open(PID, Path::new(path), OpenFlags::WRITE | OpenFlags::CREATE).unwrap();
+        vfs.write(PID, fd, data).unwrap();
+        vfs.close(PID, fd).unwrap();
+    }
+
+    #[test]
+    fn dot_dot_climbs_out_of_a_mounted_filesystem() {
+        let mut vfs = two_mounts();
+        write_file(&mut vfs, "/mnt/marker", b"root fs");
+
+        let mounted = vfs.stat(PID, Path::new("/mnt/data")).unwrap();
+        assert_eq!(mounted.device, 1);
+        let above = vfs.stat(PID, Path::new("/mnt/data/sub/../..")).unwrap();
+        assert_eq!((above.device, above.inode), (0, vfs.stat(PID, Path::new("/mnt")).unwrap().inode));
+
+        vfs.chdir(PID, Path::new("/mnt/data/sub")).unwrap();
+        let fd = vfs.open(PID, Path::new("../../marker"), OpenFlags::READ).unwrap();
+        let mut buffer = [0u8; 16];
+        let count = vfs.read(PID, fd, &mut buffer).unwrap();
+        assert_eq!(&buffer[..count], b"root fs");
+
+        // ".." at the root stays there
+        assert_eq!(vfs.stat(PID, Path::new("/../..")).unwrap().inode, vfs.stat(PID, Path::new("/")).unwrap().inode);
+        assert!(vfs.get_stats().mount_crossings >= 2);
+    }
+
+    #[test]
+    fn symlinks_resolve_across_mounts_and_stop_at_the_depth_limit() {
+        let mut vfs = two_mounts();
+        write_file(&mut vfs, "/mnt/data/sub/file", b"through a link");
+        vfs.symlink(PID, "/mnt/data/sub", Path::new("/abs")).unwrap();
+        vfs.symlink(PID, "data/sub/file", Path::new("/mnt/rel")).unwrap();
+
+        let target = vfs.stat(PID, Path::new("/mnt/data/sub/file")).unwrap();
+        let via_abs = vfs.stat(PID, Path::new("/abs/file")).unwrap();
+        let via_rel = vfs.stat(PID, Path::new("/mnt/rel")).unwrap();
+        assert_eq!((via_abs.device, via_abs.inode), (target.device, target.inode));
+        assert_eq!((via_rel.device, via_rel.inode), (target.device, target.inode));
+        assert_eq!(vfs.lstat(PID, Path::new("/mnt/rel")).unwrap().file_type, FileType::Symlink);
+        assert_eq!(vfs.readlink(PID, Path::new("/mnt/rel")).unwrap(), "data/sub/file");
+
+        // ".." after a symlink leaves the directory the link pointed into
+        assert_eq!(vfs.stat(PID, Path::new("/abs/..")).unwrap().inode, vfs.stat(PID, Path::new("/mnt/data")).unwrap().inode);
+
+        vfs.symlink(PID, "/loop-b", Path::new("/loop-a")).unwrap();
+        vfs.symlink(PID, "/loop-a", Path::new("/loop-b")).unwrap();
+        assert_eq!(vfs.stat(PID, Path::new("/loop-a")), Err(FsError::TooManySymlinks));
+
+        // A chain of exactly MAX_SYMLINK_DEPTH links resolves; one more does not
+        for link in 1..=MAX_SYMLINK_DEPTH + 1 {
+            vfs.symlink(PID, &format!("/chain{}", link - 1), Path::new(&format!("/chain{}", link))).unwrap();
+        }
+        write_file(&mut vfs, "/chain0", b"end");
+        assert!(vfs.stat(PID, Path::new(&format!("/chain{}", MAX_SYMLINK_DEPTH))).is_ok());
+        assert_eq!(
+            vfs.stat(PID, Path::new(&format!("/chain{}", MAX_SYMLINK_DEPTH + 1))),
+            Err(FsError::TooManySymlinks)
+        );
+    }
+
+    #[test]
+    fn busy_mounts_cannot_be_unmounted() {
+        let mut vfs = two_mounts();
+        assert_eq!(vfs.mount(Path::new("/mnt/data"), tmpfs()).err(), Some(FsError::AlreadyMounted));
+        assert_eq!(vfs.umount(Path::new("/")).err(), Some(FsError::Busy));
+        assert_eq!(vfs.umount(Path::new("/mnt")).err(), Some(FsError::NotMounted));
+        assert_eq!(vfs.rmdir(PID, Path::new("/mnt/data")), Err(FsError::Busy));
+
+        // An open file pins its filesystem
+        write_file(&mut vfs, "/mnt/data/open", b"x");
+        let fd = vfs.open(PID, Path::new("/mnt/data/open"), OpenFlags::READ).unwrap();
+        assert_eq!(vfs.umount(Path::new("/mnt/data")).err(), Some(FsError::Busy));
+        vfs.close(PID, fd).unwrap();
+
+        // So does a filesystem mounted inside it
+        vfs.mount(Path::new("/mnt/data/sub"), tmpfs()).unwrap();
+        assert_eq!(vfs.umount(Path::new("/mnt/data")).err(), Some(FsError::Busy));
+        vfs.umount(Path::new("/mnt/data/sub")).unwrap();
+
+        let unmounted = vfs.umount(Path::new("/mnt/data")).unwrap();
+        assert_eq!(unmounted.name(), "tmpfs");
+        assert_eq!(vfs.mounts().len(), 1);
+        assert_eq!(vfs.stat(PID, Path::new("/mnt/data/open")), Err(FsError::NotFound));
+    }
+
+    #[test]
+    fn dup_shares_the_offset_and_close_frees_the_lowest_descriptor() {
+        let mut vfs = two_mounts();
+        write_file(&mut vfs, "/file", b"abcdef");
+        let first = vfs.open(PID, Path::new("/file"), OpenFlags::READ).unwrap();
+        let copy = vfs.dup(PID, first).unwrap();
+        assert_ne!(first, copy);
+        assert_eq!(vfs.open_file_count(), 1);
+
+        let mut buffer = [0u8; 2];
+        vfs.read(PID, first, &mut buffer).unwrap();
+        vfs.read(PID, copy, &mut buffer).unwrap();
+        assert_eq!(&buffer, b"cd");
+
+        vfs.close(PID, first).unwrap();
+        assert_eq!(vfs.read(PID, first, &mut buffer), Err(FsError::BadFileDescriptor));
+        assert_eq!(vfs.close(PID, first), Err(FsError::BadFileDescriptor));
+        vfs.read(PID, copy, &mut buffer).unwrap();
+        assert_eq!(&buffer, b"ef");
+        assert_eq!(vfs.open_file_count(), 1);
+
+        // The freed slot is handed out again before a new one
+        assert_eq!(vfs.open(PID, Path::new("/file"), OpenFlags::READ).unwrap(), first);
+        vfs.close(PID, copy).unwrap();
+        vfs.close(PID, first).unwrap();
+        assert_eq!(vfs.open_file_count(), 0);
+    }
+
+    #[test]
+    fn lseek_past_end_reads_nothing_and_writes_leave_a_hole() {
+        let mut vfs = two_mounts();
+        write_file(&mut vfs, "/file", b"abc");
+        let fd = vfs.open(PID, Path::new("/file"), OpenFlags::READ_WRITE).unwrap();
+
+        assert_eq!(vfs.lseek(PID, fd, SeekFrom::Start(10)).unwrap(), 10);
+        let mut buffer = [0u8; 16];
+        assert_eq!(vfs.read(PID, fd, &mut buffer).unwrap(), 0);
+        assert_eq!(vfs.fstat(PID, fd).unwrap().size, 3);
+
+        vfs.write(PID, fd, b"z").unwrap();
+        assert_eq!(vfs.fstat(PID, fd).unwrap().size, 11);
+        vfs.lseek(PID, fd, SeekFrom::Start(0)).unwrap();
+        let count = vfs.read(PID, fd, &mut buffer).unwrap();
+        assert_eq!(&buffer[..count], b"abc\0\0\0\0\0\0\0z");
+
+        assert_eq!(vfs.lseek(PID, fd, SeekFrom::End(-1)).unwrap(), 10);
+        assert_eq!(vfs.lseek(PID, fd, SeekFrom::Current(-11)), Err(FsError::InvalidSeek));
+        assert_eq!(vfs.lseek(PID, fd, SeekFrom::Current(0)).unwrap(), 10);
+    }
+
+    #[test]
+    fn procfs_files_are_generated_on_every_read() {
+        let mut vfs = two_mounts();
+        vfs.mkdir(PID, Path::new("/proc")).unwrap();
+        let mut proc = ProcFs::new();
+        let counter = std::rc::Rc::new(std::cell::Cell::new(0));
+        let reads = counter.clone();
+        proc.register(
+            "1/status",
+            Box::new(move || {
+                reads.set(reads.get() + 1);
+                format!("reads: {}\n", reads.get())
+            }),
+        )
+        .unwrap();
+        vfs.mount(Path::new("/proc"), Box::new(proc)).unwrap();
+
+        let mut buffer = [0u8; 32];
+        for expected in ["reads: 1\n", "reads: 2\n"] {
+            let fd = vfs.open(PID, Path::new("/proc/1/status"), OpenFlags::READ).unwrap();
+            let count = vfs.read(PID, fd, &mut buffer).unwrap();
+            assert_eq!(&buffer[..count], expected.as_bytes());
+            vfs.close(PID, fd).unwrap();
+        }
+        assert_eq!(vfs.page_cache_stats().misses, 0);
+        let fd = vfs.open(PID, Path::new("/proc/1/status"), OpenFlags::WRITE).unwrap();
+        assert_eq!(vfs.write(PID, fd, b"x"), Err(FsError::ReadOnly));
+    }
+
+    #[test]
+    fn unlinked_files_stay_readable_through_open_descriptors() {
+        let mut vfs = two_mounts();
+        let flags = OpenFlags::READ | OpenFlags::WRITE | OpenFlags::CREATE;
+        let fd = vfs.open(PID, Path::new("/mnt/data/file"), flags).unwrap();
+        vfs.write(PID, fd, b"hello").unwrap();
+        vfs.unlink(PID, Path::new("/mnt/data/file")).unwrap();
+        assert_eq!(vfs.stat(PID, Path::new("/mnt/data/file")), Err(FsError::NotFound));
+
+        // A new file under the old name is unrelated to the open one
+        write_file(&mut vfs, "/mnt/data/file", b"other");
+        vfs.write(PID, fd, b" world").unwrap();
+        vfs.lseek(PID, fd, SeekFrom::Start(0)).unwrap();
+        let mut buffer = [0u8; 16];
+        let count = vfs.read(PID, fd, &mut buffer).unwrap();
+        assert_eq!(&buffer[..count], b"hello world");
+        let metadata = vfs.fstat(PID, fd).unwrap();
+        assert_eq!((metadata.size, metadata.links), (11, 0));
+        assert_eq!(vfs.stat(PID, Path::new("/mnt/data/file")).unwrap().size, 5);
+
+        vfs.close(PID, fd).unwrap();
+        assert_eq!(vfs.open_file_count(), 0);
+        assert!(vfs.orphans.is_empty());
     }
 }
@@ -0,0 +1,8 @@
+#[derive(Debug, Clone, PartialEq)]
+pub enum KernelError {
+    // Process errors
+    NoSuchProcess,
+    NotRunning,
+}
+
+pub type KernelResult<T> = Result<T, KernelError>;
@@ -68,3 +68,9 @@ impl MemoryManager {
         }
     }
 }
+
+impl Default for MemoryManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,3 +1,4 @@
+pub mod error;
 pub mod process;
 pub mod memory;
 pub mod scheduler;
@@ -28,4 +29,10 @@ impl Kernel {
             panic!("Failed to allocate memory for process")
         }
     }
-} 
\ No newline at end of file
+} 
+
+impl Default for Kernel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
\ No newline at end of file
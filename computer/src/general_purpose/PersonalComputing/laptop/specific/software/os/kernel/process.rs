@@ -6,6 +6,7 @@ pub enum ProcessState {
     Terminated,
 }
 
+#[derive(Debug, Clone)]
 pub struct Process {
     pub id: u32,
     pub state: ProcessState,
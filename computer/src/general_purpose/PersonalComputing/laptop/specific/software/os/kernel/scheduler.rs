@@ -1,39 +1,165 @@
 use super::process::{Process, ProcessState};
 use super::error::{KernelError, KernelResult};
+use std::cmp::{Ordering, Reverse};
 use std::collections::{BinaryHeap, HashMap};
-use std::time::Duration;
 
 pub struct Scheduler {
-    ready_queue: BinaryHeap<Process>,
+    ready_queue: BinaryHeap<ReadyProcess>,
     waiting_queues: HashMap<WaitReason, Vec<Process>>,
     current_process: Option<Process>,
-    time_slice: Duration,
-    policy: SchedulingPolicy,
+    next_pid: u32,
+    next_sequence: u64, // Orders equal priorities first come, first served
 }
 
-enum SchedulingPolicy {
-    RoundRobin { quantum: Duration },
-    Priority { levels: u8 },
-    MultiLevel { queues: Vec<ProcessQueue> },
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WaitReason {
+    Io,
+    Sleep,
+    Child(u32),
+}
+
+// Highest priority first; the longest waiting among equals
+struct ReadyProcess {
+    process: Process,
+    sequence: u64,
 }
 
 impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            ready_queue: BinaryHeap::new(),
+            waiting_queues: HashMap::new(),
+            current_process: None,
+            next_pid: 1,
+            next_sequence: 0,
+        }
+    }
+
+    pub fn create_process(&mut self, memory_start: u16, memory_size: u16) -> u32 {
+        let pid = self.next_pid;
+        self.next_pid += 1;
+        self.make_ready(Process::new(pid, memory_start, memory_size));
+        pid
+    }
+
     pub fn schedule(&mut self) -> KernelResult<Option<Process>> {
         // Handle current process
         if let Some(mut current) = self.current_process.take() {
             if current.state == ProcessState::Running {
                 current.state = ProcessState::Ready;
-                self.ready_queue.push(current);
+                self.make_ready(current);
             }
         }
 
         // Select next process
-        if let Some(mut next) = self.ready_queue.pop() {
-            next.state = ProcessState::Running;
-            self.current_process = Some(next.clone());
-            Ok(Some(next))
+        if let Some(ReadyProcess { mut process, .. }) = self.ready_queue.pop() {
+            process.state = ProcessState::Running;
+            self.current_process = Some(process.clone());
+            Ok(Some(process))
         } else {
             Ok(None)
         }
     }
+
+    // Parks the running process until `wake` is called with the same reason
+    pub fn block(&mut self, reason: WaitReason) -> KernelResult<()> {
+        let mut current = self.current_process.take().ok_or(KernelError::NotRunning)?;
+        current.state = ProcessState::Blocked;
+        self.waiting_queues.entry(reason).or_default().push(current);
+        Ok(())
+    }
+
+    // Makes every process waiting on `reason` ready again; returns how many
+    pub fn wake(&mut self, reason: WaitReason) -> usize {
+        let waiting = self.waiting_queues.remove(&reason).unwrap_or_default();
+        let count = waiting.len();
+        for mut process in waiting {
+            process.state = ProcessState::Ready;
+            self.make_ready(process);
+        }
+        count
+    }
+
+    pub fn exit_current(&mut self) -> KernelResult<u32> {
+        let mut current = self.current_process.take().ok_or(KernelError::NotRunning)?;
+        current.state = ProcessState::Terminated;
+        self.wake(WaitReason::Child(current.id));
+        Ok(current.id)
+    }
+
+    pub fn current(&self) -> Option<&Process> {
+        self.current_process.as_ref()
+    }
+
+    pub fn ready_count(&self) -> usize {
+        self.ready_queue.len()
+    }
+
+    fn make_ready(&mut self, process: Process) {
+        self.ready_queue.push(ReadyProcess { process, sequence: self.next_sequence });
+        self.next_sequence += 1;
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReadyProcess {
+    fn key(&self) -> (u8, Reverse<u64>) {
+        (self.process.priority, Reverse(self.sequence))
+    }
+}
+
+impl PartialEq for ReadyProcess {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for ReadyProcess {}
+
+impl PartialOrd for ReadyProcess {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ReadyProcess {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_robins_equal_priorities() {
+        let mut scheduler = Scheduler::new();
+        let first = scheduler.create_process(0x1000, 0x100);
+        let second = scheduler.create_process(0x1100, 0x100);
+
+        assert_eq!(scheduler.schedule().unwrap().unwrap().id, first);
+        assert_eq!(scheduler.schedule().unwrap().unwrap().id, second);
+        assert_eq!(scheduler.schedule().unwrap().unwrap().id, first);
+    }
+
+    #[test]
+    fn blocked_processes_wait_for_their_wakeup() {
+        let mut scheduler = Scheduler::new();
+        let pid = scheduler.create_process(0x1000, 0x100);
+        scheduler.schedule().unwrap();
+        scheduler.block(WaitReason::Io).unwrap();
+
+        assert!(scheduler.schedule().unwrap().is_none());
+        assert_eq!(scheduler.wake(WaitReason::Sleep), 0);
+        assert_eq!(scheduler.wake(WaitReason::Io), 1);
+        assert_eq!(scheduler.schedule().unwrap().unwrap().id, pid);
+        assert_eq!(scheduler.block(WaitReason::Io), Ok(()));
+        assert_eq!(scheduler.block(WaitReason::Io), Err(KernelError::NotRunning));
+    }
 }
@@ -1,4 +1,10 @@
 use super::error::{ServiceError, ServiceResult};
+use crate::hardware::bus::mmio::AccessSize;
+use crate::hardware::bus::pci_bus::ECAM_BASE;
+use crate::hardware::bus::pci_config::{ConfigAccess, PciAddress};
+use crate::hardware::bus::pci_enumeration::{self, PciFunction};
+use crate::hardware::bus::Bus;
+use crate::hardware::memory::physical::PhysicalMemory;
 use std::collections::HashMap;
 
 pub struct DeviceManager {
@@ -6,6 +12,8 @@ pub struct DeviceManager {
     drivers: HashMap<DriverId, Box<dyn Driver>>,
     bus_controllers: HashMap<BusType, Box<dyn BusController>>,
     power_manager: PowerManager,
+    pci_functions: Vec<PciFunction>, // As last scanned, in bus order
+    pci_devices: HashMap<PciAddress, DeviceId>, // Registered PCI functions by BDF
 }
 
 #[derive(Hash, Eq, PartialEq, Clone, Copy)]
@@ -47,6 +55,8 @@ impl DeviceManager {
             drivers: HashMap::new(),
             bus_controllers: HashMap::new(),
             power_manager: PowerManager::default(),
+            pci_functions: Vec::new(),
+            pci_devices: HashMap::new(),
         }
     }
 
@@ -68,6 +78,65 @@ impl DeviceManager {
         Ok(())
     }
 
+    // Walks PCI config space through the ECAM window, the way the kernel
+    // does at boot, and registers every function firmware left enabled.
+    // Functions are known by bus/device/function, so a rescan only adds
+    // what is new and drops what stopped answering. Returns how many
+    // enabled functions answered.
+    pub fn scan_pci<M: PhysicalMemory>(&mut self, bus: &mut Bus, memory: &mut M) -> ServiceResult<usize> {
+        let functions: Vec<PciFunction> = pci_enumeration::scan(&mut Ecam { bus, memory })
+            .into_iter()
+            .filter(PciFunction::is_enabled)
+            .collect();
+        if functions.is_empty() {
+            return Err(ServiceError::NotFound);
+        }
+
+        let gone: Vec<PciAddress> = self
+            .pci_devices
+            .keys()
+            .filter(|address| !functions.iter().any(|function| function.address == **address))
+            .copied()
+            .collect();
+        for address in gone {
+            if let Some(device_id) = self.pci_devices.remove(&address) {
+                self.remove_device(device_id)?;
+            }
+        }
+
+        for function in &functions {
+            if self.pci_devices.contains_key(&function.address) {
+                continue;
+            }
+            let info = DeviceInfo {
+                vendor_id: function.vendor_id,
+                device_id: function.device_id,
+                class: DeviceClass::from_pci(function.class.class, function.class.subclass),
+                subclass: function.class.subclass,
+                capabilities: DeviceCapabilities::default(),
+            };
+            let driver = self.find_driver(&info)?;
+            let device_id = self.register_device(info)?;
+            self.pci_devices.insert(function.address, device_id);
+            if let Some(driver_id) = driver {
+                self.attach_driver(device_id, driver_id)?;
+            }
+        }
+
+        let count = functions.len();
+        self.pci_functions = functions;
+        Ok(count)
+    }
+
+    pub fn pci_functions(&self) -> &[PciFunction] {
+        &self.pci_functions
+    }
+
+    // The scanned hierarchy, one line per function, like `lspci -nn`
+    pub fn lspci(&self) -> Vec<String> {
+        self.pci_functions.iter().map(pci_enumeration::describe).collect()
+    }
+
     pub fn handle_hotplug(&mut self, event: HotplugEvent) -> ServiceResult<()> {
         match event {
             HotplugEvent::DeviceAdded(info) => {
@@ -91,4 +160,43 @@ impl DeviceManager {
         }
         Ok(None)
     }
-} 
\ No newline at end of file
+} 
+
+impl DeviceClass {
+    fn from_pci(class: u8, subclass: u8) -> Self {
+        match (class, subclass) {
+            (0x01, _) => DeviceClass::Storage,
+            (0x02, _) => DeviceClass::Network,
+            (0x03, _) => DeviceClass::Display,
+            (0x04, 0x01) | (0x04, 0x03) => DeviceClass::Audio,
+            (0x07, _) => DeviceClass::Serial,
+            (0x09, _) => DeviceClass::Input,
+            (class, _) => DeviceClass::Other(class),
+        }
+    }
+}
+
+// Config space as the kernel reaches it: loads and stores in the ECAM window.
+// A failed access reads as all-ones, which is what a master abort returns.
+struct Ecam<'a, M: PhysicalMemory> {
+    bus: &'a mut Bus,
+    memory: &'a mut M, // Lent with every bus access, though config space never reaches it
+}
+
+impl<M: PhysicalMemory> ConfigAccess for Ecam<'_, M> {
+    fn config_read(&mut self, address: PciAddress, register: u16, bytes: usize) -> u32 {
+        let Some(size) = AccessSize::from_bytes(bytes as u64) else {
+            return u32::MAX;
+        };
+        let all_ones = size.mask() as u32;
+        self.bus
+            .read_sized(ECAM_BASE + address.ecam_offset(register), size, self.memory)
+            .map_or(all_ones, |value| value as u32)
+    }
+
+    fn config_write(&mut self, address: PciAddress, register: u16, bytes: usize, value: u32) {
+        if let Some(size) = AccessSize::from_bytes(bytes as u64) {
+            let _ = self.bus.write_sized(ECAM_BASE + address.ecam_offset(register), size, value as u64, self.memory);
+        }
+    }
+}
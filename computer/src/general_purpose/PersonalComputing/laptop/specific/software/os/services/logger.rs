@@ -1,12 +1,13 @@
 use super::error::{ServiceError, ServiceResult};
 use std::collections::VecDeque;
-use std::time::SystemTime;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub struct Logger {
     logs: VecDeque<LogEntry>,
     filters: Vec<LogFilter>,
     writers: Vec<Box<dyn LogWriter>>,
     config: LoggerConfig,
+    now: SystemTime, // Simulated wall time, set by the time manager
 }
 
 struct LogEntry {
@@ -39,12 +40,17 @@ impl Logger {
             filters: Vec::new(),
             writers: Vec::new(),
             config,
+            now: UNIX_EPOCH,
         }
     }
 
+    pub fn set_time(&mut self, now: SystemTime) {
+        self.now = now;
+    }
+
     pub fn log(&mut self, level: LogLevel, module: &str, message: &str, metadata: LogMetadata) -> ServiceResult<()> {
         let entry = LogEntry {
-            timestamp: SystemTime::now(),
+            timestamp: self.now,
             level,
             module: module.to_string(),
             message: message.to_string(),
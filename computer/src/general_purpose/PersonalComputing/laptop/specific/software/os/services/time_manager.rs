@@ -1,12 +1,18 @@
 use super::error::{TimeError, TimeResult};
+use crate::simulation::time::SimTime;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+// The wall clock the machine believes in at power-on. Fixed, so two runs
+// from the same seed agree on every timestamp.
+const BOOT_EPOCH_SECS: u64 = 1_700_000_000;
+
 pub struct TimeManager {
     rtc: RealTimeClock,
     ntp: NtpClient,
     timers: TimerManager,
     timezone: TimeZone,
     stats: TimeStats,
+    now: SimTime, // Simulated time since power-on, from the event kernel
 }
 
 struct RealTimeClock {
@@ -38,6 +44,7 @@ impl TimeManager {
             timers: TimerManager::new(config.timer_resolution),
             timezone: TimeZone::from_name(&config.timezone),
             stats: TimeStats::default(),
+            now: SimTime::ZERO,
         }
     }
 
@@ -62,16 +69,17 @@ impl TimeManager {
             interval: config.interval,
             callback: config.callback,
             repeating: config.repeating,
-            next_trigger: SystemTime::now() + config.interval,
+            next_trigger: self.wall_time() + config.interval,
         };
         
         self.timers.add_timer(timer)?;
         Ok(timer.id)
     }
 
-    pub fn tick(&mut self) -> TimeResult<()> {
+    pub fn tick(&mut self, now: SimTime) -> TimeResult<()> {
         // Update system time
-        let now = SystemTime::now();
+        self.now = now;
+        let now = self.wall_time();
         
         // Check for timer events
         self.timers.check_timers(now)?;
@@ -83,4 +91,14 @@ impl TimeManager {
         
         Ok(())
     }
-} 
\ No newline at end of file
+
+    // What the OS reads as the time of day: the boot epoch plus simulated
+    // uptime, never the host's clock
+    pub fn wall_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(BOOT_EPOCH_SECS) + Duration::from_nanos(self.now.as_ns())
+    }
+
+    pub fn uptime(&self) -> SimTime {
+        self.now
+    }
+}
@@ -3,9 +3,13 @@ use crate::hardware::bus::{
     SystemBus,
     MemoryBus,
     PciBus,
-    arbitration::BusArbiter
+    arbitration::{BusArbiter, DeviceId, DeviceType}
 };
 
+// Cycles shown in the ownership strip, newest on the right
+const OWNERSHIP_COLUMNS: usize = 256;
+const OWNERSHIP_STRIP_HEIGHT: f32 = 24.0;
+
 pub struct BusVisualizer {
     // Bus components
     system_bus: SystemBusView,
@@ -19,6 +23,10 @@ pub struct BusVisualizer {
     // Animation state
     data_flows: Vec<DataFlow>,
     active_transfers: Vec<TransferAnimation>,
+
+    // Arbitration
+    ownership: Vec<Option<DeviceId>>,
+    starving: Vec<DeviceId>,
 }
 
 impl BusVisualizer {
@@ -31,6 +39,8 @@ impl BusVisualizer {
             size,
             data_flows: Vec::new(),
             active_transfers: Vec::new(),
+            ownership: Vec::new(),
+            starving: Vec::new(),
         }
     }
 
@@ -44,6 +54,13 @@ impl BusVisualizer {
         self.update_data_flows(bus);
     }
 
+    pub fn update_arbitration(&mut self, arbiter: &BusArbiter) {
+        let history = arbiter.ownership_history();
+        let skip = history.len().saturating_sub(OWNERSHIP_COLUMNS);
+        self.ownership = history.iter().skip(skip).copied().collect();
+        self.starving = arbiter.starving_devices();
+    }
+
     pub fn render(&self, frame: &mut Frame) {
         // Draw bus interconnects
         self.draw_bus_topology(frame);
@@ -58,10 +75,40 @@ impl BusVisualizer {
             transfer.render(frame);
         }
         
+        // Draw who held the bus each cycle
+        self.draw_ownership_timeline(frame);
+
         // Draw metrics
         self.draw_bus_metrics(frame);
     }
 
+    fn draw_ownership_timeline(&self, frame: &mut Frame) {
+        let column_width = self.size.width / OWNERSHIP_COLUMNS as f32;
+        let top = self.position.y + self.size.height - OWNERSHIP_STRIP_HEIGHT;
+        let first = OWNERSHIP_COLUMNS - self.ownership.len();
+
+        for (i, owner) in self.ownership.iter().enumerate() {
+            let x = self.position.x + (first + i) as f32 * column_width;
+            let color = match owner {
+                Some(device) => owner_color(device.device_type()),
+                None => Color::GRAY,
+            };
+            frame.fill_rect(
+                Rect::new(Point::new(x, top), Size::new(column_width, OWNERSHIP_STRIP_HEIGHT)),
+                color
+            );
+        }
+
+        // Flag devices whose requests have waited past the starvation threshold
+        for (i, device) in self.starving.iter().enumerate() {
+            frame.draw_text(
+                &format!("{} starving", device.label()),
+                Point::new(self.position.x + i as f32 * 90.0, top - 16.0),
+                TextStyle::default()
+            );
+        }
+    }
+
     fn update_data_flows(&mut self, bus: &SystemBus) {
         // Clear completed transfers
         self.active_transfers.retain(|t| !t.is_complete());
@@ -76,4 +123,13 @@ impl BusVisualizer {
             self.active_transfers.push(animation);
         }
     }
-} 
\ No newline at end of file
+} 
+
+fn owner_color(device_type: DeviceType) -> Color {
+    match device_type {
+        DeviceType::CPU => Color::BLUE,
+        DeviceType::GPU => Color::GREEN,
+        DeviceType::DMA => Color::ORANGE,
+        DeviceType::Peripheral => Color::YELLOW,
+    }
+}
@@ -1,5 +1,11 @@
 use super::common::{Point, Size, Color, Rect};
 use crate::hardware::storage::disk::{DiskController, Platter, Head};
+use crate::hardware::storage::disk::controller::ArmMovement;
+
+// Simulated time the arm-sweep chart spans, and how finely it is sampled
+const SWEEP_WINDOW_NS: u64 = 200_000_000;
+const SWEEP_SAMPLES: usize = 400;
+const SWEEP_CHART_HEIGHT: f32 = 120.0;
 
 pub struct DiskVisualizer {
     position: Point,
@@ -8,33 +14,122 @@ pub struct DiskVisualizer {
     heads: Vec<HeadView>,
     controller: DiskControllerView,
     cache: DiskCacheView,
+
+    // Arm sweep
+    arm: ArmSweep,
+}
+
+// Where the arm has been over the last window, replayed from the controller's arm trace
+struct ArmSweep {
+    cylinders: u32,
+    now: u64,
+    current: f64,
+    samples: Vec<f64>,
+    seeks: Vec<ArmMovement>,
 }
 
 impl DiskVisualizer {
+    pub fn update_arm(&mut self, controller: &DiskController) {
+        let now = controller.now();
+        let start = now.saturating_sub(SWEEP_WINDOW_NS);
+        let step = (now - start) / SWEEP_SAMPLES as u64;
+
+        self.arm.cylinders = controller.cylinders().max(1);
+        self.arm.now = now;
+        self.arm.current = controller.arm_position_at(now);
+        self.arm.samples = (0..=SWEEP_SAMPLES as u64)
+            .map(|i| controller.arm_position_at(start + i * step))
+            .collect();
+        self.arm.seeks = controller
+            .arm_trace()
+            .iter()
+            .filter(|movement| movement.start + movement.duration >= start)
+            .cloned()
+            .collect();
+    }
+
     pub fn render(&self, frame: &mut Frame) {
         // Draw disk structure
         self.draw_disk_structure(frame);
-        
+
         // Render platters
         for platter in &self.platters {
             platter.render(frame);
         }
-        
+
         // Render heads
         for head in &self.heads {
             head.render(frame);
         }
-        
+
         // Render controller
         self.controller.render(frame);
-        
+
         // Render cache
         self.cache.render(frame);
-        
+
+        // Draw the arm over the platter and the cylinder it has swept through
+        self.draw_arm(frame);
+        self.draw_arm_sweep(frame);
+
         // Draw access patterns
         self.draw_access_patterns(frame);
-        
+
         // Draw performance metrics
         self.draw_disk_metrics(frame);
     }
-} 
\ No newline at end of file
+
+    // The arm pivots at the platter's edge; cylinder 0 is the outer rim
+    fn draw_arm(&self, frame: &mut Frame) {
+        let radius = self.size.width.min(self.size.height - SWEEP_CHART_HEIGHT) / 2.0;
+        let spindle = self.position + Point::new(radius, radius);
+        let track_radius = radius * (1.0 - 0.8 * (self.arm.current / self.arm.cylinders as f64) as f32);
+        let pivot = spindle + Point::new(radius, -radius);
+        let tip = spindle + Point::new(track_radius * 0.7071, -track_radius * 0.7071);
+
+        frame.draw_line(pivot, tip, Color::ORANGE);
+        frame.draw_text(
+            &format!("Cylinder {:.0}", self.arm.current),
+            pivot + Point::new(-60.0, -16.0),
+            TextStyle::default()
+        );
+    }
+
+    // Cylinder against time, oldest on the left: SCAN and LOOK show up as
+    // sawteeth, SSTF as short hops, FCFS as a scribble
+    fn draw_arm_sweep(&self, frame: &mut Frame) {
+        let top = self.position.y + self.size.height - SWEEP_CHART_HEIGHT;
+        let chart = Rect::new(Point::new(self.position.x, top), Size::new(self.size.width, SWEEP_CHART_HEIGHT));
+        frame.draw_rect_outline(chart, Color::GRAY, 1.0);
+
+        let column_width = self.size.width / SWEEP_SAMPLES as f32;
+        let to_point = |i: usize, cylinder: f64| {
+            Point::new(
+                self.position.x + i as f32 * column_width,
+                top + SWEEP_CHART_HEIGHT * (cylinder / self.arm.cylinders as f64) as f32,
+            )
+        };
+        for (i, pair) in self.arm.samples.windows(2).enumerate() {
+            frame.draw_line(to_point(i, pair[0]), to_point(i + 1, pair[1]), Color::WHITE);
+        }
+
+        // Mark where each seek ended, brighter when it was on behalf of a request
+        let start = self.arm.now.saturating_sub(SWEEP_WINDOW_NS);
+        let ns_per_column = (SWEEP_WINDOW_NS / SWEEP_SAMPLES as u64).max(1);
+        for movement in &self.arm.seeks {
+            let end = movement.start + movement.duration;
+            let column = (end.saturating_sub(start) / ns_per_column) as usize;
+            let color = if movement.request.is_some() { Color::GREEN } else { Color::GRAY };
+            frame.fill_rect(
+                Rect::new(to_point(column, movement.to_cylinder as f64) - Point::new(2.0, 2.0), Size::new(4.0, 4.0)),
+                color
+            );
+        }
+
+        frame.draw_text(
+            &format!("{} seeks in the last {} ms", self.arm.seeks.len(), SWEEP_WINDOW_NS / 1_000_000),
+            Point::new(self.position.x, top - 16.0),
+            TextStyle::default()
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use super::common::{Point, Size};
+use crate::hardware::io::controllers::fis::FisType;
+use crate::hardware::io::controllers::sata::{FisRecord, SataController};
+use crate::hardware::storage::nvme::controller::{NVMeController, QueuePairDepth};
+
+// How many of the most recent FIS exchanges the AHCI ladder shows
+const FIS_LADDER_LENGTH: usize = 16;
+const AHCI_SLOTS: u32 = 32;
+const ROW_HEIGHT: usize = 18;
+
+// Left, top, width and height in pixels
+type Area = (usize, usize, usize, usize);
+
+const GRAY: u32 = 0x808080;
+const WHITE: u32 = 0xFFFFFF;
+const BLUE: u32 = 0x3070FF;
+const GREEN: u32 = 0x30C050;
+const YELLOW: u32 = 0xF0D020;
+const ORANGE: u32 = 0xF08020;
+
+// AHCI and NVMe side by side: one 32-slot command list per SATA port against
+// many independent submission/completion queue pairs
+pub struct HostInterfaceVisualizer {
+    position: Point,
+    size: Size,
+    ahci: AhciView,
+    nvme: NvmeView,
+}
+
+struct AhciView {
+    ports: Vec<AhciPortView>,
+    fis: Vec<FisRecord>,
+}
+
+struct AhciPortView {
+    port: u8,
+    // PxCI: slots the host has issued and the HBA hasn't fetched yet
+    issued: u32,
+    // PxSACT: NCQ tags the drive is holding
+    active: u32,
+    outstanding: Vec<u8>,
+}
+
+struct NvmeView {
+    pairs: Vec<QueuePairDepth>,
+}
+
+impl HostInterfaceVisualizer {
+    pub fn new(position: Point, size: Size) -> Self {
+        Self {
+            position,
+            size,
+            ahci: AhciView { ports: Vec::new(), fis: Vec::new() },
+            nvme: NvmeView { pairs: Vec::new() },
+        }
+    }
+
+    pub fn update(&mut self, hba: &SataController, nvme: &NVMeController) {
+        self.ahci.ports = (0..32u8)
+            .filter_map(|port| {
+                hba.port_registers(port).map(|registers| AhciPortView {
+                    port,
+                    issued: registers.command_issue,
+                    active: registers.sata_active,
+                    outstanding: hba.outstanding_tags(port),
+                })
+            })
+            .filter(|view| view.issued != 0 || view.active != 0 || !view.outstanding.is_empty())
+            .collect();
+        self.ahci.fis = hba.fis_trace().iter().rev().take(FIS_LADDER_LENGTH).rev().cloned().collect();
+        self.nvme.pairs = nvme.queue_pairs();
+    }
+
+    // Draws straight into the window's framebuffer, one 0xRRGGBB pixel per u32
+    pub fn render(&self, buffer: &mut [u32], width: usize, height: usize) {
+        let x = self.position.x as usize;
+        let y = self.position.y as usize;
+        let half = (self.size.width as usize / 2).saturating_sub(8);
+        let rows = self.size.height as usize;
+
+        let (left, right) = ((x, y, half, rows), (x + half + 16, y, half, rows));
+        outline(buffer, width, height, left, GRAY);
+        outline(buffer, width, height, right, GRAY);
+
+        self.draw_ahci(buffer, width, height, left);
+        self.draw_nvme(buffer, width, height, right);
+    }
+
+    // One row of 32 command slots per busy port, then the FIS ladder below it
+    fn draw_ahci(&self, buffer: &mut [u32], width: usize, height: usize, (x, y, panel, rows): Area) {
+        let slot_width = (panel.saturating_sub(48) / AHCI_SLOTS as usize).max(2);
+        let bottom = y + rows.saturating_sub(ROW_HEIGHT);
+        let mut row = y + 8;
+        for view in &self.ahci.ports {
+            for slot in 0..AHCI_SLOTS {
+                let color = if view.issued & 1 << slot != 0 {
+                    YELLOW
+                } else if view.active & 1 << slot != 0 || view.outstanding.contains(&(slot as u8)) {
+                    ORANGE
+                } else {
+                    GRAY
+                };
+                let cell = x + 40 + slot as usize * slot_width;
+                fill(buffer, width, height, (cell, row, slot_width - 1, ROW_HEIGHT - 4), color);
+            }
+            row += ROW_HEIGHT;
+        }
+
+        // Host on the left, device on the right; each FIS is an arrow between them
+        row += ROW_HEIGHT;
+        let host = x + 40;
+        let device = (x + panel).saturating_sub(40);
+        for record in &self.ahci.fis {
+            if row > bottom {
+                break;
+            }
+            let color = match record.fis_type {
+                FisType::RegisterH2D => BLUE,
+                FisType::RegisterD2H => WHITE,
+                FisType::DmaSetup | FisType::Data => GREEN,
+                FisType::SetDeviceBits => ORANGE,
+                _ => GRAY,
+            };
+            fill(buffer, width, height, (host, row + ROW_HEIGHT / 2, device.saturating_sub(host), 2), color);
+            // The arrowhead marks which end the FIS travels to
+            let head = if record.to_host { host } else { device.saturating_sub(6) };
+            fill(buffer, width, height, (head, row + ROW_HEIGHT / 2 - 3, 6, 8), color);
+            row += ROW_HEIGHT;
+        }
+    }
+
+    // Each queue pair as a submission bar over a completion bar, filled to its depth
+    fn draw_nvme(&self, buffer: &mut [u32], width: usize, height: usize, (x, y, panel, rows): Area) {
+        let bar_width = panel.saturating_sub(56);
+        let bar_height = ROW_HEIGHT / 2 - 1;
+        let bottom = y + rows.saturating_sub(ROW_HEIGHT);
+        let mut row = y + 8;
+        for pair in &self.nvme.pairs {
+            if row > bottom {
+                break;
+            }
+            let depth = pair.size.max(1) as f32;
+            let sq = row;
+            let cq = row + ROW_HEIGHT / 2;
+            outline(buffer, width, height, (x + 48, sq, bar_width, bar_height), GRAY);
+            outline(buffer, width, height, (x + 48, cq, bar_width, bar_height), GRAY);
+            let submitted = (bar_width as f32 * (pair.submitted as f32 / depth).min(1.0)) as usize;
+            let completed = (bar_width as f32 * (pair.completed as f32 / depth).min(1.0)) as usize;
+            fill(buffer, width, height, (x + 48, sq, submitted, bar_height), YELLOW);
+            fill(buffer, width, height, (x + 48, cq, completed, bar_height), GREEN);
+            row += ROW_HEIGHT + 4;
+        }
+    }
+}
+
+fn fill(buffer: &mut [u32], width: usize, height: usize, (x, y, w, h): Area, color: u32) {
+    for py in y..(y + h).min(height) {
+        for px in x..(x + w).min(width) {
+            let index = py * width + px;
+            if index < buffer.len() {
+                buffer[index] = color;
+            }
+        }
+    }
+}
+
+fn outline(buffer: &mut [u32], width: usize, height: usize, (x, y, w, h): Area, color: u32) {
+    fill(buffer, width, height, (x, y, w, 1), color);
+    fill(buffer, width, height, (x, y + h.saturating_sub(1), w, 1), color);
+    fill(buffer, width, height, (x, y, 1, h), color);
+    fill(buffer, width, height, (x + w.saturating_sub(1), y, 1, h), color);
+}
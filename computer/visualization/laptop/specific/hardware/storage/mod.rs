@@ -10,6 +10,7 @@ pub mod disk;
 pub mod ssd;
 pub mod nvme;
 pub mod filesystem;
+pub mod host_interface;
 
 pub struct StorageVisualizer {
     position: Point,
@@ -19,6 +20,9 @@ pub struct StorageVisualizer {
     disk: disk::DiskVisualizer,
     ssd: ssd::SsdVisualizer,
     nvme: nvme::NvmeVisualizer,
+
+    // AHCI against NVMe queueing, along the bottom
+    host_interface: host_interface::HostInterfaceVisualizer,
     
     // Filesystem
     filesystem: filesystem::FilesystemVisualizer,
@@ -38,6 +42,10 @@ impl StorageVisualizer {
             ssd: ssd::SsdVisualizer::new(layout.ssd_region),
             nvme: nvme::NvmeVisualizer::new(layout.nvme_region),
             filesystem: filesystem::FilesystemVisualizer::new(layout.fs_region),
+            host_interface: host_interface::HostInterfaceVisualizer::new(
+                Point::new(position.x, position.y + size.height * 0.75),
+                Size::new(size.width, size.height * 0.25),
+            ),
             data_transfers: Vec::new(),
         }
     }
@@ -62,4 +70,9 @@ impl StorageVisualizer {
         // Draw performance metrics
         self.draw_storage_metrics(frame);
     }
+
+    // The AHCI/NVMe panel paints pixels itself rather than going through the frame
+    pub fn render_host_interface(&self, buffer: &mut [u32], width: usize, height: usize) {
+        self.host_interface.render(buffer, width, height);
+    }
 } 
\ No newline at end of file
@@ -1,4 +1,6 @@
 use crate::src::os::process::{Process, ProcessState};
+use crate::src::hardware::gpu::scheduler::ContextUsage;
+use std::collections::HashMap;
 use super::common::{blend_colors, ACTIVE_COLOR, INACTIVE_COLOR};
 
 pub struct TaskManager {
@@ -6,6 +8,10 @@ pub struct TaskManager {
     selected_process: Option<usize>,
     sort_column: SortColumn,
     sort_ascending: bool,
+    gpu_busy: HashMap<u32, u64>, // Busy cycles per pid at the last GPU sample
+    gpu_sampled_at: u64,
+    gpu_usage: HashMap<u32, f32>,
+    gpu_history: Vec<f32>,
 }
 
 struct ProcessInfo {
@@ -13,6 +19,7 @@ struct ProcessInfo {
     name: String,
     state: ProcessState,
     cpu_usage: f32,
+    gpu_usage: f32,
     memory_usage: usize,
     threads: usize,
     selected: bool,
@@ -21,6 +28,7 @@ struct ProcessInfo {
 enum SortColumn {
     Name,
     CPU,
+    GPU,
     Memory,
     PID,
     Status,
@@ -33,6 +41,10 @@ impl TaskManager {
             selected_process: None,
             sort_column: SortColumn::CPU,
             sort_ascending: false,
+            gpu_busy: HashMap::new(),
+            gpu_sampled_at: 0,
+            gpu_usage: HashMap::new(),
+            gpu_history: Vec::new(),
         }
     }
 
@@ -107,6 +119,29 @@ impl TaskManager {
         
         // Draw disk usage graph
         self.draw_disk_graph(buffer, width, height);
+
+        // Draw GPU usage graph
+        self.draw_gpu_graph(buffer, width, height);
+    }
+
+    fn draw_gpu_graph(&self, buffer: &mut Vec<u32>, width: usize, height: usize) {
+        let graph_width = 200;
+        let graph_height = 100;
+        let graph_x = width.saturating_sub(graph_width + 20);
+        let graph_y = height.saturating_sub(graph_height + 20);
+
+        // One column per sample, newest on the right
+        let samples = self.gpu_history.len().min(graph_width);
+        for (column, usage) in self.gpu_history[self.gpu_history.len() - samples..].iter().enumerate() {
+            let x = graph_x + graph_width - samples + column;
+            let bar = (usage.clamp(0.0, 1.0) * graph_height as f32) as usize;
+            for y in graph_y..graph_y + graph_height {
+                let pos = y * width + x;
+                if pos < buffer.len() {
+                    buffer[pos] = if y >= graph_y + graph_height - bar { ACTIVE_COLOR } else { 0x202020 };
+                }
+            }
+        }
     }
 
     pub fn update(&mut self, processes: &[Process]) {
@@ -116,6 +151,7 @@ impl TaskManager {
                 name: p.name.clone(),
                 state: p.state,
                 cpu_usage: p.cpu_usage,
+                gpu_usage: self.gpu_usage.get(&p.id).copied().unwrap_or(0.0),
                 memory_usage: p.memory_usage,
                 threads: p.threads.len(),
                 selected: false,
@@ -126,11 +162,40 @@ impl TaskManager {
         self.sort_processes();
     }
 
+    // GPU% per process: the share of cycles since the last sample that the
+    // dispatcher kept the engines busy for the process's contexts
+    pub fn update_gpu(&mut self, usage: &[ContextUsage], now: u64) {
+        let mut busy: HashMap<u32, u64> = HashMap::new();
+        for context in usage {
+            *busy.entry(context.pid).or_insert(0) += context.stats.busy_cycles;
+        }
+
+        let elapsed = now.saturating_sub(self.gpu_sampled_at);
+        if elapsed > 0 {
+            self.gpu_usage = busy
+                .iter()
+                .map(|(&pid, &cycles)| {
+                    let before = self.gpu_busy.get(&pid).copied().unwrap_or(0);
+                    (pid, cycles.saturating_sub(before) as f32 / elapsed as f32)
+                })
+                .collect();
+            self.gpu_history.push(self.gpu_usage.values().sum());
+        }
+        self.gpu_busy = busy;
+        self.gpu_sampled_at = now;
+
+        for process in &mut self.processes {
+            process.gpu_usage = self.gpu_usage.get(&process.pid).copied().unwrap_or(0.0);
+        }
+        self.sort_processes();
+    }
+
     fn sort_processes(&mut self) {
         self.processes.sort_by(|a, b| {
             let cmp = match self.sort_column {
                 SortColumn::Name => a.name.cmp(&b.name),
                 SortColumn::CPU => a.cpu_usage.partial_cmp(&b.cpu_usage).unwrap(),
+                SortColumn::GPU => a.gpu_usage.partial_cmp(&b.gpu_usage).unwrap(),
                 SortColumn::Memory => a.memory_usage.cmp(&b.memory_usage),
                 SortColumn::PID => a.pid.cmp(&b.pid),
                 SortColumn::Status => a.state.cmp(&b.state),
@@ -163,6 +228,7 @@ impl TaskManager {
             201..=300 => Some(SortColumn::Memory),
             301..=400 => Some(SortColumn::PID),
             401..=500 => Some(SortColumn::Status),
+            501..=600 => Some(SortColumn::GPU),
             _ => None,
         };
 